@@ -0,0 +1,206 @@
+//! Identifies frames by their metadata, so that all components match messages to frames in the same way.
+use crate::FrameNumber;
+use chrono::{DateTime, Utc};
+use digital_muon_streaming_types::FrameMetadata;
+use std::fmt;
+
+/// Uniquely identifies a frame by its [FrameMetadata].
+///
+/// Keys are ordered by timestamp, then by frame number, then by the remaining fields.
+/// As frame numbers may wrap around, the timestamp is the primary means of ordering frames.
+///
+/// Different digitisers currently report different veto flags for the same frame,
+/// so keys usually ignore them, see [FrameKey::ignoring_veto_flags].
+/// Keys should only be compared with others created by the same constructor,
+/// as a key which ignores veto flags is never equal to one which includes them.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FrameKey {
+    timestamp: DateTime<Utc>,
+    frame_number: FrameNumber,
+    period_number: u64,
+    protons_per_pulse: u8,
+    running: bool,
+    /// Is [None] if the key was created by [FrameKey::ignoring_veto_flags].
+    veto_flags: Option<u16>,
+}
+
+impl FrameKey {
+    /// Creates the key of the frame with the given metadata, whose veto flags do not participate in equality, hashing or ordering,
+    /// so messages whose metadata differ only in their veto flags are matched to the same frame.
+    pub fn ignoring_veto_flags(metadata: &FrameMetadata) -> Self {
+        Self {
+            timestamp: metadata.timestamp,
+            frame_number: metadata.frame_number,
+            period_number: metadata.period_number,
+            protons_per_pulse: metadata.protons_per_pulse,
+            running: metadata.running,
+            veto_flags: None,
+        }
+    }
+
+    /// Creates the key of the frame with the given metadata, whose veto flags participate in equality, hashing and ordering.
+    pub fn including_veto_flags(metadata: &FrameMetadata) -> Self {
+        Self {
+            veto_flags: Some(metadata.veto_flags),
+            ..Self::ignoring_veto_flags(metadata)
+        }
+    }
+
+    /// The timestamp of the frame.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    /// The frame number of the frame.
+    pub fn frame_number(&self) -> FrameNumber {
+        self.frame_number
+    }
+}
+
+/// Creates a key which ignores veto flags, in keeping with the implementation of [PartialEq] for [FrameMetadata].
+impl From<&FrameMetadata> for FrameKey {
+    fn from(metadata: &FrameMetadata) -> Self {
+        Self::ignoring_veto_flags(metadata)
+    }
+}
+
+impl fmt::Display for FrameKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame {} at {} (period: {}, protons per pulse: {}, running: {}",
+            self.frame_number,
+            self.timestamp.to_rfc3339(),
+            self.period_number,
+            self.protons_per_pulse,
+            self.running,
+        )?;
+        match self.veto_flags {
+            Some(veto_flags) => write!(f, ", veto flags: {veto_flags:#06x})"),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const FRAME_NUMBERS: [FrameNumber; 6] = [0, 1, 2, FrameNumber::MAX - 1, FrameNumber::MAX, 559];
+    const PERIODS: [u64; 4] = [0, 1, 18, u64::MAX];
+    const VETO_FLAGS: [u16; 3] = [0, 2, u16::MAX];
+
+    fn metadata(timestamp_ns: i64, frame_number: FrameNumber, period_number: u64) -> FrameMetadata {
+        FrameMetadata {
+            timestamp: DateTime::from_timestamp_nanos(timestamp_ns),
+            period_number,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number,
+            veto_flags: 0,
+        }
+    }
+
+    #[test]
+    fn frames_with_later_timestamps_order_later_across_wrap_around() {
+        // Consecutive frames, 20ms apart, whose frame numbers wrap around.
+        let frame_numbers = (FrameNumber::MAX - 3..=FrameNumber::MAX).chain(0..4);
+        let keys = frame_numbers
+            .enumerate()
+            .map(|(index, frame_number)| {
+                FrameKey::from(&metadata(index as i64 * 20_000_000, frame_number, 0))
+            })
+            .collect::<Vec<_>>();
+
+        for (earlier, later) in keys.iter().zip(keys.iter().skip(1)) {
+            assert!(earlier < later, "{earlier} should precede {later}");
+        }
+    }
+
+    #[test]
+    fn identical_timestamps_order_by_frame_number() {
+        for &a in &FRAME_NUMBERS {
+            for &b in &FRAME_NUMBERS {
+                let key_a = FrameKey::from(&metadata(1000, a, 0));
+                let key_b = FrameKey::from(&metadata(1000, b, 0));
+                assert_eq!(key_a.cmp(&key_b), a.cmp(&b));
+            }
+        }
+    }
+
+    #[test]
+    fn identical_timestamps_with_different_periods_are_distinct() {
+        for &frame_number in &FRAME_NUMBERS {
+            let keys = PERIODS
+                .iter()
+                .map(|&period| FrameKey::from(&metadata(1000, frame_number, period)))
+                .collect::<HashSet<_>>();
+            assert_eq!(keys.len(), PERIODS.len());
+
+            for &a in &PERIODS {
+                for &b in &PERIODS {
+                    let key_a = FrameKey::from(&metadata(1000, frame_number, a));
+                    let key_b = FrameKey::from(&metadata(1000, frame_number, b));
+                    assert_eq!(key_a == key_b, a == b);
+                    assert_eq!(key_a.cmp(&key_b), a.cmp(&b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn veto_flags_are_ignored_by_default() {
+        for &a in &VETO_FLAGS {
+            for &b in &VETO_FLAGS {
+                let metadata_a = FrameMetadata {
+                    veto_flags: a,
+                    ..metadata(1000, 559, 12)
+                };
+                let metadata_b = FrameMetadata {
+                    veto_flags: b,
+                    ..metadata(1000, 559, 12)
+                };
+                assert_eq!(FrameKey::from(&metadata_a), FrameKey::from(&metadata_b));
+                assert_eq!(
+                    FrameKey::including_veto_flags(&metadata_a)
+                        == FrameKey::including_veto_flags(&metadata_b),
+                    a == b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn equal_keys_have_equal_hashes() {
+        let keys = FRAME_NUMBERS
+            .iter()
+            .flat_map(|&frame_number| {
+                PERIODS
+                    .iter()
+                    .map(move |&period| FrameKey::from(&metadata(1000, frame_number, period)))
+            })
+            .collect::<Vec<_>>();
+        let set = keys.iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(set.len(), keys.len());
+        for key in &keys {
+            assert!(set.contains(key));
+        }
+    }
+
+    #[test]
+    fn display_includes_veto_flags_only_when_included() {
+        let metadata = FrameMetadata {
+            veto_flags: 2,
+            ..metadata(0, 559, 12)
+        };
+        assert_eq!(
+            FrameKey::from(&metadata).to_string(),
+            "frame 559 at 1970-01-01T00:00:00+00:00 (period: 12, protons per pulse: 8, running: true)"
+        );
+        assert_eq!(
+            FrameKey::including_veto_flags(&metadata).to_string(),
+            "frame 559 at 1970-01-01T00:00:00+00:00 (period: 12, protons per pulse: 8, running: true, veto flags: 0x0002)"
+        );
+    }
+}
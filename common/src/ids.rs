@@ -0,0 +1,207 @@
+//! Canonical string forms of digitiser and channel identifiers, so that metric labels,
+//! message keys and log lines represent them in the same way in every component.
+//!
+//! | Identifier      | Display and [FromStr] | Metric labels       |
+//! |-----------------|-----------------------|---------------------|
+//! | [DigitiserName] | `digitiser-4`         | `digitizer_id="4"`  |
+//! | [ChannelNo]     | `channel-3`           | `channel="3"`       |
+use crate::{Channel, DigitizerId};
+use std::{fmt, num::ParseIntError, str::FromStr};
+use thiserror::Error;
+
+/// The key of the metric label giving the id of the digitiser of a message.
+pub const DIGITIZER_ID_LABEL: &str = "digitizer_id";
+
+/// The key of the metric label giving the number of a channel.
+pub const CHANNEL_LABEL: &str = "channel";
+
+/// The value of the [DIGITIZER_ID_LABEL] of metrics of aggregated frame messages, which are from no single digitiser.
+pub const AGGREGATED_DIGITIZER_ID: &str = "aggregated";
+
+const DIGITISER_PREFIX: &str = "digitiser-";
+const CHANNEL_PREFIX: &str = "channel-";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseIdError {
+    #[error("Expected {0:?} to begin with {1:?}")]
+    MissingPrefix(String, &'static str),
+    #[error("Expected {0:?} to be a number without sign or leading zeros")]
+    NonCanonicalNumber(String),
+    #[error("Invalid number {0:?}: {1}")]
+    InvalidNumber(String, ParseIntError),
+}
+
+/// Parses the number following `prefix` in `s`, which must be in decimal digits, without a sign or leading zeros,
+/// so that each number has exactly one string form.
+fn parse_prefixed<T: FromStr<Err = ParseIntError>>(
+    s: &str,
+    prefix: &'static str,
+) -> Result<T, ParseIdError> {
+    let number = s
+        .strip_prefix(prefix)
+        .ok_or_else(|| ParseIdError::MissingPrefix(s.to_owned(), prefix))?;
+    let canonical = !number.is_empty()
+        && number.bytes().all(|byte| byte.is_ascii_digit())
+        && (number == "0" || !number.starts_with('0'));
+    if !canonical {
+        return Err(ParseIdError::NonCanonicalNumber(s.to_owned()));
+    }
+    number
+        .parse()
+        .map_err(|e| ParseIdError::InvalidNumber(s.to_owned(), e))
+}
+
+/// The name of a digitiser, given by its id, displayed as `digitiser-<ID>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DigitiserName(pub DigitizerId);
+
+impl DigitiserName {
+    /// Returns the value of the [DIGITIZER_ID_LABEL] of metrics of this digitiser, which is its bare id.
+    pub fn label_value(self) -> String {
+        self.0.to_string()
+    }
+
+    /// Returns the [DIGITIZER_ID_LABEL] label of metrics of this digitiser.
+    pub fn label(self) -> (&'static str, String) {
+        (DIGITIZER_ID_LABEL, self.label_value())
+    }
+}
+
+impl fmt::Display for DigitiserName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{DIGITISER_PREFIX}{}", self.0)
+    }
+}
+
+impl FromStr for DigitiserName {
+    type Err = ParseIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_prefixed(s, DIGITISER_PREFIX).map(Self)
+    }
+}
+
+/// Identifies a channel of a digitiser, without the digitiser, displayed as `channel-<NUMBER>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChannelNo(pub Channel);
+
+impl ChannelNo {
+    /// Returns the value of the [CHANNEL_LABEL] of metrics of this channel, which is its bare number.
+    pub fn label_value(self) -> String {
+        self.0.to_string()
+    }
+
+    /// Returns the [CHANNEL_LABEL] label of metrics of this channel.
+    pub fn label(self) -> (&'static str, String) {
+        (CHANNEL_LABEL, self.label_value())
+    }
+}
+
+impl fmt::Display for ChannelNo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{CHANNEL_PREFIX}{}", self.0)
+    }
+}
+
+impl FromStr for ChannelNo {
+    type Err = ParseIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_prefixed(s, CHANNEL_PREFIX).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_digitiser_id_round_trips() {
+        for id in DigitizerId::MIN..=DigitizerId::MAX {
+            let digitiser = DigitiserName(id);
+            assert_eq!(digitiser.to_string().parse(), Ok(digitiser));
+        }
+    }
+
+    #[test]
+    fn channel_numbers_round_trip() {
+        for number in (0..1000).chain([Channel::MAX - 1, Channel::MAX]) {
+            let channel = ChannelNo(number);
+            assert_eq!(channel.to_string().parse(), Ok(channel));
+        }
+    }
+
+    #[test]
+    fn non_canonical_digitiser_ids_are_rejected() {
+        for s in [
+            "",
+            "4",
+            "digitiser-",
+            "digitiser-04",
+            "digitiser-00",
+            "digitiser-+4",
+            "digitiser--4",
+            "digitiser- 4",
+            "digitiser-4 ",
+            " digitiser-4",
+            "digitiser-4a",
+            "digitiser-0x4",
+            "digitiser-４",
+            "digitizer-4",
+            "Digitiser-4",
+            "digitiser_4",
+            "digitiser-256",
+            "channel-4",
+            "digitiser-4/channel-3",
+        ] {
+            assert!(s.parse::<DigitiserName>().is_err(), "{s:?} was parsed");
+        }
+    }
+
+    #[test]
+    fn non_canonical_channel_numbers_are_rejected() {
+        for s in [
+            "",
+            "3",
+            "channel-",
+            "channel-03",
+            "channel-+3",
+            "channel--3",
+            "channel-3.0",
+            "Channel-3",
+            "channel-4294967296",
+            "digitiser-3",
+        ] {
+            assert!(s.parse::<ChannelNo>().is_err(), "{s:?} was parsed");
+        }
+    }
+
+    #[test]
+    fn parse_errors_describe_the_input() {
+        assert_eq!(
+            "digitizer-4".parse::<DigitiserName>(),
+            Err(ParseIdError::MissingPrefix(
+                "digitizer-4".to_owned(),
+                "digitiser-"
+            ))
+        );
+        assert_eq!(
+            "digitiser-04".parse::<DigitiserName>(),
+            Err(ParseIdError::NonCanonicalNumber("digitiser-04".to_owned()))
+        );
+        assert!(matches!(
+            "digitiser-256".parse::<DigitiserName>(),
+            Err(ParseIdError::InvalidNumber(..))
+        ));
+    }
+
+    /// Dashboards, log queries and consumers of message keys select by these strings, so they must not change.
+    #[test]
+    fn string_forms_are_stable() {
+        assert_eq!(DigitiserName(4).to_string(), "digitiser-4");
+        assert_eq!(ChannelNo(3).to_string(), "channel-3");
+        assert_eq!(DigitiserName(4).label(), ("digitizer_id", "4".to_owned()));
+        assert_eq!(ChannelNo(3).label(), ("channel", "3".to_owned()));
+        assert_eq!(AGGREGATED_DIGITIZER_ID, "aggregated");
+    }
+}
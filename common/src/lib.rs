@@ -1,8 +1,23 @@
+mod frame_key;
+pub mod ids;
 pub mod metrics;
+mod producer_tuning;
+mod resilient_consumer;
+pub mod seek;
 pub mod spanned;
 pub mod tracer;
 mod version;
 
+pub use frame_key::FrameKey;
+
+/// Re-exported for the [failure] macro, so that its users need not depend on `metrics` themselves.
+#[doc(hidden)]
+pub use ::metrics as __metrics;
+pub use producer_tuning::{Compression, ProducerTuning};
+pub use resilient_consumer::{
+    ConsumerErrorKind, RecvConsumer, ResilientConsumer, ResilientConsumerOpts,
+};
+
 use clap::Args;
 use rdkafka::{
     config::ClientConfig,
@@ -48,16 +63,23 @@ pub struct CommonKafkaOpts {
     pub password: Option<String>,
 }
 
+/// Creates the configuration of a Kafka client.
+/// Messages produced by a client with this configuration are compressed with zstd, unless `producer_tuning` specifies otherwise.
 pub fn generate_kafka_client_config(
     broker_address: &String,
     username: &Option<String>,
     password: &Option<String>,
+    producer_tuning: Option<&ProducerTuning>,
 ) -> ClientConfig {
     let mut client_config = ClientConfig::new()
         .set("bootstrap.servers", broker_address)
         .set("compression.type", "zstd")
         .clone();
 
+    if let Some(producer_tuning) = producer_tuning {
+        producer_tuning.apply(&mut client_config);
+    }
+
     // Allow for authenticated Kafka connection if details are provided
     if let (Some(sasl_username), Some(sasl_password)) = (username, password) {
         client_config
@@ -77,12 +99,13 @@ pub fn create_default_consumer(
     topics_to_subscribe: Option<&[&str]>,
 ) -> Result<StreamConsumer, KafkaError> {
     // Setup consumer with arguments and default parameters.
-    let consumer: StreamConsumer = generate_kafka_client_config(broker_address, username, password)
-        .set("group.id", consumer_group)
-        .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "false")
-        .create()?;
+    let consumer: StreamConsumer =
+        generate_kafka_client_config(broker_address, username, password, None)
+            .set("group.id", consumer_group)
+            .set("enable.partition.eof", "false")
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .create()?;
 
     // Subscribe to if topics are provided.
     if let Some(topics_to_subscribe) = topics_to_subscribe {
@@ -6,7 +6,17 @@ pub fn component_info_metric(name: &'static str) {
     describe_gauge!(NAME, "Basic information about the component");
 
     let git_rev = option_env!("GIT_VERSION").unwrap_or("unknown");
-    gauge!(NAME, "component" => name, "git_version" => git_rev).set(1);
+    gauge!(NAME, &[component_label(name), ("git_version", git_rev)]).set(1);
+}
+
+/// Returns the label identifying the component called `name`, with which [component_info_metric] is labelled.
+///
+/// Other metrics, such as [FAILURES], are not labelled by component, so that their series do not change,
+/// but dashboards can split them by component by joining them with the component info metric on `instance`.
+///
+/// [FAILURES]: names::FAILURES
+pub fn component_label(name: &'static str) -> (&'static str, &'static str) {
+    ("component", name)
 }
 
 pub mod names {
@@ -16,17 +26,42 @@ pub mod names {
 
     pub const FAILURES: &str = concatcp!(METRIC_NAME_PREFIX, "failures");
     pub const FRAMES_SENT: &str = concatcp!(METRIC_NAME_PREFIX, "frames_sent");
+    pub const ACHIEVED_FRAME_RATE_HZ: &str =
+        concatcp!(METRIC_NAME_PREFIX, "achieved_frame_rate_hz");
+    pub const PARTIAL_FRAMES_EMITTED: &str =
+        concatcp!(METRIC_NAME_PREFIX, "partial_frames_emitted");
+    pub const PARTIAL_FRAMES_DROPPED: &str =
+        concatcp!(METRIC_NAME_PREFIX, "partial_frames_dropped");
+    pub const DUPLICATE_DIGITISER_MESSAGES: &str =
+        concatcp!(METRIC_NAME_PREFIX, "duplicate_digitiser_messages");
+    pub const LATE_DIGITISER_MESSAGES: &str =
+        concatcp!(METRIC_NAME_PREFIX, "late_digitiser_messages");
+    pub const VETOED_FRAMES: &str = concatcp!(METRIC_NAME_PREFIX, "vetoed_frames");
+    pub const FRAMES_IN_FLIGHT: &str = concatcp!(METRIC_NAME_PREFIX, "frames_in_flight");
+    pub const EXPECTED_DIGITISERS: &str = concatcp!(METRIC_NAME_PREFIX, "expected_digitisers");
+    pub const FRAME_ASSEMBLY_DURATION_MS: &str =
+        concatcp!(METRIC_NAME_PREFIX, "frame_assembly_duration_ms");
+    pub const FRAME_COMPLETION_LATENCY_MS: &str =
+        concatcp!(METRIC_NAME_PREFIX, "frame_completion_latency_ms");
     pub const MESSAGES_PROCESSED: &str = concatcp!(METRIC_NAME_PREFIX, "messages_processed");
     pub const MESSAGES_RECEIVED: &str = concatcp!(METRIC_NAME_PREFIX, "messages_received");
     pub const LAST_MESSAGE_TIMESTAMP: &str =
         concatcp!(METRIC_NAME_PREFIX, "last_message_timestamp");
     pub const LAST_MESSAGE_FRAME_NUMBER: &str =
         concatcp!(METRIC_NAME_PREFIX, "last_message_frame_number");
+    pub const RUN_EVENTS_WRITTEN: &str = concatcp!(METRIC_NAME_PREFIX, "run_events_written");
+    pub const RUN_FILE_SIZE_BYTES: &str = concatcp!(METRIC_NAME_PREFIX, "run_file_size_bytes");
+    pub const RUN_FLUSHES: &str = concatcp!(METRIC_NAME_PREFIX, "run_flushes");
+    pub const CONSUMER_RECONNECTS: &str = concatcp!(METRIC_NAME_PREFIX, "consumer_reconnects");
+    pub const CONSUMER_DEGRADED_SECONDS: &str =
+        concatcp!(METRIC_NAME_PREFIX, "consumer_degraded_seconds");
+    pub const RUN_EVENT_LAG_SECONDS: &str = concatcp!(METRIC_NAME_PREFIX, "run_event_lag_seconds");
 }
 
 pub mod messages_received {
     #[derive(Debug, Clone, Eq, Hash, PartialEq)]
     pub enum MessageKind {
+        AggregatedTrace,
         Alarm,
         Event,
         LogData,
@@ -42,6 +77,7 @@ pub mod messages_received {
         (
             "message_kind",
             match message_kind {
+                MessageKind::AggregatedTrace => "aggregated_trace",
                 MessageKind::Alarm => "alarm",
                 MessageKind::Event => "event",
                 MessageKind::LogData => "log_data",
@@ -57,12 +93,17 @@ pub mod messages_received {
 }
 
 pub mod failures {
-    #[derive(Debug, Clone, Eq, Hash, PartialEq)]
+    #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
     pub enum FailureKind {
         DataProcessingFailed,
         FileWriteFailed,
+        ImplausibleTimestamp,
         InvalidMetadata,
+        InvalidTimestamp,
         KafkaPublishFailed,
+        MalformedChannel,
+        MissingChannelData,
+        MissingSampleRate,
         UnableToDecodeMessage,
     }
 
@@ -73,10 +114,125 @@ pub mod failures {
             match failure_kind {
                 FailureKind::DataProcessingFailed => "data_processing_failed",
                 FailureKind::FileWriteFailed => "file_write_failed",
+                FailureKind::ImplausibleTimestamp => "implausible_timestamp",
                 FailureKind::InvalidMetadata => "invalid_metadata",
+                FailureKind::InvalidTimestamp => "invalid_timestamp",
                 FailureKind::KafkaPublishFailed => "kafka_publish_failed",
+                FailureKind::MalformedChannel => "malformed_channel",
+                FailureKind::MissingChannelData => "missing_channel_data",
+                FailureKind::MissingSampleRate => "missing_sample_rate",
                 FailureKind::UnableToDecodeMessage => "unable_to_decode_message",
             },
         )
     }
+
+    /// Increments the [FAILURES] counter, labelled by the given [FailureKind].
+    ///
+    /// Further labels, which are `(key, value)` pairs, may follow the failure kind.
+    /// ```rust,ignore
+    /// failure!(FailureKind::MalformedChannel, ("channel", format!("{channel}")));
+    /// ```
+    ///
+    /// [FAILURES]: super::names::FAILURES
+    #[macro_export]
+    macro_rules! failure {
+        ($kind:expr $(, $label:expr)* $(,)?) => {
+            $crate::__metrics::counter!(
+                $crate::metrics::names::FAILURES,
+                ::std::vec![
+                    $crate::__metrics::Label::from(&$crate::metrics::failures::get_label($kind)),
+                    $($crate::__metrics::Label::from(&$label)),*
+                ]
+            )
+            .increment(1)
+        };
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::metrics::names::FAILURES;
+        use metrics_util::{
+            CompositeKey, MetricKind,
+            debugging::{DebugValue, DebuggingRecorder},
+        };
+
+        /// Dashboards select failures by these labels, so they must not change.
+        #[test]
+        fn labels_are_stable() {
+            let labels = [
+                FailureKind::DataProcessingFailed,
+                FailureKind::FileWriteFailed,
+                FailureKind::ImplausibleTimestamp,
+                FailureKind::InvalidMetadata,
+                FailureKind::InvalidTimestamp,
+                FailureKind::KafkaPublishFailed,
+                FailureKind::MalformedChannel,
+                FailureKind::MissingChannelData,
+                FailureKind::MissingSampleRate,
+                FailureKind::UnableToDecodeMessage,
+            ]
+            .map(get_label);
+            assert_eq!(
+                labels,
+                [
+                    ("failure_kind", "data_processing_failed"),
+                    ("failure_kind", "file_write_failed"),
+                    ("failure_kind", "implausible_timestamp"),
+                    ("failure_kind", "invalid_metadata"),
+                    ("failure_kind", "invalid_timestamp"),
+                    ("failure_kind", "kafka_publish_failed"),
+                    ("failure_kind", "malformed_channel"),
+                    ("failure_kind", "missing_channel_data"),
+                    ("failure_kind", "missing_sample_rate"),
+                    ("failure_kind", "unable_to_decode_message"),
+                ]
+            );
+        }
+
+        #[test]
+        fn failure_macro_labels_counter() {
+            let recorder = DebuggingRecorder::new();
+            let snapshotter = recorder.snapshotter();
+            metrics::with_local_recorder(&recorder, || {
+                failure!(FailureKind::MalformedChannel, ("channel", "3".to_owned()));
+                failure!(FailureKind::MalformedChannel, ("channel", "3".to_owned()));
+                failure!(FailureKind::InvalidTimestamp);
+            });
+
+            let mut counters = snapshotter
+                .snapshot()
+                .into_vec()
+                .into_iter()
+                .map(|(key, _, _, value)| {
+                    let key: CompositeKey = key;
+                    assert_eq!(key.kind(), MetricKind::Counter);
+                    assert_eq!(key.key().name(), FAILURES);
+                    let labels = key
+                        .key()
+                        .labels()
+                        .map(|label| format!("{}={}", label.key(), label.value()))
+                        .collect::<Vec<_>>();
+                    (labels, value)
+                })
+                .collect::<Vec<_>>();
+            counters.sort_by(|(a, _), (b, _)| a.cmp(b));
+            assert_eq!(
+                counters,
+                [
+                    (
+                        vec!["failure_kind=invalid_timestamp".to_owned()],
+                        DebugValue::Counter(1)
+                    ),
+                    (
+                        vec![
+                            "failure_kind=malformed_channel".to_owned(),
+                            "channel=3".to_owned()
+                        ],
+                        DebugValue::Counter(2)
+                    ),
+                ]
+            );
+        }
+    }
 }
@@ -0,0 +1,109 @@
+//! Options which tune how a Kafka producer batches and compresses the messages it sends.
+use clap::{Args, ValueEnum};
+use rdkafka::config::ClientConfig;
+
+/// The codecs with which produced messages can be compressed.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// Messages are sent uncompressed.
+    None,
+    Gzip,
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the value of the `compression.type` property which selects this codec.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Producer options for trading latency against bandwidth, and limiting the size of messages.
+/// Options which are not given are left at the defaults of librdkafka.
+#[derive(Default, Clone, Debug, Args)]
+pub struct ProducerTuning {
+    /// The codec with which produced messages are compressed.
+    #[clap(long, value_enum, default_value_t = Compression::Zstd)]
+    pub compression: Compression,
+
+    /// How long, in milliseconds, the producer waits for further messages to batch with a message before sending it.
+    #[clap(long)]
+    pub linger_ms: Option<u32>,
+
+    /// The most messages the producer sends in a single batch.
+    #[clap(long)]
+    pub batch_num_messages: Option<u32>,
+
+    /// The largest message, in bytes, the producer sends.
+    /// This should not exceed the largest message the broker accepts.
+    #[clap(long)]
+    pub message_max_bytes: Option<usize>,
+}
+
+impl ProducerTuning {
+    /// Sets the rdkafka properties of `client_config` corresponding to these options.
+    pub fn apply(&self, client_config: &mut ClientConfig) {
+        client_config.set("compression.type", self.compression.as_str());
+        if let Some(linger_ms) = self.linger_ms {
+            client_config.set("linger.ms", linger_ms.to_string());
+        }
+        if let Some(batch_num_messages) = self.batch_num_messages {
+            client_config.set("batch.num.messages", batch_num_messages.to_string());
+        }
+        if let Some(message_max_bytes) = self.message_max_bytes {
+            client_config.set("message.max.bytes", message_max_bytes.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_kafka_client_config;
+
+    fn client_config(producer_tuning: Option<&ProducerTuning>) -> ClientConfig {
+        generate_kafka_client_config(&"localhost:9092".to_owned(), &None, &None, producer_tuning)
+    }
+
+    #[test]
+    fn untuned_config_compresses_with_zstd() {
+        let config = client_config(None);
+        assert_eq!(config.get("bootstrap.servers"), Some("localhost:9092"));
+        assert_eq!(config.get("compression.type"), Some("zstd"));
+        assert_eq!(config.get("linger.ms"), None);
+        assert_eq!(config.get("batch.num.messages"), None);
+        assert_eq!(config.get("message.max.bytes"), None);
+    }
+
+    #[test]
+    fn tuning_properties_are_set() {
+        let config = client_config(Some(&ProducerTuning {
+            compression: Compression::Lz4,
+            linger_ms: Some(50),
+            batch_num_messages: Some(1000),
+            message_max_bytes: Some(10_000_000),
+        }));
+        assert_eq!(config.get("compression.type"), Some("lz4"));
+        assert_eq!(config.get("linger.ms"), Some("50"));
+        assert_eq!(config.get("batch.num.messages"), Some("1000"));
+        assert_eq!(config.get("message.max.bytes"), Some("10000000"));
+    }
+
+    #[test]
+    fn absent_options_are_not_set() {
+        let config = client_config(Some(&ProducerTuning {
+            compression: Compression::None,
+            ..Default::default()
+        }));
+        assert_eq!(config.get("compression.type"), Some("none"));
+        assert_eq!(config.get("linger.ms"), None);
+        assert_eq!(config.get("message.max.bytes"), None);
+    }
+}
@@ -0,0 +1,500 @@
+//! A wrapper around a Kafka consumer which backs off when it repeatedly receives errors,
+//! and recreates the consumer if they persist, see [ResilientConsumer].
+use crate::{
+    create_default_consumer,
+    metrics::names::{CONSUMER_DEGRADED_SECONDS, CONSUMER_RECONNECTS},
+};
+use clap::Args;
+use metrics::{counter, gauge};
+use rdkafka::{
+    consumer::StreamConsumer,
+    error::{KafkaError, RDKafkaErrorCode},
+    message::BorrowedMessage,
+};
+use std::{
+    ops::Deref,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Options which determine how a [ResilientConsumer] responds to errors.
+#[derive(Clone, Debug, Args)]
+pub struct ResilientConsumerOpts {
+    /// The delay, in milliseconds, after the first of consecutive errors received by the consumer.
+    /// This doubles with each further error, and a random jitter of up to half of it is subtracted.
+    #[clap(long, default_value = "100")]
+    pub consumer_backoff_initial_ms: u64,
+
+    /// The most delay, in milliseconds, after an error received by the consumer.
+    #[clap(long, default_value = "10000")]
+    pub consumer_backoff_max_ms: u64,
+
+    /// The number of consecutive fatal errors, such as the loss of the consumer's group membership, after which
+    /// the consumer is recreated and resubscribed to its topics, with the same consumer group.
+    #[clap(long, default_value = "5", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    pub consumer_reconnect_after: u32,
+}
+
+impl Default for ResilientConsumerOpts {
+    fn default() -> Self {
+        Self {
+            consumer_backoff_initial_ms: 100,
+            consumer_backoff_max_ms: 10_000,
+            consumer_reconnect_after: 5,
+        }
+    }
+}
+
+/// How an error received by a consumer should be handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsumerErrorKind {
+    /// The consumer is expected to recover by itself, for instance by reconnecting to a broker, even if all brokers are down.
+    Transient,
+    /// The consumer is not expected to recover, for instance if it has lost its group membership, so should be recreated.
+    Fatal,
+}
+
+impl ConsumerErrorKind {
+    /// Returns how `error` should be handled.
+    pub fn classify(error: &KafkaError) -> Self {
+        match error {
+            KafkaError::MessageConsumptionFatal(_)
+            | KafkaError::ClientCreation(_)
+            | KafkaError::Subscription(_) => Self::Fatal,
+            error => match error.rdkafka_error_code() {
+                Some(
+                    RDKafkaErrorCode::Fatal
+                    | RDKafkaErrorCode::Authentication
+                    | RDKafkaErrorCode::UnknownMemberId,
+                ) => Self::Fatal,
+                _ => Self::Transient,
+            },
+        }
+    }
+}
+
+/// A consumer which can be wrapped by [ResilientConsumer].
+///
+/// This allows the wrapper's handling of errors to be tested without a broker.
+pub trait RecvConsumer {
+    type Message<'a>
+    where
+        Self: 'a;
+
+    /// Receives the next message, as [StreamConsumer::recv].
+    fn recv(&self) -> impl Future<Output = Result<Self::Message<'_>, KafkaError>>;
+}
+
+impl RecvConsumer for StreamConsumer {
+    type Message<'a> = BorrowedMessage<'a>;
+
+    fn recv(&self) -> impl Future<Output = Result<BorrowedMessage<'_>, KafkaError>> {
+        StreamConsumer::recv(self)
+    }
+}
+
+/// The errors received since the consumer last received a message.
+#[derive(Default)]
+struct Degradation {
+    /// The number of consecutive errors, of either kind.
+    errors: u32,
+    /// The number of consecutive fatal errors since the consumer last received a message, or was recreated.
+    fatal_errors: u32,
+    /// When the first of the consecutive errors was received, if any.
+    since: Option<Instant>,
+    /// The total time spent degraded, before [Self::since].
+    total: Duration,
+    /// Whether the consumer should be recreated by [ResilientConsumer::recreate_if_required].
+    recreate: bool,
+    /// The number of times the consumer has been recreated.
+    reconnects: u64,
+}
+
+impl Degradation {
+    /// Records that a message was received.
+    fn recovered(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.total += since.elapsed();
+            gauge!(CONSUMER_DEGRADED_SECONDS).set(self.total.as_secs_f64());
+        }
+        self.errors = 0;
+        self.fatal_errors = 0;
+    }
+
+    /// Records that an error of `kind` was received, and marks the consumer to be recreated if too many fatal errors have been received.
+    /// The time degraded is updated with each error, so it grows whilst the consumer receives no messages.
+    /// # Returns
+    /// The number of consecutive errors, including this one.
+    fn errored(&mut self, kind: ConsumerErrorKind, options: &ResilientConsumerOpts) -> u32 {
+        self.since.get_or_insert_with(Instant::now);
+        gauge!(CONSUMER_DEGRADED_SECONDS).set(self.time_degraded().as_secs_f64());
+        self.errors = self.errors.saturating_add(1);
+        if kind == ConsumerErrorKind::Fatal {
+            self.fatal_errors += 1;
+            if self.fatal_errors >= options.consumer_reconnect_after {
+                self.recreate = true;
+            }
+        }
+        self.errors
+    }
+
+    fn time_degraded(&self) -> Duration {
+        self.total + self.since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Returns the range of delays after the `errors`-th consecutive error.
+/// The upper bound doubles with each error, from `consumer-backoff-initial-ms` to at most `consumer-backoff-max-ms`,
+/// and the lower bound is half of the upper.
+fn backoff_range(errors: u32, options: &ResilientConsumerOpts) -> (u64, u64) {
+    let max = options
+        .consumer_backoff_initial_ms
+        .saturating_mul(1 << errors.saturating_sub(1).min(32))
+        .min(options.consumer_backoff_max_ms);
+    (max / 2, max)
+}
+
+/// The function which creates, and recreates, the consumer of a [ResilientConsumer].
+type CreateConsumer<C> = Box<dyn Fn() -> Result<C, KafkaError> + Send + Sync>;
+
+/// Wraps a consumer so that, rather than receiving errors as fast as they are raised whilst the broker is unavailable,
+/// each error is followed by an exponentially increasing delay, see [ResilientConsumerOpts].
+///
+/// After too many consecutive fatal errors, the consumer is marked to be recreated, which is done by [Self::recreate_if_required].
+/// This is separate from [Self::recv], so messages can be borrowed from the consumer whilst it is in use,
+/// and should be called wherever no messages are held, such as at the start of each iteration of the main loop.
+///
+/// The consumer's other methods are available through [Deref].
+pub struct ResilientConsumer<C> {
+    consumer: C,
+    create: CreateConsumer<C>,
+    options: ResilientConsumerOpts,
+    degradation: Mutex<Degradation>,
+}
+
+impl<C: RecvConsumer> ResilientConsumer<C> {
+    /// Creates the consumer by calling `create`, which is called again each time the consumer is recreated.
+    pub fn new(
+        options: &ResilientConsumerOpts,
+        create: impl Fn() -> Result<C, KafkaError> + Send + Sync + 'static,
+    ) -> Result<Self, KafkaError> {
+        Ok(Self {
+            consumer: create()?,
+            create: Box::new(create),
+            options: options.clone(),
+            degradation: Default::default(),
+        })
+    }
+
+    fn degradation(&self) -> MutexGuard<'_, Degradation> {
+        self.degradation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Receives the next message, as [StreamConsumer::recv].
+    ///
+    /// If an error is received, this waits for the backoff delay before returning it, so the caller may handle errors as before.
+    pub async fn recv(&self) -> Result<C::Message<'_>, KafkaError> {
+        let result = self.consumer.recv().await;
+        match &result {
+            Ok(_) => self.degradation().recovered(),
+            Err(e) => {
+                let kind = ConsumerErrorKind::classify(e);
+                let errors = self.degradation().errored(kind, &self.options);
+                let (min, max) = backoff_range(errors, &self.options);
+                let delay = Duration::from_millis(rand::random_range(min..=max));
+                warn!("Consumer error {errors} in a row is {kind:?}, backing off for {delay:?}");
+                tokio::time::sleep(delay).await;
+            }
+        }
+        result
+    }
+
+    /// Recreates the consumer if too many consecutive fatal errors have been received by [Self::recv].
+    /// If recreating fails, the existing consumer is kept, and recreating is attempted again at the next call.
+    /// # Returns
+    /// True if the consumer was recreated.
+    pub fn recreate_if_required(&mut self) -> Result<bool, KafkaError> {
+        if !self.degradation().recreate {
+            return Ok(false);
+        }
+        self.consumer = (self.create)()?;
+        let mut degradation = self.degradation();
+        degradation.recreate = false;
+        degradation.fatal_errors = 0;
+        degradation.reconnects += 1;
+        counter!(CONSUMER_RECONNECTS).increment(1);
+        info!(
+            "Consumer recreated, {} times in total",
+            degradation.reconnects
+        );
+        Ok(true)
+    }
+
+    /// Returns the number of times the consumer has been recreated.
+    pub fn reconnects(&self) -> u64 {
+        self.degradation().reconnects
+    }
+
+    /// Returns the total time during which the consumer has been receiving errors rather than messages.
+    pub fn time_degraded(&self) -> Duration {
+        self.degradation().time_degraded()
+    }
+}
+
+impl ResilientConsumer<StreamConsumer> {
+    /// Creates a consumer, as [create_default_consumer], which is recreated with the same consumer group and topics.
+    pub fn create_default(
+        broker_address: &str,
+        username: &Option<String>,
+        password: &Option<String>,
+        consumer_group: &str,
+        topics_to_subscribe: &[&str],
+        options: &ResilientConsumerOpts,
+    ) -> Result<Self, KafkaError> {
+        let broker_address = broker_address.to_owned();
+        let username = username.clone();
+        let password = password.clone();
+        let consumer_group = consumer_group.to_owned();
+        let topics_to_subscribe = topics_to_subscribe
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        Self::new(options, move || {
+            let topics = topics_to_subscribe
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            create_default_consumer(
+                &broker_address,
+                &username,
+                &password,
+                &consumer_group,
+                Some(&topics),
+            )
+        })
+    }
+}
+
+impl<C> Deref for ResilientConsumer<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    /// Returns the results it is given, in order, then errors.
+    struct MockConsumer {
+        /// The number of the creation of this consumer, starting from zero.
+        generation: usize,
+        results: Mutex<VecDeque<Result<u32, KafkaError>>>,
+    }
+
+    impl RecvConsumer for MockConsumer {
+        type Message<'a> = (usize, u32);
+
+        async fn recv(&self) -> Result<(usize, u32), KafkaError> {
+            self.results
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(Err(fatal()))
+                .map(|message| (self.generation, message))
+        }
+    }
+
+    fn fatal() -> KafkaError {
+        KafkaError::MessageConsumption(RDKafkaErrorCode::UnknownMemberId)
+    }
+
+    fn transient() -> KafkaError {
+        KafkaError::MessageConsumption(RDKafkaErrorCode::BrokerTransportFailure)
+    }
+
+    /// Creates a consumer whose first creation returns `results`, and whose later creations return messages.
+    fn consumer(
+        results: Vec<Result<u32, KafkaError>>,
+        reconnect_after: u32,
+    ) -> ResilientConsumer<MockConsumer> {
+        let generations = Arc::new(AtomicUsize::new(0));
+        ResilientConsumer::new(
+            &ResilientConsumerOpts {
+                consumer_backoff_initial_ms: 100,
+                consumer_backoff_max_ms: 1000,
+                consumer_reconnect_after: reconnect_after,
+            },
+            move || {
+                let generation = generations.fetch_add(1, Ordering::SeqCst);
+                let results = if generation == 0 {
+                    results.clone()
+                } else {
+                    (0..10).map(Ok).collect()
+                };
+                Ok(MockConsumer {
+                    generation,
+                    results: Mutex::new(results.into()),
+                })
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn errors_are_classified() {
+        assert_eq!(
+            ConsumerErrorKind::classify(&fatal()),
+            ConsumerErrorKind::Fatal
+        );
+        assert_eq!(
+            ConsumerErrorKind::classify(&KafkaError::MessageConsumptionFatal(
+                RDKafkaErrorCode::Fatal
+            )),
+            ConsumerErrorKind::Fatal
+        );
+        assert_eq!(
+            ConsumerErrorKind::classify(&transient()),
+            ConsumerErrorKind::Transient
+        );
+        assert_eq!(
+            ConsumerErrorKind::classify(&KafkaError::MessageConsumption(
+                RDKafkaErrorCode::AllBrokersDown
+            )),
+            ConsumerErrorKind::Transient
+        );
+        assert_eq!(
+            ConsumerErrorKind::classify(&KafkaError::NoMessageReceived),
+            ConsumerErrorKind::Transient
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let options = ResilientConsumerOpts {
+            consumer_backoff_initial_ms: 100,
+            consumer_backoff_max_ms: 1000,
+            consumer_reconnect_after: 1,
+        };
+        assert_eq!(backoff_range(1, &options), (50, 100));
+        assert_eq!(backoff_range(2, &options), (100, 200));
+        assert_eq!(backoff_range(4, &options), (400, 800));
+        assert_eq!(backoff_range(5, &options), (500, 1000));
+        assert_eq!(backoff_range(u32::MAX, &options), (500, 1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn errors_are_followed_by_backoff() {
+        let consumer = consumer(vec![Err(transient()), Err(transient()), Ok(7)], 5);
+
+        let start = Instant::now();
+        assert!(consumer.recv().await.is_err());
+        let first = start.elapsed();
+        assert!((Duration::from_millis(50)..=Duration::from_millis(100)).contains(&first));
+
+        assert!(consumer.recv().await.is_err());
+        let second = start.elapsed() - first;
+        assert!((Duration::from_millis(100)..=Duration::from_millis(200)).contains(&second));
+
+        assert_eq!(consumer.recv().await.unwrap(), (0, 7));
+        assert_eq!(consumer.time_degraded(), first + second);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn messages_reset_backoff() {
+        let consumer = consumer(vec![Err(transient()), Ok(1), Err(transient()), Ok(2)], 5);
+        for message in [1, 2] {
+            let start = Instant::now();
+            assert!(consumer.recv().await.is_err());
+            assert!(start.elapsed() <= Duration::from_millis(100));
+            assert_eq!(consumer.recv().await.unwrap(), (0, message));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors_do_not_recreate() {
+        let mut consumer = consumer(vec![Err(transient()); 10], 3);
+        for _ in 0..10 {
+            assert!(consumer.recv().await.is_err());
+            assert!(!consumer.recreate_if_required().unwrap());
+        }
+        assert_eq!(consumer.reconnects(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn consecutive_fatal_errors_recreate() {
+        let mut consumer = consumer(
+            vec![
+                Err(fatal()),
+                Err(fatal()),
+                Ok(1),
+                Err(fatal()),
+                Err(transient()),
+                Err(fatal()),
+            ],
+            3,
+        );
+        // A message resets the count of fatal errors.
+        for _ in 0..2 {
+            assert!(consumer.recv().await.is_err());
+            assert!(!consumer.recreate_if_required().unwrap());
+        }
+        assert_eq!(consumer.recv().await.unwrap(), (0, 1));
+
+        // Transient errors neither count towards, nor reset, the count.
+        for _ in 0..2 {
+            assert!(consumer.recv().await.is_err());
+            assert!(!consumer.recreate_if_required().unwrap());
+        }
+        assert!(consumer.recv().await.is_err());
+        assert!(!consumer.recreate_if_required().unwrap());
+        assert!(consumer.recv().await.is_err());
+        assert!(consumer.recreate_if_required().unwrap());
+        assert!(!consumer.recreate_if_required().unwrap());
+        assert_eq!(consumer.reconnects(), 1);
+
+        assert_eq!(consumer.recv().await.unwrap(), (1, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_recreation_is_retried() {
+        let generations = Arc::new(AtomicUsize::new(0));
+        let mut consumer = ResilientConsumer::new(&ResilientConsumerOpts::default(), {
+            let generations = generations.clone();
+            move || {
+                let generation = generations.fetch_add(1, Ordering::SeqCst);
+                if generation == 1 {
+                    return Err(KafkaError::ClientCreation("Broker down".to_owned()));
+                }
+                Ok(MockConsumer {
+                    generation,
+                    results: Mutex::new(VecDeque::from([Ok(3)])),
+                })
+            }
+        })
+        .unwrap();
+        // The consumer errors once its results are exhausted.
+        assert!(consumer.recv().await.is_ok());
+        for _ in 0..5 {
+            assert!(consumer.recv().await.is_err());
+        }
+        assert!(consumer.recreate_if_required().is_err());
+        assert_eq!(consumer.reconnects(), 0);
+        assert!(consumer.recreate_if_required().unwrap());
+        assert_eq!(consumer.reconnects(), 1);
+        assert_eq!(consumer.recv().await.unwrap(), (2, 3));
+    }
+}
@@ -0,0 +1,359 @@
+//! Creates Kafka consumers which start consuming from a given timestamp, from the last messages of each partition, or from explicit offsets,
+//! rather than from the offsets committed by their consumer group.
+use crate::create_default_consumer;
+use rdkafka::{
+    Offset, TopicPartitionList,
+    consumer::{Consumer, StreamConsumer},
+    error::{KafkaError, KafkaResult, RDKafkaErrorCode},
+};
+use std::time::Duration;
+
+/// The queries made to the broker when resolving the offsets of a timestamp.
+///
+/// This is implemented by all [Consumer]s, and allows [resolve_offsets_at_timestamp]
+/// to be tested without a broker.
+pub trait ResolveOffsets {
+    /// Returns the ids of all partitions of the given topic.
+    /// # Parameters
+    /// - topic: the topic to query.
+    /// - timeout: how long to wait for the broker.
+    fn fetch_partitions(&self, topic: &str, timeout: Duration) -> KafkaResult<Vec<i32>>;
+
+    /// For each element of `timestamps`, whose offset is a timestamp in milliseconds, looks up
+    /// the offset of the earliest message whose timestamp is at or after it.
+    /// # Parameters
+    /// - timestamps: the partitions and timestamps to look up.
+    /// - timeout: how long to wait for the broker.
+    fn fetch_offsets_for_times(
+        &self,
+        timestamps: TopicPartitionList,
+        timeout: Duration,
+    ) -> KafkaResult<TopicPartitionList>;
+}
+
+impl<C: Consumer> ResolveOffsets for C {
+    fn fetch_partitions(&self, topic: &str, timeout: Duration) -> KafkaResult<Vec<i32>> {
+        let metadata = self.fetch_metadata(Some(topic), timeout)?;
+        let topic_metadata = metadata
+            .topics()
+            .iter()
+            .find(|topic_metadata| topic_metadata.name() == topic)
+            .ok_or(KafkaError::MetadataFetch(RDKafkaErrorCode::UnknownTopic))?;
+        if let Some(error) = topic_metadata.error() {
+            return Err(KafkaError::MetadataFetch(error.into()));
+        }
+        Ok(topic_metadata
+            .partitions()
+            .iter()
+            .map(|partition| partition.id())
+            .collect())
+    }
+
+    fn fetch_offsets_for_times(
+        &self,
+        timestamps: TopicPartitionList,
+        timeout: Duration,
+    ) -> KafkaResult<TopicPartitionList> {
+        self.offsets_for_times(timestamps, timeout)
+    }
+}
+
+/// Converts an offset returned by the broker's `offsets_for_times` lookup into one to start consuming from.
+///
+/// Brokers report partitions with no message at or after the timestamp with an offset of -1
+/// (which [Offset] interprets as [Offset::End]), or occasionally an invalid offset.
+/// In either case consumption should start from the end of the partition.
+fn start_offset(offset: Offset) -> Offset {
+    match offset {
+        Offset::Offset(offset) if offset >= 0 => Offset::Offset(offset),
+        _ => Offset::End,
+    }
+}
+
+/// Resolves, for every partition of the given topics, the offset of the earliest message
+/// whose timestamp is at or after the given timestamp.
+/// # Parameters
+/// - client: the client with which to query the broker.
+/// - topics: the topics to resolve.
+/// - timestamp_ms: the timestamp, in milliseconds since the epoch.
+/// - timeout: how long to wait for each query to the broker.
+/// # Return
+/// The topic partition list containing the resolved offsets.
+/// Partitions with no message at or after the timestamp are set to [Offset::End].
+pub fn resolve_offsets_at_timestamp(
+    client: &impl ResolveOffsets,
+    topics: &[&str],
+    timestamp_ms: i64,
+    timeout: Duration,
+) -> KafkaResult<TopicPartitionList> {
+    let mut timestamps = TopicPartitionList::new();
+    for &topic in topics {
+        for partition in client.fetch_partitions(topic, timeout)? {
+            timestamps.add_partition_offset(topic, partition, Offset::Offset(timestamp_ms))?;
+        }
+    }
+
+    let resolved = client.fetch_offsets_for_times(timestamps, timeout)?;
+
+    let mut offsets = TopicPartitionList::with_capacity(resolved.count());
+    for element in resolved.elements() {
+        element.error()?;
+        offsets.add_partition_offset(
+            element.topic(),
+            element.partition(),
+            start_offset(element.offset()),
+        )?;
+    }
+    Ok(offsets)
+}
+
+/// Returns, for every partition of the given topics, the offset `num_messages` before its end,
+/// or its beginning if it has fewer messages.
+/// # Parameters
+/// - client: the client with which to query the broker.
+/// - topics: the topics whose partitions are returned.
+/// - num_messages: the number of messages of each partition to consume.
+/// - timeout: how long to wait for each query to the broker.
+pub fn resolve_tail_offsets(
+    client: &impl ResolveOffsets,
+    topics: &[&str],
+    num_messages: i64,
+    timeout: Duration,
+) -> KafkaResult<TopicPartitionList> {
+    let mut offsets = TopicPartitionList::new();
+    for &topic in topics {
+        for partition in client.fetch_partitions(topic, timeout)? {
+            offsets.add_partition_offset(topic, partition, Offset::OffsetTail(num_messages))?;
+        }
+    }
+    Ok(offsets)
+}
+
+/// Assigns an existing consumer to every partition of the given topics, replacing any previous assignment,
+/// starting from the first message at or after the given timestamp.
+/// Partitions with no such message are consumed from their end.
+/// # Parameters
+/// - consumer: the consumer to assign.
+/// - topics: the topics to consume.
+/// - timestamp_ms: the timestamp, in milliseconds since the epoch.
+/// - timeout: how long to wait for each query to the broker.
+pub fn assign_at_timestamp(
+    consumer: &impl Consumer,
+    topics: &[&str],
+    timestamp_ms: i64,
+    timeout: Duration,
+) -> KafkaResult<()> {
+    let offsets = resolve_offsets_at_timestamp(consumer, topics, timestamp_ms, timeout)?;
+    consumer.assign(&offsets)
+}
+
+/// Creates a consumer which is assigned to, and starts consuming from, the given offsets.
+/// # Parameters
+/// - broker_address: address of the Kafka broker.
+/// - username: optional Kafka username.
+/// - password: optional Kafka password.
+/// - consumer_group: the consumer group.
+/// - offsets: the topics, partitions and offsets to start consuming from.
+pub fn create_consumer_at_offsets(
+    broker_address: &String,
+    username: &Option<String>,
+    password: &Option<String>,
+    consumer_group: &String,
+    offsets: &TopicPartitionList,
+) -> Result<StreamConsumer, KafkaError> {
+    let consumer =
+        create_default_consumer(broker_address, username, password, consumer_group, None)?;
+    consumer.assign(offsets)?;
+    Ok(consumer)
+}
+
+/// Creates a consumer which is assigned to every partition of the given topics,
+/// and starts consuming from the first message at or after the given timestamp.
+/// Partitions with no such message are consumed from their end.
+/// # Parameters
+/// - broker_address: address of the Kafka broker.
+/// - username: optional Kafka username.
+/// - password: optional Kafka password.
+/// - consumer_group: the consumer group.
+/// - topics: the topics to consume.
+/// - timestamp_ms: the timestamp, in milliseconds since the epoch.
+/// - timeout: how long to wait for each query to the broker.
+pub fn create_consumer_at_timestamp(
+    broker_address: &String,
+    username: &Option<String>,
+    password: &Option<String>,
+    consumer_group: &String,
+    topics: &[&str],
+    timestamp_ms: i64,
+    timeout: Duration,
+) -> Result<StreamConsumer, KafkaError> {
+    let consumer =
+        create_default_consumer(broker_address, username, password, consumer_group, None)?;
+    assign_at_timestamp(&consumer, topics, timestamp_ms, timeout)?;
+    Ok(consumer)
+}
+
+/// Creates a consumer which is assigned to every partition of the given topics, rather than sharing them with the rest of its consumer group,
+/// so that it receives every message of the topics, starting from the last `num_messages` messages of each partition.
+/// Its offsets are not committed, so every consumer created in this way starts from the same messages.
+/// # Parameters
+/// - broker_address: address of the Kafka broker.
+/// - username: optional Kafka username.
+/// - password: optional Kafka password.
+/// - consumer_group: the consumer group, which is required by the client, but does not manage the consumer's partitions.
+/// - topics: the topics to consume.
+/// - num_messages: the number of messages of each partition consumed before any which are produced after it is created.
+/// - timeout: how long to wait for each query to the broker.
+pub fn create_consumer_at_tail(
+    broker_address: &String,
+    username: &Option<String>,
+    password: &Option<String>,
+    consumer_group: &String,
+    topics: &[&str],
+    num_messages: i64,
+    timeout: Duration,
+) -> Result<StreamConsumer, KafkaError> {
+    let consumer =
+        create_default_consumer(broker_address, username, password, consumer_group, None)?;
+    let offsets = resolve_tail_offsets(&consumer, topics, num_messages, timeout)?;
+    consumer.assign(&offsets)?;
+    Ok(consumer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Mocks a broker with fixed partitions, and fixed results for `offsets_for_times`.
+    #[derive(Default)]
+    struct MockBroker {
+        partitions: HashMap<String, Vec<i32>>,
+        offsets: HashMap<(String, i32), Offset>,
+    }
+
+    impl ResolveOffsets for MockBroker {
+        fn fetch_partitions(&self, topic: &str, _: Duration) -> KafkaResult<Vec<i32>> {
+            self.partitions
+                .get(topic)
+                .cloned()
+                .ok_or(KafkaError::MetadataFetch(RDKafkaErrorCode::UnknownTopic))
+        }
+
+        fn fetch_offsets_for_times(
+            &self,
+            timestamps: TopicPartitionList,
+            _: Duration,
+        ) -> KafkaResult<TopicPartitionList> {
+            let mut offsets = TopicPartitionList::new();
+            for element in timestamps.elements() {
+                let offset = self
+                    .offsets
+                    .get(&(element.topic().to_owned(), element.partition()))
+                    .copied()
+                    .unwrap_or(Offset::End);
+                offsets.add_partition_offset(element.topic(), element.partition(), offset)?;
+            }
+            Ok(offsets)
+        }
+    }
+
+    fn to_vec(offsets: &TopicPartitionList) -> Vec<(String, i32, Offset)> {
+        offsets
+            .elements()
+            .iter()
+            .map(|element| {
+                (
+                    element.topic().to_owned(),
+                    element.partition(),
+                    element.offset(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolves_all_partitions_of_all_topics() {
+        let broker = MockBroker {
+            partitions: HashMap::from([
+                ("traces".to_owned(), vec![0, 1]),
+                ("events".to_owned(), vec![0]),
+            ]),
+            offsets: HashMap::from([
+                (("traces".to_owned(), 0), Offset::Offset(12)),
+                (("traces".to_owned(), 1), Offset::Offset(7)),
+                (("events".to_owned(), 0), Offset::Offset(3)),
+            ]),
+        };
+        let offsets = resolve_offsets_at_timestamp(
+            &broker,
+            &["traces", "events"],
+            1000,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert_eq!(
+            to_vec(&offsets),
+            [
+                ("traces".to_owned(), 0, Offset::Offset(12)),
+                ("traces".to_owned(), 1, Offset::Offset(7)),
+                ("events".to_owned(), 0, Offset::Offset(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn partitions_without_later_messages_start_at_end() {
+        let broker = MockBroker {
+            partitions: HashMap::from([("traces".to_owned(), vec![0, 1, 2])]),
+            offsets: HashMap::from([
+                (("traces".to_owned(), 0), Offset::Offset(5)),
+                (("traces".to_owned(), 1), Offset::End),
+                (("traces".to_owned(), 2), Offset::Invalid),
+            ]),
+        };
+        let offsets =
+            resolve_offsets_at_timestamp(&broker, &["traces"], 1000, Duration::from_secs(1))
+                .unwrap();
+        assert_eq!(
+            to_vec(&offsets),
+            [
+                ("traces".to_owned(), 0, Offset::Offset(5)),
+                ("traces".to_owned(), 1, Offset::End),
+                ("traces".to_owned(), 2, Offset::End),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_topic_is_an_error() {
+        let broker = MockBroker::default();
+        assert!(
+            resolve_offsets_at_timestamp(&broker, &["traces"], 1000, Duration::from_secs(1))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn tail_offsets_cover_every_partition() {
+        let broker = MockBroker {
+            partitions: HashMap::from([
+                ("config".to_owned(), vec![0, 1]),
+                ("control".to_owned(), vec![0]),
+            ]),
+            ..Default::default()
+        };
+        let offsets =
+            resolve_tail_offsets(&broker, &["config", "control"], 1, Duration::from_secs(1))
+                .unwrap();
+        assert_eq!(
+            to_vec(&offsets),
+            [
+                ("config".to_owned(), 0, Offset::OffsetTail(1)),
+                ("config".to_owned(), 1, Offset::OffsetTail(1)),
+                ("control".to_owned(), 0, Offset::OffsetTail(1)),
+            ]
+        );
+        assert!(resolve_tail_offsets(&broker, &["traces"], 1, Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Correlates the spans of each component which handles the same frame, even when the parent context
+//! of a span is lost between components, so that a frame's journey through the pipeline can be found as one trace.
+use digital_muon_streaming_types::FrameMetadata;
+use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+use rdkafka::message::{Header, Headers, OwnedHeaders};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+use tracing::{Span, debug};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The key of the Kafka header in which the [FrameCorrelation] of a message's frame is propagated.
+pub const FRAME_CORRELATION_HEADER: &str = "frame-correlation";
+
+const FNV_OFFSET_BASIS_128: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME_128: u128 = 0x0000000001000000000000000000013b;
+const FNV_OFFSET_BASIS_64: u64 = 0xcbf29ce484222325;
+const FNV_PRIME_64: u64 = 0x00000100000001b3;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FrameCorrelationError {
+    #[error("Frame correlation should be a trace id and span id separated by '-': {0}")]
+    Format(String),
+    #[error("Invalid trace id of frame correlation: {0}")]
+    TraceId(String),
+    #[error("Invalid span id of frame correlation: {0}")]
+    SpanId(String),
+}
+
+/// Identifies a frame by a trace id and span id derived deterministically from its [FrameMetadata],
+/// so that every component derives the same ids for the same frame, without needing to communicate.
+///
+/// The spans of each component are linked to the span context formed by these ids, see [Self::link_span].
+/// Components which alter the metadata of a frame, such as by rewriting its timestamp, should propagate
+/// the correlation in the [FRAME_CORRELATION_HEADER] header, which takes precedence over the metadata of the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FrameCorrelation {
+    trace_id: u128,
+    span_id: u64,
+}
+
+impl FrameCorrelation {
+    /// Derives the correlation of the frame with the given metadata, from its timestamp, frame number and period number.
+    ///
+    /// The ids are FNV-1a hashes, rather than those of [std::hash::DefaultHasher], whose algorithm may differ
+    /// between the versions of Rust with which each component is built.
+    pub fn new(metadata: &FrameMetadata) -> Self {
+        let bytes = [
+            metadata.timestamp.timestamp().to_le_bytes().as_slice(),
+            metadata
+                .timestamp
+                .timestamp_subsec_nanos()
+                .to_le_bytes()
+                .as_slice(),
+            metadata.frame_number.to_le_bytes().as_slice(),
+            metadata.period_number.to_le_bytes().as_slice(),
+        ]
+        .concat();
+        let trace_id = bytes.iter().fold(FNV_OFFSET_BASIS_128, |hash, &byte| {
+            (hash ^ u128::from(byte)).wrapping_mul(FNV_PRIME_128)
+        });
+        let span_id = bytes.iter().fold(FNV_OFFSET_BASIS_64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME_64)
+        });
+        // All zero ids are invalid in OpenTelemetry.
+        Self {
+            trace_id: trace_id.max(1),
+            span_id: span_id.max(1),
+        }
+    }
+
+    /// Returns the correlation in the [FRAME_CORRELATION_HEADER] header of `headers`,
+    /// or [None] if there is no such header, or its value is malformed.
+    pub fn from_headers<H: Headers>(headers: &H) -> Option<Self> {
+        let value = headers
+            .iter()
+            .find(|header| header.key == FRAME_CORRELATION_HEADER)?
+            .value?;
+        std::str::from_utf8(value)
+            .ok()?
+            .parse()
+            .inspect_err(|e| debug!("{e}"))
+            .ok()
+    }
+
+    /// Returns `headers` with the correlation inserted in the [FRAME_CORRELATION_HEADER] header.
+    pub fn insert_into(&self, headers: OwnedHeaders) -> OwnedHeaders {
+        headers.insert(Header {
+            key: FRAME_CORRELATION_HEADER,
+            value: Some(&self.to_string()),
+        })
+    }
+
+    /// The remote span context formed by the correlation's ids.
+    pub fn span_context(&self) -> SpanContext {
+        SpanContext::new(
+            TraceId::from_bytes(self.trace_id.to_be_bytes()),
+            SpanId::from_bytes(self.span_id.to_be_bytes()),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        )
+    }
+
+    /// Links `span` to the correlation's span context, this does nothing if OpenTelemetry is not used.
+    pub fn link_span(&self, span: &Span) {
+        span.add_link(self.span_context());
+    }
+}
+
+/// Formats the correlation as its hexadecimal trace id and span id, separated by `-`.
+impl fmt::Display for FrameCorrelation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}-{:016x}", self.trace_id, self.span_id)
+    }
+}
+
+impl FromStr for FrameCorrelation {
+    type Err = FrameCorrelationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (trace_id, span_id) = s
+            .split_once('-')
+            .ok_or_else(|| FrameCorrelationError::Format(s.to_owned()))?;
+        Ok(Self {
+            trace_id: u128::from_str_radix(trace_id, 16)
+                .map_err(|_| FrameCorrelationError::TraceId(trace_id.to_owned()))?,
+            span_id: u64::from_str_radix(span_id, 16)
+                .map_err(|_| FrameCorrelationError::SpanId(span_id.to_owned()))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    fn metadata(timestamp: &str, frame_number: u32, period_number: u64) -> FrameMetadata {
+        FrameMetadata {
+            timestamp: timestamp.parse::<DateTime<Utc>>().unwrap(),
+            period_number,
+            protons_per_pulse: 0,
+            running: true,
+            frame_number,
+            veto_flags: 0,
+        }
+    }
+
+    #[test]
+    fn correlation_is_deterministic() {
+        let correlation = FrameCorrelation::new(&metadata("2025-06-01T12:00:00.5Z", 7, 2));
+        assert_eq!(
+            correlation,
+            FrameCorrelation::new(&metadata("2025-06-01T12:00:00.5Z", 7, 2))
+        );
+        // Pinned, so that a change to the derivation, which would break correlation with components
+        // of earlier versions, is noticed.
+        assert_eq!(
+            correlation.to_string(),
+            "f0c03bbfc9423b2d5db0c624b6d6ec0d-ab8a9bd227f56f95"
+        );
+        assert!(correlation.span_context().is_valid());
+        assert!(correlation.span_context().is_remote());
+    }
+
+    #[test]
+    fn correlation_ignores_other_fields() {
+        let mut other = metadata("2025-06-01T12:00:00.5Z", 7, 2);
+        other.veto_flags = 3;
+        other.protons_per_pulse = 10;
+        other.running = false;
+        assert_eq!(
+            FrameCorrelation::new(&metadata("2025-06-01T12:00:00.5Z", 7, 2)),
+            FrameCorrelation::new(&other)
+        );
+    }
+
+    #[test]
+    fn correlation_distinguishes_frames() {
+        let correlation = FrameCorrelation::new(&metadata("2025-06-01T12:00:00.5Z", 7, 2));
+        for other in [
+            metadata("2025-06-01T12:00:00.500000001Z", 7, 2),
+            metadata("2025-06-01T12:00:01.5Z", 7, 2),
+            metadata("2025-06-01T12:00:00.5Z", 8, 2),
+            metadata("2025-06-01T12:00:00.5Z", 7, 3),
+        ] {
+            assert_ne!(correlation, FrameCorrelation::new(&other));
+        }
+    }
+
+    #[test]
+    fn correlation_round_trips_through_headers() {
+        let correlation = FrameCorrelation::new(&metadata("2025-06-01T12:00:00.5Z", 7, 2));
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "vetoed",
+            value: Some("0x0001"),
+        });
+        let headers = correlation.insert_into(headers);
+        assert_eq!(headers.count(), 2);
+        assert_eq!(FrameCorrelation::from_headers(&headers), Some(correlation));
+    }
+
+    #[test]
+    fn missing_or_malformed_header_is_none() {
+        assert_eq!(FrameCorrelation::from_headers(&OwnedHeaders::new()), None);
+        for value in ["", "0123", "xyz-0123", "0123-xyz"] {
+            let headers = OwnedHeaders::new().insert(Header {
+                key: FRAME_CORRELATION_HEADER,
+                value: Some(value),
+            });
+            assert_eq!(FrameCorrelation::from_headers(&headers), None);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "0123".parse::<FrameCorrelation>(),
+            Err(FrameCorrelationError::Format("0123".to_owned()))
+        );
+        assert_eq!(
+            "xyz-0123".parse::<FrameCorrelation>(),
+            Err(FrameCorrelationError::TraceId("xyz".to_owned()))
+        );
+        assert_eq!(
+            "0123-xyz".parse::<FrameCorrelation>(),
+            Err(FrameCorrelationError::SpanId("xyz".to_owned()))
+        );
+    }
+}
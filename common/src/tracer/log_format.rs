@@ -0,0 +1,272 @@
+//! Formats the log lines written to stdout, either as human-readable text, or as JSON which log aggregators can parse into fields.
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{
+    Layer,
+    field::RecordFields,
+    fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter, format::Writer},
+    registry::LookupSpan,
+};
+
+/// The formats in which log lines are written to stdout.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// A JSON object per line, into which the fields of the event, and of every span it is in, are flattened.
+    Json,
+}
+
+impl LogFormat {
+    /// Creates the layer which writes log lines in this format to `make_writer`.
+    pub(super) fn layer<S, W>(self, make_writer: W) -> Box<dyn Layer<S> + Send + Sync>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        match self {
+            LogFormat::Text => Box::new(tracing_subscriber::fmt::layer().with_writer(make_writer)),
+            LogFormat::Json => Box::new(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(FlattenedJsonFields)
+                    .event_format(FlattenedJson)
+                    .with_writer(make_writer),
+            ),
+        }
+    }
+}
+
+/// Records the fields of an event or span into a JSON object.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: impl Into<Value>) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}"));
+    }
+}
+
+/// Stores the fields of each span as a JSON object, which is merged with any fields recorded later.
+struct FlattenedJsonFields;
+
+impl<'writer> FormatFields<'writer> for FlattenedJsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut object = Map::new();
+        fields.record(&mut JsonVisitor(&mut object));
+        write!(writer, "{}", Value::Object(object))
+    }
+
+    fn add_fields(
+        &self,
+        current: &mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        let mut object = parse_object(current);
+        fields.record(&mut JsonVisitor(&mut object));
+        current.fields = Value::Object(object).to_string();
+        Ok(())
+    }
+}
+
+/// Parses the fields stored by [FlattenedJsonFields], which are empty if the span has none.
+fn parse_object(fields: &str) -> Map<String, Value> {
+    match serde_json::from_str(fields) {
+        Ok(Value::Object(object)) => object,
+        _ => Map::new(),
+    }
+}
+
+/// Writes each event as a JSON object, with the fields of its spans, from the outermost in, then those of the event itself.
+/// A field of an inner span, or of the event, replaces a field of the same name of an outer span.
+struct FlattenedJson;
+
+impl<S> FormatEvent<S, FlattenedJsonFields> for FlattenedJson
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, FlattenedJsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut record = Map::new();
+        record.insert(
+            "timestamp".to_owned(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        record.insert("level".to_owned(), metadata.level().as_str().into());
+        record.insert("target".to_owned(), metadata.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = Vec::new();
+            for span in scope.from_root() {
+                spans.push(Value::from(span.name()));
+                if let Some(fields) = span
+                    .extensions()
+                    .get::<FormattedFields<FlattenedJsonFields>>()
+                {
+                    record.extend(parse_object(fields));
+                }
+            }
+            record.insert("spans".to_owned(), spans.into());
+        }
+        event.record(&mut JsonVisitor(&mut record));
+        writeln!(writer, "{}", Value::Object(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_metadata_fields_to_span;
+    use chrono::DateTime;
+    use digital_muon_streaming_types::FrameMetadata;
+    use serde_json::json;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{debug, field::Empty, info, instrument};
+    use tracing_subscriber::{Registry, layer::SubscriberExt};
+
+    /// Captures the lines written by a layer.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        /// Parses each line captured, removing the fields whose values vary between runs.
+        fn records(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let mut record: Value = serde_json::from_str(line).unwrap();
+                    let fields = record.as_object_mut().unwrap();
+                    assert!(fields.remove("timestamp").is_some());
+                    assert_eq!(fields.remove("target"), Some(json!(module_path!())));
+                    record
+                })
+                .collect()
+        }
+    }
+
+    #[instrument(
+        skip_all,
+        fields(
+            digitizer_id = 4,
+            metadata_timestamp = Empty,
+            metadata_frame_number = Empty,
+            metadata_period_number = Empty,
+            metadata_veto_flags = Empty,
+            metadata_protons_per_pulse = Empty,
+            metadata_running = Empty,
+        )
+    )]
+    fn process_message(metadata: &FrameMetadata) {
+        record_metadata_fields_to_span!(metadata, tracing::Span::current());
+        debug!(
+            topic = "traces",
+            partition = 2,
+            offset = 31,
+            "Received message"
+        );
+    }
+
+    #[test]
+    fn json_lines_have_flattened_fields() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = Registry::default().with(LogFormat::Json.layer(move || writer.clone()));
+        let metadata = FrameMetadata {
+            timestamp: DateTime::from_timestamp_millis(1_000).unwrap(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 12,
+            veto_flags: 0,
+        };
+        tracing::subscriber::with_default(subscriber, || {
+            info!(count = 3, "Started");
+            process_message(&metadata);
+        });
+
+        let mut records = captured.records().into_iter();
+        assert_eq!(
+            records.next().unwrap(),
+            json!({ "level": "INFO", "message": "Started", "count": 3 })
+        );
+        assert_eq!(
+            records.next().unwrap(),
+            json!({
+                "level": "DEBUG",
+                "message": "Received message",
+                "spans": ["process_message"],
+                "topic": "traces",
+                "partition": 2,
+                "offset": 31,
+                "digitizer_id": 4,
+                "metadata_timestamp": metadata.timestamp.to_rfc3339(),
+                "metadata_frame_number": 12,
+                "metadata_period_number": 1,
+                "metadata_veto_flags": 0,
+                "metadata_protons_per_pulse": 8,
+                "metadata_running": true,
+            })
+        );
+        assert!(records.next().is_none());
+    }
+}
@@ -1,9 +1,15 @@
+mod correlation;
+mod log_format;
 mod otel_tracer;
 mod propagator;
+mod sampler;
 mod tracer_engine;
 
+pub use correlation::{FRAME_CORRELATION_HEADER, FrameCorrelation, FrameCorrelationError};
+pub use log_format::LogFormat;
 pub use otel_tracer::OtelTracer;
 pub use propagator::{FutureRecordTracerExt, OptionalHeaderTracerExt};
+pub use sampler::{OtelSamplingOpts, SamplingDecision, SpanSampler};
 pub use tracer_engine::{TracerEngine, TracerOptions};
 
 /// Should be called at the start of each component
@@ -1,3 +1,4 @@
+use super::FrameCorrelation;
 use opentelemetry::propagation::{Extractor, Injector};
 use rdkafka::{
     message::{BorrowedHeaders, Headers, OwnedHeaders},
@@ -54,6 +55,14 @@ pub trait FutureRecordTracerExt {
     fn optional_headers(self, headers: Option<OwnedHeaders>) -> Self;
     fn conditional_inject_current_span_into_headers(self, use_otel: bool) -> Self;
     fn conditional_inject_span_into_headers(self, use_otel: bool, span: &Span) -> Self;
+    /// Inserts `correlation`, if given, into the [FRAME_CORRELATION_HEADER] header, if `use_otel` is true.
+    ///
+    /// [FRAME_CORRELATION_HEADER]: super::FRAME_CORRELATION_HEADER
+    fn conditional_inject_frame_correlation(
+        self,
+        use_otel: bool,
+        correlation: Option<&FrameCorrelation>,
+    ) -> Self;
 }
 
 impl FutureRecordTracerExt for FutureRecord<'_, str, [u8]> {
@@ -80,6 +89,19 @@ impl FutureRecordTracerExt for FutureRecord<'_, str, [u8]> {
             self
         }
     }
+
+    fn conditional_inject_frame_correlation(
+        self,
+        use_otel: bool,
+        correlation: Option<&FrameCorrelation>,
+    ) -> Self {
+        if use_otel && let Some(correlation) = correlation {
+            let headers = self.headers.clone().unwrap_or_default();
+            self.headers(correlation.insert_into(headers))
+        } else {
+            self
+        }
+    }
 }
 
 /// May be used when the component consumne messages.
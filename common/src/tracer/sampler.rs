@@ -0,0 +1,180 @@
+use chrono::Utc;
+use clap::Args;
+use rand::{
+    SeedableRng,
+    distr::{Bernoulli, Distribution},
+    rngs::StdRng,
+};
+
+/// Options controlling which messages are traced in full by OpenTelemetry.
+#[derive(Clone, Debug, Args)]
+pub struct OtelSamplingOpts {
+    /// The fraction, between 0 and 1, of messages whose spans are sent to OpenTelemetry in full.
+    /// The remaining messages only have their top level spans sent.
+    #[clap(long, default_value = "1", value_parser = parse_ratio)]
+    pub otel_sample_ratio: f64,
+
+    /// If set, every message which fails is sent to OpenTelemetry in full, regardless of `otel-sample-ratio`.
+    #[clap(long)]
+    pub otel_always_sample_on_error: bool,
+}
+
+impl Default for OtelSamplingOpts {
+    fn default() -> Self {
+        Self {
+            otel_sample_ratio: 1.0,
+            otel_always_sample_on_error: false,
+        }
+    }
+}
+
+fn parse_ratio(ratio: &str) -> Result<f64, String> {
+    let ratio: f64 = ratio.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err(format!("{ratio} is not between 0 and 1"))
+    }
+}
+
+/// Whether a message's spans are sent to OpenTelemetry in full, see [SpanSampler::decide].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplingDecision {
+    /// Every span of the message is sent.
+    Sampled,
+    /// Only the message's top level spans are sent.
+    Unsampled,
+    /// Only the message's top level spans are sent, unless the message fails.
+    SampledOnFailure,
+}
+
+impl SamplingDecision {
+    /// Returns true if every span of the message should be sent.
+    /// # Parameters
+    /// - failed: whether the message has failed.
+    pub fn is_sampled(self, failed: bool) -> bool {
+        match self {
+            Self::Sampled => true,
+            Self::Unsampled => false,
+            Self::SampledOnFailure => failed,
+        }
+    }
+}
+
+/// Decides which messages are traced in full, according to [OtelSamplingOpts].
+///
+/// The decision is made once per message, before any of its child spans are created,
+/// so those of unsampled messages can be skipped entirely, rather than created and discarded.
+pub struct SpanSampler {
+    ratio: Bernoulli,
+    always_sample_on_error: bool,
+    rng: StdRng,
+}
+
+impl SpanSampler {
+    /// Creates a sampler whose decisions are determined by `seed`.
+    pub fn new(options: &OtelSamplingOpts, seed: u64) -> Self {
+        // A ratio outside of [0,1] is rejected by the Cli, so is replaced here rather than reported.
+        let ratio = Some(options.otel_sample_ratio)
+            .filter(|ratio| (0.0..=1.0).contains(ratio))
+            .unwrap_or(1.0);
+        Self {
+            ratio: Bernoulli::new(ratio).expect("Ratio is a valid probability, this never fails"),
+            always_sample_on_error: options.otel_always_sample_on_error,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Creates a sampler seeded from the current time.
+    pub fn from_time(options: &OtelSamplingOpts) -> Self {
+        Self::new(options, Utc::now().timestamp_subsec_nanos() as u64)
+    }
+
+    /// Creates a sampler which samples every message.
+    pub fn always() -> Self {
+        Self::new(&OtelSamplingOpts::default(), 0)
+    }
+
+    /// Decides whether the next message is traced in full.
+    pub fn decide(&mut self) -> SamplingDecision {
+        if self.ratio.sample(&mut self.rng) {
+            SamplingDecision::Sampled
+        } else if self.always_sample_on_error {
+            SamplingDecision::SampledOnFailure
+        } else {
+            SamplingDecision::Unsampled
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(otel_sample_ratio: f64, otel_always_sample_on_error: bool) -> OtelSamplingOpts {
+        OtelSamplingOpts {
+            otel_sample_ratio,
+            otel_always_sample_on_error,
+        }
+    }
+
+    fn decisions(sampler: &mut SpanSampler, num: usize) -> Vec<SamplingDecision> {
+        (0..num).map(|_| sampler.decide()).collect()
+    }
+
+    #[test]
+    fn decisions_are_determined_by_seed() {
+        let options = options(0.5, false);
+        assert_eq!(
+            decisions(&mut SpanSampler::new(&options, 42), 100),
+            decisions(&mut SpanSampler::new(&options, 42), 100)
+        );
+    }
+
+    #[test]
+    fn ratio_of_messages_are_sampled() {
+        let mut sampler = SpanSampler::new(&options(0.25, false), 7);
+        let num_sampled = decisions(&mut sampler, 10_000)
+            .into_iter()
+            .filter(|decision| decision.is_sampled(false))
+            .count();
+        assert!((2300..2700).contains(&num_sampled), "{num_sampled}");
+    }
+
+    #[test]
+    fn extreme_ratios() {
+        let mut sampler = SpanSampler::new(&options(0.0, false), 7);
+        assert!(
+            decisions(&mut sampler, 100)
+                .into_iter()
+                .all(|decision| decision == SamplingDecision::Unsampled)
+        );
+        let mut sampler = SpanSampler::always();
+        assert!(
+            decisions(&mut sampler, 100)
+                .into_iter()
+                .all(|decision| decision == SamplingDecision::Sampled)
+        );
+    }
+
+    #[test]
+    fn failures_force_sampling() {
+        let mut sampler = SpanSampler::new(&options(0.0, true), 7);
+        let decision = sampler.decide();
+        assert_eq!(decision, SamplingDecision::SampledOnFailure);
+        assert!(!decision.is_sampled(false));
+        assert!(decision.is_sampled(true));
+
+        // Without `otel-always-sample-on-error`, failures make no difference.
+        let decision = SpanSampler::new(&options(0.0, false), 7).decide();
+        assert!(!decision.is_sampled(true));
+    }
+
+    #[test]
+    fn ratio_is_parsed() {
+        assert_eq!(parse_ratio("0.1"), Ok(0.1));
+        assert!(parse_ratio("1.5").is_err());
+        assert!(parse_ratio("-0.1").is_err());
+        assert!(parse_ratio("half").is_err());
+    }
+}
@@ -1,4 +1,8 @@
-use super::otel_tracer::{OtelOptions, OtelTracer};
+use super::{
+    log_format::LogFormat,
+    otel_tracer::{OtelOptions, OtelTracer},
+    sampler::{OtelSamplingOpts, SpanSampler},
+};
 use opentelemetry_otlp::ExporterBuildError;
 use opentelemetry_sdk::trace::SdkTracerProvider;
 use tracing::{Span, warn};
@@ -7,6 +11,8 @@ use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt};
 
 pub struct TracerOptions<'a> {
     otel_options: Option<OtelOptions<'a>>,
+    sampling: OtelSamplingOpts,
+    log_format: LogFormat,
 }
 
 impl<'a> TracerOptions<'a> {
@@ -16,8 +22,21 @@ impl<'a> TracerOptions<'a> {
                 endpoint,
                 namespace,
             }),
+            sampling: Default::default(),
+            log_format: Default::default(),
         }
     }
+
+    /// Sets which messages are traced in full, see [TracerEngine::span_sampler].
+    pub fn with_sampling(self, sampling: OtelSamplingOpts) -> Self {
+        Self { sampling, ..self }
+    }
+
+    /// Sets the format of the log lines written to stdout.
+    /// [LogFormat::Json] is only used if no OpenTelemetry endpoint is given, otherwise lines are written as text.
+    pub fn with_log_format(self, log_format: LogFormat) -> Self {
+        Self { log_format, ..self }
+    }
 }
 
 /// This object initialises all tracers, given a TracerOptions struct.
@@ -27,6 +46,7 @@ pub struct TracerEngine {
     use_otel: bool,
     otel_tracer_provider: Option<SdkTracerProvider>,
     otel_setup_error: Option<ExporterBuildError>,
+    sampling: OtelSamplingOpts,
 }
 
 impl TracerEngine {
@@ -42,7 +62,12 @@ impl TracerEngine {
     pub fn new(options: TracerOptions, service_name: &str) -> Self {
         let use_otel = options.otel_options.is_some();
 
-        let stdout_tracer = tracing_subscriber::fmt::layer().with_writer(std::io::stdout);
+        let log_format = if use_otel {
+            LogFormat::Text
+        } else {
+            options.log_format
+        };
+        let stdout_tracer = log_format.layer(std::io::stdout);
 
         // if options.otel_options is provided then attempt to setup OtelTracer
         let (otel_tracer, otel_setup_error) = options
@@ -75,6 +100,7 @@ impl TracerEngine {
             use_otel,
             otel_tracer_provider,
             otel_setup_error,
+            sampling: options.sampling,
         }
     }
 
@@ -92,6 +118,16 @@ impl TracerEngine {
     pub fn get_otel_setup_error(&self) -> Option<&ExporterBuildError> {
         self.otel_setup_error.as_ref()
     }
+
+    /// Creates a sampler deciding which messages are traced in full, according to the options given by [TracerOptions::with_sampling].
+    /// If OpenTelemetry is not used, every message is sampled.
+    pub fn span_sampler(&self) -> SpanSampler {
+        if self.use_otel {
+            SpanSampler::from_time(&self.sampling)
+        } else {
+            SpanSampler::always()
+        }
+    }
 }
 
 impl Drop for TracerEngine {
@@ -0,0 +1,107 @@
+//! Tests the [digital_muon_common::seek] module against a real Kafka broker.
+//!
+//! Run with `cargo test -p digital-muon-common --features broker-tests`, the broker address
+//! is read from the `KAFKA_BROKER` environment variable, and defaults to "localhost:9092".
+#![cfg(feature = "broker-tests")]
+
+use digital_muon_common::seek::{create_consumer_at_offsets, create_consumer_at_timestamp};
+use rdkafka::{
+    ClientConfig, Message, Offset, TopicPartitionList,
+    admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+    producer::{FutureProducer, FutureRecord},
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn broker() -> String {
+    std::env::var("KAFKA_BROKER").unwrap_or_else(|_| "localhost:9092".to_owned())
+}
+
+/// Creates a uniquely named topic with two partitions, and writes messages with the given
+/// timestamps (in milliseconds) and payloads to the given partitions.
+async fn create_topic_with_messages(messages: &[(i32, i64, &str)]) -> String {
+    let topic = format!(
+        "seek-test-{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+    let config = ClientConfig::new()
+        .set("bootstrap.servers", broker())
+        .clone();
+
+    let admin: AdminClient<_> = config.create().unwrap();
+    admin
+        .create_topics(
+            &[NewTopic::new(&topic, 2, TopicReplication::Fixed(1))],
+            &AdminOptions::new(),
+        )
+        .await
+        .unwrap();
+
+    let producer: FutureProducer = config.create().unwrap();
+    for &(partition, timestamp, payload) in messages {
+        producer
+            .send(
+                FutureRecord::<(), _>::to(&topic)
+                    .partition(partition)
+                    .timestamp(timestamp)
+                    .payload(payload),
+                TIMEOUT,
+            )
+            .await
+            .unwrap();
+    }
+    topic
+}
+
+#[tokio::test]
+async fn consumer_at_timestamp_starts_from_first_later_message() {
+    let topic = create_topic_with_messages(&[
+        (0, 1_000, "a"),
+        (0, 2_000, "b"),
+        (0, 3_000, "c"),
+        (1, 1_500, "d"),
+    ])
+    .await;
+
+    let consumer = create_consumer_at_timestamp(
+        &broker(),
+        &None,
+        &None,
+        &"seek-test".to_owned(),
+        &[&topic],
+        2_000,
+        TIMEOUT,
+    )
+    .unwrap();
+
+    // Partition 1 has no messages after the timestamp so is consumed from its end.
+    let mut payloads = Vec::new();
+    while let Ok(Ok(msg)) = timeout(Duration::from_secs(5), consumer.recv()).await {
+        payloads.push(String::from_utf8(msg.payload().unwrap().to_vec()).unwrap());
+    }
+    assert_eq!(payloads, ["b", "c"]);
+}
+
+#[tokio::test]
+async fn consumer_at_offsets_starts_from_offsets() {
+    let topic =
+        create_topic_with_messages(&[(0, 1_000, "a"), (0, 2_000, "b"), (1, 1_500, "c")]).await;
+
+    let mut offsets = TopicPartitionList::new();
+    offsets
+        .add_partition_offset(&topic, 0, Offset::Offset(1))
+        .unwrap();
+    let consumer =
+        create_consumer_at_offsets(&broker(), &None, &None, &"seek-test".to_owned(), &offsets)
+            .unwrap();
+
+    let msg = timeout(TIMEOUT, consumer.recv()).await.unwrap().unwrap();
+    assert_eq!(msg.partition(), 0);
+    assert_eq!(msg.offset(), 1);
+    assert_eq!(msg.payload(), Some("b".as_bytes()));
+}
@@ -46,6 +46,7 @@ pub(crate) async fn run(args: DaqTraceOpts) -> miette::Result<()> {
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
+        None,
     )
     .set("group.id", &args.common.consumer_group)
     .set("enable.partition.eof", "false")
@@ -16,6 +16,7 @@ pub(crate) async fn run(args: CommonOpts) -> miette::Result<()> {
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
+        None,
     )
     .set("group.id", &args.consumer_group)
     .set("enable.partition.eof", "false")
@@ -12,6 +12,9 @@ use digital_muon_streaming_types::{
     frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args},
 };
 
+/// The polarity of an event whose pulse is of the polarity configured in `trace-to-events`.
+const POSITIVE_PULSE: i8 = 1;
+
 /// Event list, either for a digitiser message, or frame message.
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct EventData {
@@ -21,15 +24,19 @@ pub(crate) struct EventData {
     intensity: Vec<Intensity>,
     /// Id of the detector which registered the event.
     channel: Vec<Channel>,
+    /// Sign of the pulse of the event, [POSITIVE_PULSE] unless it is opposite the configured polarity.
+    polarity: Vec<i8>,
 }
 
 impl EventData {
     #[cfg(test)]
     pub(crate) fn new(time: Vec<Time>, intensity: Vec<Intensity>, channel: Vec<Channel>) -> Self {
+        let polarity = vec![POSITIVE_PULSE; time.len()];
         Self {
             time,
             intensity,
             channel,
+            polarity,
         }
     }
 
@@ -59,6 +66,7 @@ impl EventData {
             time,
             intensity,
             channel,
+            polarity: vec![POSITIVE_PULSE; channels.len() * events_per_channel],
         }
     }
 
@@ -72,6 +80,7 @@ impl EventData {
             time: Vec::with_capacity(capacity),
             intensity: Vec::with_capacity(capacity),
             channel: Vec::with_capacity(capacity),
+            polarity: Vec::with_capacity(capacity),
         }
     }
 
@@ -92,11 +101,16 @@ impl<'a> From<DigitizerEventListMessage<'a>> for EventData {
             .expect("data should have intensities")
             .iter()
             .collect();
-        let channel = msg
+        let channel: Vec<Channel> = msg
             .channel()
             .expect("data should have channel numbers")
             .iter()
             .collect();
+        // The polarity vector is absent unless both polarities are detected, in which case every pulse is positive.
+        let polarity = msg.polarity().map_or_else(
+            || vec![POSITIVE_PULSE; channel.len()],
+            |polarity| polarity.iter().collect(),
+        );
 
         // The guarantee that all fields are of equal length depends on the inputs
         // having fields of equal length. This is guaranteed by the `trace-to-events`
@@ -105,6 +119,7 @@ impl<'a> From<DigitizerEventListMessage<'a>> for EventData {
             time,
             intensity,
             channel,
+            polarity,
         }
     }
 }
@@ -120,6 +135,7 @@ impl Accumulate<EventData> for DigitiserData<EventData> {
                 acc.time.append(&mut value.1.time);
                 acc.intensity.append(&mut value.1.intensity);
                 acc.channel.append(&mut value.1.channel);
+                acc.polarity.append(&mut value.1.polarity);
                 acc
             })
     }
@@ -140,6 +156,13 @@ impl From<AggregatedFrame<EventData>> for Vec<u8> {
         };
         let metadata = FrameMetadataV2::create(&mut fbb, &metadata);
 
+        // As in the digitisers' messages, the polarity vector is only present if any pulse is not positive.
+        let polarity = &frame.digitiser_data.polarity;
+        let polarity = polarity
+            .iter()
+            .any(|&polarity| polarity != POSITIVE_PULSE)
+            .then(|| fbb.create_vector::<i8>(polarity));
+
         let message = FrameAssembledEventListMessageArgs {
             metadata: Some(metadata),
             time: Some(fbb.create_vector::<Time>(&frame.digitiser_data.time)),
@@ -147,6 +170,7 @@ impl From<AggregatedFrame<EventData>> for Vec<u8> {
             channel: Some(fbb.create_vector::<Channel>(&frame.digitiser_data.channel)),
             complete: frame.complete,
             digitizers_present: Some(fbb.create_vector::<DigitizerId>(&frame.digitiser_ids)),
+            polarity,
         };
         let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
 
@@ -159,7 +183,14 @@ impl From<AggregatedFrame<EventData>> for Vec<u8> {
 #[cfg(test)]
 mod test {
     use chrono::Utc;
-    use digital_muon_streaming_types::FrameMetadata;
+    use digital_muon_streaming_types::{
+        FrameMetadata,
+        aev2_frame_assembled_event_v2_generated::root_as_frame_assembled_event_list_message,
+        dev2_digitizer_event_v2_generated::{
+            DigitizerEventListMessageArgs, finish_digitizer_event_list_message_buffer,
+            root_as_digitizer_event_list_message,
+        },
+    };
 
     use super::*;
 
@@ -202,6 +233,7 @@ mod test {
                 channel: Some(fbb.create_vector::<Channel>(&[1, 3, 1, 0, 4])),
                 complete: true,
                 digitizers_present: Some(fbb.create_vector::<DigitizerId>(&[0, 1])),
+                polarity: None,
             };
             let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
 
@@ -222,15 +254,79 @@ mod test {
                 },
                 true,
                 vec![0, 1],
-                EventData {
-                    time: vec![1, 2, 8, 9, 7],
-                    intensity: vec![2, 8, 8, 2, 7],
-                    channel: vec![1, 3, 1, 0, 4],
-                },
+                EventData::new(
+                    vec![1, 2, 8, 9, 7],
+                    vec![2, 8, 8, 2, 7],
+                    vec![1, 3, 1, 0, 4],
+                ),
             );
             frame.into()
         };
 
         assert_eq!(test, reference);
     }
+
+    #[test]
+    fn polarities_are_aggregated() {
+        let digitiser_message = |polarity: Option<&[i8]>| {
+            let mut fbb = FlatBufferBuilder::new();
+            let timestamp = Utc::now().into();
+            let metadata = FrameMetadataV2::create(
+                &mut fbb,
+                &FrameMetadataV2Args {
+                    timestamp: Some(&timestamp),
+                    ..Default::default()
+                },
+            );
+            let message = DigitizerEventListMessageArgs {
+                metadata: Some(metadata),
+                time: Some(fbb.create_vector::<Time>(&[1, 2])),
+                voltage: Some(fbb.create_vector::<Intensity>(&[3, 4])),
+                channel: Some(fbb.create_vector::<Channel>(&[5, 6])),
+                polarity: polarity.map(|polarity| fbb.create_vector(polarity)),
+                ..Default::default()
+            };
+            let message = DigitizerEventListMessage::create(&mut fbb, &message);
+            finish_digitizer_event_list_message_buffer(&mut fbb, message);
+            EventData::from(root_as_digitizer_event_list_message(fbb.finished_data()).unwrap())
+        };
+        let frame_polarity = |digitiser_data: Vec<EventData>| {
+            let mut digitiser_data = (0..).zip(digitiser_data).collect();
+            let frame = AggregatedFrame::new(
+                FrameMetadata {
+                    timestamp: Utc::now(),
+                    period_number: 0,
+                    protons_per_pulse: 0,
+                    running: true,
+                    frame_number: 0,
+                    veto_flags: 0,
+                },
+                true,
+                vec![0, 1],
+                DigitiserData::<EventData>::accumulate(&mut digitiser_data),
+            );
+            let bytes: Vec<u8> = frame.into();
+            root_as_frame_assembled_event_list_message(&bytes)
+                .unwrap()
+                .polarity()
+                .map(|polarity| polarity.iter().collect::<Vec<_>>())
+        };
+
+        // Without an opposite pulse in any digitiser's message, the frame has no polarity vector.
+        assert_eq!(
+            frame_polarity(vec![
+                digitiser_message(None),
+                digitiser_message(Some(&[1, 1]))
+            ]),
+            None
+        );
+        // Digitisers which do not detect both polarities have only positive pulses.
+        assert_eq!(
+            frame_polarity(vec![
+                digitiser_message(None),
+                digitiser_message(Some(&[1, -1]))
+            ]),
+            Some(vec![1, 1, 1, -1])
+        );
+    }
 }
@@ -1,11 +1,14 @@
 //! Defines the struct for a frame which is ready to be dispatched.
-use super::partial::PartialFrame;
+use super::{SUPPLEMENT_HEADER, VETOED_HEADER, format_veto_flags, partial::PartialFrame};
 use crate::data::{Accumulate, DigitiserData};
 use digital_muon_common::{
     DigitizerId,
     spanned::{SpanOnce, Spanned, SpannedMut},
+    tracer::FrameCorrelation,
 };
 use digital_muon_streaming_types::FrameMetadata;
+use itertools::Itertools;
+use rdkafka::message::{Header, OwnedHeaders};
 
 /// A frame with that is ready to be dispatched.
 pub(crate) struct AggregatedFrame<D> {
@@ -15,12 +18,24 @@ pub(crate) struct AggregatedFrame<D> {
     span: SpanOnce,
     /// The uniquely identifying metadata of the frame, common to all digitiser messages related to this frame (except possibly for [FrameMetadata::veto_flags]).
     pub(crate) metadata: FrameMetadata,
+    /// The correlation of the frame, which is propagated in the header of the dispatched message.
+    pub(crate) correlation: FrameCorrelation,
     /// Is `true` if and only if the frame has received data frame all expected digitisers.
     pub(crate) complete: bool,
     /// List of digitisers from which the frame has received data.
     pub(crate) digitiser_ids: Vec<DigitizerId>,
     /// The frame's event data.
     pub(crate) digitiser_data: D,
+    /// The bits of [FrameMetadata::veto_flags] which are set in the veto mask,
+    /// these are only non-zero if the frame is forwarded by [VetoPolicy::ForwardFlagged].
+    ///
+    /// [VetoPolicy::ForwardFlagged]: super::VetoPolicy::ForwardFlagged
+    pub(crate) vetoed: u16,
+    /// Is `true` if and only if the frame contains only the data of a late digitiser message, for a frame already dispatched,
+    /// these are only dispatched by [LatePolicy::EmitSupplement].
+    ///
+    /// [LatePolicy::EmitSupplement]: super::LatePolicy::EmitSupplement
+    pub(crate) supplement: bool,
 }
 
 #[cfg(test)]
@@ -33,14 +48,34 @@ impl<D> AggregatedFrame<D> {
     ) -> Self {
         Self {
             span: Default::default(),
+            correlation: FrameCorrelation::new(&metadata),
             metadata,
             complete,
             digitiser_ids,
             digitiser_data,
+            vetoed: 0,
+            supplement: false,
         }
     }
 }
 
+impl<D> AggregatedFrame<D> {
+    /// Returns the headers marking the frame as vetoed, or as a supplement, or [None] if it is neither.
+    pub(crate) fn headers(&self) -> Option<OwnedHeaders> {
+        let vetoed = (self.vetoed != 0).then(|| format_veto_flags(self.vetoed));
+        let supplement = self.supplement.then(|| self.digitiser_ids.iter().join(","));
+        [(VETOED_HEADER, vetoed), (SUPPLEMENT_HEADER, supplement)]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .fold(None, |headers, (key, value)| {
+                Some(headers.unwrap_or_else(OwnedHeaders::new).insert(Header {
+                    key,
+                    value: Some(&value),
+                }))
+            })
+    }
+}
+
 impl<D> From<PartialFrame<D>> for AggregatedFrame<D>
 where
     DigitiserData<D>: Accumulate<D>,
@@ -52,11 +87,14 @@ where
                 .take()
                 .expect("partial frame should have a span"),
             metadata: partial.metadata.clone(),
+            correlation: partial.correlation,
             complete: partial.is_complete(),
             digitiser_ids: partial.digitiser_ids(),
             digitiser_data: <DigitiserData<D> as Accumulate<D>>::accumulate(
                 &mut partial.digitiser_data,
             ),
+            vetoed: 0,
+            supplement: false,
         }
     }
 }
@@ -1,31 +1,84 @@
 //! Defines the cache stores frames as they are assembled from digitiser messages.
-use super::{AggregatedFrame, RejectMessageError, partial::PartialFrame};
+use super::{
+    AggregatedFrame, Arrival, DuplicatePolicy, LatePolicy, PartialFramePolicy, RejectMessageError,
+    VetoPolicy, format_veto_flags,
+    partial::PartialFrame,
+    run::{Run, RunDigitisers},
+};
 use crate::{
     data::{Accumulate, DigitiserData},
     frame::FrameCacheError,
 };
 use chrono::{DateTime, Utc};
 use digital_muon_common::{
-    DigitizerId, record_metadata_fields_to_span, spanned::SpannedAggregator,
+    DigitizerId, FrameKey,
+    metrics::names::{
+        DUPLICATE_DIGITISER_MESSAGES, EXPECTED_DIGITISERS, FRAME_ASSEMBLY_DURATION_MS,
+        FRAME_COMPLETION_LATENCY_MS, FRAMES_IN_FLIGHT, LATE_DIGITISER_MESSAGES,
+        PARTIAL_FRAMES_DROPPED, PARTIAL_FRAMES_EMITTED, VETOED_FRAMES,
+    },
+    record_metadata_fields_to_span,
+    spanned::SpannedAggregator,
+    tracer::FrameCorrelation,
 };
 use digital_muon_streaming_types::FrameMetadata;
 use itertools::Itertools;
+use metrics::{counter, gauge, histogram};
 use std::{collections::VecDeque, fmt::Debug, time::Duration};
-use tracing::{info_span, warn};
+use tokio::time::Instant;
+use tracing::{debug, info, info_span, warn};
+
+/// A frame which has been dispatched, remembered for the late arrival window
+/// so that late digitiser messages can be matched to it.
+struct DispatchedFrame {
+    /// Identifies the frame, ignoring veto flags, so that late digitiser messages can be matched to it.
+    key: FrameKey,
+    /// The metadata of the frame, as dispatched.
+    metadata: FrameMetadata,
+    /// The correlation of the frame, as dispatched, which is given to its supplements.
+    correlation: FrameCorrelation,
+    /// List of digitisers whose data has been dispatched, either in the frame or in a supplement to it.
+    digitiser_ids: Vec<DigitizerId>,
+    /// The vetoed flags with which the frame was dispatched.
+    vetoed: u16,
+    /// Time at which the frame was dispatched.
+    dispatched_at: Instant,
+}
 
 /// Contains all the partial frames as well as handling the frame lifetime and completeness.
 pub(crate) struct FrameCache<D: Debug> {
     /// Specifies the maximum time that a partial frame should live
     /// in the cache before being dispatched event if it is missing some digitisers.
     ttl: Duration,
+    /// Specifies what happens to partial frames which expire before they are complete.
+    partial_frame_policy: PartialFramePolicy,
+    /// Specifies what happens to messages from a digitiser which has already contributed to their frame.
+    duplicate_policy: DuplicatePolicy,
+    /// Frames whose veto flags share any bits with this mask are vetoed.
+    veto_mask: u16,
+    /// Specifies what happens to vetoed frames.
+    veto_policy: VetoPolicy,
+    /// Specifies how long after a frame is dispatched that messages from its missing digitisers are late, rather than too late.
+    late_arrival_window: Duration,
+    /// Specifies what happens to late messages.
+    late_policy: LatePolicy,
     /// Specifies the complete set of digitisers
     /// a partial frame should have before being complete.
     expected_digitisers: Vec<DigitizerId>,
+    /// The number of frames at the start of each run from which the digitisers expected in its remaining frames are learned.
+    /// If zero, the digitisers are not learned.
+    learn_digitisers_frames: usize,
+    /// The runs which have started, in order of their start times, until every frame they contain has been dispatched.
+    runs: VecDeque<Run>,
     /// The metadata timestamp of the last frame to be dispatched,
     /// value is [None] if no frame has been dispatched yet.
     latest_timestamp_dispatched: Option<DateTime<Utc>>,
     /// The partial frames currently in the cache.
     frames: VecDeque<PartialFrame<D>>,
+    /// The frames dispatched within the late arrival window, in the order they were dispatched.
+    dispatched: VecDeque<DispatchedFrame>,
+    /// The supplementary frames created from late messages, awaiting dispatch.
+    supplements: VecDeque<AggregatedFrame<D>>,
 }
 
 impl<D: Debug> FrameCache<D>
@@ -51,53 +104,253 @@ where
         if duplicates.is_empty() {
             Ok(Self {
                 ttl,
+                partial_frame_policy: Default::default(),
+                duplicate_policy: Default::default(),
+                veto_mask: 0,
+                veto_policy: Default::default(),
+                late_arrival_window: Duration::ZERO,
+                late_policy: Default::default(),
                 expected_digitisers,
+                learn_digitisers_frames: 0,
+                runs: Default::default(),
                 latest_timestamp_dispatched: None,
                 frames: Default::default(),
+                dispatched: Default::default(),
+                supplements: Default::default(),
             })
         } else {
             Err(FrameCacheError::DuplicateDigitiserId(duplicates))
         }
     }
 
+    /// Sets what happens to partial frames which expire before they are complete.
+    pub(crate) fn with_partial_frame_policy(
+        mut self,
+        partial_frame_policy: PartialFramePolicy,
+    ) -> Self {
+        self.partial_frame_policy = partial_frame_policy;
+        self
+    }
+
+    /// Sets what happens to messages from a digitiser which has already contributed to their frame.
+    pub(crate) fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// Sets which frames are vetoed, and what happens to them.
+    /// A frame is vetoed if its veto flags share any bits with `veto_mask`,
+    /// so if `veto_mask` is zero, no frame is vetoed.
+    pub(crate) fn with_veto_filter(mut self, veto_mask: u16, veto_policy: VetoPolicy) -> Self {
+        self.veto_mask = veto_mask;
+        self.veto_policy = veto_policy;
+        self
+    }
+
+    /// Sets how long after a frame is dispatched that messages from its missing digitisers are late,
+    /// and what happens to them. If `late_arrival_window` is zero, no message is late, and all are too late.
+    pub(crate) fn with_late_arrival(
+        mut self,
+        late_arrival_window: Duration,
+        late_policy: LatePolicy,
+    ) -> Self {
+        self.late_arrival_window = late_arrival_window;
+        self.late_policy = late_policy;
+        self
+    }
+
+    /// Sets the number of frames at the start of each run, which does not declare its digitisers,
+    /// from which the digitisers expected in the remaining frames of the run are learned, see [Self::start_run].
+    /// If zero, the digitisers are not learned.
+    pub(crate) fn with_learned_digitisers(mut self, learn_digitisers_frames: usize) -> Self {
+        self.learn_digitisers_frames = learn_digitisers_frames;
+        self
+    }
+
+    /// Starts the run `run_name` at `start`. Frames whose timestamps are from `start` until the run is stopped
+    /// are complete once they have received messages from the `declared` digitisers, if given.
+    /// Otherwise, if learning is enabled, they are complete once they have received messages from each digitiser
+    /// seen in the first frames of the run to be dispatched, and until then from the digitisers given on construction.
+    ///
+    /// Any earlier run which has not been stopped is stopped at `start`.
+    /// Frames already in the cache are reevaluated against the new expected digitisers.
+    pub(crate) fn start_run(
+        &mut self,
+        run_name: &str,
+        start: DateTime<Utc>,
+        declared: Option<Vec<DigitizerId>>,
+    ) {
+        let previous = self.current_expected_digitisers().to_vec();
+        for run in self
+            .runs
+            .iter_mut()
+            .filter(|run| run.stop.is_none() && run.start < start)
+        {
+            warn!(
+                "Run {0} was not stopped before run {run_name} started, stopping it at {start}",
+                run.name
+            );
+            run.stop = Some(start);
+        }
+        let run = Run {
+            name: run_name.to_owned(),
+            start,
+            stop: None,
+            digitisers: RunDigitisers::new(declared, self.learn_digitisers_frames),
+        };
+        match &run.digitisers {
+            Some(RunDigitisers::Learning { .. }) => info!(
+                "Run {run_name} started at {start}, learning expected digitisers from its first {0} frames, until then expecting: {1:?}",
+                self.learn_digitisers_frames, self.expected_digitisers
+            ),
+            Some(run_digitisers) => info!(
+                "Run {run_name} started at {start}, expecting {0} digitisers: {1:?}",
+                run_digitisers.source(),
+                run.digitiser_ids().unwrap_or_default()
+            ),
+            None => info!(
+                "Run {run_name} started at {start}, expecting digitisers: {0:?}",
+                self.expected_digitisers
+            ),
+        }
+        let index = self
+            .runs
+            .partition_point(|existing| existing.start <= start);
+        self.runs.insert(index, run);
+        self.update_expected_digitisers(&previous);
+    }
+
+    /// Stops the run `run_name` at `stop`, so that frames whose timestamps are from `stop` are complete
+    /// once they have received messages from the digitisers given on construction, unless they belong to a later run.
+    ///
+    /// If no run of this name is in progress, the stop is logged and ignored.
+    pub(crate) fn stop_run(&mut self, run_name: &str, stop: DateTime<Utc>) {
+        let previous = self.current_expected_digitisers().to_vec();
+        let Some(run) = self
+            .runs
+            .iter_mut()
+            .rev()
+            .find(|run| run.name == run_name && run.stop.is_none())
+        else {
+            warn!("Ignoring stop of run {run_name}, which is not in progress");
+            return;
+        };
+        run.stop = Some(stop.max(run.start));
+        info!("Run {run_name} stopped at {stop}");
+        self.update_expected_digitisers(&previous);
+    }
+
+    /// Returns the digitisers from which a frame of the latest run, if it is in progress, must receive messages before it is complete,
+    /// otherwise those given on construction.
+    pub(crate) fn current_expected_digitisers(&self) -> &[DigitizerId] {
+        self.runs
+            .back()
+            .filter(|run| run.stop.is_none())
+            .and_then(Run::digitiser_ids)
+            .unwrap_or(&self.expected_digitisers)
+    }
+
+    /// Sets the [EXPECTED_DIGITISERS] gauge, labelled by digitiser id, to one for each digitiser currently expected.
+    pub(crate) fn record_expected_digitisers(&self) {
+        for digitiser_id in self.current_expected_digitisers() {
+            gauge!(EXPECTED_DIGITISERS, "digitiser_id" => digitiser_id.to_string()).set(1);
+        }
+    }
+
+    /// Records the change of the currently expected digitisers from `previous`, and marks complete
+    /// any frame in the cache which has received messages from each of the digitisers now expected of it.
+    fn update_expected_digitisers(&mut self, previous: &[DigitizerId]) {
+        let current = self.current_expected_digitisers();
+        for digitiser_id in previous.iter().filter(|id| !current.contains(id)) {
+            gauge!(EXPECTED_DIGITISERS, "digitiser_id" => digitiser_id.to_string()).set(0);
+        }
+        self.record_expected_digitisers();
+
+        for frame in self.frames.iter_mut().filter(|frame| !frame.is_complete()) {
+            frame.set_completion_status(expected_digitisers_at(
+                &self.expected_digitisers,
+                &self.runs,
+                frame.metadata.timestamp,
+            ));
+            if frame.is_complete() {
+                record_frame_assembly(frame);
+            }
+        }
+        self.record_frames_in_flight();
+    }
+
     /// Pushes the contents of a new digitiser message into the cache.
     /// If a partial frame with the same `metadata` already exists, and is yet
     /// to receive a message with the same `digitiser_id`, then `data` is added
     /// to the partial frame, otherwise a new [PartialFrame] is created.
+    ///
+    /// If the partial frame has already received a message with the same `digitiser_id`,
+    /// the message is handled according to the [DuplicatePolicy], and counted in the
+    /// [DUPLICATE_DIGITISER_MESSAGES] metric, labelled by the digitiser id and the outcome.
+    ///
+    /// If the message's timestamp is no later than that of the latest frame dispatched,
+    /// it is handled by [Self::push_late] instead.
+    ///
+    /// # Return
+    /// Whether the message arrived on time or late, if it is accepted.
     #[tracing::instrument(skip_all, level = "trace")]
     pub(crate) fn push(
         &mut self,
         digitiser_id: DigitizerId,
         metadata: &FrameMetadata,
+        correlation: FrameCorrelation,
         data: D,
-    ) -> Result<(), RejectMessageError> {
+    ) -> Result<Arrival, RejectMessageError> {
         if let Some(latest_timestamp_dispatched) = self.latest_timestamp_dispatched
             && metadata.timestamp <= latest_timestamp_dispatched
         {
-            warn!(
-                "Frame's timestamp earlier than or equal to the latest frame dispatched: {0} <= {1}",
-                metadata.timestamp, latest_timestamp_dispatched
-            );
-            return Err(RejectMessageError::TimestampTooEarly);
+            return self.push_late(digitiser_id, metadata, data, latest_timestamp_dispatched);
         }
+        let key = FrameKey::from(metadata);
         let frame = {
-            match self
-                .frames
-                .iter_mut()
-                .find(|frame| frame.metadata.equals_ignoring_veto_flags(metadata))
-            {
+            match self.frames.iter_mut().find(|frame| frame.key == key) {
                 Some(frame) => {
                     if frame.has_digitiser_id(digitiser_id) {
-                        warn!("Frame already has digitiser id: {digitiser_id}");
-                        return Err(RejectMessageError::IdAlreadyPresent);
+                        counter!(
+                            DUPLICATE_DIGITISER_MESSAGES,
+                            "digitiser_id" => digitiser_id.to_string(),
+                            "outcome" => self.duplicate_policy.outcome()
+                        )
+                        .increment(1);
+                        // The digitiser is already linked to the frame's span, so is not linked again.
+                        return match self.duplicate_policy {
+                            DuplicatePolicy::Ignore => {
+                                debug!("Ignoring duplicate of digitiser id: {digitiser_id}, {key}");
+                                Ok(Arrival::OnTime)
+                            }
+                            DuplicatePolicy::Replace => {
+                                debug!(
+                                    "Replacing duplicate of digitiser id: {digitiser_id}, {key}"
+                                );
+                                frame.replace(digitiser_id, data);
+                                frame.push_veto_flags(metadata.veto_flags);
+                                Ok(Arrival::OnTime)
+                            }
+                            DuplicatePolicy::Error => {
+                                warn!("Frame already has digitiser id: {digitiser_id}, {key}");
+                                Err(RejectMessageError::IdAlreadyPresent)
+                            }
+                        };
                     }
                     frame.push(digitiser_id, data);
                     frame.push_veto_flags(metadata.veto_flags);
-                    frame.set_completion_status(&self.expected_digitisers);
+                    frame.set_completion_status(expected_digitisers_at(
+                        &self.expected_digitisers,
+                        &self.runs,
+                        metadata.timestamp,
+                    ));
+                    if frame.is_complete() {
+                        record_frame_assembly(frame);
+                    }
                     frame
                 }
                 None => {
-                    let mut frame = PartialFrame::<D>::new(self.ttl, metadata.clone());
+                    let mut frame = PartialFrame::<D>::new(self.ttl, metadata.clone(), correlation);
 
                     // Initialise the span field
                     if let Err(e) = frame.span_init() {
@@ -113,33 +366,139 @@ where
             }
         };
 
-        // Link this span with the frame aggregator span associated with `frame`
-        if let Err(e) = frame.link_current_span(|| {
-            let span = info_span!(
-                "Digitiser Event List",
-                digitiser_id = digitiser_id,
-                "metadata_timestamp" = tracing::field::Empty,
-                "metadata_frame_number" = tracing::field::Empty,
-                "metadata_period_number" = tracing::field::Empty,
-                "metadata_veto_flags" = tracing::field::Empty,
-                "metadata_protons_per_pulse" = tracing::field::Empty,
-                "metadata_running" = tracing::field::Empty,
+        link_digitiser_span(frame, digitiser_id, metadata);
+
+        self.record_frames_in_flight();
+        Ok(Arrival::OnTime)
+    }
+
+    /// Handles a message whose timestamp is no later than that of the latest frame dispatched.
+    ///
+    /// If the message's frame was dispatched within the late arrival window, and is missing the message's digitiser,
+    /// the message is late, and is handled according to the [LatePolicy]. Otherwise it is too late, and is rejected.
+    /// Either way it is counted in the [LATE_DIGITISER_MESSAGES] metric, labelled by its arrival and the outcome.
+    fn push_late(
+        &mut self,
+        digitiser_id: DigitizerId,
+        metadata: &FrameMetadata,
+        data: D,
+        latest_timestamp_dispatched: DateTime<Utc>,
+    ) -> Result<Arrival, RejectMessageError> {
+        self.forget_dispatched();
+        let key = FrameKey::from(metadata);
+        let Some(dispatched) = self
+            .dispatched
+            .iter_mut()
+            .find(|frame| frame.key == key && !frame.digitiser_ids.contains(&digitiser_id))
+        else {
+            counter!(LATE_DIGITISER_MESSAGES, "arrival" => "too_late", "outcome" => "rejected")
+                .increment(1);
+            warn!(
+                "Frame's timestamp earlier than or equal to the latest frame dispatched: {0} <= {1}",
+                metadata.timestamp, latest_timestamp_dispatched
             );
-            record_metadata_fields_to_span!(metadata, span);
-            span
-        }) {
-            warn!("Frame span linking failed {e}")
+            return Err(RejectMessageError::TimestampTooEarly);
+        };
+        counter!(
+            LATE_DIGITISER_MESSAGES,
+            "arrival" => "within_window",
+            "outcome" => self.late_policy.outcome()
+        )
+        .increment(1);
+        match self.late_policy {
+            LatePolicy::Drop => {
+                debug!("Dropping late message from digitiser id: {digitiser_id}, {key}");
+                Err(RejectMessageError::LateArrival)
+            }
+            LatePolicy::LogOnly => {
+                warn!(
+                    "Late message from digitiser id: {digitiser_id}, {key}, arrived {0:?} after its frame was dispatched",
+                    dispatched.dispatched_at.elapsed()
+                );
+                Err(RejectMessageError::LateArrival)
+            }
+            LatePolicy::EmitSupplement => {
+                debug!("Supplementing frame with late digitiser id: {digitiser_id}, {key}");
+                dispatched.digitiser_ids.push(digitiser_id);
+
+                let mut supplement = PartialFrame::<D>::new(
+                    self.ttl,
+                    dispatched.metadata.clone(),
+                    dispatched.correlation,
+                );
+                if let Err(e) = supplement.span_init() {
+                    warn!("Frame span initiation failed {e}")
+                }
+                supplement.push(digitiser_id, data);
+                link_digitiser_span(&supplement, digitiser_id, metadata);
+
+                let mut supplement = AggregatedFrame::from(supplement);
+                supplement.vetoed = dispatched.vetoed;
+                supplement.supplement = true;
+                self.supplements.push_back(supplement);
+                Ok(Arrival::Late)
+            }
         }
+    }
 
-        Ok(())
+    /// Remembers `frame`, as it is dispatched, for the late arrival window.
+    fn remember_dispatched(&mut self, frame: &AggregatedFrame<D>) {
+        self.forget_dispatched();
+        if self.late_arrival_window.is_zero() {
+            return;
+        }
+        self.dispatched.push_back(DispatchedFrame {
+            key: FrameKey::from(&frame.metadata),
+            metadata: frame.metadata.clone(),
+            correlation: frame.correlation,
+            digitiser_ids: frame.digitiser_ids.clone(),
+            vetoed: frame.vetoed,
+            dispatched_at: Instant::now(),
+        });
+    }
+
+    /// Forgets the frames dispatched before the late arrival window.
+    fn forget_dispatched(&mut self) {
+        while self
+            .dispatched
+            .front()
+            .is_some_and(|frame| frame.dispatched_at.elapsed() > self.late_arrival_window)
+        {
+            self.dispatched.pop_front();
+        }
     }
 
     /// Checks whether any partial frame is ready to be dispatched, that is either
     /// has a complete complement of digitisers, or has been in the cache past its expiry time.
     /// If one is found it is removed from the cache and returned as an [AggregatedFrame].
+    ///
+    /// Expired frames which are incomplete are handled according to the [PartialFramePolicy],
+    /// and counted in the [PARTIAL_FRAMES_EMITTED] or [PARTIAL_FRAMES_DROPPED] metrics,
+    /// labelled by the number of expected digitisers which did not report.
+    ///
+    /// Frames which are vetoed, whether complete or partial, are then handled according to the [VetoPolicy],
+    /// and counted in the [VETOED_FRAMES] metric, labelled by the vetoed flags and the outcome.
+    ///
+    /// Supplementary frames created from late messages, see [LatePolicy::EmitSupplement],
+    /// are returned before any frame still in the cache.
     pub(crate) fn poll(&mut self) -> Option<AggregatedFrame<D>> {
-        // Find a frame which is completed
-        if self
+        let frame = self.poll_frame();
+        if let Some(frame) = &frame
+            && !frame.supplement
+        {
+            self.remember_dispatched(frame);
+        }
+        self.record_frames_in_flight();
+        frame
+    }
+
+    fn poll_frame(&mut self) -> Option<AggregatedFrame<D>> {
+        if let Some(supplement) = self.supplements.pop_front() {
+            return Some(supplement);
+        }
+
+        // Find a frame which is completed or expired
+        while self
             .frames
             .front()
             .is_some_and(|frame| frame.is_complete() || frame.is_expired())
@@ -152,12 +511,110 @@ where
                 warn!("Frame span drop failed {e}")
             }
 
+            if let Some(run) = self
+                .runs
+                .iter_mut()
+                .rev()
+                .find(|run| run.contains(frame.metadata.timestamp))
+                && let Some(run_digitisers) = &mut run.digitisers
+                && run_digitisers.observe(&frame.digitiser_ids())
+            {
+                info!(
+                    "Learned expected digitisers of run {0}: {1:?}",
+                    run.name,
+                    run.digitiser_ids().unwrap_or_default()
+                );
+                let previous = self.expected_digitisers.clone();
+                self.update_expected_digitisers(&previous);
+            }
+
             // This frame is the next to be set to latest timestamp dispatched
             self.latest_timestamp_dispatched = Some(frame.metadata.timestamp);
-            Some(frame.into())
-        } else {
-            None
+            self.forget_finished_runs(frame.metadata.timestamp);
+
+            if frame.is_complete() {
+                match self.filter_vetoed(frame) {
+                    Some(frame) => return Some(frame),
+                    None => continue,
+                }
+            }
+            record_frame_assembly(&frame);
+
+            let missing_digitisers = expected_digitisers_at(
+                &self.expected_digitisers,
+                &self.runs,
+                frame.metadata.timestamp,
+            )
+            .iter()
+            .filter(|&&digitiser_id| !frame.has_digitiser_id(digitiser_id))
+            .count()
+            .to_string();
+            match self.partial_frame_policy {
+                PartialFramePolicy::Emit => {
+                    counter!(PARTIAL_FRAMES_EMITTED, "missing_digitisers" => missing_digitisers)
+                        .increment(1);
+                    if let Some(frame) = self.filter_vetoed(frame) {
+                        return Some(frame);
+                    }
+                }
+                PartialFramePolicy::Drop => {
+                    info!(
+                        "Dropping partial frame missing {missing_digitisers} digitiser(s): {0:?}",
+                        frame.metadata
+                    );
+                    counter!(PARTIAL_FRAMES_DROPPED, "missing_digitisers" => missing_digitisers)
+                        .increment(1);
+                }
+            }
+        }
+        None
+    }
+
+    /// Forgets the runs which stopped no later than `latest_timestamp_dispatched`,
+    /// as any later message of their frames is late, so is not pushed into the cache.
+    fn forget_finished_runs(&mut self, latest_timestamp_dispatched: DateTime<Utc>) {
+        self.runs.retain(|run| {
+            run.stop
+                .is_none_or(|stop| stop > latest_timestamp_dispatched)
+        });
+    }
+
+    /// Applies the [VetoPolicy] to `frame`, if it is vetoed.
+    ///
+    /// # Returns
+    /// The frame to dispatch, or [None] if it is dropped.
+    fn filter_vetoed(&self, frame: PartialFrame<D>) -> Option<AggregatedFrame<D>> {
+        let vetoed = frame.metadata.veto_flags & self.veto_mask;
+        if vetoed == 0 {
+            return Some(frame.into());
         }
+        counter!(
+            VETOED_FRAMES,
+            "flags" => format_veto_flags(vetoed),
+            "outcome" => self.veto_policy.outcome()
+        )
+        .increment(1);
+        match self.veto_policy {
+            VetoPolicy::Drop => {
+                debug!("Dropping vetoed frame: {0:?}", frame.metadata);
+                None
+            }
+            VetoPolicy::ForwardFlagged => {
+                let mut frame = AggregatedFrame::from(frame);
+                frame.vetoed = vetoed;
+                Some(frame)
+            }
+        }
+    }
+
+    /// Sets the [FRAMES_IN_FLIGHT] gauge to the number of incomplete frames currently in the cache.
+    fn record_frames_in_flight(&self) {
+        let in_flight = self
+            .frames
+            .iter()
+            .filter(|frame| !frame.is_complete())
+            .count();
+        gauge!(FRAMES_IN_FLIGHT).set(in_flight as f64);
     }
 
     /// Returns the number of partial frames currently in the cache.
@@ -166,11 +623,70 @@ where
     }
 }
 
+/// Returns the digitisers expected in a frame with `timestamp`, those of the latest run to contain it, if known,
+/// otherwise `expected_digitisers`.
+fn expected_digitisers_at<'a>(
+    expected_digitisers: &'a [DigitizerId],
+    runs: &'a VecDeque<Run>,
+    timestamp: DateTime<Utc>,
+) -> &'a [DigitizerId] {
+    runs.iter()
+        .rev()
+        .find(|run| run.contains(timestamp))
+        .and_then(Run::digitiser_ids)
+        .unwrap_or(expected_digitisers)
+}
+
+/// Links the current span, of the message from `digitiser_id`, with the span of `frame`.
+fn link_digitiser_span<D>(
+    frame: &PartialFrame<D>,
+    digitiser_id: DigitizerId,
+    metadata: &FrameMetadata,
+) {
+    if let Err(e) = frame.link_current_span(|| {
+        let span = info_span!(
+            "Digitiser Event List",
+            digitiser_id = digitiser_id,
+            "metadata_timestamp" = tracing::field::Empty,
+            "metadata_frame_number" = tracing::field::Empty,
+            "metadata_period_number" = tracing::field::Empty,
+            "metadata_veto_flags" = tracing::field::Empty,
+            "metadata_protons_per_pulse" = tracing::field::Empty,
+            "metadata_running" = tracing::field::Empty,
+        );
+        record_metadata_fields_to_span!(metadata, span);
+        span
+    }) {
+        warn!("Frame span linking failed {e}")
+    }
+}
+
+/// Records the [FRAME_ASSEMBLY_DURATION_MS] and [FRAME_COMPLETION_LATENCY_MS] metrics of `frame`,
+/// at the moment it is completed, or when it expires if it is incomplete.
+fn record_frame_assembly<D>(frame: &PartialFrame<D>) {
+    let label = if frame.is_complete() {
+        "complete"
+    } else {
+        "partial"
+    };
+    histogram!(FRAME_ASSEMBLY_DURATION_MS, "frame" => label)
+        .record(frame.assembly_duration().as_micros() as f64 / 1000.0);
+    histogram!(FRAME_COMPLETION_LATENCY_MS, "frame" => label)
+        .record((Utc::now() - frame.metadata.timestamp).as_seconds_f64() * 1000.0);
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::data::EventData;
+    use crate::{
+        data::EventData,
+        frame::{SUPPLEMENT_HEADER, VETOED_HEADER},
+    };
     use chrono::Utc;
+    use metrics_util::{
+        CompositeKey,
+        debugging::{DebugValue, DebuggingRecorder},
+    };
 
     #[test]
     fn test_repeated_digitiser_ids() {
@@ -210,7 +726,12 @@ mod test {
         assert_eq!(cache.get_num_partial_frames(), 0);
         assert!(
             cache
-                .push(0, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    0,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
                 .is_ok()
         );
         assert_eq!(cache.get_num_partial_frames(), 1);
@@ -219,7 +740,12 @@ mod test {
 
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[3, 4, 5]))
+                .push(
+                    1,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[3, 4, 5])
+                )
                 .is_ok()
         );
 
@@ -227,7 +753,12 @@ mod test {
 
         assert!(
             cache
-                .push(4, &frame_1, EventData::dummy_data(0, 5, &[6, 7, 8]))
+                .push(
+                    4,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[6, 7, 8])
+                )
                 .is_ok()
         );
 
@@ -235,7 +766,12 @@ mod test {
 
         assert!(
             cache
-                .push(8, &frame_1, EventData::dummy_data(0, 5, &[9, 10, 11]))
+                .push(
+                    8,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[9, 10, 11])
+                )
                 .is_ok()
         );
 
@@ -288,7 +824,12 @@ mod test {
 
         assert!(
             cache
-                .push(0, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    0,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
                 .is_ok()
         );
 
@@ -296,7 +837,12 @@ mod test {
 
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[3, 4, 5]))
+                .push(
+                    1,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[3, 4, 5])
+                )
                 .is_ok()
         );
 
@@ -304,7 +850,12 @@ mod test {
 
         assert!(
             cache
-                .push(8, &frame_1, EventData::dummy_data(0, 5, &[9, 10, 11]))
+                .push(
+                    8,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[9, 10, 11])
+                )
                 .is_ok()
         );
 
@@ -355,17 +906,32 @@ mod test {
         };
         assert!(
             cache
-                .push(0, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    0,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
                 .is_ok()
         );
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[3, 4, 5]))
+                .push(
+                    1,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[3, 4, 5])
+                )
                 .is_ok()
         );
         assert!(
             cache
-                .push(8, &frame_1, EventData::dummy_data(0, 5, &[9, 10, 11]))
+                .push(
+                    8,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[9, 10, 11])
+                )
                 .is_ok()
         );
 
@@ -376,7 +942,12 @@ mod test {
         //  This call to push should return an error
         assert!(
             cache
-                .push(4, &frame_1, EventData::dummy_data(0, 5, &[6, 7, 8]))
+                .push(
+                    4,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[6, 7, 8])
+                )
                 .is_err()
         );
     }
@@ -412,7 +983,12 @@ mod test {
 
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    1,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
                 .is_ok()
         );
         assert_eq!(cache.frames.len(), 1);
@@ -420,10 +996,973 @@ mod test {
 
         assert!(
             cache
-                .push(2, &frame_2, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    2,
+                    &frame_2,
+                    FrameCorrelation::new(&frame_2),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
                 .is_ok()
         );
         assert_eq!(cache.frames.len(), 1);
         assert!(cache.poll().is_some());
     }
+
+    #[test]
+    fn identical_timestamps_with_different_periods_are_different_frames() {
+        let mut cache =
+            FrameCache::<EventData>::new(Duration::from_millis(100), vec![1, 2]).unwrap();
+
+        let timestamp = Utc::now();
+        let frame_1 = FrameMetadata {
+            timestamp,
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 0,
+        };
+        let frame_2 = FrameMetadata {
+            period_number: 2,
+            ..frame_1.clone()
+        };
+
+        assert!(
+            cache
+                .push(
+                    1,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
+                .is_ok()
+        );
+        assert!(
+            cache
+                .push(
+                    2,
+                    &frame_2,
+                    FrameCorrelation::new(&frame_2),
+                    EventData::dummy_data(0, 5, &[0, 1, 2])
+                )
+                .is_ok()
+        );
+        assert_eq!(cache.frames.len(), 2);
+        assert!(cache.poll().is_none());
+    }
+
+    /// The name, labels and value of a recorded metric.
+    type RecordedMetric<T> = (String, Vec<(String, String)>, T);
+
+    /// Returns each metric recorded.
+    fn recorded(recorder: &DebuggingRecorder) -> Vec<RecordedMetric<DebugValue>> {
+        recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = CompositeKey::key(&key);
+                let labels = key
+                    .labels()
+                    .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                    .collect();
+                (key.name().to_owned(), labels, value)
+            })
+            .collect()
+    }
+
+    /// Returns each counter recorded.
+    fn counters(recorder: &DebuggingRecorder) -> Vec<RecordedMetric<u64>> {
+        recorded(recorder)
+            .into_iter()
+            .filter_map(|(name, labels, value)| match value {
+                DebugValue::Counter(count) => Some((name, labels, count)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the values recorded by the histogram `name`, with label `frame` equal to `label`.
+    fn histogram_values(
+        recorded: &[RecordedMetric<DebugValue>],
+        name: &str,
+        label: &str,
+    ) -> Vec<f64> {
+        recorded
+            .iter()
+            .find_map(|(metric_name, labels, value)| match value {
+                DebugValue::Histogram(values)
+                    if metric_name == name
+                        && labels == &[("frame".to_owned(), label.to_owned())] =>
+                {
+                    Some(values.iter().map(|value| value.into_inner()).collect())
+                }
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the value of the [FRAMES_IN_FLIGHT] gauge.
+    fn frames_in_flight(recorded: &[RecordedMetric<DebugValue>]) -> Option<f64> {
+        recorded.iter().find_map(|(name, _, value)| match value {
+            DebugValue::Gauge(value) if name == FRAMES_IN_FLIGHT => Some(value.into_inner()),
+            _ => None,
+        })
+    }
+
+    /// Pushes a frame from three of the four expected digitisers, then polls it after its TTL.
+    async fn poll_three_of_four(
+        partial_frame_policy: PartialFramePolicy,
+    ) -> (
+        Option<AggregatedFrame<EventData>>,
+        FrameCache<EventData>,
+        DebuggingRecorder,
+    ) {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 4, 8])
+            .unwrap()
+            .with_partial_frame_policy(partial_frame_policy);
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        for (digitiser_id, channels) in [(0, [0, 1, 2]), (4, [6, 7, 8]), (8, [9, 10, 11])] {
+            assert!(
+                cache
+                    .push(
+                        digitiser_id,
+                        &frame_1,
+                        FrameCorrelation::new(&frame_1),
+                        EventData::dummy_data(0, 2, &channels)
+                    )
+                    .is_ok()
+            );
+        }
+
+        let recorder = DebuggingRecorder::new();
+        assert!(metrics::with_local_recorder(&recorder, || cache.poll()).is_none());
+        assert!(counters(&recorder).is_empty());
+
+        tokio::time::sleep(Duration::from_millis(105)).await;
+
+        let frame = metrics::with_local_recorder(&recorder, || cache.poll());
+        (frame, cache, recorder)
+    }
+
+    #[tokio::test]
+    async fn partial_frame_emitted_after_ttl() {
+        let (frame, mut cache, recorder) = poll_three_of_four(PartialFramePolicy::Emit).await;
+
+        let frame = frame.unwrap();
+        assert!(!frame.complete);
+        assert_eq!(frame.digitiser_ids, &[0, 4, 8]);
+        assert_eq!(
+            frame.digitiser_data,
+            EventData::new(
+                vec![0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1],
+                vec![0; 18],
+                vec![0, 0, 1, 1, 2, 2, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11],
+            )
+        );
+        assert_eq!(
+            counters(&recorder),
+            vec![(
+                PARTIAL_FRAMES_EMITTED.to_owned(),
+                vec![("missing_digitisers".to_owned(), "1".to_owned())],
+                1
+            )]
+        );
+        assert!(cache.poll().is_none());
+    }
+
+    #[tokio::test]
+    async fn partial_frame_dropped_after_ttl() {
+        let (frame, mut cache, recorder) = poll_three_of_four(PartialFramePolicy::Drop).await;
+
+        assert!(frame.is_none());
+        assert_eq!(cache.get_num_partial_frames(), 0);
+        assert_eq!(
+            counters(&recorder),
+            vec![(
+                PARTIAL_FRAMES_DROPPED.to_owned(),
+                vec![("missing_digitisers".to_owned(), "1".to_owned())],
+                1
+            )]
+        );
+
+        // A late message for the dropped frame is rejected rather than starting a new frame.
+        let late_frame = FrameMetadata {
+            timestamp: cache.latest_timestamp_dispatched.unwrap(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        assert!(
+            cache
+                .push(
+                    1,
+                    &late_frame,
+                    FrameCorrelation::new(&late_frame),
+                    EventData::dummy_data(0, 2, &[3, 4, 5])
+                )
+                .is_err()
+        );
+        assert_eq!(cache.get_num_partial_frames(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frame_assembly_metrics() {
+        let mut cache =
+            FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1]).unwrap();
+        let recorder = DebuggingRecorder::new();
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let frame_2 = FrameMetadata {
+            frame_number: 1729,
+            ..frame_1.clone()
+        };
+
+        metrics::with_local_recorder(&recorder, || {
+            assert!(
+                cache
+                    .push(
+                        0,
+                        &frame_1,
+                        FrameCorrelation::new(&frame_1),
+                        EventData::dummy_data(0, 5, &[0])
+                    )
+                    .is_ok()
+            );
+            assert!(
+                cache
+                    .push(
+                        0,
+                        &frame_2,
+                        FrameCorrelation::new(&frame_2),
+                        EventData::dummy_data(0, 5, &[0])
+                    )
+                    .is_ok()
+            );
+        });
+        assert_eq!(frames_in_flight(&recorded(&recorder)), Some(2.0));
+
+        tokio::time::advance(Duration::from_millis(30)).await;
+
+        metrics::with_local_recorder(&recorder, || {
+            assert!(
+                cache
+                    .push(
+                        1,
+                        &frame_1,
+                        FrameCorrelation::new(&frame_1),
+                        EventData::dummy_data(0, 5, &[1])
+                    )
+                    .is_ok()
+            );
+        });
+        // Histogram values are cleared by each snapshot, so only take one.
+        let metrics = recorded(&recorder);
+        assert_eq!(frames_in_flight(&metrics), Some(1.0));
+        assert_eq!(
+            histogram_values(&metrics, FRAME_ASSEMBLY_DURATION_MS, "complete"),
+            [30.0]
+        );
+        // The wall clock is not paused, so allow for the time taken by the test.
+        let latency = histogram_values(&metrics, FRAME_COMPLETION_LATENCY_MS, "complete");
+        assert!(
+            latency.len() == 1 && latency.iter().all(|ms| (0.0..1000.0).contains(ms)),
+            "{latency:?}"
+        );
+
+        let frame = metrics::with_local_recorder(&recorder, || cache.poll()).unwrap();
+        assert!(frame.complete);
+        assert_eq!(frames_in_flight(&recorded(&recorder)), Some(1.0));
+
+        tokio::time::advance(Duration::from_millis(105)).await;
+
+        let frame = metrics::with_local_recorder(&recorder, || cache.poll()).unwrap();
+        assert!(!frame.complete);
+        let metrics = recorded(&recorder);
+        assert_eq!(frames_in_flight(&metrics), Some(0.0));
+        assert_eq!(
+            histogram_values(&metrics, FRAME_ASSEMBLY_DURATION_MS, "partial"),
+            [0.0]
+        );
+        assert_eq!(
+            histogram_values(&metrics, FRAME_COMPLETION_LATENCY_MS, "partial").len(),
+            1
+        );
+    }
+
+    /// Returns the [DUPLICATE_DIGITISER_MESSAGES] counter expected after one duplicate from digitiser `0`.
+    fn one_duplicate(outcome: &str) -> Vec<RecordedMetric<u64>> {
+        vec![(
+            DUPLICATE_DIGITISER_MESSAGES.to_owned(),
+            vec![
+                ("digitiser_id".to_owned(), "0".to_owned()),
+                ("outcome".to_owned(), outcome.to_owned()),
+            ],
+            1,
+        )]
+    }
+
+    /// Pushes a frame from both expected digitisers, with a duplicate from digitiser `0`
+    /// injected either before or after the frame is complete, then polls it.
+    /// Digitisers `0` and `1` send an event in channels `0` and `1` respectively, and the duplicate in channel `2`.
+    ///
+    /// # Returns
+    /// The data of the frame, and the counters recorded.
+    fn push_with_duplicate(
+        duplicate_policy: DuplicatePolicy,
+        after_completion: bool,
+    ) -> (EventData, Vec<RecordedMetric<u64>>) {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1])
+            .unwrap()
+            .with_duplicate_policy(duplicate_policy);
+        let recorder = DebuggingRecorder::new();
+
+        let timestamp = Utc::now();
+        let frame_1 = FrameMetadata {
+            timestamp,
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 0,
+        };
+        // An earlier incomplete frame, which keeps complete frames behind it in the cache.
+        let frame_0 = FrameMetadata {
+            timestamp: timestamp - chrono::Duration::milliseconds(20),
+            frame_number: 1727,
+            ..frame_1.clone()
+        };
+
+        let frame = metrics::with_local_recorder(&recorder, || {
+            let mut push = |digitiser_id, metadata, channel| {
+                cache.push(
+                    digitiser_id,
+                    metadata,
+                    FrameCorrelation::new(metadata),
+                    EventData::dummy_data(0, 1, &[channel]),
+                )
+            };
+            assert!(push(0, &frame_0, 0).is_ok());
+            assert!(push(0, &frame_1, 0).is_ok());
+            let duplicate_result = if after_completion {
+                assert!(push(1, &frame_1, 1).is_ok());
+                push(0, &frame_1, 2)
+            } else {
+                let result = push(0, &frame_1, 2);
+                assert!(push(1, &frame_1, 1).is_ok());
+                result
+            };
+            match duplicate_policy {
+                DuplicatePolicy::Ignore | DuplicatePolicy::Replace => {
+                    assert!(duplicate_result.is_ok())
+                }
+                DuplicatePolicy::Error => assert!(matches!(
+                    duplicate_result,
+                    Err(RejectMessageError::IdAlreadyPresent)
+                )),
+            }
+            assert!(cache.poll().is_none());
+
+            // Completing the earlier frame allows both frames to be dispatched.
+            assert!(
+                cache
+                    .push(
+                        1,
+                        &frame_0,
+                        FrameCorrelation::new(&frame_0),
+                        EventData::dummy_data(0, 1, &[1])
+                    )
+                    .is_ok()
+            );
+            assert_eq!(cache.poll().unwrap().metadata.frame_number, 1727);
+            let frame = cache.poll().unwrap();
+            assert!(frame.complete);
+            assert_eq!(frame.digitiser_ids, &[0, 1]);
+
+            // A duplicate after the frame is dispatched is too late to be matched to it.
+            assert!(matches!(
+                cache.push(
+                    0,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 1, &[2])
+                ),
+                Err(RejectMessageError::TimestampTooEarly)
+            ));
+            assert_eq!(cache.get_num_partial_frames(), 0);
+            frame
+        });
+        // The duplicate after dispatch is also counted as too late, which is tested separately.
+        let counters = counters(&recorder)
+            .into_iter()
+            .filter(|(name, _, _)| name != LATE_DIGITISER_MESSAGES)
+            .collect();
+        (frame.digitiser_data, counters)
+    }
+
+    #[test]
+    fn duplicate_ignored() {
+        for after_completion in [false, true] {
+            let (data, counters) = push_with_duplicate(DuplicatePolicy::Ignore, after_completion);
+            assert_eq!(data, EventData::new(vec![0, 0], vec![0, 0], vec![0, 1]));
+            assert_eq!(counters, one_duplicate("ignored"));
+        }
+    }
+
+    #[test]
+    fn duplicate_replaced() {
+        for after_completion in [false, true] {
+            let (data, counters) = push_with_duplicate(DuplicatePolicy::Replace, after_completion);
+            assert_eq!(data, EventData::new(vec![0, 0], vec![0, 0], vec![2, 1]));
+            assert_eq!(counters, one_duplicate("replaced"));
+        }
+    }
+
+    #[test]
+    fn duplicate_rejected() {
+        for after_completion in [false, true] {
+            let (data, counters) = push_with_duplicate(DuplicatePolicy::Error, after_completion);
+            assert_eq!(data, EventData::new(vec![0, 0], vec![0, 0], vec![0, 1]));
+            assert_eq!(counters, one_duplicate("rejected"));
+        }
+    }
+
+    /// Returns the [VETOED_FRAMES] counter expected after one vetoed frame.
+    fn one_vetoed(flags: &str, outcome: &str) -> Vec<RecordedMetric<u64>> {
+        vec![(
+            VETOED_FRAMES.to_owned(),
+            vec![
+                ("flags".to_owned(), flags.to_owned()),
+                ("outcome".to_owned(), outcome.to_owned()),
+            ],
+            1,
+        )]
+    }
+
+    /// Pushes a frame with veto flags `0b0101`, from both expected digitisers if `complete`,
+    /// and otherwise from only one, in which case it is polled after its TTL.
+    ///
+    /// # Returns
+    /// The polled frame, and the counters recorded, ignoring [PARTIAL_FRAMES_EMITTED].
+    async fn poll_vetoed(
+        veto_mask: u16,
+        veto_policy: VetoPolicy,
+        complete: bool,
+    ) -> (Option<AggregatedFrame<EventData>>, Vec<RecordedMetric<u64>>) {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1])
+            .unwrap()
+            .with_veto_filter(veto_mask, veto_policy);
+        let recorder = DebuggingRecorder::new();
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 0b0101,
+        };
+        let digitiser_ids: &[DigitizerId] = if complete { &[0, 1] } else { &[0] };
+        for &digitiser_id in digitiser_ids {
+            assert!(
+                cache
+                    .push(
+                        digitiser_id,
+                        &frame_1,
+                        FrameCorrelation::new(&frame_1),
+                        EventData::dummy_data(0, 2, &[0])
+                    )
+                    .is_ok()
+            );
+        }
+        if !complete {
+            tokio::time::advance(Duration::from_millis(105)).await;
+        }
+
+        let frame = metrics::with_local_recorder(&recorder, || cache.poll());
+        assert_eq!(cache.get_num_partial_frames(), 0);
+        let counters = counters(&recorder)
+            .into_iter()
+            .filter(|(name, _, _)| name != PARTIAL_FRAMES_EMITTED)
+            .collect();
+        (frame, counters)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_veto_mask_vetoes_nothing() {
+        for veto_policy in [VetoPolicy::Drop, VetoPolicy::ForwardFlagged] {
+            for complete in [true, false] {
+                let (frame, counters) = poll_vetoed(0, veto_policy, complete).await;
+                let frame = frame.unwrap();
+                assert_eq!(frame.complete, complete);
+                assert_eq!(frame.metadata.veto_flags, 0b0101);
+                assert_eq!(frame.vetoed, 0);
+                assert!(frame.headers().is_none());
+                assert!(counters.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn veto_mask_not_matching() {
+        for complete in [true, false] {
+            let (frame, counters) = poll_vetoed(0b1010, VetoPolicy::Drop, complete).await;
+            assert_eq!(frame.unwrap().vetoed, 0);
+            assert!(counters.is_empty());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn vetoed_frame_dropped() {
+        for complete in [true, false] {
+            let (frame, counters) = poll_vetoed(0b0110, VetoPolicy::Drop, complete).await;
+            assert!(frame.is_none());
+            assert_eq!(counters, one_vetoed("0x0004", "dropped"));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn vetoed_frame_forwarded_flagged() {
+        use rdkafka::message::Headers;
+
+        for complete in [true, false] {
+            let (frame, counters) = poll_vetoed(0b1111, VetoPolicy::ForwardFlagged, complete).await;
+            let frame = frame.unwrap();
+            assert_eq!(frame.complete, complete);
+            assert_eq!(frame.vetoed, 0b0101);
+            assert_eq!(counters, one_vetoed("0x0005", "forwarded"));
+
+            let headers = frame.headers().unwrap();
+            assert_eq!(headers.count(), 1);
+            let header = headers.get_as::<str>(0).unwrap();
+            assert_eq!(header.key, VETOED_HEADER);
+            assert_eq!(header.value, Some("0x0005"));
+        }
+    }
+
+    /// Returns the [LATE_DIGITISER_MESSAGES] counter expected after one late message.
+    fn one_late(arrival: &str, outcome: &str) -> Vec<RecordedMetric<u64>> {
+        vec![(
+            LATE_DIGITISER_MESSAGES.to_owned(),
+            vec![
+                ("arrival".to_owned(), arrival.to_owned()),
+                ("outcome".to_owned(), outcome.to_owned()),
+            ],
+            1,
+        )]
+    }
+
+    /// Dispatches frame `1727`, from digitiser `0` only, after its TTL, followed by the complete frame `1728`.
+    /// After `delay`, delivers digitiser `1`'s message for frame `1727` out of order, followed by the complete frame `1729`.
+    /// Digitisers `0` and `1` send an event in channels `0` and `1` respectively, except the late message is in channel `2`.
+    ///
+    /// # Returns
+    /// The result of pushing the late message, the frames polled after it, and the counters recorded since it was pushed.
+    async fn push_out_of_order(
+        late_policy: LatePolicy,
+        delay: Duration,
+    ) -> (
+        Result<(), RejectMessageError>,
+        Vec<AggregatedFrame<EventData>>,
+        Vec<RecordedMetric<u64>>,
+    ) {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1])
+            .unwrap()
+            .with_late_arrival(Duration::from_millis(100), late_policy);
+        let recorder = DebuggingRecorder::new();
+
+        let timestamp = Utc::now();
+        let frames = [1727, 1728, 1729].map(|frame_number| FrameMetadata {
+            timestamp: timestamp
+                + chrono::Duration::milliseconds(20 * i64::from(frame_number - 1727)),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number,
+            veto_flags: 0,
+        });
+        let [frame_0, frame_1, frame_2] = &frames;
+        let push = |cache: &mut FrameCache<EventData>,
+                    digitiser_id: DigitizerId,
+                    metadata: &FrameMetadata,
+                    channel| {
+            cache.push(
+                digitiser_id,
+                metadata,
+                FrameCorrelation::new(metadata),
+                EventData::dummy_data(0, 1, &[channel]),
+            )
+        };
+
+        assert!(push(&mut cache, 0, frame_0, 0).is_ok());
+        assert!(push(&mut cache, 0, frame_1, 0).is_ok());
+        assert!(push(&mut cache, 1, frame_1, 1).is_ok());
+        assert!(cache.poll().is_none());
+        tokio::time::advance(Duration::from_millis(105)).await;
+        assert_eq!(cache.poll().unwrap().digitiser_ids, &[0]);
+        assert_eq!(cache.poll().unwrap().metadata.frame_number, 1728);
+        assert!(cache.poll().is_none());
+
+        tokio::time::advance(delay).await;
+        metrics::with_local_recorder(&recorder, || {
+            let result = push(&mut cache, 1, frame_0, 2);
+            assert!(push(&mut cache, 0, frame_2, 0).is_ok());
+            assert!(push(&mut cache, 1, frame_2, 1).is_ok());
+            let frames = std::iter::from_fn(|| cache.poll()).collect();
+            (result, frames, counters(&recorder))
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_message_dropped() {
+        let (result, frames, counters) =
+            push_out_of_order(LatePolicy::Drop, Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(RejectMessageError::LateArrival)));
+        assert_eq!(
+            frames
+                .iter()
+                .map(|frame| frame.metadata.frame_number)
+                .collect::<Vec<_>>(),
+            [1729]
+        );
+        assert_eq!(counters, one_late("within_window", "dropped"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_message_logged() {
+        let (result, frames, counters) =
+            push_out_of_order(LatePolicy::LogOnly, Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(RejectMessageError::LateArrival)));
+        assert_eq!(
+            frames
+                .iter()
+                .map(|frame| frame.metadata.frame_number)
+                .collect::<Vec<_>>(),
+            [1729]
+        );
+        assert_eq!(counters, one_late("within_window", "logged"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_message_supplemented() {
+        use rdkafka::message::Headers;
+
+        let (result, frames, counters) =
+            push_out_of_order(LatePolicy::EmitSupplement, Duration::from_millis(50)).await;
+        assert!(matches!(result, Ok(Arrival::Late)));
+        assert_eq!(counters, one_late("within_window", "supplemented"));
+
+        // The supplement is dispatched before the frame which completed after it.
+        let mut frames = frames.into_iter();
+        let supplement = frames.next().unwrap();
+        assert!(supplement.supplement);
+        assert!(!supplement.complete);
+        assert_eq!(supplement.metadata.frame_number, 1727);
+        assert_eq!(supplement.digitiser_ids, &[1]);
+        assert_eq!(
+            supplement.digitiser_data,
+            EventData::new(vec![0], vec![0], vec![2])
+        );
+        let headers = supplement.headers().unwrap();
+        assert_eq!(headers.count(), 1);
+        let header = headers.get_as::<str>(0).unwrap();
+        assert_eq!(header.key, SUPPLEMENT_HEADER);
+        assert_eq!(header.value, Some("1"));
+
+        let frame = frames.next().unwrap();
+        assert!(!frame.supplement && frame.complete);
+        assert_eq!(frame.metadata.frame_number, 1729);
+        assert!(frame.headers().is_none());
+        assert!(frames.next().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn message_after_late_arrival_window_rejected() {
+        for late_policy in [
+            LatePolicy::Drop,
+            LatePolicy::EmitSupplement,
+            LatePolicy::LogOnly,
+        ] {
+            let (result, frames, counters) =
+                push_out_of_order(late_policy, Duration::from_millis(150)).await;
+            assert!(matches!(result, Err(RejectMessageError::TimestampTooEarly)));
+            assert_eq!(
+                frames
+                    .iter()
+                    .map(|frame| frame.metadata.frame_number)
+                    .collect::<Vec<_>>(),
+                [1729]
+            );
+            assert_eq!(counters, one_late("too_late", "rejected"));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn second_late_message_from_digitiser_rejected() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1])
+            .unwrap()
+            .with_late_arrival(Duration::from_millis(100), LatePolicy::EmitSupplement);
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 0,
+        };
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 1, &[0])
+                )
+                .is_ok()
+        );
+        tokio::time::advance(Duration::from_millis(105)).await;
+        assert!(!cache.poll().unwrap().supplement);
+
+        assert!(
+            cache
+                .push(
+                    1,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 1, &[1])
+                )
+                .is_ok()
+        );
+        // Once a digitiser's data has been dispatched, in the frame or in a supplement, its messages are too late.
+        for digitiser_id in [1, 0] {
+            assert!(matches!(
+                cache.push(
+                    digitiser_id,
+                    &frame_1,
+                    FrameCorrelation::new(&frame_1),
+                    EventData::dummy_data(0, 1, &[2])
+                ),
+                Err(RejectMessageError::TimestampTooEarly)
+            ));
+        }
+        assert!(cache.poll().unwrap().supplement);
+        assert!(cache.poll().is_none());
+    }
+
+    /// Pushes `num_frames` consecutive frames, each from digitisers 0 and 1, and polls each,
+    /// once it is pushed, or after its TTL if it is not dispatched at once.
+    /// Returns whether each frame was dispatched complete.
+    async fn push_without_digitiser_2(
+        cache: &mut FrameCache<EventData>,
+        num_frames: u32,
+    ) -> Vec<bool> {
+        let start = Utc::now();
+        let mut complete = Vec::new();
+        for frame_number in 0..num_frames {
+            let metadata = FrameMetadata {
+                timestamp: start + chrono::TimeDelta::milliseconds(frame_number.into()),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number,
+                veto_flags: 0,
+            };
+            for digitiser_id in [0, 1] {
+                assert!(
+                    cache
+                        .push(
+                            digitiser_id,
+                            &metadata,
+                            FrameCorrelation::new(&metadata),
+                            EventData::dummy_data(0, 1, &[0])
+                        )
+                        .is_ok()
+                );
+            }
+            let frame = match cache.poll() {
+                Some(frame) => frame,
+                None => {
+                    tokio::time::advance(Duration::from_millis(105)).await;
+                    cache.poll().unwrap()
+                }
+            };
+            assert_eq!(frame.metadata, metadata);
+            complete.push(frame.complete);
+        }
+        complete
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missing_digitiser_not_expected_once_learned() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 2])
+            .unwrap()
+            .with_learned_digitisers(2);
+        cache.start_run("Run1", Utc::now(), None);
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1, 2]);
+
+        // Until the first two frames are dispatched, digitiser 2 is expected, so they are only dispatched after their TTL.
+        assert_eq!(
+            push_without_digitiser_2(&mut cache, 4).await,
+            [false, false, true, true]
+        );
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1]);
+
+        cache.stop_run("Run1", Utc::now());
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1, 2]);
+        assert_eq!(push_without_digitiser_2(&mut cache, 1).await, [false]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missing_digitiser_always_expected_when_static() {
+        let mut cache =
+            FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 2]).unwrap();
+        cache.start_run("Run1", Utc::now(), None);
+        assert_eq!(push_without_digitiser_2(&mut cache, 4).await, [false; 4]);
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn declared_digitisers_expected_for_run() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 2])
+            .unwrap()
+            .with_learned_digitisers(2);
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            cache.record_expected_digitisers();
+            cache.start_run("Run1", Utc::now(), Some(vec![1, 0]));
+        });
+        assert_eq!(push_without_digitiser_2(&mut cache, 2).await, [true, true]);
+
+        let mut gauges = recorded(&recorder)
+            .into_iter()
+            .filter_map(|(name, labels, value)| match value {
+                DebugValue::Gauge(value) if name == EXPECTED_DIGITISERS => {
+                    Some((labels, value.into_inner()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        gauges.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let label = |digitiser_id: &str| vec![("digitiser_id".to_owned(), digitiser_id.to_owned())];
+        assert_eq!(
+            gauges,
+            [(label("0"), 1.0), (label("1"), 1.0), (label("2"), 0.0)]
+        );
+
+        cache.stop_run("Run1", Utc::now());
+        assert_eq!(push_without_digitiser_2(&mut cache, 1).await, [false]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frame_in_cache_completed_by_declared_digitisers() {
+        let mut cache =
+            FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 2]).unwrap();
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 0,
+        };
+        for digitiser_id in [0, 1] {
+            assert!(
+                cache
+                    .push(
+                        digitiser_id,
+                        &frame_1,
+                        FrameCorrelation::new(&frame_1),
+                        EventData::dummy_data(0, 1, &[0])
+                    )
+                    .is_ok()
+            );
+        }
+        assert!(cache.poll().is_none());
+
+        cache.start_run("Run1", frame_1.timestamp, Some(vec![0, 1]));
+        assert!(cache.poll().unwrap().complete);
+    }
+
+    /// Returns whether each frame in `cache` is complete, in order.
+    fn frames_complete(cache: &FrameCache<EventData>) -> Vec<bool> {
+        cache.frames.iter().map(PartialFrame::is_complete).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_are_scoped_by_frame_timestamps() {
+        let mut cache =
+            FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 2]).unwrap();
+        let start = Utc::now();
+        let at = |ms| start + chrono::TimeDelta::milliseconds(ms);
+        cache.start_run("Run1", at(0), Some(vec![0, 1]));
+        cache.stop_run("Run1", at(10));
+
+        // Frames are pushed after the control messages, but only those during the run expect its digitisers.
+        for (frame_number, offset_ms) in [(0, -1), (1, 0), (2, 9), (3, 10)] {
+            let metadata = FrameMetadata {
+                timestamp: at(offset_ms),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number,
+                veto_flags: 0,
+            };
+            for digitiser_id in [0, 1] {
+                assert!(
+                    cache
+                        .push(
+                            digitiser_id,
+                            &metadata,
+                            FrameCorrelation::new(&metadata),
+                            EventData::dummy_data(0, 1, &[0])
+                        )
+                        .is_ok()
+                );
+            }
+        }
+        assert_eq!(frames_complete(&cache), [false, true, true, false]);
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1, 2]);
+
+        // A run started later, but whose frames are in the cache, completes them.
+        cache.start_run("Run2", at(10), Some(vec![0, 1]));
+        assert_eq!(frames_complete(&cache), [false, true, true, true]);
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_of_other_runs_are_ignored() {
+        let mut cache =
+            FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 2]).unwrap();
+        let start = Utc::now();
+        cache.start_run("Run1", start, Some(vec![0, 1]));
+        cache.stop_run("Run0", start);
+        assert_eq!(cache.current_expected_digitisers(), &[0, 1]);
+
+        // Starting a run stops any still in progress.
+        cache.start_run("Run2", start + chrono::TimeDelta::milliseconds(5), None);
+        cache.stop_run("Run1", start + chrono::TimeDelta::milliseconds(10));
+        assert_eq!(
+            cache.runs[0].stop,
+            Some(start + chrono::TimeDelta::milliseconds(5))
+        );
+        assert_eq!(cache.runs[1].stop, None);
+    }
 }
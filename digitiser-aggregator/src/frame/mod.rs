@@ -5,13 +5,109 @@
 mod aggregated;
 mod cache;
 mod partial;
+mod run;
 
+use clap::ValueEnum;
 use digital_muon_common::DigitizerId;
 use thiserror::Error;
 
 pub(crate) use aggregated::AggregatedFrame;
 pub(crate) use cache::FrameCache;
 
+/// Determines what the [FrameCache] does with a frame whose TTL expires
+/// before messages have been received from all expected digitisers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum PartialFramePolicy {
+    /// The frame is dispatched with the data received so far, and marked incomplete.
+    #[default]
+    Emit,
+    /// The frame is discarded.
+    Drop,
+}
+
+/// Determines what the [FrameCache] does with a digitiser message for a frame
+/// which has already received a message from the same digitiser.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum DuplicatePolicy {
+    /// The duplicate message is discarded, and the first message's data is kept.
+    #[default]
+    Ignore,
+    /// The duplicate message's data replaces that of the previous message.
+    Replace,
+    /// The duplicate message is discarded, and rejected with [RejectMessageError::IdAlreadyPresent].
+    Error,
+}
+
+impl DuplicatePolicy {
+    /// Returns the label of the outcome of applying this policy to a duplicate message.
+    pub(crate) fn outcome(self) -> &'static str {
+        match self {
+            Self::Ignore => "ignored",
+            Self::Replace => "replaced",
+            Self::Error => "rejected",
+        }
+    }
+}
+
+/// Determines what the [FrameCache] does with a frame, complete or partial, whose
+/// [veto_flags] share any bits with the veto mask.
+///
+/// [veto_flags]: digital_muon_streaming_types::FrameMetadata::veto_flags
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum VetoPolicy {
+    /// The frame is discarded.
+    #[default]
+    Drop,
+    /// The frame is dispatched, with a [VETOED_HEADER] header giving the vetoed flags.
+    ForwardFlagged,
+}
+
+impl VetoPolicy {
+    /// Returns the label of the outcome of applying this policy to a vetoed frame.
+    pub(crate) fn outcome(self) -> &'static str {
+        match self {
+            Self::Drop => "dropped",
+            Self::ForwardFlagged => "forwarded",
+        }
+    }
+}
+
+/// Determines what the [FrameCache] does with a late digitiser message, that is one for a frame which
+/// was dispatched, without data from the message's digitiser, within the late arrival window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LatePolicy {
+    /// The message is discarded.
+    #[default]
+    Drop,
+    /// A supplementary frame containing only the message's data is dispatched, with a [SUPPLEMENT_HEADER] header.
+    EmitSupplement,
+    /// The message is discarded, and logged as a warning.
+    LogOnly,
+}
+
+impl LatePolicy {
+    /// Returns the label of the outcome of applying this policy to a late message.
+    pub(crate) fn outcome(self) -> &'static str {
+        match self {
+            Self::Drop => "dropped",
+            Self::EmitSupplement => "supplemented",
+            Self::LogOnly => "logged",
+        }
+    }
+}
+
+/// The key of the Kafka header which marks a frame as a supplement to one already dispatched,
+/// see [LatePolicy::EmitSupplement]. Its value is the id of the late digitiser.
+pub(crate) const SUPPLEMENT_HEADER: &str = "supplement";
+
+/// The key of the Kafka header which marks a frame as vetoed, see [VetoPolicy::ForwardFlagged].
+pub(crate) const VETOED_HEADER: &str = "vetoed";
+
+/// Formats veto flags as the hexadecimal value of their bits, as used in the [VETOED_HEADER] header and metric labels.
+pub(crate) fn format_veto_flags(veto_flags: u16) -> String {
+    format!("{veto_flags:#06x}")
+}
+
 /// Represents errors in the [FrameCache] object.
 #[derive(Debug, Error)]
 pub(crate) enum FrameCacheError {
@@ -20,12 +116,24 @@ pub(crate) enum FrameCacheError {
     DuplicateDigitiserId(Vec<DigitizerId>),
 }
 
+/// How a digitiser event list message accepted by the [FrameCache] arrived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Arrival {
+    /// The message's frame had not been dispatched.
+    OnTime,
+    /// The message's frame had been dispatched within the late arrival window,
+    /// and the message is dispatched as a supplementary frame, see [LatePolicy::EmitSupplement].
+    Late,
+}
+
 /// Represents the reason why a digitiser event list message is rejected
 pub(crate) enum RejectMessageError {
     /// The frame has already encountered an event list from this digitiser.
     IdAlreadyPresent,
     /// The event list's timestamp occurs before [FrameCache::latest_timestamp_dispatched].
     TimestampTooEarly,
+    /// The event list is late for a frame dispatched within the late arrival window, and is discarded by the [LatePolicy].
+    LateArrival,
 }
 
 impl From<RejectMessageError> for &'static str {
@@ -33,6 +141,7 @@ impl From<RejectMessageError> for &'static str {
         match value {
             RejectMessageError::IdAlreadyPresent => "id_already_present",
             RejectMessageError::TimestampTooEarly => "timestamp_too_early",
+            RejectMessageError::LateArrival => "late_arrival",
         }
     }
 }
@@ -1,8 +1,9 @@
 //! Defines the struct for a frame which is awaiting data from digitiser messages.
 use crate::data::DigitiserData;
 use digital_muon_common::{
-    DigitizerId,
+    DigitizerId, FrameKey,
     spanned::{SpanOnce, SpanOnceError, Spanned, SpannedAggregator, SpannedMut},
+    tracer::FrameCorrelation,
 };
 use digital_muon_streaming_types::FrameMetadata;
 use std::time::Duration;
@@ -20,21 +21,37 @@ pub(crate) struct PartialFrame<D> {
     /// Time at which the partial frame should be considered expired, and can be dispatched
     /// from the cache even if incomplete.
     expiry: Instant,
+    /// Time at which the first digitiser message of the frame arrived.
+    first_arrival: Instant,
+    /// Time at which the most recent digitiser message of the frame arrived.
+    last_arrival: Instant,
+    /// Identifies the frame, ignoring veto flags, so that digitiser messages can be matched to it.
+    pub(super) key: FrameKey,
     /// The uniquely identifying metadata of the frame, common to all digitiser messages related to this frame (except possibly for [FrameMetadata::veto_flags]).
     pub(super) metadata: FrameMetadata,
+    /// The correlation of the frame, as propagated in the header of the digitiser message which created it.
+    pub(super) correlation: FrameCorrelation,
     /// The frame's event data.
     pub(super) digitiser_data: DigitiserData<D>,
 }
 
 impl<D> PartialFrame<D> {
-    pub(super) fn new(ttl: Duration, metadata: FrameMetadata) -> Self {
-        let expiry = Instant::now() + ttl;
+    pub(super) fn new(
+        ttl: Duration,
+        metadata: FrameMetadata,
+        correlation: FrameCorrelation,
+    ) -> Self {
+        let now = Instant::now();
 
         Self {
             span: SpanOnce::default(),
             complete: false,
-            expiry,
+            expiry: now + ttl,
+            first_arrival: now,
+            last_arrival: now,
+            key: FrameKey::from(&metadata),
             metadata,
+            correlation,
             digitiser_data: Default::default(),
         }
     }
@@ -70,6 +87,28 @@ impl<D> PartialFrame<D> {
     /// - data: the data in the message.
     pub(super) fn push(&mut self, digitiser_id: DigitizerId, data: D) {
         self.digitiser_data.push((digitiser_id, data));
+        self.last_arrival = Instant::now();
+    }
+
+    /// Replaces the data previously pushed by a digitiser with the given data.
+    /// If the digitiser has not pushed any data, then nothing is replaced.
+    /// # Parameters
+    /// - digitiser_id: the id of the digitiser sending the data.
+    /// - data: the data in the message.
+    pub(super) fn replace(&mut self, digitiser_id: DigitizerId, data: D) {
+        if let Some((_, existing)) = self
+            .digitiser_data
+            .iter_mut()
+            .find(|(id, _)| *id == digitiser_id)
+        {
+            *existing = data;
+            self.last_arrival = Instant::now();
+        }
+    }
+
+    /// Returns the time between the arrival of the first and the most recent digitiser messages.
+    pub(super) fn assembly_duration(&self) -> Duration {
+        self.last_arrival - self.first_arrival
     }
 
     /// Ammends the metadata [veto_flags] field with `veto_flags` from a new digitiser message.
@@ -107,16 +146,21 @@ impl<D> SpannedMut for PartialFrame<D> {
 }
 
 impl<D> SpannedAggregator for PartialFrame<D> {
+    /// The span is a root span, which is linked to the frame's [FrameCorrelation],
+    /// so that it can be found alongside the spans of other components which handled the frame.
     fn span_init(&mut self) -> Result<(), SpanOnceError> {
-        self.span.init(info_span!(parent: None, "Frame",
+        let span = info_span!(parent: None, "Frame",
             "metadata_timestamp" = self.metadata.timestamp.to_rfc3339(),
             "metadata_frame_number" = self.metadata.frame_number,
             "metadata_period_number" = self.metadata.period_number,
             "metadata_veto_flags" = self.metadata.veto_flags,
             "metadata_protons_per_pulse" = self.metadata.protons_per_pulse,
             "metadata_running" = self.metadata.running,
+            "frame_correlation" = self.correlation.to_string(),
             "frame_is_expired" = tracing::field::Empty,
-        ))
+        );
+        self.correlation.link_span(&span);
+        self.span.init(span)
     }
 
     fn link_current_span<F: Fn() -> Span>(
@@ -0,0 +1,150 @@
+//! Defines the runs, and the set of digitisers expected to report in each frame of a run.
+use chrono::{DateTime, Utc};
+use digital_muon_common::DigitizerId;
+use std::collections::BTreeSet;
+
+/// A run, whose frames are those whose timestamps are from its start time until its stop time, if it has stopped.
+pub(super) struct Run {
+    pub(super) name: String,
+    pub(super) start: DateTime<Utc>,
+    /// The stop time of the run, or [None] whilst it is in progress.
+    pub(super) stop: Option<DateTime<Utc>>,
+    /// The digitisers expected in the frames of the run, if they differ from those given on construction.
+    pub(super) digitisers: Option<RunDigitisers>,
+}
+
+impl Run {
+    /// Returns true if a frame with `timestamp` belongs to the run.
+    pub(super) fn contains(&self, timestamp: DateTime<Utc>) -> bool {
+        self.start <= timestamp && self.stop.is_none_or(|stop| timestamp < stop)
+    }
+
+    /// Returns the digitisers expected in the frames of the run, or [None] if they are those given on construction.
+    pub(super) fn digitiser_ids(&self) -> Option<&[DigitizerId]> {
+        self.digitisers
+            .as_ref()
+            .and_then(RunDigitisers::digitiser_ids)
+    }
+}
+
+/// The digitisers expected in each frame of a run, in place of those given on the command line.
+pub(super) enum RunDigitisers {
+    /// The digitisers declared in the run start message, in increasing order without repetitions.
+    Declared(Vec<DigitizerId>),
+    /// The digitisers are being learned from the frames of the run,
+    /// until then those given on the command line are expected.
+    Learning {
+        /// The number of frames still to be dispatched before the digitisers seen so far are expected.
+        remaining_frames: usize,
+        /// The digitisers seen in the frames dispatched so far.
+        seen: BTreeSet<DigitizerId>,
+    },
+    /// The digitisers seen in the first frames of the run, in increasing order.
+    Learned(Vec<DigitizerId>),
+}
+
+impl RunDigitisers {
+    /// Returns the declared digitisers, if given, otherwise starts learning the digitisers from the
+    /// first `learn_frames` frames of the run. Returns [None] if neither is the case.
+    pub(super) fn new(declared: Option<Vec<DigitizerId>>, learn_frames: usize) -> Option<Self> {
+        match declared {
+            Some(mut digitiser_ids) => {
+                digitiser_ids.sort();
+                digitiser_ids.dedup();
+                Some(Self::Declared(digitiser_ids))
+            }
+            None if learn_frames > 0 => Some(Self::Learning {
+                remaining_frames: learn_frames,
+                seen: Default::default(),
+            }),
+            None => None,
+        }
+    }
+
+    /// Returns the expected digitisers, or [None] if they are still being learned.
+    pub(super) fn digitiser_ids(&self) -> Option<&[DigitizerId]> {
+        match self {
+            Self::Declared(digitiser_ids) | Self::Learned(digitiser_ids) => Some(digitiser_ids),
+            Self::Learning { .. } => None,
+        }
+    }
+
+    /// Returns the label of how the expected digitisers were found.
+    pub(super) fn source(&self) -> &'static str {
+        match self {
+            Self::Declared(_) => "declared",
+            Self::Learning { .. } => "learning",
+            Self::Learned(_) => "learned",
+        }
+    }
+
+    /// Learns from `digitiser_ids`, which are those of a frame being dispatched.
+    /// Returns `true` if this is the last frame to be learned from, so the expected digitisers have changed.
+    pub(super) fn observe(&mut self, digitiser_ids: &[DigitizerId]) -> bool {
+        let Self::Learning {
+            remaining_frames,
+            seen,
+        } = self
+        else {
+            return false;
+        };
+        seen.extend(digitiser_ids);
+        *remaining_frames = remaining_frames.saturating_sub(1);
+        if *remaining_frames == 0 {
+            *self = Self::Learned(seen.iter().copied().collect());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared_digitisers_are_ordered() {
+        let run = RunDigitisers::new(Some(vec![4, 1, 4, 0]), 3).unwrap();
+        assert_eq!(run.digitiser_ids(), Some([0, 1, 4].as_slice()));
+        assert_eq!(run.source(), "declared");
+    }
+
+    #[test]
+    fn digitisers_learned_from_first_frames() {
+        let mut run = RunDigitisers::new(None, 2).unwrap();
+        assert_eq!(run.digitiser_ids(), None);
+        assert!(!run.observe(&[3, 1]));
+        assert_eq!(run.digitiser_ids(), None);
+        assert!(run.observe(&[0, 1]));
+        assert_eq!(run.digitiser_ids(), Some([0, 1, 3].as_slice()));
+        assert_eq!(run.source(), "learned");
+
+        // Later frames do not change the learned digitisers.
+        assert!(!run.observe(&[7]));
+        assert_eq!(run.digitiser_ids(), Some([0, 1, 3].as_slice()));
+    }
+
+    #[test]
+    fn no_run_digitisers_without_learning() {
+        assert!(RunDigitisers::new(None, 0).is_none());
+    }
+
+    #[test]
+    fn runs_contain_frames_from_start_until_stop() {
+        let at = |ms| DateTime::from_timestamp_millis(ms).unwrap();
+        let mut run = Run {
+            name: "Run1".to_owned(),
+            start: at(10),
+            stop: None,
+            digitisers: None,
+        };
+        assert!(!run.contains(at(9)));
+        assert!(run.contains(at(10)));
+        assert!(run.contains(at(1000)));
+
+        run.stop = Some(at(20));
+        assert!(run.contains(at(19)));
+        assert!(!run.contains(at(20)));
+    }
+}
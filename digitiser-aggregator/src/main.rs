@@ -11,7 +11,11 @@
 //! * Employs multithreading to allow messages to be dispatched whilst waiting for digitiser messages.
 //! * Records completion status of a frame event list message as well as all digitiser ids that contributed to it.
 //! * Ignores any digitiser message whose timestamp is before the that of last frame event list to be dispatched.
-//! * Ignores any digitiser message whose [id] and [metadata] have already been seen.
+//! * Ignores, or replaces with, any digitiser message whose [id] and [metadata] have already been seen, as set by [DuplicatePolicy].
+//! * Drops, or forwards with a header, any frame whose veto flags match a user specified mask, as set by [VetoPolicy].
+//! * Drops, logs, or dispatches as a supplementary frame, any digitiser message arriving shortly after its frame was dispatched, as set by [LatePolicy].
+//! * Optionally expects, in the frames of each run, the digitisers declared by its run start message, or learned from its first frames,
+//!   rather than those given on the command line. A frame belongs to a run if its timestamp is between the run's start and stop times.
 //!
 //! ## Assumptions
 //! * That each [DigitizerEventListMessage] has equally sized event fields (i.e. [time], [channel], and [voltage] are
@@ -37,18 +41,28 @@ mod data;
 mod frame;
 
 use crate::data::EventData;
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use digital_muon_common::{
-    CommonKafkaOpts, DigitizerId, init_tracer,
+    CommonKafkaOpts, DigitizerId, failure, init_tracer,
     metrics::{
         component_info_metric,
-        failures::{self, FailureKind},
+        failures::FailureKind,
         messages_received::{self, MessageKind},
-        names::{FAILURES, FRAMES_SENT, MESSAGES_PROCESSED, MESSAGES_RECEIVED},
+        names::{
+            DUPLICATE_DIGITISER_MESSAGES, EXPECTED_DIGITISERS, FAILURES,
+            FRAME_ASSEMBLY_DURATION_MS, FRAME_COMPLETION_LATENCY_MS, FRAMES_IN_FLIGHT, FRAMES_SENT,
+            LATE_DIGITISER_MESSAGES, MESSAGES_PROCESSED, MESSAGES_RECEIVED, PARTIAL_FRAMES_DROPPED,
+            PARTIAL_FRAMES_EMITTED, VETOED_FRAMES,
+        },
     },
     record_metadata_fields_to_span,
+    seek::create_consumer_at_tail,
     spanned::Spanned,
-    tracer::{FutureRecordTracerExt, OptionalHeaderTracerExt, TracerEngine, TracerOptions},
+    tracer::{
+        FrameCorrelation, FutureRecordTracerExt, OptionalHeaderTracerExt, TracerEngine,
+        TracerOptions,
+    },
 };
 use digital_muon_streaming_types::{
     dev2_digitizer_event_v2_generated::{
@@ -57,16 +71,25 @@ use digital_muon_streaming_types::{
     },
     flatbuffers::InvalidFlatbuffer,
 };
-use frame::{AggregatedFrame, FrameCache};
+use frame::{
+    AggregatedFrame, Arrival, DuplicatePolicy, FrameCache, LatePolicy, PartialFramePolicy,
+    VetoPolicy,
+};
+use isis_streaming_data_types::flatbuffers_generated::{
+    run_start_pl72::{RunStart, root_as_run_start, run_start_buffer_has_identifier},
+    run_stop_6s4t::{root_as_run_stop, run_stop_buffer_has_identifier},
+};
 use metrics::counter;
 use metrics_exporter_prometheus::PrometheusBuilder;
 use miette::{Context, IntoDiagnostic};
 use rdkafka::{
-    consumer::{CommitMode, Consumer},
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    error::KafkaResult,
     message::{BorrowedMessage, Message},
     producer::{FutureProducer, FutureRecord},
     util::Timeout,
 };
+use serde::Deserialize;
 use std::{fmt::Debug, net::SocketAddr, time::Duration};
 use tokio::{
     select,
@@ -79,6 +102,13 @@ use tracing::{debug, error, info, info_span, instrument, warn};
 /// Triggers error if the producer takes longer than this to dispatch a message.
 const PRODUCER_TIMEOUT: Timeout = Timeout::After(Duration::from_millis(100));
 
+/// The number of the latest messages of each partition of the control topic which are consumed on startup,
+/// so that the run in progress, and those whose frames may still arrive, are found.
+const CONTROL_TOPIC_REPLAY_MESSAGES: i64 = 100;
+
+/// How long to wait for the broker when looking up the offsets of the control topic.
+const CONTROL_TOPIC_TIMEOUT: Duration = Duration::from_secs(10);
+
 type AggregatedFrameToBufferSender = Sender<AggregatedFrame<EventData>>;
 type SendAggregatedFrameError = SendError<AggregatedFrame<EventData>>;
 
@@ -107,11 +137,58 @@ struct Cli {
     #[clap(short, long, value_delimiter = ',')]
     digitiser_ids: Vec<DigitizerId>,
 
+    /// Kafka topic on which to listen for run start and run stop messages.
+    /// If set, the digitisers expected in the frames of each run are those declared by the `digitiser_ids` list of the JSON metadata
+    /// of its run start message, or if it has none, those learned according to `--learn-digitisers-frames`.
+    /// A frame belongs to a run if its timestamp is from the run's start time until its stop time.
+    /// Between runs, the digitisers given by `--digitiser-ids` are expected.
+    /// Every partition of the topic is consumed, outside of the consumer group, from the latest messages before startup.
+    #[clap(long)]
+    control_topic: Option<String>,
+
+    /// If nonzero, the digitisers expected during a run, which does not declare them, are those seen in this many of its first frames.
+    /// Until these frames have been dispatched, the digitisers given by `--digitiser-ids` are expected.
+    #[clap(long, default_value = "0")]
+    learn_digitisers_frames: usize,
+
     /// Frame TTL in milliseconds.
     /// The time in which messages for a given frame must have been received from all digitisers.
     #[clap(long, default_value = "500")]
     frame_ttl_ms: u64,
 
+    /// Determines what happens to a frame which has not received messages from all digitisers within its TTL.
+    /// If `emit`, it is dispatched with the data received so far, and marked incomplete. If `drop`, it is discarded.
+    #[clap(long, value_enum, default_value_t = PartialFramePolicy::Emit)]
+    partial_frame_policy: PartialFramePolicy,
+
+    /// Determines what happens to a message from a digitiser which has already sent a message for the same frame.
+    /// If `ignore`, it is discarded. If `replace`, its data replaces that of the previous message.
+    /// If `error`, it is discarded and recorded as an error in the message's span.
+    #[clap(long, value_enum, default_value_t = DuplicatePolicy::Ignore)]
+    duplicate_policy: DuplicatePolicy,
+
+    /// A frame, complete or partial, is vetoed if its veto flags share any bits with this mask.
+    /// If zero, no frame is vetoed.
+    #[clap(long, default_value = "0")]
+    veto_mask: u16,
+
+    /// Determines what happens to a vetoed frame.
+    /// If `drop`, it is discarded. If `forward-flagged`, it is dispatched with a `vetoed` header giving the vetoed flags.
+    #[clap(long, value_enum, default_value_t = VetoPolicy::Drop)]
+    veto_policy: VetoPolicy,
+
+    /// Time in milliseconds, after a frame is dispatched, in which a message from a digitiser missing from it is late, rather than too late.
+    /// Late messages are handled according to `--late-policy`, and too late messages are rejected.
+    /// If zero, every such message is too late.
+    #[clap(long, default_value = "0")]
+    late_arrival_window_ms: u64,
+
+    /// Determines what happens to a late message.
+    /// If `drop`, it is discarded. If `emit-supplement`, a supplementary frame, containing only its data, is dispatched with a `supplement` header.
+    /// If `log-only`, it is discarded and logged as a warning.
+    #[clap(long, value_enum, default_value_t = LatePolicy::Drop)]
+    late_policy: LatePolicy,
+
     /// Frame cache poll interval in milliseconds.
     /// This may affect the rate at which incomplete frames are transmitted.
     #[clap(long, default_value = "500")]
@@ -156,18 +233,44 @@ async fn main() -> miette::Result<()> {
     )
     .into_diagnostic()?;
 
+    let control_consumer = args
+        .control_topic
+        .as_deref()
+        .map(|control_topic| {
+            create_consumer_at_tail(
+                &kafka_opts.broker,
+                &kafka_opts.username,
+                &kafka_opts.password,
+                &args.consumer_group,
+                &[control_topic],
+                CONTROL_TOPIC_REPLAY_MESSAGES,
+                CONTROL_TOPIC_TIMEOUT,
+            )
+        })
+        .transpose()
+        .into_diagnostic()?;
+
     let producer: FutureProducer = digital_muon_common::generate_kafka_client_config(
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
+        None,
     )
     .create()
     .into_diagnostic()?;
 
     let ttl = Duration::from_millis(args.frame_ttl_ms);
 
-    let mut cache =
-        FrameCache::<EventData>::new(ttl, args.digitiser_ids.clone()).into_diagnostic()?;
+    let mut cache = FrameCache::<EventData>::new(ttl, args.digitiser_ids.clone())
+        .into_diagnostic()?
+        .with_partial_frame_policy(args.partial_frame_policy)
+        .with_duplicate_policy(args.duplicate_policy)
+        .with_veto_filter(args.veto_mask, args.veto_policy)
+        .with_late_arrival(
+            Duration::from_millis(args.late_arrival_window_ms),
+            args.late_policy,
+        )
+        .with_learned_digitisers(args.learn_digitisers_frames);
 
     // Install exporter and register metrics
     let builder = PrometheusBuilder::new();
@@ -196,6 +299,53 @@ async fn main() -> miette::Result<()> {
         metrics::Unit::Count,
         "Number of complete frames sent by the aggregator"
     );
+    metrics::describe_counter!(
+        PARTIAL_FRAMES_EMITTED,
+        metrics::Unit::Count,
+        "Number of incomplete frames sent by the aggregator after their TTL expired"
+    );
+    metrics::describe_counter!(
+        PARTIAL_FRAMES_DROPPED,
+        metrics::Unit::Count,
+        "Number of incomplete frames discarded by the aggregator after their TTL expired"
+    );
+    metrics::describe_counter!(
+        DUPLICATE_DIGITISER_MESSAGES,
+        metrics::Unit::Count,
+        "Number of messages from digitisers which had already sent a message for the same frame"
+    );
+    metrics::describe_counter!(
+        VETOED_FRAMES,
+        metrics::Unit::Count,
+        "Number of frames whose veto flags matched the veto mask"
+    );
+    metrics::describe_counter!(
+        LATE_DIGITISER_MESSAGES,
+        metrics::Unit::Count,
+        "Number of messages from digitisers which arrived after their frame was dispatched"
+    );
+    metrics::describe_gauge!(
+        FRAMES_IN_FLIGHT,
+        metrics::Unit::Count,
+        "Number of incomplete frames currently in the aggregator's cache"
+    );
+    metrics::describe_histogram!(
+        FRAME_ASSEMBLY_DURATION_MS,
+        metrics::Unit::Milliseconds,
+        "Time between the first and last digitiser message of a frame arriving"
+    );
+    metrics::describe_histogram!(
+        FRAME_COMPLETION_LATENCY_MS,
+        metrics::Unit::Milliseconds,
+        "Time between a frame's metadata timestamp and its completion or expiry"
+    );
+
+    metrics::describe_gauge!(
+        EXPECTED_DIGITISERS,
+        metrics::Unit::Count,
+        "One for each digitiser from which a frame must receive a message to be complete, zero for those no longer expected"
+    );
+    cache.record_expected_digitisers();
 
     let mut cache_poll_interval = tokio::time::interval(Duration::from_millis(args.cache_poll_ms));
 
@@ -232,6 +382,18 @@ async fn main() -> miette::Result<()> {
                     Err(e) => warn!("Kafka error: {}", e),
                 };
             }
+            Some(event) = recv_control(control_consumer.as_ref()) => {
+                match event {
+                    Ok(msg) => {
+                        let span = info_span!("message_received");
+                        msg.headers().conditional_extract_to_span(tracer.use_otel(), &span);
+                        let _guard = span.enter();
+                        process_control_message(&mut cache, &msg);
+                        cache_poll(&channel_send, &mut cache).await.into_diagnostic()?;
+                    }
+                    Err(e) => warn!("Kafka error consuming control topic: {}", e),
+                };
+            }
             _ = cache_poll_interval.tick() => {
                 cache_poll(&channel_send, &mut cache).await.into_diagnostic()?;
             }
@@ -245,6 +407,16 @@ async fn main() -> miette::Result<()> {
     }
 }
 
+/// Receives the next message of the control topic, if `control_consumer` is given, otherwise never returns.
+async fn recv_control(
+    control_consumer: Option<&StreamConsumer>,
+) -> Option<KafkaResult<BorrowedMessage<'_>>> {
+    match control_consumer {
+        Some(control_consumer) => Some(control_consumer.recv().await),
+        None => std::future::pending().await,
+    }
+}
+
 ///  This function wraps the [root_as_digitizer_event_list_message] function, allowing it to be instrumented.
 #[instrument(skip_all, level = "trace", err(level = "warn"))]
 fn spanned_root_as_digitizer_event_list_message(
@@ -280,17 +452,14 @@ async fn process_kafka_message(
                         channel_send,
                         cache,
                         kafka_timestamp_ms,
+                        msg.headers().and_then(FrameCorrelation::from_headers),
                         data,
                     )
                     .await?;
                 }
                 Err(e) => {
                     warn!("Failed to parse message: {}", e);
-                    counter!(
-                        FAILURES,
-                        &[failures::get_label(FailureKind::UnableToDecodeMessage)]
-                    )
-                    .increment(1);
+                    failure!(FailureKind::UnableToDecodeMessage);
                 }
             }
         } else {
@@ -307,12 +476,108 @@ async fn process_kafka_message(
     Ok(())
 }
 
+/// The fields of the JSON metadata of a run start message which are used by the aggregator.
+#[derive(Default, Deserialize)]
+struct RunStartMetadata {
+    /// The digitisers expected in each frame of the run, if declared.
+    digitiser_ids: Option<Vec<DigitizerId>>,
+}
+
+/// Returns the digitisers declared in the metadata of `run_start`, if any.
+/// If the metadata cannot be parsed, this is logged, and no digitisers are declared.
+fn declared_digitiser_ids(run_start: &RunStart<'_>) -> Option<Vec<DigitizerId>> {
+    let metadata = run_start
+        .metadata()
+        .filter(|metadata| !metadata.is_empty())?;
+    match serde_json::from_str::<RunStartMetadata>(metadata) {
+        Ok(metadata) => metadata.digitiser_ids,
+        Err(e) => {
+            warn!("Failed to parse run start metadata: {e}");
+            None
+        }
+    }
+}
+
+/// Returns the time `millis` milliseconds after the epoch, as given by run start and stop messages, if it can be represented.
+fn run_timestamp(millis: u64) -> Option<DateTime<Utc>> {
+    i64::try_from(millis)
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+}
+
+/// Extracts the payload of a Kafka message from the control topic, and starts or stops a run in the given [FrameCache].
+/// Other messages on the control topic are ignored.
+/// # Parameters
+/// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
+/// - msg: the message.
+#[instrument(skip_all, level = "info")]
+fn process_control_message(cache: &mut FrameCache<EventData>, msg: &BorrowedMessage<'_>) {
+    let Some(payload) = msg.payload() else {
+        return;
+    };
+    if run_start_buffer_has_identifier(payload) {
+        counter!(
+            MESSAGES_RECEIVED,
+            &[messages_received::get_label(MessageKind::RunStart)]
+        )
+        .increment(1);
+        match root_as_run_start(payload) {
+            Ok(run_start) => match run_timestamp(run_start.start_time()) {
+                Some(start) => cache.start_run(
+                    run_start.run_name().unwrap_or_default(),
+                    start,
+                    declared_digitiser_ids(&run_start),
+                ),
+                None => {
+                    warn!(
+                        "Run start has invalid start time: {}",
+                        run_start.start_time()
+                    );
+                    failure!(FailureKind::InvalidTimestamp);
+                }
+            },
+            Err(e) => {
+                warn!("Failed to parse run start message: {e}");
+                failure!(FailureKind::UnableToDecodeMessage);
+            }
+        }
+    } else if run_stop_buffer_has_identifier(payload) {
+        counter!(
+            MESSAGES_RECEIVED,
+            &[messages_received::get_label(MessageKind::RunStop)]
+        )
+        .increment(1);
+        match root_as_run_stop(payload) {
+            Ok(run_stop) => match run_timestamp(run_stop.stop_time()) {
+                Some(stop) => cache.stop_run(run_stop.run_name().unwrap_or_default(), stop),
+                None => {
+                    warn!("Run stop has invalid stop time: {}", run_stop.stop_time());
+                    failure!(FailureKind::InvalidTimestamp);
+                }
+            },
+            Err(e) => {
+                warn!("Failed to parse run stop message: {e}");
+                failure!(FailureKind::UnableToDecodeMessage);
+            }
+        }
+    } else {
+        debug!(
+            "Ignoring unexpected message type on topic \"{}\"",
+            msg.topic()
+        );
+    }
+}
+
 /// Processes a [DigitizerEventListMessage], pushing it to the given [FrameCache].
 /// # Parameters
 /// - channel_send: send channel which takes [AggregatedFrame] objects to dispatch.
 /// - kafka_message_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header. Only used for tracing.
+/// - correlation: the frame correlation of the Kafka message's header, if it has one, otherwise it is derived from the message's metadata.
+///   The span is linked to it, and it is recorded to the `frame_correlation` field.
+///   A new partial frame keeps it, so that it is propagated to the frame's event list.
 /// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
-/// - message: the digitiser message.
+/// - message: the digitiser message. If it is late for a dispatched frame, the `late_arrival` field is recorded,
+///   whether it is dropped or dispatched as a supplementary frame.
 #[tracing::instrument(skip_all, fields(
     digitiser_id = message.digitizer_id(),
     kafka_message_timestamp_ms=kafka_message_timestamp_ms,
@@ -322,23 +587,41 @@ async fn process_kafka_message(
     metadata_veto_flags,
     metadata_protons_per_pulse,
     metadata_running,
+    frame_correlation,
     num_cached_frames = cache.get_num_partial_frames(),
     timestamp_too_early = false,
     id_already_present = false,
+    late_arrival = false,
 ))]
 async fn process_digitiser_event_list_message(
     channel_send: &AggregatedFrameToBufferSender,
     cache: &mut FrameCache<EventData>,
     kafka_message_timestamp_ms: i64,
+    correlation: Option<FrameCorrelation>,
     message: DigitizerEventListMessage<'_>,
 ) -> Result<(), SendAggregatedFrameError> {
     match message.metadata().try_into() {
         Ok(metadata) => {
             debug!("Event packet: metadata: {:?}", message.metadata());
 
+            let correlation = correlation.unwrap_or_else(|| FrameCorrelation::new(&metadata));
+            correlation.link_span(&tracing::Span::current());
+            tracing::Span::current().record("frame_correlation", correlation.to_string());
+
             // Push the current digitiser message to the frame cache, possibly creating a new partial frame
-            if let Err(err) = cache.push(message.digitizer_id(), &metadata, message.into()) {
-                tracing::Span::current().record(err.into(), true);
+            match cache.push(
+                message.digitizer_id(),
+                &metadata,
+                correlation,
+                message.into(),
+            ) {
+                Ok(Arrival::OnTime) => {}
+                Ok(Arrival::Late) => {
+                    tracing::Span::current().record("late_arrival", true);
+                }
+                Err(err) => {
+                    tracing::Span::current().record(err.into(), true);
+                }
             }
 
             record_metadata_fields_to_span!(&metadata, tracing::Span::current());
@@ -347,11 +630,7 @@ async fn process_digitiser_event_list_message(
         }
         Err(e) => {
             warn!("Invalid Metadata: {e}");
-            counter!(
-                FAILURES,
-                &[failures::get_label(FailureKind::InvalidMetadata)]
-            )
-            .increment(1);
+            failure!(FailureKind::InvalidMetadata);
         }
     }
     Ok(())
@@ -503,7 +782,7 @@ async fn flush_frame(
 
 /// Dispatches the given frame to the Kafka broker on the given topic.
 /// # Parameters
-/// - use_otel: if true, then the thread attempts to inject [AggregatedFrame::span()] into the Kafka header.
+/// - use_otel: if true, then the thread attempts to inject [AggregatedFrame::span()], and the [FrameCorrelation] of the frame, into the Kafka header.
 /// - frame: the frame to dispatch.
 /// - producer: the Kafka producer object.
 /// - output_topic: the Kafka topic to produce the message to.
@@ -515,11 +794,15 @@ async fn produce_frame_to_kafka(
     output_topic: &str,
 ) {
     let frame_span = frame.span().get().expect("Span should exist").clone();
+    let headers = frame.headers();
+    let correlation = frame.correlation;
     let data: Vec<u8> = frame.into();
 
     let future_record = FutureRecord::to(output_topic)
         .payload(data.as_slice())
+        .optional_headers(headers)
         .conditional_inject_span_into_headers(use_otel, &frame_span)
+        .conditional_inject_frame_correlation(use_otel, Some(&correlation))
         .key("Frame Events List");
 
     match producer.send(future_record, PRODUCER_TIMEOUT).await {
@@ -529,11 +812,7 @@ async fn produce_frame_to_kafka(
         }
         Err(e) => {
             error!("Delivery failed: {:?}", e);
-            counter!(
-                FAILURES,
-                &[failures::get_label(FailureKind::KafkaPublishFailed)]
-            )
-            .increment(1);
+            failure!(FailureKind::KafkaPublishFailed);
         }
     }
 }
@@ -50,6 +50,9 @@ pub(crate) enum NexusWriterError {
     /// Indicates an IO error, most likely a file handling error.
     #[error("IO Error: {0}")]
     IO(#[from] std::io::Error),
+    /// Indicates a JSON (de)serialisation error, most likely from a malformed offsets sidecar file.
+    #[error("JSON Error: {0}")]
+    Json(#[from] serde_json::Error),
     /// Error converting one integer type to another, either trying to convert a negative to an unsigned, or a value is too large to fit into the bit-width.
     #[error("Integer Conversion Error")]
     TryFromInt(#[from] TryFromIntError),
@@ -133,6 +136,9 @@ pub(crate) enum FlatBufferMissingError {
     /// The alarm status string is missing.
     #[error("Status Missing from Flatbuffer Alarm Message")]
     AlarmMessage,
+    /// The connection status is not a known variant.
+    #[error("Unknown Status in Flatbuffer Connection Status Message")]
+    ConnectionStatus,
     /// The file name string is missing.
     #[error("File name missing from flatbuffer RunStart Message")]
     FileName,
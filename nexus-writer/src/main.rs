@@ -28,7 +28,10 @@ use digital_muon_common::{
     metrics::{
         component_info_metric,
         messages_received::{self, MessageKind},
-        names::{FAILURES, MESSAGES_PROCESSED, MESSAGES_RECEIVED},
+        names::{
+            FAILURES, MESSAGES_PROCESSED, MESSAGES_RECEIVED, RUN_EVENT_LAG_SECONDS,
+            RUN_EVENTS_WRITTEN, RUN_FILE_SIZE_BYTES, RUN_FLUSHES,
+        },
     },
     tracer::{OptionalHeaderTracerExt, TracerEngine, TracerOptions},
 };
@@ -44,16 +47,23 @@ use metrics_exporter_prometheus::PrometheusBuilder;
 use miette::IntoDiagnostic;
 use nexus::NexusFile;
 use rdkafka::{
-    consumer::{CommitMode, Consumer},
+    Offset,
+    consumer::{CommitMode, Consumer, StreamConsumer},
     message::{BorrowedMessage, Message},
+    topic_partition_list::TopicPartitionList,
+};
+use run_engine::{
+    NexusConfiguration, NexusEngine, NexusEngineDependencies, NexusSettings, RUN_METRICS_MASK,
+    RunOffsets,
+};
+use std::{
+    collections::HashMap, fs::create_dir_all, marker::PhantomData, net::SocketAddr, path::PathBuf,
 };
-use run_engine::{NexusConfiguration, NexusEngine, NexusEngineDependencies, NexusSettings};
-use std::{fs::create_dir_all, marker::PhantomData, net::SocketAddr, path::PathBuf};
 use tokio::{
     signal::unix::{SignalKind, signal},
     time,
 };
-use tracing::{debug, error, info_span, warn};
+use tracing::{debug, error, info, info_span, warn};
 
 /// [clap] derived struct to handle command line parameters.
 #[derive(Debug, Parser)]
@@ -102,6 +112,18 @@ struct Cli {
     #[clap(long, default_value = "60")]
     archive_flush_interval_sec: u64,
 
+    /// Minimum time in seconds between flushes of a run's NeXus file to disk. Each flush also records the Kafka offsets written to the run in a sidecar file, from which consumption resumes if the writer is interrupted mid-run. If zero, the file is flushed after every message
+    #[clap(long, default_value = "0")]
+    flush_interval_s: u64,
+
+    /// The number of event list messages written to a run between logs of its progress, that is the events written, file size and flushes of the run. If zero, progress is never logged
+    #[clap(long, default_value = "1000")]
+    progress_log_interval_frames: u64,
+
+    /// The time in seconds for which the progress metrics of a run remain after it has completed
+    #[clap(long, default_value = "60")]
+    run_metrics_retention_s: u64,
+
     /// How often in milliseconds expired runs are checked for and removed
     #[clap(long, default_value = "200")]
     cache_poll_interval_ms: u64,
@@ -183,6 +205,8 @@ async fn main() -> miette::Result<()> {
         args.event_list_chunk_size,
         args.archive_path.as_deref(),
         args.archive_flush_interval_sec,
+        args.flush_interval_s,
+        args.progress_log_interval_frames,
     );
 
     let mut cache_poll_interval =
@@ -205,9 +229,14 @@ async fn main() -> miette::Result<()> {
         topics_subscriber,
     );
     nexus_engine.resume_partial_runs().into_diagnostic()?;
+    let mut recovered_offsets = RecoveredOffsets::new(&nexus_engine);
 
     // Install exporter and register metrics
-    let builder = PrometheusBuilder::new();
+    // The progress metrics of active runs are republished as they are polled, so only those of completed runs become idle.
+    let builder = PrometheusBuilder::new().idle_timeout(
+        RUN_METRICS_MASK,
+        Some(time::Duration::from_secs(args.run_metrics_retention_s)),
+    );
     builder
         .with_http_listener(args.observability_address)
         .install()
@@ -228,6 +257,26 @@ async fn main() -> miette::Result<()> {
         metrics::Unit::Count,
         "Number of failures encountered"
     );
+    metrics::describe_gauge!(
+        RUN_EVENTS_WRITTEN,
+        metrics::Unit::Count,
+        "Number of muon events written to each active run"
+    );
+    metrics::describe_gauge!(
+        RUN_FILE_SIZE_BYTES,
+        metrics::Unit::Bytes,
+        "Size of the file of each active run when it was last flushed"
+    );
+    metrics::describe_gauge!(
+        RUN_FLUSHES,
+        metrics::Unit::Count,
+        "Number of flushes of the file of each active run"
+    );
+    metrics::describe_gauge!(
+        RUN_EVENT_LAG_SECONDS,
+        metrics::Unit::Seconds,
+        "Time from the timestamp of the latest frame written to each active run until it was written"
+    );
 
     let run_ttl =
         Duration::try_milliseconds(args.cache_run_ttl_ms).expect("Conversion is possible");
@@ -241,12 +290,18 @@ async fn main() -> miette::Result<()> {
         tokio::select! {
             _ = cache_poll_interval.tick() => {
                 nexus_engine.flush(&run_ttl).into_diagnostic()?;
+                commit_offsets(&consumer, &nexus_engine.get_flushed_offsets(), CommitMode::Async);
+                // The component info is a gauge, so is removed by the exporter unless it is republished, like the run metrics.
+                component_info_metric("nexus-writer");
             }
             event = consumer.recv() => {
                 match event {
                     Err(e) => {
                         warn!("{e}")
                     },
+                    Ok(msg) if recovered_offsets.seek_past(&consumer, &msg) => {
+                        debug!("Skipping message already written to a recovered run");
+                    }
                     Ok(msg) => {
                         let span = info_span!("message_received");
                         msg.headers().conditional_extract_to_span(tracer.use_otel(), &span);
@@ -254,14 +309,15 @@ async fn main() -> miette::Result<()> {
 
                         process_kafka_message(&topics, &mut nexus_engine, &msg);
 
-                        if let Err(e) = consumer.commit_message(&msg, CommitMode::Async) {
-                            error!("Failed to commit Kafka message consumption: {e}");
+                        if let Err(e) = nexus_engine.commit_offset(msg.topic(), msg.partition(), msg.offset()) {
+                            error!("Failed to flush runs after committing message: {e}");
                         }
                     }
                 }
             }
             _ = sigint.recv() => {
-                nexus_engine.close_all().into_diagnostic()?;
+                let offsets = nexus_engine.close_all().into_diagnostic()?;
+                commit_offsets(&consumer, &offsets, CommitMode::Sync);
                 // Await completion of the archive_flush_task (which also receives sigint)
                 if let Some(archive_flush_task) = archive_flush_task {
                     let _ = archive_flush_task.await.into_diagnostic()?;
@@ -272,6 +328,89 @@ async fn main() -> miette::Result<()> {
     }
 }
 
+/// Commits consumption to Kafka of the messages up to and including the given offsets,
+/// which should only be those of messages which have been written to disk, so none are lost if the writer is killed.
+/// # Parameters
+/// - consumer: the consumer which received the messages.
+/// - offsets: the offset of the latest message to commit from each partition.
+/// - mode: whether to block until the commit completes.
+fn commit_offsets(consumer: &StreamConsumer, offsets: &RunOffsets, mode: CommitMode) {
+    let mut partitions = TopicPartitionList::new();
+    for (topic, partition, offset) in offsets.iter() {
+        if let Err(e) =
+            partitions.add_partition_offset(topic, partition, Offset::Offset(offset + 1))
+        {
+            error!(
+                "Failed to commit Kafka message consumption of {topic} partition {partition}: {e}"
+            );
+        }
+    }
+    if partitions.count() == 0 {
+        return;
+    }
+    if let Err(e) = consumer.commit(&partitions, mode) {
+        error!("Failed to commit Kafka message consumption: {e}");
+    }
+}
+
+/// The offsets from which consumption of each partition resumes, so that messages already written
+/// to the partial runs recovered on startup are not written to them again.
+///
+/// Partitions are assigned to the consumer by its group, so a partition is sought once its first message is received.
+struct RecoveredOffsets {
+    /// Maps each topic and partition not yet resumed to the offset of the first message not written to the recovered runs,
+    /// and whether the consumer has been sought to it.
+    resume_from: HashMap<(String, i32), (i64, bool)>,
+}
+
+impl RecoveredOffsets {
+    /// Creates the offsets of the runs recovered by `nexus_engine`.
+    /// # Parameters
+    /// - nexus_engine: the engine containing the recovered runs.
+    fn new(nexus_engine: &NexusEngine<EngineDependencies>) -> Self {
+        let resume_from = nexus_engine
+            .get_recovered_offsets()
+            .iter()
+            .map(|(topic, partition, offset)| {
+                info!(
+                    "Resuming {topic} partition {partition} from offset {}",
+                    offset + 1
+                );
+                ((topic.to_owned(), partition), (offset + 1, false))
+            })
+            .collect();
+        Self { resume_from }
+    }
+
+    /// Returns `true` if `msg` was already written to the recovered runs, in which case it should be skipped,
+    /// and, if it has not been already, seeks the consumer to the first message of the partition which was not.
+    /// # Parameters
+    /// - consumer: the consumer which received the message.
+    /// - msg: the message received.
+    fn seek_past(&mut self, consumer: &StreamConsumer, msg: &BorrowedMessage) -> bool {
+        let key = (msg.topic().to_owned(), msg.partition());
+        let Some((resume_from, sought)) = self.resume_from.get_mut(&key) else {
+            return false;
+        };
+        if msg.offset() >= *resume_from {
+            self.resume_from.remove(&key);
+            return false;
+        }
+        if !*sought {
+            match consumer.seek(
+                msg.topic(),
+                msg.partition(),
+                Offset::Offset(*resume_from),
+                time::Duration::from_secs(1),
+            ) {
+                Ok(()) => *sought = true,
+                Err(e) => warn!("Failed to seek past messages written to recovered runs: {e}"),
+            }
+        }
+        true
+    }
+}
+
 /// Extracts the payload of a Kafka message and passes it to a function in [message_handlers]
 /// depending on the topic from which the message was processed.
 /// # Parameters
@@ -25,6 +25,9 @@ use isis_streaming_data_types::flatbuffers_generated::{
     data_se00::{
         root_as_se_00_sample_environment_data, se_00_sample_environment_data_buffer_has_identifier,
     },
+    epics_connection_ep01::{
+        epics_pvconnection_info_buffer_has_identifier, root_as_epics_pvconnection_info,
+    },
     logdata_f144::{f_144_log_data_buffer_has_identifier, root_as_f_144_log_data},
     run_start_pl72::{root_as_run_start, run_start_buffer_has_identifier},
     run_stop_6s4t::{root_as_run_stop, run_stop_buffer_has_identifier},
@@ -65,6 +68,8 @@ pub(crate) fn process_payload_on_sample_env_topic(
         push_f144_sample_environment_log(nexus_engine, message_kafka_timestamp_ms, payload);
     } else if se_00_sample_environment_data_buffer_has_identifier(payload) {
         push_se00_sample_environment_log(nexus_engine, message_kafka_timestamp_ms, payload);
+    } else if epics_pvconnection_info_buffer_has_identifier(payload) {
+        push_ep01_connection_status(nexus_engine, message_kafka_timestamp_ms, payload);
     } else {
         warn!("Incorrect message identifier on sample environment topic");
     }
@@ -254,11 +259,11 @@ fn push_f144_sample_environment_log(
     let wrapped_result =
         spanned_root_as(root_as_f_144_log_data, payload).map(SampleEnvironmentLog::LogData);
     match wrapped_result {
-        Ok(wrapped_se) => {
-            if let Err(e) = nexus_engine.push_sample_environment_log(wrapped_se) {
-                warn!("Sample environment error: {e}.");
-            }
-        }
+        Ok(wrapped_se) => match nexus_engine.push_sample_environment_log(wrapped_se) {
+            Ok(true) => {}
+            Ok(false) => nexus_engine.hold_sample_environment(payload),
+            Err(e) => warn!("Sample environment error: {e}."),
+        },
         Err(e) => report_parse_message_failure(e),
     }
 }
@@ -278,11 +283,33 @@ fn push_se00_sample_environment_log(
     let wrapped_result = spanned_root_as(root_as_se_00_sample_environment_data, payload)
         .map(SampleEnvironmentLog::SampleEnvironmentData);
     match wrapped_result {
-        Ok(wrapped_se) => {
-            if let Err(e) = nexus_engine.push_sample_environment_log(wrapped_se) {
-                warn!("Sample environment error: {e}.");
-            }
-        }
+        Ok(wrapped_se) => match nexus_engine.push_sample_environment_log(wrapped_se) {
+            Ok(true) => {}
+            Ok(false) => nexus_engine.hold_sample_environment(payload),
+            Err(e) => warn!("Sample environment error: {e}."),
+        },
+        Err(e) => report_parse_message_failure(e),
+    }
+}
+
+/// Decode, validate and process a flatbuffer `EpicsPVConnectionInfo` message
+/// # Parameters
+/// - nexus_engine: the engine to push the message to.
+/// - kafka_message_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header. Only used for tracing.
+/// - payload: the byte-stream of the message.
+#[tracing::instrument(skip_all, fields(kafka_message_timestamp_ms=kafka_message_timestamp_ms, has_run))]
+fn push_ep01_connection_status(
+    nexus_engine: &mut NexusEngine<EngineDependencies>,
+    kafka_message_timestamp_ms: i64,
+    payload: &[u8],
+) {
+    increment_message_received_counter(MessageKind::SampleEnvironmentData);
+    match spanned_root_as(root_as_epics_pvconnection_info, payload) {
+        Ok(data) => match nexus_engine.push_connection_status(data) {
+            Ok(true) => {}
+            Ok(false) => nexus_engine.hold_sample_environment(payload),
+            Err(e) => warn!("Connection status error: {e}."),
+        },
         Err(e) => report_parse_message_failure(e),
     }
 }
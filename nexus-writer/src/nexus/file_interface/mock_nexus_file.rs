@@ -32,6 +32,10 @@ impl NexusFileInterface for NexusNoFile {
         Ok(())
     }
 
+    fn size(&self) -> u64 {
+        0
+    }
+
     fn close(self) -> NexusHDF5Result<()> {
         Ok(())
     }
@@ -46,6 +46,9 @@ pub(crate) trait NexusFileInterface: Sized + HandlesAllNexusMessages {
     /// [File]: hdf5::File
     fn flush(&self) -> NexusHDF5Result<()>;
 
+    /// Returns the size of the file, in bytes.
+    fn size(&self) -> u64;
+
     /// Takes ownership and close the hdf5 file.
     fn close(self) -> NexusHDF5Result<()>;
 }
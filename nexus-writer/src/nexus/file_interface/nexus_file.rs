@@ -41,6 +41,10 @@ impl NexusFileInterface for NexusFile {
         Ok(self.file.flush()?)
     }
 
+    fn size(&self) -> u64 {
+        self.file.size()
+    }
+
     fn extract_run_parameters(&self) -> NexusHDF5Result<RunParameters> {
         self.root.extract_run_parameters()
     }
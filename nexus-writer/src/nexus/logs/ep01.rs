@@ -0,0 +1,38 @@
+//! Implementation allows flatbuffer [EpicsPVConnectionInfo] messages to robustly write data to a [Dataset].
+use super::{ConnectionStatusMessage, adjust_nanoseconds_by_origin_to_sec, remove_prefixes};
+use crate::{
+    error::FlatBufferMissingError,
+    hdf5_handlers::{ConvertResult, DatasetExt, NexusHDF5Result},
+    run_engine::NexusDateTime,
+};
+use hdf5::{Dataset, types::VarLenUnicode};
+use isis_streaming_data_types::flatbuffers_generated::epics_connection_ep01::EpicsPVConnectionInfo;
+
+impl<'a> ConnectionStatusMessage<'a> for EpicsPVConnectionInfo<'a> {
+    fn get_name(&self) -> String {
+        remove_prefixes(self.source_name())
+    }
+
+    fn append_timestamp_to(
+        &self,
+        dataset: &Dataset,
+        origin_time: &NexusDateTime,
+    ) -> NexusHDF5Result<()> {
+        dataset
+            .append_value(adjust_nanoseconds_by_origin_to_sec(
+                self.timestamp(),
+                origin_time,
+            ))
+            .err_dataset(dataset)
+    }
+
+    fn append_status_to(&self, dataset: &Dataset) -> NexusHDF5Result<()> {
+        let status = self
+            .status()
+            .variant_name()
+            .ok_or(FlatBufferMissingError::ConnectionStatus)
+            .err_dataset(dataset)?;
+        let status = status.parse::<VarLenUnicode>().err_dataset(dataset)?;
+        dataset.append_value(status).err_dataset(dataset)
+    }
+}
@@ -1,6 +1,7 @@
 //! Defines traits which, when implemented for appropriate flatbuffer messages,
 //! allow the messages to write their data into a given [Dataset].
 mod alarm;
+mod ep01;
 mod f114;
 mod se00;
 
@@ -88,6 +89,39 @@ pub(crate) trait AlarmMessage<'a>: Sized {
     fn append_message_to(&self, dataset: &Dataset) -> NexusHDF5Result<()>;
 }
 
+/// Is implemented on [EpicsPVConnectionInfo].
+///
+/// [EpicsPVConnectionInfo]: isis_streaming_data_types::flatbuffers_generated::epics_connection_ep01::EpicsPVConnectionInfo
+pub(crate) trait ConnectionStatusMessage<'a>: Sized {
+    /// Returns name of the log whose connection status this message contains.
+    fn get_name(&self) -> String;
+
+    /// Append given dataset with the connection status message time value.
+    /// # Parameters
+    /// - dataset: [Dataset] to write data to.
+    /// - origin_time: the time by which the timestamps should be written relative to. Usually the start time of the run.
+    /// # Error
+    /// Emits an error if either of the following requirements on the given [Dataset] are violated:
+    /// - has data type equal to [f64].
+    /// - is one-dimentional.
+    fn append_timestamp_to(
+        &self,
+        dataset: &Dataset,
+        origin_time: &NexusDateTime,
+    ) -> NexusHDF5Result<()>;
+
+    /// Appends given dataset with the connection status.
+    /// # Parameters
+    /// - dataset: [Dataset] to write data to.
+    /// # Error
+    /// Emits an error if either of the following requirements on the given [Dataset] are violated:
+    /// - has data type equal to [VarLenUnicode].
+    /// - is one-dimentional.
+    ///
+    /// [VarLenUnicode]: hdf5::types::VarLenUnicode
+    fn append_status_to(&self, dataset: &Dataset) -> NexusHDF5Result<()>;
+}
+
 /// Coverts ns since epoch to ns since `origin_time`.
 /// # Parameters
 /// - nanoseconds: time since epoch to adjust.
@@ -13,7 +13,9 @@ use crate::{
     },
 };
 use digital_muon_common::{Channel, Time};
-use digital_muon_streaming_types::aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage;
+use digital_muon_streaming_types::{
+    aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage, flatbuffers::Vector,
+};
 use hdf5::{Attribute, Dataset, Group};
 
 /// Field names for [EventData].
@@ -186,7 +188,40 @@ impl EventData {
     }
 }
 
+/// Returns true if the event with `polarity` is a muon event, that is if its pulse is not opposite the configured polarity.
+fn is_muon_event(polarity: i8) -> bool {
+    polarity >= 0
+}
+
+/// Returns the number of muon events of `message`, see [is_muon_event].
+pub(crate) fn num_muon_events(message: &FrameAssembledEventListMessage) -> usize {
+    match message.polarity() {
+        Some(polarity) => polarity.iter().filter(|&p| is_muon_event(p)).count(),
+        None => message.time().map_or(0, |time| time.len()),
+    }
+}
+
+/// Collects those of `values`, one per event of a frame event list, whose events are muon events, see [is_muon_event].
+/// # Parameters
+/// - values: the values of a field indexed by event.
+/// - polarity: the polarity vector of the frame event list, if present, without which every event is a muon event.
+fn collect_muon_events<T>(
+    values: impl Iterator<Item = T>,
+    polarity: Option<Vector<'_, i8>>,
+) -> Vec<T> {
+    match polarity {
+        Some(polarity) => values
+            .zip(polarity.iter())
+            .filter(|&(_, polarity)| is_muon_event(polarity))
+            .map(|(value, _)| value)
+            .collect(),
+        None => values.collect(),
+    }
+}
+
 /// Appends data from the provided [FrameAssembledEventListMessage] message.
+///
+/// Events whose pulses are opposite the configured polarity are not muon events, so are not appended, see [is_muon_event].
 impl NexusMessageHandler<PushFrameEventList<'_>> for EventData {
     fn handle_message(
         &mut self,
@@ -215,23 +250,26 @@ impl NexusMessageHandler<PushFrameEventList<'_>> for EventData {
 
         // Fields Indexed By Event
 
-        let intensities = &message
-            .voltage()
-            .ok_or(FlatBufferMissingError::Intensities)?
-            .iter()
-            .collect::<Vec<_>>();
-
-        let times = &message
-            .time()
-            .ok_or(FlatBufferMissingError::Times)?
-            .iter()
-            .collect::<Vec<_>>();
-
-        let channels = &message
-            .channel()
-            .ok_or(FlatBufferMissingError::Channels)?
-            .iter()
-            .collect::<Vec<_>>();
+        let intensities = &collect_muon_events(
+            message
+                .voltage()
+                .ok_or(FlatBufferMissingError::Intensities)?
+                .iter(),
+            message.polarity(),
+        );
+
+        let times = &collect_muon_events(
+            message.time().ok_or(FlatBufferMissingError::Times)?.iter(),
+            message.polarity(),
+        );
+
+        let channels = &collect_muon_events(
+            message
+                .channel()
+                .ok_or(FlatBufferMissingError::Channels)?
+                .iter(),
+            message.polarity(),
+        );
 
         let num_new_events = channels.len();
         let total_events = self.num_events + num_new_events;
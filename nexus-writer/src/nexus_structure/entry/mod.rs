@@ -13,14 +13,15 @@ use crate::{
     run_engine::{
         ChunkSizeSettings, RunParameters, RunStopParameters,
         run_messages::{
-            InitialiseNewNexusRun, InitialiseNewNexusStructure, PushAlarm, PushFrameEventList,
-            PushInternallyGeneratedLogWarning, PushRunLog, PushRunStart, PushSampleEnvironmentLog,
-            SetEndTime, UpdatePeriodList,
+            InitialiseNewNexusRun, InitialiseNewNexusStructure, PushAlarm, PushConnectionStatus,
+            PushFrameEventList, PushInternallyGeneratedLogWarning, PushRunLog, PushRunStart,
+            PushSampleEnvironmentLog, SetEndTime, UpdatePeriodList,
         },
     },
 };
 use chrono::Utc;
 use event_data::EventData;
+pub(crate) use event_data::num_muon_events;
 use hdf5::{Dataset, Group};
 use instrument::Instrument;
 use period::Period;
@@ -318,6 +319,13 @@ impl NexusMessageHandler<PushAlarm<'_>> for Entry {
     }
 }
 
+// Direct `PushConnectionStatus` to the group(s) that need it
+impl NexusMessageHandler<PushConnectionStatus<'_>> for Entry {
+    fn handle_message(&mut self, message: &PushConnectionStatus<'_>) -> NexusHDF5Result<()> {
+        self.selogs.handle_message(message)
+    }
+}
+
 // Direct `PushInternallyGeneratedLogWarning` to the group(s) that need it
 impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for Entry {
     fn handle_message(
@@ -1,9 +1,12 @@
 //! Defines group structure which contains the sample environment logs of the run.
 use crate::{
     hdf5_handlers::NexusHDF5Result,
-    nexus::{AlarmMessage, LogMessage, NexusClass, NexusGroup, NexusMessageHandler},
+    nexus::{
+        AlarmMessage, ConnectionStatusMessage, LogMessage, NexusClass, NexusGroup,
+        NexusMessageHandler,
+    },
     nexus_structure::{NexusSchematic, logs::ValueLog},
-    run_engine::run_messages::{PushAlarm, PushSampleEnvironmentLog},
+    run_engine::run_messages::{PushAlarm, PushConnectionStatus, PushSampleEnvironmentLog},
 };
 use hdf5::Group;
 use std::collections::{HashMap, hash_map::Entry};
@@ -75,3 +78,21 @@ impl NexusMessageHandler<PushAlarm<'_>> for SELog {
         }
     }
 }
+
+/// If the sample environment log already exists then add the connection status to it,
+/// otherwise create a new log and append the connection status to it.
+impl NexusMessageHandler<PushConnectionStatus<'_>> for SELog {
+    #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
+    fn handle_message(&mut self, message: &PushConnectionStatus<'_>) -> NexusHDF5Result<()> {
+        match self.selogs.entry(message.get_name()) {
+            Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().handle_message(message),
+            Entry::Vacant(vacant_entry) => vacant_entry
+                .insert(ValueLog::build_new_group(
+                    &self.group,
+                    &message.get_name(),
+                    &(),
+                )?)
+                .handle_message(message),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Implements the [ConnectionLog] struct which represents some of the fields in a NeXus group of class `NXLog`.
+
+use crate::{
+    hdf5_handlers::{GroupExt, NexusHDF5Result},
+    nexus::{
+        ConnectionStatusMessage, DatasetUnitExt, NexusClass, NexusMessageHandler, NexusSchematic,
+        NexusUnits,
+    },
+    run_engine::{ConnectionChunkSize, run_messages::PushConnectionStatus},
+};
+use hdf5::{Dataset, Group, types::VarLenUnicode};
+
+/// Field names for [ConnectionLog].
+mod labels {
+    pub(super) const CONNECTION_STATUS: &str = "connection_status";
+    pub(super) const CONNECTION_STATUS_TIME: &str = "connection_status_time";
+}
+
+pub(crate) struct ConnectionLog {
+    connection_status: Dataset,
+    connection_status_time: Dataset,
+}
+
+impl NexusSchematic for ConnectionLog {
+    /// The nexus class of this group.
+    const CLASS: NexusClass = NexusClass::Log;
+
+    /// This group structure only needs the appropriate chunk size.
+    type Settings = ConnectionChunkSize;
+
+    fn build_group_structure(
+        group: &Group,
+        &connection_chunk_size: &Self::Settings,
+    ) -> NexusHDF5Result<Self> {
+        Ok(Self {
+            connection_status: group.create_resizable_empty_dataset::<VarLenUnicode>(
+                labels::CONNECTION_STATUS,
+                connection_chunk_size,
+            )?,
+            connection_status_time: group
+                .create_resizable_empty_dataset::<f64>(
+                    labels::CONNECTION_STATUS_TIME,
+                    connection_chunk_size,
+                )?
+                .with_units(NexusUnits::Seconds)?,
+        })
+    }
+
+    fn populate_group_structure(group: &Group) -> NexusHDF5Result<Self> {
+        Ok(Self {
+            connection_status: group.get_dataset(labels::CONNECTION_STATUS)?,
+            connection_status_time: group.get_dataset(labels::CONNECTION_STATUS_TIME)?,
+        })
+    }
+}
+
+impl NexusMessageHandler<PushConnectionStatus<'_>> for ConnectionLog {
+    /// Appends connection status data to the appropriate datasets.
+    /// # Error Modes
+    /// - Propagates errors from [ConnectionStatusMessage::append_timestamp_to()].
+    /// - Propagates errors from [ConnectionStatusMessage::append_status_to()].
+    #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
+    fn handle_message(&mut self, message: &PushConnectionStatus<'_>) -> NexusHDF5Result<()> {
+        message.append_timestamp_to(&self.connection_status_time, message.origin)?;
+        message.append_status_to(&self.connection_status)?;
+        Ok(())
+    }
+}
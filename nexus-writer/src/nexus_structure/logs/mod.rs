@@ -1,9 +1,11 @@
 //! Exposes the structs which implement NeXus classes related to storing logs.
 
 mod alarm_log;
+mod connection_log;
 mod log;
 mod value_log;
 
 pub(crate) use alarm_log::AlarmLog;
+pub(crate) use connection_log::ConnectionLog;
 pub(crate) use log::{Log, LogSettings};
 pub(crate) use value_log::ValueLog;
@@ -1,10 +1,10 @@
 //! Implements the [ValueLog] struct which represents a NeXus group of class `IXseblock`.
 
-use super::{AlarmLog, Log, LogSettings};
+use super::{AlarmLog, ConnectionLog, Log, LogSettings};
 use crate::{
     hdf5_handlers::NexusHDF5Result,
     nexus::{LogMessage, NexusClass, NexusGroup, NexusMessageHandler, NexusSchematic},
-    run_engine::run_messages::{PushAlarm, PushSampleEnvironmentLog},
+    run_engine::run_messages::{PushAlarm, PushConnectionStatus, PushSampleEnvironmentLog},
 };
 use hdf5::Group;
 
@@ -16,6 +16,7 @@ mod labels {
 pub(crate) struct ValueLog {
     group: Group,
     alarm: Option<AlarmLog>,
+    connection: Option<ConnectionLog>,
     log: Option<NexusGroup<Log>>,
 }
 
@@ -30,6 +31,7 @@ impl NexusSchematic for ValueLog {
         Ok(Self {
             group: group.clone(),
             alarm: None,
+            connection: None,
             log: None,
         })
     }
@@ -38,6 +40,7 @@ impl NexusSchematic for ValueLog {
         Ok(Self {
             group: group.clone(),
             alarm: AlarmLog::populate_group_structure(group).ok(),
+            connection: ConnectionLog::populate_group_structure(group).ok(),
             log: Log::open_group(group, labels::VALUE_LOG).ok(),
         })
     }
@@ -88,3 +91,25 @@ impl NexusMessageHandler<PushAlarm<'_>> for ValueLog {
             .handle_message(message)
     }
 }
+
+impl NexusMessageHandler<PushConnectionStatus<'_>> for ValueLog {
+    /// If the connection status structure exists, appends the connection status to it,
+    /// otherwise create it and append.
+    /// # Error Modes
+    /// - Propagates errors from [ConnectionLog::build_group_structure()].
+    /// - Propagates errors from [ConnectionLog::handle_message()].
+    #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
+    fn handle_message(&mut self, message: &PushConnectionStatus<'_>) -> NexusHDF5Result<()> {
+        if self.connection.is_none() {
+            self.connection = Some(ConnectionLog::build_group_structure(
+                &self.group,
+                &message.settings.connection,
+            )?);
+        }
+
+        self.connection
+            .as_mut()
+            .expect("connection exists, this shouldn't happen")
+            .handle_message(message)
+    }
+}
@@ -9,12 +9,13 @@ mod entry;
 mod logs;
 
 use crate::{
-    hdf5_handlers::{HasAttributesExt, NexusHDF5Result},
+    hdf5_handlers::{ConvertResult, HasAttributesExt, NexusHDF5Result},
     nexus::{NexusClass, NexusGroup, NexusMessageHandler, NexusSchematic},
-    run_engine::{ChunkSizeSettings, RunParameters},
+    run_engine::{ChunkSizeSettings, RunParameters, run_messages::SetIncompleteRun},
 };
 use chrono::{SecondsFormat, Utc};
 use entry::Entry;
+pub(crate) use entry::num_muon_events;
 use hdf5::{Attribute, Group};
 use std::{path::PathBuf, str::FromStr};
 
@@ -24,6 +25,7 @@ mod labels {
     pub(super) const NEXUS_VERSION: &str = "NeXuS_version";
     pub(super) const FILE_NAME: &str = "file_name";
     pub(super) const FILE_TIME: &str = "file_time";
+    pub(super) const INCOMPLETE_RUN: &str = "incomplete_run";
     pub(super) const RAW_DATA_1: &str = "raw_data_1";
 }
 
@@ -37,6 +39,8 @@ pub(crate) struct Root {
     _file_name: Attribute,
     /// Is set to the time this object is created
     _file_time: Attribute,
+    /// Is set to true if the run was interrupted and recovered from a partially written file
+    incomplete_run: Attribute,
     /// All incoming data goes here
     raw_data_1: NexusGroup<Entry>,
 }
@@ -79,6 +83,11 @@ impl NexusSchematic for Root {
                     .to_rfc3339_opts(SecondsFormat::Secs, true)
                     .as_str(),
             )?,
+            incomplete_run: {
+                let attr = group.add_attribute::<bool>(labels::INCOMPLETE_RUN)?;
+                attr.write_scalar(&false).err_attribute(&attr)?;
+                attr
+            },
             raw_data_1: Entry::build_new_group(group, labels::RAW_DATA_1, settings)?,
         })
     }
@@ -89,6 +98,10 @@ impl NexusSchematic for Root {
             _nexus_version: group.get_attribute(labels::NEXUS_VERSION)?,
             _file_name: group.get_attribute(labels::FILE_NAME)?,
             _file_time: group.get_attribute(labels::FILE_TIME)?,
+            // Files written before this attribute was introduced will not have it.
+            incomplete_run: group
+                .get_attribute(labels::INCOMPLETE_RUN)
+                .or_else(|_| group.add_attribute::<bool>(labels::INCOMPLETE_RUN))?,
             raw_data_1: Entry::open_group(group, labels::RAW_DATA_1)?,
         })
     }
@@ -106,3 +119,12 @@ where
         self.raw_data_1.handle_message(message)
     }
 }
+
+/// Flag the file as belonging to an incomplete run.
+impl NexusMessageHandler<SetIncompleteRun> for Root {
+    fn handle_message(&mut self, _: &SetIncompleteRun) -> NexusHDF5Result<()> {
+        self.incomplete_run
+            .write_scalar(&true)
+            .err_attribute(&self.incomplete_run)
+    }
+}
@@ -5,19 +5,29 @@ use crate::{
     error::{ErrorCodeLocation, FlatBufferMissingError, NexusWriterError, NexusWriterResult},
     kafka_topic_interface::KafkaTopicInterface,
     nexus::NexusFileInterface,
-    run_engine::{NexusConfiguration, NexusDateTime, NexusSettings, Run},
+    run_engine::{NexusConfiguration, NexusDateTime, NexusSettings, Run, RunOffsets},
 };
 use chrono::Duration;
 use digital_muon_common::spanned::SpannedAggregator;
 use digital_muon_streaming_types::aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage;
 use glob::glob;
 use isis_streaming_data_types::flatbuffers_generated::{
-    alarm_al00::Alarm, logdata_f144::f144_LogData, run_start_pl72::RunStart, run_stop_6s4t::RunStop,
+    alarm_al00::Alarm,
+    data_se00::{
+        root_as_se_00_sample_environment_data, se_00_sample_environment_data_buffer_has_identifier,
+    },
+    epics_connection_ep01::{EpicsPVConnectionInfo, root_as_epics_pvconnection_info},
+    logdata_f144::{f_144_log_data_buffer_has_identifier, f144_LogData, root_as_f_144_log_data},
+    run_start_pl72::RunStart,
+    run_stop_6s4t::RunStop,
 };
 #[cfg(test)]
 use std::collections::vec_deque;
 use std::{collections::VecDeque, ffi::OsStr};
-use tracing::{debug, info_span, warn};
+use tracing::{debug, info, info_span, warn};
+
+/// The most sample environment messages held whilst no run can accept them, see [NexusEngine::hold_sample_environment].
+const MAX_HELD_SAMPLE_ENVIRONMENT_MESSAGES: usize = 1024;
 
 /// Enables searching for a valid run based on a timestamp.
 trait FindValidRun<I: NexusFileInterface> {
@@ -113,6 +123,11 @@ pub(crate) struct NexusEngine<D: NexusEngineDependencies> {
     nexus_configuration: NexusConfiguration,
     /// Interface to control Kafka topic subscriptions.
     kafka_topic_interface: D::TopicInterface,
+    /// Payloads of the sample environment messages which no run could accept when they were consumed,
+    /// most likely as they arrived before the run start. These are pushed to the next run to start.
+    held_sample_environment: VecDeque<Vec<u8>>,
+    /// Kafka offsets of the latest message committed from each partition.
+    consumed_offsets: RunOffsets,
 }
 
 impl<D: NexusEngineDependencies> NexusEngine<D> {
@@ -132,6 +147,8 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
             run_cache: Default::default(),
             nexus_configuration,
             kafka_topic_interface,
+            held_sample_environment: Default::default(),
+            consumed_offsets: Default::default(),
         }
     }
 
@@ -171,6 +188,52 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
         Ok(())
     }
 
+    /// Returns the Kafka offsets up to which messages have been written to the runs
+    /// in the cache, taking the earliest offset of each partition over all runs.
+    /// Called after [Self::resume_partial_runs], consuming can be resumed from the
+    /// message following each of these offsets without losing data from the partial runs.
+    pub(crate) fn get_recovered_offsets(&self) -> RunOffsets {
+        self.run_cache
+            .iter()
+            .fold(RunOffsets::default(), |mut offsets, run| {
+                offsets.merge_earliest(run.offsets());
+                offsets
+            })
+    }
+
+    /// Returns the Kafka offsets up to which every committed message has been written to disk,
+    /// that is the latest offset committed from each partition, lowered to before the earliest one
+    /// which is yet to be flushed to the file of any run in the cache.
+    /// Consumption can be committed to Kafka up to these offsets without losing messages if the writer is killed.
+    pub(crate) fn get_flushed_offsets(&self) -> RunOffsets {
+        self.run_cache
+            .iter()
+            .fold(self.consumed_offsets.clone(), |mut offsets, run| {
+                offsets.limit_before(run.unflushed_offsets());
+                offsets
+            })
+    }
+
+    /// Records the Kafka offset of a committed message in the runs in the cache it was sent to,
+    /// flushing any whose flush interval has passed.
+    /// # Parameters
+    /// - topic: the topic the message was consumed from.
+    /// - partition: the partition the message was consumed from.
+    /// - offset: the offset of the message.
+    #[tracing::instrument(skip_all, level = "debug")]
+    pub(crate) fn commit_offset(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> NexusWriterResult<()> {
+        self.consumed_offsets.record(topic, partition, offset);
+        for run in self.run_cache.iter_mut() {
+            run.commit_offset(&self.nexus_settings, topic, partition, offset)?;
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     fn cache_iter(&self) -> vec_deque::Iter<'_, Run<D::FileInterface>> {
         self.run_cache.iter()
@@ -185,7 +248,9 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
         self.run_cache.len()
     }
 
-    /// This there is a run in the run cache, and the final one is still running,
+    /// If the message restarts a run in the run cache (matched by run name and start time),
+    /// this method returns it, so that it continues receiving data.
+    /// Otherwise, if there is a run in the run cache, and the final one is still running,
     /// this method aborts it, and creates a new run
     /// # Parameters
     /// - run_start: the flatbuffers `RunStart` message.
@@ -194,6 +259,21 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
         &mut self,
         run_start: RunStart<'_>,
     ) -> NexusWriterResult<&mut Run<D::FileInterface>> {
+        //  If the run start restarts a run in progress, most likely one
+        //  recovered from a partial file, then continue appending to it.
+        if let Some(index) = self
+            .run_cache
+            .iter()
+            .position(|run| run.is_restarted_by(&run_start))
+        {
+            info!("Resuming run {:?}", run_start.run_name());
+            self.kafka_topic_interface
+                .ensure_subscription_mode_is(TopicMode::Full)?;
+            let run = self.run_cache.get_mut(index).expect("Run exists");
+            run.mark_received();
+            return Ok(run);
+        }
+
         //  If a run is already in progress, and is missing a run-stop
         //  then call an abort run on the current run.
         if self.run_cache.back().is_some_and(|run| !run.has_run_stop()) {
@@ -202,6 +282,7 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
 
         let run = Run::new_run(&self.nexus_settings, run_start, &self.nexus_configuration)?;
         self.run_cache.push_back(run);
+        self.push_held_sample_environment();
 
         //  Ensure Topic Subscription Mode is set to Full)
         self.kafka_topic_interface
@@ -231,7 +312,7 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
             .try_into()?;
 
         if let Some(run) = self.run_cache.find_run_containing(&timestamp) {
-            run.push_frame_event_list(&self.nexus_settings, message)?;
+            run.push_frame_event_list(&self.nexus_settings, message, &timestamp)?;
         }
         Ok(())
     }
@@ -251,25 +332,27 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
     }
 
     /// This pushes a Sample Environment Log message to the first valid run it finds in the run cache.
-    /// If no run is found then this method does nothing.
-    /// Should a warning be emitted?
+    /// If no run is found then this method does nothing, and the message should be held, see [Self::hold_sample_environment].
     /// # Parameters
     /// - data: the SampleEnvironmentLog message to push.
+    /// # Return
+    /// Whether a run was found.
     #[tracing::instrument(skip_all, level = "debug")]
     pub(crate) fn push_sample_environment_log(
         &mut self,
         data: SampleEnvironmentLog,
-    ) -> NexusWriterResult<()> {
+    ) -> NexusWriterResult<bool> {
         let timestamp = NexusDateTime::from_timestamp_nanos(match data {
             SampleEnvironmentLog::LogData(f144_log_data) => f144_log_data.timestamp(),
             SampleEnvironmentLog::SampleEnvironmentData(se00_sample_environment_data) => {
                 se00_sample_environment_data.packet_timestamp()
             }
         });
-        if let Some(run) = self.run_cache.find_run_not_ending_before(&timestamp) {
-            run.push_sample_environment_log(&self.nexus_settings, &data)?;
-        }
-        Ok(())
+        let Some(run) = self.run_cache.find_run_not_ending_before(&timestamp) else {
+            return Ok(false);
+        };
+        run.push_sample_environment_log(&self.nexus_settings, &data)?;
+        Ok(true)
     }
 
     /// This pushes an Alarm message to the first valid run it finds in the run cache.
@@ -286,6 +369,71 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
         Ok(())
     }
 
+    /// This pushes a Connection Status message to the first valid run it finds in the run cache.
+    /// If no run is found then this method does nothing, and the message should be held, see [Self::hold_sample_environment].
+    /// # Parameters
+    /// - data: the EpicsPVConnectionInfo message to push.
+    /// # Return
+    /// Whether a run was found.
+    #[tracing::instrument(skip_all, level = "debug")]
+    pub(crate) fn push_connection_status(
+        &mut self,
+        data: EpicsPVConnectionInfo<'_>,
+    ) -> NexusWriterResult<bool> {
+        let timestamp = NexusDateTime::from_timestamp_nanos(data.timestamp());
+        let Some(run) = self.run_cache.find_run_not_ending_before(&timestamp) else {
+            return Ok(false);
+        };
+        run.push_connection_status(&self.nexus_settings, &data)?;
+        Ok(true)
+    }
+
+    /// Holds the payload of a sample environment message which no run could accept,
+    /// so that it is pushed to the next run to start.
+    /// Only the most recent [MAX_HELD_SAMPLE_ENVIRONMENT_MESSAGES] messages are held.
+    /// # Parameters
+    /// - payload: the byte-stream of a `f144_LogData`, `se00_SampleEnvironmentData` or `EpicsPVConnectionInfo` message.
+    pub(crate) fn hold_sample_environment(&mut self, payload: &[u8]) {
+        if self.held_sample_environment.len() == MAX_HELD_SAMPLE_ENVIRONMENT_MESSAGES {
+            debug!("Too many sample environment messages held, discarding the oldest");
+            self.held_sample_environment.pop_front();
+        }
+        self.held_sample_environment.push_back(payload.to_vec());
+    }
+
+    /// Pushes the held sample environment messages to the run at the back of the cache, which has just started.
+    #[tracing::instrument(skip_all, level = "debug", fields(num_held = self.held_sample_environment.len()))]
+    fn push_held_sample_environment(&mut self) {
+        let Some(run) = self.run_cache.back_mut() else {
+            return;
+        };
+        for payload in self.held_sample_environment.drain(..) {
+            let result = if f_144_log_data_buffer_has_identifier(&payload) {
+                root_as_f_144_log_data(&payload).map(|data| {
+                    run.push_sample_environment_log(
+                        &self.nexus_settings,
+                        &SampleEnvironmentLog::LogData(data),
+                    )
+                })
+            } else if se_00_sample_environment_data_buffer_has_identifier(&payload) {
+                root_as_se_00_sample_environment_data(&payload).map(|data| {
+                    run.push_sample_environment_log(
+                        &self.nexus_settings,
+                        &SampleEnvironmentLog::SampleEnvironmentData(data),
+                    )
+                })
+            } else {
+                root_as_epics_pvconnection_info(&payload)
+                    .map(|data| run.push_connection_status(&self.nexus_settings, &data))
+            };
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Held sample environment message failed: {e}"),
+                Err(e) => warn!("Failed to parse held sample environment message: {e}"),
+            }
+        }
+    }
+
     /// This pushes a RunStop message to the final run in the cache.
     /// # Parameters
     /// - data: the RunStop message to push.
@@ -330,17 +478,20 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
         // directing completed runs to self.run_move_cache
         // and incomplete ones back to self.run_cache
         let temp: Vec<_> = self.run_cache.drain(..).collect();
-        for run in temp.into_iter() {
+        for mut run in temp.into_iter() {
             if run.has_completed(delay) {
                 if let Err(e) = run.end_span() {
                     warn!("Run span drop failed {e}")
                 }
+                run.remove_offsets_file(&self.nexus_settings)?;
                 run.move_to_completed(
                     self.nexus_settings.get_local_path(),
                     self.nexus_settings.get_local_completed_path(),
                 )?;
                 run.close()?;
             } else {
+                run.flush_if_due(&self.nexus_settings)?;
+                run.publish_progress();
                 self.run_cache.push_back(run);
             }
         }
@@ -354,22 +505,34 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
         Ok(())
     }
 
-    pub(crate) fn close_all(self) -> NexusWriterResult<()> {
-        for run in self.run_cache.into_iter() {
+    /// Flushes and closes all runs in the cache, leaving them to be resumed on restart.
+    /// # Return
+    /// The Kafka offsets of the latest message committed from each partition, all of which have been written to disk.
+    pub(crate) fn close_all(self) -> NexusWriterResult<RunOffsets> {
+        for mut run in self.run_cache.into_iter() {
+            run.flush_to_disk(&self.nexus_settings)?;
             run.close()?;
         }
-        Ok(())
+        Ok(self.consumed_offsets)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{NexusEngine, NexusEngineDependencies};
+    use super::{
+        MAX_HELD_SAMPLE_ENVIRONMENT_MESSAGES, NexusEngine, NexusEngineDependencies,
+        SampleEnvironmentLog,
+    };
     use crate::{
-        NexusSettings, kafka_topic_interface::NoKafka, nexus::NexusNoFile,
-        run_engine::NexusConfiguration,
+        NexusSettings,
+        kafka_topic_interface::NoKafka,
+        nexus::{NexusFile, NexusNoFile},
+        run_engine::{NexusConfiguration, RunOffsets, RunParameters},
     };
     use chrono::{DateTime, Duration, Utc};
+    use digital_muon_common::metrics::names::{
+        RUN_EVENT_LAG_SECONDS, RUN_EVENTS_WRITTEN, RUN_FILE_SIZE_BYTES, RUN_FLUSHES,
+    };
     use digital_muon_streaming_types::{
         aev2_frame_assembled_event_v2_generated::{
             FrameAssembledEventListMessage, FrameAssembledEventListMessageArgs,
@@ -380,9 +543,27 @@ mod test {
         frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
     };
     use isis_streaming_data_types::flatbuffers_generated::{
+        data_se00::{
+            DoubleArray, DoubleArrayArgs, ValueUnion, finish_se_00_sample_environment_data_buffer,
+            root_as_se_00_sample_environment_data, se00_SampleEnvironmentData,
+            se00_SampleEnvironmentDataArgs,
+        },
+        epics_connection_ep01::{
+            ConnectionInfo, EpicsPVConnectionInfo, EpicsPVConnectionInfoArgs,
+            finish_epics_pvconnection_info_buffer, root_as_epics_pvconnection_info,
+        },
         run_start_pl72::{RunStart, RunStartArgs, finish_run_start_buffer, root_as_run_start},
         run_stop_6s4t::{RunStop, RunStopArgs, finish_run_stop_buffer, root_as_run_stop},
     };
+    use metrics_util::{
+        CompositeKey,
+        debugging::{DebugValue, DebuggingRecorder},
+    };
+    use std::{
+        fs::{create_dir_all, remove_dir_all},
+        path::Path,
+        process::{Command, Stdio},
+    };
 
     fn create_start<'a, 'b: 'a>(
         fbb: &'b mut FlatBufferBuilder,
@@ -441,6 +622,65 @@ mod test {
         root_as_frame_assembled_event_list_message(fbb.finished_data())
     }
 
+    fn create_frame_assembled_message_with_events<'a, 'b: 'a>(
+        fbb: &'b mut FlatBufferBuilder,
+        timestamp: &GpsTime,
+        channels: &[u32],
+        times: &[u32],
+        voltages: &[u16],
+    ) -> Result<FrameAssembledEventListMessage<'a>, InvalidFlatbuffer> {
+        let metadata = FrameMetadataV2::create(fbb, &create_metadata(timestamp));
+        let args = FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            channel: Some(fbb.create_vector(channels)),
+            time: Some(fbb.create_vector(times)),
+            voltage: Some(fbb.create_vector(voltages)),
+            complete: true,
+            ..Default::default()
+        };
+        let message = FrameAssembledEventListMessage::create(fbb, &args);
+        finish_frame_assembled_event_list_message_buffer(fbb, message);
+        root_as_frame_assembled_event_list_message(fbb.finished_data())
+    }
+
+    fn create_se00<'a, 'b: 'a>(
+        fbb: &'b mut FlatBufferBuilder,
+        name: &str,
+        timestamps_ns: &[i64],
+        values: &[f64],
+    ) -> Result<se00_SampleEnvironmentData<'a>, InvalidFlatbuffer> {
+        let value = Some(fbb.create_vector(values));
+        let values = DoubleArray::create(fbb, &DoubleArrayArgs { value });
+        let args = se00_SampleEnvironmentDataArgs {
+            name: Some(fbb.create_string(name)),
+            packet_timestamp: *timestamps_ns.first().unwrap(),
+            values_type: ValueUnion::DoubleArray,
+            values: Some(values.as_union_value()),
+            timestamps: Some(fbb.create_vector(timestamps_ns)),
+            ..Default::default()
+        };
+        let message = se00_SampleEnvironmentData::create(fbb, &args);
+        finish_se_00_sample_environment_data_buffer(fbb, message);
+        root_as_se_00_sample_environment_data(fbb.finished_data())
+    }
+
+    fn create_ep01<'a, 'b: 'a>(
+        fbb: &'b mut FlatBufferBuilder,
+        name: &str,
+        timestamp_ns: i64,
+        status: ConnectionInfo,
+    ) -> Result<EpicsPVConnectionInfo<'a>, InvalidFlatbuffer> {
+        let args = EpicsPVConnectionInfoArgs {
+            timestamp: timestamp_ns,
+            status,
+            source_name: Some(fbb.create_string(name)),
+            ..Default::default()
+        };
+        let message = EpicsPVConnectionInfo::create(fbb, &args);
+        finish_epics_pvconnection_info_buffer(fbb, message);
+        root_as_epics_pvconnection_info(fbb.finished_data())
+    }
+
     struct MockDependencies;
     impl NexusEngineDependencies for MockDependencies {
         type FileInterface = NexusNoFile;
@@ -601,4 +841,593 @@ mod test {
         let _ = nexus.flush(&Duration::zero());
         assert_eq!(nexus.cache_iter().len(), 0);
     }
+
+    struct FileDependencies;
+    impl NexusEngineDependencies for FileDependencies {
+        type FileInterface = NexusFile;
+        type TopicInterface = NoKafka;
+    }
+
+    #[test]
+    fn sample_environment_data_written_to_file() {
+        let local_path =
+            std::env::temp_dir().join(format!("nexus-writer-selog-test-{}", std::process::id()));
+        let nexus_settings = NexusSettings::new(&local_path, 16, 16, None, 60, 0, 0);
+        create_dir_all(nexus_settings.get_local_completed_path()).unwrap();
+
+        let mut nexus = NexusEngine::<FileDependencies>::new(
+            nexus_settings,
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        let s_to_ns = |s: f64| (s * 1_000_000_000.0) as i64;
+        let block = "IN:MUSR:CS:SB:Temp";
+
+        let mut fbb = FlatBufferBuilder::new();
+        let start = create_start(&mut fbb, "SELogTest", 15_000).unwrap();
+        nexus.push_run_start(start).unwrap();
+
+        // Timestamped before the run start, but delivered once the run has started.
+        fbb.reset();
+        let se00 = create_se00(
+            &mut fbb,
+            block,
+            &[s_to_ns(14.0), s_to_ns(14.5)],
+            &[1.5, 2.5],
+        )
+        .unwrap();
+        nexus
+            .push_sample_environment_log(SampleEnvironmentLog::SampleEnvironmentData(se00))
+            .unwrap();
+
+        fbb.reset();
+        let ep01 =
+            create_ep01(&mut fbb, block, s_to_ns(16.0), ConnectionInfo::DISCONNECTED).unwrap();
+        nexus.push_connection_status(ep01).unwrap();
+
+        fbb.reset();
+        let stop = create_stop(&mut fbb, "SELogTest", 17_000).unwrap();
+        nexus.push_run_stop(stop).unwrap();
+
+        // Delivered after the run stop, within the grace period.
+        fbb.reset();
+        let se00 = create_se00(&mut fbb, block, &[s_to_ns(16.5)], &[3.5]).unwrap();
+        nexus
+            .push_sample_environment_log(SampleEnvironmentLog::SampleEnvironmentData(se00))
+            .unwrap();
+
+        // Timestamped after the run stop, so is not written.
+        fbb.reset();
+        let se00 = create_se00(&mut fbb, block, &[s_to_ns(17.5)], &[4.5]).unwrap();
+        nexus
+            .push_sample_environment_log(SampleEnvironmentLog::SampleEnvironmentData(se00))
+            .unwrap();
+
+        nexus.flush(&Duration::zero()).unwrap();
+        assert_eq!(nexus.cache_iter().len(), 0);
+
+        let file = hdf5::File::open(RunParameters::get_hdf5_filename(
+            &local_path.join("completed"),
+            "SELogTest",
+        ))
+        .unwrap();
+        let selog = file.group("raw_data_1/selog/Temp").unwrap();
+        let value_log = selog.group("value_log").unwrap();
+        assert_eq!(
+            value_log
+                .dataset("value")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [1.5, 2.5, 3.5]
+        );
+        assert_eq!(
+            value_log
+                .dataset("time")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [-1.0, -0.5, 1.5]
+        );
+        assert_eq!(
+            selog
+                .dataset("connection_status")
+                .unwrap()
+                .read_raw::<hdf5::types::VarLenUnicode>()
+                .unwrap()
+                .iter()
+                .map(|status| status.as_str())
+                .collect::<Vec<_>>(),
+            ["DISCONNECTED"]
+        );
+        assert_eq!(
+            selog
+                .dataset("connection_status_time")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [1.0]
+        );
+
+        drop(file);
+        remove_dir_all(local_path).unwrap();
+    }
+
+    #[test]
+    fn sample_environment_data_before_run_start_is_held_for_the_run() {
+        let local_path =
+            std::env::temp_dir().join(format!("nexus-writer-held-test-{}", std::process::id()));
+        let nexus_settings = NexusSettings::new(&local_path, 16, 16, None, 60, 0, 0);
+        create_dir_all(nexus_settings.get_local_completed_path()).unwrap();
+
+        let mut nexus = NexusEngine::<FileDependencies>::new(
+            nexus_settings,
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        let s_to_ns = |s: f64| (s * 1_000_000_000.0) as i64;
+        let block = "IN:MUSR:CS:SB:Temp";
+
+        // Delivered before the run start, so no run accepts them.
+        let mut fbb = FlatBufferBuilder::new();
+        let se00 = create_se00(&mut fbb, block, &[s_to_ns(14.0)], &[1.5]).unwrap();
+        assert!(
+            !nexus
+                .push_sample_environment_log(SampleEnvironmentLog::SampleEnvironmentData(se00))
+                .unwrap()
+        );
+        nexus.hold_sample_environment(fbb.finished_data());
+
+        fbb.reset();
+        let ep01 = create_ep01(&mut fbb, block, s_to_ns(14.5), ConnectionInfo::CONNECTED).unwrap();
+        assert!(!nexus.push_connection_status(ep01).unwrap());
+        nexus.hold_sample_environment(fbb.finished_data());
+
+        fbb.reset();
+        let start = create_start(&mut fbb, "HeldTest", 15_000).unwrap();
+        nexus.push_run_start(start).unwrap();
+        assert!(nexus.held_sample_environment.is_empty());
+
+        fbb.reset();
+        let se00 = create_se00(&mut fbb, block, &[s_to_ns(16.0)], &[2.5]).unwrap();
+        assert!(
+            nexus
+                .push_sample_environment_log(SampleEnvironmentLog::SampleEnvironmentData(se00))
+                .unwrap()
+        );
+
+        fbb.reset();
+        let stop = create_stop(&mut fbb, "HeldTest", 17_000).unwrap();
+        nexus.push_run_stop(stop).unwrap();
+        nexus.flush(&Duration::zero()).unwrap();
+
+        let file = hdf5::File::open(RunParameters::get_hdf5_filename(
+            &local_path.join("completed"),
+            "HeldTest",
+        ))
+        .unwrap();
+        let selog = file.group("raw_data_1/selog/Temp").unwrap();
+        let value_log = selog.group("value_log").unwrap();
+        assert_eq!(
+            value_log
+                .dataset("value")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [1.5, 2.5]
+        );
+        assert_eq!(
+            value_log
+                .dataset("time")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [-1.0, 1.0]
+        );
+        assert_eq!(
+            selog
+                .dataset("connection_status_time")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [-0.5]
+        );
+
+        drop(file);
+        remove_dir_all(local_path).unwrap();
+    }
+
+    #[test]
+    fn only_the_most_recent_sample_environment_messages_are_held() {
+        let mut nexus = NexusEngine::<MockDependencies>::new(
+            NexusSettings::default(),
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        for index in 0..=MAX_HELD_SAMPLE_ENVIRONMENT_MESSAGES {
+            nexus.hold_sample_environment(&index.to_le_bytes());
+        }
+        assert_eq!(
+            nexus.held_sample_environment.len(),
+            MAX_HELD_SAMPLE_ENVIRONMENT_MESSAGES
+        );
+        assert_eq!(nexus.held_sample_environment[0], 1usize.to_le_bytes());
+    }
+
+    #[test]
+    fn opposite_polarity_events_are_not_written() {
+        let local_path =
+            std::env::temp_dir().join(format!("nexus-writer-polarity-test-{}", std::process::id()));
+        let nexus_settings = NexusSettings::new(&local_path, 16, 16, None, 60, 0, 0);
+        create_dir_all(nexus_settings.get_local_completed_path()).unwrap();
+
+        let mut nexus = NexusEngine::<FileDependencies>::new(
+            nexus_settings,
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+
+        let ts_start: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 15, 0, 0, 0).try_into().unwrap();
+        let ts_end: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 17, 0, 0, 0).try_into().unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        let start =
+            create_start(&mut fbb, "PolarityTest", ts_start.timestamp_millis() as u64).unwrap();
+        nexus.push_run_start(start).unwrap();
+
+        // The second lobe of each bipolar pulse is opposite the configured polarity.
+        fbb.reset();
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &create_metadata(&GpsTime::new(0, 1, 0, 0, 16, 0, 0, 0)),
+        );
+        let args = FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            channel: Some(fbb.create_vector(&[1u32, 1, 2, 2])),
+            time: Some(fbb.create_vector(&[10u32, 30, 10, 30])),
+            voltage: Some(fbb.create_vector(&[100u16, 100, 200, 200])),
+            polarity: Some(fbb.create_vector(&[1i8, -1, 1, -1])),
+            complete: true,
+            ..Default::default()
+        };
+        let message = FrameAssembledEventListMessage::create(&mut fbb, &args);
+        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        let message = root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        nexus.push_frame_event_list(message).unwrap();
+
+        // Without a polarity vector, every event is written.
+        let mut fbb = FlatBufferBuilder::new();
+        let message = create_frame_assembled_message_with_events(
+            &mut fbb,
+            &GpsTime::new(0, 1, 0, 0, 16, 500, 0, 0),
+            &[3],
+            &[20],
+            &[300],
+        )
+        .unwrap();
+        nexus.push_frame_event_list(message).unwrap();
+
+        let mut fbb = FlatBufferBuilder::new();
+        let stop = create_stop(&mut fbb, "PolarityTest", ts_end.timestamp_millis() as u64).unwrap();
+        nexus.push_run_stop(stop).unwrap();
+        nexus.flush(&Duration::zero()).unwrap();
+
+        let file = hdf5::File::open(RunParameters::get_hdf5_filename(
+            &local_path.join("completed"),
+            "PolarityTest",
+        ))
+        .unwrap();
+        let events = file.group("raw_data_1/detector_1_events").unwrap();
+        assert_eq!(
+            events
+                .dataset("event_id")
+                .unwrap()
+                .read_raw::<u32>()
+                .unwrap(),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            events
+                .dataset("event_time_offset")
+                .unwrap()
+                .read_raw::<u32>()
+                .unwrap(),
+            [10, 10, 20]
+        );
+        assert_eq!(
+            events
+                .dataset("pulse_height")
+                .unwrap()
+                .read_raw::<f64>()
+                .unwrap(),
+            [100.0, 200.0, 300.0]
+        );
+        assert_eq!(
+            events
+                .dataset("event_index")
+                .unwrap()
+                .read_raw::<u64>()
+                .unwrap(),
+            [0, 2]
+        );
+
+        drop(file);
+        remove_dir_all(local_path).unwrap();
+    }
+
+    /// If set, [partial_run_recovered_after_writer_killed] is being run in a child process,
+    /// which writes part of a run to the directory given by the variable, then aborts.
+    const KILLED_WRITER_PATH: &str = "NEXUS_WRITER_TEST_KILLED_WRITER_PATH";
+
+    /// Name of the file in which [write_partial_run_then_abort] saves the offsets it would have committed to Kafka.
+    const KILLED_WRITER_COMMITTED_OFFSETS: &str = "committed.offsets.json";
+
+    /// Writes a run start and a frame to a run in `local_path`, flushes it, then writes another frame
+    /// which is not flushed, before aborting the process, so that the file of the run is never closed.
+    /// The offsets which would have been committed to Kafka are saved to [KILLED_WRITER_COMMITTED_OFFSETS].
+    fn write_partial_run_then_abort(local_path: &Path) -> ! {
+        let mut nexus = NexusEngine::<FileDependencies>::new(
+            NexusSettings::new(local_path, 16, 16, None, 60, 60, 0),
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+
+        let ts_start: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 15, 0, 0, 0).try_into().unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        let start =
+            create_start(&mut fbb, "RecoveryTest", ts_start.timestamp_millis() as u64).unwrap();
+        nexus.push_run_start(start).unwrap();
+        nexus.commit_offset("control", 0, 3).unwrap();
+
+        fbb.reset();
+        let message = create_frame_assembled_message_with_events(
+            &mut fbb,
+            &GpsTime::new(0, 1, 0, 0, 16, 0, 0, 0),
+            &[1, 2],
+            &[10, 20],
+            &[100, 200],
+        )
+        .unwrap();
+        nexus.push_frame_event_list(message).unwrap();
+        nexus.commit_offset("events", 0, 7).unwrap();
+
+        // The run is flushed, as it would be once the flush interval has passed.
+        for run in nexus.run_cache.iter_mut() {
+            run.flush_to_disk(&nexus.nexus_settings).unwrap();
+        }
+
+        // A frame outside of the run is written to no run, so its offset is not recorded.
+        fbb.reset();
+        let message =
+            create_frame_assembled_message(&mut fbb, &GpsTime::new(0, 1, 0, 0, 14, 0, 0, 0))
+                .unwrap();
+        nexus.push_frame_event_list(message).unwrap();
+        nexus.commit_offset("events", 0, 8).unwrap();
+
+        // This frame is written to the run, but is not flushed before the writer is killed.
+        fbb.reset();
+        let message = create_frame_assembled_message_with_events(
+            &mut fbb,
+            &GpsTime::new(0, 1, 0, 0, 16, 500, 0, 0),
+            &[3],
+            &[30],
+            &[300],
+        )
+        .unwrap();
+        nexus.push_frame_event_list(message).unwrap();
+        nexus.commit_offset("events", 0, 9).unwrap();
+
+        nexus
+            .get_flushed_offsets()
+            .save(&local_path.join(KILLED_WRITER_COMMITTED_OFFSETS))
+            .unwrap();
+
+        std::process::abort()
+    }
+
+    #[test]
+    fn partial_run_recovered_after_writer_killed() {
+        if let Some(local_path) = std::env::var_os(KILLED_WRITER_PATH) {
+            write_partial_run_then_abort(Path::new(&local_path));
+        }
+
+        let local_path =
+            std::env::temp_dir().join(format!("nexus-writer-recovery-test-{}", std::process::id()));
+        let nexus_settings = || NexusSettings::new(&local_path, 16, 16, None, 60, 0, 0);
+        create_dir_all(nexus_settings().get_local_completed_path()).unwrap();
+
+        let ts_start: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 15, 0, 0, 0).try_into().unwrap();
+        let ts_end: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 17, 0, 0, 0).try_into().unwrap();
+
+        // The writer is killed mid-run by running this test again in a child process, which aborts
+        // without a run stop, `close_all`, or the file of the run being closed.
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "run_engine::engine::test::partial_run_recovered_after_writer_killed",
+            ])
+            .env(KILLED_WRITER_PATH, &local_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+
+        let mut nexus = NexusEngine::<FileDependencies>::new(
+            nexus_settings(),
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        nexus.resume_partial_runs().unwrap();
+        assert_eq!(nexus.cache_iter().len(), 1);
+
+        let recovered_offsets = nexus.get_recovered_offsets();
+        assert_eq!(
+            recovered_offsets.iter().collect::<Vec<_>>(),
+            [("control", 0, 3), ("events", 0, 7)]
+        );
+
+        // Consumption of the frame which was not flushed was not committed, so it is consumed again.
+        let committed_offsets = RunOffsets::load(&local_path.join(KILLED_WRITER_COMMITTED_OFFSETS))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            committed_offsets.iter().collect::<Vec<_>>(),
+            [("control", 0, 3), ("events", 0, 8)]
+        );
+
+        // The same run start is replayed, so the recovered run continues.
+        let mut fbb = FlatBufferBuilder::new();
+        let start =
+            create_start(&mut fbb, "RecoveryTest", ts_start.timestamp_millis() as u64).unwrap();
+        nexus.push_run_start(start).unwrap();
+        assert_eq!(nexus.cache_iter().len(), 1);
+
+        fbb.reset();
+        let message = create_frame_assembled_message_with_events(
+            &mut fbb,
+            &GpsTime::new(0, 1, 0, 0, 16, 500, 0, 0),
+            &[3],
+            &[30],
+            &[300],
+        )
+        .unwrap();
+        nexus.push_frame_event_list(message).unwrap();
+        nexus.commit_offset("events", 0, 9).unwrap();
+
+        fbb.reset();
+        let stop = create_stop(&mut fbb, "RecoveryTest", ts_end.timestamp_millis() as u64).unwrap();
+        nexus.push_run_stop(stop).unwrap();
+
+        nexus.flush(&Duration::zero()).unwrap();
+        assert_eq!(nexus.cache_iter().len(), 0);
+        assert!(
+            !nexus_settings()
+                .get_offsets_filename("RecoveryTest")
+                .exists()
+        );
+
+        let file = hdf5::File::open(RunParameters::get_hdf5_filename(
+            &local_path.join("completed"),
+            "RecoveryTest",
+        ))
+        .unwrap();
+        assert!(
+            file.attr("incomplete_run")
+                .unwrap()
+                .read_scalar::<bool>()
+                .unwrap()
+        );
+        assert_eq!(
+            file.dataset("raw_data_1/detector_1_events/event_id")
+                .unwrap()
+                .read_raw::<u32>()
+                .unwrap(),
+            [1, 2, 3]
+        );
+
+        drop(file);
+        remove_dir_all(local_path).unwrap();
+    }
+
+    /// Returns the value of the metric `name` labelled by the run `run_name`, if it has been recorded.
+    fn run_metric(recorder: &DebuggingRecorder, name: &str, run_name: &str) -> Option<DebugValue> {
+        recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .find_map(|(key, _, _, value)| {
+                let key = CompositeKey::key(&key);
+                (key.name() == name
+                    && key
+                        .labels()
+                        .any(|label| label.key() == "run_name" && label.value() == run_name))
+                .then_some(value)
+            })
+    }
+
+    #[test]
+    fn progress_metrics_recorded() {
+        let local_path =
+            std::env::temp_dir().join(format!("nexus-writer-progress-test-{}", std::process::id()));
+        let nexus_settings = NexusSettings::new(&local_path, 16, 16, None, 60, 0, 2);
+        create_dir_all(nexus_settings.get_local_completed_path()).unwrap();
+
+        let mut nexus = NexusEngine::<FileDependencies>::new(
+            nexus_settings,
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        let ts_start: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 15, 0, 0, 0).try_into().unwrap();
+        let ts_frame = GpsTime::new(0, 1, 0, 0, 16, 0, 0, 0);
+        let ts_end: DateTime<Utc> = GpsTime::new(0, 1, 0, 0, 17, 0, 0, 0).try_into().unwrap();
+
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            let mut fbb = FlatBufferBuilder::new();
+            let start =
+                create_start(&mut fbb, "ProgressRun", ts_start.timestamp_millis() as u64).unwrap();
+            nexus.push_run_start(start).unwrap();
+
+            for (offset, num_events) in [2, 3, 0].into_iter().enumerate() {
+                fbb.reset();
+                let message = create_frame_assembled_message_with_events(
+                    &mut fbb,
+                    &ts_frame,
+                    &vec![1; num_events],
+                    &vec![10; num_events],
+                    &vec![100; num_events],
+                )
+                .unwrap();
+                nexus.push_frame_event_list(message).unwrap();
+                nexus.commit_offset("events", 0, offset as i64).unwrap();
+            }
+        });
+
+        assert!(matches!(
+            run_metric(&recorder, RUN_EVENTS_WRITTEN, "ProgressRun"),
+            Some(DebugValue::Gauge(events)) if events.into_inner() == 5.0
+        ));
+        assert!(matches!(
+            run_metric(&recorder, RUN_FLUSHES, "ProgressRun"),
+            Some(DebugValue::Gauge(flushes)) if flushes.into_inner() == 3.0
+        ));
+        // The size of the file written so far, as reported by the hdf5 library.
+        assert!(matches!(
+            run_metric(&recorder, RUN_FILE_SIZE_BYTES, "ProgressRun"),
+            Some(DebugValue::Gauge(size)) if size.into_inner() > 0.0
+        ));
+        // The frames are timestamped in 2000, so lag far behind the wall clock.
+        assert!(matches!(
+            run_metric(&recorder, RUN_EVENT_LAG_SECONDS, "ProgressRun"),
+            Some(DebugValue::Gauge(lag)) if lag.into_inner() > 0.0
+        ));
+        assert!(run_metric(&recorder, RUN_EVENTS_WRITTEN, "OtherRun").is_none());
+
+        // The metrics of an active run are republished each time the runs are polled.
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || nexus.flush(&Duration::zero()).unwrap());
+        assert!(matches!(
+            run_metric(&recorder, RUN_EVENTS_WRITTEN, "ProgressRun"),
+            Some(DebugValue::Gauge(events)) if events.into_inner() == 5.0
+        ));
+
+        // Those of a completed run are not, so are removed by the exporter.
+        let mut fbb = FlatBufferBuilder::new();
+        let stop = create_stop(&mut fbb, "ProgressRun", ts_end.timestamp_millis() as u64).unwrap();
+        nexus.push_run_stop(stop).unwrap();
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || nexus.flush(&Duration::zero()).unwrap());
+        assert_eq!(nexus.cache_iter().len(), 0);
+        for name in [
+            RUN_EVENTS_WRITTEN,
+            RUN_FLUSHES,
+            RUN_FILE_SIZE_BYTES,
+            RUN_EVENT_LAG_SECONDS,
+        ] {
+            assert!(run_metric(&recorder, name, "ProgressRun").is_none());
+        }
+
+        remove_dir_all(local_path).unwrap();
+    }
 }
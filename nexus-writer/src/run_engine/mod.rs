@@ -6,10 +6,12 @@ mod settings;
 
 use chrono::{DateTime, Utc};
 pub(crate) use engine::{NexusEngine, NexusEngineDependencies};
-pub(crate) use run::{NexusConfiguration, Run, RunParameters, RunStopParameters};
+pub(crate) use run::{
+    NexusConfiguration, RUN_METRICS_MASK, Run, RunOffsets, RunParameters, RunStopParameters,
+};
 pub(crate) use settings::{
-    AlarmChunkSize, ChunkSizeSettings, EventChunkSize, FrameChunkSize, NexusSettings,
-    PeriodChunkSize,
+    AlarmChunkSize, ChunkSizeSettings, ConnectionChunkSize, EventChunkSize, FrameChunkSize,
+    NexusSettings, PeriodChunkSize,
 };
 
 /// UTC-timezoned DateTime type to reduce boiler plate.
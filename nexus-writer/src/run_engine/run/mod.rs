@@ -1,26 +1,36 @@
 //! Encapsulates a single run and provides methods for handling flatbuffer messages, intended for this run.
+mod run_offsets;
 mod run_parameters;
+mod run_progress;
 mod run_spans;
 
 use super::{
     NexusDateTime, NexusSettings,
     run_messages::{
-        InitialiseNewNexusStructure, InternallyGeneratedLog, PushAlarm, PushFrameEventList,
-        PushInternallyGeneratedLogWarning, PushRunLog, PushRunStart, PushSampleEnvironmentLog,
-        SampleEnvironmentLog, SetEndTime, UpdatePeriodList,
+        InitialiseNewNexusStructure, InternallyGeneratedLog, PushAlarm, PushConnectionStatus,
+        PushFrameEventList, PushInternallyGeneratedLogWarning, PushRunLog, PushRunStart,
+        PushSampleEnvironmentLog, SampleEnvironmentLog, SetEndTime, SetIncompleteRun,
+        UpdatePeriodList,
     },
 };
-use crate::{error::NexusWriterResult, hdf5_handlers::NexusHDF5Result, nexus::NexusFileInterface};
+use crate::{
+    error::NexusWriterResult, hdf5_handlers::NexusHDF5Result, nexus::NexusFileInterface,
+    nexus_structure::num_muon_events,
+};
 use chrono::{Duration, Utc};
 use digital_muon_common::spanned::SpanOnce;
 use digital_muon_streaming_types::aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage;
 use isis_streaming_data_types::flatbuffers_generated::{
-    alarm_al00::Alarm, logdata_f144::f144_LogData, run_start_pl72::RunStart, run_stop_6s4t::RunStop,
+    alarm_al00::Alarm, epics_connection_ep01::EpicsPVConnectionInfo, logdata_f144::f144_LogData,
+    run_start_pl72::RunStart, run_stop_6s4t::RunStop,
 };
+pub(crate) use run_offsets::RunOffsets;
 pub(crate) use run_parameters::{NexusConfiguration, RunParameters, RunStopParameters};
+pub(crate) use run_progress::RUN_METRICS_MASK;
+use run_progress::RunProgress;
 pub(crate) use run_spans::RunSpan;
-use std::{io, path::Path};
-use tracing::{error, info, info_span};
+use std::{io, mem, path::Path};
+use tracing::{error, info, info_span, warn};
 
 /// Represents a single run.
 ///
@@ -36,6 +46,16 @@ pub(crate) struct Run<I: NexusFileInterface> {
     parameters: RunParameters,
     /// Must implement the [NexusFileInterface] trait, allows for the creation of and interaction with HDF5 files.
     file: I,
+    /// Kafka offsets of the messages committed to the run.
+    offsets: RunOffsets,
+    /// Kafka offsets of the earliest messages committed to the run since the file was last flushed.
+    unflushed_offsets: RunOffsets,
+    /// Whether the run has been sent a message whose offset has not yet been recorded in `offsets`.
+    received_message: bool,
+    /// Timestamp of the last flush of the file to disk.
+    last_flushed: NexusDateTime,
+    /// Counts of the data written to the run, which are published as metrics.
+    progress: RunProgress,
 }
 
 impl<I: NexusFileInterface> Run<I> {
@@ -68,6 +88,11 @@ impl<I: NexusFileInterface> Run<I> {
             span: Default::default(),
             parameters,
             file,
+            offsets: Default::default(),
+            unflushed_offsets: Default::default(),
+            received_message: true,
+            last_flushed: Utc::now(),
+            progress: Default::default(),
         };
         run.link_run_start_span();
 
@@ -75,6 +100,8 @@ impl<I: NexusFileInterface> Run<I> {
     }
 
     /// Creates a run, and populates it from an existing NeXus file.
+    /// The file is flagged as an incomplete run, and the Kafka offsets
+    /// committed to it are loaded from its sidecar file, if one exists.
     /// # Parameters
     /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
     /// - filename: path of the NeXus file.
@@ -92,12 +119,24 @@ impl<I: NexusFileInterface> Run<I> {
             origin: &parameters.collect_from,
             settings: nexus_settings.get_chunk_sizes(),
         })?;
+        file.handle_message(&SetIncompleteRun)?;
         file.flush()?;
 
+        let offsets = RunOffsets::load(&nexus_settings.get_offsets_filename(filename))?
+            .unwrap_or_else(|| {
+                warn!("No offsets file found for partial run {filename}");
+                Default::default()
+            });
+
         Ok(Self {
             span: Default::default(),
             parameters,
             file,
+            offsets,
+            unflushed_offsets: Default::default(),
+            received_message: false,
+            last_flushed: Utc::now(),
+            progress: Default::default(),
         })
     }
 
@@ -106,6 +145,101 @@ impl<I: NexusFileInterface> Run<I> {
         &self.parameters
     }
 
+    /// Returns a ref to the Kafka offsets committed to the run.
+    pub(crate) fn offsets(&self) -> &RunOffsets {
+        &self.offsets
+    }
+
+    /// Returns a ref to the Kafka offsets of the earliest messages committed to the run
+    /// which have not yet been flushed to disk.
+    pub(crate) fn unflushed_offsets(&self) -> &RunOffsets {
+        &self.unflushed_offsets
+    }
+
+    /// Checks whether a `run_start` message restarts this run, that is
+    /// it has the same run name and start time, and the run has not been stopped.
+    /// Start times are compared to the second, as this is the precision stored in the NeXus file.
+    /// # Parameters
+    /// - run_start: the message to test.
+    pub(crate) fn is_restarted_by(&self, run_start: &RunStart<'_>) -> bool {
+        !self.has_run_stop()
+            && run_start.run_name() == Some(self.parameters.run_name.as_str())
+            && i64::try_from(run_start.start_time() / 1000)
+                .is_ok_and(|start_time| start_time == self.parameters.collect_from.timestamp())
+    }
+
+    /// Notes that the run has been sent a message, whose offset is recorded by the next call to [Self::commit_offset].
+    pub(crate) fn mark_received(&mut self) {
+        self.received_message = true;
+    }
+
+    /// Records the Kafka offset of a message which has been committed, if it was sent to the run,
+    /// and flushes the file to disk if at least the flush interval has passed since it was last flushed.
+    /// # Parameters
+    /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
+    /// - topic: the topic the message was consumed from.
+    /// - partition: the partition the message was consumed from.
+    /// - offset: the offset of the message.
+    pub(crate) fn commit_offset(
+        &mut self,
+        nexus_settings: &NexusSettings,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> NexusWriterResult<()> {
+        if mem::take(&mut self.received_message) {
+            self.offsets.record(topic, partition, offset);
+            self.unflushed_offsets
+                .record_earliest(topic, partition, offset);
+        }
+        self.flush_if_due(nexus_settings)
+    }
+
+    /// Flushes the file to disk, and saves the offsets committed to the run to its sidecar file,
+    /// if at least the flush interval has passed since the file was last flushed.
+    /// # Parameters
+    /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
+    pub(crate) fn flush_if_due(&mut self, nexus_settings: &NexusSettings) -> NexusWriterResult<()> {
+        if Utc::now() - self.last_flushed >= nexus_settings.get_flush_interval() {
+            self.flush_to_disk(nexus_settings)?;
+        }
+        Ok(())
+    }
+
+    /// Republishes the run's metrics, so they are not removed whilst it is active.
+    pub(crate) fn publish_progress(&self) {
+        self.progress.publish(&self.parameters.run_name);
+    }
+
+    /// Flushes the file to disk, saves the offsets committed to the run to its sidecar file,
+    /// and records the flush and the size of the file in the run's metrics.
+    /// # Parameters
+    /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
+    pub(crate) fn flush_to_disk(
+        &mut self,
+        nexus_settings: &NexusSettings,
+    ) -> NexusWriterResult<()> {
+        self.file.flush()?;
+        self.offsets
+            .save(&nexus_settings.get_offsets_filename(&self.parameters.file_name))?;
+        self.unflushed_offsets = Default::default();
+        self.last_flushed = Utc::now();
+        self.progress
+            .record_flush(&self.parameters.run_name, self.file.size());
+        Ok(())
+    }
+
+    /// Deletes the run's offsets sidecar file, if it exists.
+    /// # Parameters
+    /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
+    pub(crate) fn remove_offsets_file(&self, nexus_settings: &NexusSettings) -> io::Result<()> {
+        match std::fs::remove_file(nexus_settings.get_offsets_filename(&self.parameters.file_name))
+        {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     /// Renames the path of "LOCAL_PATH/temp/FILENAME.nxs" to "LOCAL_PATH/completed/FILENAME.nxs"
     /// As these paths are on the same mount, no actual file move occurs,
     /// So this does not need to be async.
@@ -137,15 +271,18 @@ impl<I: NexusFileInterface> Run<I> {
         })
     }
 
-    /// Takes `frame_event_list` message and attempts to append it to the run.
+    /// Takes `frame_event_list` message and attempts to append it to the run,
+    /// recording its events in the run's metrics.
     /// # Parameters
     /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
     /// - message: message to push.
+    /// - timestamp: the timestamp of the message's frame.
     #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
     pub(crate) fn push_frame_event_list(
         &mut self,
         nexus_settings: &NexusSettings,
         message: FrameAssembledEventListMessage,
+        timestamp: &NexusDateTime,
     ) -> NexusWriterResult<()> {
         self.link_frame_event_list_span(message);
         self.file
@@ -173,9 +310,14 @@ impl<I: NexusFileInterface> Run<I> {
                 })?;
         }
 
-        self.file.flush()?;
-
+        self.progress.record_frame(
+            &self.parameters.run_name,
+            timestamp,
+            num_muon_events(&message),
+            nexus_settings.get_progress_log_interval_frames(),
+        );
         self.parameters.update_last_modified();
+        self.mark_received();
         Ok(())
     }
 
@@ -196,9 +338,9 @@ impl<I: NexusFileInterface> Run<I> {
             origin: &self.parameters.collect_from,
             settings: nexus_settings.get_chunk_sizes(),
         })?;
-        self.file.flush()?;
 
         self.parameters.update_last_modified();
+        self.mark_received();
         Ok(())
     }
 
@@ -219,9 +361,9 @@ impl<I: NexusFileInterface> Run<I> {
             origin: &self.parameters.collect_from,
             settings: nexus_settings.get_chunk_sizes(),
         })?;
-        self.file.flush()?;
 
         self.parameters.update_last_modified();
+        self.mark_received();
         Ok(())
     }
 
@@ -242,9 +384,32 @@ impl<I: NexusFileInterface> Run<I> {
             origin: &self.parameters.collect_from,
             settings: nexus_settings.get_chunk_sizes(),
         })?;
-        self.file.flush()?;
 
         self.parameters.update_last_modified();
+        self.mark_received();
+        Ok(())
+    }
+
+    /// Takes `connection_status` message and attempts to append it to the run.
+    /// # Parameters
+    /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
+    /// - connection_status: message to push.
+    #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
+    pub(crate) fn push_connection_status(
+        &mut self,
+        nexus_settings: &NexusSettings,
+        connection_status: &EpicsPVConnectionInfo,
+    ) -> NexusWriterResult<()> {
+        self.link_connection_status_span();
+
+        self.file.handle_message(&PushConnectionStatus {
+            message: connection_status,
+            origin: &self.parameters.collect_from,
+            settings: nexus_settings.get_chunk_sizes(),
+        })?;
+
+        self.parameters.update_last_modified();
+        self.mark_received();
         Ok(())
     }
 
@@ -276,6 +441,7 @@ impl<I: NexusFileInterface> Run<I> {
                 .collect_until,
         })?;
         self.file.flush()?;
+        self.mark_received();
         Ok(())
     }
 
@@ -0,0 +1,116 @@
+//! Records the Kafka offsets of messages which have been written to a run,
+//! persisted alongside the NeXus file so an interrupted run can be recovered.
+use crate::error::NexusWriterResult;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Last committed Kafka offset, per partition, per topic.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RunOffsets {
+    /// Maps topic names to maps of partition numbers to offsets.
+    topics: BTreeMap<String, BTreeMap<i32, i64>>,
+}
+
+impl RunOffsets {
+    /// Records the offset of a message which has been committed.
+    /// Offsets earlier than the one already recorded for the partition are ignored.
+    /// # Parameters
+    /// - topic: the topic the message was consumed from.
+    /// - partition: the partition the message was consumed from.
+    /// - offset: the offset of the message.
+    pub(crate) fn record(&mut self, topic: &str, partition: i32, offset: i64) {
+        let last = self
+            .topics
+            .entry(topic.to_owned())
+            .or_default()
+            .entry(partition)
+            .or_insert(offset);
+        *last = offset.max(*last);
+    }
+
+    /// Records the offset of a message which has been committed.
+    /// Offsets later than the one already recorded for the partition are ignored.
+    /// # Parameters
+    /// - topic: the topic the message was consumed from.
+    /// - partition: the partition the message was consumed from.
+    /// - offset: the offset of the message.
+    pub(crate) fn record_earliest(&mut self, topic: &str, partition: i32, offset: i64) {
+        let first = self
+            .topics
+            .entry(topic.to_owned())
+            .or_default()
+            .entry(partition)
+            .or_insert(offset);
+        *first = offset.min(*first);
+    }
+
+    /// Combines the offsets of another run into this one, keeping the earliest offset of each partition.
+    /// # Parameters
+    /// - other: the offsets to combine.
+    pub(crate) fn merge_earliest(&mut self, other: &RunOffsets) {
+        for (topic, partitions) in &other.topics {
+            let this_partitions = self.topics.entry(topic.clone()).or_default();
+            for (&partition, &offset) in partitions {
+                let last = this_partitions.entry(partition).or_insert(offset);
+                *last = offset.min(*last);
+            }
+        }
+    }
+
+    /// Lowers the offset of each partition to before the offset of the same partition in `other`, if it is not already.
+    /// Partitions which are not recorded in this instance are ignored.
+    /// # Parameters
+    /// - other: the offsets of the earliest messages to exclude.
+    pub(crate) fn limit_before(&mut self, other: &RunOffsets) {
+        for (topic, partition, offset) in other.iter() {
+            if let Some(last) = self
+                .topics
+                .get_mut(topic)
+                .and_then(|partitions| partitions.get_mut(&partition))
+            {
+                *last = (offset - 1).min(*last);
+            }
+        }
+    }
+
+    /// Iterates over all recorded offsets as `(topic, partition, offset)` triples.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, i32, i64)> {
+        self.topics.iter().flat_map(|(topic, partitions)| {
+            partitions
+                .iter()
+                .map(|(&partition, &offset)| (topic.as_str(), partition, offset))
+        })
+    }
+
+    /// Loads the offsets from a sidecar file.
+    /// # Parameters
+    /// - path: path of the sidecar file.
+    /// # Return
+    /// The offsets, or [None] if the file does not exist.
+    pub(crate) fn load(path: &Path) -> NexusWriterResult<Option<Self>> {
+        match File::open(path) {
+            Ok(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the offsets to a sidecar file, overwriting any existing file.
+    /// The offsets are written to a temporary file first, which is then renamed,
+    /// so an interruption never leaves a partially written sidecar file.
+    /// # Parameters
+    /// - path: path of the sidecar file.
+    pub(crate) fn save(&self, path: &Path) -> NexusWriterResult<()> {
+        let temp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(temp_path, path)?;
+        Ok(())
+    }
+}
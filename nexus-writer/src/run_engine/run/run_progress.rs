@@ -0,0 +1,100 @@
+//! Tracks how much of a run has been written, which is published as metrics labelled by the run name,
+//! and logged periodically for those without access to the metrics.
+//!
+//! The metrics are gauges, which are republished whilst the run is active, so the exporter removes
+//! those of a run once it has completed, see [RUN_METRICS_MASK].
+use crate::run_engine::NexusDateTime;
+use chrono::Utc;
+use digital_muon_common::metrics::names::{
+    RUN_EVENT_LAG_SECONDS, RUN_EVENTS_WRITTEN, RUN_FILE_SIZE_BYTES, RUN_FLUSHES,
+};
+use metrics::gauge;
+use metrics_util::MetricKindMask;
+use tracing::info;
+
+/// The kinds of metric removed by the exporter once they have not been updated for a while.
+/// Every run metric is of these kinds, so those of a completed run, which are no longer republished, are removed.
+pub(crate) const RUN_METRICS_MASK: MetricKindMask = MetricKindMask::GAUGE;
+
+/// Cumulative counts of the data written to a run.
+#[derive(Default, Debug)]
+pub(crate) struct RunProgress {
+    /// Number of frame event list messages written.
+    frames_written: u64,
+    /// Number of muon events written.
+    events_written: u64,
+    /// Timestamp of the latest frame written.
+    newest_frame: Option<NexusDateTime>,
+    /// Size of the file, in bytes, when it was last flushed.
+    file_size_bytes: Option<u64>,
+    /// Number of times the file has been flushed to disk.
+    flushes: u64,
+}
+
+impl RunProgress {
+    /// Records a frame event list message which has been written to the run, and updates the metrics.
+    /// # Parameters
+    /// - run_name: the name of the run.
+    /// - timestamp: the timestamp of the frame.
+    /// - num_events: the number of muon events in the frame.
+    /// - log_interval_frames: if non-zero, the progress is logged every this many frames.
+    pub(crate) fn record_frame(
+        &mut self,
+        run_name: &str,
+        timestamp: &NexusDateTime,
+        num_events: usize,
+        log_interval_frames: u64,
+    ) {
+        self.frames_written += 1;
+        self.events_written += num_events as u64;
+        if self.newest_frame.is_none_or(|newest| newest < *timestamp) {
+            self.newest_frame = Some(*timestamp);
+        }
+        self.publish(run_name);
+
+        if log_interval_frames != 0 && self.frames_written.is_multiple_of(log_interval_frames) {
+            info!(
+                run_name,
+                frames_written = self.frames_written,
+                events_written = self.events_written,
+                file_size_bytes = self.file_size_bytes,
+                flushes = self.flushes,
+                event_lag_seconds = self.event_lag_seconds(),
+                "Run progress"
+            );
+        }
+    }
+
+    /// Records a flush of the run's file to disk, and updates the metrics.
+    /// # Parameters
+    /// - run_name: the name of the run.
+    /// - file_size_bytes: the size of the file after the flush.
+    pub(crate) fn record_flush(&mut self, run_name: &str, file_size_bytes: u64) {
+        self.flushes += 1;
+        self.file_size_bytes = Some(file_size_bytes);
+        self.publish(run_name);
+    }
+
+    /// Sets the run's metrics, those which are not yet known being left unset.
+    /// This should be called periodically whilst the run is active, as the metrics are removed once they have not been set for a while.
+    /// # Parameters
+    /// - run_name: the name of the run.
+    pub(crate) fn publish(&self, run_name: &str) {
+        let labels = [("run_name", run_name.to_owned())];
+        gauge!(RUN_EVENTS_WRITTEN, &labels).set(self.events_written as f64);
+        gauge!(RUN_FLUSHES, &labels).set(self.flushes as f64);
+        if let Some(file_size_bytes) = self.file_size_bytes {
+            gauge!(RUN_FILE_SIZE_BYTES, &labels).set(file_size_bytes as f64);
+        }
+        if let Some(lag) = self.event_lag_seconds() {
+            gauge!(RUN_EVENT_LAG_SECONDS, &labels).set(lag);
+        }
+    }
+
+    /// Returns the time, in seconds, from the timestamp of the latest frame written until now,
+    /// or [None] if no frames have been written.
+    fn event_lag_seconds(&self) -> Option<f64> {
+        self.newest_frame
+            .map(|newest| (Utc::now() - newest).as_seconds_f64())
+    }
+}
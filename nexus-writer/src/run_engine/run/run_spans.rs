@@ -76,6 +76,9 @@ pub(crate) trait RunSpan: SpannedAggregator {
     /// Links the span instrumenting a function processing a `Alarm` message to a child span of the run.
     fn link_alarm_span(&mut self);
 
+    /// Links the span instrumenting a function processing a `EpicsPVConnectionInfo` message to a child span of the run.
+    fn link_connection_status_span(&mut self);
+
     /// Links the span instrumenting a function processing a `RunStop` message to a child span of the run,
     /// which records the `collect_until` field of the [RunStopParameters].
     ///
@@ -136,6 +139,10 @@ impl<I: NexusFileInterface> RunSpan for Run<I> {
         self.link_span(|| info_span!("Alarm"));
     }
 
+    fn link_connection_status_span(&mut self) {
+        self.link_span(|| info_span!("Connection Status"));
+    }
+
     fn link_run_stop_span(&mut self) {
         let collect_until = self
             .parameters()
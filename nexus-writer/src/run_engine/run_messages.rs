@@ -6,7 +6,8 @@ use super::{ChunkSizeSettings, NexusConfiguration, NexusDateTime, RunParameters}
 use crate::nexus::NexusMessageHandler;
 use digital_muon_streaming_types::aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage;
 use isis_streaming_data_types::flatbuffers_generated::{
-    alarm_al00::Alarm, data_se00::se00_SampleEnvironmentData, logdata_f144::f144_LogData,
+    alarm_al00::Alarm, data_se00::se00_SampleEnvironmentData,
+    epics_connection_ep01::EpicsPVConnectionInfo, logdata_f144::f144_LogData,
     run_start_pl72::RunStart,
 };
 use std::ops::Deref;
@@ -93,6 +94,11 @@ pub(crate) type PushSampleEnvironmentLog<'a> = PushLog<'a, &'a SampleEnvironment
 /// [nexus_structure]: crate::nexus_structure
 pub(crate) type PushAlarm<'a> = PushLog<'a, &'a Alarm<'a>>;
 
+/// Tells [nexus_structure] a new `EpicsPVConnectionInfo` has been received.
+///
+/// [nexus_structure]: crate::nexus_structure
+pub(crate) type PushConnectionStatus<'a> = PushLog<'a, &'a EpicsPVConnectionInfo<'a>>;
+
 /// Enum for internally generated logs.
 pub(crate) enum InternallyGeneratedLog<'a> {
     /// When a previously started run, is resumed.
@@ -125,6 +131,12 @@ pub(crate) struct SetEndTime<'a> {
     pub(crate) end_time: &'a NexusDateTime,
 }
 
+/// Tells [nexus_structure] to flag the file as belonging to a run
+/// which was interrupted before it was completed.
+///
+/// [nexus_structure]: crate::nexus_structure
+pub(crate) struct SetIncompleteRun;
+
 /// Ensures anything implementing [NexusFileInterface] must implement the correct [NexusMessageHandler]s.
 /// Any new message that is added to this module should be added here.
 ///
@@ -138,6 +150,8 @@ pub(crate) trait HandlesAllNexusMessages:
     + for<'a> NexusMessageHandler<PushSampleEnvironmentLog<'a>>
     + for<'a> NexusMessageHandler<PushInternallyGeneratedLogWarning<'a>>
     + for<'a> NexusMessageHandler<PushAlarm<'a>>
+    + for<'a> NexusMessageHandler<PushConnectionStatus<'a>>
     + for<'a> NexusMessageHandler<SetEndTime<'a>>
+    + NexusMessageHandler<SetIncompleteRun>
 {
 }
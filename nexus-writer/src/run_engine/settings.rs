@@ -1,5 +1,6 @@
 //! This module defines types used to configure `NexusEngine`
 //! and the modules of `nexus_structure`.
+use chrono::TimeDelta;
 use std::path::{Path, PathBuf};
 use tokio::time::Interval;
 
@@ -24,6 +25,9 @@ pub(crate) type SELogChunkSize = usize;
 /// Type alias to tie the `AlarmLog`'s chunk size to an associated type [crate::nexus::NexusSchematic::Settings].
 pub(crate) type AlarmChunkSize = usize;
 
+/// Type alias to tie the `ConnectionLog`'s chunk size to an associated type [crate::nexus::NexusSchematic::Settings].
+pub(crate) type ConnectionChunkSize = usize;
+
 /// Type alias to tie the chunk sizes of fields which increment by frame, to an associated type [crate::nexus::NexusSchematic::Settings].
 pub(crate) type FrameChunkSize = usize;
 
@@ -48,6 +52,8 @@ pub(crate) struct ChunkSizeSettings {
     pub(crate) selog: SELogChunkSize,
     /// Chunk size for alarm fields.
     pub(crate) alarm: AlarmChunkSize,
+    /// Chunk size for connection status fields.
+    pub(crate) connection: ConnectionChunkSize,
 }
 
 impl ChunkSizeSettings {
@@ -61,6 +67,7 @@ impl ChunkSizeSettings {
             runlog: 64,
             selog: 1024,
             alarm: 32,
+            connection: 32,
         }
     }
 }
@@ -78,6 +85,12 @@ pub(crate) struct NexusSettings {
     archive_path: Option<PathBuf>,
     /// Interval (in seconds) in which the NeXus files in `local_path_completed` are moved to `archive_path` (if set).
     archive_flush_interval_sec: u64,
+    /// Minimum interval (in seconds) between flushes of a run's NeXus file to disk.
+    /// If zero, the file is flushed after every message.
+    flush_interval_sec: u64,
+    /// The progress of each run is logged every this many frame event list messages.
+    /// If zero, the progress is never logged.
+    progress_log_interval_frames: u64,
 }
 
 impl NexusSettings {
@@ -88,6 +101,8 @@ impl NexusSettings {
         eventlist_chunk_size: usize,
         archive_path: Option<&Path>,
         archive_flush_interval_sec: u64,
+        flush_interval_sec: u64,
+        progress_log_interval_frames: u64,
     ) -> Self {
         let local_path = local_path.to_path_buf();
         let mut local_path_completed = local_path.to_path_buf();
@@ -98,6 +113,8 @@ impl NexusSettings {
             chunk_sizes: ChunkSizeSettings::new(framelist_chunk_size, eventlist_chunk_size),
             archive_path: archive_path.map(Path::to_owned),
             archive_flush_interval_sec,
+            flush_interval_sec,
+            progress_log_interval_frames,
         }
     }
 
@@ -139,6 +156,28 @@ impl NexusSettings {
         ))
     }
 
+    /// Returns the minimum time between flushes of a run's NeXus file to disk.
+    pub(crate) fn get_flush_interval(&self) -> TimeDelta {
+        i64::try_from(self.flush_interval_sec)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .unwrap_or(TimeDelta::MAX)
+    }
+
+    /// Returns the number of frame event list messages between logs of each run's progress, or zero if it is never logged.
+    pub(crate) fn get_progress_log_interval_frames(&self) -> u64 {
+        self.progress_log_interval_frames
+    }
+
+    /// Creates the path of the file recording the Kafka offsets written to the run with the given file name.
+    /// # Parameters
+    /// - file_name: the file name of the run.
+    /// # Return
+    /// The path, i.e. of the form "\[local directory\]/\[file_name\].offsets.json".
+    pub(crate) fn get_offsets_filename(&self, file_name: &str) -> PathBuf {
+        self.local_path.join(format!("{file_name}.offsets.json"))
+    }
+
     /// Returns the sizes of the hdf5 chunks to use.
     pub(crate) fn get_chunk_sizes(&self) -> &ChunkSizeSettings {
         &self.chunk_sizes
@@ -0,0 +1,53 @@
+//! Sends actions to the control socket of a simulator run in `defined` mode with `--control-socket`,
+//! printing the reply to each.
+//!
+//! Each action is given as json, in the same format as in the schedule, either as an argument, or if there are none,
+//! as a line of stdin. For instance:
+//! ```sh
+//! cargo run --example send-action -- --address 127.0.0.1:9100 '{ "set-timestamp": "now" }' '{ "set-period": { "const": 2 } }'
+//! ```
+use clap::Parser;
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+#[derive(Parser)]
+struct Cli {
+    /// The address of the simulator's control socket.
+    #[clap(long)]
+    address: SocketAddr,
+
+    /// The json encoded actions to send, in order. If none are given, they are read from stdin, one per line.
+    actions: Vec<String>,
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+
+    let stream = TcpStream::connect(cli.address)?;
+    let mut writer = stream.try_clone()?;
+    let mut replies = BufReader::new(stream).lines();
+
+    let actions: Box<dyn Iterator<Item = io::Result<String>>> = if cli.actions.is_empty() {
+        Box::new(io::stdin().lock().lines())
+    } else {
+        Box::new(cli.actions.into_iter().map(Ok))
+    };
+    for action in actions {
+        // Each action must be sent on a single line.
+        let action = action?.replace('\n', " ");
+        if action.trim().is_empty() {
+            continue;
+        }
+        writeln!(writer, "{action}")?;
+        match replies.next() {
+            Some(reply) => println!("{}", reply?),
+            None => {
+                eprintln!("Control socket closed");
+                break;
+            }
+        }
+    }
+    Ok(())
+}
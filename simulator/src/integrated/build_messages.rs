@@ -1,9 +1,18 @@
-use crate::integrated::{
-    simulation_elements::event_list::{EventList, Trace},
-    simulation_engine::{
-        actions::{SelectionModeOptions, SourceOptions},
-        cache::{CacheError, SimulationEngineCache},
+use crate::{
+    integrated::{
+        simulation_elements::{
+            Transformation,
+            event_list::{EventList, Trace, TraceError},
+            noise::DigitiserNoise,
+            overflow::ClippingCounts,
+            utils::JsonValueError,
+        },
+        simulation_engine::{
+            actions::{DropoutMode, SelectionModeOptions, SourceOptions},
+            cache::{CacheError, SimulationEngineCache},
+        },
     },
+    message_size::estimate_aggregated_event_list_size,
 };
 use digital_muon_common::{Channel, DigitizerId, Intensity, Time, spanned::Spanned};
 use digital_muon_streaming_types::{
@@ -20,17 +29,25 @@ use digital_muon_streaming_types::{
         DigitizerEventListMessage, DigitizerEventListMessageArgs,
         finish_digitizer_event_list_message_buffer,
     },
-    flatbuffers::FlatBufferBuilder,
+    flatbuffers::{FlatBufferBuilder, Push, Vector, WIPOffset},
     frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
 };
+use rayon::prelude::*;
 use std::collections::VecDeque;
 use thiserror::Error;
-use tracing::info_span;
+use tracing::{Span, debug, info_span};
 
 #[derive(Debug, Error)]
 pub(crate) enum BuildError {
     #[error("Cache Error: {0}")]
     Cache(#[from] CacheError),
+    #[error("Json Value Error: {0}")]
+    JsonValue(#[from] JsonValueError),
+    #[error("Trace Error on Channel {channel}: {source}")]
+    Trace {
+        channel: Channel,
+        source: TraceError,
+    },
 }
 
 fn create_v2_metadata_args<'a>(
@@ -47,33 +64,143 @@ fn create_v2_metadata_args<'a>(
     }
 }
 
-pub(crate) fn build_trace_message(
-    fbb: &mut FlatBufferBuilder<'_>,
-    sample_rate: u64,
-    cache: &mut VecDeque<Trace>,
-    metadata: &FrameMetadata,
-    digitizer_id: DigitizerId,
-    channels: &[Channel],
+/// The pulses injected into the traces of a trace message, in the order of its channels.
+#[derive(Default)]
+pub(crate) struct TraceGroundTruth {
+    time: Vec<Time>,
+    voltage: Vec<Intensity>,
+    channel: Vec<Channel>,
+}
+
+impl TraceGroundTruth {
+    fn push(&mut self, channel: Channel, trace: &Trace<'_>) {
+        for &(time, voltage) in trace.get_ground_truth() {
+            self.time.push(time);
+            self.voltage.push(voltage);
+            self.channel.push(channel);
+        }
+    }
+}
+
+/// The events injected into the traces of the trace messages sent for a frame,
+/// from which an aggregated frame event list of the frame is built.
+#[derive(Default)]
+pub(crate) struct FrameTraceEvents {
+    /// The metadata of the first trace message of the frame.
+    metadata: Option<FrameMetadata>,
+    digitizers_present: Vec<DigitizerId>,
+    events: Vec<(Channel, Time, Intensity)>,
+}
+
+impl FrameTraceEvents {
+    /// Adds the events of a trace message, in the order of its channels.
+    pub(crate) fn push(
+        &mut self,
+        metadata: &FrameMetadata,
+        digitizer_id: DigitizerId,
+        ground_truth: &TraceGroundTruth,
+    ) {
+        self.metadata.get_or_insert_with(|| metadata.clone());
+        self.digitizers_present.push(digitizer_id);
+        self.events.extend(
+            ground_truth
+                .channel
+                .iter()
+                .zip(&ground_truth.time)
+                .zip(&ground_truth.voltage)
+                .map(|((&channel, &time), &voltage)| (channel, time, voltage)),
+        );
+    }
+}
+
+/// The trace selected from the cache for a channel of a trace message.
+pub(crate) struct SelectedTrace<'a> {
+    span: Span,
+    channel: Channel,
+    transformation: Transformation<f64>,
+    trace: Trace<'a>,
+}
+
+impl SelectedTrace<'_> {
+    /// Generates the intensities of the trace, with `digitiser_noise` added, see [Trace::generate_intensities_with].
+    fn generate(
+        &self,
+        digitiser_noise: &DigitiserNoise<'_>,
+    ) -> Result<(Vec<Intensity>, usize), BuildError> {
+        self.span.in_scope(|| {
+            self.trace
+                .generate_intensities_with(digitiser_noise, &self.transformation)
+                .map_err(|source| BuildError::Trace {
+                    channel: self.channel,
+                    source,
+                })
+        })
+    }
+}
+
+/// Selects a trace from the cache for each channel, and returns them with the ground truth of the traces.
+///
+/// The traces are selected together, see [SimulationEngineCache::extract].
+pub(crate) fn select_traces<'a>(
+    cache: &mut VecDeque<Trace<'a>>,
+    channels: &[(Channel, &Transformation<f64>)],
     selection_mode: SelectionModeOptions,
-) -> Result<(), BuildError> {
-    let channels = channels
+) -> Result<(TraceGroundTruth, Vec<SelectedTrace<'a>>), BuildError> {
+    let mut ground_truth = TraceGroundTruth::default();
+    let selected = channels
         .iter()
-        .map(|&channel| {
-            info_span!("channel", channel = channel).in_scope(|| {
-                let trace = cache.extract_one(selection_mode)?;
-
+        .zip(cache.extract(selection_mode, channels.len())?)
+        .map(|(&(channel, transformation), trace)| {
+            let span = info_span!("channel", channel = channel);
+            span.in_scope(|| {
+                ground_truth.push(channel, trace);
                 tracing::Span::current()
                     .follows_from(trace.span().get().expect("Span should be initialised"));
-                let voltage = Some(fbb.create_vector::<Intensity>(trace.get_intensities()));
-
-                cache.finish_one(selection_mode)?;
-                Ok(ChannelTrace::create(
-                    fbb,
-                    &ChannelTraceArgs { channel, voltage },
-                ))
-            })
+            });
+            SelectedTrace {
+                span,
+                channel,
+                transformation: transformation.clone(),
+                trace: trace.clone(),
+            }
         })
-        .collect::<Result<Vec<_>, BuildError>>()?;
+        .collect();
+    cache.finish(selection_mode, channels.len())?;
+    Ok((ground_truth, selected))
+}
+
+/// Generates the samples of any per-digitiser noise of the traces of a trace message, which are shared by every channel's trace.
+fn generate_digitiser_noise<'a>(
+    selected: &'a [SelectedTrace<'_>],
+) -> Result<DigitiserNoise<'a>, BuildError> {
+    Ok(Trace::generate_digitiser_noise(
+        selected.iter().map(|selected| &selected.trace),
+    )?)
+}
+
+/// Writes the trace message, whose channels are those of `selected`, and whose traces are taken from `voltages`, in the same order.
+fn write_trace_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    sample_rate: u64,
+    selected: &[SelectedTrace<'_>],
+    voltages: impl IntoIterator<Item = Result<(Vec<Intensity>, usize), BuildError>>,
+    metadata: &FrameMetadata,
+    digitizer_id: DigitizerId,
+) -> Result<ClippingCounts, BuildError> {
+    let mut channels = Vec::with_capacity(selected.len());
+    let mut clipping = ClippingCounts::default();
+    for (selected, voltage) in selected.iter().zip(voltages) {
+        let (voltage, clipped) = voltage?;
+        clipping.add(selected.channel, clipped);
+        let voltage = Some(fbb.create_vector::<Intensity>(&voltage));
+        channels.push(ChannelTrace::create(
+            fbb,
+            &ChannelTraceArgs {
+                channel: selected.channel,
+                voltage,
+            },
+        ));
+    }
 
     let timestamp = metadata.timestamp.into();
     let metadata_args = create_v2_metadata_args(&timestamp, metadata);
@@ -86,59 +213,185 @@ pub(crate) fn build_trace_message(
     };
     let message = DigitizerAnalogTraceMessage::create(fbb, &message);
     finish_digitizer_analog_trace_message_buffer(fbb, message);
-    Ok(())
+    Ok(clipping)
 }
 
-pub(crate) fn build_digitiser_event_list_message(
+/// Builds the trace message from the traces selected for its channels.
+///
+/// The samples of any per-digitiser noise are generated first, and shared by every channel's trace.
+/// Each channel's trace is then generated, and written into the message before the next is generated,
+/// so only one trace is held at once, besides the message.
+///
+/// # Returns
+/// The number of samples of each channel whose values did not fit the [Intensity] type.
+pub(crate) fn build_trace_message(
     fbb: &mut FlatBufferBuilder<'_>,
-    cache: &mut VecDeque<EventList<'_>>,
+    sample_rate: u64,
+    selected: &[SelectedTrace<'_>],
     metadata: &FrameMetadata,
     digitizer_id: DigitizerId,
-    channels: &[Channel],
-    source_options: &SourceOptions,
-) -> Result<(), BuildError> {
-    let mut time = Vec::<Time>::new();
-    let mut voltage = Vec::<Intensity>::new();
-    let mut channel = Vec::<Channel>::new();
-
-    if let SourceOptions::SelectFromCache(selection_mode) = source_options {
-        let event_lists = cache.extract(*selection_mode, channels.len())?;
-        channels
+) -> Result<ClippingCounts, BuildError> {
+    let digitiser_noise = generate_digitiser_noise(selected)?;
+    write_trace_message(
+        fbb,
+        sample_rate,
+        selected,
+        selected
             .iter()
-            .zip(event_lists)
-            .for_each(|(c, event_list)| {
-                info_span!("channel", channel = c).in_scope(|| {
-                    tracing::Span::current()
-                        .follows_from(event_list.span().get().expect("Span exists"));
-                    event_list.pulses.iter().for_each(|p| {
-                        time.push(p.time());
-                        voltage.push(p.intensity());
-                        channel.push(*c);
-                    });
-                })
-            });
-        cache.finish(*selection_mode, channels.len())?;
+            .map(|selected| selected.generate(&digitiser_noise)),
+        metadata,
+        digitizer_id,
+    )
+}
+
+/// A trace message whose traces have been selected from the cache, ready to be built by [build_trace_messages].
+pub(crate) struct PendingTraceMessage<'a> {
+    pub(crate) sample_rate: u64,
+    pub(crate) metadata: FrameMetadata,
+    pub(crate) digitizer_id: DigitizerId,
+    pub(crate) selected: Vec<SelectedTrace<'a>>,
+    pub(crate) ground_truth: TraceGroundTruth,
+    /// If present, the digitiser has dropped out, so the message is not sent as usual.
+    pub(crate) dropout: Option<DropoutMode>,
+}
+
+impl PendingTraceMessage<'_> {
+    fn build(&self) -> Result<(FlatBufferBuilder<'static>, ClippingCounts), BuildError> {
+        let mut fbb = FlatBufferBuilder::new();
+        let clipping = build_trace_message(
+            &mut fbb,
+            self.sample_rate,
+            &self.selected,
+            &self.metadata,
+            self.digitizer_id,
+        )?;
+        Ok((fbb, clipping))
+    }
+}
+
+/// Builds the trace messages of `pending`, as they were built before traces were generated when sent,
+/// by generating the traces of every channel of every message, in parallel, before writing any into its message.
+fn build_trace_messages_eagerly(
+    pending: &[PendingTraceMessage<'_>],
+) -> Result<Vec<(FlatBufferBuilder<'static>, ClippingCounts)>, BuildError> {
+    let digitiser_noise = pending
+        .iter()
+        .map(|pending| generate_digitiser_noise(&pending.selected))
+        .collect::<Result<Vec<_>, BuildError>>()?;
+    let voltages = pending
+        .par_iter()
+        .zip(&digitiser_noise)
+        .map(|(pending, digitiser_noise)| {
+            pending
+                .selected
+                .par_iter()
+                .map(|selected| selected.generate(digitiser_noise))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    pending
+        .iter()
+        .zip(voltages)
+        .map(|(pending, voltages)| {
+            let mut fbb = FlatBufferBuilder::new();
+            let clipping = write_trace_message(
+                &mut fbb,
+                pending.sample_rate,
+                &pending.selected,
+                voltages,
+                &pending.metadata,
+                pending.digitizer_id,
+            )?;
+            Ok((fbb, clipping))
+        })
+        .collect()
+}
+
+/// Builds the trace message of each of `pending`, and passes each, in order, to `send` with the number of its clipped samples.
+///
+/// The messages are built in parallel, in chunks of up to `max_materialised_channels` messages,
+/// each generating one channel's trace at a time, see [build_trace_message],
+/// so at most `max_materialised_channels` traces are held at once, besides the messages of the chunk.
+/// Each chunk is passed to `send`, and dropped, before the next is built.
+///
+/// If `max_materialised_channels` is zero, the traces of every channel of every message are generated first,
+/// as they were before traces were generated when sent, which takes memory proportional to the number of channels.
+pub(crate) fn build_trace_messages<E: From<BuildError>>(
+    pending: &[PendingTraceMessage<'_>],
+    max_materialised_channels: usize,
+    mut send: impl FnMut(
+        &PendingTraceMessage<'_>,
+        FlatBufferBuilder<'static>,
+        ClippingCounts,
+    ) -> Result<(), E>,
+) -> Result<(), E> {
+    let chunk_len = match max_materialised_channels {
+        0 => pending.len().max(1),
+        max_materialised_channels => max_materialised_channels,
+    };
+    for chunk in pending.chunks(chunk_len) {
+        let built = if max_materialised_channels == 0 {
+            build_trace_messages_eagerly(chunk)?
+        } else {
+            chunk
+                .par_iter()
+                .map(PendingTraceMessage::build)
+                .collect::<Result<Vec<_>, BuildError>>()?
+        };
+        for (pending, (fbb, clipping)) in chunk.iter().zip(built) {
+            send(pending, fbb, clipping)?;
+        }
     }
+    Ok(())
+}
+
+/// Builds a digitiser event list message of the pulses injected into the traces of a trace message,
+/// with the same metadata and digitiser id as the trace message.
+pub(crate) fn build_trace_ground_truth_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    ground_truth: &TraceGroundTruth,
+    metadata: &FrameMetadata,
+    digitizer_id: DigitizerId,
+) {
+    finish_digitiser_event_list_message(
+        fbb,
+        metadata,
+        digitizer_id,
+        &ground_truth.time,
+        &ground_truth.voltage,
+        &ground_truth.channel,
+    );
+}
 
+fn finish_digitiser_event_list_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    metadata: &FrameMetadata,
+    digitizer_id: DigitizerId,
+    time: &[Time],
+    voltage: &[Intensity],
+    channel: &[Channel],
+) {
     let timestamp = metadata.timestamp.into();
     let metadata_args = create_v2_metadata_args(&timestamp, metadata);
 
     let message = DigitizerEventListMessageArgs {
         digitizer_id,
         metadata: Some(FrameMetadataV2::create(fbb, &metadata_args)),
-        time: Some(fbb.create_vector(&time)),
-        voltage: Some(fbb.create_vector(&voltage)),
-        channel: Some(fbb.create_vector(&channel)),
+        time: Some(fbb.create_vector(time)),
+        voltage: Some(fbb.create_vector(voltage)),
+        channel: Some(fbb.create_vector(channel)),
+        detector: None,
+        polarity: None,
     };
     let message = DigitizerEventListMessage::create(fbb, &message);
     finish_digitizer_event_list_message_buffer(fbb, message);
-    Ok(())
 }
 
-pub(crate) fn build_aggregated_event_list_message(
+pub(crate) fn build_digitiser_event_list_message(
     fbb: &mut FlatBufferBuilder<'_>,
     cache: &mut VecDeque<EventList<'_>>,
     metadata: &FrameMetadata,
+    digitizer_id: DigitizerId,
     channels: &[Channel],
     source_options: &SourceOptions,
 ) -> Result<(), BuildError> {
@@ -165,18 +418,946 @@ pub(crate) fn build_aggregated_event_list_message(
         cache.finish(*selection_mode, channels.len())?;
     }
 
+    finish_digitiser_event_list_message(fbb, metadata, digitizer_id, &time, &voltage, &channel);
+    Ok(())
+}
+
+/// Builds an aggregated frame event list message of the event lists selected from the cache for each channel,
+/// in a builder sized by [estimate_aggregated_event_list_size], so that it is not grown whilst the message is built.
+///
+/// The events are written into the message directly from the event lists, without first being collected,
+/// so the only copy of them held whilst the message is built is the message itself.
+///
+/// # Returns
+/// The builder, holding the finished message.
+pub(crate) fn build_aggregated_event_list_message(
+    cache: &mut VecDeque<EventList<'_>>,
+    metadata: &FrameMetadata,
+    channels: &[Channel],
+    source_options: &SourceOptions,
+) -> Result<FlatBufferBuilder<'static>, BuildError> {
+    let event_lists = match source_options {
+        SourceOptions::SelectFromCache(selection_mode) => {
+            cache.extract(*selection_mode, channels.len())?
+        }
+        SourceOptions::NoSource => Vec::new(),
+    };
+    let event_lists = channels
+        .iter()
+        .copied()
+        .zip(event_lists)
+        .collect::<Vec<_>>();
+    let num_events = event_lists
+        .iter()
+        .map(|(c, event_list)| {
+            info_span!("channel", channel = c).in_scope(|| {
+                tracing::Span::current()
+                    .follows_from(event_list.span().get().expect("Span exists"));
+            });
+            event_list.pulses.len()
+        })
+        .sum::<usize>();
+
+    let estimate = estimate_aggregated_event_list_size(num_events);
+    let mut fbb = FlatBufferBuilder::with_capacity(estimate);
+
     let timestamp = metadata.timestamp.into();
     let metadata_args = create_v2_metadata_args(&timestamp, metadata);
+    let metadata = FrameMetadataV2::create(&mut fbb, &metadata_args);
+
+    // The events in reverse order, as each vector is written from its end.
+    let reversed_events = || {
+        event_lists.iter().rev().flat_map(|&(c, event_list)| {
+            event_list.pulses.iter().rev().map(move |pulse| (c, pulse))
+        })
+    };
+    let time = create_vector_from_reversed(
+        &mut fbb,
+        num_events,
+        reversed_events().map(|(_, pulse)| pulse.time()),
+    );
+    let voltage = create_vector_from_reversed(
+        &mut fbb,
+        num_events,
+        reversed_events().map(|(_, pulse)| pulse.intensity()),
+    );
+    let channel =
+        create_vector_from_reversed(&mut fbb, num_events, reversed_events().map(|(c, _)| c));
+
+    let message = FrameAssembledEventListMessageArgs {
+        metadata: Some(metadata),
+        time: Some(time),
+        voltage: Some(voltage),
+        channel: Some(channel),
+        complete: true,
+        digitizers_present: None,
+        polarity: None,
+    };
+    let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+    finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+    debug!(
+        "Aggregated frame event list of {num_events} events: estimated {estimate} bytes, built {0} bytes",
+        fbb.finished_data().len()
+    );
+
+    if let SourceOptions::SelectFromCache(selection_mode) = source_options {
+        cache.finish(*selection_mode, channels.len())?;
+    }
+    Ok(fbb)
+}
+
+/// Writes a vector of `len` items into `fbb`, taking each from `reversed_items` as it is written,
+/// rather than from a slice as [FlatBufferBuilder::create_vector] does, whose message is otherwise identical.
+/// As a flatbuffer is written from its end, `reversed_items` yields the items of the vector in reverse order.
+fn create_vector_from_reversed<'fbb, T: Push + Copy>(
+    fbb: &mut FlatBufferBuilder<'fbb>,
+    len: usize,
+    reversed_items: impl Iterator<Item = T>,
+) -> WIPOffset<Vector<'fbb, T>> {
+    fbb.start_vector::<T>(len);
+    let mut written = 0;
+    for item in reversed_items {
+        fbb.push(item);
+        written += 1;
+    }
+    debug_assert_eq!(written, len);
+    fbb.end_vector(len)
+}
+
+/// Builds an aggregated frame event list message of the events of the trace messages sent for a frame,
+/// with the metadata of the first of them.
+///
+/// The events are sorted by channel, those of each channel remaining in the order of its trace,
+/// so the message does not depend on the order in which the digitisers' messages were sent.
+/// The message is complete only if every one of the `num_digitisers` digitisers sent a trace message.
+///
+/// # Returns
+/// False, without building a message, if no trace messages were sent for the frame.
+pub(crate) fn build_frame_trace_events_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    mut events: FrameTraceEvents,
+    num_digitisers: usize,
+) -> bool {
+    let Some(metadata) = events.metadata else {
+        return false;
+    };
+    events.events.sort_by_key(|&(channel, _, _)| channel);
+    events.digitizers_present.sort_unstable();
+    events.digitizers_present.dedup();
+    let (channel, (time, voltage)): (Vec<_>, (Vec<_>, Vec<_>)) = events
+        .events
+        .into_iter()
+        .map(|(channel, time, voltage)| (channel, (time, voltage)))
+        .unzip();
+
+    let timestamp = metadata.timestamp.into();
+    let metadata_args = create_v2_metadata_args(&timestamp, &metadata);
 
     let message = FrameAssembledEventListMessageArgs {
         metadata: Some(FrameMetadataV2::create(fbb, &metadata_args)),
         time: Some(fbb.create_vector(&time)),
         voltage: Some(fbb.create_vector(&voltage)),
         channel: Some(fbb.create_vector(&channel)),
-        complete: true,
-        digitizers_present: None,
+        complete: events.digitizers_present.len() == num_digitisers,
+        digitizers_present: Some(fbb.create_vector(&events.digitizers_present)),
+        polarity: None,
     };
     let message = FrameAssembledEventListMessage::create(fbb, &message);
     finish_frame_assembled_event_list_message_buffer(fbb, message);
-    Ok(())
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrated::{simulation::Simulation, simulation_elements::overflow::OnOverflow},
+        peak_memory::peak_allocated_during,
+    };
+    use chrono::Utc;
+    use digital_muon_streaming_types::{
+        aev2_frame_assembled_event_v2_generated::root_as_frame_assembled_event_list_message,
+        dat2_digitizer_analog_trace_v2_generated::root_as_digitizer_analog_trace_message,
+        dev2_digitizer_event_v2_generated::root_as_digitizer_event_list_message,
+    };
+
+    const JSON_INPUT: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "manual-digitisers": [
+                {
+                    "id": 3,
+                    "channels": { "min": 0, "max": 1 },
+                    "channel-transformations": [
+                        { "scale": 1, "translate": 0 },
+                        { "scale": 2, "translate": 0 }
+                    ]
+                }
+            ]
+        },
+        "pulses": [{
+                        "pulse-type": "flat",
+                        "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                        "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                        "height": { "random-type": "constant-float", "value": { "const": 50 } }
+                    }],
+        "event-lists": [
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 1 } }
+            }
+        ],
+        "schedule": []
+    }
+    "#;
+
+    fn frame_metadata() -> FrameMetadata {
+        FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 2,
+            protons_per_pulse: 0,
+            running: true,
+            frame_number: 7,
+            veto_flags: 0,
+        }
+    }
+
+    #[test]
+    fn channel_gains_scale_identical_traces() {
+        let simulation: Simulation = serde_json::from_str(JSON_INPUT).unwrap();
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let transformations = simulation
+            .digitiser_config
+            .generate_channel_transformations()
+            .unwrap();
+        let digitisers = simulation.digitiser_config.generate_digitisers().unwrap();
+        assert_eq!(channels, vec![0, 1]);
+        assert_eq!(digitisers[0].channel_indices, vec![0, 1]);
+
+        let event_lists = simulation.generate_event_lists(0, 0, 0, 2).unwrap();
+        let mut cache: VecDeque<_> = simulation.generate_traces(&event_lists, 0).unwrap().into();
+        assert_eq!(
+            cache[0].generate_intensities().unwrap(),
+            cache[1].generate_intensities().unwrap(),
+            "Identical event lists should produce identical traces"
+        );
+
+        let metadata = frame_metadata();
+        let (_, selected) = select_traces(
+            &mut cache,
+            &channels
+                .into_iter()
+                .zip(&transformations)
+                .collect::<Vec<_>>(),
+            SelectionModeOptions::PopFront,
+        )
+        .unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        build_trace_message(&mut fbb, 1_000_000_000, &selected, &metadata, 3).unwrap();
+
+        let message = root_as_digitizer_analog_trace_message(fbb.finished_data()).unwrap();
+        let voltages = message
+            .channels()
+            .unwrap()
+            .iter()
+            .map(|trace| trace.voltage().unwrap().iter().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert!(voltages[0].iter().any(|&v| v > 0));
+        assert!(
+            voltages[0]
+                .iter()
+                .zip(&voltages[1])
+                .all(|(&v1, &v2)| v2 == 2 * v1)
+        );
+    }
+
+    #[test]
+    fn ground_truth_matches_trace_message() {
+        let simulation: Simulation = serde_json::from_str(JSON_INPUT).unwrap();
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let transformations = simulation
+            .digitiser_config
+            .generate_channel_transformations()
+            .unwrap();
+        let frame_number = 7;
+        let event_lists = simulation
+            .generate_event_lists(0, frame_number, 0, 2)
+            .unwrap();
+        let mut cache: VecDeque<_> = simulation
+            .generate_traces(&event_lists, frame_number)
+            .unwrap()
+            .into();
+
+        let metadata = frame_metadata();
+        let (ground_truth, selected) = select_traces(
+            &mut cache,
+            &channels
+                .iter()
+                .copied()
+                .zip(&transformations)
+                .collect::<Vec<_>>(),
+            SelectionModeOptions::PopFront,
+        )
+        .unwrap();
+        let mut trace_fbb = FlatBufferBuilder::new();
+        build_trace_message(&mut trace_fbb, 1_000_000_000, &selected, &metadata, 3).unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        build_trace_ground_truth_message(&mut fbb, &ground_truth, &metadata, 3);
+
+        let trace = root_as_digitizer_analog_trace_message(trace_fbb.finished_data()).unwrap();
+        let message = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+        assert_eq!(message.digitizer_id(), trace.digitizer_id());
+        assert_eq!(
+            format!("{:?}", message.metadata()),
+            format!("{:?}", trace.metadata())
+        );
+
+        // The flat pulse template starts at 10ns with height 50, regardless of the channel gain.
+        assert_eq!(
+            message.channel().unwrap().iter().collect::<Vec<_>>(),
+            channels
+        );
+        assert_eq!(
+            message.channel().unwrap().iter().collect::<Vec<_>>(),
+            trace
+                .channels()
+                .unwrap()
+                .iter()
+                .map(|channel| channel.channel())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            message.time().unwrap().iter().collect::<Vec<_>>(),
+            vec![10, 10]
+        );
+        assert_eq!(
+            message.voltage().unwrap().iter().collect::<Vec<_>>(),
+            vec![50, 50]
+        );
+    }
+
+    /// Returns the JSON of a simulation of one digitiser with four channels, whose traces contain no pulses,
+    /// only Gaussian noise of the given scope about an intensity of 1000.
+    fn noise_only_json(scope: &str) -> String {
+        format!(
+            r#"
+            {{
+                "voltage-transformation": {{"scale": 1, "translate": 1000 }},
+                "time-bins": {{ "const": 2000 }},
+                "sample-rate": {{ "const": 1000000000 }},
+                "digitiser-config": {{
+                    "manual-digitisers": [{{ "id": 3, "channels": {{ "min": 0, "max": 3 }} }}]
+                }},
+                "pulses": [{{
+                    "pulse-type": "flat",
+                    "start":  {{ "random-type": "constant-float", "value": {{ "const": 10 }} }},
+                    "width":  {{ "random-type": "constant-float", "value": {{ "const": 20 }} }},
+                    "height": {{ "random-type": "constant-float", "value": {{ "const": 50 }} }}
+                }}],
+                "event-lists": [
+                    {{
+                        "pulses": [{{"weight": 1, "pulse-index": 0}}],
+                        "noises": [{{
+                            "attributes": {{ "noise-type" : "gaussian", "mean" : {{ "const": 0 }}, "sd" : {{ "const": 50 }} }},
+                            "smoothing-window-length" : {{ "const": 1 }},
+                            "bounds" : {{ "min": {{ "const": 0 }}, "max": {{ "const": 2000 }} }}
+                            {scope}
+                        }}],
+                        "num-pulses": {{ "random-type": "constant-int", "value": {{ "const": 0 }} }}
+                    }}
+                ],
+                "schedule": []
+            }}
+            "#
+        )
+    }
+
+    /// Returns the voltages of each channel of a trace message built from the simulation of [noise_only_json].
+    fn noise_only_voltages(scope: &str) -> Vec<Vec<f64>> {
+        let simulation: Simulation = serde_json::from_str(&noise_only_json(scope)).unwrap();
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let transformations = simulation
+            .digitiser_config
+            .generate_channel_transformations()
+            .unwrap();
+        let event_lists = simulation.generate_event_lists(0, 0, 0, 4).unwrap();
+        let mut cache: VecDeque<_> = simulation.generate_traces(&event_lists, 0).unwrap().into();
+        let (_, selected) = select_traces(
+            &mut cache,
+            &channels
+                .into_iter()
+                .zip(&transformations)
+                .collect::<Vec<_>>(),
+            SelectionModeOptions::PopFront,
+        )
+        .unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        build_trace_message(&mut fbb, 1_000_000_000, &selected, &frame_metadata(), 3).unwrap();
+
+        root_as_digitizer_analog_trace_message(fbb.finished_data())
+            .unwrap()
+            .channels()
+            .unwrap()
+            .iter()
+            .map(|trace| trace.voltage().unwrap().iter().map(|v| v as f64).collect())
+            .collect()
+    }
+
+    /// Returns the Pearson correlation coefficient of `a` and `b`.
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let mean = |x: &[f64]| x.iter().sum::<f64>() / x.len() as f64;
+        let (mean_a, mean_b) = (mean(a), mean(b));
+        let covariance = a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| (a - mean_a) * (b - mean_b))
+            .sum::<f64>();
+        let variance = |x: &[f64], m: f64| x.iter().map(|x| (x - m).powi(2)).sum::<f64>();
+        covariance / (variance(a, mean_a) * variance(b, mean_b)).sqrt()
+    }
+
+    #[test]
+    fn per_digitiser_noise_is_shared_by_every_channel() {
+        let voltages = noise_only_voltages(r#", "scope": "per-digitiser""#);
+        assert_eq!(voltages.len(), 4);
+        for channel in &voltages[1..] {
+            assert!(correlation(&voltages[0], channel) > 0.99);
+        }
+    }
+
+    #[test]
+    fn per_channel_noise_is_uncorrelated() {
+        for scope in ["", r#", "scope": "per-channel""#] {
+            let voltages = noise_only_voltages(scope);
+            for channel in &voltages[1..] {
+                assert!(correlation(&voltages[0], channel).abs() < 0.15);
+            }
+        }
+    }
+
+    /// Returns the JSON of a simulation of `num_digitisers` digitisers of four channels, with `time_bins` time bins,
+    /// whose traces each contain a pulse, per-channel Gaussian noise, and if `per_digitiser` is set, per-digitiser Gaussian noise.
+    fn noisy_json(num_digitisers: usize, time_bins: usize, per_digitiser: bool) -> String {
+        let per_digitiser = if per_digitiser {
+            r#", {
+                "attributes": { "noise-type" : "gaussian", "mean" : { "const": 0 }, "sd" : { "const": 20 } },
+                "smoothing-window-length" : { "const": 4 },
+                "bounds" : { "min": { "const": 0 }, "max": { "const": 1000000 } },
+                "scope": "per-digitiser"
+            }"#
+        } else {
+            ""
+        };
+        format!(
+            r#"
+            {{
+                "voltage-transformation": {{"scale": 1, "translate": 1000 }},
+                "time-bins": {{ "const": {time_bins} }},
+                "sample-rate": {{ "const": 1000000000 }},
+                "digitiser-config": {{
+                    "auto-digitisers": {{
+                        "num-digitisers": {{ "const" : {num_digitisers} }},
+                        "num-channels-per-digitiser": {{ "const" : 4 }}
+                    }}
+                }},
+                "pulses": [{{
+                    "pulse-type": "flat",
+                    "start":  {{ "random-type": "constant-float", "value": {{ "const": 10 }} }},
+                    "width":  {{ "random-type": "constant-float", "value": {{ "const": 20 }} }},
+                    "height": {{ "random-type": "constant-float", "value": {{ "const": 50 }} }}
+                }}],
+                "event-lists": [
+                    {{
+                        "pulses": [{{"weight": 1, "pulse-index": 0}}],
+                        "noises": [{{
+                            "attributes": {{ "noise-type" : "gaussian", "mean" : {{ "const": 0 }}, "sd" : {{ "const": 50 }} }},
+                            "smoothing-window-length" : {{ "const": 1 }},
+                            "bounds" : {{ "min": {{ "const": 0 }}, "max": {{ "const": 1000000 }} }}
+                        }}{per_digitiser}],
+                        "num-pulses": {{ "random-type": "constant-int", "value": {{ "const": 1 }} }}
+                    }}
+                ],
+                "schedule": []
+            }}
+            "#
+        )
+    }
+
+    /// Generates a trace for each channel of `simulation`, and selects those of each digitiser for a trace message.
+    fn pending_trace_messages(simulation: &Simulation) -> Vec<PendingTraceMessage<'_>> {
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let transformations = simulation
+            .digitiser_config
+            .generate_channel_transformations()
+            .unwrap();
+        let event_lists = simulation
+            .generate_event_lists(0, 0, 0, channels.len())
+            .unwrap();
+        let mut cache: VecDeque<_> = simulation.generate_traces(&event_lists, 0).unwrap().into();
+        let metadata = frame_metadata();
+        simulation
+            .digitiser_config
+            .generate_digitisers()
+            .unwrap()
+            .into_iter()
+            .map(|digitiser| {
+                let (ground_truth, selected) = select_traces(
+                    &mut cache,
+                    &digitiser
+                        .channel_indices
+                        .iter()
+                        .map(|&i| (channels[i], &transformations[i]))
+                        .collect::<Vec<_>>(),
+                    SelectionModeOptions::PopFront,
+                )
+                .unwrap();
+                PendingTraceMessage {
+                    sample_rate: 1_000_000_000,
+                    metadata: metadata.clone(),
+                    digitizer_id: digitiser.id,
+                    selected,
+                    ground_truth,
+                    dropout: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Builds the messages of `pending`, and returns the size of each, see [build_trace_messages].
+    /// If `payloads` is present, the payload of each message is pushed to it.
+    fn send_trace_messages(
+        pending: &[PendingTraceMessage<'_>],
+        max_materialised_channels: usize,
+        mut payloads: Option<&mut Vec<Vec<u8>>>,
+    ) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        build_trace_messages::<BuildError>(pending, max_materialised_channels, |_, fbb, _| {
+            sizes.push(fbb.finished_data().len());
+            if let Some(payloads) = payloads.as_mut() {
+                payloads.push(fbb.finished_data().to_vec());
+            }
+            Ok(())
+        })
+        .unwrap();
+        sizes
+    }
+
+    #[test]
+    fn trace_messages_are_identical_however_many_channels_are_materialised() {
+        let simulation: Simulation = serde_json::from_str(&noisy_json(3, 500, true)).unwrap();
+        let pending = pending_trace_messages(&simulation);
+
+        // With no limit, the traces of every channel are generated before any message is built.
+        let mut eager = Vec::new();
+        send_trace_messages(&pending, 0, Some(&mut eager));
+        assert_eq!(eager.len(), 3);
+        let message = root_as_digitizer_analog_trace_message(&eager[0]).unwrap();
+        let channels = message.channels().unwrap();
+        assert_ne!(
+            channels
+                .get(0)
+                .voltage()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            channels
+                .get(1)
+                .voltage()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            "The channels' traces should have independent noise"
+        );
+
+        // The noise of each trace is sampled from its seeds, so is the same each time the trace is generated.
+        for max_materialised_channels in [1, 2, 5] {
+            let mut payloads = Vec::new();
+            send_trace_messages(&pending, max_materialised_channels, Some(&mut payloads));
+            assert_eq!(payloads, eager, "{max_materialised_channels}");
+        }
+    }
+
+    #[test]
+    fn trace_messages_take_less_peak_memory() {
+        const NUM_DIGITISERS: usize = 8;
+        const TIME_BINS: usize = 50_000;
+        let simulation: Simulation =
+            serde_json::from_str(&noisy_json(NUM_DIGITISERS, TIME_BINS, false)).unwrap();
+        let pending = pending_trace_messages(&simulation);
+        let intensities_size = NUM_DIGITISERS * 4 * TIME_BINS * size_of::<Intensity>();
+
+        // The memory of each thread is tracked separately, so the messages are built on a single thread.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let (eager_sizes, eager_peak) =
+            pool.install(|| peak_allocated_during(|| send_trace_messages(&pending, 0, None)));
+        let (sizes, peak) =
+            pool.install(|| peak_allocated_during(|| send_trace_messages(&pending, 1, None)));
+        assert_eq!(sizes, eager_sizes);
+
+        // Generating every trace first holds the intensities of every channel at once.
+        assert!(eager_peak > intensities_size);
+        // Whereas one trace, and one message, of a quarter of the intensities, is held at once.
+        assert!(
+            peak < intensities_size / 2,
+            "{peak} bytes with one materialised channel, {eager_peak} bytes with every channel materialised, for {intensities_size} bytes of intensities"
+        );
+    }
+
+    /// Builds a trace message from the simulation of [JSON_INPUT], with the given `on-overflow`,
+    /// and a pulse so high that it does not fit the [Intensity] type.
+    /// # Returns
+    /// The result of building the message, and the voltages of its channels if it was built.
+    fn build_overflowing_trace_message(
+        on_overflow: &str,
+    ) -> (Result<ClippingCounts, BuildError>, Vec<Vec<Intensity>>) {
+        let json = JSON_INPUT
+            .replacen('{', &format!(r#"{{ "on-overflow": "{on_overflow}","#), 1)
+            .replace(r#""const": 50"#, r#""const": 100000"#);
+        let simulation: Simulation = serde_json::from_str(&json).unwrap();
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let transformations = simulation
+            .digitiser_config
+            .generate_channel_transformations()
+            .unwrap();
+        let event_lists = simulation.generate_event_lists(0, 0, 0, 2).unwrap();
+        let mut cache: VecDeque<_> = simulation.generate_traces(&event_lists, 0).unwrap().into();
+        let (_, selected) = select_traces(
+            &mut cache,
+            &channels
+                .into_iter()
+                .zip(&transformations)
+                .collect::<Vec<_>>(),
+            SelectionModeOptions::PopFront,
+        )
+        .unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        // The channels are generated one at a time, so an error is always that of the first channel.
+        let result = build_trace_message(&mut fbb, 1_000_000_000, &selected, &frame_metadata(), 3);
+        let voltages = match result {
+            Ok(_) => root_as_digitizer_analog_trace_message(fbb.finished_data())
+                .unwrap()
+                .channels()
+                .unwrap()
+                .iter()
+                .map(|trace| trace.voltage().unwrap().iter().collect())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        (result, voltages)
+    }
+
+    #[test]
+    fn overflowing_values_saturate_by_default() {
+        let simulation: Simulation = serde_json::from_str(JSON_INPUT).unwrap();
+        assert_eq!(simulation.on_overflow, OnOverflow::Saturate);
+
+        let (result, voltages) = build_overflowing_trace_message("saturate");
+        let clipping = result.unwrap();
+        // Both channels hold the same pulse, channel 1 is clipped again after its gain, but each sample is counted once.
+        let pulse_samples = voltages[0].iter().filter(|&&v| v > 0).count();
+        assert!(pulse_samples > 0);
+        for channel in &voltages {
+            assert!(channel.iter().all(|&v| v == 0 || v == Intensity::MAX));
+        }
+        assert_eq!(clipping.total(), 2 * pulse_samples);
+        assert_eq!(
+            clipping.to_string(),
+            format!(
+                "{} samples clipped: {pulse_samples} on channel 0, {pulse_samples} on channel 1",
+                2 * pulse_samples
+            )
+        );
+    }
+
+    #[test]
+    fn overflowing_values_wrap() {
+        let (result, voltages) = build_overflowing_trace_message("wrap");
+        let clipping = result.unwrap();
+        // 100000 wraps to 34464, which channel 1 doubles to 68928, and wraps again to 3392.
+        assert!(voltages[0].iter().all(|&v| v == 0 || v == 34464));
+        assert!(voltages[1].iter().all(|&v| v == 0 || v == 3392));
+        let pulse_samples = voltages[0].iter().filter(|&&v| v > 0).count();
+        assert!(pulse_samples > 0);
+        assert_eq!(clipping.total(), 2 * pulse_samples);
+    }
+
+    #[test]
+    fn overflowing_values_are_errors() {
+        let (result, _) = build_overflowing_trace_message("error");
+        match result {
+            Err(BuildError::Trace {
+                channel,
+                source: TraceError::Overflow(overflow),
+            }) => {
+                // The flat pulse template starts at 10ns.
+                assert_eq!(channel, 0);
+                assert_eq!(
+                    overflow.to_string(),
+                    "Value 100000 at time bin 10 does not fit the Intensity type"
+                );
+            }
+            other => panic!("Expected an overflow error, found {other:?}"),
+        }
+    }
+
+    const AGGREGATED_JSON_INPUT: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "manual-aggregated-frame": { "channels": [5, 2, 9] }
+        },
+        "pulses": [{
+                        "pulse-type": "flat",
+                        "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                        "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                        "height": { "random-type": "constant-float", "value": { "const": 50 } }
+                    }],
+        "event-lists": [
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 2 } }
+            }
+        ],
+        "schedule": []
+    }
+    "#;
+
+    #[test]
+    fn aggregated_frame_event_list_covers_all_channels() {
+        let simulation: Simulation = serde_json::from_str(AGGREGATED_JSON_INPUT).unwrap();
+        simulation.validate().unwrap();
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        assert!(
+            simulation
+                .digitiser_config
+                .generate_digitisers()
+                .unwrap()
+                .is_empty()
+        );
+
+        let event_lists = simulation.generate_event_lists(0, 7, 0, 3).unwrap();
+        let mut cache: VecDeque<_> = event_lists.into();
+        let metadata = frame_metadata();
+        let fbb = build_aggregated_event_list_message(
+            &mut cache,
+            &metadata,
+            &channels,
+            &SourceOptions::SelectFromCache(SelectionModeOptions::PopFront),
+        )
+        .unwrap();
+        assert!(cache.is_empty());
+
+        let message = root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        assert!(message.complete());
+        assert_eq!(message.metadata().frame_number(), metadata.frame_number);
+        assert_eq!(message.metadata().period_number(), metadata.period_number);
+
+        // Each channel has two events, in the order of the digitiser config's channels.
+        assert_eq!(
+            message.channel().unwrap().iter().collect::<Vec<_>>(),
+            vec![5, 5, 2, 2, 9, 9]
+        );
+        assert_eq!(
+            message.time().unwrap().iter().collect::<Vec<_>>(),
+            vec![10; 6]
+        );
+        assert_eq!(
+            message.voltage().unwrap().iter().collect::<Vec<_>>(),
+            vec![50; 6]
+        );
+    }
+
+    /// Returns the JSON of a simulation of an aggregated frame of `num_channels` channels,
+    /// each of whose event lists has up to `max_pulses` pulses, of random times and heights.
+    fn random_aggregated_json(num_channels: usize, max_pulses: usize) -> String {
+        let channels = (0..num_channels)
+            .map(|channel| channel.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"
+            {{
+                "voltage-transformation": {{"scale": 1, "translate": 0 }},
+                "time-bins": {{ "const": 30000 }},
+                "sample-rate": {{ "const": 1000000000 }},
+                "digitiser-config": {{
+                    "manual-aggregated-frame": {{ "channels": [{channels}] }}
+                }},
+                "pulses": [{{
+                    "pulse-type": "flat",
+                    "start":  {{ "random-type": "uniform-float", "min": {{ "const": 0 }}, "max": {{ "const": 29000 }} }},
+                    "width":  {{ "random-type": "constant-float", "value": {{ "const": 20 }} }},
+                    "height": {{ "random-type": "uniform-float", "min": {{ "const": 10 }}, "max": {{ "const": 5000 }} }}
+                }}],
+                "event-lists": [
+                    {{
+                        "pulses": [{{"weight": 1, "pulse-index": 0}}],
+                        "noises": [],
+                        "num-pulses": {{ "random-type": "uniform-int", "min": {{ "const": 0 }}, "max": {{ "const": {max_pulses} }} }}
+                    }}
+                ],
+                "schedule": []
+            }}
+            "#
+        )
+    }
+
+    /// Builds the aggregated frame event list message as it was built before its events were written directly into the message,
+    /// by collecting the events of every channel, and only then writing them into a builder which is grown as required.
+    fn build_collected_aggregated_event_list_message(
+        cache: &mut VecDeque<EventList<'_>>,
+        metadata: &FrameMetadata,
+        channels: &[Channel],
+    ) -> FlatBufferBuilder<'static> {
+        let mut fbb = FlatBufferBuilder::new();
+        let mut time = Vec::<Time>::new();
+        let mut voltage = Vec::<Intensity>::new();
+        let mut channel = Vec::<Channel>::new();
+        let event_lists = cache
+            .extract(SelectionModeOptions::PopFront, channels.len())
+            .unwrap();
+        for (&c, event_list) in channels.iter().zip(event_lists) {
+            for pulse in &event_list.pulses {
+                time.push(pulse.time());
+                voltage.push(pulse.intensity());
+                channel.push(c);
+            }
+        }
+        cache
+            .finish(SelectionModeOptions::PopFront, channels.len())
+            .unwrap();
+
+        let timestamp = metadata.timestamp.into();
+        let metadata_args = create_v2_metadata_args(&timestamp, metadata);
+        let message = FrameAssembledEventListMessageArgs {
+            metadata: Some(FrameMetadataV2::create(&mut fbb, &metadata_args)),
+            time: Some(fbb.create_vector(&time)),
+            voltage: Some(fbb.create_vector(&voltage)),
+            channel: Some(fbb.create_vector(&channel)),
+            complete: true,
+            digitizers_present: None,
+            polarity: None,
+        };
+        let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        fbb
+    }
+
+    /// Returns the channels and the event lists of a frame of the simulation of [random_aggregated_json].
+    fn random_aggregated_event_lists(
+        simulation: &Simulation,
+        num_channels: usize,
+    ) -> (Vec<Channel>, Vec<EventList<'_>>) {
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let event_lists = simulation
+            .generate_event_lists(0, 7, 0, num_channels)
+            .unwrap();
+        (channels, event_lists)
+    }
+
+    #[test]
+    fn aggregated_event_list_is_identical_to_collected() {
+        let simulation: Simulation = serde_json::from_str(&random_aggregated_json(5, 20)).unwrap();
+        simulation.validate().unwrap();
+        let metadata = frame_metadata();
+        // The same event lists are used by both, so the messages are identical however the pulses were sampled.
+        for _ in 0..10 {
+            let (channels, event_lists) = random_aggregated_event_lists(&simulation, 5);
+            let mut collected_cache: VecDeque<_> = event_lists.clone().into();
+            let collected = build_collected_aggregated_event_list_message(
+                &mut collected_cache,
+                &metadata,
+                &channels,
+            );
+
+            let mut cache: VecDeque<_> = event_lists.into();
+            let fbb = build_aggregated_event_list_message(
+                &mut cache,
+                &metadata,
+                &channels,
+                &SourceOptions::SelectFromCache(SelectionModeOptions::PopFront),
+            )
+            .unwrap();
+            assert!(cache.is_empty());
+            assert_eq!(fbb.finished_data(), collected.finished_data());
+        }
+
+        // Without a source, the message is empty, and the cache is untouched.
+        let (channels, event_lists) = random_aggregated_event_lists(&simulation, 5);
+        let mut cache: VecDeque<_> = event_lists.into();
+        let fbb = build_aggregated_event_list_message(
+            &mut cache,
+            &metadata,
+            &channels,
+            &SourceOptions::NoSource,
+        )
+        .unwrap();
+        assert_eq!(cache.len(), 5);
+        let message = root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        assert!(message.time().unwrap().is_empty());
+        assert!(message.channel().unwrap().is_empty());
+    }
+
+    #[test]
+    fn aggregated_event_list_takes_less_peak_memory() {
+        const NUM_CHANNELS: usize = 512;
+        let simulation: Simulation =
+            serde_json::from_str(&random_aggregated_json(NUM_CHANNELS, 400)).unwrap();
+        let metadata = frame_metadata();
+        let (channels, event_lists) = random_aggregated_event_lists(&simulation, NUM_CHANNELS);
+        let num_events = event_lists
+            .iter()
+            .map(|event_list| event_list.pulses.len())
+            .sum::<usize>();
+        let mut collected_cache: VecDeque<_> = event_lists.clone().into();
+        let mut cache: VecDeque<_> = event_lists.into();
+
+        let (collected, collected_peak) = peak_allocated_during(|| {
+            build_collected_aggregated_event_list_message(
+                &mut collected_cache,
+                &metadata,
+                &channels,
+            )
+        });
+        let (fbb, peak) = peak_allocated_during(|| {
+            build_aggregated_event_list_message(
+                &mut cache,
+                &metadata,
+                &channels,
+                &SourceOptions::SelectFromCache(SelectionModeOptions::PopFront),
+            )
+            .unwrap()
+        });
+        assert_eq!(fbb.finished_data(), collected.finished_data());
+
+        // The builder is never grown, so the message is the only copy of the events held.
+        let size = fbb.finished_data().len();
+        assert!(peak <= estimate_aggregated_event_list_size(num_events) + 64 * NUM_CHANNELS);
+        // Collecting the events, and growing the builder, takes more than twice the size of the message.
+        assert!(
+            collected_peak > 2 * size,
+            "{collected_peak} bytes collected, {peak} bytes streamed, for {size} byte message"
+        );
+        assert!(peak < collected_peak / 2);
+    }
+
+    #[test]
+    fn no_frame_trace_events_message_without_trace_messages() {
+        let mut fbb = FlatBufferBuilder::new();
+        assert!(!build_frame_trace_events_message(
+            &mut fbb,
+            FrameTraceEvents::default(),
+            2
+        ));
+    }
 }
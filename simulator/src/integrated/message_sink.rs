@@ -0,0 +1,487 @@
+//! Sends the messages of a simulation, either to Kafka or, in a dry run, to files.
+//!
+//! The simulation engine produces every message through a [MessageSink], so the messages generated
+//! are identical whichever sink is used.
+use crate::integrated::simulation_engine::pacing::QueueFullCounter;
+use chrono::{DateTime, Utc};
+use digital_muon_common::{DigitizerId, FrameNumber, tracer::FutureRecordTracerExt};
+use digital_muon_streaming_types::dat2_digitizer_analog_trace_v2_generated::digitizer_analog_trace_message_buffer_has_identifier;
+use rdkafka::{
+    Message,
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+};
+use serde::{Deserialize, Serialize};
+use simulator::trace_archive::{TraceArchiveError, TraceArchiveWriter};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tracing::{Span, debug, debug_span, error, warn};
+
+/// The name of the file, in the dry run directory, listing the messages written.
+pub(crate) const INDEX_FILE: &str = "index.jsonl";
+
+#[derive(Debug, Error)]
+pub(crate) enum SinkError {
+    #[error("File Error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Json Error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Trace Archive Error: {0}")]
+    TraceArchive(#[from] TraceArchiveError),
+}
+
+/// A message to be produced, with the frame and digitiser it belongs to, if any.
+pub(crate) struct SinkMessage<'a> {
+    pub(crate) topic: &'a str,
+    pub(crate) key: &'static str,
+    pub(crate) payload: Vec<u8>,
+    pub(crate) frame_number: Option<FrameNumber>,
+    pub(crate) digitizer_id: Option<DigitizerId>,
+}
+
+impl<'a> SinkMessage<'a> {
+    pub(crate) fn new(topic: &'a str, key: &'static str, payload: Vec<u8>) -> Self {
+        Self {
+            topic,
+            key,
+            payload,
+            frame_number: None,
+            digitizer_id: None,
+        }
+    }
+
+    /// Sets the frame to which the message belongs.
+    pub(crate) fn with_frame_number(mut self, frame_number: FrameNumber) -> Self {
+        self.frame_number = Some(frame_number);
+        self
+    }
+
+    /// Sets the digitiser from which the message is sent.
+    pub(crate) fn with_digitizer_id(mut self, digitizer_id: DigitizerId) -> Self {
+        self.digitizer_id = Some(digitizer_id);
+        self
+    }
+}
+
+/// Implemented for each destination of the messages of a simulation.
+pub(crate) trait MessageSink {
+    /// Produces `message`. Messages to the same topic are produced in the order sent.
+    fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError>;
+
+    /// Ensures every message sent so far has been written, if the sink buffers them.
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Produces each message to Kafka, in a task spawned on a [JoinSet], which the caller should join.
+pub(crate) struct KafkaSink<'a> {
+    use_otel: bool,
+    producer: &'a FutureProducer,
+    kafka_producer_thread_set: &'a mut JoinSet<()>,
+    /// Counts the messages which could not be sent because the producer's queue was full.
+    queue_full: QueueFullCounter,
+}
+
+impl<'a> KafkaSink<'a> {
+    pub(crate) fn new(
+        use_otel: bool,
+        producer: &'a FutureProducer,
+        kafka_producer_thread_set: &'a mut JoinSet<()>,
+        queue_full: QueueFullCounter,
+    ) -> Self {
+        Self {
+            use_otel,
+            producer,
+            kafka_producer_thread_set,
+            queue_full,
+        }
+    }
+}
+
+impl MessageSink for KafkaSink<'_> {
+    fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError> {
+        let send_args = SendMessageArgs {
+            use_otel: self.use_otel,
+            producer: self.producer.to_owned(),
+            payload: message.payload,
+            topic: message.topic.to_owned(),
+            span: tracing::Span::current(),
+            key: message.key,
+        };
+        self.kafka_producer_thread_set
+            .spawn(send_message(send_args, self.queue_full.clone()));
+        Ok(())
+    }
+}
+
+struct SendMessageArgs {
+    use_otel: bool,
+    producer: FutureProducer,
+    payload: Vec<u8>,
+    topic: String,
+    span: Span,
+    key: &'static str,
+}
+
+/// Sends the message, recording to `queue_full` if it could not be queued because the producer's queue was full.
+#[tracing::instrument(skip_all)]
+async fn send_message(args: SendMessageArgs, queue_full: QueueFullCounter) {
+    let span = debug_span!(parent: &args.span, "Send Message Thread");
+    let _guard = span.enter();
+
+    let future_record = FutureRecord::to(&args.topic)
+        .payload(&args.payload)
+        .conditional_inject_span_into_headers(args.use_otel, &args.span)
+        .key(args.key);
+
+    let timeout = Timeout::After(Duration::from_millis(100));
+    match args.producer.send(future_record, timeout).await {
+        Ok(r) => debug!("Delivery: {:?}", r),
+        Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+            warn!("Delivery failed: producer queue is full");
+            queue_full.record();
+        }
+        Err(e) => error!(
+            "Delivery failed: {:?}. Message Size: {}",
+            e.0,
+            e.1.payload().unwrap_or(&[]).len()
+        ),
+    };
+}
+
+/// A line of the index file of a dry run, describing a message written.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    pub(crate) topic: String,
+    pub(crate) key: String,
+    /// When the message was written, as Kafka would timestamp it on production.
+    pub(crate) timestamp: DateTime<Utc>,
+    /// The name of the file containing the payload, relative to the dry run directory.
+    pub(crate) filename: String,
+}
+
+/// Writes the payload of each message to its own file in a directory, in place of producing it,
+/// and appends an [IndexEntry] describing it to the directory's [INDEX_FILE].
+///
+/// The files are named `<topic>-<sequence>[-f<frame number>][-d<digitiser id>].bin`, where the sequence counts
+/// every message written, so the files of each topic sort in the order their messages were sent.
+pub(crate) struct FileSink {
+    directory: PathBuf,
+    /// The number of messages written so far.
+    sequence: usize,
+    index: BufWriter<File>,
+}
+
+impl FileSink {
+    /// Creates `directory` if necessary, and an empty index file within it.
+    pub(crate) fn new(directory: &Path) -> Result<Self, SinkError> {
+        fs::create_dir_all(directory)?;
+        Ok(Self {
+            directory: directory.to_owned(),
+            sequence: 0,
+            index: BufWriter::new(File::create(directory.join(INDEX_FILE))?),
+        })
+    }
+
+    fn filename(&self, message: &SinkMessage<'_>) -> String {
+        let mut filename = format!("{}-{:08}", message.topic, self.sequence);
+        if let Some(frame_number) = message.frame_number {
+            filename.push_str(&format!("-f{frame_number}"));
+        }
+        if let Some(digitizer_id) = message.digitizer_id {
+            filename.push_str(&format!("-d{digitizer_id}"));
+        }
+        filename + ".bin"
+    }
+}
+
+impl MessageSink for FileSink {
+    fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError> {
+        let filename = self.filename(&message);
+        fs::write(self.directory.join(&filename), &message.payload)?;
+        let entry = IndexEntry {
+            topic: message.topic.to_owned(),
+            key: message.key.to_owned(),
+            timestamp: Utc::now(),
+            filename,
+        };
+        serde_json::to_writer(&mut self.index, &entry)?;
+        writeln!(self.index)?;
+        self.sequence += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(self.index.flush()?)
+    }
+}
+
+/// Passes each message to another sink, and also appends each digitiser trace message to a trace archive.
+pub(crate) struct ArchivingSink<'a, W: Write> {
+    sink: &'a mut dyn MessageSink,
+    archive: TraceArchiveWriter<W>,
+}
+
+impl<'a, W: Write> ArchivingSink<'a, W> {
+    pub(crate) fn new(sink: &'a mut dyn MessageSink, archive: TraceArchiveWriter<W>) -> Self {
+        Self { sink, archive }
+    }
+}
+
+impl<W: Write> MessageSink for ArchivingSink<'_, W> {
+    fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError> {
+        if digitizer_analog_trace_message_buffer_has_identifier(&message.payload) {
+            self.archive.write_record(&message.payload)?;
+        }
+        self.sink.send(message)
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.archive.flush()?;
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::{
+        Topics, open_trace_archive,
+        simulation::Simulation,
+        simulation_config_hash,
+        simulation_elements::{fault_injection::FaultInjector, overflow::ClippingCounts},
+        simulation_engine::{
+            SimulationEngine, SimulationEngineExternals,
+            pacing::{Pacer, PacingOptions},
+            run_schedule,
+            shard::Shard,
+            wait::MessageWaiter,
+        },
+    };
+    use digital_muon_streaming_types::dat2_digitizer_analog_trace_v2_generated::root_as_digitizer_analog_trace_message;
+    use simulator::trace_archive::{ArchiveEntry, read_trace_archive};
+    use std::io::{BufRead, BufReader};
+
+    const DRY_RUN_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 2 },
+                "num-channels-per-digitiser": { "const" : 2 }
+            }
+        },
+        "pulses": [{
+                        "pulse-type": "flat",
+                        "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                        "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                        "height": { "random-type": "constant-float", "value": { "const": 50 } }
+                    }],
+        "event-lists": [
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 1 } }
+            }
+        ],
+        "schedule": [
+            { "send-run-start": {
+                    "run-command": "SendRunStart",
+                    "name": { "text": "Run1" },
+                    "filename": { "text": "run1.nxs" },
+                    "instrument": { "text": "MuSR" }
+                }
+            },
+            { "frame-loop": {
+                    "start": { "const": 0 },
+                    "end": { "const": 1 },
+                    "schedule": [
+                        { "digitiser-loop": {
+                                "start": { "const": 0 },
+                                "end": { "const": 1 },
+                                "schedule": [
+                                    { "generate-trace": { "event-list-index": 0, "repeat": 2 } },
+                                    { "send-digitiser-trace": "pop-front" }
+                                ]
+                            }
+                        }
+                    ]
+                }
+            },
+            { "send-run-stop": { "run-command": "SendRunStop", "name": { "text": "Run1" } } }
+        ]
+    }
+    "#;
+
+    /// Runs the schedule of `simulation`, writing its messages to `directory`.
+    fn run_dry(simulation: &Simulation, directory: &Path) {
+        run_into_sink(simulation, &mut FileSink::new(directory).unwrap());
+    }
+
+    /// Runs the schedule of `simulation`, sending its messages to `sink`.
+    fn run_into_sink(simulation: &Simulation, sink: &mut dyn MessageSink) {
+        let queue_full = QueueFullCounter::default();
+        let mut engine = SimulationEngine::new(
+            SimulationEngineExternals {
+                sink,
+                topics: Topics {
+                    traces: "traces",
+                    events: "events",
+                    frame_events: "frame_events",
+                    run_controls: "run_controls",
+                    runlog: "runlog",
+                    selog: "selog",
+                    alarm: "alarm",
+                    ground_truth: None,
+                },
+                ground_truth: None,
+                shard: Shard::default(),
+                max_materialised_channels: 8,
+                fault_injector: FaultInjector::new(None),
+                clipping: ClippingCounts::default(),
+                queue_full: queue_full.clone(),
+                pacer: Pacer::new(&PacingOptions::default(), queue_full),
+                message_waiter: MessageWaiter::new(None),
+            },
+            simulation,
+        )
+        .unwrap();
+        run_schedule(&mut engine).unwrap();
+        engine.flush_sink().unwrap();
+    }
+
+    #[test]
+    fn dry_run_writes_messages_to_files() {
+        let directory =
+            std::env::temp_dir().join(format!("simulator-dry-run-test-{}", std::process::id()));
+        let simulation: Simulation = serde_json::from_str(DRY_RUN_SIMULATION).unwrap();
+        run_dry(&simulation, &directory);
+
+        let expected = [
+            ("run_controls", "run_controls-00000000.bin"),
+            ("traces", "traces-00000001-f0-d0.bin"),
+            ("traces", "traces-00000002-f0-d1.bin"),
+            ("traces", "traces-00000003-f1-d0.bin"),
+            ("traces", "traces-00000004-f1-d1.bin"),
+            ("run_controls", "run_controls-00000005.bin"),
+        ];
+        let mut files = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        let mut expected_files = expected
+            .iter()
+            .map(|(_, filename)| filename.to_string())
+            .chain([INDEX_FILE.to_owned()])
+            .collect::<Vec<_>>();
+        expected_files.sort();
+        assert_eq!(files, expected_files);
+
+        // The index lists every message in the order it was sent.
+        let index = BufReader::new(File::open(directory.join(INDEX_FILE)).unwrap())
+            .lines()
+            .map(|line| serde_json::from_str::<IndexEntry>(&line.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(index.len(), expected.len());
+        for (entry, (topic, filename)) in index.iter().zip(expected) {
+            assert_eq!(entry.topic, topic);
+            assert_eq!(entry.filename, filename);
+        }
+        assert!(index.is_sorted_by_key(|entry| entry.timestamp));
+        assert_eq!(index[0].key, "Simulated Run Start");
+        assert_eq!(index[5].key, "Simulated Run Stop");
+
+        // The trace payloads are the messages which would have been produced.
+        for (frame_number, digitizer_id, entry) in [
+            (0, 0, &index[1]),
+            (0, 1, &index[2]),
+            (1, 0, &index[3]),
+            (1, 1, &index[4]),
+        ] {
+            assert_eq!(entry.key, "Simulated Trace");
+            let payload = fs::read(directory.join(&entry.filename)).unwrap();
+            let message = root_as_digitizer_analog_trace_message(&payload).unwrap();
+            assert_eq!(message.metadata().frame_number(), frame_number);
+            assert_eq!(message.digitizer_id(), digitizer_id);
+            assert_eq!(message.channels().unwrap().len(), 2);
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn trace_messages_are_archived() {
+        let directory = std::env::temp_dir().join(format!(
+            "simulator-trace-archive-test-{}",
+            std::process::id()
+        ));
+        let archive_path = directory.with_extension("dmptrace");
+        let simulation_path = directory.with_extension("json");
+        fs::write(&simulation_path, DRY_RUN_SIMULATION).unwrap();
+        let simulation: Simulation = serde_json::from_str(DRY_RUN_SIMULATION).unwrap();
+        {
+            let mut file_sink = FileSink::new(&directory).unwrap();
+            let mut sink = ArchivingSink::new(
+                &mut file_sink,
+                open_trace_archive(&archive_path, &simulation_path, Shard::default()).unwrap(),
+            );
+            run_into_sink(&simulation, &mut sink);
+        }
+
+        // Only the trace messages are archived, following the header of the run,
+        // which identifies the simulator and the settings of the simulation.
+        let entries = read_trace_archive(&archive_path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 5);
+        let ArchiveEntry::Header(header) = &entries[0] else {
+            panic!("Expected a header, found {:?}", entries[0]);
+        };
+        assert_eq!(header.simulator_version, digital_muon_common::version!());
+        assert_eq!(
+            header.config_hash,
+            simulation_config_hash(
+                serde_json::from_str(DRY_RUN_SIMULATION).unwrap(),
+                Shard::default()
+            )
+        );
+        for (frame_number, digitizer_id, entry) in [
+            (0, 0, &entries[1]),
+            (0, 1, &entries[2]),
+            (1, 0, &entries[3]),
+            (1, 1, &entries[4]),
+        ] {
+            let ArchiveEntry::Record(payload) = entry else {
+                panic!("Expected a record, found {entry:?}");
+            };
+            let message = root_as_digitizer_analog_trace_message(payload).unwrap();
+            assert_eq!(message.metadata().frame_number(), frame_number);
+            assert_eq!(message.digitizer_id(), digitizer_id);
+        }
+
+        // The messages are still passed to the wrapped sink.
+        assert_eq!(
+            fs::read_to_string(directory.join(INDEX_FILE))
+                .unwrap()
+                .lines()
+                .count(),
+            6
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_file(&archive_path).unwrap();
+        fs::remove_file(&simulation_path).unwrap();
+    }
+}
@@ -1,20 +1,47 @@
 pub(crate) mod active_pulses;
 pub(crate) mod build_messages;
+pub(crate) mod message_sink;
+pub(crate) mod playlist;
 pub(crate) mod send_messages;
 pub(crate) mod simulation;
 pub(crate) mod simulation_elements;
 pub(crate) mod simulation_engine;
+pub(crate) mod validation;
 
-use crate::Defined;
-use rdkafka::producer::FutureProducer;
+use crate::{
+    Defined,
+    message_size::{MessageTooLarge, check_trace_message_size},
+};
+use message_sink::{ArchivingSink, FileSink, KafkaSink, MessageSink, SinkError};
+use playlist::Playlist;
+use rdkafka::{ClientConfig, producer::FutureProducer};
 use simulation::{Simulation, SimulationError};
+use simulation_elements::{
+    fault_injection::FaultInjector,
+    ground_truth::{GroundTruthError, GroundTruthWriter},
+    metadata_source::MetadataSourceError,
+    pulse_shapes::PulseShapesError,
+};
 use simulation_engine::{
-    SimulationEngine, SimulationEngineExternals, engine::SimulationEngineError, run_schedule,
+    SimulationEngine, SimulationEngineExternals,
+    control::ControlSocket,
+    engine::{SimulationEngineError, SimulationEngineState},
+    pacing::{Pacer, QueueFullCounter},
+    run_schedule,
+    shard::Shard,
+    wait::MessageWaiter,
+};
+use simulator::trace_archive::{ArchiveHeader, TraceArchiveError, TraceArchiveWriter, config_hash};
+use std::{
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    path::Path,
+    time::Duration,
 };
-use std::fs::File;
 use thiserror::Error;
 use tokio::task::JoinSet;
-use tracing::{error, trace};
+use tracing::{error, info, trace};
+use validation::ValidationErrors;
 
 pub(crate) struct Topics<'a> {
     pub(crate) traces: &'a str,
@@ -24,33 +51,189 @@ pub(crate) struct Topics<'a> {
     pub(crate) runlog: &'a str,
     pub(crate) selog: &'a str,
     pub(crate) alarm: &'a str,
+    /// If present, the ground truth of each trace message is sent here.
+    pub(crate) ground_truth: Option<&'a str>,
 }
 
 #[derive(Debug, Error)]
 pub(crate) enum ConfiguredError {
     #[error("Simulation Error: {0}")]
     Simulation(#[from] SimulationError),
+    #[error("Invalid Simulation: {0}")]
+    Validation(#[from] ValidationErrors),
     #[error("Simulation Engine Error: {0}")]
     SimulationEngine(#[from] SimulationEngineError),
     #[error("Json Error: {0}")]
     Json(#[from] serde_json::Error),
     #[error("File Error: {0}")]
     IO(#[from] std::io::Error),
+    #[error("Ground Truth Error: {0}")]
+    GroundTruth(#[from] GroundTruthError),
+    #[error("Metadata Source Error: {0}")]
+    MetadataSource(#[from] MetadataSourceError),
+    #[error("Pulse Shapes Error: {0}")]
+    PulseShapes(#[from] PulseShapesError),
+    #[error("Schedule Error: {0}")]
+    Schedule(SimulationEngineError),
+    #[error("Message Size Error: {0}")]
+    MessageSize(#[from] MessageTooLarge),
+    #[error("Sink Error: {0}")]
+    Sink(#[from] SinkError),
+    #[error("Trace Archive Error: {0}")]
+    TraceArchive(#[from] TraceArchiveError),
+    #[error("Playlist Error: {0} of {1} runs failed")]
+    PlaylistFailures(usize, usize),
 }
 
 #[tracing::instrument(skip_all, err(level = "error"))]
 pub(crate) async fn run_configured_simulation(
     use_otel: bool,
     producer: &FutureProducer,
+    consumer_config: &ClientConfig,
     defined: Defined,
+    message_max_bytes: Option<usize>,
 ) -> Result<(), ConfiguredError> {
-    let simulation: Simulation = serde_json::from_reader(File::open(defined.file)?)?;
+    // Each simulation appends to the ground truth file, so it is emptied before the first.
+    if let Some(path) = &defined.ground_truth_file {
+        File::create(path)?;
+    }
+    // Likewise each simulation appends its traces to the trace archive.
+    if let Some(path) = &defined.trace_archive {
+        File::create(path)?;
+    }
+    // In a dry run, the messages of every simulation are written to the same directory, and listed by the same index.
+    let mut file_sink = defined
+        .dry_run_dir
+        .as_deref()
+        .map(FileSink::new)
+        .transpose()?;
+    // The control socket is shared by every simulation, so that a client may stay connected between them.
+    let control = defined
+        .control_socket
+        .map(ControlSocket::bind)
+        .transpose()?
+        .map(|control| match defined.control_linger_s {
+            Some(linger_s) => control.with_linger(Duration::from_secs(linger_s)),
+            None => control,
+        });
+    if let Some(path) = &defined.playlist {
+        let playlist = Playlist::load(path)?;
+        let outcome = playlist
+            .run(
+                use_otel,
+                producer,
+                consumer_config,
+                file_sink.as_mut(),
+                control.as_ref(),
+                &defined,
+                message_max_bytes,
+            )
+            .await;
+        if outcome.failures > 0 {
+            return Err(ConfiguredError::PlaylistFailures(
+                outcome.failures,
+                outcome.runs,
+            ));
+        }
+    } else if let Some(file) = &defined.file {
+        match run_simulation(
+            use_otel,
+            producer,
+            consumer_config,
+            file_sink.as_mut(),
+            control.as_ref(),
+            &defined,
+            file,
+            SimulationEngineState::default(),
+            message_max_bytes,
+        )
+        .await
+        {
+            Err(ConfiguredError::Schedule(e)) => error!("Critical Error: {e}"),
+            result => {
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the simulation defined by a json file.
+/// # Parameters
+/// - consumer_config: the configuration of the consumers of the topics waited on by the schedule, see [MessageWaiter].
+/// - file_sink: if present, the messages are written to this in place of being produced by `producer`, see [Defined::dry_run_dir].
+/// - control: if present, the actions received on this are applied whilst the schedule is run, see [Defined::control_socket].
+/// - file: the json file of the simulation.
+/// - state: the state from which the simulation's schedule is run.
+/// - message_max_bytes: if present, the simulation fails before it is run if its trace messages could be larger.
+///
+/// # Returns
+/// The state at the end of the schedule.
+#[tracing::instrument(skip_all, fields(file = %file.display()))]
+pub(crate) async fn run_simulation(
+    use_otel: bool,
+    producer: &FutureProducer,
+    consumer_config: &ClientConfig,
+    file_sink: Option<&mut FileSink>,
+    control: Option<&ControlSocket>,
+    defined: &Defined,
+    file: &Path,
+    state: SimulationEngineState,
+    message_max_bytes: Option<usize>,
+) -> Result<SimulationEngineState, ConfiguredError> {
+    let mut simulation: Simulation = serde_json::from_reader(File::open(file)?)?;
+    simulation.validate()?;
+    let (num_channels, time_bins) = simulation.max_trace_message_dimensions()?;
+    check_trace_message_size(num_channels, time_bins, message_max_bytes)?;
+    simulation.load_pulse_shapes()?;
+    simulation.load_ground_truth()?;
+    if let Some(metadata_source) = simulation.metadata_source.as_mut() {
+        metadata_source.load()?;
+    }
+    let ground_truth = defined
+        .ground_truth_file
+        .as_ref()
+        .map(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(|file| GroundTruthWriter::new(BufWriter::new(file)))
+        })
+        .transpose()?;
+    let trace_archive = defined
+        .trace_archive
+        .as_deref()
+        .map(|path| open_trace_archive(path, file, defined.shard))
+        .transpose()?;
+    // In a dry run, no topics are consumed.
+    let message_waiter = MessageWaiter::new(file_sink.is_none().then(|| consumer_config.clone()));
     let mut kafka_producer_thread_set = JoinSet::<()>::new();
+    let queue_full = QueueFullCounter::default();
+    let mut kafka_sink;
+    let sink: &mut dyn MessageSink = match file_sink {
+        Some(file_sink) => file_sink,
+        None => {
+            kafka_sink = KafkaSink::new(
+                use_otel,
+                producer,
+                &mut kafka_producer_thread_set,
+                queue_full.clone(),
+            );
+            &mut kafka_sink
+        }
+    };
+    let mut archiving_sink;
+    let sink: &mut dyn MessageSink = match trace_archive {
+        Some(trace_archive) => {
+            archiving_sink = ArchivingSink::new(sink, trace_archive);
+            &mut archiving_sink
+        }
+        None => sink,
+    };
     let mut engine = SimulationEngine::new(
         SimulationEngineExternals {
-            use_otel,
-            producer,
-            kafka_producer_thread_set: &mut kafka_producer_thread_set,
+            sink,
             topics: Topics {
                 traces: &defined.digitiser_trace_topic,
                 events: &defined.digitiser_event_topic,
@@ -59,14 +242,33 @@ pub(crate) async fn run_configured_simulation(
                 runlog: &defined.runlog_topic,
                 selog: &defined.selog_topic,
                 alarm: &defined.alarm_topic,
+                ground_truth: defined.ground_truth_topic.as_deref(),
             },
+            ground_truth,
+            shard: defined.shard,
+            max_materialised_channels: defined.max_materialised_channels,
+            fault_injector: FaultInjector::new(simulation.fault_injection.as_ref()),
+            clipping: Default::default(),
+            queue_full: queue_full.clone(),
+            pacer: Pacer::new(&defined.pacing, queue_full),
+            message_waiter,
         },
         &simulation,
-    )?;
+    )?
+    .with_state(state);
+    if let Some(control) = control {
+        engine = engine.with_control(control);
+    }
 
-    if let Err(e) = run_schedule(&mut engine) {
-        error!("Critical Error: {e}");
+    let result = run_schedule(&mut engine);
+    engine.flush_sink()?;
+    engine.flush_ground_truth()?;
+    if simulation.fault_injection.is_some() {
+        info!("Injected faults: {}", engine.injected_faults());
     }
+    info!("Clipping: {}", engine.clipped_samples());
+    let state = engine.state().clone();
+    drop(engine);
 
     trace!("Waiting for delivery threads to finish.");
     while let Some(result) = kafka_producer_thread_set.join_next().await {
@@ -76,5 +278,91 @@ pub(crate) async fn run_configured_simulation(
     }
 
     trace!("All finished.");
-    Ok(())
+    result.map_err(ConfiguredError::Schedule)?;
+    Ok(state)
+}
+
+/// Returns `value` with the keys of every object in sorted order, so that its string form
+/// does not depend on the order or whitespace of the file it was read from.
+fn canonicalise(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalise(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(canonicalise).collect())
+        }
+        value => value,
+    }
+}
+
+/// Returns the hash of every setting affecting the traces generated by a simulation,
+/// which are those of its json file, `config`, and the digitisers it simulates, given by `shard`.
+fn simulation_config_hash(config: serde_json::Value, shard: Shard) -> u64 {
+    config_hash(format!("{}\n{shard}", canonicalise(config)).as_bytes())
+}
+
+/// Opens the trace archive at `path`, and appends the header of the simulation of the json file `file` to it.
+fn open_trace_archive(
+    path: &Path,
+    file: &Path,
+    shard: Shard,
+) -> Result<TraceArchiveWriter<BufWriter<File>>, ConfiguredError> {
+    let config = serde_json::from_reader(File::open(file)?)?;
+    let header = ArchiveHeader {
+        simulator_version: digital_muon_common::version!(),
+        config_hash: simulation_config_hash(config, shard),
+    };
+    Ok(TraceArchiveWriter::append_to(path, &header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(config: &str) -> u64 {
+        simulation_config_hash(serde_json::from_str(config).unwrap(), Shard::default())
+    }
+
+    #[test]
+    fn config_hash_ignores_layout() {
+        assert_eq!(
+            hash(r#"{"time-bins": {"const": 100}, "sample-rate": {"const": 1000}}"#),
+            hash(
+                r#"{
+                    "sample-rate": { "const": 1000 },
+                    "time-bins": { "const": 100 }
+                }"#
+            )
+        );
+    }
+
+    #[test]
+    fn config_hash_changes_with_any_field() {
+        let base = hash(r#"{"time-bins": {"const": 100}, "pulses": [{"width": 20}]}"#);
+        for changed in [
+            r#"{"time-bins": {"const": 101}, "pulses": [{"width": 20}]}"#,
+            r#"{"time-bins": {"const": 100}, "pulses": [{"width": 21}]}"#,
+            r#"{"time-bins": {"const": 100}, "pulses": [{"width": 20}, {"width": 20}]}"#,
+            r#"{"time-bins": {"const": 100}, "pulses": [{"width": 20}], "seed": 1}"#,
+            r#"{"time-bins": {"const": 100}}"#,
+        ] {
+            assert_ne!(hash(changed), base, "{changed}");
+        }
+        assert_ne!(
+            simulation_config_hash(
+                serde_json::from_str(r#"{"time-bins": {"const": 100}, "pulses": [{"width": 20}]}"#)
+                    .unwrap(),
+                "1/2".parse().unwrap()
+            ),
+            base
+        );
+    }
 }
@@ -0,0 +1,315 @@
+//! Runs the simulations of several json files one after the other, as listed by a playlist file.
+use super::{
+    ConfiguredError,
+    message_sink::FileSink,
+    run_simulation,
+    simulation_engine::{control::ControlSocket, engine::SimulationEngineState},
+};
+use crate::Defined;
+use rdkafka::{ClientConfig, producer::FutureProducer};
+use serde::Deserialize;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{error, info};
+
+fn one_run() -> usize {
+    1
+}
+
+/// An entry of a [Playlist], which runs the simulation of a json file one or more times.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct PlaylistEntry {
+    /// The json file of the simulation, relative to the playlist file.
+    config: PathBuf,
+    /// The number of times the simulation is run.
+    #[serde(default = "one_run")]
+    repeat: usize,
+    /// The time to wait after each run of the simulation, in milliseconds.
+    #[serde(default)]
+    delay_ms: u64,
+    /// If `true`, each run continues from the timestamp and running flag at the end of the previous run,
+    /// otherwise they are reset as at the start of a single simulation.
+    #[serde(default)]
+    carry_timestamp: bool,
+}
+
+/// A sequence of simulations, given as a json list of [PlaylistEntry] objects.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Playlist {
+    entries: Vec<PlaylistEntry>,
+}
+
+/// The result of running a [Playlist].
+#[derive(Debug, Default)]
+pub(crate) struct PlaylistOutcome {
+    /// The number of simulations run, including those which failed.
+    pub(crate) runs: usize,
+    /// The number of simulations which failed.
+    pub(crate) failures: usize,
+    /// The state at the end of the last simulation to succeed.
+    pub(crate) state: Option<SimulationEngineState>,
+}
+
+impl Playlist {
+    /// Reads the playlist file at `path`, and resolves the path of each entry's json file.
+    pub(crate) fn load(path: &Path) -> Result<Self, ConfiguredError> {
+        let mut playlist: Self = serde_json::from_reader(File::open(path)?)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for entry in &mut playlist.entries {
+            entry.config = directory.join(&entry.config);
+        }
+        Ok(playlist)
+    }
+
+    /// Runs the simulation of each entry in order, with the same producer, consumer configuration and topics,
+    /// or if `file_sink` is present, writing every message to it.
+    /// If `control` is present, the actions received on it are applied whilst each simulation is run.
+    /// The size of the trace messages of each simulation is checked against `message_max_bytes`, if present.
+    ///
+    /// A simulation which fails is reported, and the playlist is abandoned,
+    /// unless [Defined::continue_on_error] is set, in which case the next simulation is run.
+    #[tracing::instrument(skip_all, fields(num_entries = self.entries.len()))]
+    pub(crate) async fn run(
+        &self,
+        use_otel: bool,
+        producer: &FutureProducer,
+        consumer_config: &ClientConfig,
+        mut file_sink: Option<&mut FileSink>,
+        control: Option<&ControlSocket>,
+        defined: &Defined,
+        message_max_bytes: Option<usize>,
+    ) -> PlaylistOutcome {
+        let mut outcome = PlaylistOutcome::default();
+        for (index, entry) in self.entries.iter().enumerate() {
+            for run in 1..=entry.repeat {
+                let state = match &outcome.state {
+                    Some(previous) if entry.carry_timestamp => previous.carried_over(),
+                    _ => SimulationEngineState::default(),
+                };
+                info!(
+                    "Running playlist entry {index}, {} ({run} of {})",
+                    entry.config.display(),
+                    entry.repeat
+                );
+                outcome.runs += 1;
+                match run_simulation(
+                    use_otel,
+                    producer,
+                    consumer_config,
+                    file_sink.as_deref_mut(),
+                    control,
+                    defined,
+                    &entry.config,
+                    state,
+                    message_max_bytes,
+                )
+                .await
+                {
+                    Ok(state) => {
+                        info!(
+                            "Finished at timestamp {}, running: {}",
+                            state.timestamp(),
+                            state.running()
+                        );
+                        outcome.state = Some(state);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Playlist entry {index}, {}, failed: {e}",
+                            entry.config.display()
+                        );
+                        outcome.failures += 1;
+                        if !defined.continue_on_error {
+                            return outcome;
+                        }
+                    }
+                }
+                if entry.delay_ms > 0 {
+                    tokio::time::sleep(Duration::from_millis(entry.delay_ms)).await;
+                }
+            }
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use clap::Parser;
+    use rdkafka::ClientConfig;
+
+    const TINY_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 10 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 1 },
+                "num-channels-per-digitiser": { "const" : 1 }
+            }
+        },
+        "pulses": [],
+        "event-lists": [],
+        "schedule": SCHEDULE
+    }
+    "#;
+
+    /// Sets the timestamp, stops running, then advances the timestamp by a second.
+    const FIRST_SCHEDULE: &str = r#"[
+        { "set-timestamp": { "to": "2024-01-01T00:00:00Z" } },
+        { "set-running": false },
+        { "set-timestamp": { "advance-by-ms": 1000 } }
+    ]"#;
+
+    /// Advances the timestamp by half a second.
+    const SECOND_SCHEDULE: &str = r#"[
+        { "set-timestamp": { "advance-by-ms": 500 } }
+    ]"#;
+
+    fn start() -> DateTime<Utc> {
+        "2024-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    /// A directory containing a playlist and the json files of the tiny simulations, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, playlist: &str) -> Self {
+            let directory = std::env::temp_dir().join(format!(
+                "simulator-playlist-test-{name}-{}",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&directory).unwrap();
+            std::fs::write(directory.join("playlist.json"), playlist).unwrap();
+            for (file, schedule) in [
+                ("first.json", FIRST_SCHEDULE),
+                ("second.json", SECOND_SCHEDULE),
+            ] {
+                std::fs::write(
+                    directory.join(file),
+                    TINY_SIMULATION.replace("SCHEDULE", schedule),
+                )
+                .unwrap();
+            }
+            Self(directory)
+        }
+
+        async fn run(&self, continue_on_error: bool) -> PlaylistOutcome {
+            let playlist_path = self.0.join("playlist.json");
+            let mut args = vec![
+                "defined",
+                "--playlist",
+                playlist_path.to_str().unwrap(),
+                "--digitiser-trace-topic",
+                "traces",
+                "--digitiser-event-topic",
+                "events",
+                "--frame-event-topic",
+                "frame_events",
+                "--control-topic",
+                "controls",
+                "--runlog-topic",
+                "runlog",
+                "--selog-topic",
+                "selog",
+                "--alarm-topic",
+                "alarm",
+            ];
+            if continue_on_error {
+                args.push("--continue-on-error");
+            }
+            let defined = Defined::try_parse_from(args).unwrap();
+            let producer: FutureProducer = ClientConfig::new().create().unwrap();
+            Playlist::load(&playlist_path)
+                .unwrap()
+                .run(
+                    false,
+                    &producer,
+                    &ClientConfig::new(),
+                    None,
+                    None,
+                    &defined,
+                    None,
+                )
+                .await
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_run_in_order_with_carried_timestamp() {
+        let fixture = Fixture::new(
+            "carried",
+            r#"[
+                { "config": "first.json" },
+                { "config": "second.json", "repeat": 2, "carry-timestamp": true }
+            ]"#,
+        );
+        let outcome = fixture.run(false).await;
+        assert_eq!(outcome.runs, 3);
+        assert_eq!(outcome.failures, 0);
+
+        // Had the second entry run first, the first would have overwritten its timestamp.
+        let state = outcome.state.unwrap();
+        assert_eq!(state.timestamp(), start() + TimeDelta::milliseconds(2000));
+        assert!(!state.running());
+    }
+
+    #[tokio::test]
+    async fn timestamp_reset_without_carry() {
+        let fixture = Fixture::new(
+            "reset",
+            r#"[
+                { "config": "first.json" },
+                { "config": "second.json" }
+            ]"#,
+        );
+        let before = Utc::now();
+        let outcome = fixture.run(false).await;
+        let after = Utc::now();
+        assert_eq!(outcome.runs, 2);
+        assert_eq!(outcome.failures, 0);
+
+        // The second entry starts from the current time, and is running.
+        let state = outcome.state.unwrap();
+        let advance = TimeDelta::milliseconds(500);
+        assert!((before + advance..=after + advance).contains(&state.timestamp()));
+        assert!(state.running());
+    }
+
+    #[tokio::test]
+    async fn failed_entry_abandons_playlist_unless_continuing() {
+        let playlist = r#"[
+            { "config": "missing.json" },
+            { "config": "first.json" }
+        ]"#;
+
+        let fixture = Fixture::new("abandon", playlist);
+        let outcome = fixture.run(false).await;
+        assert_eq!(outcome.runs, 1);
+        assert_eq!(outcome.failures, 1);
+        assert!(outcome.state.is_none());
+
+        let fixture = Fixture::new("continue", playlist);
+        let outcome = fixture.run(true).await;
+        assert_eq!(outcome.runs, 2);
+        assert_eq!(outcome.failures, 1);
+        assert_eq!(
+            outcome.state.unwrap().timestamp(),
+            start() + TimeDelta::milliseconds(1000)
+        );
+    }
+}
@@ -1,45 +1,45 @@
 use crate::{
     integrated::{
         build_messages::{
-            BuildError, build_aggregated_event_list_message, build_digitiser_event_list_message,
-            build_trace_message,
+            BuildError, FrameTraceEvents, PendingTraceMessage, build_aggregated_event_list_message,
+            build_digitiser_event_list_message, build_frame_trace_events_message,
+            build_trace_ground_truth_message, build_trace_messages, select_traces,
         },
+        message_sink::{SinkError, SinkMessage},
         simulation_elements::{
-            EventList, Trace,
+            EventList, Trace, Transformation,
             run_messages::{
-                SendAlarm, SendRunLogData, SendRunStart, SendRunStop, SendSampleEnvLog,
-                SendSampleEnvLogValues,
+                SendAlarm, SendLogData, SendRunLogData, SendSampleEnvLog, SendSampleEnvLogValues,
             },
             utils::JsonValueError,
         },
         simulation_engine::{
             SimulationEngineExternals,
-            actions::{SelectionModeOptions, SourceOptions},
+            actions::{DropoutMode, SelectionModeOptions, SourceOptions},
+            dropout::garbage_payload,
+            engine::CurrentRun,
         },
     },
     runs::{RunCommandError, runlog, sample_environment},
 };
-use chrono::{DateTime, Utc};
-use digital_muon_common::{Channel, DigitizerId, tracer::FutureRecordTracerExt};
+use chrono::{DateTime, TimeDelta, Utc};
+use digital_muon_common::{Channel, DigitizerId, FrameNumber};
 use digital_muon_streaming_types::{FrameMetadata, flatbuffers::FlatBufferBuilder};
 use isis_streaming_data_types::flatbuffers_generated::{
-    alarm_al00::{Alarm, AlarmArgs, finish_alarm_buffer},
+    alarm_al00::{Alarm, AlarmArgs, Severity, finish_alarm_buffer},
     data_se00::{
         finish_se_00_sample_environment_data_buffer, se00_SampleEnvironmentData,
         se00_SampleEnvironmentDataArgs,
     },
-    logdata_f144::{f144_LogData, f144_LogDataArgs, finish_f_144_log_data_buffer},
+    logdata_f144::{
+        Double, DoubleArgs, Value, f144_LogData, f144_LogDataArgs, finish_f_144_log_data_buffer,
+    },
     run_start_pl72::{RunStart, RunStartArgs, finish_run_start_buffer},
     run_stop_6s4t::{RunStop, RunStopArgs, finish_run_stop_buffer},
 };
-use rdkafka::{
-    Message,
-    producer::{FutureProducer, FutureRecord},
-    util::Timeout,
-};
-use std::{collections::VecDeque, num::TryFromIntError, time::Duration};
+use std::{collections::VecDeque, num::TryFromIntError};
 use thiserror::Error;
-use tracing::{Span, debug, debug_span, error};
+use tracing::debug;
 
 #[derive(Debug, Error)]
 pub(crate) enum SendError {
@@ -53,55 +53,17 @@ pub(crate) enum SendError {
     Build(#[from] BuildError),
     #[error("Build error: {0}")]
     JsonValue(#[from] JsonValueError),
+    #[error("Log Data Timestamp out of Range")]
+    LogDataTimestamp,
+    #[error("Sink Error: {0}")]
+    Sink(#[from] SinkError),
 }
 
-struct SendMessageArgs<'a> {
-    use_otel: bool,
-    producer: FutureProducer,
-    fbb: FlatBufferBuilder<'a>,
-    topic: String,
-    span: Span,
-    key: &'static str,
-}
-
-impl<'a> SendMessageArgs<'a> {
-    fn new(
-        use_otel: bool,
-        fbb: FlatBufferBuilder<'a>,
-        producer: &FutureProducer,
-        topic: &str,
-        key: &'static str,
-    ) -> Self {
-        Self {
-            use_otel,
-            fbb,
-            producer: producer.to_owned(),
-            topic: topic.to_owned(),
-            span: tracing::Span::current(),
-            key,
-        }
-    }
-}
-
-#[tracing::instrument(skip_all)]
-async fn send_message(args: SendMessageArgs<'_>) {
-    let span = debug_span!(parent: &args.span, "Send Message Thread");
-    let _guard = span.enter();
-
-    let future_record = FutureRecord::to(&args.topic)
-        .payload(args.fbb.finished_data())
-        .conditional_inject_span_into_headers(args.use_otel, &args.span)
-        .key(args.key);
-
-    let timeout = Timeout::After(Duration::from_millis(100));
-    match args.producer.send(future_record, timeout).await {
-        Ok(r) => debug!("Delivery: {:?}", r),
-        Err(e) => error!(
-            "Delivery failed: {:?}. Message Size: {}",
-            e.0,
-            e.1.payload().unwrap_or(&[]).len()
-        ),
-    };
+/// Takes the finished message out of `fbb`, without copying it to a new buffer.
+fn finished_payload(fbb: FlatBufferBuilder<'_>) -> Vec<u8> {
+    let (mut payload, head) = fbb.collapse();
+    payload.drain(..head);
+    payload
 }
 
 fn get_time_since_epoch_ms(timestamp: &DateTime<Utc>) -> Result<u64, SendError> {
@@ -114,61 +76,95 @@ fn get_time_since_epoch_ns(timestamp: &DateTime<Utc>) -> Result<i64, SendError>
         .ok_or(SendError::TimestampToNanos(*timestamp))
 }
 
-#[tracing::instrument(skip_all, err(level = "error"))]
-pub(crate) fn send_run_start_command(
-    externals: &mut SimulationEngineExternals,
-    status: &SendRunStart,
+/// Builds a `RunStart` message for `run`, starting at `timestamp`.
+pub(crate) fn build_run_start_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    run: &CurrentRun,
     timestamp: &DateTime<Utc>,
 ) -> Result<(), SendError> {
-    let mut fbb = FlatBufferBuilder::new();
     let run_start = RunStartArgs {
         start_time: get_time_since_epoch_ms(timestamp)?,
-        run_name: Some(fbb.create_string(&status.name.value()?)),
-        filename: Some(fbb.create_string(&status.filename.value()?)),
-        instrument_name: Some(fbb.create_string(&status.instrument.value()?)),
+        run_name: Some(fbb.create_string(&run.name)),
+        filename: Some(fbb.create_string(&run.filename)),
+        instrument_name: Some(fbb.create_string(&run.instrument)),
         ..Default::default()
     };
-    let message = RunStart::create(&mut fbb, &run_start);
-    finish_run_start_buffer(&mut fbb, message);
+    let message = RunStart::create(fbb, &run_start);
+    finish_run_start_buffer(fbb, message);
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, err(level = "error"))]
+pub(crate) fn send_run_start_command(
+    externals: &mut SimulationEngineExternals,
+    run: &CurrentRun,
+    timestamp: &DateTime<Utc>,
+) -> Result<(), SendError> {
+    let mut fbb = FlatBufferBuilder::new();
+    build_run_start_message(&mut fbb, run, timestamp)?;
 
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
+    externals.sink.send(SinkMessage::new(
         externals.topics.run_controls,
         "Simulated Run Start",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+        finished_payload(fbb),
+    ))?;
     Ok(())
 }
 
 #[tracing::instrument(skip_all, err(level = "error"))]
 pub(crate) fn send_run_stop_command(
     externals: &mut SimulationEngineExternals,
-    status: &SendRunStop,
+    name: &str,
     timestamp: &DateTime<Utc>,
 ) -> Result<(), SendError> {
     let mut fbb = FlatBufferBuilder::new();
     let run_stop = RunStopArgs {
         stop_time: get_time_since_epoch_ms(timestamp)?,
-        run_name: Some(fbb.create_string(&status.name.value()?)),
+        run_name: Some(fbb.create_string(name)),
         ..Default::default()
     };
     let message = RunStop::create(&mut fbb, &run_stop);
     finish_run_stop_buffer(&mut fbb, message);
 
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
+    externals.sink.send(SinkMessage::new(
         externals.topics.run_controls,
         "Simulated Run Stop",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+        finished_payload(fbb),
+    ))?;
+    Ok(())
+}
+
+/// Builds a `MAJOR` severity `Alarm` message, from the source `name`, reporting that the run `name` was aborted at `timestamp`.
+pub(crate) fn build_run_abort_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    name: &str,
+    timestamp: &DateTime<Utc>,
+) -> Result<(), SendError> {
+    let alarm_args = AlarmArgs {
+        source_name: Some(fbb.create_string(name)),
+        timestamp: get_time_since_epoch_ns(timestamp)?,
+        severity: Severity::MAJOR,
+        message: Some(fbb.create_string(&format!("Run {name} aborted"))),
+    };
+    let message = Alarm::create(fbb, &alarm_args);
+    finish_alarm_buffer(fbb, message);
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, err(level = "error"))]
+pub(crate) fn send_run_abort_command(
+    externals: &mut SimulationEngineExternals,
+    name: &str,
+    timestamp: &DateTime<Utc>,
+) -> Result<(), SendError> {
+    let mut fbb = FlatBufferBuilder::new();
+    build_run_abort_message(&mut fbb, name, timestamp)?;
+
+    externals.sink.send(SinkMessage::new(
+        externals.topics.run_controls,
+        "Simulated Run Abort",
+        finished_payload(fbb),
+    ))?;
     Ok(())
 }
 
@@ -190,16 +186,58 @@ pub(crate) fn send_run_log_command(
     let message = f144_LogData::create(&mut fbb, &run_log_args);
     finish_f_144_log_data_buffer(&mut fbb, message);
 
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
+    externals.sink.send(SinkMessage::new(
         externals.topics.runlog,
         "Simulated Run Log Data",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+        finished_payload(fbb),
+    ))?;
+    Ok(())
+}
+
+/// Creates the f144 messages of `log_data`, with values sampled for frame `frame_index`.
+fn build_log_data_messages(
+    timestamp: &DateTime<Utc>,
+    frame_index: usize,
+    log_data: &SendLogData,
+) -> Result<Vec<FlatBufferBuilder<'static>>, SendError> {
+    let source_name = log_data.source_name.value()?;
+    (0..log_data.repeat)
+        .map(|index| {
+            let timestamp = (index as u64)
+                .checked_mul(log_data.interval_ms)
+                .and_then(|ms| i64::try_from(ms).ok())
+                .and_then(TimeDelta::try_milliseconds)
+                .and_then(|delta| timestamp.checked_add_signed(delta))
+                .ok_or(SendError::LogDataTimestamp)?;
+            let mut fbb = FlatBufferBuilder::new();
+            let value = log_data.value.sample(frame_index)?;
+            let log_data_args = f144_LogDataArgs {
+                source_name: Some(fbb.create_string(&source_name)),
+                timestamp: get_time_since_epoch_ns(&timestamp)?,
+                value_type: Value::Double,
+                value: Some(Double::create(&mut fbb, &DoubleArgs { value }).as_union_value()),
+            };
+            let message = f144_LogData::create(&mut fbb, &log_data_args);
+            finish_f_144_log_data_buffer(&mut fbb, message);
+            Ok(fbb)
+        })
+        .collect()
+}
+
+#[tracing::instrument(skip_all, fields(repeat = log_data.repeat), err(level = "error"))]
+pub(crate) fn send_log_data_command(
+    externals: &mut SimulationEngineExternals,
+    timestamp: &DateTime<Utc>,
+    frame_index: usize,
+    log_data: &SendLogData,
+) -> Result<(), SendError> {
+    for fbb in build_log_data_messages(timestamp, frame_index, log_data)? {
+        externals.sink.send(SinkMessage::new(
+            externals.topics.runlog,
+            "Simulated Log Data",
+            finished_payload(fbb),
+        ))?;
+    }
     Ok(())
 }
 
@@ -253,16 +291,11 @@ pub(crate) fn send_se_log_command(
     let message = se00_SampleEnvironmentData::create(&mut fbb, &se_log_args);
     finish_se_00_sample_environment_data_buffer(&mut fbb, message);
 
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
+    externals.sink.send(SinkMessage::new(
         externals.topics.selog,
         "Simulated Sample Environment Log",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+        finished_payload(fbb),
+    ))?;
     Ok(())
 }
 
@@ -283,55 +316,110 @@ pub(crate) fn send_alarm_command(
     let message = Alarm::create(&mut fbb, &alarm_args);
     finish_alarm_buffer(&mut fbb, message);
 
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
+    externals.sink.send(SinkMessage::new(
         externals.topics.alarm,
         "Simulated Alarm",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+        finished_payload(fbb),
+    ))?;
     Ok(())
 }
 
+/// Selects the traces of a trace message of `digitizer_id` from `cache`,
+/// and returns the message, ready to be built and sent by [send_digitiser_trace_messages].
+///
+/// If `dropout` is present, the digitiser has dropped out. The traces are still taken from `cache`,
+/// so those of other digitisers are unchanged, but the message is either not sent, in which case [None] is returned,
+/// or sent with a corrupted payload in place of any injected faults.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(skip_all, fields(digitizer_id = digitizer_id))]
-pub(crate) fn send_digitiser_trace_message(
-    externals: &mut SimulationEngineExternals,
+pub(crate) fn select_digitiser_trace_message<'a>(
     sample_rate: u64,
-    cache: &mut VecDeque<Trace>,
+    cache: &mut VecDeque<Trace<'a>>,
     metadata: &FrameMetadata,
     digitizer_id: DigitizerId,
-    channels: &[Channel],
+    channels: &[(Channel, &Transformation<f64>)],
     selection_mode: SelectionModeOptions,
-) -> Result<(), SendError> {
-    let mut fbb = FlatBufferBuilder::new();
-
-    build_trace_message(
-        &mut fbb,
+    frame_trace_events: Option<&mut FrameTraceEvents>,
+    dropout: Option<DropoutMode>,
+) -> Result<Option<PendingTraceMessage<'a>>, SendError> {
+    let (ground_truth, selected) = select_traces(cache, channels, selection_mode)?;
+    if dropout == Some(DropoutMode::Silent) {
+        return Ok(None);
+    }
+    if let Some(frame_trace_events) = frame_trace_events {
+        frame_trace_events.push(metadata, digitizer_id, &ground_truth);
+    }
+    Ok(Some(PendingTraceMessage {
         sample_rate,
-        cache,
-        metadata,
+        metadata: metadata.clone(),
         digitizer_id,
-        channels,
-        selection_mode,
-    )?;
-
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
-        externals.topics.traces,
-        "Simulated Trace",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+        selected,
+        ground_truth,
+        dropout,
+    }))
+}
 
-    Ok(())
+/// Builds and sends the trace messages `pending`, in order, each followed by its ground truth message if required.
+///
+/// The messages are built in parallel, across digitisers, see [build_trace_messages].
+#[tracing::instrument(skip_all, fields(num_messages = pending.len()))]
+pub(crate) fn send_digitiser_trace_messages(
+    externals: &mut SimulationEngineExternals,
+    pending: &[PendingTraceMessage<'_>],
+) -> Result<(), SendError> {
+    build_trace_messages(
+        pending,
+        externals.max_materialised_channels,
+        |pending, fbb, clipping| {
+            let PendingTraceMessage {
+                metadata,
+                digitizer_id,
+                ground_truth,
+                dropout,
+                ..
+            } = pending;
+            if clipping.total() > 0 {
+                debug!(
+                    "Frame {} of digitiser {digitizer_id}: {clipping}",
+                    metadata.frame_number
+                );
+            }
+            externals.clipping.merge(&clipping);
+
+            let payloads = match dropout {
+                Some(_) => vec![garbage_payload(finished_payload(fbb))],
+                None => externals
+                    .fault_injector
+                    .inject(metadata.frame_number as usize, finished_payload(fbb))?,
+            };
+            for payload in payloads {
+                externals.sink.send(
+                    SinkMessage::new(externals.topics.traces, "Simulated Trace", payload)
+                        .with_frame_number(metadata.frame_number)
+                        .with_digitizer_id(*digitizer_id),
+                )?;
+            }
+
+            if let Some(topic) = externals.topics.ground_truth {
+                let mut fbb = FlatBufferBuilder::new();
+                build_trace_ground_truth_message(&mut fbb, ground_truth, metadata, *digitizer_id);
+
+                // Keyed identically to the trace message, so the two can be joined downstream.
+                externals.sink.send(
+                    SinkMessage::new(topic, "Simulated Trace", finished_payload(fbb))
+                        .with_frame_number(metadata.frame_number)
+                        .with_digitizer_id(*digitizer_id),
+                )?;
+            }
+            Ok(())
+        },
+    )
 }
 
+/// Sends an event list message of `digitizer_id`, whose event lists are taken from `cache`.
+///
+/// If `dropout` is present, the digitiser has dropped out, so the event lists are still taken from `cache`,
+/// but the message is either not sent, or sent with a corrupted payload.
 #[tracing::instrument(skip_all, fields(digitizer_id = digitizer_id))]
 pub(crate) fn send_digitiser_event_list_message(
     externals: &mut SimulationEngineExternals,
@@ -340,6 +428,7 @@ pub(crate) fn send_digitiser_event_list_message(
     digitizer_id: DigitizerId,
     channels: &[Channel],
     source_options: &SourceOptions,
+    dropout: Option<DropoutMode>,
 ) -> Result<(), SendError> {
     let mut fbb = FlatBufferBuilder::new();
 
@@ -352,16 +441,20 @@ pub(crate) fn send_digitiser_event_list_message(
         source_options,
     )?;
 
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
-        externals.topics.events,
-        "Simulated Digitiser Event List",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+    let payload = match dropout {
+        None => finished_payload(fbb),
+        Some(DropoutMode::Silent) => return Ok(()),
+        Some(DropoutMode::Garbage) => garbage_payload(finished_payload(fbb)),
+    };
+    externals.sink.send(
+        SinkMessage::new(
+            externals.topics.events,
+            "Simulated Digitiser Event List",
+            payload,
+        )
+        .with_frame_number(metadata.frame_number)
+        .with_digitizer_id(digitizer_id),
+    )?;
 
     Ok(())
 }
@@ -373,20 +466,114 @@ pub(crate) fn send_aggregated_frame_event_list_message(
     metadata: &FrameMetadata,
     channels: &[Channel],
     source_options: &SourceOptions,
+) -> Result<(), SendError> {
+    let fbb = build_aggregated_event_list_message(cache, metadata, channels, source_options)?;
+
+    externals.sink.send(
+        SinkMessage::new(
+            externals.topics.frame_events,
+            "Simulated Frame Assembled Event List",
+            finished_payload(fbb),
+        )
+        .with_frame_number(metadata.frame_number),
+    )?;
+    Ok(())
+}
+
+/// Sends the events of the trace messages sent for frame `frame_number` as an aggregated frame event list, if any were sent.
+#[tracing::instrument(skip_all)]
+pub(crate) fn send_frame_trace_events_message(
+    externals: &mut SimulationEngineExternals,
+    frame_trace_events: FrameTraceEvents,
+    frame_number: FrameNumber,
+    num_digitisers: usize,
 ) -> Result<(), SendError> {
     let mut fbb = FlatBufferBuilder::new();
+    if !build_frame_trace_events_message(&mut fbb, frame_trace_events, num_digitisers) {
+        return Ok(());
+    }
 
-    build_aggregated_event_list_message(&mut fbb, cache, metadata, channels, source_options)?;
-
-    let send_args = SendMessageArgs::new(
-        externals.use_otel,
-        fbb,
-        externals.producer,
-        externals.topics.frame_events,
-        "Simulated Digitiser Event List",
-    );
-    externals
-        .kafka_producer_thread_set
-        .spawn(send_message(send_args));
+    externals.sink.send(
+        SinkMessage::new(
+            externals.topics.frame_events,
+            "Simulated Frame Assembled Event List",
+            finished_payload(fbb),
+        )
+        .with_frame_number(frame_number),
+    )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::simulation_engine::actions::{Action, FrameAction};
+    use isis_streaming_data_types::flatbuffers_generated::logdata_f144::root_as_f_144_log_data;
+
+    const LOG_DATA_SCHEDULE: &str = r#"
+    [
+        { "set-timestamp": "now" },
+        { "frame-loop": {
+                "start": { "const": 0 },
+                "end": { "const": 9 },
+                "schedule": [
+                    { "send-log-data": {
+                            "source-name": { "text": "Temperature" },
+                            "value": { "random-type": "constant-float", "value": { "num-func": { "scale": 2, "translate": 1 } } },
+                            "repeat": 3,
+                            "interval-ms": 10
+                        }
+                    }
+                ]
+            }
+        },
+        { "send-log-data": {
+                "source-name": { "text": "Field" },
+                "value": { "random-type": "uniform-float", "min": { "const": 0 }, "max": { "const": 1 } }
+            }
+        }
+    ]
+    "#;
+
+    fn frame_loop_log_data(schedule: &[Action]) -> Option<&SendLogData> {
+        match schedule.get(1)? {
+            Action::FrameLoop(frame_loop) => match frame_loop.schedule.first()? {
+                FrameAction::SendLogData(log_data) => Some(log_data),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn deserialize_log_data() {
+        let schedule: Vec<Action> = serde_json::from_str(LOG_DATA_SCHEDULE).unwrap();
+        let log_data = frame_loop_log_data(&schedule).unwrap();
+        assert_eq!(log_data.repeat, 3);
+        assert_eq!(log_data.interval_ms, 10);
+
+        let Some(Action::SendLogData(log_data)) = schedule.get(2) else {
+            unreachable!()
+        };
+        assert_eq!(log_data.repeat, 1);
+        assert_eq!(log_data.interval_ms, 0);
+    }
+
+    #[test]
+    fn build_log_data_from_frame() {
+        let schedule: Vec<Action> = serde_json::from_str(LOG_DATA_SCHEDULE).unwrap();
+        let log_data = frame_loop_log_data(&schedule).unwrap();
+
+        let timestamp = Utc::now();
+        let start_ns = get_time_since_epoch_ns(&timestamp).unwrap();
+        let messages = build_log_data_messages(&timestamp, 5, log_data).unwrap();
+        assert_eq!(messages.len(), 3);
+        for (index, fbb) in messages.iter().enumerate() {
+            let message = root_as_f_144_log_data(fbb.finished_data()).unwrap();
+            assert_eq!(message.source_name(), "Temperature");
+            assert_eq!(message.timestamp(), start_ns + index as i64 * 10_000_000);
+            // The value is sampled from the frame index of the enclosing frame loop.
+            assert_eq!(message.value_as_double().unwrap().value(), 11.0);
+        }
+    }
+}
@@ -2,7 +2,12 @@ use crate::integrated::{
     build_messages::BuildError,
     simulation_elements::{
         DigitiserConfig, Transformation,
-        event_list::{EventList, EventListTemplate, Trace},
+        event_list::{EventList, EventListSource, EventListTemplate, EventPulseTemplate, Trace},
+        fault_injection::FaultInjection,
+        ground_truth::{GroundTruth, GroundTruthError},
+        metadata_source::MetadataSource,
+        overflow::OnOverflow,
+        pulse_shapes::{PulseShapes, PulseShapesError},
         pulses::PulseTemplate,
         utils::{JsonValueError, NumConstant},
     },
@@ -18,6 +23,10 @@ use rand::distr::weighted::WeightedIndex;
 use rand_distr::Distribution;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::Deserialize;
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::PathBuf,
+};
 use thiserror::Error;
 use tracing::instrument;
 
@@ -29,6 +38,9 @@ use tracing::instrument;
 pub(crate) struct Simulation {
     // Is applied to all voltages when traces are created
     pub(crate) voltage_transformation: Transformation<f64>,
+    // How trace values which do not fit the Intensity type are handled
+    #[serde(default)]
+    pub(crate) on_overflow: OnOverflow,
     //  The length of each trace
     pub(crate) time_bins: NumConstant<Time>,
     //  Number of samples (time_bins) per second
@@ -36,6 +48,12 @@ pub(crate) struct Simulation {
     pub(crate) digitiser_config: DigitiserConfig,
     pub(crate) event_lists: Vec<EventListTemplate>,
     pub(crate) pulses: Vec<PulseTemplate>,
+    // If present, overrides the metadata of each frame with a recorded sequence
+    #[serde(default)]
+    pub(crate) metadata_source: Option<MetadataSource>,
+    // If present, faults are injected into trace messages just before they are produced
+    #[serde(default)]
+    pub(crate) fault_injection: Option<FaultInjection>,
     pub(crate) schedule: Vec<Action>,
 }
 
@@ -49,38 +67,97 @@ pub(crate) enum SimulationError {
     JsonValue(#[from] JsonValueError),
     #[error("Build error: {0}")]
     Build(#[from] BuildError),
+    #[error("Ground Truth error: {0}")]
+    GroundTruth(#[from] GroundTruthError),
 }
 
 impl Simulation {
+    /// Reads the shapes of each pulse template which is loaded from a file,
+    /// each file being read only once, however many templates refer to it.
+    #[instrument(skip_all, err(level = "error"))]
+    pub(crate) fn load_pulse_shapes(&mut self) -> Result<(), PulseShapesError> {
+        let mut cache = HashMap::<PathBuf, PulseShapes>::new();
+        for template in &mut self.pulses {
+            if let PulseTemplate::FromFile {
+                path,
+                weights,
+                shapes,
+                ..
+            } = template
+            {
+                let loaded = match cache.entry(path.clone()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(PulseShapes::load(path)?),
+                };
+                *shapes = loaded.with_weights(weights.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the ground truth file of each event list template replayed from one,
+    /// resolving its pulse template references against [Self::pulses].
+    #[instrument(skip_all, err(level = "error"))]
+    pub(crate) fn load_ground_truth(&mut self) -> Result<(), GroundTruthError> {
+        for template in &mut self.event_lists {
+            if let EventListSource::FromGroundTruth {
+                from_ground_truth,
+                ground_truth,
+            } = &mut template.source
+            {
+                *ground_truth = GroundTruth::load(from_ground_truth, &self.pulses)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of channels and time bins of the largest trace message the simulation can produce.
+    pub(crate) fn max_trace_message_dimensions(&self) -> Result<(usize, usize), SimulationError> {
+        Ok((
+            self.digitiser_config.max_channels_per_digitiser()?,
+            self.time_bins.value()? as usize,
+        ))
+    }
+
     #[instrument(skip_all, level = "debug", err(level = "error"))]
     pub(crate) fn get_random_pulse_template(
         &self,
-        source: &EventListTemplate,
+        pulses: &[EventPulseTemplate],
         distr: &WeightedIndex<f64>,
-    ) -> Result<&PulseTemplate, SimulationError> {
+    ) -> Result<(usize, &PulseTemplate), SimulationError> {
         //  get a random index for the pulse
         let index = distr.sample(&mut rand::rngs::StdRng::seed_from_u64(
             Utc::now().timestamp_subsec_nanos() as u64,
         ));
         let event_pulse_template =
-            source
-                .pulses
+            pulses
                 .get(index)
                 .ok_or(SimulationError::EventPulseTemplateIndexOutOfRange(
                     index,
-                    source.pulses.len(),
+                    pulses.len(),
                 ))?;
         // Return a pointer to either a local or global pulse
-        self.pulses.get(event_pulse_template.pulse_index).ok_or(
-            SimulationError::EventPulseTemplateIndexOutOfRange(index, source.pulses.len()),
-        )
+        let pulse = self.pulses.get(event_pulse_template.pulse_index).ok_or(
+            SimulationError::EventPulseTemplateIndexOutOfRange(index, pulses.len()),
+        )?;
+        Ok((event_pulse_template.pulse_index, pulse))
     }
 
+    /// Generates `repeat` event lists from the given template.
+    ///
+    /// If the template has a [coincidence](super::simulation_elements::coincidence::Coincidence),
+    /// the lists are taken to be those of the channels of the digitiser config at their positions within the frame,
+    /// and pulses are duplicated between neighbouring channels of each digitiser before the lists are cached.
+    ///
+    /// # Parameters
+    /// - first: the position, among all event lists generated for this frame,
+    ///   of the first list generated. This is used to look up replayed ground truth.
     #[instrument(skip_all, err(level = "error"))]
     pub(crate) fn generate_event_lists(
         &self,
         index: usize,
         frame_number: FrameNumber,
+        first: usize,
         repeat: usize,
     ) -> Result<Vec<EventList<'_>>, SimulationError> {
         let source =
@@ -91,29 +168,57 @@ impl Simulation {
                     self.event_lists.len(),
                 ))?;
 
-        let vec = (0..repeat)
-            .map(SpanWrapper::<usize>::new_with_current)
-            .collect::<Vec<_>>()
-            .into_par_iter()
-            .map(|span_wrapper| {
-                span_wrapper
-                    .span()
-                    .get()
-                    .expect("Span should exist, this never fails")
-                    .in_scope(|| EventList::new(self, frame_number, source))
-            })
-            .collect::<Vec<Result<_, SimulationError>>>()
-            .into_iter()
-            .collect::<Result<_, _>>()?;
-        Ok(vec)
+        match &source.source {
+            EventListSource::Random { pulses, num_pulses } => {
+                let mut vec: Vec<_> = (0..repeat)
+                    .map(SpanWrapper::<usize>::new_with_current)
+                    .collect::<Vec<_>>()
+                    .into_par_iter()
+                    .map(|span_wrapper| {
+                        span_wrapper
+                            .span()
+                            .get()
+                            .expect("Span should exist, this never fails")
+                            .in_scope(|| {
+                                EventList::new(
+                                    self,
+                                    frame_number,
+                                    pulses,
+                                    num_pulses,
+                                    &source.noises,
+                                )
+                            })
+                    })
+                    .collect::<Vec<Result<_, SimulationError>>>()
+                    .into_iter()
+                    .collect::<Result<_, _>>()?;
+                if let Some(coincidence) = &source.coincidence {
+                    coincidence.apply(
+                        &mut vec,
+                        first,
+                        &self.digitiser_config.channel_layout()?,
+                        frame_number as usize,
+                    )?;
+                }
+                Ok(vec)
+            }
+            EventListSource::FromGroundTruth { ground_truth, .. } => (first..first + repeat)
+                .map(|position| {
+                    Ok(EventList::from_ground_truth(
+                        ground_truth.event_list(frame_number, position)?,
+                        &source.noises,
+                    ))
+                })
+                .collect(),
+        }
     }
 
     #[instrument(skip_all, level = "debug", err(level = "error"))]
     pub(crate) fn generate_traces<'a>(
         &'a self,
-        event_lists: &'a [EventList],
+        event_lists: &[EventList<'a>],
         frame_number: FrameNumber,
-    ) -> Result<Vec<Trace>, JsonValueError> {
+    ) -> Result<Vec<Trace<'a>>, JsonValueError> {
         event_lists
             .iter()
             .map(SpanWrapper::<_>::new_with_current)
@@ -136,6 +241,9 @@ impl Simulation {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::integrated::simulation_elements::{
+        IntRandomDistribution, ground_truth::GroundTruthWriter, pulses::PulseEvent,
+    };
 
     const JSON_INPUT_1: &str = r#"
     {
@@ -189,6 +297,16 @@ mod tests {
                     }
                 ],
                 "num-pulses": { "random-type": "constant-int", "value": { "const": 500 } }
+            },
+            {
+                "pulses": [{"weight": 1, "pulse-index": 1}],
+                "noises": [],
+                "num-pulses": { "random-type": "poisson", "mean": { "num-func": { "scale": 0.1, "translate": 20 } } }
+            },
+            {
+                "pulses": [{"weight": 1, "pulse-index": 1}],
+                "noises": [],
+                "num-pulses": { "random-type": "poisson", "mean": { "const": 0 } }
             }
         ],
         "schedule": [
@@ -215,4 +333,199 @@ mod tests {
         assert_eq!(simulation.voltage_transformation.scale, 1.0);
         assert_eq!(simulation.voltage_transformation.translate, 0.0);
     }
+
+    #[test]
+    fn poisson_num_pulses() {
+        let simulation: Simulation = serde_json::from_str(JSON_INPUT_1).unwrap();
+        assert!(matches!(
+            &simulation.event_lists[1].source,
+            EventListSource::Random {
+                num_pulses: IntRandomDistribution::Poisson { .. },
+                ..
+            }
+        ));
+
+        // The mean rises from 20 to 40 across the frames, averaging 30.
+        let num_frames = 200;
+        let total_pulses: usize = (0..num_frames)
+            .map(|frame| {
+                let event_lists = simulation.generate_event_lists(1, frame, 0, 1).unwrap();
+                event_lists[0].pulses.len()
+            })
+            .sum();
+        let mean = total_pulses as f64 / num_frames as f64;
+        assert!((mean - 29.95).abs() < 1.5, "{mean}");
+
+        // A mean of zero produces empty event lists.
+        for frame in 0..10 {
+            let event_lists = simulation.generate_event_lists(2, frame, 0, 1).unwrap();
+            assert!(event_lists[0].pulses.is_empty());
+        }
+    }
+
+    const PIECEWISE_NOISE_JSON: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 1 },
+                "num-channels-per-digitiser": { "const" : 1 }
+            }
+        },
+        "pulses": [],
+        "event-lists": [
+            {
+                "pulses": [],
+                "noises": [
+                    {
+                        "attributes": {
+                            "noise-type" : "gaussian",
+                            "mean" : { "const": 0 },
+                            "sd" : {
+                                "piecewise": [
+                                    { "until-frame": 9, "value": { "const": 0 } },
+                                    { "until-frame": 19, "value": { "num-func": { "scale": 1, "translate": -10 } } },
+                                    { "until-frame": 20, "value": { "const": 10 } }
+                                ]
+                            }
+                        },
+                        "smoothing-window-length" : { "const": 1 },
+                        "bounds" : { "min": { "const": 0 }, "max": { "const": 100 } }
+                    }
+                ],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 0 } }
+            }
+        ],
+        "schedule": []
+    }
+    "#;
+
+    #[test]
+    fn piecewise_ramp_as_noise_sd() {
+        let simulation: Simulation = serde_json::from_str(PIECEWISE_NOISE_JSON).unwrap();
+        let noise = &simulation.event_lists[0].noises[0];
+
+        // Until the ramp has begun, the noise has no spread.
+        for frame in 0..=10 {
+            assert_eq!(noise.sample(50, frame).unwrap(), 0.0);
+        }
+        // Beyond the last piece, the noise keeps the spread of its end.
+        assert!((0..100).any(|_| noise.sample(50, 100).unwrap() != 0.0));
+    }
+
+    fn from_file_json(path: &std::path::Path) -> String {
+        let path = serde_json::to_string(path).unwrap();
+        format!(
+            r#"
+        {{
+            "voltage-transformation": {{"scale": 1, "translate": 0 }},
+            "time-bins": {{ "const": 100 }},
+            "sample-rate": {{ "const": 1000000000 }},
+            "digitiser-config": {{ "auto-aggregated-frame": {{ "num-channels": {{ "const" : 1 }} }} }},
+            "pulses": [{{
+                            "pulse-type": "from-file",
+                            "path": {path},
+                            "weights": [1, 0],
+                            "height": {{ "random-type": "constant-float", "value": {{ "const": 100 }} }},
+                            "time":   {{ "random-type": "constant-float", "value": {{ "const": 50 }} }}
+                        }},
+                        {{
+                            "pulse-type": "from-file",
+                            "path": {path},
+                            "height": {{ "random-type": "constant-float", "value": {{ "const": 100 }} }},
+                            "time":   {{ "random-type": "constant-float", "value": {{ "const": 50 }} }}
+                        }}],
+            "event-lists": [
+                {{
+                    "pulses": [{{"weight": 1, "pulse-index": 0}}],
+                    "noises": [],
+                    "num-pulses": {{ "random-type": "constant-int", "value": {{ "const": 5 }} }}
+                }}
+            ],
+            "schedule": []
+        }}
+        "#
+        )
+    }
+
+    /// A pulse shapes file in the temporary directory, which is removed when dropped.
+    struct PulseShapesFixture(PathBuf);
+
+    impl PulseShapesFixture {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "simulator-pulse-shapes-test-{name}-{}.csv",
+                std::process::id()
+            ));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for PulseShapesFixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn from_file_pulses() {
+        let fixture = PulseShapesFixture::new("values", "0, 2, 4, 2\n0, 1, 2, 4, 2, 0\n");
+        let mut simulation: Simulation = serde_json::from_str(&from_file_json(&fixture.0)).unwrap();
+        // Shapes cannot be sampled until they are loaded.
+        assert!(simulation.generate_event_lists(0, 0, 0, 1).is_err());
+        simulation.load_pulse_shapes().unwrap();
+
+        let event_lists = simulation.generate_event_lists(0, 0, 0, 2).unwrap();
+        for pulse in event_lists.iter().flat_map(|event_list| &event_list.pulses) {
+            assert!(matches!(pulse, PulseEvent::FromFile { shape_index: 0, .. }));
+            assert_eq!(pulse.get_start(), 48);
+            assert_eq!(pulse.get_end(), 51);
+            assert_eq!(pulse.time(), 50);
+            assert_eq!(pulse.intensity(), 100);
+            assert_eq!(pulse.get_value_at(47.0), 0.0);
+            assert_eq!(pulse.get_value_at(48.5), 25.0);
+            assert_eq!(pulse.get_value_at(49.5), 75.0);
+            assert_eq!(pulse.get_value_at(50.0), 100.0);
+            assert_eq!(pulse.get_value_at(51.0), 50.0);
+            assert_eq!(pulse.get_value_at(52.0), 0.0);
+        }
+
+        // The shape values are restored when the pulses are replayed from ground truth.
+        let mut buffer = Vec::<u8>::new();
+        let mut writer = GroundTruthWriter::new(&mut buffer);
+        for (index, event_list) in event_lists.iter().enumerate() {
+            writer.write(0, index, event_list).unwrap();
+        }
+        let ground_truth = GroundTruth::from_reader(buffer.as_slice(), &simulation.pulses).unwrap();
+        for (index, event_list) in event_lists.iter().enumerate() {
+            let replayed = ground_truth.event_list(0, index).unwrap();
+            assert_eq!(replayed.len(), 5);
+            for (original, replayed) in event_list.pulses.iter().zip(replayed) {
+                assert_eq!(original, &replayed.event);
+            }
+        }
+    }
+
+    #[test]
+    fn from_file_errors_on_load() {
+        let mut simulation: Simulation = serde_json::from_str(&from_file_json(
+            &std::env::temp_dir().join("simulator-pulse-shapes-test-missing.csv"),
+        ))
+        .unwrap();
+        assert!(matches!(
+            simulation.load_pulse_shapes(),
+            Err(PulseShapesError::IO(_))
+        ));
+
+        // The first template has two weights, so the file must have two shapes.
+        let fixture = PulseShapesFixture::new("weights", "0, 2, 4, 2\n");
+        let mut simulation: Simulation = serde_json::from_str(&from_file_json(&fixture.0)).unwrap();
+        assert!(matches!(
+            simulation.load_pulse_shapes(),
+            Err(PulseShapesError::WeightsMismatch(2, 1))
+        ));
+    }
 }
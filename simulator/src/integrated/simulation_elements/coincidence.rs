@@ -0,0 +1,300 @@
+//! Duplicates pulses onto neighbouring channels, as when a muon stopping near the boundary of two detectors fires both.
+use super::{EventList, FloatRandomDistribution, utils::JsonValueError};
+use digital_muon_common::Channel;
+use rand::RngExt;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+fn adjacent_channels() -> Vec<i32> {
+    vec![-1, 1]
+}
+
+/// Determines how pulses of event lists are duplicated onto those of neighbouring channels of the same digitiser.
+///
+/// Each pulse generated for a channel is duplicated, with the sampled `probability`, onto one of its neighbours, chosen uniformly.
+/// The original keeps the sampled `amplitude-fraction` of its amplitude, which is clamped between zero and one, and the duplicate
+/// has the remainder, so together they have the amplitude of the original. The duplicate is also delayed by the sampled `time-offset`, in ns.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Coincidence {
+    /// The probability that each pulse is duplicated onto a neighbouring channel.
+    probability: FloatRandomDistribution<f64>,
+    /// The offsets of the neighbours of each channel, by channel id. Only channels of the same digitiser,
+    /// whose event lists are generated together, are neighbours, so a pulse of a channel with no neighbours is never duplicated.
+    #[serde(default = "adjacent_channels")]
+    neighbour_offsets: Vec<i32>,
+    /// The fraction of the amplitude of a duplicated pulse which it keeps, the remainder is given to its duplicate.
+    amplitude_fraction: FloatRandomDistribution<f64>,
+    /// How much later, in ns, the duplicate of a pulse is than the original.
+    time_offset: FloatRandomDistribution<f64>,
+}
+
+impl Coincidence {
+    /// Duplicates the pulses of `event_lists` onto their neighbours.
+    ///
+    /// The event list at position `p` within the frame is that of the `p`th channel of `layout`, counting on from
+    /// the first channel once every channel has an event list, so pulses are only duplicated between channels of one digitiser.
+    /// Only the pulses originally generated for each list are duplicated, not those duplicated onto it,
+    /// and each list remains in order of the start of its pulses.
+    ///
+    /// # Parameters
+    /// - first: the position, within the frame, of the first of `event_lists`.
+    /// - layout: the channels of each digitiser, see [DigitiserConfig::channel_layout].
+    ///
+    /// [DigitiserConfig::channel_layout]: super::DigitiserConfig::channel_layout
+    pub(crate) fn apply(
+        &self,
+        event_lists: &mut [EventList<'_>],
+        first: usize,
+        layout: &[Vec<Channel>],
+        frame_index: usize,
+    ) -> Result<(), JsonValueError> {
+        let channels = layout
+            .iter()
+            .enumerate()
+            .flat_map(|(digitiser, channels)| {
+                channels.iter().map(move |&channel| (digitiser, channel))
+            })
+            .collect::<Vec<_>>();
+        if channels.is_empty() {
+            return Ok(());
+        }
+        let placed = (first..first + event_lists.len())
+            .map(|position| channels[position % channels.len()])
+            .collect::<Vec<_>>();
+        let index_of = placed
+            .iter()
+            .copied()
+            .zip(0..)
+            .collect::<HashMap<_, usize>>();
+
+        let probability = self.probability.sample(frame_index)?;
+        let mut rng = rand::rng();
+        let mut duplicates = Vec::new();
+        for (index, &(digitiser, channel)) in placed.iter().enumerate() {
+            let neighbours = self
+                .neighbour_offsets
+                .iter()
+                .filter_map(|&offset| channel.checked_add_signed(offset))
+                .filter_map(|neighbour| index_of.get(&(digitiser, neighbour)).copied())
+                .filter(|&neighbour| neighbour != index)
+                .collect::<Vec<_>>();
+            if neighbours.is_empty() {
+                continue;
+            }
+            let event_list = &mut event_lists[index];
+            for (pulse, pulse_index) in event_list
+                .pulses
+                .iter_mut()
+                .zip(event_list.pulse_indices.iter().copied())
+            {
+                if rng.random::<f64>() >= probability {
+                    continue;
+                }
+                let neighbour = neighbours[rng.random_range(..neighbours.len())];
+                let fraction = self.amplitude_fraction.sample(frame_index)?.clamp(0.0, 1.0);
+                let duplicate = pulse
+                    .scaled(1.0 - fraction)
+                    .shifted(self.time_offset.sample(frame_index)?);
+                *pulse = pulse.scaled(fraction);
+                duplicates.push((neighbour, pulse_index, duplicate));
+            }
+        }
+
+        let mut changed = vec![false; event_lists.len()];
+        for (neighbour, pulse_index, duplicate) in duplicates {
+            let event_list = &mut event_lists[neighbour];
+            event_list.pulses.push(duplicate);
+            event_list.pulse_indices.push(pulse_index);
+            changed[neighbour] = true;
+        }
+        for (event_list, _) in event_lists
+            .iter_mut()
+            .zip(changed)
+            .filter(|(_, changed)| *changed)
+        {
+            event_list.sort_pulses();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::integrated::simulation::Simulation;
+    use digital_muon_common::{Intensity, Time};
+
+    fn simulation(coincidence: &str) -> Simulation {
+        simulation_with_digitisers(1, 4, coincidence)
+    }
+
+    fn simulation_with_digitisers(
+        num_digitisers: usize,
+        num_channels_per_digitiser: usize,
+        coincidence: &str,
+    ) -> Simulation {
+        serde_json::from_str(&format!(
+            r#"
+            {{
+                "voltage-transformation": {{ "scale": 1, "translate": 0 }},
+                "time-bins": {{ "const": 30000 }},
+                "sample-rate": {{ "const": 1000000000 }},
+                "digitiser-config": {{
+                    "auto-digitisers": {{
+                        "num-digitisers": {{ "const": {num_digitisers} }},
+                        "num-channels-per-digitiser": {{ "const": {num_channels_per_digitiser} }}
+                    }}
+                }},
+                "pulses": [{{
+                    "pulse-type": "flat",
+                    "start":  {{ "random-type": "uniform-float", "min": {{ "const": 100 }}, "max": {{ "const": 20000 }} }},
+                    "width":  {{ "random-type": "constant-float", "value": {{ "const": 20 }} }},
+                    "height": {{ "random-type": "constant-float", "value": {{ "const": 100 }} }}
+                }}],
+                "event-lists": [{{
+                    "pulses": [{{ "weight": 1, "pulse-index": 0 }}],
+                    "noises": [],
+                    "num-pulses": {{ "random-type": "constant-int", "value": {{ "const": 10 }} }},
+                    "coincidence": {coincidence}
+                }}],
+                "schedule": []
+            }}
+            "#
+        ))
+        .unwrap()
+    }
+
+    /// Returns the times of the pulses of `pulses` with the given intensity, in order.
+    fn times_with_intensity(pulses: &[(Time, Intensity)], intensity: Intensity) -> Vec<Time> {
+        let mut times = pulses
+            .iter()
+            .filter(|(_, i)| *i == intensity)
+            .map(|(time, _)| *time)
+            .collect::<Vec<_>>();
+        times.sort();
+        times
+    }
+
+    #[test]
+    fn every_pulse_is_mirrored_onto_neighbour() {
+        let simulation = simulation(
+            r#"{
+                "probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "neighbour-offsets": [1],
+                "amplitude-fraction": { "random-type": "constant-float", "value": { "const": 0.25 } },
+                "time-offset": { "random-type": "constant-float", "value": { "const": 5 } }
+            }"#,
+        );
+        let event_lists = simulation.generate_event_lists(0, 0, 0, 4).unwrap();
+        let pulses = event_lists
+            .iter()
+            .map(|event_list| {
+                assert!(
+                    event_list
+                        .pulses
+                        .is_sorted_by_key(|pulse| pulse.get_start())
+                );
+                assert_eq!(event_list.pulses.len(), event_list.pulse_indices.len());
+                event_list
+                    .pulses
+                    .iter()
+                    .map(|pulse| (pulse.time(), pulse.intensity()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // The last channel has no neighbour, so keeps the whole amplitude of its pulses.
+        assert_eq!(times_with_intensity(&pulses[3], 100).len(), 10);
+        assert_eq!(times_with_intensity(&pulses[0], 75).len(), 0);
+        for channel in 0..3 {
+            let originals = times_with_intensity(&pulses[channel], 25);
+            assert_eq!(originals.len(), 10);
+            let mirrored = times_with_intensity(&pulses[channel + 1], 75);
+            assert_eq!(
+                mirrored,
+                originals.iter().map(|time| time + 5).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn zero_probability_generates_independent_channels() {
+        let simulation = simulation(
+            r#"{
+                "probability": { "random-type": "constant-float", "value": { "const": 0 } },
+                "amplitude-fraction": { "random-type": "constant-float", "value": { "const": 0.5 } },
+                "time-offset": { "random-type": "constant-float", "value": { "const": 0 } }
+            }"#,
+        );
+        for event_list in simulation.generate_event_lists(0, 0, 0, 4).unwrap() {
+            assert_eq!(event_list.pulses.len(), 10);
+            assert!(
+                event_list
+                    .pulses
+                    .iter()
+                    .all(|pulse| pulse.intensity() == 100)
+            );
+        }
+    }
+
+    #[test]
+    fn single_channel_is_never_duplicated() {
+        let simulation = simulation(
+            r#"{
+                "probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "amplitude-fraction": { "random-type": "constant-float", "value": { "const": 0.5 } },
+                "time-offset": { "random-type": "constant-float", "value": { "const": 0 } }
+            }"#,
+        );
+        let event_lists = simulation.generate_event_lists(0, 0, 0, 1).unwrap();
+        assert_eq!(event_lists[0].pulses.len(), 10);
+        assert!(
+            event_lists[0]
+                .pulses
+                .iter()
+                .all(|pulse| pulse.intensity() == 100)
+        );
+    }
+
+    #[test]
+    fn pulses_are_never_duplicated_onto_another_digitiser() {
+        let simulation = simulation_with_digitisers(
+            2,
+            2,
+            r#"{
+                "probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "neighbour-offsets": [1],
+                "amplitude-fraction": { "random-type": "constant-float", "value": { "const": 0.25 } },
+                "time-offset": { "random-type": "constant-float", "value": { "const": 0 } }
+            }"#,
+        );
+        // The lists of the second digitiser follow those of the first, whether generated together or not.
+        let together = simulation.generate_event_lists(0, 0, 0, 4).unwrap();
+        let separately = [
+            simulation.generate_event_lists(0, 0, 0, 2).unwrap(),
+            simulation.generate_event_lists(0, 0, 2, 2).unwrap(),
+        ]
+        .concat();
+        for event_lists in [together, separately] {
+            let intensities = event_lists
+                .iter()
+                .map(|event_list| {
+                    let mut intensities = event_list
+                        .pulses
+                        .iter()
+                        .map(|pulse| pulse.intensity())
+                        .collect::<Vec<_>>();
+                    intensities.sort();
+                    intensities.dedup();
+                    intensities
+                })
+                .collect::<Vec<_>>();
+            // The last channel of each digitiser has no neighbour, so keeps the whole amplitude of its pulses,
+            // and the first is duplicated onto by no channel.
+            assert_eq!(
+                intensities,
+                [vec![25], vec![75, 100], vec![25], vec![75, 100]]
+            );
+        }
+    }
+}
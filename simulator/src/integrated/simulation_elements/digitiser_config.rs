@@ -1,25 +1,88 @@
 use crate::integrated::{
     simulation_elements::{
-        Interval,
+        FloatRandomDistribution, Interval, Transformation,
         utils::{JsonValueError, NumConstant},
     },
     simulation_engine::engine::SimulationEngineDigitiser,
 };
 use digital_muon_common::{Channel, DigitizerId};
 use serde::Deserialize;
+use thiserror::Error;
 use tracing::instrument;
 
+#[derive(Debug, Error)]
+pub(crate) enum DigitiserConfigError {
+    #[error("Json Value Error: {0}")]
+    JsonValue(#[from] JsonValueError),
+    #[error("Digitiser {0} has {1} channels but {2} channel transformations")]
+    ChannelTransformationCount(DigitizerId, usize, usize),
+}
+
+/// Random spread of the per-channel transformations, which are applied after the global `voltage-transformation`.
+/// Each channel's gain and offset are sampled once, when the channels are generated.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ChannelSpread {
+    /// If present, the gain of each channel is sampled from this, otherwise it is one.
+    #[serde(default)]
+    pub(crate) gain_spread: Option<FloatRandomDistribution<f64>>,
+    /// If present, the offset of each channel is sampled from this, otherwise it is zero.
+    #[serde(default)]
+    pub(crate) offset_spread: Option<FloatRandomDistribution<f64>>,
+}
+
+impl ChannelSpread {
+    fn sample(&self) -> Result<Transformation<f64>, JsonValueError> {
+        Ok(Transformation {
+            scale: self
+                .gain_spread
+                .as_ref()
+                .map(|gain| gain.sample(0))
+                .transpose()?
+                .unwrap_or(1.0),
+            translate: self
+                .offset_spread
+                .as_ref()
+                .map(|offset| offset.sample(0))
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Perturbation of the timestamps of a digitiser's trace messages, which models the offset and jitter of its clock.
+/// Both are in nanoseconds, and only the timestamp is perturbed, the frame number is unchanged.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DigitiserTiming {
+    /// If present, the constant offset of the digitiser's clock is sampled from this once, when the digitiser is generated.
+    #[serde(default)]
+    pub(crate) skew_ns: Option<FloatRandomDistribution<f64>>,
+    /// If present, an offset is sampled from this for each trace message, in addition to the skew.
+    #[serde(default)]
+    pub(crate) jitter_ns: Option<FloatRandomDistribution<f64>>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum DigitiserConfig {
     #[serde(rename_all = "kebab-case")]
-    AutoAggregatedFrame { num_channels: NumConstant<usize> },
+    AutoAggregatedFrame {
+        num_channels: NumConstant<usize>,
+        #[serde(flatten)]
+        spread: ChannelSpread,
+    },
     #[serde(rename_all = "kebab-case")]
     ManualAggregatedFrame { channels: Vec<Channel> },
     #[serde(rename_all = "kebab-case")]
     AutoDigitisers {
         num_digitisers: NumConstant<usize>,
         num_channels_per_digitiser: NumConstant<usize>,
+        #[serde(flatten)]
+        spread: ChannelSpread,
+        /// Applies to every digitiser, each of which samples its own skew.
+        #[serde(default)]
+        timing: DigitiserTiming,
     },
     #[serde(rename_all = "kebab-case")]
     ManualDigitisers(Vec<Digitiser>),
@@ -29,13 +92,14 @@ impl DigitiserConfig {
     #[instrument(skip_all)]
     pub(crate) fn generate_channels(&self) -> Result<Vec<Channel>, JsonValueError> {
         let channels = match self {
-            DigitiserConfig::AutoAggregatedFrame { num_channels } => {
+            DigitiserConfig::AutoAggregatedFrame { num_channels, .. } => {
                 (0..num_channels.value()? as Channel).collect()
             }
             DigitiserConfig::ManualAggregatedFrame { channels } => channels.clone(),
             DigitiserConfig::AutoDigitisers {
                 num_digitisers,
                 num_channels_per_digitiser,
+                ..
             } => (0..((num_digitisers.value()? * num_channels_per_digitiser.value()?) as Channel))
                 .collect(),
             DigitiserConfig::ManualDigitisers(digitisers) => digitisers
@@ -56,25 +120,112 @@ impl DigitiserConfig {
             DigitiserConfig::AutoDigitisers {
                 num_digitisers,
                 num_channels_per_digitiser,
+                timing,
+                ..
             } => (0..num_digitisers.value()?)
                 .map(|d| {
-                    Ok(SimulationEngineDigitiser::new(
+                    SimulationEngineDigitiser::new(
                         d as DigitizerId,
                         ((d * num_channels_per_digitiser.value()?)
                             ..((d + 1) * num_channels_per_digitiser.value()?))
                             .collect(),
-                    ))
+                    )
+                    .with_timing(timing)
                 })
                 .collect::<Result<_, JsonValueError>>()?,
+            DigitiserConfig::ManualDigitisers(digitisers) => {
+                // Channel indices are assigned in the order given by [Self::generate_channels].
+                let mut first_index = 0;
+                digitisers
+                    .iter()
+                    .map(|digitiser| {
+                        let num_channels = digitiser.channels.range_inclusive().count();
+                        let channel_indices = (first_index..first_index + num_channels).collect();
+                        first_index += num_channels;
+                        SimulationEngineDigitiser::new(digitiser.id, channel_indices)
+                            .with_timing(&digitiser.timing)
+                    })
+                    .collect::<Result<_, JsonValueError>>()?
+            }
+        };
+        Ok(digitisers)
+    }
+
+    /// Returns the channels of each digitiser, in the order given by [Self::generate_channels].
+    /// Aggregated frame configs have no digitisers, so their channels are returned as one group.
+    pub(crate) fn channel_layout(&self) -> Result<Vec<Vec<Channel>>, JsonValueError> {
+        let layout = match self {
+            DigitiserConfig::AutoAggregatedFrame { .. }
+            | DigitiserConfig::ManualAggregatedFrame { .. } => vec![self.generate_channels()?],
+            DigitiserConfig::AutoDigitisers {
+                num_channels_per_digitiser,
+                ..
+            } => self
+                .generate_channels()?
+                .chunks(num_channels_per_digitiser.value()?.max(1))
+                .map(<[Channel]>::to_vec)
+                .collect(),
             DigitiserConfig::ManualDigitisers(digitisers) => digitisers
                 .iter()
-                .map(|digitiser| SimulationEngineDigitiser {
-                    id: digitiser.id,
-                    channel_indices: Vec::<_>::new(), //TODO
-                })
+                .map(|digitiser| digitiser.channels.range_inclusive().collect())
                 .collect(),
         };
-        Ok(digitisers)
+        Ok(layout)
+    }
+
+    /// Returns the number of channels of the digitiser with the most channels,
+    /// this is zero for aggregated frame configs, as these have no digitisers.
+    pub(crate) fn max_channels_per_digitiser(&self) -> Result<usize, JsonValueError> {
+        let max_channels = match self {
+            DigitiserConfig::AutoAggregatedFrame { .. }
+            | DigitiserConfig::ManualAggregatedFrame { .. } => 0,
+            DigitiserConfig::AutoDigitisers {
+                num_channels_per_digitiser,
+                ..
+            } => num_channels_per_digitiser.value()?,
+            DigitiserConfig::ManualDigitisers(digitisers) => digitisers
+                .iter()
+                .map(|digitiser| digitiser.channels.range_inclusive().count())
+                .max()
+                .unwrap_or_default(),
+        };
+        Ok(max_channels)
+    }
+
+    /// Returns the transformation of each channel, in the order given by [Self::generate_channels].
+    #[instrument(skip_all)]
+    pub(crate) fn generate_channel_transformations(
+        &self,
+    ) -> Result<Vec<Transformation<f64>>, DigitiserConfigError> {
+        let num_channels = self.generate_channels()?.len();
+        let transformations = match self {
+            DigitiserConfig::AutoAggregatedFrame { spread, .. }
+            | DigitiserConfig::AutoDigitisers { spread, .. } => (0..num_channels)
+                .map(|_| spread.sample())
+                .collect::<Result<_, _>>()?,
+            DigitiserConfig::ManualAggregatedFrame { .. } => {
+                vec![Transformation::default(); num_channels]
+            }
+            DigitiserConfig::ManualDigitisers(digitisers) => digitisers
+                .iter()
+                .map(|digitiser| {
+                    let num_channels = digitiser.channels.range_inclusive().count();
+                    if digitiser.channel_transformations.is_empty() {
+                        Ok(vec![Transformation::default(); num_channels])
+                    } else if digitiser.channel_transformations.len() == num_channels {
+                        Ok(digitiser.channel_transformations.clone())
+                    } else {
+                        Err(DigitiserConfigError::ChannelTransformationCount(
+                            digitiser.id,
+                            num_channels,
+                            digitiser.channel_transformations.len(),
+                        ))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .concat(),
+        };
+        Ok(transformations)
     }
 }
 
@@ -83,4 +234,119 @@ impl DigitiserConfig {
 pub(crate) struct Digitiser {
     pub(crate) id: DigitizerId,
     pub(crate) channels: Interval<Channel>,
+    /// If given, there must be one for each channel, applied after the global `voltage-transformation`.
+    #[serde(default)]
+    pub(crate) channel_transformations: Vec<Transformation<f64>>,
+    /// If set, the channels of this digitiser may overlap those of earlier digitisers.
+    #[serde(default)]
+    pub(crate) allow_overlapping_channels: bool,
+    #[serde(default)]
+    pub(crate) timing: DigitiserTiming,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta};
+    use digital_muon_streaming_types::FrameMetadata;
+
+    #[test]
+    fn channel_transformations_are_optional() {
+        let config: DigitiserConfig = serde_json::from_str(
+            r#"{ "manual-digitisers": [{ "id": 0, "channels": { "min": 4, "max": 6 } }] }"#,
+        )
+        .unwrap();
+        let transformations = config.generate_channel_transformations().unwrap();
+        assert_eq!(transformations.len(), 3);
+        assert!(
+            transformations
+                .iter()
+                .all(|t| t.scale == 1.0 && t.translate == 0.0)
+        );
+
+        let config: DigitiserConfig = serde_json::from_str(
+            r#"{ "auto-digitisers": {
+                "num-digitisers": { "const": 2 },
+                "num-channels-per-digitiser": { "const": 4 },
+                "gain-spread": { "random-type": "uniform-float", "min": { "const": 0.9 }, "max": { "const": 1.1 } }
+            } }"#,
+        )
+        .unwrap();
+        let transformations = config.generate_channel_transformations().unwrap();
+        assert_eq!(transformations.len(), 8);
+        assert!(
+            transformations
+                .iter()
+                .all(|t| (0.9..=1.1).contains(&t.scale) && t.translate == 0.0)
+        );
+    }
+
+    #[test]
+    fn max_channels_per_digitiser() {
+        let config: DigitiserConfig = serde_json::from_str(
+            r#"{ "manual-digitisers": [
+                { "id": 0, "channels": { "min": 0, "max": 3 } },
+                { "id": 1, "channels": { "min": 4, "max": 10 } }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_channels_per_digitiser().unwrap(), 7);
+
+        let config: DigitiserConfig =
+            serde_json::from_str(r#"{ "manual-aggregated-frame": { "channels": [0, 1, 2] } }"#)
+                .unwrap();
+        assert_eq!(config.max_channels_per_digitiser().unwrap(), 0);
+    }
+
+    #[test]
+    fn constant_skew_offsets_every_timestamp() {
+        let config: DigitiserConfig = serde_json::from_str(
+            r#"{ "manual-digitisers": [
+                { "id": 0, "channels": { "min": 0, "max": 3 } },
+                { "id": 1, "channels": { "min": 4, "max": 7 }, "timing": {
+                    "skew-ns": { "random-type": "constant-float", "value": { "const": 1500 } }
+                } },
+                { "id": 2, "channels": { "min": 8, "max": 11 }, "timing": {
+                    "skew-ns": { "random-type": "constant-float", "value": { "const": -250 } },
+                    "jitter-ns": { "random-type": "uniform-float", "min": { "const": -100 }, "max": { "const": 100 } }
+                } }
+            ] }"#,
+        )
+        .unwrap();
+        let digitisers = config.generate_digitisers().unwrap();
+
+        let nominal = FrameMetadata {
+            timestamp: DateTime::from_timestamp_nanos(1_000_000_000),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 0,
+            veto_flags: 0,
+        };
+        for frame_number in 0..10 {
+            let nominal = FrameMetadata {
+                frame_number,
+                timestamp: nominal.timestamp + TimeDelta::milliseconds(20 * frame_number as i64),
+                ..nominal.clone()
+            };
+            let offsets = digitisers
+                .iter()
+                .map(|digitiser| {
+                    let metadata = digitiser.clock_metadata(&nominal).unwrap();
+                    assert_eq!(
+                        FrameMetadata {
+                            timestamp: nominal.timestamp,
+                            ..metadata.clone()
+                        },
+                        nominal
+                    );
+                    (metadata.timestamp - nominal.timestamp)
+                        .num_nanoseconds()
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(offsets[..2], [0, 1500]);
+            assert!((-350..=-150).contains(&offsets[2]), "{offsets:?}");
+        }
+    }
 }
@@ -2,26 +2,76 @@ use crate::integrated::{
     active_pulses::ActivePulses,
     simulation::{Simulation, SimulationError},
     simulation_elements::{
-        IntRandomDistribution,
-        noise::{Noise, NoiseSource},
+        IntRandomDistribution, Transformation,
+        coincidence::Coincidence,
+        ground_truth::{GroundTruth, GroundTruthPulse},
+        noise::{DigitiserNoise, Noise, NoiseScope, NoiseSource},
+        overflow::{IntensityOverflow, OnOverflow},
         pulses::PulseEvent,
         utils::JsonValueError,
     },
 };
+use chrono::Utc;
 use digital_muon_common::{
-    FrameNumber, Intensity,
+    FrameNumber, Intensity, Time,
     spanned::{SpanOnce, Spanned},
 };
-use rand::distr::weighted::WeightedIndex;
+use rand::{SeedableRng, distr::weighted::WeightedIndex, rngs::StdRng};
 use serde::Deserialize;
+use std::{mem, path::PathBuf};
+use thiserror::Error;
 use tracing::instrument;
 
-pub(crate) struct Trace {
+#[derive(Debug, Error)]
+pub(crate) enum TraceError {
+    #[error("Json Value Error: {0}")]
+    JsonValue(#[from] JsonValueError),
+    #[error("{0}")]
+    Overflow(#[from] IntensityOverflow),
+}
+
+/// A trace, ready to be generated.
+///
+/// As the intensities of a trace take memory proportional to `time-bins`, they are not stored,
+/// but generated by [Self::generate_intensities_with] when the trace is written into a message.
+/// Its noise is sampled from seeds drawn when the trace is created, so the trace is the same each time it is generated.
+pub(crate) struct Trace<'a> {
     span: SpanOnce,
-    intensities: Vec<Intensity>,
+    time_bins: Time,
+    /// Sample time in ns.
+    sample_time: f64,
+    frame_number: FrameNumber,
+    voltage_transformation: &'a Transformation<f64>,
+    on_overflow: OnOverflow,
+    pulses: Vec<PulseEvent>,
+    noises: &'a [NoiseSource],
+    /// The seed of the per-channel noise of the trace.
+    channel_noise_seed: u64,
+    /// The seed of any per-digitiser noise first appearing in the trace, see [Self::generate_digitiser_noise].
+    digitiser_noise_seed: u64,
+    /// The time and intensity of each pulse injected into the trace.
+    ground_truth: Vec<(Time, Intensity)>,
 }
 
-impl Trace {
+impl<'a> Clone for Trace<'a> {
+    fn clone(&self) -> Self {
+        Self {
+            span: SpanOnce::Spanned(tracing::Span::current()),
+            time_bins: self.time_bins,
+            sample_time: self.sample_time,
+            frame_number: self.frame_number,
+            voltage_transformation: self.voltage_transformation,
+            on_overflow: self.on_overflow,
+            pulses: self.pulses.clone(),
+            noises: self.noises,
+            channel_noise_seed: self.channel_noise_seed,
+            digitiser_noise_seed: self.digitiser_noise_seed,
+            ground_truth: self.ground_truth.clone(),
+        }
+    }
+}
+
+impl<'a> Trace<'a> {
     #[instrument(
         skip_all,
         level = "debug",
@@ -34,42 +84,128 @@ impl Trace {
         err(level = "error")
     )]
     pub(crate) fn new(
-        simulation: &Simulation,
+        simulation: &'a Simulation,
         frame_number: FrameNumber,
-        event_list: &EventList<'_>,
+        event_list: &EventList<'a>,
     ) -> Result<Self, JsonValueError> {
-        let mut noise = event_list.noises.iter().map(Noise::new).collect::<Vec<_>>();
-        let mut active_pulses = ActivePulses::new(&event_list.pulses);
-        let sample_time = 1_000_000_000.0 / simulation.sample_rate.value()? as f64;
+        let noise_seed = Utc::now().timestamp_subsec_nanos() as u64;
         Ok(Self {
             span: SpanOnce::Spanned(tracing::Span::current()),
-            intensities: (0..simulation.time_bins.value()?)
-                .map(|time| {
-                    //  Remove any expired muons
-                    active_pulses.drop_spent_muons(time);
-                    //  Append any new muons
-                    active_pulses.push_new_muons(time);
-
-                    //  Sum the signal of the currenty active muons
-                    let signal = active_pulses
+            time_bins: simulation.time_bins.value()?,
+            sample_time: 1_000_000_000.0 / simulation.sample_rate.value()? as f64,
+            frame_number,
+            voltage_transformation: &simulation.voltage_transformation,
+            on_overflow: simulation.on_overflow,
+            pulses: event_list.pulses.clone(),
+            noises: event_list.noises,
+            channel_noise_seed: noise_seed,
+            //  Differs from the channel seed, so that per-digitiser noise is independent of per-channel noise.
+            digitiser_noise_seed: noise_seed ^ u64::MAX,
+            ground_truth: event_list
+                .pulses
+                .iter()
+                .map(|pulse| (pulse.time(), pulse.intensity()))
+                .collect(),
+        })
+    }
+
+    /// Generates the samples of the per-digitiser noise sources of `traces`, the traces of the channels of one digitiser message.
+    /// A source shared by several traces is sampled with the time bins, frame number and seed of the first of them.
+    pub(crate) fn generate_digitiser_noise<'t>(
+        traces: impl IntoIterator<Item = &'t Self>,
+    ) -> Result<DigitiserNoise<'a>, JsonValueError>
+    where
+        'a: 't,
+    {
+        DigitiserNoise::generate(traces.into_iter().map(|trace| {
+            (
+                trace.noises,
+                trace.time_bins,
+                trace.frame_number as usize,
+                trace.digitiser_noise_seed,
+            )
+        }))
+    }
+
+    /// Generates the intensities of the trace, as if it were the only channel of its digitiser, with no channel transformation.
+    #[cfg(test)]
+    pub(crate) fn generate_intensities(&self) -> Result<Vec<Intensity>, TraceError> {
+        let (intensities, _) = self.generate_intensities_with(
+            &Self::generate_digitiser_noise([self])?,
+            &Transformation::default(),
+        )?;
+        Ok(intensities)
+    }
+
+    /// Generates the intensities of the trace, which are the same on each call.
+    ///
+    /// Each value is converted to an [Intensity] after the `voltage-transformation`, and again after `channel_transformation`,
+    /// any value out of range being handled according to the simulation's `on-overflow`.
+    /// # Parameters
+    /// - digitiser_noise: the per-digitiser noise of the trace's digitiser message, which is added to the intensities.
+    /// - channel_transformation: the transformation of the channel into which the trace is written.
+    ///
+    /// # Returns
+    /// The intensities, and the number of them which were out of range at either conversion.
+    #[instrument(skip_all, level = "debug", err(level = "error"))]
+    pub(crate) fn generate_intensities_with(
+        &self,
+        digitiser_noise: &DigitiserNoise<'_>,
+        channel_transformation: &Transformation<f64>,
+    ) -> Result<(Vec<Intensity>, usize), TraceError> {
+        let (shared, per_channel): (Vec<_>, Vec<_>) = self
+            .noises
+            .iter()
+            .partition(|source| source.scope() == NoiseScope::PerDigitiser);
+        let shared = shared
+            .into_iter()
+            .map(|source| digitiser_noise.samples(source))
+            .collect::<Vec<_>>();
+        let mut noise = per_channel.into_iter().map(Noise::new).collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(self.channel_noise_seed);
+        let mut active_pulses = ActivePulses::new(&self.pulses);
+        let mut clipped = 0;
+        let intensities = (0..self.time_bins)
+            .map(|time| {
+                //  Remove any expired muons
+                active_pulses.drop_spent_muons(time);
+                //  Append any new muons
+                active_pulses.push_new_muons(time);
+
+                //  Sum the signal of the currenty active muons
+                let signal = active_pulses
+                    .iter()
+                    .map(|p| p.get_value_at(time as f64 * self.sample_time))
+                    .sum::<f64>();
+                let val = noise.iter_mut().try_fold(signal, |signal, n| {
+                    n.noisify(signal, time, self.frame_number as usize, &mut rng)
+                })?;
+                let val = val
+                    + shared
                         .iter()
-                        .map(|p| p.get_value_at(time as f64 * sample_time))
+                        .filter_map(|samples| samples.get(time as usize))
                         .sum::<f64>();
-                    let val = noise.iter_mut().try_fold(signal, |signal, n| {
-                        n.noisify(signal, time, frame_number as usize)
-                    })?;
-                    Ok(simulation.voltage_transformation.transform(val) as Intensity)
-                })
-                .collect::<Result<_, JsonValueError>>()?,
-        })
+                let (intensity, simulation_clipped) = self
+                    .on_overflow
+                    .convert(self.voltage_transformation.transform(val), time)?;
+                let (intensity, channel_clipped) = self
+                    .on_overflow
+                    .convert(channel_transformation.transform(intensity as f64), time)?;
+                if simulation_clipped || channel_clipped {
+                    clipped += 1;
+                }
+                Ok(intensity)
+            })
+            .collect::<Result<_, TraceError>>()?;
+        Ok((intensities, clipped))
     }
 
-    pub(crate) fn get_intensities(&self) -> &[Intensity] {
-        &self.intensities
+    pub(crate) fn get_ground_truth(&self) -> &[(Time, Intensity)] {
+        &self.ground_truth
     }
 }
 
-impl Spanned for Trace {
+impl Spanned for Trace<'_> {
     fn span(&self) -> &SpanOnce {
         &self.span
     }
@@ -85,15 +221,41 @@ pub(crate) struct EventPulseTemplate {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct EventListTemplate {
-    pub(crate) pulses: Vec<EventPulseTemplate>,
+    #[serde(flatten)]
+    pub(crate) source: EventListSource,
     pub(crate) noises: Vec<NoiseSource>,
-    pub(crate) num_pulses: IntRandomDistribution<i32>,
+    /// If present, pulses are duplicated onto neighbouring channels, see [Coincidence].
+    /// This only applies to pulses sampled from the pulse templates, as replayed pulses already include any duplicates.
+    #[serde(default)]
+    pub(crate) coincidence: Option<Coincidence>,
+}
+
+/// Determines where the pulses of an event list come from.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum EventListSource {
+    /// Pulses are sampled from the weighted pulse templates.
+    #[serde(rename_all = "kebab-case")]
+    Random {
+        pulses: Vec<EventPulseTemplate>,
+        num_pulses: IntRandomDistribution<i32>,
+    },
+    /// Pulses are replayed from a ground truth file written by a previous run.
+    /// The file is loaded by [Simulation::load_ground_truth].
+    #[serde(rename_all = "kebab-case")]
+    FromGroundTruth {
+        from_ground_truth: PathBuf,
+        #[serde(skip)]
+        ground_truth: GroundTruth,
+    },
 }
 
 #[derive(Default)]
 pub(crate) struct EventList<'a> {
     pub(crate) span: SpanOnce,
     pub(crate) pulses: Vec<PulseEvent>,
+    /// The index of the pulse template each pulse was sampled from.
+    pub(crate) pulse_indices: Vec<usize>,
     pub(crate) noises: &'a [NoiseSource],
 }
 
@@ -102,6 +264,7 @@ impl<'a> Clone for EventList<'a> {
         Self {
             span: SpanOnce::Spanned(tracing::Span::current()),
             pulses: self.pulses.clone(),
+            pulse_indices: self.pulse_indices.clone(),
             noises: self.noises,
         }
     }
@@ -112,40 +275,71 @@ impl<'a> EventList<'a> {
     pub(crate) fn new(
         simulator: &Simulation,
         frame_number: FrameNumber,
-        source: &'a EventListTemplate,
+        pulses: &[EventPulseTemplate],
+        num_pulses: &IntRandomDistribution<i32>,
+        noises: &'a [NoiseSource],
     ) -> Result<Self, SimulationError> {
-        let pulses = {
-            let weighted_distribution = if source.pulses.is_empty() {
+        let (pulse_indices, pulses) = {
+            let weighted_distribution = if pulses.is_empty() {
                 None
             } else {
                 // This will never panic
                 Some(
-                    WeightedIndex::new(source.pulses.iter().map(|p| p.weight))
+                    WeightedIndex::new(pulses.iter().map(|p| p.weight))
                         .expect("Pulse should be non-empty, this never fails"),
                 )
             };
             // Creates a unique template for each channel
-            let mut pulses = (0..source.num_pulses.sample(frame_number as usize)? as usize)
+            let num_pulses = num_pulses.sample(frame_number as usize)?;
+            let num_pulses = usize::try_from(num_pulses)
+                .map_err(|_| JsonValueError::NegativeCount(num_pulses.into()))?;
+            let mut sampled = (0..num_pulses)
                 .map(|_| {
                     //  The below is only ever called when weighted_distribution is Some()
                     let weighted_distribution = weighted_distribution
                         .as_ref()
                         .expect("Pulse should be non-empty, this never fails");
-                    Ok(PulseEvent::sample(
-                        simulator.get_random_pulse_template(source, weighted_distribution)?,
-                        frame_number as usize,
-                    )?)
+                    let (pulse_index, template) =
+                        simulator.get_random_pulse_template(pulses, weighted_distribution)?;
+                    Ok((
+                        pulse_index,
+                        PulseEvent::sample(template, frame_number as usize)?,
+                    ))
                 })
                 .collect::<Result<Vec<_>, SimulationError>>()?;
-            pulses.sort_by_key(|a| a.get_start());
-            pulses
+            sampled.sort_by_key(|(_, a)| a.get_start());
+            sampled.into_iter().unzip()
         };
         Ok(Self {
             span: SpanOnce::Spanned(tracing::Span::current()),
             pulses,
-            noises: &source.noises,
+            pulse_indices,
+            noises,
         })
     }
+
+    /// Sorts the pulses, with the index of the pulse template of each, by their start.
+    pub(crate) fn sort_pulses(&mut self) {
+        let mut sorted = mem::take(&mut self.pulses)
+            .into_iter()
+            .zip(mem::take(&mut self.pulse_indices))
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|(pulse, _)| pulse.get_start());
+        (self.pulses, self.pulse_indices) = sorted.into_iter().unzip();
+    }
+
+    #[instrument(skip_all, level = "debug", "Replayed Event List")]
+    pub(crate) fn from_ground_truth(
+        pulses: &[GroundTruthPulse],
+        noises: &'a [NoiseSource],
+    ) -> Self {
+        Self {
+            span: SpanOnce::Spanned(tracing::Span::current()),
+            pulses: pulses.iter().map(|pulse| pulse.event.clone()).collect(),
+            pulse_indices: pulses.iter().map(|pulse| pulse.pulse_index).collect(),
+            noises,
+        }
+    }
 }
 
 impl Spanned for EventList<'_> {
@@ -0,0 +1,240 @@
+//! Injects faults into trace messages just before they are produced, to test the robustness of the pipeline.
+use super::{FloatRandomDistribution, NumExpression, utils::JsonValueError};
+use rand::RngExt;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+
+fn one_byte() -> NumExpression<usize> {
+    NumExpression::Const(1)
+}
+
+/// The probabilities with which each fault is injected into a trace message.
+/// Each probability is sampled for the frame of the message, and any which is absent is zero.
+///
+/// A message which is dropped has no other faults injected. Otherwise, it is truncated and corrupted,
+/// in that order, and then duplicated, in which case both copies carry the same faults.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct FaultInjection {
+    /// The probability that the message is not produced.
+    #[serde(default)]
+    pub(crate) drop_probability: Option<FloatRandomDistribution<f64>>,
+    /// The probability that the payload is truncated to a uniformly random, shorter, length.
+    #[serde(default)]
+    pub(crate) truncate_probability: Option<FloatRandomDistribution<f64>>,
+    /// The probability that `corrupt-bytes` distinct bytes of the payload are overwritten with different values.
+    #[serde(default)]
+    pub(crate) corrupt_probability: Option<FloatRandomDistribution<f64>>,
+    /// The number of bytes corrupted, this is capped at the length of the payload.
+    #[serde(default = "one_byte")]
+    pub(crate) corrupt_bytes: NumExpression<usize>,
+    /// The probability that the message is produced twice.
+    #[serde(default)]
+    pub(crate) duplicate_probability: Option<FloatRandomDistribution<f64>>,
+}
+
+/// The number of messages into which each fault has been injected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FaultCounts {
+    pub(crate) dropped: usize,
+    pub(crate) truncated: usize,
+    pub(crate) corrupted: usize,
+    pub(crate) duplicated: usize,
+}
+
+impl Display for FaultCounts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} dropped, {} truncated, {} corrupted, {} duplicated",
+            self.dropped, self.truncated, self.corrupted, self.duplicated
+        )
+    }
+}
+
+/// Applies a [FaultInjection], if present, and counts the faults it has injected.
+pub(crate) struct FaultInjector<'a> {
+    faults: Option<&'a FaultInjection>,
+    counts: FaultCounts,
+}
+
+impl<'a> FaultInjector<'a> {
+    /// Creates an injector, which injects no faults if `faults` is absent.
+    pub(crate) fn new(faults: Option<&'a FaultInjection>) -> Self {
+        Self {
+            faults,
+            counts: Default::default(),
+        }
+    }
+
+    pub(crate) fn counts(&self) -> &FaultCounts {
+        &self.counts
+    }
+
+    /// Injects faults into `payload`.
+    /// # Parameters
+    /// - frame_index: the frame at which the probabilities are sampled.
+    /// - payload: the message to inject faults into.
+    ///
+    /// # Returns
+    /// The payloads to produce, of which there are none if the message is dropped, or two if it is duplicated.
+    pub(crate) fn inject(
+        &mut self,
+        frame_index: usize,
+        mut payload: Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, JsonValueError> {
+        let Some(faults) = self.faults else {
+            return Ok(vec![payload]);
+        };
+        if occurs(&faults.drop_probability, frame_index)? {
+            self.counts.dropped += 1;
+            return Ok(Vec::new());
+        }
+        if occurs(&faults.truncate_probability, frame_index)? && !payload.is_empty() {
+            payload.truncate(rand::random_range(..payload.len()));
+            self.counts.truncated += 1;
+        }
+        if occurs(&faults.corrupt_probability, frame_index)? && !payload.is_empty() {
+            let amount = faults.corrupt_bytes.value(frame_index)?.min(payload.len());
+            for index in rand::seq::index::sample(&mut rand::rng(), payload.len(), amount) {
+                // Xor with a non-zero value, so the byte is always changed.
+                payload[index] ^= rand::random_range(1..=u8::MAX);
+            }
+            self.counts.corrupted += 1;
+        }
+        if occurs(&faults.duplicate_probability, frame_index)? {
+            self.counts.duplicated += 1;
+            return Ok(vec![payload.clone(), payload]);
+        }
+        Ok(vec![payload])
+    }
+}
+
+/// Samples `probability` at `frame_index`, and returns whether the fault occurs.
+/// Probabilities of at least one always occur, and those of at most zero never do.
+fn occurs(
+    probability: &Option<FloatRandomDistribution<f64>>,
+    frame_index: usize,
+) -> Result<bool, JsonValueError> {
+    match probability {
+        Some(probability) => Ok(rand::rng().random::<f64>() < probability.sample(frame_index)?),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAULT_INJECTION: &str = r#"
+    {
+        "drop-probability": { "random-type": "constant-float", "value": { "const": 0.1 } },
+        "truncate-probability": { "random-type": "uniform-float", "min": { "const": 0 }, "max": { "const": 0.5 } },
+        "corrupt-probability": { "random-type": "constant-float", "value": { "num-func": { "scale": 0.01, "translate": 0 } } },
+        "corrupt-bytes": { "const": 4 }
+    }
+    "#;
+
+    fn parse(json: &str) -> FaultInjection {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn deserialize_fault_injection() {
+        let faults = parse(FAULT_INJECTION);
+        assert!(matches!(
+            faults.drop_probability,
+            Some(FloatRandomDistribution::ConstantFloat { .. })
+        ));
+        assert!(matches!(
+            faults.truncate_probability,
+            Some(FloatRandomDistribution::UniformFloat { .. })
+        ));
+        assert!(matches!(
+            faults.corrupt_probability,
+            Some(FloatRandomDistribution::ConstantFloat {
+                value: NumExpression::NumFunc(_)
+            })
+        ));
+        assert_eq!(faults.corrupt_bytes.value(0).unwrap(), 4);
+        assert!(faults.duplicate_probability.is_none());
+    }
+
+    #[test]
+    fn absent_faults_default_to_none() {
+        let faults = parse("{}");
+        assert!(faults.drop_probability.is_none());
+        assert_eq!(faults.corrupt_bytes.value(0).unwrap(), 1);
+
+        let payload = vec![1, 2, 3];
+        let mut injector = FaultInjector::new(Some(&faults));
+        assert_eq!(injector.inject(0, payload.clone()).unwrap(), [payload]);
+        assert_eq!(injector.counts(), &FaultCounts::default());
+    }
+
+    #[test]
+    fn certain_faults_are_always_injected() {
+        let faults = parse(
+            r#"
+            {
+                "truncate-probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "duplicate-probability": { "random-type": "constant-float", "value": { "const": 1 } }
+            }
+            "#,
+        );
+        let payload = (0..=255).collect::<Vec<u8>>();
+        let mut injector = FaultInjector::new(Some(&faults));
+        for frame_index in 0..10 {
+            let payloads = injector.inject(frame_index, payload.clone()).unwrap();
+            assert_eq!(payloads.len(), 2);
+            assert_eq!(payloads[0], payloads[1]);
+            assert!(payload.starts_with(&payloads[0]));
+            assert!(payloads[0].len() < payload.len());
+        }
+        assert_eq!(
+            injector.counts(),
+            &FaultCounts {
+                dropped: 0,
+                truncated: 10,
+                corrupted: 0,
+                duplicated: 10
+            }
+        );
+    }
+
+    #[test]
+    fn corrupted_bytes_are_changed() {
+        let faults = parse(
+            r#"
+            {
+                "corrupt-probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "corrupt-bytes": { "const": 3 }
+            }
+            "#,
+        );
+        let payload = vec![0; 100];
+        let mut injector = FaultInjector::new(Some(&faults));
+        for frame_index in 0..10 {
+            let payloads = injector.inject(frame_index, payload.clone()).unwrap();
+            assert_eq!(payloads.len(), 1);
+            assert_eq!(payloads[0].iter().filter(|&&x| x != 0).count(), 3);
+        }
+        assert_eq!(injector.counts().corrupted, 10);
+    }
+
+    #[test]
+    fn dropped_messages_have_no_other_faults() {
+        let faults = parse(
+            r#"
+            {
+                "drop-probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "duplicate-probability": { "random-type": "constant-float", "value": { "const": 1 } }
+            }
+            "#,
+        );
+        let mut injector = FaultInjector::new(Some(&faults));
+        assert!(injector.inject(0, vec![1, 2, 3]).unwrap().is_empty());
+        assert_eq!(injector.counts().dropped, 1);
+        assert_eq!(injector.counts().duplicated, 0);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::integrated::simulation_elements::{
+    EventList,
+    pulses::{PulseEvent, PulseTemplate},
+};
+use digital_muon_common::FrameNumber;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum GroundTruthError {
+    #[error("Ground Truth File Error: {0}")]
+    IO(#[from] io::Error),
+    #[error("Ground Truth Json Error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid Ground Truth on line {0}: {1}")]
+    InvalidLine(usize, serde_json::Error),
+    #[error("Pulse Template index {1} on line {0} out of range {2}")]
+    PulseTemplateIndexOutOfRange(usize, usize, usize),
+    #[error("Pulse on line {0} does not have the shape of Pulse Template {1}")]
+    PulseShapeMismatch(usize, usize),
+    #[error("Duplicate Event List {2} for Frame {1} on line {0}")]
+    DuplicateEventList(usize, FrameNumber, usize),
+    #[error("Ground Truth has no Event List {1} for Frame {0}")]
+    EventListMissing(FrameNumber, usize),
+}
+
+/// A pulse of a ground truth event list, along with a reference to the pulse template it was sampled from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct GroundTruthPulse {
+    pub(crate) pulse_index: usize,
+    pub(crate) event: PulseEvent,
+}
+
+/// A single line of a ground truth file, holding the pulses of one event list.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct GroundTruthRecord {
+    pub(crate) frame: FrameNumber,
+    /// The position of the event list among all those generated for the frame,
+    /// this is the channel index when one event list is generated per channel.
+    pub(crate) index: usize,
+    pub(crate) pulses: Vec<GroundTruthPulse>,
+}
+
+/// The event lists of a ground truth file, indexed by frame and position within the frame.
+#[derive(Debug, Default)]
+pub(crate) struct GroundTruth {
+    event_lists: HashMap<(FrameNumber, usize), Vec<GroundTruthPulse>>,
+}
+
+impl GroundTruth {
+    pub(crate) fn load(path: &Path, templates: &[PulseTemplate]) -> Result<Self, GroundTruthError> {
+        Self::from_reader(BufReader::new(File::open(path)?), templates)
+    }
+
+    /// Reads ground truth records, one per line, checking each pulse refers
+    /// to a template in `templates` of the same shape, from which any measured shape values are restored.
+    pub(crate) fn from_reader(
+        reader: impl BufRead,
+        templates: &[PulseTemplate],
+    ) -> Result<Self, GroundTruthError> {
+        let mut event_lists = HashMap::new();
+        for (line_index, line) in reader.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut record: GroundTruthRecord = serde_json::from_str(&line)
+                .map_err(|e| GroundTruthError::InvalidLine(line_number, e))?;
+
+            for pulse in &mut record.pulses {
+                let template = templates.get(pulse.pulse_index).ok_or(
+                    GroundTruthError::PulseTemplateIndexOutOfRange(
+                        line_number,
+                        pulse.pulse_index,
+                        templates.len(),
+                    ),
+                )?;
+                if !pulse.event.attach_shape(template) {
+                    return Err(GroundTruthError::PulseShapeMismatch(
+                        line_number,
+                        pulse.pulse_index,
+                    ));
+                }
+            }
+            if event_lists
+                .insert((record.frame, record.index), record.pulses)
+                .is_some()
+            {
+                return Err(GroundTruthError::DuplicateEventList(
+                    line_number,
+                    record.frame,
+                    record.index,
+                ));
+            }
+        }
+        Ok(Self { event_lists })
+    }
+
+    pub(crate) fn event_list(
+        &self,
+        frame: FrameNumber,
+        index: usize,
+    ) -> Result<&[GroundTruthPulse], GroundTruthError> {
+        self.event_lists
+            .get(&(frame, index))
+            .map(Vec::as_slice)
+            .ok_or(GroundTruthError::EventListMissing(frame, index))
+    }
+}
+
+/// Writes generated event lists as ground truth records, one per line.
+pub(crate) struct GroundTruthWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> GroundTruthWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        frame: FrameNumber,
+        index: usize,
+        event_list: &EventList,
+    ) -> Result<(), GroundTruthError> {
+        let record = GroundTruthRecord {
+            frame,
+            index,
+            pulses: event_list
+                .pulse_indices
+                .iter()
+                .zip(&event_list.pulses)
+                .map(|(&pulse_index, event)| GroundTruthPulse {
+                    pulse_index,
+                    event: event.clone(),
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), GroundTruthError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::{
+        simulation::Simulation, simulation_elements::event_list::EventListSource,
+    };
+
+    fn simulation_json(event_list: &str, noise_sd: f64) -> String {
+        format!(
+            r#"
+        {{
+            "voltage-transformation": {{"scale": 1, "translate": 0 }},
+            "time-bins": {{ "const": 3000 }},
+            "sample-rate": {{ "const": 1000000000 }},
+            "digitiser-config": {{ "auto-aggregated-frame": {{ "num-channels": {{ "const" : 4 }} }} }},
+            "pulses": [{{
+                            "pulse-type": "flat",
+                            "start":  {{ "random-type": "uniform-float", "min": {{ "const": 0 }}, "max": {{ "const": 2500 }} }},
+                            "width":  {{ "random-type": "uniform-float", "min": {{ "const": 20 }}, "max": {{ "const": 50 }} }},
+                            "height": {{ "random-type": "uniform-float", "min": {{ "const": 30 }}, "max": {{ "const": 70 }} }}
+                        }},
+                        {{
+                            "pulse-type": "gaussian",
+                            "peak_time": {{ "random-type": "uniform-float", "min": {{ "const": 100 }}, "max": {{ "const": 2500 }} }},
+                            "sd":        {{ "random-type": "uniform-float", "min": {{ "const": 5 }}, "max": {{ "const": 10 }} }},
+                            "height":    {{ "random-type": "uniform-float", "min": {{ "const": 30 }}, "max": {{ "const": 70 }} }}
+                        }}],
+            "event-lists": [
+                {{
+                    {event_list},
+                    "noises": [
+                        {{
+                            "attributes": {{ "noise-type" : "gaussian", "mean" : {{ "const": 0 }}, "sd" : {{ "const": {noise_sd} }} }},
+                            "smoothing-window-length" : {{ "const": 1 }},
+                            "bounds" : {{ "min": {{ "const": 0 }}, "max": {{ "const": 3000 }} }}
+                        }}
+                    ]
+                }}
+            ],
+            "schedule": []
+        }}
+        "#
+        )
+    }
+
+    const RANDOM_EVENT_LIST: &str = r#"
+        "pulses": [
+            {"weight": 1, "pulse-index": 0},
+            {"weight": 1, "pulse-index": 1}
+        ],
+        "num-pulses": { "random-type": "constant-int", "value": { "const": 20 } }
+    "#;
+
+    const REPLAYED_EVENT_LIST: &str = r#""from-ground-truth": "unused.jsonl""#;
+
+    fn replay(simulation: &mut Simulation, ground_truth: GroundTruth) {
+        simulation.event_lists[0].source = EventListSource::FromGroundTruth {
+            from_ground_truth: Default::default(),
+            ground_truth,
+        };
+    }
+
+    fn rms(trace: &crate::integrated::simulation_elements::Trace) -> f64 {
+        let intensities = trace.generate_intensities().unwrap();
+        (intensities.iter().map(|&i| (i as f64).powi(2)).sum::<f64>() / intensities.len() as f64)
+            .sqrt()
+    }
+
+    #[test]
+    fn replay_ground_truth_with_different_noise() {
+        let original: Simulation =
+            serde_json::from_str(&simulation_json(RANDOM_EVENT_LIST, 1.0)).unwrap();
+
+        let mut writer = GroundTruthWriter::new(Vec::<u8>::new());
+        let mut original_lists = Vec::new();
+        for frame in 0..3 {
+            let event_lists = original.generate_event_lists(0, frame, 0, 4).unwrap();
+            for (index, event_list) in event_lists.iter().enumerate() {
+                writer.write(frame, index, event_list).unwrap();
+            }
+            original_lists.push(event_lists);
+        }
+
+        let mut replayed: Simulation =
+            serde_json::from_str(&simulation_json(REPLAYED_EVENT_LIST, 200.0)).unwrap();
+        assert!(matches!(
+            replayed.event_lists[0].source,
+            EventListSource::FromGroundTruth { .. }
+        ));
+        let ground_truth =
+            GroundTruth::from_reader(writer.writer.as_slice(), &replayed.pulses).unwrap();
+        replay(&mut replayed, ground_truth);
+
+        for (frame, original_lists) in original_lists.iter().enumerate() {
+            let frame = frame as FrameNumber;
+            let replayed_lists = replayed.generate_event_lists(0, frame, 0, 4).unwrap();
+            assert_eq!(replayed_lists.len(), original_lists.len());
+
+            for (original_list, replayed_list) in original_lists.iter().zip(&replayed_lists) {
+                assert_eq!(original_list.pulses.len(), 20);
+                assert_eq!(original_list.pulse_indices, replayed_list.pulse_indices);
+                for (original, replayed) in original_list.pulses.iter().zip(&replayed_list.pulses) {
+                    assert_eq!(original.time(), replayed.time());
+                    assert_eq!(original.intensity(), replayed.intensity());
+                    assert_eq!(original.get_start(), replayed.get_start());
+                    assert_eq!(original.get_end(), replayed.get_end());
+                }
+            }
+
+            let original_traces = original.generate_traces(original_lists, frame).unwrap();
+            let replayed_traces = replayed.generate_traces(&replayed_lists, frame).unwrap();
+            for (original, replayed) in original_traces.iter().zip(&replayed_traces) {
+                assert!(rms(replayed) > 2.0 * rms(original));
+            }
+        }
+    }
+
+    #[test]
+    fn replay_missing_event_list() {
+        let mut replayed: Simulation =
+            serde_json::from_str(&simulation_json(REPLAYED_EVENT_LIST, 1.0)).unwrap();
+        let ground_truth = GroundTruth::from_reader(
+            r#"{"frame": 0, "index": 0, "pulses": []}"#.as_bytes(),
+            &replayed.pulses,
+        )
+        .unwrap();
+        replay(&mut replayed, ground_truth);
+
+        assert_eq!(replayed.generate_event_lists(0, 0, 0, 1).unwrap().len(), 1);
+        assert!(replayed.generate_event_lists(0, 0, 0, 2).is_err());
+        assert!(replayed.generate_event_lists(0, 1, 0, 1).is_err());
+    }
+
+    const FLAT_PULSE: &str =
+        r#"{"pulse-type": "flat", "start": 10.0, "stop": 20.0, "amplitude": 5.0}"#;
+
+    fn load(lines: &[String]) -> Result<GroundTruth, GroundTruthError> {
+        let simulation: Simulation =
+            serde_json::from_str(&simulation_json(RANDOM_EVENT_LIST, 1.0)).unwrap();
+        GroundTruth::from_reader(lines.join("\n").as_bytes(), &simulation.pulses)
+    }
+
+    #[test]
+    fn missing_pulse_template_reports_line() {
+        let result = load(&[
+            format!(
+                r#"{{"frame": 0, "index": 0, "pulses": [{{"pulse-index": 0, "event": {FLAT_PULSE}}}]}}"#
+            ),
+            String::new(),
+            format!(
+                r#"{{"frame": 0, "index": 1, "pulses": [{{"pulse-index": 2, "event": {FLAT_PULSE}}}]}}"#
+            ),
+        ]);
+        assert!(matches!(
+            result,
+            Err(GroundTruthError::PulseTemplateIndexOutOfRange(3, 2, 2))
+        ));
+    }
+
+    #[test]
+    fn mismatched_pulse_shape_reports_line() {
+        let result = load(&[format!(
+            r#"{{"frame": 0, "index": 0, "pulses": [{{"pulse-index": 1, "event": {FLAT_PULSE}}}]}}"#
+        )]);
+        assert!(matches!(
+            result,
+            Err(GroundTruthError::PulseShapeMismatch(1, 1))
+        ));
+    }
+
+    #[test]
+    fn duplicate_event_list_reports_line() {
+        let line = format!(
+            r#"{{"frame": 0, "index": 0, "pulses": [{{"pulse-index": 0, "event": {FLAT_PULSE}}}]}}"#
+        );
+        let result = load(&[line.clone(), line]);
+        assert!(matches!(
+            result,
+            Err(GroundTruthError::DuplicateEventList(2, 0, 0))
+        ));
+    }
+}
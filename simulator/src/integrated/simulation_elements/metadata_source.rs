@@ -0,0 +1,201 @@
+use digital_muon_streaming_types::FrameMetadata;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum MetadataSourceError {
+    #[error("Metadata Source File Error: {0}")]
+    IO(#[from] io::Error),
+    #[error("Invalid Metadata Source on line {0}: {1}")]
+    InvalidLine(usize, serde_json::Error),
+    #[error("Metadata Source has no rows")]
+    Empty,
+    #[error("Metadata Source exhausted at frame index {0}, only {1} rows available")]
+    Exhausted(usize, usize),
+}
+
+/// Determines what happens when more frames are run than there are rows in a [MetadataSource].
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ExhaustionPolicy {
+    /// The simulation stops with an error.
+    #[default]
+    Error,
+    /// Rows are reused from the beginning.
+    Cycle,
+}
+
+/// The values of a single frame's metadata read from a [MetadataSource].
+/// Fields which are absent leave the value set by the schedule unchanged.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct MetadataRow {
+    pub(crate) period_number: Option<u64>,
+    pub(crate) protons_per_pulse: Option<u8>,
+    pub(crate) veto_flags: Option<u16>,
+}
+
+impl MetadataRow {
+    pub(crate) fn apply(&self, metadata: &mut FrameMetadata) {
+        if let Some(period_number) = self.period_number {
+            metadata.period_number = period_number;
+        }
+        if let Some(protons_per_pulse) = self.protons_per_pulse {
+            metadata.protons_per_pulse = protons_per_pulse;
+        }
+        if let Some(veto_flags) = self.veto_flags {
+            metadata.veto_flags = veto_flags;
+        }
+    }
+}
+
+/// A recorded sequence of frame metadata, given as a json lines file with one row per frame.
+/// The rows are consumed in order, one for each frame run by a `frame-loop`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct MetadataSource {
+    file: PathBuf,
+    #[serde(default)]
+    on_exhaustion: ExhaustionPolicy,
+    /// The rows of `file`, populated by [MetadataSource::load].
+    #[serde(skip)]
+    rows: Vec<MetadataRow>,
+}
+
+impl MetadataSource {
+    pub(crate) fn load(&mut self) -> Result<(), MetadataSourceError> {
+        self.rows = Self::read_rows(BufReader::new(File::open(&self.file)?))?;
+        Ok(())
+    }
+
+    fn read_rows(reader: impl BufRead) -> Result<Vec<MetadataRow>, MetadataSourceError> {
+        let mut rows = Vec::new();
+        for (line_index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows.push(
+                serde_json::from_str(&line)
+                    .map_err(|e| MetadataSourceError::InvalidLine(line_index + 1, e))?,
+            );
+        }
+        if rows.is_empty() {
+            return Err(MetadataSourceError::Empty);
+        }
+        Ok(rows)
+    }
+
+    /// Returns the row for the `frame_index`th frame run, according to the exhaustion policy.
+    pub(crate) fn row(&self, frame_index: usize) -> Result<&MetadataRow, MetadataSourceError> {
+        let index = match self.on_exhaustion {
+            ExhaustionPolicy::Error => frame_index,
+            ExhaustionPolicy::Cycle => frame_index % self.rows.len().max(1),
+        };
+        self.rows
+            .get(index)
+            .ok_or(MetadataSourceError::Exhausted(frame_index, self.rows.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    const FIXTURE: &str = r#"
+{"period-number": 1, "protons-per-pulse": 10, "veto-flags": 0}
+{"protons-per-pulse": 20}
+
+{"veto-flags": 4}
+"#;
+
+    fn source(on_exhaustion: ExhaustionPolicy) -> MetadataSource {
+        MetadataSource {
+            file: Default::default(),
+            on_exhaustion,
+            rows: MetadataSource::read_rows(FIXTURE.as_bytes()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn read_fixture() {
+        let source = source(ExhaustionPolicy::Error);
+        assert_eq!(source.rows.len(), 3);
+        assert_eq!(
+            source.rows[1],
+            MetadataRow {
+                protons_per_pulse: Some(20),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_rows() {
+        assert!(matches!(
+            MetadataSource::read_rows("\n\n".as_bytes()),
+            Err(MetadataSourceError::Empty)
+        ));
+        assert!(matches!(
+            MetadataSource::read_rows("{\"veto-flags\": 1}\n{\"veto-flags\": -1}".as_bytes()),
+            Err(MetadataSourceError::InvalidLine(2, _))
+        ));
+        assert!(matches!(
+            MetadataSource::read_rows("{\"protons\": 1}".as_bytes()),
+            Err(MetadataSourceError::InvalidLine(1, _))
+        ));
+    }
+
+    #[test]
+    fn error_at_exhaustion() {
+        let source = source(ExhaustionPolicy::Error);
+        assert_eq!(source.row(2).unwrap().veto_flags, Some(4));
+        assert!(matches!(
+            source.row(3),
+            Err(MetadataSourceError::Exhausted(3, 3))
+        ));
+    }
+
+    #[test]
+    fn cycle_at_exhaustion() {
+        let source = source(ExhaustionPolicy::Cycle);
+        for frame_index in 0..9 {
+            assert_eq!(
+                source.row(frame_index).unwrap(),
+                &source.rows[frame_index % 3]
+            );
+        }
+    }
+
+    #[test]
+    fn apply_frame_by_frame() {
+        let source = source(ExhaustionPolicy::Error);
+        // The values set by the schedule.
+        let scheduled = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 7,
+            protons_per_pulse: 50,
+            running: true,
+            frame_number: 0,
+            veto_flags: 1,
+        };
+        let expected = [(1, 10, 0), (7, 20, 1), (7, 50, 4)];
+        for (frame_index, (period_number, protons_per_pulse, veto_flags)) in
+            expected.into_iter().enumerate()
+        {
+            let mut metadata = scheduled.clone();
+            source.row(frame_index).unwrap().apply(&mut metadata);
+            assert_eq!(metadata.period_number, period_number);
+            assert_eq!(metadata.protons_per_pulse, protons_per_pulse);
+            assert_eq!(metadata.veto_flags, veto_flags);
+            assert_eq!(metadata.timestamp, scheduled.timestamp);
+            assert!(metadata.running);
+        }
+    }
+}
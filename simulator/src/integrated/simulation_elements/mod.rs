@@ -1,6 +1,12 @@
+pub(crate) mod coincidence;
 pub(crate) mod digitiser_config;
 pub(crate) mod event_list;
+pub(crate) mod fault_injection;
+pub(crate) mod ground_truth;
+pub(crate) mod metadata_source;
 pub(crate) mod noise;
+pub(crate) mod overflow;
+pub(crate) mod pulse_shapes;
 pub(crate) mod pulses;
 pub(crate) mod run_messages;
 pub(crate) mod utils;
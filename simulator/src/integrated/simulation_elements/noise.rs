@@ -3,11 +3,23 @@ use std::collections::VecDeque;
 use crate::integrated::simulation_elements::FloatRandomDistribution;
 
 use super::{Interval, NumExpression, utils::JsonValueError};
-use chrono::Utc;
 use digital_muon_common::Time;
-use rand::SeedableRng;
+use rand::{RngExt, SeedableRng, rngs::StdRng};
 use rand_distr::{Distribution, Normal};
 use serde::Deserialize;
+use std::ptr;
+
+/// Determines which channel traces share the samples of a noise source.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum NoiseScope {
+    /// Each channel trace is given its own samples.
+    #[default]
+    PerChannel,
+    /// The samples are generated once for each digitiser message, and added to every channel trace of the message.
+    /// This models common-mode noise, such as pickup on a digitiser's shared clock or power supply.
+    PerDigitiser,
+}
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -20,30 +32,41 @@ pub(crate) struct NoiseSource {
     /// "smoothing-window-length": { "const": 1 }
     /// ```
     smoothing_window_length: NumExpression<usize>,
+    /// If absent, each channel trace is given its own samples.
+    #[serde(default)]
+    scope: NoiseScope,
 }
 
 impl NoiseSource {
-    pub(crate) fn sample(&self, time: Time, frame_index: usize) -> Result<f64, JsonValueError> {
+    pub(crate) fn scope(&self) -> NoiseScope {
+        self.scope
+    }
+
+    /// Samples the source with `rng`, so that a seeded `rng` gives the same samples each time.
+    pub(crate) fn sample(
+        &self,
+        time: Time,
+        frame_index: usize,
+        rng: &mut StdRng,
+    ) -> Result<f64, JsonValueError> {
         if self.bounds.is_in(time, frame_index)? {
             match &self.attributes {
                 NoiseAttributes::Bernoulli { probability, value } => {
-                    if rand::random_bool(probability.value(frame_index)?) {
-                        value.sample(frame_index)
+                    if rng.random_bool(probability.value(frame_index)?) {
+                        value.sample_with(frame_index, rng)
                     } else {
                         Ok(0.0)
                     }
                 }
                 NoiseAttributes::Uniform(Interval { min, max }) => {
                     let val = (max.value(frame_index)? - min.value(frame_index)?)
-                        * rand::random::<f64>()
+                        * rng.random::<f64>()
                         + min.value(frame_index)?;
                     Ok(val)
                 }
                 NoiseAttributes::Gaussian { mean, sd } => {
-                    let val = Normal::new(mean.value(frame_index)?, sd.value(frame_index)?)?
-                        .sample(&mut rand::rngs::StdRng::seed_from_u64(
-                            Utc::now().timestamp_subsec_nanos() as u64,
-                        ));
+                    let val =
+                        Normal::new(mean.value(frame_index)?, sd.value(frame_index)?)?.sample(rng);
                     Ok(val)
                 }
             }
@@ -85,12 +108,66 @@ impl<'a> Noise<'a> {
         value: f64,
         time: Time,
         frame_index: usize,
+        rng: &mut StdRng,
     ) -> Result<f64, JsonValueError> {
         let window_len = self.source.smoothing_window_length.value(frame_index)?;
         if self.prev.len() == window_len {
             self.prev.pop_front();
         }
-        self.prev.push_back(self.source.sample(time, frame_index)?);
+        self.prev
+            .push_back(self.source.sample(time, frame_index, rng)?);
         Ok(value + self.prev.iter().sum::<f64>() / self.prev.len() as f64)
     }
 }
+
+/// The samples of the per-digitiser noise sources of the channel traces of a digitiser message.
+///
+/// These are generated once, before the channel traces, so that every channel trace can add the same samples.
+#[derive(Default)]
+pub(crate) struct DigitiserNoise<'a> {
+    /// The smoothed samples of each source, in order of time.
+    samples: Vec<(&'a NoiseSource, Vec<f64>)>,
+}
+
+impl<'a> DigitiserNoise<'a> {
+    /// Generates the samples of each per-digitiser source of `sources`, those appearing more than once are only generated once.
+    /// # Parameters
+    /// - sources: the noise sources of each trace, with its number of time bins, frame index,
+    ///   and the seed from which the sources first appearing in it are sampled.
+    pub(crate) fn generate(
+        sources: impl IntoIterator<Item = (&'a [NoiseSource], Time, usize, u64)>,
+    ) -> Result<Self, JsonValueError> {
+        let mut digitiser_noise = Self::default();
+        for (sources, time_bins, frame_index, seed) in sources {
+            let mut rng = StdRng::seed_from_u64(seed);
+            for source in sources {
+                if source.scope != NoiseScope::PerDigitiser
+                    || digitiser_noise.find(source).is_some()
+                {
+                    continue;
+                }
+                let mut noise = Noise::new(source);
+                let samples = (0..time_bins)
+                    .map(|time| noise.noisify(0.0, time, frame_index, &mut rng))
+                    .collect::<Result<_, _>>()?;
+                digitiser_noise.samples.push((source, samples));
+            }
+        }
+        Ok(digitiser_noise)
+    }
+
+    fn find(&self, source: &NoiseSource) -> Option<&[f64]> {
+        self.samples
+            .iter()
+            .find(|(sampled, _)| ptr::eq(*sampled, source))
+            .map(|(_, samples)| samples.as_slice())
+    }
+
+    /// Returns the samples of a per-digitiser source.
+    /// # Parameters
+    /// - source: a per-digitiser source, which should be one of those these were generated from.
+    pub(crate) fn samples(&self, source: &NoiseSource) -> &[f64] {
+        self.find(source)
+            .expect("Samples should be generated for each trace's sources, this never fails")
+    }
+}
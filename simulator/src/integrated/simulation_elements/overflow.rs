@@ -0,0 +1,140 @@
+//! Converts the values of generated traces into [Intensity], and counts those which do not fit.
+use digital_muon_common::{Channel, Intensity, Time};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+};
+use thiserror::Error;
+
+/// What is done with a trace value which, truncated to an integer, lies outside the range of [Intensity].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum OnOverflow {
+    /// The value is clamped to the nearest end of the range, or zero if it is not a number.
+    #[default]
+    Saturate,
+    /// The value is taken modulo the size of the range.
+    Wrap,
+    /// Generating the trace fails.
+    Error,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("Value {value} at time bin {time} does not fit the Intensity type")]
+pub(crate) struct IntensityOverflow {
+    pub(crate) time: Time,
+    pub(crate) value: f64,
+}
+
+impl OnOverflow {
+    /// Converts `value`, the value of the trace at time bin `time`, into an [Intensity].
+    ///
+    /// # Returns
+    /// The intensity, and whether the value was out of range, or an error if it was and `self` is [Self::Error].
+    pub(crate) fn convert(
+        self,
+        value: f64,
+        time: Time,
+    ) -> Result<(Intensity, bool), IntensityOverflow> {
+        let truncated = value.trunc();
+        if (0.0..=Intensity::MAX as f64).contains(&truncated) {
+            return Ok((truncated as Intensity, false));
+        }
+        match self {
+            OnOverflow::Saturate => Ok((truncated as Intensity, true)),
+            OnOverflow::Wrap => Ok((
+                (truncated as i64).rem_euclid(1 << Intensity::BITS) as Intensity,
+                true,
+            )),
+            OnOverflow::Error => Err(IntensityOverflow { time, value }),
+        }
+    }
+}
+
+/// The number of samples of each channel whose values did not fit the [Intensity] type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ClippingCounts {
+    channels: BTreeMap<Channel, usize>,
+}
+
+impl ClippingCounts {
+    /// Adds `clipped` samples to the count of `channel`.
+    pub(crate) fn add(&mut self, channel: Channel, clipped: usize) {
+        if clipped > 0 {
+            *self.channels.entry(channel).or_default() += clipped;
+        }
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        self.channels.values().sum()
+    }
+
+    /// Adds the counts of every channel of `other`.
+    pub(crate) fn merge(&mut self, other: &ClippingCounts) {
+        for (&channel, &clipped) in &other.channels {
+            self.add(channel, clipped);
+        }
+    }
+}
+
+impl Display for ClippingCounts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} samples clipped", self.total())?;
+        for (index, (channel, clipped)) in self.channels.iter().enumerate() {
+            let separator = if index == 0 { ": " } else { ", " };
+            write!(f, "{separator}{clipped} on channel {channel}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_in_range_are_truncated() {
+        for on_overflow in [OnOverflow::Saturate, OnOverflow::Wrap, OnOverflow::Error] {
+            assert_eq!(on_overflow.convert(0.0, 0), Ok((0, false)));
+            assert_eq!(on_overflow.convert(-0.5, 0), Ok((0, false)));
+            assert_eq!(on_overflow.convert(12.9, 0), Ok((12, false)));
+            assert_eq!(on_overflow.convert(65535.5, 0), Ok((65535, false)));
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_are_handled() {
+        assert_eq!(OnOverflow::Saturate.convert(65536.0, 0), Ok((65535, true)));
+        assert_eq!(OnOverflow::Saturate.convert(-1.0, 0), Ok((0, true)));
+        assert_eq!(OnOverflow::Saturate.convert(f64::NAN, 0), Ok((0, true)));
+        assert_eq!(OnOverflow::Wrap.convert(65537.0, 0), Ok((1, true)));
+        assert_eq!(OnOverflow::Wrap.convert(-1.0, 0), Ok((65535, true)));
+        assert_eq!(
+            OnOverflow::Error.convert(70000.0, 3),
+            Err(IntensityOverflow {
+                time: 3,
+                value: 70000.0
+            })
+        );
+    }
+
+    #[test]
+    fn clipping_counts_are_summarised() {
+        let mut counts = ClippingCounts::default();
+        assert_eq!(counts.to_string(), "0 samples clipped");
+        counts.add(4, 10);
+        counts.add(1, 0);
+        counts.add(2, 5);
+        counts.add(4, 1);
+        assert_eq!(counts.total(), 16);
+
+        let mut merged = ClippingCounts::default();
+        merged.add(2, 1);
+        merged.merge(&counts);
+        assert_eq!(
+            merged.to_string(),
+            "17 samples clipped: 6 on channel 2, 11 on channel 4"
+        );
+    }
+}
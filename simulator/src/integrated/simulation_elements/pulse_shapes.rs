@@ -0,0 +1,211 @@
+use chrono::Utc;
+use rand::{SeedableRng, distr::weighted::WeightedIndex};
+use rand_distr::Distribution;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    num::ParseFloatError,
+    path::Path,
+    sync::Arc,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum PulseShapesError {
+    #[error("Pulse Shapes File Error: {0}")]
+    IO(#[from] io::Error),
+    #[error("Invalid Pulse Shape value {1} on line {0}: {2}")]
+    InvalidValue(usize, usize, ParseFloatError),
+    #[error("Pulse Shape on line {0} has no positive value")]
+    NoPeak(usize),
+    #[error("Pulse Shapes file has no shapes")]
+    Empty,
+    #[error("Pulse Shapes has {0} weights but {1} shapes")]
+    WeightsMismatch(usize, usize),
+    #[error("Invalid Pulse Shapes weights: {0}")]
+    Weights(#[from] rand::distr::weighted::Error),
+}
+
+/// A measured pulse shape, with one value per sample, scaled so that its maximum is one.
+#[derive(Debug, Clone)]
+pub(crate) struct PulseShape {
+    /// The index of the first sample at which the shape takes its maximum.
+    pub(crate) peak_index: usize,
+    pub(crate) values: Arc<[f64]>,
+}
+
+impl PulseShape {
+    /// Returns the value of the shape `x` samples after its first sample,
+    /// linearly interpolated between the stored samples, and zero outside of them.
+    pub(crate) fn value_at(values: &[f64], x: f64) -> f64 {
+        if x < 0.0 {
+            return Default::default();
+        }
+        let index = x.floor() as usize;
+        match (values.get(index), values.get(index + 1)) {
+            (Some(&before), Some(&after)) => before + (x - index as f64) * (after - before),
+            (Some(&last), None) if x == index as f64 => last,
+            _ => Default::default(),
+        }
+    }
+}
+
+/// The pulse shapes read from a file, along with the distribution by which one is chosen for each pulse.
+/// These are shared by every pulse template which reads the same file, so the file is only read once.
+///
+/// The file is a csv file with one shape per line, each consisting of comma separated values, one per sample.
+/// Empty lines, and lines beginning with `#`, are ignored.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PulseShapes {
+    shapes: Arc<[PulseShape]>,
+    /// The distribution of shape indices, populated by [PulseShapes::with_weights].
+    choice: Option<WeightedIndex<f64>>,
+}
+
+impl PulseShapes {
+    pub(crate) fn load(path: &Path) -> Result<Self, PulseShapesError> {
+        Self::read_shapes(BufReader::new(File::open(path)?))
+    }
+
+    fn read_shapes(reader: impl BufRead) -> Result<Self, PulseShapesError> {
+        let mut shapes = Vec::new();
+        for (line_index, line) in reader.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split(',')
+                .enumerate()
+                .map(|(value_index, value)| {
+                    value.trim().parse::<f64>().map_err(|e| {
+                        PulseShapesError::InvalidValue(line_number, value_index + 1, e)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let (peak_index, peak) = values
+                .iter()
+                .copied()
+                .enumerate()
+                .fold(
+                    None,
+                    |max: Option<(usize, f64)>, (index, value)| match max {
+                        Some((_, max_value)) if max_value >= value => max,
+                        _ => Some((index, value)),
+                    },
+                )
+                .filter(|(_, peak)| peak.is_finite() && *peak > 0.0)
+                .ok_or(PulseShapesError::NoPeak(line_number))?;
+            shapes.push(PulseShape {
+                peak_index,
+                values: values.into_iter().map(|value| value / peak).collect(),
+            });
+        }
+        if shapes.is_empty() {
+            return Err(PulseShapesError::Empty);
+        }
+        Ok(Self {
+            shapes: shapes.into(),
+            choice: None,
+        })
+    }
+
+    /// Returns the same shapes, chosen with the given relative weights, or uniformly if `weights` is absent.
+    pub(crate) fn with_weights(&self, weights: Option<&[f64]>) -> Result<Self, PulseShapesError> {
+        let weights = match weights {
+            Some(weights) if weights.len() != self.shapes.len() => {
+                return Err(PulseShapesError::WeightsMismatch(
+                    weights.len(),
+                    self.shapes.len(),
+                ));
+            }
+            Some(weights) => weights.to_vec(),
+            None => vec![1.0; self.shapes.len()],
+        };
+        Ok(Self {
+            shapes: self.shapes.clone(),
+            choice: Some(WeightedIndex::new(weights)?),
+        })
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&PulseShape> {
+        self.shapes.get(index)
+    }
+
+    /// Returns the index of a randomly chosen shape, or [None] if the shapes have not been loaded.
+    pub(crate) fn choose(&self) -> Option<usize> {
+        self.choice.as_ref().map(|choice| {
+            choice.sample(&mut rand::rngs::StdRng::seed_from_u64(
+                Utc::now().timestamp_subsec_nanos() as u64,
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = "
+# Two measured pulses.
+0, 1, 4, 2, 1
+
+0.5, 1.0, 0.5
+";
+
+    #[test]
+    fn read_fixture() {
+        let shapes = PulseShapes::read_shapes(FIXTURE.as_bytes()).unwrap();
+        assert_eq!(shapes.shapes.len(), 2);
+        assert_eq!(shapes.shapes[0].peak_index, 2);
+        assert_eq!(&*shapes.shapes[0].values, &[0.0, 0.25, 1.0, 0.5, 0.25]);
+        assert_eq!(shapes.shapes[1].peak_index, 1);
+        assert_eq!(&*shapes.shapes[1].values, &[0.5, 1.0, 0.5]);
+        // Shapes cannot be chosen until weights are given.
+        assert_eq!(shapes.choose(), None);
+    }
+
+    #[test]
+    fn invalid_shapes() {
+        assert!(matches!(
+            PulseShapes::read_shapes("# Nothing\n\n".as_bytes()),
+            Err(PulseShapesError::Empty)
+        ));
+        assert!(matches!(
+            PulseShapes::read_shapes("0, 1\n0, x, 1".as_bytes()),
+            Err(PulseShapesError::InvalidValue(2, 2, _))
+        ));
+        assert!(matches!(
+            PulseShapes::read_shapes("0, 1\n0, -1, 0".as_bytes()),
+            Err(PulseShapesError::NoPeak(2))
+        ));
+    }
+
+    #[test]
+    fn interpolated_values() {
+        let values = [0.0, 0.25, 1.0, 0.5];
+        assert_eq!(PulseShape::value_at(&values, -0.5), 0.0);
+        assert_eq!(PulseShape::value_at(&values, 0.0), 0.0);
+        assert_eq!(PulseShape::value_at(&values, 1.5), 0.625);
+        assert_eq!(PulseShape::value_at(&values, 2.0), 1.0);
+        assert_eq!(PulseShape::value_at(&values, 3.0), 0.5);
+        assert_eq!(PulseShape::value_at(&values, 3.5), 0.0);
+    }
+
+    #[test]
+    fn weighted_choice() {
+        let shapes = PulseShapes::read_shapes(FIXTURE.as_bytes()).unwrap();
+        assert!(matches!(
+            shapes.with_weights(Some(&[1.0])),
+            Err(PulseShapesError::WeightsMismatch(1, 2))
+        ));
+        assert!(shapes.with_weights(Some(&[-1.0, 1.0])).is_err());
+
+        let shapes = shapes.with_weights(Some(&[0.0, 1.0])).unwrap();
+        assert!((0..20).all(|_| shapes.choose() == Some(1)));
+        let shapes = shapes.with_weights(None).unwrap();
+        assert!((0..20).all(|_| shapes.choose().is_some_and(|index| index < 2)));
+    }
+}
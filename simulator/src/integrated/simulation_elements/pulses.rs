@@ -1,9 +1,16 @@
 use core::f64;
 
-use super::{FloatRandomDistribution, utils::JsonValueError};
+use super::{
+    FloatRandomDistribution,
+    pulse_shapes::{PulseShape, PulseShapes},
+    utils::JsonValueError,
+};
 use digital_muon_common::{Intensity, Time};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
+use std::{path::PathBuf, sync::Arc};
 
+/// The fields of each pulse template are named in snake case,
+/// but may also be given in kebab case, in keeping with the rest of the configuration file.
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case", tag = "pulse-type")]
 pub(crate) enum PulseTemplate {
@@ -14,25 +21,98 @@ pub(crate) enum PulseTemplate {
     },
     Triangular {
         start: FloatRandomDistribution<f64>,
+        #[serde(alias = "peak-time")]
         peak_time: FloatRandomDistribution<f64>,
         width: FloatRandomDistribution<f64>,
         height: FloatRandomDistribution<f64>,
     },
     Gaussian {
         height: FloatRandomDistribution<f64>,
+        #[serde(alias = "peak-time")]
         peak_time: FloatRandomDistribution<f64>,
         sd: FloatRandomDistribution<f64>,
     },
     BackToBackExp {
+        #[serde(alias = "peak-height")]
         peak_height: FloatRandomDistribution<f64>,
+        #[serde(alias = "peak-time")]
         peak_time: FloatRandomDistribution<f64>,
         spread: FloatRandomDistribution<f64>,
         falling: FloatRandomDistribution<f64>,
         rising: FloatRandomDistribution<f64>,
     },
+    /// A gamma function pulse, with a long tail after the peak.
+    /// Values of `shape_k` below 1 are treated as 1, and `scale_theta` must not be zero.
+    Gamma {
+        #[serde(alias = "peak-height")]
+        peak_height: FloatRandomDistribution<f64>,
+        #[serde(alias = "peak-time")]
+        peak_time: FloatRandomDistribution<f64>,
+        #[serde(alias = "shape-k")]
+        shape_k: FloatRandomDistribution<f64>,
+        #[serde(alias = "scale-theta", deserialize_with = "deserialize_scale_theta")]
+        scale_theta: FloatRandomDistribution<f64>,
+    },
+    /// A pulse with one of the measured shapes read from the file at `path`,
+    /// scaled by `height`, and placed so that its peak is at `time`.
+    /// If `weights` is given, it holds the relative frequency of each shape, otherwise every shape is equally likely.
+    /// The file is read when the simulation is loaded, see [PulseShapes] for its format.
+    FromFile {
+        path: PathBuf,
+        #[serde(default)]
+        weights: Option<Vec<f64>>,
+        height: FloatRandomDistribution<f64>,
+        time: FloatRandomDistribution<f64>,
+        #[serde(skip)]
+        shapes: PulseShapes,
+    },
 }
 
-#[derive(Debug, Clone)]
+/// Deserialises the `scale_theta` of a gamma pulse, rejecting distributions which can only sample zero,
+/// as the pulse would have no width.
+fn deserialize_scale_theta<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<FloatRandomDistribution<f64>, D::Error> {
+    let scale_theta = FloatRandomDistribution::deserialize(deserializer)?;
+    if scale_theta.max_magnitude() == Some(0.0) {
+        return Err(D::Error::custom("scale_theta must not be zero"));
+    }
+    Ok(scale_theta)
+}
+
+/// Returns the logarithm of the gamma pulse, relative to its peak height,
+/// at `x` scale lengths from the pulse origin, for a pulse with peak at `mode` scale lengths.
+fn gamma_log_relative_value(mode: f64, x: f64) -> f64 {
+    if mode == 0.0 {
+        -x
+    } else {
+        mode * (x / mode).ln() - (x - mode)
+    }
+}
+
+/// Finds the point, in scale lengths from the pulse origin, between `below` and `above`
+/// where the gamma pulse of the given peak height takes the value one.
+/// The pulse should be less than one at `below`, and at least one at `above`.
+fn gamma_unit_crossing(peak_height: f64, mode: f64, mut below: f64, mut above: f64) -> f64 {
+    const ITERATIONS: usize = 64;
+    let log_peak_height = peak_height.ln();
+    for _ in 0..ITERATIONS {
+        let mid = 0.5 * (below + above);
+        if log_peak_height + gamma_log_relative_value(mode, mid) < 0.0 {
+            below = mid;
+        } else {
+            above = mid;
+        }
+    }
+    0.5 * (below + above)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case",
+    tag = "pulse-type"
+)]
 pub(crate) enum PulseEvent {
     Flat {
         start: f64,
@@ -63,6 +143,27 @@ pub(crate) enum PulseEvent {
         falling_spread: f64,
         frac_1_sqrt_2_spread: f64,
     },
+    Gamma {
+        start: f64,
+        stop: f64,
+        peak_time: f64,
+        peak_height: f64,
+        /// The peak time, in scale lengths, from the origin of the pulse, this is `shape_k - 1`.
+        mode: f64,
+        scale_theta: f64,
+    },
+    FromFile {
+        start: f64,
+        stop: f64,
+        peak_time: f64,
+        height: f64,
+        /// The index of the shape in the file of the pulse template.
+        shape_index: usize,
+        /// The values of the shape, these are not written to ground truth files,
+        /// but are restored from the pulse template when the ground truth is loaded.
+        #[serde(skip)]
+        values: Arc<[f64]>,
+    },
 }
 
 impl PulseEvent {
@@ -161,15 +262,113 @@ impl PulseEvent {
                     frac_1_sqrt_2_spread,
                 })
             }
+            PulseTemplate::Gamma {
+                peak_height,
+                peak_time,
+                shape_k,
+                scale_theta,
+            } => {
+                let peak_height = peak_height.sample(frame)?;
+                let peak_time = peak_time.sample(frame)?;
+                let mode = shape_k.sample(frame)?.max(1.0) - 1.0;
+                let scale_theta = scale_theta.sample(frame)?;
+
+                let origin = peak_time - mode * scale_theta;
+                let (start, stop) = if peak_height <= 1.0 {
+                    (peak_time, peak_time)
+                } else {
+                    let start = if mode == 0.0 {
+                        mode
+                    } else {
+                        gamma_unit_crossing(peak_height, mode, 0.0, mode)
+                    };
+                    //  Find a point beyond which the pulse is below one, to bound the search.
+                    let mut beyond = mode + 1.0;
+                    while peak_height.ln() + gamma_log_relative_value(mode, beyond) >= 0.0 {
+                        beyond = mode + 2.0 * (beyond - mode);
+                    }
+                    let stop = gamma_unit_crossing(peak_height, mode, beyond, mode);
+                    (origin + start * scale_theta, origin + stop * scale_theta)
+                };
+
+                Ok(Self::Gamma {
+                    start,
+                    stop,
+                    peak_time,
+                    peak_height,
+                    mode,
+                    scale_theta,
+                })
+            }
+            PulseTemplate::FromFile {
+                height,
+                time,
+                shapes,
+                ..
+            } => {
+                let shape_index = shapes
+                    .choose()
+                    .ok_or(JsonValueError::PulseShapesNotLoaded)?;
+                let shape = shapes
+                    .get(shape_index)
+                    .ok_or(JsonValueError::PulseShapesNotLoaded)?;
+                let peak_time = time.sample(frame)?;
+                let start = peak_time - shape.peak_index as f64;
+                Ok(Self::FromFile {
+                    start,
+                    stop: start + (shape.values.len() - 1) as f64,
+                    peak_time,
+                    height: height.sample(frame)?,
+                    shape_index,
+                    values: shape.values.clone(),
+                })
+            }
+        }
+    }
+
+    /// Restores the values of a pulse read from a ground truth file, from the shapes of its template.
+    /// Returns false if the template does not have the pulse's shape.
+    pub(crate) fn attach_shape(&mut self, template: &PulseTemplate) -> bool {
+        match (self, template) {
+            (
+                Self::FromFile {
+                    shape_index,
+                    values,
+                    ..
+                },
+                PulseTemplate::FromFile { shapes, .. },
+            ) => shapes
+                .get(*shape_index)
+                .map(|shape| *values = shape.values.clone())
+                .is_some(),
+            (pulse, template) => pulse.is_shape_of(template),
         }
     }
 
+    /// Returns true if this event has the shape of pulse generated by the given template.
+    pub(crate) fn is_shape_of(&self, template: &PulseTemplate) -> bool {
+        matches!(
+            (self, template),
+            (Self::Flat { .. }, PulseTemplate::Flat { .. })
+                | (Self::Triangular { .. }, PulseTemplate::Triangular { .. })
+                | (Self::Gaussian { .. }, PulseTemplate::Gaussian { .. })
+                | (
+                    Self::BackToBackExp { .. },
+                    PulseTemplate::BackToBackExp { .. }
+                )
+                | (Self::Gamma { .. }, PulseTemplate::Gamma { .. })
+                | (Self::FromFile { .. }, PulseTemplate::FromFile { .. })
+        )
+    }
+
     pub(crate) fn get_start(&self) -> Time {
         (match self {
             Self::Flat { start, .. } => *start,
             Self::Triangular { start, .. } => *start,
             Self::Gaussian { start, .. } => *start,
             Self::BackToBackExp { start, .. } => *start,
+            Self::Gamma { start, .. } => *start,
+            Self::FromFile { start, .. } => *start,
         }) as Time
     }
 
@@ -179,6 +378,8 @@ impl PulseEvent {
             Self::Triangular { stop, .. } => *stop,
             Self::Gaussian { stop, .. } => *stop,
             Self::BackToBackExp { stop, .. } => *stop,
+            Self::Gamma { stop, .. } => *stop,
+            Self::FromFile { stop, .. } => *stop,
         }) as Time
     }
 
@@ -188,6 +389,8 @@ impl PulseEvent {
             Self::Triangular { peak_time, .. } => *peak_time,
             Self::Gaussian { mean, .. } => *mean,
             Self::BackToBackExp { peak_time, .. } => *peak_time,
+            Self::Gamma { peak_time, .. } => *peak_time,
+            Self::FromFile { peak_time, .. } => *peak_time,
         }) as Time
     }
 
@@ -212,9 +415,76 @@ impl PulseEvent {
 
                 normalising_factor * (rising_exp * rising_erfc + falling_exp * falling_erfc)
             }
+            Self::Gamma { peak_height, .. } => *peak_height,
+            Self::FromFile { height, .. } => *height,
         }) as Intensity
     }
 
+    /// Returns the pulse with its amplitude multiplied by `fraction`, which should be between zero and one.
+    /// The start and stop of the pulse are unchanged, these still bound the values at least one, as the amplitude is not increased.
+    pub(crate) fn scaled(&self, fraction: f64) -> Self {
+        let mut pulse = self.clone();
+        match &mut pulse {
+            Self::Flat { amplitude, .. } | Self::Triangular { amplitude, .. } => {
+                *amplitude *= fraction
+            }
+            Self::Gaussian { peak_amplitude, .. } => *peak_amplitude *= fraction,
+            Self::BackToBackExp {
+                normalising_factor, ..
+            } => *normalising_factor *= fraction,
+            Self::Gamma { peak_height, .. } => *peak_height *= fraction,
+            Self::FromFile { height, .. } => *height *= fraction,
+        }
+        pulse
+    }
+
+    /// Returns the pulse with each of its times moved later by `offset`.
+    pub(crate) fn shifted(&self, offset: f64) -> Self {
+        let mut pulse = self.clone();
+        match &mut pulse {
+            Self::Flat { start, stop, .. } => {
+                *start += offset;
+                *stop += offset;
+            }
+            Self::Gaussian {
+                start, stop, mean, ..
+            } => {
+                *start += offset;
+                *stop += offset;
+                *mean += offset;
+            }
+            Self::Triangular {
+                start,
+                peak_time,
+                stop,
+                ..
+            }
+            | Self::BackToBackExp {
+                start,
+                stop,
+                peak_time,
+                ..
+            }
+            | Self::Gamma {
+                start,
+                stop,
+                peak_time,
+                ..
+            }
+            | Self::FromFile {
+                start,
+                stop,
+                peak_time,
+                ..
+            } => {
+                *start += offset;
+                *stop += offset;
+                *peak_time += offset;
+            }
+        }
+        pulse
+    }
+
     pub(crate) fn get_value_at(&self, time: f64) -> f64 {
         if self.get_start() > time as Time || time as Time > self.get_end() {
             return Default::default();
@@ -270,6 +540,26 @@ impl PulseEvent {
 
                 normalising_factor * (rising_exp * rising_erfc + falling_exp * falling_erfc)
             }
+            Self::Gamma {
+                peak_time,
+                peak_height,
+                mode,
+                scale_theta,
+                ..
+            } => {
+                let x = (time - peak_time) / scale_theta + mode;
+                if x < 0.0 {
+                    Default::default()
+                } else {
+                    peak_height * f64::exp(gamma_log_relative_value(mode, x))
+                }
+            }
+            Self::FromFile {
+                start,
+                height,
+                ref values,
+                ..
+            } => height * PulseShape::value_at(values, time - start),
         }
     }
 }
@@ -387,4 +677,103 @@ mod tests {
             );
         }
     }
+
+    const GAMMA_TEMPLATE: PulseTemplate = PulseTemplate::Gamma {
+        peak_height: FloatRandomDistribution::ConstantFloat {
+            value: NumExpression::Const(1000.0),
+        },
+        peak_time: FloatRandomDistribution::ConstantFloat {
+            value: NumExpression::Const(2200.0),
+        },
+        shape_k: FloatRandomDistribution::ConstantFloat {
+            value: NumExpression::Const(3.0),
+        },
+        scale_theta: FloatRandomDistribution::ConstantFloat {
+            value: NumExpression::Const(4.0),
+        },
+    };
+
+    #[test]
+    fn gamma_template() {
+        let pulse = PulseEvent::sample(&GAMMA_TEMPLATE, 0).unwrap();
+        assert_eq!(pulse.get_start(), 2192);
+        assert_eq!(pulse.get_end(), 2242);
+        assert_eq!(pulse.intensity(), 1000);
+        assert_eq!(pulse.time(), 2200);
+    }
+
+    #[test]
+    fn gamma_values() {
+        let pulse = PulseEvent::sample(&GAMMA_TEMPLATE, 0).unwrap();
+        const VALUES: [Intensity; 52] = [
+            0, 89, 280, 490, 679, 826, 927, 983, 1000, 985, 947, 893, 827, 756, 683, 610, 541, 475,
+            415, 360, 311, 267, 228, 194, 164, 139, 117, 98, 82, 68, 57, 47, 39, 32, 27, 22, 18,
+            15, 12, 10, 8, 6, 5, 4, 3, 3, 2, 2, 1, 1, 1, 0,
+        ];
+        for (t, &v) in VALUES.iter().enumerate() {
+            assert_eq!(
+                pulse.get_value_at((pulse.get_start() + t as Time) as f64) as Intensity,
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn gamma_exponential_shape() {
+        // With `shape_k` at most one, the pulse is an exponential decay starting at its peak.
+        let template = PulseTemplate::Gamma {
+            peak_height: FloatRandomDistribution::ConstantFloat {
+                value: NumExpression::Const(100.0),
+            },
+            peak_time: FloatRandomDistribution::ConstantFloat {
+                value: NumExpression::Const(50.0),
+            },
+            shape_k: FloatRandomDistribution::ConstantFloat {
+                value: NumExpression::Const(0.5),
+            },
+            scale_theta: FloatRandomDistribution::ConstantFloat {
+                value: NumExpression::Const(10.0),
+            },
+        };
+        let pulse = PulseEvent::sample(&template, 0).unwrap();
+        assert_eq!(pulse.get_start(), 50);
+        // The value falls to one after ln(100) scale lengths.
+        assert_eq!(pulse.get_end(), 96);
+        assert_eq!(pulse.get_value_at(49.0), 0.0);
+        assert_eq!(pulse.get_value_at(50.0), 100.0);
+        assert_eq!(pulse.get_value_at(60.0) as Intensity, 36);
+    }
+
+    #[test]
+    fn gamma_deserialize() {
+        let template: PulseTemplate = serde_json::from_str(
+            r#"{
+                "pulse-type": "gamma",
+                "peak_height": { "random-type": "constant-float", "value": { "const": 1000 } },
+                "peak_time":   { "random-type": "constant-float", "value": { "const": 2200 } },
+                "shape_k":     { "random-type": "constant-float", "value": { "const": 3 } },
+                "scale_theta": { "random-type": "constant-float", "value": { "const": 4 } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            PulseEvent::sample(&template, 0).unwrap(),
+            PulseEvent::sample(&GAMMA_TEMPLATE, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn gamma_zero_scale_is_rejected() {
+        let error = serde_json::from_str::<PulseTemplate>(
+            r#"{
+                "pulse-type": "gamma",
+                "peak_height": { "random-type": "constant-float", "value": { "const": 1000 } },
+                "peak_time":   { "random-type": "constant-float", "value": { "const": 2200 } },
+                "shape_k":     { "random-type": "constant-float", "value": { "const": 3 } },
+                "scale_theta": { "random-type": "constant-float", "value": { "const": 0 } }
+            }"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("scale_theta must not be zero"));
+    }
 }
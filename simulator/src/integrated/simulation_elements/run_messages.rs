@@ -1,5 +1,8 @@
 use crate::{
-    integrated::simulation_elements::{noise::NoiseSource, utils::TextConstant},
+    integrated::simulation_elements::{
+        noise::NoiseSource,
+        utils::{FloatRandomDistribution, TextConstant},
+    },
     runs::{
         alarm::SeverityLevel,
         runlog::ValueType,
@@ -23,6 +26,33 @@ pub(crate) struct SendRunStop {
     pub(crate) name: TextConstant,
 }
 
+/// Sends a `RunStop` message which does not end the current run, such as one before any run has started.
+/// If `name` is absent, the message is for the current run.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SendRunStopUnmatched {
+    #[serde(default)]
+    pub(crate) name: Option<TextConstant>,
+}
+
+/// Sends a second `RunStart` message for the current run, with the current timestamp.
+/// If `name` is present, it replaces the name of the current run in the message.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SendRunStartDuplicate {
+    #[serde(default)]
+    pub(crate) name: Option<TextConstant>,
+}
+
+/// Sends a `MAJOR` severity `Alarm` message reporting that a run was aborted.
+/// If `name` is absent, the aborted run is the current run.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SendRunAbort {
+    #[serde(default)]
+    pub(crate) name: Option<TextConstant>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", tag = "run-command")]
 pub(crate) struct SendRunLogData {
@@ -31,6 +61,23 @@ pub(crate) struct SendRunLogData {
     pub(crate) value: Vec<String>,
 }
 
+/// Sends `repeat` f144 log messages of type double, with values sampled from `value`.
+/// The first message is stamped with the current timestamp, and each subsequent one `interval-ms` later.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SendLogData {
+    pub(crate) source_name: TextConstant,
+    pub(crate) value: FloatRandomDistribution<f64>,
+    #[serde(default = "default_log_data_repeat")]
+    pub(crate) repeat: usize,
+    #[serde(default)]
+    pub(crate) interval_ms: u64,
+}
+
+fn default_log_data_repeat() -> usize {
+    1
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct SendSampleEnvLog {
@@ -3,9 +3,9 @@ use num::{
     Float, Num, NumCast,
     traits::{Inv, NumOps, int::PrimInt},
 };
-use rand::{RngExt, SeedableRng};
-use rand_distr::{Distribution, Exp, Normal, uniform::SampleUniform};
-use serde::Deserialize;
+use rand::{RngExt, SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, Exp, Normal, Poisson, uniform::SampleUniform};
+use serde::{Deserialize, Serialize};
 use std::{
     env::{self, VarError},
     num::{ParseFloatError, ParseIntError},
@@ -28,6 +28,20 @@ pub(crate) enum JsonValueError {
     NormalDistribution(#[from] rand_distr::NormalError),
     #[error("Invalid Exponential Distribution: {0}")]
     ExpDistribution(#[from] rand_distr::ExpError),
+    #[error("Invalid Poisson Distribution: {0}")]
+    PoissonDistribution(#[from] rand_distr::PoissonError),
+    #[error("Sampled value {0} out of range")]
+    SampleOutOfRange(f64),
+    #[error("Sampled count {0} is negative")]
+    NegativeCount(i64),
+    #[error("Expression value {0} out of range")]
+    ExpressionOutOfRange(f64),
+    #[error("Expression nested deeper than {0}")]
+    ExpressionTooDeep(usize),
+    #[error("Piecewise expression has no pieces")]
+    EmptyPiecewise,
+    #[error("Pulse shapes sampled before being loaded")]
+    PulseShapesNotLoaded,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -66,12 +80,35 @@ impl TextConstant {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// A number which may vary with the frame index.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum NumExpression<T> {
     Const(T),
     FromEnvVar(String),
     NumFunc(Transformation<T>),
+    /// `amplitude * sin(2π (frame_index + phase) / period) + offset`.
+    /// This is computed in floating point, and rounded to the nearest integer for integer types.
+    Sin {
+        amplitude: f64,
+        period: f64,
+        phase: f64,
+        offset: f64,
+    },
+    /// The value of the first piece whose `until-frame` is at or after the frame index.
+    /// Beyond the last piece, its value continues.
+    Piecewise(Vec<Piece<T>>),
+    /// The sum of the values of each expression, or zero if there are none.
+    Sum(Vec<NumExpression<T>>),
+}
+
+/// A part of a [NumExpression::Piecewise] expression.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Piece<T> {
+    /// The last frame index to which this piece applies.
+    pub(crate) until_frame: usize,
+    pub(crate) value: NumExpression<T>,
 }
 
 impl<T> NumExpression<T>
@@ -79,17 +116,66 @@ where
     T: Num + NumCast + FromStr + Copy,
     JsonValueError: From<<T as FromStr>::Err>,
 {
+    /// The maximum depth to which expressions may be nested within [Self::Piecewise] and [Self::Sum].
+    const MAX_DEPTH: usize = 16;
+
     pub(crate) fn value(&self, frame_index: usize) -> Result<T, JsonValueError> {
+        self.value_at_depth(frame_index, 0)
+    }
+
+    /// Returns the value if it is a constant, which does not depend on the frame index or environment.
+    pub(crate) fn constant(&self) -> Option<T> {
+        match self {
+            Self::Const(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn value_at_depth(&self, frame_index: usize, depth: usize) -> Result<T, JsonValueError> {
+        if depth > Self::MAX_DEPTH {
+            return Err(JsonValueError::ExpressionTooDeep(Self::MAX_DEPTH));
+        }
         match self {
             Self::Const(v) => Ok(*v),
             Self::FromEnvVar(environment_variable) => Ok(env::var(environment_variable)?.parse()?),
             Self::NumFunc(frame_function) => Ok(frame_function.transform(
                 NumCast::from::<usize>(frame_index).ok_or(JsonValueError::UsizeConvert)?,
             )),
+            Self::Sin {
+                amplitude,
+                period,
+                phase,
+                offset,
+            } => from_f64(
+                amplitude * (std::f64::consts::TAU * (frame_index as f64 + phase) / period).sin()
+                    + offset,
+            ),
+            Self::Piecewise(pieces) => pieces
+                .iter()
+                .find(|piece| frame_index <= piece.until_frame)
+                .or(pieces.last())
+                .ok_or(JsonValueError::EmptyPiecewise)?
+                .value
+                .value_at_depth(frame_index, depth + 1),
+            Self::Sum(expressions) => expressions.iter().try_fold(T::zero(), |sum, expression| {
+                Ok(sum + expression.value_at_depth(frame_index, depth + 1)?)
+            }),
         }
     }
 }
 
+/// Converts `value` to `T`, rounding to the nearest integer if `T` is an integer type.
+fn from_f64<T: NumCast>(value: f64) -> Result<T, JsonValueError> {
+    // Conversions to integer types truncate, so one half only survives conversion to a floating point type.
+    let is_integer = <T as NumCast>::from(0.5).and_then(|half| half.to_f64()) != Some(0.5);
+    let converted = if is_integer {
+        <T as NumCast>::from(value.round())
+    } else {
+        <T as NumCast>::from(value)
+    };
+    converted.ok_or(JsonValueError::ExpressionOutOfRange(value))
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case", tag = "random-type")]
 pub(crate) enum FloatRandomDistribution<T> {
@@ -117,30 +203,48 @@ where
     rand_distr::Exp1: rand_distr::Distribution<T>,
 {
     pub(crate) fn sample(&self, frame_index: usize) -> Result<T, JsonValueError> {
+        self.sample_with(
+            frame_index,
+            &mut StdRng::seed_from_u64(Utc::now().timestamp_subsec_nanos() as u64),
+        )
+    }
+
+    /// Samples the distribution with `rng`, so that a seeded `rng` gives the same samples each time.
+    pub(crate) fn sample_with(
+        &self,
+        frame_index: usize,
+        rng: &mut StdRng,
+    ) -> Result<T, JsonValueError> {
         match self {
             Self::ConstantFloat { value } => value.value(frame_index),
             Self::UniformFloat { min, max } => {
-                let val =
-                    rand::rngs::StdRng::seed_from_u64(Utc::now().timestamp_subsec_nanos() as u64)
-                        .random_range(min.value(frame_index)?..max.value(frame_index)?);
-                Ok(val)
+                Ok(rng.random_range(min.value(frame_index)?..max.value(frame_index)?))
             }
             Self::Normal { mean, sd } => {
-                let val = Normal::new(mean.value(frame_index)?, sd.value(frame_index)?)?.sample(
-                    &mut rand::rngs::StdRng::seed_from_u64(
-                        Utc::now().timestamp_subsec_nanos() as u64
-                    ),
-                );
-                Ok(val)
+                Ok(Normal::new(mean.value(frame_index)?, sd.value(frame_index)?)?.sample(rng))
             }
             Self::Exponential { lifetime } => {
-                let val = Exp::new(lifetime.value(frame_index)?.inv())?.sample(
-                    &mut rand::rngs::StdRng::seed_from_u64(
-                        Utc::now().timestamp_subsec_nanos() as u64
-                    ),
-                );
-                Ok(val)
+                Ok(Exp::new(lifetime.value(frame_index)?.inv())?.sample(rng))
+            }
+        }
+    }
+
+    /// Returns the greatest magnitude which could be sampled, which is infinite if the distribution is unbounded,
+    /// or [None] if its parameters are not constants.
+    pub(crate) fn max_magnitude(&self) -> Option<T> {
+        match self {
+            Self::ConstantFloat { value } => Some(value.constant()?.abs()),
+            Self::UniformFloat { min, max } => {
+                Some(min.constant()?.abs().max(max.constant()?.abs()))
             }
+            Self::Normal { mean, sd } => {
+                if sd.constant()?.is_zero() {
+                    Some(mean.constant()?.abs())
+                } else {
+                    Some(T::infinity())
+                }
+            }
+            Self::Exponential { .. } => Some(T::infinity()),
         }
     }
 }
@@ -155,6 +259,10 @@ pub(crate) enum IntRandomDistribution<T> {
         min: NumExpression<T>,
         max: NumExpression<T>,
     },
+    /// A mean of zero always samples zero.
+    Poisson {
+        mean: NumExpression<f64>,
+    },
 }
 
 impl<T: PrimInt + FromStr + SampleUniform> IntRandomDistribution<T>
@@ -170,6 +278,16 @@ where
                     .random_range(min.value(frame_index)?..max.value(frame_index)?);
                 Ok(value)
             }
+            Self::Poisson { mean } => {
+                let mean = mean.value(frame_index)?;
+                if mean == 0.0 {
+                    return Ok(T::zero());
+                }
+                let val = Poisson::new(mean)?.sample(&mut rand::rngs::StdRng::seed_from_u64(
+                    Utc::now().timestamp_subsec_nanos() as u64,
+                ));
+                <T as NumCast>::from(val).ok_or(JsonValueError::SampleOutOfRange(val))
+            }
         }
     }
 }
@@ -207,15 +325,141 @@ where
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Transformation<T> {
     pub(crate) scale: T,
     pub(crate) translate: T,
 }
 
+impl<T: Num> Default for Transformation<T> {
+    /// The identity transformation.
+    fn default() -> Self {
+        Self {
+            scale: T::one(),
+            translate: T::zero(),
+        }
+    }
+}
+
 impl<T: NumOps + Copy> Transformation<T> {
     pub(crate) fn transform(&self, x: T) -> T {
         x * self.scale + self.translate
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse<T: for<'de> Deserialize<'de>>(json: &str) -> NumExpression<T> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// Asserts that `json` deserializes to an expression which serializes back to the same json.
+    fn assert_round_trips<T: Serialize + for<'de> Deserialize<'de>>(json: &str) {
+        let expression = parse::<T>(json);
+        assert_eq!(
+            serde_json::to_value(&expression).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
+
+    const SIN: &str =
+        r#"{ "sin": { "amplitude": 10.0, "period": 4.0, "phase": 0.0, "offset": 20.0 } }"#;
+    const PIECEWISE: &str = r#"
+    {
+        "piecewise": [
+            { "until-frame": 9, "value": { "const": 0.0 } },
+            { "until-frame": 19, "value": { "num-func": { "scale": 1.0, "translate": -10.0 } } },
+            { "until-frame": 29, "value": { "const": 10.0 } }
+        ]
+    }
+    "#;
+    const SUM: &str = r#"
+    {
+        "sum": [
+            { "const": 5 },
+            { "num-func": { "scale": 2, "translate": 0 } },
+            { "sin": { "amplitude": 1.0, "period": 8.0, "phase": 2.0, "offset": 0.0 } }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn expressions_round_trip() {
+        assert_round_trips::<f64>(r#"{ "const": 1.5 }"#);
+        assert_round_trips::<f64>(r#"{ "from-env-var": "VARIABLE" }"#);
+        assert_round_trips::<f64>(r#"{ "num-func": { "scale": 2.0, "translate": 1.0 } }"#);
+        assert_round_trips::<f64>(SIN);
+        assert_round_trips::<f64>(PIECEWISE);
+        assert_round_trips::<i64>(SUM);
+    }
+
+    #[test]
+    fn sin_is_evaluated_against_frame_index() {
+        let expression = parse::<f64>(SIN);
+        for (frame_index, expected) in [(0, 20.0), (1, 30.0), (2, 20.0), (3, 10.0), (4, 20.0)] {
+            assert!((expression.value(frame_index).unwrap() - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn sin_is_rounded_for_integers() {
+        let expression = parse::<u32>(
+            r#"{ "sin": { "amplitude": 10.0, "period": 12.0, "phase": 0.0, "offset": 20.0 } }"#,
+        );
+        // 10 sin(π/6) + 20 = 25, and 10 sin(π/3) + 20 = 28.66...
+        assert_eq!(expression.value(1).unwrap(), 25);
+        assert_eq!(expression.value(2).unwrap(), 29);
+
+        let negative = parse::<u32>(
+            r#"{ "sin": { "amplitude": 10.0, "period": 4.0, "phase": 0.0, "offset": 0.0 } }"#,
+        );
+        assert!(matches!(
+            negative.value(3),
+            Err(JsonValueError::ExpressionOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn piecewise_selects_piece_by_frame_index() {
+        let expression = parse::<f64>(PIECEWISE);
+        assert_eq!(expression.value(0).unwrap(), 0.0);
+        assert_eq!(expression.value(9).unwrap(), 0.0);
+        assert_eq!(expression.value(10).unwrap(), 0.0);
+        assert_eq!(expression.value(15).unwrap(), 5.0);
+        assert_eq!(expression.value(19).unwrap(), 9.0);
+        assert_eq!(expression.value(20).unwrap(), 10.0);
+        // Beyond the last piece, its value continues.
+        assert_eq!(expression.value(1000).unwrap(), 10.0);
+
+        assert!(matches!(
+            parse::<f64>(r#"{ "piecewise": [] }"#).value(0),
+            Err(JsonValueError::EmptyPiecewise)
+        ));
+    }
+
+    #[test]
+    fn sum_adds_expressions() {
+        let expression = parse::<i64>(SUM);
+        // 5 + 2 * 3 + round(sin(5π/4))
+        assert_eq!(expression.value(3).unwrap(), 10);
+        assert_eq!(parse::<i64>(r#"{ "sum": [] }"#).value(3).unwrap(), 0);
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let nested = |depth| {
+            (0..depth).fold(NumExpression::Const(1), |expression, _| {
+                NumExpression::Sum(vec![expression])
+            })
+        };
+        let max_depth = NumExpression::<i32>::MAX_DEPTH;
+        assert_eq!(nested(max_depth).value(0).unwrap(), 1);
+        assert!(matches!(
+            nested(max_depth + 1).value(0),
+            Err(JsonValueError::ExpressionTooDeep(_))
+        ));
+    }
+}
@@ -1,9 +1,13 @@
 use crate::integrated::simulation_elements::{
     Interval,
-    run_messages::{SendAlarm, SendRunLogData, SendRunStart, SendRunStop, SendSampleEnvLog},
-    utils::NumConstant,
+    run_messages::{
+        SendAlarm, SendLogData, SendRunAbort, SendRunLogData, SendRunStart, SendRunStartDuplicate,
+        SendRunStop, SendRunStopUnmatched, SendSampleEnvLog,
+    },
+    utils::{NumConstant, NumExpression},
 };
 use chrono::{DateTime, Utc};
+use digital_muon_common::DigitizerId;
 use serde::Deserialize;
 
 #[derive(Clone, Copy, Debug, Deserialize)]
@@ -24,7 +28,9 @@ pub(crate) enum SourceOptions {
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct SendAggregatedEventListOptions {
     pub(crate) source_options: SourceOptions,
-    pub(crate) channel_indices: Interval<usize>,
+    /// If absent, the message covers every channel, in the order of the digitiser config.
+    #[serde(default)]
+    pub(crate) channel_indices: Option<Interval<usize>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -54,6 +60,14 @@ pub(crate) struct GenerateEventList {
 pub(crate) struct Loop<A> {
     pub(crate) start: NumConstant<usize>,
     pub(crate) end: NumConstant<usize>,
+    /// If present, the frames of a frame loop are produced at this many per second, overriding `--target-frame-rate-hz`.
+    /// This is only used by frame loops.
+    #[serde(default)]
+    pub(crate) target_frame_rate_hz: Option<f64>,
+    /// If true, after each frame of a frame loop, the events of the trace messages sent for the frame
+    /// are also sent as an aggregated frame event list. This is only used by frame loops.
+    #[serde(default)]
+    pub(crate) also_emit_aggregated: bool,
     pub(crate) schedule: Vec<A>,
 }
 
@@ -80,6 +94,77 @@ pub(crate) struct TracingEvent {
     pub(crate) message: String,
 }
 
+/// What to do when no message matching a [WaitForMessage] arrives in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum OnTimeout {
+    /// Log a warning, and continue with the schedule.
+    #[default]
+    Continue,
+    /// End the simulation with an error.
+    Abort,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct WaitForMessage {
+    /// The topic on which the message is awaited.
+    pub(crate) topic: String,
+    /// If present, only a message whose key contains this matches.
+    #[serde(default)]
+    pub(crate) key_contains: Option<String>,
+    /// If true, only a digitiser trace, digitiser event list or frame assembled event list message
+    /// whose frame number is that of the current frame matches.
+    #[serde(default)]
+    pub(crate) match_frame_number: bool,
+    /// The longest the schedule is paused for.
+    pub(crate) timeout_ms: u64,
+    #[serde(default)]
+    pub(crate) on_timeout: OnTimeout,
+}
+
+/// What a digitiser sends whilst it has dropped out, see [DigitiserDropout].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DropoutMode {
+    /// Nothing is sent.
+    #[default]
+    Silent,
+    /// Each message is sent with its payload corrupted, so that it fails verification.
+    Garbage,
+}
+
+/// One digitiser id, or a list of them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum DigitiserIds {
+    One(DigitizerId),
+    Many(Vec<DigitizerId>),
+}
+
+impl DigitiserIds {
+    pub(crate) fn ids(&self) -> &[DigitizerId] {
+        match self {
+            Self::One(id) => std::slice::from_ref(id),
+            Self::Many(ids) => ids,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DigitiserDropout {
+    /// The ids, rather than the indices, of the digitisers which drop out.
+    pub(crate) digitiser_id: DigitiserIds,
+    /// The number of frames started after this action, by any frame loop, before the dropout begins.
+    #[serde(default)]
+    pub(crate) start_offset_frames: usize,
+    /// The number of frames for which the digitisers send their trace and event list messages as given by [Self::mode].
+    pub(crate) duration_frames: usize,
+    #[serde(default)]
+    pub(crate) mode: DropoutMode,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum Action {
@@ -87,11 +172,21 @@ pub(crate) enum Action {
     TracingEvent(TracingEvent),
     WaitMs(usize),
     EnsureDelayMs(usize),
+    /// Pauses the schedule until a matching message arrives, see [WaitForMessage].
+    WaitForMessage(WaitForMessage),
     SendRunStart(SendRunStart),
     SendRunStop(SendRunStop),
+    SendRunStopUnmatched(SendRunStopUnmatched),
+    SendRunStartDuplicate(SendRunStartDuplicate),
+    SendRunAbort(SendRunAbort),
     SendRunLogData(SendRunLogData),
+    SendLogData(SendLogData),
     SendSampleEnvLog(SendSampleEnvLog),
     SendAlarm(SendAlarm),
+    /// Logs that the pipeline is expected to have survived the messages sent so far.
+    ExpectNoCrash(String),
+    /// Drops out digitisers for a span of the frames which follow, see [DigitiserDropout].
+    DigitiserDropout(DigitiserDropout),
     //
     FrameLoop(Loop<FrameAction>),
     //
@@ -113,13 +208,27 @@ pub(crate) enum FrameAction {
     Comment(#[allow(unused)] String),
     WaitMs(usize),
     EnsureDelayMs(usize),
+    /// Pauses the schedule until a matching message arrives, see [WaitForMessage].
+    WaitForMessage(WaitForMessage),
     TracingEvent(TracingEvent),
     //
     SendAggregatedFrameEventList(SendAggregatedEventListOptions),
+    SendLogData(SendLogData),
+    SendRunStopUnmatched(SendRunStopUnmatched),
+    SendRunStartDuplicate(SendRunStartDuplicate),
+    SendRunAbort(SendRunAbort),
+    /// Logs that the pipeline is expected to have survived the messages sent so far.
+    ExpectNoCrash(String),
     //
     DigitiserLoop(Loop<DigitiserAction>),
     //
     SetTimestamp(Timestamp),
+    /// Sets the protons per pulse from this frame onwards, until the end of the frame loop.
+    /// The expression is evaluated at the frame number, and must lie in the range of a `u8`.
+    SetProtonsPerPulse(NumExpression<i64>),
+    /// Sets the veto flags from this frame onwards, until the end of the frame loop.
+    /// The expression is evaluated at the frame number, and must lie in the range of a `u16`.
+    SetVetoFlags(NumExpression<i64>),
     //
     GenerateTrace(GenerateTrace),
     GenerateEventList(GenerateEventList),
@@ -146,8 +255,57 @@ pub(crate) enum DigitiserAction {
 pub(crate) enum LogAction {
     Comment(#[allow(unused)] String),
     SendRunLogData(SendRunLogData),
+    SendLogData(SendLogData),
     SendSampleEnvLog(SendSampleEnvLog),
     SendAlarm(SendAlarm),
     SetTimestamp(Timestamp),
     WaitMs(usize),
 }
+
+impl Action {
+    /// Returns true if this action sends a message which is not specific to a digitiser,
+    /// so should be sent only by the primary [Shard](super::shard::Shard).
+    pub(crate) fn is_shared(&self) -> bool {
+        matches!(
+            self,
+            Self::SendRunStart(_)
+                | Self::SendRunStop(_)
+                | Self::SendRunStopUnmatched(_)
+                | Self::SendRunStartDuplicate(_)
+                | Self::SendRunAbort(_)
+                | Self::SendRunLogData(_)
+                | Self::SendLogData(_)
+                | Self::SendSampleEnvLog(_)
+                | Self::SendAlarm(_)
+        )
+    }
+}
+
+impl FrameAction {
+    /// Returns true if this action sends a message which is not specific to a digitiser,
+    /// so should be sent only by the primary [Shard](super::shard::Shard).
+    pub(crate) fn is_shared(&self) -> bool {
+        matches!(
+            self,
+            Self::SendAggregatedFrameEventList(_)
+                | Self::SendLogData(_)
+                | Self::SendRunStopUnmatched(_)
+                | Self::SendRunStartDuplicate(_)
+                | Self::SendRunAbort(_)
+        )
+    }
+}
+
+impl LogAction {
+    /// Returns true if this action sends a message which is not specific to a digitiser,
+    /// so should be sent only by the primary [Shard](super::shard::Shard).
+    pub(crate) fn is_shared(&self) -> bool {
+        matches!(
+            self,
+            Self::SendRunLogData(_)
+                | Self::SendLogData(_)
+                | Self::SendSampleEnvLog(_)
+                | Self::SendAlarm(_)
+        )
+    }
+}
@@ -1,13 +1,11 @@
 use super::actions::SelectionModeOptions;
 use chrono::Utc;
-use rand::{RngExt, SeedableRng, seq::SliceRandom};
+use rand::{RngExt, SeedableRng};
 use std::collections::VecDeque;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub(crate) enum CacheError {
-    #[error("Attempted to read from Empty Cache")]
-    CacheEmpty,
     #[error("Attempted to read {0} form  Cache of size {1}")]
     CacheTooSmall(usize, usize),
 }
@@ -15,16 +13,11 @@ pub(crate) enum CacheError {
 pub(crate) trait SimulationEngineCache: Default + Extend<Self::Item> {
     type Item;
 
-    fn extract_one(
-        &mut self,
-        selection_mode: SelectionModeOptions,
-    ) -> Result<&Self::Item, CacheError>;
     fn extract(
         &mut self,
         selection_mode: SelectionModeOptions,
         amount: usize,
     ) -> Result<Vec<&Self::Item>, CacheError>;
-    fn finish_one(&mut self, selection_mode: SelectionModeOptions) -> Result<(), CacheError>;
     fn finish(
         &mut self,
         selection_mode: SelectionModeOptions,
@@ -35,21 +28,11 @@ pub(crate) trait SimulationEngineCache: Default + Extend<Self::Item> {
 impl<T> SimulationEngineCache for VecDeque<T> {
     type Item = T;
 
-    fn extract_one(
-        &mut self,
-        selection_mode: SelectionModeOptions,
-    ) -> Result<&Self::Item, CacheError> {
-        match selection_mode {
-            SelectionModeOptions::PopFront => self.front().ok_or(CacheError::CacheEmpty),
-            SelectionModeOptions::ReplaceRandom => {
-                let mut rng =
-                    rand::rngs::StdRng::seed_from_u64(Utc::now().timestamp_subsec_nanos().into());
-                self.get(rng.random_range(0..self.len()))
-                    .ok_or(CacheError::CacheEmpty)
-            }
-        }
-    }
-
+    /// Selects `amount` consecutive entries.
+    ///
+    /// With [SelectionModeOptions::ReplaceRandom], the entries are one of the blocks of `amount` entries
+    /// into which the cache divides, chosen at random, so entries generated together for the channels of a digitiser,
+    /// such as event lists whose pulses are coincident, are selected together, in the order they were generated.
     fn extract(
         &mut self,
         selection_mode: SelectionModeOptions,
@@ -58,30 +41,21 @@ impl<T> SimulationEngineCache for VecDeque<T> {
         let indices = match selection_mode {
             SelectionModeOptions::PopFront => self.iter().take(amount).collect(),
             SelectionModeOptions::ReplaceRandom => {
+                if amount == 0 {
+                    return Ok(Vec::new());
+                }
+                if self.len() < amount {
+                    return Err(CacheError::CacheTooSmall(amount, self.len()));
+                }
                 let mut rng =
                     rand::rngs::StdRng::seed_from_u64(Utc::now().timestamp_subsec_nanos().into());
-                let mut indices = (0..self.len()).collect::<Vec<_>>();
-                let (random_indices, _) = indices.partial_shuffle(&mut rng, amount);
-                random_indices
-                    .iter()
-                    .map(|i| self.get(*i))
-                    .collect::<Option<_>>()
-                    .ok_or(CacheError::CacheTooSmall(amount, self.len()))?
+                let start = rng.random_range(0..self.len() / amount) * amount;
+                self.range(start..start + amount).collect()
             }
         };
         Ok(indices)
     }
 
-    fn finish_one(&mut self, selection_mode: SelectionModeOptions) -> Result<(), CacheError> {
-        match selection_mode {
-            SelectionModeOptions::PopFront => {
-                self.pop_front().ok_or(CacheError::CacheEmpty)?;
-            }
-            SelectionModeOptions::ReplaceRandom => {}
-        }
-        Ok(())
-    }
-
     fn finish(
         &mut self,
         selection_mode: SelectionModeOptions,
@@ -99,3 +73,32 @@ impl<T> SimulationEngineCache for VecDeque<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_selections_are_blocks_generated_together() {
+        let mut cache = VecDeque::from_iter(0..10);
+        for _ in 0..100 {
+            let selected = cache
+                .extract(SelectionModeOptions::ReplaceRandom, 3)
+                .unwrap()
+                .into_iter()
+                .copied()
+                .collect::<Vec<_>>();
+            let first = selected[0];
+            assert_eq!(first % 3, 0);
+            assert_eq!(selected, [first, first + 1, first + 2]);
+            cache
+                .finish(SelectionModeOptions::ReplaceRandom, 3)
+                .unwrap();
+        }
+        assert_eq!(cache.len(), 10);
+        assert!(matches!(
+            cache.extract(SelectionModeOptions::ReplaceRandom, 11),
+            Err(CacheError::CacheTooSmall(11, 10))
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+//! Accepts actions on a TCP socket whilst a simulation runs, so that they can be triggered on demand,
+//! rather than only by the schedule.
+//!
+//! Each line received is a json encoded [Action], exactly as it would appear in a schedule.
+//! The action is queued, and applied by the engine at the next frame boundary, or between the actions of the schedule,
+//! after which the line `ok` is returned, or `error: ` followed by the reason it could not be applied.
+use super::actions::Action;
+use std::{
+    fmt::Display,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// An action received on the control socket, and where to report whether it was applied.
+struct ControlCommand {
+    action: Action,
+    reply: Sender<Result<(), String>>,
+}
+
+/// Receives the actions sent to the control socket, which is served by its own threads.
+pub(crate) struct ControlSocket {
+    address: SocketAddr,
+    receiver: Receiver<ControlCommand>,
+    /// If present, once the schedule is complete, actions are applied until none are received for this long.
+    linger: Option<Duration>,
+}
+
+impl ControlSocket {
+    /// Binds the control socket to `address`, and accepts connections to it until the process ends.
+    pub(crate) fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(stream, sender) {
+                                warn!("Control connection failed: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {e}"),
+                }
+            }
+        });
+        info!("Accepting actions on control socket {address}");
+        Ok(Self {
+            address,
+            receiver,
+            linger: None,
+        })
+    }
+
+    /// Sets how long, once the schedule is complete, actions continue to be applied after the last one received.
+    pub(crate) fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// The address to which the socket is bound.
+    pub(crate) fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Calls `apply` on each action received so far, without waiting for more.
+    pub(crate) fn apply_pending<E: Display>(
+        &self,
+        mut apply: impl FnMut(&Action) -> Result<(), E>,
+    ) {
+        while let Ok(command) = self.receiver.try_recv() {
+            command.apply(&mut apply);
+        }
+    }
+
+    /// If a linger is set, calls `apply` on each action received, until none are received for the duration of the linger.
+    pub(crate) fn linger<E: Display>(&self, mut apply: impl FnMut(&Action) -> Result<(), E>) {
+        let Some(linger) = self.linger else {
+            return;
+        };
+        info!("Schedule complete, applying actions from control socket until idle for {linger:?}");
+        loop {
+            match self.receiver.recv_timeout(linger) {
+                Ok(command) => command.apply(&mut apply),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+}
+
+impl ControlCommand {
+    fn apply<E: Display>(self, apply: &mut impl FnMut(&Action) -> Result<(), E>) {
+        let result = apply(&self.action).map_err(|e| e.to_string());
+        // The client may have disconnected whilst the action was applied.
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Queues each action received on `stream`, replying once it is applied.
+fn handle_connection(stream: TcpStream, sender: Sender<ControlCommand>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str::<Action>(&line) {
+            Ok(action) => {
+                let (reply, response) = mpsc::channel();
+                sender
+                    .send(ControlCommand { action, reply })
+                    .map_err(|_| "Simulation has finished".to_owned())
+                    .and_then(|_| {
+                        response.recv().unwrap_or_else(|_| {
+                            Err("Simulation finished before the action was applied".to_owned())
+                        })
+                    })
+            }
+            Err(e) => Err(format!("Invalid action: {e}")),
+        };
+        match result {
+            Ok(()) => writeln!(writer, "ok")?,
+            Err(e) => writeln!(writer, "error: {e}")?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::{
+        Topics,
+        message_sink::FileSink,
+        simulation::Simulation,
+        simulation_elements::fault_injection::FaultInjector,
+        simulation_engine::{
+            SimulationEngine, SimulationEngineExternals,
+            pacing::{Pacer, PacingOptions, QueueFullCounter},
+            run_schedule,
+            shard::Shard,
+        },
+    };
+    use chrono::{DateTime, Utc};
+    use isis_streaming_data_types::flatbuffers_generated::run_start_pl72::root_as_run_start;
+    use std::fs;
+
+    const CONTROLLED_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 1 },
+                "num-channels-per-digitiser": { "const" : 1 }
+            }
+        },
+        "pulses": [],
+        "event-lists": [],
+        "schedule": []
+    }
+    "#;
+
+    /// Sends each line to the control socket at `address`, returning the reply to each.
+    fn send_lines(address: SocketAddr, lines: &[&str]) -> Vec<String> {
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut replies = BufReader::new(stream).lines();
+        lines
+            .iter()
+            .map(|line| {
+                writeln!(writer, "{line}").unwrap();
+                replies.next().unwrap().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn actions_are_applied_by_the_engine() {
+        let control = ControlSocket::bind("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_linger(Duration::from_secs(1));
+        let address = control.address();
+        let client = thread::spawn(move || {
+            send_lines(
+                address,
+                &[
+                    r#"{ "no-such-action": {} }"#,
+                    r#"{ "send-run-abort": {} }"#,
+                    r#"{ "set-timestamp": { "to": "2025-06-01T12:00:00Z" } }"#,
+                    r#"{ "send-run-start": {
+                        "run-command": "SendRunStart",
+                        "name": { "text": "Run1" },
+                        "filename": { "text": "run1.nxs" },
+                        "instrument": { "text": "MuSR" }
+                    } }"#
+                        .replace('\n', " ")
+                        .as_str(),
+                ],
+            )
+        });
+
+        let directory =
+            std::env::temp_dir().join(format!("simulator-control-test-{}", std::process::id()));
+        let simulation: Simulation = serde_json::from_str(CONTROLLED_SIMULATION).unwrap();
+        let mut sink = FileSink::new(&directory).unwrap();
+        let queue_full = QueueFullCounter::default();
+        let mut engine = SimulationEngine::new(
+            SimulationEngineExternals {
+                sink: &mut sink,
+                topics: Topics {
+                    traces: "traces",
+                    events: "events",
+                    frame_events: "frame_events",
+                    run_controls: "run_controls",
+                    runlog: "runlog",
+                    selog: "selog",
+                    alarm: "alarm",
+                    ground_truth: None,
+                },
+                ground_truth: None,
+                shard: Shard::default(),
+                max_materialised_channels: 8,
+                fault_injector: FaultInjector::new(None),
+                clipping: Default::default(),
+                queue_full: queue_full.clone(),
+                pacer: Pacer::new(&PacingOptions::default(), queue_full),
+            },
+            &simulation,
+        )
+        .unwrap()
+        .with_control(&control);
+        run_schedule(&mut engine).unwrap();
+        engine.flush_sink().unwrap();
+
+        let replies = client.join().unwrap();
+        assert!(replies[0].starts_with("error: Invalid action"));
+        assert!(replies[1].starts_with("error: send-run-abort requires a run"));
+        assert_eq!(replies[2..], ["ok", "ok"]);
+
+        let timestamp: DateTime<Utc> = "2025-06-01T12:00:00Z".parse().unwrap();
+        let state = engine.state();
+        assert_eq!(state.timestamp(), timestamp);
+        assert_eq!(state.current_run.as_ref().unwrap().name, "Run1");
+
+        let payload = fs::read(directory.join("run_controls-00000000.bin")).unwrap();
+        let run_start = root_as_run_start(&payload).unwrap();
+        assert_eq!(run_start.run_name(), Some("Run1"));
+        assert_eq!(run_start.start_time(), timestamp.timestamp_millis() as u64);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
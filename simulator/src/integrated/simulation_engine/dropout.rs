@@ -0,0 +1,188 @@
+use super::actions::{DigitiserDropout, DropoutMode};
+use digital_muon_common::{DigitizerId, FrameNumber};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Debug, Error)]
+pub(crate) enum DropoutError {
+    #[error("Digitiser {0} is already dropped out for frames {1}..{2} of the simulation")]
+    Overlapping(DigitizerId, usize, usize),
+}
+
+/// The span of frames for which a digitiser has dropped out.
+///
+/// Frames are identified by their position in the simulation, that is the number of frames started before them
+/// by every frame loop, as frame numbers may repeat between frame loops.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DropoutWindow {
+    pub(crate) digitizer_id: DigitizerId,
+    /// The position of the first frame of the dropout.
+    pub(crate) first: usize,
+    /// The position of the first frame after the dropout, from which the digitiser has recovered.
+    pub(crate) end: usize,
+    pub(crate) mode: DropoutMode,
+}
+
+impl DropoutWindow {
+    fn contains(&self, position: usize) -> bool {
+        (self.first..self.end).contains(&position)
+    }
+
+    fn overlaps(&self, other: &DropoutWindow) -> bool {
+        self.digitizer_id == other.digitizer_id && self.first < other.end && other.first < self.end
+    }
+}
+
+impl DigitiserDropout {
+    /// Returns the window of each digitiser of the dropout, if the action is run before the frame at position `next_frame`.
+    /// A dropout of no frames has no windows.
+    pub(crate) fn windows(&self, next_frame: usize) -> Vec<DropoutWindow> {
+        if self.duration_frames == 0 {
+            return Vec::new();
+        }
+        let first = next_frame + self.start_offset_frames;
+        self.digitiser_id
+            .ids()
+            .iter()
+            .map(|&digitizer_id| DropoutWindow {
+                digitizer_id,
+                first,
+                end: first + self.duration_frames,
+                mode: self.mode,
+            })
+            .collect()
+    }
+}
+
+/// The dropouts of digitisers which have not yet recovered, across all frame loops.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Dropouts(Vec<DropoutWindow>);
+
+impl Dropouts {
+    /// Adds `windows`, unless any overlaps another window of the same digitiser, in which case none is added.
+    pub(crate) fn schedule(&mut self, windows: &[DropoutWindow]) -> Result<(), DropoutError> {
+        for (index, window) in windows.iter().enumerate() {
+            if let Some(existing) = self
+                .0
+                .iter()
+                .chain(&windows[..index])
+                .find(|existing| existing.overlaps(window))
+            {
+                return Err(DropoutError::Overlapping(
+                    existing.digitizer_id,
+                    existing.first,
+                    existing.end,
+                ));
+            }
+        }
+        self.0.extend_from_slice(windows);
+        Ok(())
+    }
+
+    /// Logs the digitisers which drop out or recover at the frame at `position`, whose frame number is `frame_number`,
+    /// and forgets those which have recovered.
+    pub(crate) fn start_frame(&mut self, position: usize, frame_number: FrameNumber) {
+        self.0.retain(|window| {
+            if window.first == position {
+                info!(
+                    "Digitiser {} dropped out ({:?}) at frame {frame_number}",
+                    window.digitizer_id, window.mode
+                );
+            }
+            if window.end == position {
+                info!(
+                    "Digitiser {} recovered at frame {frame_number}",
+                    window.digitizer_id
+                );
+            }
+            window.end > position
+        });
+    }
+
+    /// Returns how the digitiser with `digitizer_id` sends its messages in the frame at `position`, if it has dropped out.
+    pub(crate) fn mode(&self, digitizer_id: DigitizerId, position: usize) -> Option<DropoutMode> {
+        self.0
+            .iter()
+            .find(|window| window.digitizer_id == digitizer_id && window.contains(position))
+            .map(|window| window.mode)
+    }
+}
+
+/// Returns `payload` with every byte inverted.
+///
+/// The offset of a flatbuffer's root table, in its first four bytes, is less than its length,
+/// so once inverted it always lies beyond the end of the payload, and the message fails verification.
+pub(crate) fn garbage_payload(payload: Vec<u8>) -> Vec<u8> {
+    payload.into_iter().map(|byte| !byte).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::simulation_engine::actions::DigitiserIds;
+
+    fn dropout(ids: &[DigitizerId], offset: usize, duration: usize) -> DigitiserDropout {
+        DigitiserDropout {
+            digitiser_id: DigitiserIds::Many(ids.to_vec()),
+            start_offset_frames: offset,
+            duration_frames: duration,
+            mode: DropoutMode::Silent,
+        }
+    }
+
+    #[test]
+    fn deserialize_dropout() {
+        let dropout: DigitiserDropout = serde_json::from_str(
+            r#"{ "digitiser-id": 3, "start-offset-frames": 2, "duration-frames": 4, "mode": "garbage" }"#,
+        )
+        .unwrap();
+        assert_eq!(dropout.digitiser_id.ids(), [3]);
+        assert_eq!(dropout.mode, DropoutMode::Garbage);
+
+        let dropout: DigitiserDropout =
+            serde_json::from_str(r#"{ "digitiser-id": [1, 2], "duration-frames": 4 }"#).unwrap();
+        assert_eq!(dropout.digitiser_id.ids(), [1, 2]);
+        assert_eq!(dropout.start_offset_frames, 0);
+        assert_eq!(dropout.mode, DropoutMode::Silent);
+    }
+
+    #[test]
+    fn windows_are_relative_to_the_next_frame() {
+        let mut dropouts = Dropouts::default();
+        dropouts
+            .schedule(&dropout(&[1, 2], 2, 3).windows(10))
+            .unwrap();
+        for position in 9..=16 {
+            dropouts.start_frame(position, position as FrameNumber);
+            let expected = (12..15).contains(&position).then_some(DropoutMode::Silent);
+            assert_eq!(dropouts.mode(1, position), expected, "{position}");
+            assert_eq!(dropouts.mode(2, position), expected, "{position}");
+            assert_eq!(dropouts.mode(3, position), None, "{position}");
+        }
+        // Recovered digitisers are forgotten.
+        assert!(dropouts.0.is_empty());
+        assert!(dropout(&[1], 0, 0).windows(10).is_empty());
+    }
+
+    #[test]
+    fn overlapping_dropouts_of_a_digitiser_are_rejected() {
+        let mut dropouts = Dropouts::default();
+        dropouts.schedule(&dropout(&[1], 0, 3).windows(0)).unwrap();
+        // Adjacent dropouts, and those of other digitisers, do not overlap.
+        dropouts.schedule(&dropout(&[1], 3, 2).windows(0)).unwrap();
+        dropouts.schedule(&dropout(&[2], 1, 3).windows(0)).unwrap();
+
+        assert!(matches!(
+            dropouts.schedule(&dropout(&[3, 1], 4, 2).windows(0)),
+            Err(DropoutError::Overlapping(1, 3, 5))
+        ));
+        // Neither window of a rejected dropout is added.
+        assert_eq!(dropouts.mode(3, 4), None);
+
+        // A digitiser repeated in one dropout overlaps itself.
+        assert!(matches!(
+            dropouts.schedule(&dropout(&[4, 4], 0, 1).windows(0)),
+            Err(DropoutError::Overlapping(4, 0, 1))
+        ));
+    }
+}
@@ -1,34 +1,77 @@
 use crate::integrated::{
     Topics,
+    build_messages::{FrameTraceEvents, PendingTraceMessage},
+    message_sink::{MessageSink, SinkError},
     send_messages::{
-        SendError, send_aggregated_frame_event_list_message, send_alarm_command,
-        send_digitiser_event_list_message, send_digitiser_trace_message, send_run_log_command,
-        send_run_start_command, send_run_stop_command, send_se_log_command,
+        SendError, select_digitiser_trace_message, send_aggregated_frame_event_list_message,
+        send_alarm_command, send_digitiser_event_list_message, send_digitiser_trace_messages,
+        send_frame_trace_events_message, send_log_data_command, send_run_abort_command,
+        send_run_log_command, send_run_start_command, send_run_stop_command, send_se_log_command,
     },
     simulation::{Simulation, SimulationError},
     simulation_elements::{
+        FloatRandomDistribution, Transformation,
+        digitiser_config::{DigitiserConfigError, DigitiserTiming},
         event_list::{EventList, Trace},
-        utils::JsonValueError,
+        fault_injection::{FaultCounts, FaultInjector},
+        ground_truth::{GroundTruthError, GroundTruthWriter},
+        metadata_source::{MetadataRow, MetadataSource, MetadataSourceError},
+        overflow::ClippingCounts,
+        run_messages::{SendRunAbort, SendRunStart, SendRunStartDuplicate, SendRunStopUnmatched},
+        utils::{JsonValueError, NumExpression, TextConstant},
     },
-    simulation_engine::actions::{
-        Action, DigitiserAction, FrameAction, GenerateEventList, GenerateTrace, LogAction,
-        Timestamp, TracingEvent, TracingLevel,
+    simulation_engine::{
+        actions::{
+            Action, DigitiserAction, DigitiserDropout, DropoutMode, FrameAction, GenerateEventList,
+            GenerateTrace, LogAction, Timestamp, TracingEvent, TracingLevel, WaitForMessage,
+        },
+        control::ControlSocket,
+        dropout::{DropoutError, Dropouts},
+        pacing::{Pacer, QueueFullCounter},
+        shard::Shard,
+        wait::{MessageWaiter, WaitError, waited_topics},
     },
 };
 use chrono::{DateTime, TimeDelta, Utc};
 use digital_muon_common::{Channel, DigitizerId, FrameNumber};
 use digital_muon_streaming_types::FrameMetadata;
-use rdkafka::producer::FutureProducer;
-use std::{collections::VecDeque, thread::sleep, time::Duration};
+use std::{collections::VecDeque, fs::File, io::BufWriter, mem, thread::sleep, time::Duration};
 use thiserror::Error;
-use tokio::task::JoinSet;
 use tracing::{debug, info, instrument};
 
+/// The fields of the run most recently started by the schedule.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CurrentRun {
+    pub(crate) name: String,
+    pub(crate) filename: String,
+    pub(crate) instrument: String,
+}
+
+impl CurrentRun {
+    fn new(run_start: &SendRunStart) -> Result<Self, JsonValueError> {
+        Ok(Self {
+            name: run_start.name.value()?,
+            filename: run_start.filename.value()?,
+            instrument: run_start.instrument.value()?,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct SimulationEngineState {
     pub(super) metadata: FrameMetadata,
     pub(super) digitiser_index: usize,
     pub(super) delay_from: DateTime<Utc>,
+    /// The number of event lists generated so far in the current frame.
+    pub(super) event_lists_in_frame: usize,
+    /// The number of frames started so far, across all frame loops.
+    pub(super) frames_started: usize,
+    /// The metadata source row applied to the current frame, if any.
+    pub(super) metadata_row: Option<MetadataRow>,
+    /// The run most recently started, to which the run control edge case actions default.
+    pub(super) current_run: Option<CurrentRun>,
+    /// The digitisers which have dropped out, or will, and not yet recovered.
+    pub(super) dropouts: Dropouts,
 }
 
 impl Default for SimulationEngineState {
@@ -44,13 +87,194 @@ impl Default for SimulationEngineState {
             },
             digitiser_index: Default::default(),
             delay_from: Utc::now(),
+            event_lists_in_frame: Default::default(),
+            frames_started: Default::default(),
+            metadata_row: Default::default(),
+            current_run: Default::default(),
+            dropouts: Default::default(),
         }
     }
 }
 
+impl SimulationEngineState {
+    /// Returns a new state, which continues from the timestamp, running flag and current run of this one.
+    pub(crate) fn carried_over(&self) -> Self {
+        let state = Self::default();
+        Self {
+            metadata: FrameMetadata {
+                timestamp: self.metadata.timestamp,
+                running: self.metadata.running,
+                ..state.metadata
+            },
+            current_run: self.current_run.clone(),
+            ..state
+        }
+    }
+
+    /// The timestamp given to the messages currently being sent.
+    pub(crate) fn timestamp(&self) -> DateTime<Utc> {
+        self.metadata.timestamp
+    }
+
+    /// Whether the messages currently being sent are flagged as during a run.
+    pub(crate) fn running(&self) -> bool {
+        self.metadata.running
+    }
+
+    /// Sets the timestamp given to the messages subsequently sent.
+    pub(super) fn set_timestamp(
+        &mut self,
+        timestamp: &Timestamp,
+    ) -> Result<(), SimulationEngineError> {
+        match timestamp {
+            Timestamp::Now => self.metadata.timestamp = Utc::now(),
+            Timestamp::To(ts) => self.metadata.timestamp = *ts,
+            Timestamp::AdvanceByMs(ms) => {
+                self.metadata.timestamp = self
+                    .metadata
+                    .timestamp
+                    .checked_add_signed(TimeDelta::milliseconds(*ms as i64))
+                    .ok_or(SimulationEngineError::TimestampAdd(*ms))?
+            }
+            Timestamp::RewindByMs(ms) => {
+                self.metadata.timestamp = self
+                    .metadata
+                    .timestamp
+                    .checked_sub_signed(TimeDelta::milliseconds(*ms as i64))
+                    .ok_or(SimulationEngineError::TimestampSub(*ms))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the run started by `run_start` the current run, and returns it.
+    pub(super) fn start_run(
+        &mut self,
+        run_start: &SendRunStart,
+    ) -> Result<CurrentRun, SimulationEngineError> {
+        Ok(self.current_run.insert(CurrentRun::new(run_start)?).clone())
+    }
+
+    /// Returns the value of `name` if present, otherwise the name of the current run.
+    /// # Parameters
+    /// - action: the action requiring the name, which is reported if there is no current run.
+    pub(super) fn run_name(
+        &self,
+        action: &'static str,
+        name: Option<&TextConstant>,
+    ) -> Result<String, SimulationEngineError> {
+        match name {
+            Some(name) => Ok(name.value()?),
+            None => self
+                .current_run
+                .as_ref()
+                .map(|run| run.name.clone())
+                .ok_or(SimulationEngineError::NoCurrentRun(action)),
+        }
+    }
+
+    /// Returns the current run, with its name replaced by the value of `name` if present.
+    pub(super) fn duplicate_run(
+        &self,
+        name: Option<&TextConstant>,
+    ) -> Result<CurrentRun, SimulationEngineError> {
+        let action = "send-run-start-duplicate";
+        let run = self
+            .current_run
+            .as_ref()
+            .ok_or(SimulationEngineError::NoCurrentRun(action))?;
+        Ok(CurrentRun {
+            name: self.run_name(action, name)?,
+            ..run.clone()
+        })
+    }
+
+    /// Prepares the state for a new frame, taking the next row from `metadata_source` if present.
+    pub(super) fn start_frame(
+        &mut self,
+        frame_number: FrameNumber,
+        metadata_source: Option<&MetadataSource>,
+    ) -> Result<(), MetadataSourceError> {
+        self.metadata.frame_number = frame_number;
+        self.event_lists_in_frame = 0;
+        self.metadata_row = metadata_source
+            .map(|source| source.row(self.frames_started))
+            .transpose()?
+            .cloned();
+        self.dropouts.start_frame(self.frames_started, frame_number);
+        self.frames_started += 1;
+        Ok(())
+    }
+
+    /// Builds and sends the trace messages selected since this was last called.
+    ///
+    /// As the trace messages of the digitisers of a [FrameAction::DigitiserLoop] are built together,
+    /// they are built in parallel across digitisers, see [send_digitiser_trace_messages].
+    fn send_pending_trace_messages(
+        engine: &mut SimulationEngine,
+    ) -> Result<(), SimulationEngineError> {
+        if !engine.pending_trace_messages.is_empty() {
+            let pending = mem::take(&mut engine.pending_trace_messages);
+            send_digitiser_trace_messages(&mut engine.externals, &pending)?;
+        }
+        Ok(())
+    }
+
+    /// Returns how the digitiser with `digitizer_id` sends its messages in the current frame, if it has dropped out.
+    pub(super) fn dropout_mode(&self, digitizer_id: DigitizerId) -> Option<DropoutMode> {
+        let position = self.frames_started.checked_sub(1)?;
+        self.dropouts.mode(digitizer_id, position)
+    }
+
+    /// Sets the protons per pulse of the current frame, evaluating `protons_per_pulse` at its frame number.
+    pub(super) fn set_protons_per_pulse(
+        &mut self,
+        protons_per_pulse: &NumExpression<i64>,
+    ) -> Result<(), SimulationEngineError> {
+        self.metadata.protons_per_pulse = self.evaluate("Protons per pulse", protons_per_pulse)?;
+        Ok(())
+    }
+
+    /// Sets the veto flags of the current frame, evaluating `veto_flags` at its frame number.
+    pub(super) fn set_veto_flags(
+        &mut self,
+        veto_flags: &NumExpression<i64>,
+    ) -> Result<(), SimulationEngineError> {
+        self.metadata.veto_flags = self.evaluate("Veto flags", veto_flags)?;
+        Ok(())
+    }
+
+    /// Evaluates `expression` at the current frame number,
+    /// returning an error rather than wrapping if the value is out of the range of `T`.
+    fn evaluate<T: TryFrom<i64>>(
+        &self,
+        field: &'static str,
+        expression: &NumExpression<i64>,
+    ) -> Result<T, SimulationEngineError> {
+        let frame_number = self.metadata.frame_number;
+        let value = expression.value(frame_number as usize)?;
+        T::try_from(value)
+            .map_err(|_| SimulationEngineError::MetadataOutOfRange(field, value, frame_number))
+    }
+
+    /// Returns the metadata of the current frame, with any metadata source row applied
+    /// over the values set by the schedule.
+    pub(super) fn frame_metadata(&self) -> FrameMetadata {
+        let mut metadata = self.metadata.clone();
+        if let Some(row) = &self.metadata_row {
+            row.apply(&mut metadata);
+        }
+        metadata
+    }
+}
+
 pub(crate) struct SimulationEngineDigitiser {
     pub(crate) id: DigitizerId,
     pub(crate) channel_indices: Vec<usize>,
+    /// The constant offset of the digitiser's clock in nanoseconds, sampled when the digitiser is generated.
+    pub(crate) timestamp_skew_ns: f64,
+    /// If present, the additional offset of each trace message's timestamp in nanoseconds is sampled from this.
+    pub(crate) timestamp_jitter_ns: Option<FloatRandomDistribution<f64>>,
 }
 
 impl SimulationEngineDigitiser {
@@ -59,15 +283,66 @@ impl SimulationEngineDigitiser {
         SimulationEngineDigitiser {
             id,
             channel_indices,
+            timestamp_skew_ns: 0.0,
+            timestamp_jitter_ns: None,
         }
     }
+
+    /// Sets the perturbation of the digitiser's timestamps, sampling its skew.
+    pub(crate) fn with_timing(mut self, timing: &DigitiserTiming) -> Result<Self, JsonValueError> {
+        if let Some(skew_ns) = &timing.skew_ns {
+            self.timestamp_skew_ns = skew_ns.sample(0)?;
+        }
+        self.timestamp_jitter_ns = timing.jitter_ns.clone();
+        Ok(self)
+    }
+
+    /// Returns `metadata` as reported by this digitiser, that is with its timestamp offset
+    /// by the digitiser's skew and a sample of its jitter, and all other fields unchanged.
+    pub(crate) fn clock_metadata(
+        &self,
+        metadata: &FrameMetadata,
+    ) -> Result<FrameMetadata, JsonValueError> {
+        let jitter_ns = self
+            .timestamp_jitter_ns
+            .as_ref()
+            .map(|jitter_ns| jitter_ns.sample(metadata.frame_number as usize))
+            .transpose()?
+            .unwrap_or_default();
+        let offset_ns = self.timestamp_skew_ns + jitter_ns;
+        let timestamp = metadata
+            .timestamp
+            .checked_add_signed(TimeDelta::nanoseconds(offset_ns.round() as i64))
+            .ok_or(JsonValueError::SampleOutOfRange(offset_ns))?;
+        Ok(FrameMetadata {
+            timestamp,
+            ..metadata.clone()
+        })
+    }
 }
 
 pub(crate) struct SimulationEngineExternals<'a> {
-    pub(crate) use_otel: bool,
-    pub(crate) producer: &'a FutureProducer,
-    pub(crate) kafka_producer_thread_set: &'a mut JoinSet<()>,
+    /// Every message generated is produced by this, to Kafka or in a dry run to files.
+    pub(crate) sink: &'a mut dyn MessageSink,
     pub(crate) topics: Topics<'a>,
+    /// If present, every generated event list is written here.
+    pub(crate) ground_truth: Option<GroundTruthWriter<BufWriter<File>>>,
+    /// The part of the schedule this process simulates.
+    pub(crate) shard: Shard,
+    /// The maximum number of channel traces generated at once, when building trace messages, see [build_trace_messages].
+    ///
+    /// [build_trace_messages]: crate::integrated::build_messages::build_trace_messages
+    pub(crate) max_materialised_channels: usize,
+    /// Injects faults into each trace message before it is produced.
+    pub(crate) fault_injector: FaultInjector<'a>,
+    /// Counts the samples of each channel whose values did not fit the Intensity type.
+    pub(crate) clipping: ClippingCounts,
+    /// Counts the messages which could not be sent because the producer's queue was full.
+    pub(crate) queue_full: QueueFullCounter,
+    /// Paces the frames of each frame loop, backing off whilst [Self::queue_full] is counting.
+    pub(crate) pacer: Pacer,
+    /// Consumes the topics on which [Action::WaitForMessage] actions wait.
+    pub(crate) message_waiter: MessageWaiter,
 }
 
 #[derive(Debug, Error)]
@@ -78,39 +353,123 @@ pub(crate) enum SimulationEngineError {
     Send(#[from] SendError),
     #[error("Aggregated Frame Event List Channel Index {0} out of Range: {1}")]
     AggregatedFrameEventListChannelIndexOutOfRange(usize, usize),
+    #[error("Digitiser Loop Index {0} out of Range: {1} digitisers are configured")]
+    DigitiserIndexOutOfRange(usize, usize),
     #[error("Json Numerical Error: {0}")]
     JsonNum(#[from] JsonValueError),
     #[error("checked_add_signed failed: {0}")]
     TimestampAdd(usize),
     #[error("checked_sub_signed failed: {0}")]
     TimestampSub(usize),
+    #[error("Metadata Source Error: {0}")]
+    MetadataSource(#[from] MetadataSourceError),
+    #[error("Digitiser Config Error: {0}")]
+    DigitiserConfig(#[from] DigitiserConfigError),
+    #[error("{0} of {1} at frame {2} is out of range")]
+    MetadataOutOfRange(&'static str, i64, FrameNumber),
+    #[error("{0} requires a run to have been started, or a run name to be given")]
+    NoCurrentRun(&'static str),
+    #[error("Wait For Message Error: {0}")]
+    WaitForMessage(#[from] WaitError),
+    #[error("Digitiser Dropout Error: {0}")]
+    Dropout(#[from] DropoutError),
 }
 
 pub(crate) struct SimulationEngine<'a> {
     externals: SimulationEngineExternals<'a>,
     state: SimulationEngineState,
-    trace_cache: VecDeque<Trace>,
+    trace_cache: VecDeque<Trace<'a>>,
     event_list_cache: VecDeque<EventList<'a>>,
     simulation: &'a Simulation,
     channels: Vec<Channel>,
+    /// The transformation applied to the traces of each channel in [Self::channels].
+    channel_transformations: Vec<Transformation<f64>>,
     digitiser_ids: Vec<SimulationEngineDigitiser>,
+    /// If present, the events of the trace messages sent in the current frame,
+    /// which are sent as an aggregated frame event list at the end of the frame.
+    frame_trace_events: Option<FrameTraceEvents>,
+    /// The trace messages selected by the digitisers of the current [FrameAction::DigitiserLoop],
+    /// which are built in parallel, see [send_pending_trace_messages].
+    pending_trace_messages: Vec<PendingTraceMessage<'a>>,
+    /// If present, the actions received on this are applied between the actions of the schedule, and at each frame boundary.
+    control: Option<&'a ControlSocket>,
 }
 
 impl<'a> SimulationEngine<'a> {
     pub(crate) fn new(
-        externals: SimulationEngineExternals<'a>,
+        mut externals: SimulationEngineExternals<'a>,
         simulation: &'a Simulation,
     ) -> Result<Self, SimulationEngineError> {
+        // Consume the waited on topics now, so no message sent in response to the schedule is missed.
+        externals
+            .message_waiter
+            .consume(waited_topics(&simulation.schedule))?;
+        let digitiser_ids = simulation.digitiser_config.generate_digitisers()?;
+        let shard = externals.shard;
+        info!(
+            "Shard {shard} owns digitisers {:?}",
+            digitiser_ids
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| shard.owns(*index))
+                .map(|(_, digitiser)| digitiser.id)
+                .collect::<Vec<_>>()
+        );
         Ok(Self {
             externals,
             simulation,
             state: Default::default(),
             trace_cache: Default::default(),
             event_list_cache: Default::default(),
-            digitiser_ids: simulation.digitiser_config.generate_digitisers()?,
+            digitiser_ids,
+            frame_trace_events: Default::default(),
+            pending_trace_messages: Default::default(),
+            control: None,
             channels: simulation.digitiser_config.generate_channels()?,
+            channel_transformations: simulation
+                .digitiser_config
+                .generate_channel_transformations()?,
         })
     }
+
+    /// Sets the state from which the schedule is run, in place of the default state.
+    pub(crate) fn with_state(mut self, state: SimulationEngineState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Applies the actions received on `control` whilst the schedule is run, see [run_schedule].
+    pub(crate) fn with_control(mut self, control: &'a ControlSocket) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// The state of the engine, which after the schedule is run can be carried over to the next simulation.
+    pub(crate) fn state(&self) -> &SimulationEngineState {
+        &self.state
+    }
+
+    /// The number of trace messages into which each fault has been injected.
+    pub(crate) fn injected_faults(&self) -> &FaultCounts {
+        self.externals.fault_injector.counts()
+    }
+
+    /// The number of samples of each channel whose values did not fit the Intensity type.
+    pub(crate) fn clipped_samples(&self) -> &ClippingCounts {
+        &self.externals.clipping
+    }
+
+    /// Ensures every message sent so far has been written by the sink.
+    pub(crate) fn flush_sink(&mut self) -> Result<(), SinkError> {
+        self.externals.sink.flush()
+    }
+
+    pub(crate) fn flush_ground_truth(&mut self) -> Result<(), GroundTruthError> {
+        if let Some(ground_truth) = self.externals.ground_truth.as_mut() {
+            ground_truth.flush()?;
+        }
+        Ok(())
+    }
 }
 
 #[instrument(skip_all, level = "debug", err(level = "error"))]
@@ -118,32 +477,98 @@ fn set_timestamp(
     engine: &mut SimulationEngine,
     timestamp: &Timestamp,
 ) -> Result<(), SimulationEngineError> {
-    match timestamp {
-        Timestamp::Now => engine.state.metadata.timestamp = Utc::now(),
-        Timestamp::To(ts) => engine.state.metadata.timestamp = *ts,
-        Timestamp::AdvanceByMs(ms) => {
-            engine.state.metadata.timestamp = engine
-                .state
-                .metadata
-                .timestamp
-                .checked_add_signed(TimeDelta::milliseconds(*ms as i64))
-                .ok_or(SimulationEngineError::TimestampAdd(*ms))?
-        }
-        Timestamp::RewindByMs(ms) => {
-            engine.state.metadata.timestamp = engine
-                .state
-                .metadata
-                .timestamp
-                .checked_sub_signed(TimeDelta::milliseconds(*ms as i64))
-                .ok_or(SimulationEngineError::TimestampSub(*ms))?
-        }
+    engine.state.set_timestamp(timestamp)
+}
+
+#[instrument(skip_all, level = "debug", err(level = "error"))]
+fn send_run_stop_unmatched(
+    engine: &mut SimulationEngine,
+    run_stop: &SendRunStopUnmatched,
+) -> Result<(), SimulationEngineError> {
+    let name = engine
+        .state
+        .run_name("send-run-stop-unmatched", run_stop.name.as_ref())?;
+    info!("Sending unmatched run stop for run {name}");
+    send_run_stop_command(
+        &mut engine.externals,
+        &name,
+        &engine.state.metadata.timestamp,
+    )?;
+    Ok(())
+}
+
+#[instrument(skip_all, level = "debug", err(level = "error"))]
+fn send_run_start_duplicate(
+    engine: &mut SimulationEngine,
+    run_start: &SendRunStartDuplicate,
+) -> Result<(), SimulationEngineError> {
+    let run = engine.state.duplicate_run(run_start.name.as_ref())?;
+    info!("Sending duplicate run start for run {}", run.name);
+    send_run_start_command(
+        &mut engine.externals,
+        &run,
+        &engine.state.metadata.timestamp,
+    )?;
+    Ok(())
+}
+
+#[instrument(skip_all, level = "debug", err(level = "error"))]
+fn send_run_abort(
+    engine: &mut SimulationEngine,
+    run_abort: &SendRunAbort,
+) -> Result<(), SimulationEngineError> {
+    let name = engine
+        .state
+        .run_name("send-run-abort", run_abort.name.as_ref())?;
+    info!("Sending run abort for run {name}");
+    send_run_abort_command(
+        &mut engine.externals,
+        &name,
+        &engine.state.metadata.timestamp,
+    )?;
+    Ok(())
+}
+
+/// Marks the point in the schedule by which the pipeline should have survived the messages sent,
+/// so that test harnesses can find it in the log.
+#[instrument(skip_all, level = "debug")]
+fn expect_no_crash(label: &str) {
+    info!("Expect no crash: {label}");
+}
+
+/// Drops out the digitisers of `dropout` for a span of the frames which follow,
+/// which are counted from the next frame started by any frame loop.
+#[instrument(skip_all, level = "debug", err(level = "error"))]
+fn digitiser_dropout(
+    engine: &mut SimulationEngine,
+    dropout: &DigitiserDropout,
+) -> Result<(), SimulationEngineError> {
+    let windows = dropout.windows(engine.state.frames_started);
+    engine.state.dropouts.schedule(&windows)?;
+    for window in windows {
+        info!(
+            "Digitiser {} will drop out ({:?}) for frames {}..{} of the simulation",
+            window.digitizer_id, window.mode, window.first, window.end
+        );
     }
     Ok(())
 }
 
+/// Waits for `ms` milliseconds, in addition to any pacing of the current frame loop.
 #[instrument(skip_all, level = "debug")]
-fn wait_ms(ms: usize) {
-    sleep(Duration::from_millis(ms as u64));
+fn wait_ms(pacer: &mut Pacer, ms: usize) {
+    pacer.wait(Duration::from_millis(ms as u64));
+}
+
+/// Pauses the schedule until a message matching `wait` arrives, see [MessageWaiter::wait].
+#[instrument(skip_all, level = "debug", fields(topic = %wait.topic), err(level = "error"))]
+fn wait_for_message(
+    engine: &mut SimulationEngine,
+    wait: &WaitForMessage,
+) -> Result<(), SimulationEngineError> {
+    let frame_number = engine.state.metadata.frame_number;
+    engine.externals.message_waiter.wait(wait, frame_number)?;
+    Ok(())
 }
 
 #[instrument(skip_all, level = "debug")]
@@ -155,14 +580,36 @@ fn ensure_delay_ms(ms: usize, delay_from: &mut DateTime<Utc>) {
     *delay_from = Utc::now();
 }
 
+#[instrument(skip_all, level = "debug", err(level = "error"))]
+fn generate_event_lists<'a>(
+    engine: &mut SimulationEngine<'a>,
+    event_list_index: usize,
+    repeat: usize,
+) -> Result<Vec<EventList<'a>>, SimulationError> {
+    let frame_number = engine.state.metadata.frame_number;
+    let first = engine.state.event_lists_in_frame;
+    let event_lists =
+        engine
+            .simulation
+            .generate_event_lists(event_list_index, frame_number, first, repeat)?;
+    engine.state.event_lists_in_frame += event_lists.len();
+
+    if let Some(ground_truth) = engine.externals.ground_truth.as_mut() {
+        for (position, event_list) in (first..).zip(&event_lists) {
+            ground_truth.write(frame_number, position, event_list)?;
+        }
+    }
+    Ok(event_lists)
+}
+
 #[instrument(skip_all, level = "debug", err(level = "error"))]
 fn generate_trace_push_to_cache(
     engine: &mut SimulationEngine,
     generate_trace: &GenerateTrace,
 ) -> Result<(), SimulationEngineError> {
-    let event_lists = engine.simulation.generate_event_lists(
+    let event_lists = generate_event_lists(
+        engine,
         generate_trace.event_list_index,
-        engine.state.metadata.frame_number,
         generate_trace.repeat,
     )?;
     let traces = engine
@@ -177,9 +624,9 @@ fn generate_event_lists_push_to_cache(
     engine: &mut SimulationEngine,
     generate_event: &GenerateEventList,
 ) -> Result<(), SimulationError> {
-    let event_lists = engine.simulation.generate_event_lists(
+    let event_lists = generate_event_lists(
+        engine,
         generate_event.event_list_index,
-        engine.state.metadata.frame_number,
         generate_event.repeat,
     )?;
     engine.event_list_cache.extend(event_lists);
@@ -191,9 +638,9 @@ fn generate_event_lists_and_traces_push_to_cache(
     engine: &mut SimulationEngine,
     generate_event: &GenerateEventList,
 ) -> Result<(), SimulationError> {
-    let event_lists = engine.simulation.generate_event_lists(
+    let event_lists = generate_event_lists(
+        engine,
         generate_event.event_list_index,
-        engine.state.metadata.frame_number,
         generate_event.repeat,
     )?;
     engine.event_list_cache.extend(event_lists.clone());
@@ -212,75 +659,143 @@ fn tracing_event(event: &TracingEvent) {
     }
 }
 
+/// Runs each action of the schedule in turn.
+///
+/// If the engine has a [ControlSocket], the actions received on it are applied before each action of the schedule,
+/// and at the start of each frame of a frame loop. Once the schedule is complete, they continue to be applied
+/// whilst the socket lingers, see [ControlSocket::with_linger].
 #[tracing::instrument(skip_all, level = "debug", fields(num_actions = engine.simulation.schedule.len()), err(level = "error"))]
 pub(crate) fn run_schedule(engine: &mut SimulationEngine) -> Result<(), SimulationEngineError> {
     for action in engine.simulation.schedule.iter() {
-        match action {
-            Action::WaitMs(ms) => wait_ms(*ms),
-            Action::EnsureDelayMs(ms) => ensure_delay_ms(*ms, &mut engine.state.delay_from),
-            Action::TracingEvent(event) => tracing_event(event),
-            Action::SendRunStart(run_start) => send_run_start_command(
+        apply_control_actions(engine);
+        run_action(engine, action)?;
+    }
+    apply_control_actions(engine);
+    if let Some(control) = engine.control {
+        control.linger(|action| run_action(engine, action));
+    }
+    Ok(())
+}
+
+/// Applies the actions received on the engine's [ControlSocket] so far, if it has one.
+/// An action which fails is reported to the client which sent it, rather than ending the simulation.
+fn apply_control_actions(engine: &mut SimulationEngine) {
+    if let Some(control) = engine.control {
+        control.apply_pending(|action| run_action(engine, action));
+    }
+}
+
+#[tracing::instrument(skip_all, level = "debug", err(level = "error"))]
+fn run_action(engine: &mut SimulationEngine, action: &Action) -> Result<(), SimulationEngineError> {
+    if action.is_shared() && !engine.externals.shard.is_primary() {
+        return Ok(());
+    }
+    match action {
+        Action::WaitMs(ms) => wait_ms(&mut engine.externals.pacer, *ms),
+        Action::EnsureDelayMs(ms) => ensure_delay_ms(*ms, &mut engine.state.delay_from),
+        Action::WaitForMessage(wait) => wait_for_message(engine, wait)?,
+        Action::TracingEvent(event) => tracing_event(event),
+        Action::SendRunStart(run_start) => {
+            let run = engine.state.start_run(run_start)?;
+            send_run_start_command(
                 &mut engine.externals,
-                run_start,
+                &run,
                 &engine.state.metadata.timestamp,
-            )?,
-            Action::SendRunStop(run_stop) => send_run_stop_command(
+            )?
+        }
+        Action::SendRunStop(run_stop) => send_run_stop_command(
+            &mut engine.externals,
+            &run_stop.name.value()?,
+            &engine.state.metadata.timestamp,
+        )?,
+        Action::SendRunStopUnmatched(run_stop) => send_run_stop_unmatched(engine, run_stop)?,
+        Action::SendRunStartDuplicate(run_start) => send_run_start_duplicate(engine, run_start)?,
+        Action::SendRunAbort(run_abort) => send_run_abort(engine, run_abort)?,
+        Action::ExpectNoCrash(label) => expect_no_crash(label),
+        Action::DigitiserDropout(dropout) => digitiser_dropout(engine, dropout)?,
+        Action::SendRunLogData(run_log_data) => send_run_log_command(
+            &mut engine.externals,
+            &engine.state.metadata.timestamp,
+            run_log_data,
+        )?,
+        Action::SendLogData(log_data) => send_log_data_command(
+            &mut engine.externals,
+            &engine.state.metadata.timestamp,
+            engine.state.metadata.frame_number as usize,
+            log_data,
+        )?,
+        Action::SendSampleEnvLog(sample_env_log) => {
+            send_se_log_command(
                 &mut engine.externals,
-                run_stop,
                 &engine.state.metadata.timestamp,
-            )?,
-            Action::SendRunLogData(run_log_data) => send_run_log_command(
+                sample_env_log,
+            )?;
+        }
+        Action::SendAlarm(alarm) => {
+            send_alarm_command(
                 &mut engine.externals,
                 &engine.state.metadata.timestamp,
-                run_log_data,
-            )?,
-            Action::SendSampleEnvLog(sample_env_log) => {
-                send_se_log_command(
-                    &mut engine.externals,
-                    &engine.state.metadata.timestamp,
-                    sample_env_log,
-                )?;
-            }
-            Action::SendAlarm(alarm) => {
-                send_alarm_command(
-                    &mut engine.externals,
-                    &engine.state.metadata.timestamp,
-                    alarm,
+                alarm,
+            )?;
+        }
+        Action::SetVetoFlags(vetoes) => {
+            engine.state.metadata.veto_flags = vetoes.value()?;
+        }
+        Action::SetPeriod(period) => {
+            engine.state.metadata.period_number = period.value()?;
+        }
+        Action::SetProtonsPerPulse(ppp) => {
+            engine.state.metadata.protons_per_pulse = ppp.value()?;
+        }
+        Action::SetRunning(running) => {
+            engine.state.metadata.running = *running;
+        }
+        Action::GenerateTrace(generate_trace) => {
+            generate_trace_push_to_cache(engine, generate_trace)?
+        }
+        Action::GenerateEventList(generate_event) => {
+            generate_event_lists_push_to_cache(engine, generate_event)?
+        }
+        Action::SetTimestamp(timestamp) => set_timestamp(engine, timestamp)?,
+        Action::FrameLoop(frame_loop) => {
+            // Values set within the frame loop's schedule do not outlast it.
+            let protons_per_pulse = engine.state.metadata.protons_per_pulse;
+            let veto_flags = engine.state.metadata.veto_flags;
+            engine
+                .externals
+                .pacer
+                .start_loop(frame_loop.target_frame_rate_hz);
+            for frame in frame_loop.start.value()?..=frame_loop.end.value()? {
+                apply_control_actions(engine);
+                engine.externals.pacer.start_frame();
+                engine.state.start_frame(
+                    frame as FrameNumber,
+                    engine.simulation.metadata_source.as_ref(),
                 )?;
-            }
-            Action::SetVetoFlags(vetoes) => {
-                engine.state.metadata.veto_flags = vetoes.value()?;
-            }
-            Action::SetPeriod(period) => {
-                engine.state.metadata.period_number = period.value()?;
-            }
-            Action::SetProtonsPerPulse(ppp) => {
-                engine.state.metadata.protons_per_pulse = ppp.value()?;
-            }
-            Action::SetRunning(running) => {
-                engine.state.metadata.running = *running;
-            }
-            Action::GenerateTrace(generate_trace) => {
-                generate_trace_push_to_cache(engine, generate_trace)?
-            }
-            Action::GenerateEventList(generate_event) => {
-                generate_event_lists_push_to_cache(engine, generate_event)?
-            }
-            Action::SetTimestamp(timestamp) => set_timestamp(engine, timestamp)?,
-            Action::FrameLoop(frame_loop) => {
-                for frame in frame_loop.start.value()?..=frame_loop.end.value()? {
-                    engine.state.metadata.frame_number = frame as FrameNumber;
-                    run_frame(engine, frame_loop.schedule.as_slice())?;
+                if frame_loop.also_emit_aggregated {
+                    engine.frame_trace_events = Some(Default::default());
                 }
-            }
-            Action::LogLoop(log_loop) => {
-                for index in log_loop.start.value()?..=log_loop.end.value()? {
-                    engine.state.metadata.frame_number = index as FrameNumber;
-                    run_logloop_schedule(engine, log_loop.schedule.as_slice())?;
+                run_frame(engine, frame_loop.schedule.as_slice())?;
+                if let Some(frame_trace_events) = engine.frame_trace_events.take() {
+                    send_frame_trace_events_message(
+                        &mut engine.externals,
+                        frame_trace_events,
+                        engine.state.metadata.frame_number,
+                        engine.digitiser_ids.len(),
+                    )?;
                 }
             }
-            Action::Comment(_) => (),
+            engine.state.metadata.protons_per_pulse = protons_per_pulse;
+            engine.state.metadata.veto_flags = veto_flags;
+        }
+        Action::LogLoop(log_loop) => {
+            for index in log_loop.start.value()?..=log_loop.end.value()? {
+                engine.state.metadata.frame_number = index as FrameNumber;
+                engine.state.event_lists_in_frame = 0;
+                run_logloop_schedule(engine, log_loop.schedule.as_slice())?;
+            }
         }
+        Action::Comment(_) => (),
     }
     Ok(())
 }
@@ -298,27 +813,51 @@ fn run_frame(
     frame_actions: &[FrameAction],
 ) -> Result<(), SimulationEngineError> {
     for action in frame_actions {
+        if action.is_shared() && !engine.externals.shard.is_primary() {
+            continue;
+        }
         match action {
-            FrameAction::WaitMs(ms) => wait_ms(*ms),
+            FrameAction::WaitMs(ms) => wait_ms(&mut engine.externals.pacer, *ms),
             FrameAction::EnsureDelayMs(ms) => ensure_delay_ms(*ms, &mut engine.state.delay_from),
+            FrameAction::WaitForMessage(wait) => wait_for_message(engine, wait)?,
             FrameAction::TracingEvent(event) => tracing_event(event),
             FrameAction::SendAggregatedFrameEventList(source) => {
+                let channels = match &source.channel_indices {
+                    Some(channel_indices) => channel_indices
+                        .range_inclusive()
+                        .map(|i| {
+                            engine.channels.get(i).copied().ok_or(
+                                SimulationEngineError::AggregatedFrameEventListChannelIndexOutOfRange(
+                                    i,
+                                    engine.channels.len(),
+                                ),
+                            )
+                        })
+                        .collect::<Result<Vec<_>, SimulationEngineError>>()?,
+                    None => engine.channels.clone(),
+                };
                 send_aggregated_frame_event_list_message(
                     &mut engine.externals,
                     &mut engine.event_list_cache,
-                    &engine.state.metadata,
-                    &source
-                        .channel_indices
-                        .range_inclusive()
-                        .map(|i| engine.channels
-                            .get(i)
-                            .copied()
-                            .ok_or(SimulationEngineError::AggregatedFrameEventListChannelIndexOutOfRange(i, engine.channels.len()))
-                        )
-                        .collect::<Result<Vec<_>,SimulationEngineError>>()?,
+                    &engine.state.frame_metadata(),
+                    &channels,
                     &source.source_options,
                 )?
             }
+            FrameAction::SendLogData(log_data) => send_log_data_command(
+                &mut engine.externals,
+                &engine.state.metadata.timestamp,
+                engine.state.metadata.frame_number as usize,
+                log_data,
+            )?,
+            FrameAction::SendRunStopUnmatched(run_stop) => {
+                send_run_stop_unmatched(engine, run_stop)?
+            }
+            FrameAction::SendRunStartDuplicate(run_start) => {
+                send_run_start_duplicate(engine, run_start)?
+            }
+            FrameAction::SendRunAbort(run_abort) => send_run_abort(engine, run_abort)?,
+            FrameAction::ExpectNoCrash(label) => expect_no_crash(label),
             FrameAction::GenerateTrace(generate_trace) => {
                 generate_trace_push_to_cache(engine, generate_trace)?
             }
@@ -326,11 +865,22 @@ fn run_frame(
                 generate_event_lists_push_to_cache(engine, generate_event)?
             }
             FrameAction::SetTimestamp(timestamp) => set_timestamp(engine, timestamp)?,
+            FrameAction::SetProtonsPerPulse(protons_per_pulse) => {
+                engine.state.set_protons_per_pulse(protons_per_pulse)?
+            }
+            FrameAction::SetVetoFlags(veto_flags) => engine.state.set_veto_flags(veto_flags)?,
             FrameAction::DigitiserLoop(digitiser_loop) => {
                 for digitiser in digitiser_loop.start.value()?..=digitiser_loop.end.value()? {
+                    if digitiser >= engine.digitiser_ids.len() {
+                        return Err(SimulationEngineError::DigitiserIndexOutOfRange(
+                            digitiser,
+                            engine.digitiser_ids.len(),
+                        ));
+                    }
                     engine.state.digitiser_index = digitiser;
                     run_digitiser(engine, &digitiser_loop.schedule)?;
                 }
+                send_pending_trace_messages(engine)?;
             }
             FrameAction::Comment(_) => (),
         }
@@ -350,9 +900,34 @@ pub(crate) fn run_digitiser(
     engine: &mut SimulationEngine,
     digitiser_actions: &[DigitiserAction],
 ) -> Result<(), SimulationEngineError> {
+    // A digitiser owned by another shard is still run, so that the traces and event lists generated for it,
+    // by this or the frame's schedule, are taken from the caches, but it neither sends messages nor waits.
+    let owned = engine.externals.shard.owns(engine.state.digitiser_index);
     for action in digitiser_actions {
+        // Trace messages are sent together at the end of the loop, unless an action which waits or sends is reached first,
+        // so that messages are sent in the order of the schedule.
+        if !matches!(
+            action,
+            DigitiserAction::SendDigitiserTrace(_)
+                | DigitiserAction::GenerateTrace(_)
+                | DigitiserAction::GenerateEventList(_)
+                | DigitiserAction::GenerateEventListAndTraces(_)
+                | DigitiserAction::Comment(_)
+        ) {
+            send_pending_trace_messages(engine)?;
+        }
+        if !owned
+            && matches!(
+                action,
+                DigitiserAction::WaitMs(_)
+                    | DigitiserAction::EnsureDelayMs(_)
+                    | DigitiserAction::TracingEvent(_)
+            )
+        {
+            continue;
+        }
         match action {
-            DigitiserAction::WaitMs(ms) => wait_ms(*ms),
+            DigitiserAction::WaitMs(ms) => wait_ms(&mut engine.externals.pacer, *ms),
             DigitiserAction::EnsureDelayMs(ms) => {
                 ensure_delay_ms(*ms, &mut engine.state.delay_from)
             }
@@ -367,19 +942,22 @@ pub(crate) fn run_digitiser(
                             engine.digitiser_ids.len(),
                         ),
                     )?;
-                send_digitiser_trace_message(
-                    &mut engine.externals,
+                let mode = send_mode(engine, owned, digitiser.id);
+                let pending = select_digitiser_trace_message(
                     engine.simulation.sample_rate.value()?,
                     &mut engine.trace_cache,
-                    &engine.state.metadata,
+                    &digitiser.clock_metadata(&engine.state.frame_metadata())?,
                     digitiser.id,
                     &digitiser
                         .channel_indices
                         .iter()
-                        .map(|idx| engine.channels[*idx])
+                        .map(|idx| (engine.channels[*idx], &engine.channel_transformations[*idx]))
                         .collect::<Vec<_>>(),
                     source.0,
+                    engine.frame_trace_events.as_mut(),
+                    mode,
                 )?;
+                engine.pending_trace_messages.extend(pending);
             }
             DigitiserAction::SendDigitiserEventList(source) => {
                 let digitiser = engine
@@ -391,10 +969,11 @@ pub(crate) fn run_digitiser(
                             engine.digitiser_ids.len(),
                         ),
                     )?;
+                let mode = send_mode(engine, owned, digitiser.id);
                 send_digitiser_event_list_message(
                     &mut engine.externals,
                     &mut engine.event_list_cache,
-                    &engine.state.metadata,
+                    &engine.state.frame_metadata(),
                     digitiser.id,
                     &digitiser
                         .channel_indices
@@ -402,6 +981,7 @@ pub(crate) fn run_digitiser(
                         .map(|idx| engine.channels[*idx])
                         .collect::<Vec<_>>(),
                     &source.0,
+                    mode,
                 )?;
             }
             DigitiserAction::GenerateTrace(generate_trace) => {
@@ -419,6 +999,32 @@ pub(crate) fn run_digitiser(
     Ok(())
 }
 
+/// Builds and sends the trace messages selected since this was last called.
+///
+/// As the trace messages of the digitisers of a [FrameAction::DigitiserLoop] are built together,
+/// they are built in parallel across digitisers, see [send_digitiser_trace_messages].
+fn send_pending_trace_messages(engine: &mut SimulationEngine) -> Result<(), SimulationEngineError> {
+    if !engine.pending_trace_messages.is_empty() {
+        let pending = mem::take(&mut engine.pending_trace_messages);
+        send_digitiser_trace_messages(&mut engine.externals, &pending)?;
+    }
+    Ok(())
+}
+
+/// Returns how the digitiser with `digitizer_id` sends its messages in the current frame, if not as usual,
+/// that is if it has dropped out, or is not `owned` by this shard, in which case they are not sent.
+fn send_mode(
+    engine: &SimulationEngine,
+    owned: bool,
+    digitizer_id: DigitizerId,
+) -> Option<DropoutMode> {
+    if owned {
+        engine.state.dropout_mode(digitizer_id)
+    } else {
+        Some(DropoutMode::Silent)
+    }
+}
+
 #[tracing::instrument(skip_all, level = "debug"
     fields(
         index = engine.state.metadata.frame_number,
@@ -431,13 +1037,22 @@ pub(crate) fn run_logloop_schedule(
     log_actions: &[LogAction],
 ) -> Result<(), SimulationEngineError> {
     for action in log_actions {
+        if action.is_shared() && !engine.externals.shard.is_primary() {
+            continue;
+        }
         match action {
-            LogAction::WaitMs(ms) => wait_ms(*ms),
+            LogAction::WaitMs(ms) => wait_ms(&mut engine.externals.pacer, *ms),
             LogAction::SendRunLogData(run_log_data) => send_run_log_command(
                 &mut engine.externals,
                 &engine.state.metadata.timestamp,
                 run_log_data,
             )?,
+            LogAction::SendLogData(log_data) => send_log_data_command(
+                &mut engine.externals,
+                &engine.state.metadata.timestamp,
+                engine.state.metadata.frame_number as usize,
+                log_data,
+            )?,
             LogAction::SendSampleEnvLog(sample_env_log) => send_se_log_command(
                 &mut engine.externals,
                 &engine.state.metadata.timestamp,
@@ -456,3 +1071,835 @@ pub(crate) fn run_logloop_schedule(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::{
+        build_messages::{build_trace_message, select_traces},
+        message_sink::{KafkaSink, SinkMessage},
+        simulation_engine::{
+            actions::{Loop, SelectionModeOptions},
+            pacing::PacingOptions,
+        },
+    };
+    use digital_muon_common::Intensity;
+    use digital_muon_streaming_types::{
+        aev2_frame_assembled_event_v2_generated::root_as_frame_assembled_event_list_message,
+        dat2_digitizer_analog_trace_v2_generated::root_as_digitizer_analog_trace_message,
+        flatbuffers::FlatBufferBuilder,
+    };
+    use isis_streaming_data_types::flatbuffers_generated::run_start_pl72::{
+        root_as_run_start, run_start_buffer_has_identifier,
+    };
+    use rdkafka::{ClientConfig, producer::FutureProducer};
+    use tokio::task::JoinSet;
+
+    const RAMP_SCHEDULE: &str = r#"
+    [
+        { "set-protons-per-pulse": { "const": 7 } },
+        { "frame-loop": {
+                "start": { "const": 0 },
+                "end": { "const": 9 },
+                "schedule": [
+                    { "set-protons-per-pulse": { "num-func": { "scale": 20, "translate": 50 } } },
+                    { "set-veto-flags": { "num-func": { "scale": 1, "translate": -4 } } }
+                ]
+            }
+        }
+    ]
+    "#;
+
+    const RAMP_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 1 },
+                "num-channels-per-digitiser": { "const" : 1 }
+            }
+        },
+        "pulses": [],
+        "event-lists": [],
+        "schedule": RAMP_SCHEDULE
+    }
+    "#;
+
+    fn ramp_simulation() -> Simulation {
+        serde_json::from_str(&RAMP_SIMULATION.replace("RAMP_SCHEDULE", RAMP_SCHEDULE)).unwrap()
+    }
+
+    fn frame_loop(schedule: &[Action]) -> &Loop<FrameAction> {
+        match schedule.get(1) {
+            Some(Action::FrameLoop(frame_loop)) => frame_loop,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Starts frame `frame_number` of `engine` and runs the frame loop's schedule with [run_frame],
+    /// returning the metadata of the frame.
+    fn run_frame_metadata(
+        engine: &mut SimulationEngine,
+        frame_loop: &Loop<FrameAction>,
+        frame_number: FrameNumber,
+    ) -> Result<FrameMetadata, SimulationEngineError> {
+        engine.state.start_frame(frame_number, None)?;
+        run_frame(engine, &frame_loop.schedule)?;
+        Ok(engine.state.frame_metadata())
+    }
+
+    #[test]
+    fn deserialize_frame_metadata_actions() {
+        let schedule: Vec<Action> = serde_json::from_str(RAMP_SCHEDULE).unwrap();
+        let frame_loop = frame_loop(&schedule);
+        assert!(matches!(
+            frame_loop.schedule.as_slice(),
+            [
+                FrameAction::SetProtonsPerPulse(NumExpression::NumFunc(_)),
+                FrameAction::SetVetoFlags(NumExpression::NumFunc(_))
+            ]
+        ));
+    }
+
+    #[test]
+    fn frame_metadata_follows_ramp() {
+        let simulation = ramp_simulation();
+        let frame_loop = frame_loop(&simulation.schedule);
+        let mut sink = FrameEventsRecorder::default();
+        let mut engine = offline_engine(
+            &simulation,
+            &mut sink,
+            QueueFullCounter::default(),
+            Shard::default(),
+        );
+
+        for frame_number in 4..=9 {
+            let metadata = run_frame_metadata(&mut engine, frame_loop, frame_number).unwrap();
+            assert_eq!(metadata.frame_number, frame_number);
+            assert_eq!(metadata.protons_per_pulse as u32, 20 * frame_number + 50);
+            assert_eq!(metadata.veto_flags as u32, frame_number - 4);
+        }
+    }
+
+    #[test]
+    fn out_of_range_metadata_is_an_error() {
+        let simulation = ramp_simulation();
+        let frame_loop = frame_loop(&simulation.schedule);
+        let mut sink = FrameEventsRecorder::default();
+        let mut engine = offline_engine(
+            &simulation,
+            &mut sink,
+            QueueFullCounter::default(),
+            Shard::default(),
+        );
+
+        // The protons per pulse of frame 10 would be 250, but of frame 11 would be 270.
+        assert!(run_frame_metadata(&mut engine, frame_loop, 10).is_ok());
+        assert!(matches!(
+            run_frame_metadata(&mut engine, frame_loop, 11),
+            Err(SimulationEngineError::MetadataOutOfRange(_, 270, 11))
+        ));
+
+        // The veto flags of frame 3 would be negative.
+        assert!(matches!(
+            run_frame_metadata(&mut engine, frame_loop, 3),
+            Err(SimulationEngineError::MetadataOutOfRange(_, -1, 3))
+        ));
+    }
+
+    const FAULT_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 2 },
+                "num-channels-per-digitiser": { "const" : 2 }
+            }
+        },
+        "pulses": [{
+                        "pulse-type": "flat",
+                        "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                        "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                        "height": { "random-type": "constant-float", "value": { "const": 50 } }
+                    }],
+        "event-lists": [
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 1 } }
+            }
+        ],
+        "fault-injection": FAULT_INJECTION,
+        "schedule": [
+            { "frame-loop": {
+                    "start": { "const": 0 },
+                    "end": { "const": 4 },
+                    "schedule": [
+                        { "digitiser-loop": {
+                                "start": { "const": 0 },
+                                "end": { "const": 1 },
+                                "schedule": [
+                                    { "generate-trace": { "event-list-index": 0, "repeat": 2 } },
+                                    { "send-digitiser-trace": "pop-front" }
+                                ]
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    }
+    "#;
+
+    fn fault_simulation(fault_injection: &str) -> Simulation {
+        serde_json::from_str(&FAULT_SIMULATION.replace("FAULT_INJECTION", fault_injection)).unwrap()
+    }
+
+    /// Runs the schedule of `simulation`, without a broker,
+    /// returning the final state, the faults injected and the number of messages produced.
+    fn run_offline(simulation: &Simulation) -> (SimulationEngineState, FaultCounts, usize) {
+        let producer: FutureProducer = ClientConfig::new().create().unwrap();
+        let mut kafka_producer_thread_set = JoinSet::new();
+        let queue_full = QueueFullCounter::default();
+        let mut sink = KafkaSink::new(
+            false,
+            &producer,
+            &mut kafka_producer_thread_set,
+            queue_full.clone(),
+        );
+        let (state, counts) = run_into_sink(simulation, &mut sink, queue_full);
+        drop(sink);
+        (state, counts, kafka_producer_thread_set.len())
+    }
+
+    /// Returns an engine which simulates the part of `simulation` given by `shard`, without a broker,
+    /// sending its messages to `sink`, whose full queue is counted by `queue_full`.
+    fn offline_engine<'a>(
+        simulation: &'a Simulation,
+        sink: &'a mut dyn MessageSink,
+        queue_full: QueueFullCounter,
+        shard: Shard,
+    ) -> SimulationEngine<'a> {
+        SimulationEngine::new(
+            SimulationEngineExternals {
+                sink,
+                topics: Topics {
+                    traces: "traces",
+                    events: "events",
+                    frame_events: "frame_events",
+                    run_controls: "run_controls",
+                    runlog: "runlog",
+                    selog: "selog",
+                    alarm: "alarm",
+                    ground_truth: None,
+                },
+                ground_truth: None,
+                shard,
+                max_materialised_channels: 8,
+                fault_injector: FaultInjector::new(simulation.fault_injection.as_ref()),
+                clipping: ClippingCounts::default(),
+                queue_full: queue_full.clone(),
+                pacer: Pacer::new(&PacingOptions::default(), queue_full),
+                message_waiter: MessageWaiter::new(None),
+            },
+            simulation,
+        )
+        .unwrap()
+    }
+
+    /// Runs the schedule of `simulation`, sending its messages to `sink`, whose full queue is counted by `queue_full`,
+    /// returning the final state and the faults injected.
+    fn run_into_sink(
+        simulation: &Simulation,
+        sink: &mut dyn MessageSink,
+        queue_full: QueueFullCounter,
+    ) -> (SimulationEngineState, FaultCounts) {
+        let mut engine = offline_engine(simulation, sink, queue_full, Shard::default());
+        run_schedule(&mut engine).unwrap();
+        (engine.state().clone(), engine.injected_faults().clone())
+    }
+
+    /// Runs the schedule of `simulation`, returning the faults injected and the number of messages produced.
+    fn run_with_faults(simulation: &Simulation) -> (FaultCounts, usize) {
+        let (_, counts, produced) = run_offline(simulation);
+        (counts, produced)
+    }
+
+    #[tokio::test]
+    async fn certainly_dropped_messages_are_not_produced() {
+        let simulation = fault_simulation(
+            r#"{ "drop-probability": { "random-type": "constant-float", "value": { "const": 1 } } }"#,
+        );
+        let (counts, produced) = run_with_faults(&simulation);
+        assert_eq!(counts.dropped, 10);
+        assert_eq!(produced, 0);
+    }
+
+    #[tokio::test]
+    async fn certainly_duplicated_messages_are_produced_twice() {
+        let simulation = fault_simulation(
+            r#"{ "duplicate-probability": { "random-type": "constant-float", "value": { "const": 1 } } }"#,
+        );
+        let (counts, produced) = run_with_faults(&simulation);
+        assert_eq!(counts.duplicated, 10);
+        assert_eq!(produced, 20);
+    }
+
+    /// Records the payloads of the trace messages and aggregated frame event lists sent, in the order sent.
+    #[derive(Default)]
+    struct FrameEventsRecorder {
+        traces: Vec<Vec<u8>>,
+        frame_events: Vec<Vec<u8>>,
+    }
+
+    impl MessageSink for FrameEventsRecorder {
+        fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError> {
+            match message.topic {
+                "traces" => self.traces.push(message.payload),
+                "frame_events" => self.frame_events.push(message.payload),
+                _ => (),
+            }
+            Ok(())
+        }
+    }
+
+    /// Decodes each aggregated frame event list recorded by `recorder`, asserting that it holds the events
+    /// of the trace messages of its frame, sorted by channel, and returning the number of events of each channel.
+    ///
+    /// The flat pulses of a trace coincide, so the number of them is its peak divided by their height, `pulse_height`.
+    fn decode_frame_events(
+        recorder: &FrameEventsRecorder,
+        pulse_height: Intensity,
+    ) -> Vec<Vec<(Channel, usize)>> {
+        let traces = recorder
+            .traces
+            .iter()
+            .map(|payload| root_as_digitizer_analog_trace_message(payload).unwrap())
+            .collect::<Vec<_>>();
+        recorder
+            .frame_events
+            .iter()
+            .map(|payload| {
+                let aggregated = root_as_frame_assembled_event_list_message(payload).unwrap();
+                let frame_number = aggregated.metadata().frame_number();
+                let traces = traces
+                    .iter()
+                    .filter(|trace| trace.metadata().frame_number() == frame_number)
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    aggregated.metadata().timestamp(),
+                    traces[0].metadata().timestamp()
+                );
+                let mut digitizers_present = traces
+                    .iter()
+                    .map(|trace| trace.digitizer_id())
+                    .collect::<Vec<_>>();
+                digitizers_present.sort();
+                assert_eq!(
+                    aggregated
+                        .digitizers_present()
+                        .unwrap()
+                        .iter()
+                        .collect::<Vec<_>>(),
+                    digitizers_present
+                );
+
+                let mut trace_counts = traces
+                    .iter()
+                    .flat_map(|trace| trace.channels().unwrap())
+                    .map(|trace| {
+                        let peak = trace.voltage().unwrap().iter().max().unwrap();
+                        (trace.channel(), usize::from(peak / pulse_height))
+                    })
+                    .collect::<Vec<_>>();
+                trace_counts.sort();
+                let channels = aggregated.channel().unwrap().iter().collect::<Vec<_>>();
+                assert!(channels.is_sorted());
+                let aggregated_counts = channels
+                    .chunk_by(|a, b| a == b)
+                    .map(|events| (events[0], events.len()))
+                    .collect::<Vec<_>>();
+                assert_eq!(aggregated_counts, trace_counts);
+                assert!(
+                    aggregated
+                        .voltage()
+                        .unwrap()
+                        .iter()
+                        .all(|voltage| voltage == pulse_height)
+                );
+                aggregated_counts
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aggregated_frame_event_lists_are_also_emitted_each_frame() {
+        let json = FAULT_SIMULATION.replace("FAULT_INJECTION", "{}").replacen(
+            r#""end": { "const": 4 },"#,
+            r#""end": { "const": 4 }, "also-emit-aggregated": true,"#,
+            1,
+        );
+        let simulation: Simulation = serde_json::from_str(&json).unwrap();
+        simulation.validate().unwrap();
+        let mut recorder = FrameEventsRecorder::default();
+        run_into_sink(&simulation, &mut recorder, QueueFullCounter::default());
+
+        // Each of the five frames has two trace messages and an aggregated frame event list.
+        assert_eq!(recorder.traces.len(), 10);
+        assert_eq!(
+            recorder
+                .frame_events
+                .iter()
+                .map(|payload| {
+                    let aggregated = root_as_frame_assembled_event_list_message(payload).unwrap();
+                    assert!(aggregated.complete());
+                    aggregated.metadata().frame_number()
+                })
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        for counts in decode_frame_events(&recorder, 50) {
+            assert_eq!(counts, vec![(0, 1), (1, 1), (2, 1), (3, 1)]);
+        }
+    }
+
+    /// Each frame sends the traces of digitiser 1 before those of digitiser 0,
+    /// the traces of channels 0 and 3 having three coincident pulses, and those of channels 1 and 2 one.
+    const FRAME_TRACE_EVENTS_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 2 },
+                "num-channels-per-digitiser": { "const" : 2 }
+            }
+        },
+        "pulses": [{
+                        "pulse-type": "flat",
+                        "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                        "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                        "height": { "random-type": "constant-float", "value": { "const": 50 } }
+                    }],
+        "event-lists": [
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 1 } }
+            },
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 3 } }
+            }
+        ],
+        "schedule": [
+            { "frame-loop": {
+                    "start": { "const": 7 },
+                    "end": { "const": 8 },
+                    "also-emit-aggregated": true,
+                    "schedule": [
+                        { "generate-trace": { "event-list-index": 0, "repeat": 1 } },
+                        { "generate-trace": { "event-list-index": 1, "repeat": 2 } },
+                        { "generate-trace": { "event-list-index": 0, "repeat": 1 } },
+                        { "digitiser-loop": {
+                                "start": { "const": 1 },
+                                "end": { "const": 1 },
+                                "schedule": [{ "send-digitiser-trace": "pop-front" }]
+                            }
+                        },
+                        { "digitiser-loop": {
+                                "start": { "const": 0 },
+                                "end": { "const": 0 },
+                                "schedule": [{ "send-digitiser-trace": "pop-front" }]
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn frame_trace_events_match_trace_messages() {
+        // Every distribution is constant, so the run is reproducible.
+        let simulation: Simulation = serde_json::from_str(FRAME_TRACE_EVENTS_SIMULATION).unwrap();
+        simulation.validate().unwrap();
+        let mut recorder = FrameEventsRecorder::default();
+        run_into_sink(&simulation, &mut recorder, QueueFullCounter::default());
+        assert_eq!(recorder.traces.len(), 4);
+
+        // The digitisers are sent in reverse, so the aggregated messages must be sorted into channel order.
+        let counts = decode_frame_events(&recorder, 50);
+        assert_eq!(counts, vec![vec![(0, 3), (1, 1), (2, 1), (3, 3)]; 2]);
+        for payload in &recorder.frame_events {
+            let aggregated = root_as_frame_assembled_event_list_message(payload).unwrap();
+            assert!(aggregated.complete());
+            assert_eq!(aggregated.metadata().period_number(), 0);
+        }
+    }
+
+    #[test]
+    fn certainly_corrupted_messages_fail_verification() {
+        // Every byte is corrupted, so the offset of the root table is never valid.
+        let simulation = fault_simulation(
+            r#"{
+                "corrupt-probability": { "random-type": "constant-float", "value": { "const": 1 } },
+                "corrupt-bytes": { "const": 1000000 }
+            }"#,
+        );
+        let mut injector = FaultInjector::new(simulation.fault_injection.as_ref());
+        let channels = simulation.digitiser_config.generate_channels().unwrap();
+        let transformations = simulation
+            .digitiser_config
+            .generate_channel_transformations()
+            .unwrap();
+        for frame_number in 0..10 {
+            let event_lists = simulation
+                .generate_event_lists(0, frame_number, 0, 2)
+                .unwrap();
+            let mut cache: VecDeque<_> = simulation
+                .generate_traces(&event_lists, frame_number)
+                .unwrap()
+                .into();
+            let (_, selected) = select_traces(
+                &mut cache,
+                &channels
+                    .iter()
+                    .copied()
+                    .zip(&transformations)
+                    .take(2)
+                    .collect::<Vec<_>>(),
+                SelectionModeOptions::PopFront,
+            )
+            .unwrap();
+            let mut metadata = SimulationEngineState::default().frame_metadata();
+            metadata.frame_number = frame_number;
+            let mut fbb = FlatBufferBuilder::new();
+            build_trace_message(&mut fbb, 1_000_000_000, &selected, &metadata, 0).unwrap();
+            assert!(root_as_digitizer_analog_trace_message(fbb.finished_data()).is_ok());
+
+            let payloads = injector
+                .inject(frame_number as usize, fbb.finished_data().to_vec())
+                .unwrap();
+            assert_eq!(payloads.len(), 1);
+            assert!(root_as_digitizer_analog_trace_message(&payloads[0]).is_err());
+        }
+        assert_eq!(injector.counts().corrupted, 10);
+    }
+
+    /// Records the frame number, digitiser id and payload of each trace message.
+    #[derive(Default)]
+    struct TraceRecorder(Vec<(FrameNumber, DigitizerId, Vec<u8>)>);
+
+    impl MessageSink for TraceRecorder {
+        fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError> {
+            if message.topic == "traces" {
+                self.0.push((
+                    message.frame_number.unwrap(),
+                    message.digitizer_id.unwrap(),
+                    message.payload,
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns [FAULT_SIMULATION] without faults, whose schedule sets a fixed timestamp, runs `dropouts`,
+    /// and then runs its frame loop twice, so that the frame at position `p` has frame number `p % 5`.
+    fn dropout_simulation(dropouts: &[&str]) -> Simulation {
+        let json = FAULT_SIMULATION.replace("FAULT_INJECTION", "{}");
+        let start = json.find(r#"{ "frame-loop""#).unwrap();
+        let end = json.rfind(']').unwrap();
+        let frame_loop = json[start..end].trim_end();
+        let actions = [r#"{ "set-timestamp": { "to": "2025-06-01T12:00:00Z" } }"#]
+            .into_iter()
+            .chain(dropouts.iter().copied())
+            .chain([frame_loop, frame_loop])
+            .collect::<Vec<_>>()
+            .join(", ");
+        let json = format!("{}{actions}{}", &json[..start], &json[end..]);
+        let simulation: Simulation = serde_json::from_str(&json).unwrap();
+        simulation.validate().unwrap();
+        simulation
+    }
+
+    /// Runs the schedule of `simulation`, returning the trace messages sent.
+    fn run_recording(simulation: &Simulation) -> Vec<(FrameNumber, DigitizerId, Vec<u8>)> {
+        let mut recorder = TraceRecorder::default();
+        run_into_sink(simulation, &mut recorder, QueueFullCounter::default());
+        recorder.0
+    }
+
+    #[test]
+    fn silent_dropout_withholds_exactly_its_frames() {
+        // Each frame has a trace message of digitiser 0, then of digitiser 1.
+        let baseline = run_recording(&dropout_simulation(&[]));
+        assert_eq!(baseline.len(), 20);
+
+        let messages = run_recording(&dropout_simulation(&[
+            r#"{ "digitiser-dropout": { "digitiser-id": 1, "start-offset-frames": 3, "duration-frames": 4 } }"#,
+        ]));
+        // Frames 3 and 4 of the first frame loop, and 0 and 1 of the second, are missing for digitiser 1,
+        // and every other message, including those of digitiser 0, is unchanged.
+        let expected = baseline
+            .iter()
+            .enumerate()
+            .filter(|(index, (_, digitizer_id, _))| {
+                !(*digitizer_id == 1 && (3..7).contains(&(index / 2)))
+            })
+            .map(|(_, message)| message.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 16);
+        assert_eq!(messages, expected);
+    }
+
+    #[test]
+    fn garbage_dropout_corrupts_exactly_its_frames() {
+        let baseline = run_recording(&dropout_simulation(&[]));
+        // The dropout of digitiser 1 outlasts the simulation.
+        let messages = run_recording(&dropout_simulation(&[
+            r#"{ "digitiser-dropout": { "digitiser-id": [0], "duration-frames": 2, "mode": "garbage" } }"#,
+            r#"{ "digitiser-dropout": { "digitiser-id": 1, "start-offset-frames": 8, "duration-frames": 5, "mode": "garbage" } }"#,
+        ]));
+        assert_eq!(messages.len(), baseline.len());
+        for (index, (message, expected)) in messages.iter().zip(&baseline).enumerate() {
+            let (frame_number, digitizer_id, payload) = message;
+            assert_eq!((frame_number, digitizer_id), (&expected.0, &expected.1));
+            let position = index / 2;
+            if (*digitizer_id == 0 && position < 2) || (*digitizer_id == 1 && position >= 8) {
+                assert!(
+                    root_as_digitizer_analog_trace_message(payload).is_err(),
+                    "{index}"
+                );
+            } else {
+                assert_eq!(payload, &expected.2, "{index}");
+            }
+        }
+    }
+
+    const RUN_CONTROL_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 1 },
+                "num-channels-per-digitiser": { "const" : 1 }
+            }
+        },
+        "pulses": [],
+        "event-lists": [],
+        "schedule": [
+            { "set-timestamp": { "to": "2025-06-01T12:00:00Z" } },
+            { "send-run-stop-unmatched": { "name": { "text": "Before" } } },
+            { "send-run-start": {
+                    "run-command": "SendRunStart",
+                    "name": { "text": "Run1" },
+                    "filename": { "text": "run1.nxs" },
+                    "instrument": { "text": "MuSR" }
+                }
+            },
+            { "set-timestamp": { "advance-by-ms": 10 } },
+            { "send-run-start-duplicate": {} },
+            { "frame-loop": {
+                    "start": { "const": 0 },
+                    "end": { "const": 1 },
+                    "schedule": [
+                        { "send-run-abort": {} },
+                        { "expect-no-crash": "Run aborted mid-loop" }
+                    ]
+                }
+            },
+            { "send-run-stop-unmatched": {} },
+            { "expect-no-crash": "Duplicate run start" }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn deserialize_run_control_actions() {
+        let simulation: Simulation = serde_json::from_str(RUN_CONTROL_SIMULATION).unwrap();
+        assert!(matches!(
+            simulation.schedule.as_slice(),
+            [
+                Action::SetTimestamp(_),
+                Action::SendRunStopUnmatched(SendRunStopUnmatched { name: Some(_) }),
+                Action::SendRunStart(_),
+                Action::SetTimestamp(_),
+                Action::SendRunStartDuplicate(SendRunStartDuplicate { name: None }),
+                Action::FrameLoop(_),
+                Action::SendRunStopUnmatched(SendRunStopUnmatched { name: None }),
+                Action::ExpectNoCrash(_),
+            ]
+        ));
+        let Action::FrameLoop(frame_loop) = &simulation.schedule[5] else {
+            unreachable!()
+        };
+        assert!(matches!(
+            frame_loop.schedule.as_slice(),
+            [
+                FrameAction::SendRunAbort(SendRunAbort { name: None }),
+                FrameAction::ExpectNoCrash(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn run_control_requires_a_run() {
+        let state = SimulationEngineState::default();
+        assert!(matches!(
+            state.run_name("send-run-abort", None),
+            Err(SimulationEngineError::NoCurrentRun("send-run-abort"))
+        ));
+        assert_eq!(
+            state
+                .run_name(
+                    "send-run-abort",
+                    Some(&TextConstant::Text("Run2".to_owned()))
+                )
+                .unwrap(),
+            "Run2"
+        );
+        assert!(matches!(
+            state.duplicate_run(Some(&TextConstant::Text("Run2".to_owned()))),
+            Err(SimulationEngineError::NoCurrentRun(_))
+        ));
+    }
+
+    /// Records the payload of each run control message.
+    #[derive(Default)]
+    struct RunControlRecorder(Vec<Vec<u8>>);
+
+    impl MessageSink for RunControlRecorder {
+        fn send(&mut self, message: SinkMessage<'_>) -> Result<(), SinkError> {
+            if message.topic == "run_controls" {
+                self.0.push(message.payload);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn duplicate_run_start_is_produced_with_a_new_timestamp() {
+        let simulation: Simulation = serde_json::from_str(RUN_CONTROL_SIMULATION).unwrap();
+        let mut recorder = RunControlRecorder::default();
+        let (state, _) = run_into_sink(&simulation, &mut recorder, QueueFullCounter::default());
+        // Two run stops, two run starts, and an abort in each of the two frames.
+        assert_eq!(recorder.0.len(), 6);
+        let run = state.current_run.unwrap();
+        assert_eq!(run.name, "Run1");
+
+        let [first, duplicate] = recorder
+            .0
+            .iter()
+            .filter(|payload| run_start_buffer_has_identifier(payload))
+            .map(|payload| root_as_run_start(payload).unwrap())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        assert_eq!(first.run_name(), Some("Run1"));
+        assert_eq!(duplicate.run_name(), first.run_name());
+        assert_eq!(duplicate.filename(), Some("run1.nxs"));
+        assert_eq!(duplicate.start_time(), first.start_time() + 10);
+    }
+
+    /// Two digitisers, whose traces are generated by the frame's schedule, rather than each digitiser's,
+    /// those of digitiser 1 having pulses twice the height of those of digitiser 0.
+    const SHARD_SIMULATION: &str = r#"
+    {
+        "voltage-transformation": {"scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "auto-digitisers": {
+                "num-digitisers": { "const" : 2 },
+                "num-channels-per-digitiser": { "const" : 2 }
+            }
+        },
+        "pulses": [
+            {
+                "pulse-type": "flat",
+                "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                "height": { "random-type": "constant-float", "value": { "const": 50 } }
+            },
+            {
+                "pulse-type": "flat",
+                "start":  { "random-type": "constant-float", "value": { "const": 10 } },
+                "width":  { "random-type": "constant-float", "value": { "const": 20 } },
+                "height": { "random-type": "constant-float", "value": { "const": 100 } }
+            }
+        ],
+        "event-lists": [
+            {
+                "pulses": [{"weight": 1, "pulse-index": 0}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 1 } }
+            },
+            {
+                "pulses": [{"weight": 1, "pulse-index": 1}],
+                "noises": [],
+                "num-pulses": { "random-type": "constant-int", "value": { "const": 1 } }
+            }
+        ],
+        "schedule": [
+            { "set-timestamp": { "to": "2025-06-01T12:00:00Z" } },
+            { "frame-loop": {
+                    "start": { "const": 0 },
+                    "end": { "const": 4 },
+                    "schedule": [
+                        { "generate-trace": { "event-list-index": 0, "repeat": 2 } },
+                        { "generate-trace": { "event-list-index": 1, "repeat": 2 } },
+                        { "digitiser-loop": {
+                                "start": { "const": 0 },
+                                "end": { "const": 1 },
+                                "schedule": [
+                                    { "send-digitiser-trace": "pop-front" }
+                                ]
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn shards_take_the_traces_of_digitisers_they_do_not_own_from_the_cache() {
+        let simulation: Simulation = serde_json::from_str(SHARD_SIMULATION).unwrap();
+        let whole = run_recording(&simulation);
+        assert_eq!(whole.len(), 10);
+
+        for index in 0..2 {
+            let mut recorder = TraceRecorder::default();
+            let mut engine = offline_engine(
+                &simulation,
+                &mut recorder,
+                QueueFullCounter::default(),
+                Shard::new(index, 2).unwrap(),
+            );
+            run_schedule(&mut engine).unwrap();
+            // The traces generated for the other shard's digitiser do not accumulate.
+            assert!(engine.trace_cache.is_empty());
+            drop(engine);
+
+            // Each shard sends the very messages of its digitiser which a single process would.
+            let expected = whole
+                .iter()
+                .filter(|(_, digitizer_id, _)| *digitizer_id as usize == index)
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(expected.len(), 5);
+            assert_eq!(recorder.0, expected);
+        }
+    }
+}
@@ -1,5 +1,10 @@
 pub(crate) mod actions;
 pub(crate) mod cache;
+pub(crate) mod control;
+pub(crate) mod dropout;
 pub(crate) mod engine;
+pub(crate) mod pacing;
+pub(crate) mod shard;
+pub(crate) mod wait;
 
 pub(crate) use engine::{SimulationEngine, SimulationEngineExternals, run_schedule};
@@ -0,0 +1,413 @@
+//! Paces the frames of frame loops to a target rate, and slows them further whilst the producer's queue is full.
+//!
+//! The pacing is by deadline: each frame is due one period after the previous frame was due,
+//! so the time spent generating and sending a frame is part of its period, rather than added to it.
+//! If a frame overruns its period, the next frame is started immediately, and is due one period later,
+//! so frames are not produced in a burst to catch up.
+use clap::Args;
+use digital_muon_common::metrics::names::ACHIEVED_FRAME_RATE_HZ;
+use metrics::gauge;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// How often the achieved frame rate is reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+const DEFAULT_BACKOFF_INITIAL_MS: u64 = 10;
+const DEFAULT_BACKOFF_MAX_MS: u64 = 1000;
+
+fn parse_frame_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if rate > 0.0 && rate.is_finite() {
+        Ok(rate)
+    } else {
+        Err(format!("Frame rate {rate} is not positive and finite"))
+    }
+}
+
+/// Options for pacing the frames of frame loops.
+#[derive(Clone, Debug, Args)]
+pub(crate) struct PacingOptions {
+    /// If set, the frames of each frame loop are produced at this many per second,
+    /// unless the frame loop's `target-frame-rate-hz` overrides it, otherwise they are produced as fast as possible.
+    #[clap(long, value_parser = parse_frame_rate)]
+    pub(crate) target_frame_rate_hz: Option<f64>,
+
+    /// The delay, in milliseconds, added before the next frame when the producer's queue is first found to be full.
+    /// The delay is doubled for each frame in which the queue is still full, and halved for each in which it is not.
+    #[clap(long, default_value_t = DEFAULT_BACKOFF_INITIAL_MS)]
+    pub(crate) backoff_initial_ms: u64,
+
+    /// The maximum delay, in milliseconds, added before each frame whilst the producer's queue is full.
+    #[clap(long, default_value_t = DEFAULT_BACKOFF_MAX_MS)]
+    pub(crate) backoff_max_ms: u64,
+}
+
+impl Default for PacingOptions {
+    /// Frames are produced as fast as possible, and backed off by the default delays.
+    fn default() -> Self {
+        Self {
+            target_frame_rate_hz: None,
+            backoff_initial_ms: DEFAULT_BACKOFF_INITIAL_MS,
+            backoff_max_ms: DEFAULT_BACKOFF_MAX_MS,
+        }
+    }
+}
+
+/// Counts the messages which could not be sent because the producer's queue was full.
+/// This is shared between the engine and the tasks which send its messages.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct QueueFullCounter(Arc<AtomicUsize>);
+
+impl QueueFullCounter {
+    pub(crate) fn record(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of messages recorded since this was last called.
+    fn take(&self) -> usize {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// The delay added before each frame whilst the producer's queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Backoff {
+    /// The queue has not been full, so no delay is added.
+    Steady,
+    /// The queue has recently been full, so this delay is added.
+    BackingOff(Duration),
+}
+
+impl Backoff {
+    /// Returns the backoff for the next frame, given whether the queue has been full since the last frame.
+    /// # Parameters
+    /// - initial: the delay when the queue is first found to be full, and the least delay before returning to [Self::Steady].
+    /// - max: the greatest delay.
+    fn next(self, queue_full: bool, initial: Duration, max: Duration) -> Self {
+        match (self, queue_full) {
+            (Self::Steady, false) => Self::Steady,
+            (Self::Steady, true) => Self::BackingOff(initial.min(max)),
+            (Self::BackingOff(delay), true) => Self::BackingOff((delay * 2).min(max)),
+            (Self::BackingOff(delay), false) => {
+                let delay = delay / 2;
+                if delay < initial || delay.is_zero() {
+                    Self::Steady
+                } else {
+                    Self::BackingOff(delay)
+                }
+            }
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        match self {
+            Self::Steady => Duration::ZERO,
+            Self::BackingOff(delay) => *delay,
+        }
+    }
+}
+
+/// The source of time for a [Pacer], which is replaced in tests.
+pub(crate) trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The system clock, which blocks the thread when sleeping, as does [WaitMs](crate::integrated::simulation_engine::actions::Action::WaitMs).
+#[derive(Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        sleep(duration);
+    }
+}
+
+/// Paces the frames of frame loops, see the [module](self) documentation.
+pub(crate) struct Pacer<C: Clock = SystemClock> {
+    clock: C,
+    /// The period of frames of loops which do not override it, if any.
+    default_period: Option<Duration>,
+    /// The period of frames of the current loop, if any.
+    period: Option<Duration>,
+    /// When the next frame of the current loop is due, if a frame has started.
+    next_frame: Option<Instant>,
+    backoff: Backoff,
+    backoff_initial: Duration,
+    backoff_max: Duration,
+    queue_full: QueueFullCounter,
+    /// The number of frames started since [Self::report_since].
+    frames_since_report: usize,
+    report_since: Option<Instant>,
+}
+
+impl Pacer {
+    pub(crate) fn new(options: &PacingOptions, queue_full: QueueFullCounter) -> Self {
+        Self::with_clock(options, queue_full, SystemClock)
+    }
+}
+
+impl<C: Clock> Pacer<C> {
+    pub(crate) fn with_clock(
+        options: &PacingOptions,
+        queue_full: QueueFullCounter,
+        clock: C,
+    ) -> Self {
+        Self {
+            clock,
+            default_period: options.target_frame_rate_hz.map(period_of),
+            period: None,
+            next_frame: None,
+            backoff: Backoff::Steady,
+            backoff_initial: Duration::from_millis(options.backoff_initial_ms),
+            backoff_max: Duration::from_millis(options.backoff_max_ms),
+            queue_full,
+            frames_since_report: 0,
+            report_since: None,
+        }
+    }
+
+    /// Begins a frame loop, whose frames are paced to `target_frame_rate_hz` if given, otherwise to the default rate.
+    pub(crate) fn start_loop(&mut self, target_frame_rate_hz: Option<f64>) {
+        self.period = target_frame_rate_hz.map(period_of).or(self.default_period);
+        self.next_frame = None;
+        self.frames_since_report = 0;
+        self.report_since = None;
+    }
+
+    /// Waits until the next frame of the current loop is due, and for any backoff, then begins the frame.
+    pub(crate) fn start_frame(&mut self) {
+        let backoff = self.backoff.next(
+            self.queue_full.take() > 0,
+            self.backoff_initial,
+            self.backoff_max,
+        );
+        match (self.backoff, backoff) {
+            (Backoff::Steady, Backoff::BackingOff(delay)) => {
+                warn!("Producer queue is full, delaying each frame by {delay:?}")
+            }
+            (Backoff::BackingOff(_), Backoff::Steady) => {
+                info!("Producer queue is no longer full, frames are no longer delayed")
+            }
+            _ => (),
+        }
+        self.backoff = backoff;
+
+        let now = self.clock.now();
+        let due = self.next_frame.map_or(now, |due| due.max(now)) + self.backoff.delay();
+        if due > now {
+            self.clock.sleep(due - now);
+        }
+        self.next_frame = self.period.map(|period| due + period);
+        self.report(due);
+    }
+
+    /// Sleeps for `duration`, which postpones the next frame by as long, so waits add to the pacing rather than being absorbed by it.
+    pub(crate) fn wait(&mut self, duration: Duration) {
+        self.clock.sleep(duration);
+        if let Some(next_frame) = self.next_frame.as_mut() {
+            *next_frame += duration;
+        }
+    }
+
+    /// Counts the frame begun at `now`, and reports the achieved frame rate if it has not been reported recently.
+    fn report(&mut self, now: Instant) {
+        let Some(since) = self.report_since else {
+            self.report_since = Some(now);
+            return;
+        };
+        self.frames_since_report += 1;
+        let elapsed = now - since;
+        if elapsed >= REPORT_INTERVAL {
+            let rate = self.frames_since_report as f64 / elapsed.as_secs_f64();
+            info!("Achieved frame rate: {rate:.2} Hz");
+            gauge!(ACHIEVED_FRAME_RATE_HZ).set(rate);
+            self.frames_since_report = 0;
+            self.report_since = Some(now);
+        }
+    }
+}
+
+fn period_of(frame_rate_hz: f64) -> Duration {
+    Duration::from_secs_f64(1.0 / frame_rate_hz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock which only advances when slept, or when advanced by the test.
+    struct MockClock {
+        now: Instant,
+        slept: Vec<Duration>,
+    }
+
+    impl MockClock {
+        fn new() -> Self {
+            Self {
+                now: Instant::now(),
+                slept: Vec::new(),
+            }
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.now
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration;
+            self.slept.push(duration);
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn new_pacer(target_frame_rate_hz: Option<f64>) -> (Pacer<MockClock>, QueueFullCounter) {
+        let queue_full = QueueFullCounter::default();
+        let options = PacingOptions {
+            target_frame_rate_hz,
+            backoff_initial_ms: 10,
+            backoff_max_ms: 80,
+        };
+        let pacer = Pacer::with_clock(&options, queue_full.clone(), MockClock::new());
+        (pacer, queue_full)
+    }
+
+    /// Simulates the time taken to generate and send a frame.
+    fn generate(pacer: &mut Pacer<MockClock>, duration: Duration) {
+        pacer.clock.now += duration;
+    }
+
+    #[test]
+    fn frames_are_paced_including_generation_time() {
+        let (mut pacer, _) = new_pacer(Some(10.0));
+        pacer.start_loop(None);
+
+        pacer.start_frame();
+        generate(&mut pacer, ms(30));
+        pacer.start_frame();
+        generate(&mut pacer, ms(100));
+        pacer.start_frame();
+        generate(&mut pacer, ms(99));
+        pacer.start_frame();
+        assert_eq!(pacer.clock.slept, [ms(70), ms(1)]);
+    }
+
+    #[test]
+    fn overrunning_frames_do_not_burst() {
+        let (mut pacer, _) = new_pacer(Some(10.0));
+        pacer.start_loop(None);
+
+        pacer.start_frame();
+        generate(&mut pacer, ms(250));
+        // The frame is late, so the next starts immediately, and is due a period after it starts.
+        pacer.start_frame();
+        generate(&mut pacer, ms(10));
+        pacer.start_frame();
+        assert_eq!(pacer.clock.slept, [ms(90)]);
+    }
+
+    #[test]
+    fn waits_add_to_pacing() {
+        let (mut pacer, _) = new_pacer(Some(10.0));
+        pacer.start_loop(None);
+
+        let start = pacer.clock.now;
+        pacer.start_frame();
+        generate(&mut pacer, ms(30));
+        pacer.wait(ms(20));
+        pacer.start_frame();
+        assert_eq!(pacer.clock.slept, [ms(20), ms(70)]);
+        // The frame period is the target period plus the wait.
+        assert_eq!(pacer.clock.now - start, ms(120));
+    }
+
+    #[test]
+    fn loops_override_default_rate() {
+        let (mut pacer, _) = new_pacer(Some(10.0));
+        pacer.start_loop(Some(50.0));
+        pacer.start_frame();
+        pacer.start_frame();
+        assert_eq!(pacer.clock.slept, [ms(20)]);
+
+        // Without a target rate, frames are not paced.
+        let (mut pacer, _) = new_pacer(None);
+        pacer.start_loop(None);
+        pacer.start_frame();
+        pacer.start_frame();
+        assert!(pacer.clock.slept.is_empty());
+    }
+
+    #[test]
+    fn backoff_transitions() {
+        let next = |backoff: Backoff, queue_full| backoff.next(queue_full, ms(10), ms(80));
+        assert_eq!(next(Backoff::Steady, false), Backoff::Steady);
+        assert_eq!(next(Backoff::Steady, true), Backoff::BackingOff(ms(10)));
+        assert_eq!(
+            next(Backoff::BackingOff(ms(10)), true),
+            Backoff::BackingOff(ms(20))
+        );
+        // The delay is capped.
+        assert_eq!(
+            next(Backoff::BackingOff(ms(60)), true),
+            Backoff::BackingOff(ms(80))
+        );
+        assert_eq!(
+            next(Backoff::BackingOff(ms(80)), true),
+            Backoff::BackingOff(ms(80))
+        );
+        // The delay decays once the queue is no longer full, until it is less than the initial delay.
+        assert_eq!(
+            next(Backoff::BackingOff(ms(80)), false),
+            Backoff::BackingOff(ms(40))
+        );
+        assert_eq!(
+            next(Backoff::BackingOff(ms(20)), false),
+            Backoff::BackingOff(ms(10))
+        );
+        assert_eq!(next(Backoff::BackingOff(ms(10)), false), Backoff::Steady);
+    }
+
+    #[test]
+    fn queue_full_delays_frames() {
+        let (mut pacer, queue_full) = new_pacer(None);
+        pacer.start_loop(None);
+
+        pacer.start_frame();
+        queue_full.record();
+        queue_full.record();
+        pacer.start_frame();
+        queue_full.record();
+        pacer.start_frame();
+        pacer.start_frame();
+        pacer.start_frame();
+        pacer.start_frame();
+        assert_eq!(pacer.backoff, Backoff::Steady);
+        assert_eq!(pacer.clock.slept, [ms(10), ms(20), ms(10)]);
+    }
+
+    #[test]
+    fn frame_rates_are_parsed() {
+        assert_eq!(parse_frame_rate("12.5"), Ok(12.5));
+        assert!(parse_frame_rate("0").is_err());
+        assert!(parse_frame_rate("-1").is_err());
+        assert!(parse_frame_rate("inf").is_err());
+        assert!(parse_frame_rate("fast").is_err());
+    }
+}
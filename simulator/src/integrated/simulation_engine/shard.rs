@@ -0,0 +1,139 @@
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum ShardError {
+    #[error("Shard should be of the form k/n, found {0}")]
+    Format(String),
+    #[error("Shard count must be nonzero")]
+    ZeroCount,
+    #[error("Shard index {0} must be less than the shard count {1}")]
+    IndexOutOfRange(usize, usize),
+}
+
+/// Identifies which part of a scenario this process simulates, when the scenario
+/// is split between several processes.
+///
+/// Digitisers are partitioned by their index modulo `count`, so the shards of a
+/// scenario own disjoint sets of digitisers which together cover every digitiser.
+/// Only the primary shard, with index zero, sends messages which are not specific to a digitiser,
+/// such as run controls, logs, alarms and aggregated frame event lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Shard {
+    index: usize,
+    count: usize,
+}
+
+impl Default for Shard {
+    /// A single process which simulates the whole scenario.
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+impl Shard {
+    pub(crate) fn new(index: usize, count: usize) -> Result<Self, ShardError> {
+        if count == 0 {
+            return Err(ShardError::ZeroCount);
+        }
+        if index >= count {
+            return Err(ShardError::IndexOutOfRange(index, count));
+        }
+        Ok(Self { index, count })
+    }
+
+    /// Returns true if the digitiser at `digitiser_index` is simulated by this shard.
+    pub(crate) fn owns(&self, digitiser_index: usize) -> bool {
+        digitiser_index % self.count == self.index
+    }
+
+    /// Returns true if this shard sends the messages shared by all shards.
+    pub(crate) fn is_primary(&self) -> bool {
+        self.index == 0
+    }
+}
+
+impl FromStr for Shard {
+    type Err = ShardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .and_then(|(index, count)| {
+                Some((index.trim().parse().ok()?, count.trim().parse().ok()?))
+            })
+            .ok_or_else(|| ShardError::Format(s.to_owned()))?;
+        Self::new(index, count)
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrated::simulation_engine::actions::Action;
+
+    #[test]
+    fn parse_shard() {
+        assert_eq!("2/4".parse::<Shard>().unwrap(), Shard::new(2, 4).unwrap());
+        assert_eq!("0/1".parse::<Shard>().unwrap(), Shard::default());
+        assert!(matches!(
+            "4/4".parse::<Shard>(),
+            Err(ShardError::IndexOutOfRange(4, 4))
+        ));
+        assert!(matches!("0/0".parse::<Shard>(), Err(ShardError::ZeroCount)));
+        assert!(matches!("1".parse::<Shard>(), Err(ShardError::Format(_))));
+        assert!(matches!(
+            "-1/2".parse::<Shard>(),
+            Err(ShardError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn partition_covers_each_digitiser_once() {
+        for count in 1..=5 {
+            let shards = (0..count)
+                .map(|index| Shard::new(index, count).unwrap())
+                .collect::<Vec<_>>();
+            for digitiser_index in 0..32 {
+                let owners = shards
+                    .iter()
+                    .filter(|shard| shard.owns(digitiser_index))
+                    .count();
+                assert_eq!(owners, 1, "{digitiser_index} in {count} shards");
+            }
+        }
+    }
+
+    #[test]
+    fn only_first_shard_is_primary() {
+        let primaries = (0..4)
+            .map(|index| Shard::new(index, 4).unwrap())
+            .filter(Shard::is_primary)
+            .collect::<Vec<_>>();
+        assert_eq!(primaries, vec![Shard::new(0, 4).unwrap()]);
+    }
+
+    #[test]
+    fn shared_actions_are_identified() {
+        let schedule: Vec<Action> = serde_json::from_str(
+            r#"[
+                { "send-run-start": { "name": { "text": "MyRun" }, "filename": { "text": "RunFile" }, "instrument": { "text": "MuSR" } } },
+                { "send-alarm": { "source-name": { "text": "Alarm" }, "severity": "major", "message": "Message" } },
+                { "set-timestamp": "now" },
+                { "frame-loop": { "start": { "const": 0 }, "end": { "const": 1 }, "schedule": [] } },
+                { "send-run-stop": { "name": { "text": "MyRun" } } }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule.iter().map(Action::is_shared).collect::<Vec<_>>(),
+            vec![true, true, false, false, true]
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! Pauses the schedule until a message arrives on a Kafka topic, so that the simulator does not outpace the
+//! components downstream of it, see [WaitForMessage].
+use crate::integrated::simulation_engine::actions::{
+    Action, FrameAction, OnTimeout, WaitForMessage,
+};
+use digital_muon_common::FrameNumber;
+use digital_muon_streaming_types::{
+    aev2_frame_assembled_event_v2_generated::{
+        frame_assembled_event_list_message_buffer_has_identifier,
+        root_as_frame_assembled_event_list_message,
+    },
+    dat2_digitizer_analog_trace_v2_generated::{
+        digitizer_analog_trace_message_buffer_has_identifier,
+        root_as_digitizer_analog_trace_message,
+    },
+    dev2_digitizer_event_v2_generated::{
+        digitizer_event_list_message_buffer_has_identifier, root_as_digitizer_event_list_message,
+    },
+};
+use rdkafka::{
+    ClientConfig, Message, Offset, TopicPartitionList,
+    consumer::{BaseConsumer, Consumer},
+    error::KafkaError,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+/// The group id of the consumers created by [MessageWaiter], which never commit their offsets.
+const GROUP_ID: &str = "simulator-wait-for-message";
+
+/// The longest the metadata and watermarks of a topic are awaited, when its consumer is created.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub(crate) enum WaitError {
+    #[error("Kafka Error: {0}")]
+    Kafka(#[from] KafkaError),
+    #[error("Topic {0} has no partitions")]
+    TopicNotFound(String),
+    #[error("No matching message arrived on topic {0} within {1} ms")]
+    TimedOut(String, u64),
+}
+
+/// Returns the frame number of `payload`, if it is a digitiser trace,
+/// digitiser event list or frame assembled event list message.
+fn message_frame_number(payload: &[u8]) -> Option<FrameNumber> {
+    if digitizer_event_list_message_buffer_has_identifier(payload) {
+        root_as_digitizer_event_list_message(payload)
+            .ok()
+            .map(|message| message.metadata().frame_number())
+    } else if frame_assembled_event_list_message_buffer_has_identifier(payload) {
+        root_as_frame_assembled_event_list_message(payload)
+            .ok()
+            .map(|message| message.metadata().frame_number())
+    } else if digitizer_analog_trace_message_buffer_has_identifier(payload) {
+        root_as_digitizer_analog_trace_message(payload)
+            .ok()
+            .map(|message| message.metadata().frame_number())
+    } else {
+        None
+    }
+}
+
+impl WaitForMessage {
+    /// Returns true if a message with `key` and `payload` ends the wait, when the current frame is `frame_number`.
+    pub(crate) fn matches(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+        frame_number: FrameNumber,
+    ) -> bool {
+        let key_matches = self.key_contains.as_ref().is_none_or(|substring| {
+            key.is_some_and(|key| String::from_utf8_lossy(key).contains(substring.as_str()))
+        });
+        let frame_matches = !self.match_frame_number
+            || payload.and_then(message_frame_number) == Some(frame_number);
+        key_matches && frame_matches
+    }
+}
+
+/// Returns the topics waited on by the [WaitForMessage] actions of `schedule`, including those of its frame loops.
+pub(crate) fn waited_topics(schedule: &[Action]) -> BTreeSet<&str> {
+    schedule
+        .iter()
+        .flat_map(|action| match action {
+            Action::WaitForMessage(wait) => vec![wait.topic.as_str()],
+            Action::FrameLoop(frame_loop) => frame_loop
+                .schedule
+                .iter()
+                .filter_map(|action| match action {
+                    FrameAction::WaitForMessage(wait) => Some(wait.topic.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Consumes the topics waited on by [WaitForMessage] actions.
+///
+/// The consumers of the topics waited on by the schedule are created before it is run, see [Self::consume],
+/// so a matching message is never missed, however soon it arrives after the messages which provoke it are sent.
+/// Each consumer is kept for later waits, so a message which arrives between two waits on the same topic may end the second.
+pub(crate) struct MessageWaiter {
+    /// The configuration of the consumers, or [None] in a dry run, in which no messages are awaited.
+    client_config: Option<ClientConfig>,
+    consumers: HashMap<String, BaseConsumer>,
+}
+
+impl MessageWaiter {
+    pub(crate) fn new(client_config: Option<ClientConfig>) -> Self {
+        Self {
+            client_config,
+            consumers: Default::default(),
+        }
+    }
+
+    /// Creates the consumer of each of `topics` which is not already consumed, so that messages which arrive
+    /// from now on can end later waits on them. In a dry run no consumers are created.
+    pub(crate) fn consume<'t>(
+        &mut self,
+        topics: impl IntoIterator<Item = &'t str>,
+    ) -> Result<(), WaitError> {
+        for topic in topics {
+            self.consumer(topic)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the consumer of `topic`, or [None] in a dry run.
+    /// The consumer is created if `topic` is not already consumed,
+    /// as is the case for a wait received on the control socket.
+    ///
+    /// A new consumer is assigned each partition of the topic from its high watermark,
+    /// rather than from any committed offset, so that messages produced before it was created are never matched.
+    fn consumer(&mut self, topic: &str) -> Result<Option<&BaseConsumer>, WaitError> {
+        let Some(client_config) = &self.client_config else {
+            return Ok(None);
+        };
+        if !self.consumers.contains_key(topic) {
+            let consumer: BaseConsumer = client_config
+                .clone()
+                .set("group.id", GROUP_ID)
+                .set("enable.auto.commit", "false")
+                .create()?;
+            let metadata = consumer.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+            let partitions = metadata
+                .topics()
+                .iter()
+                .find(|metadata| metadata.name() == topic)
+                .map(|metadata| metadata.partitions())
+                .filter(|partitions| !partitions.is_empty())
+                .ok_or_else(|| WaitError::TopicNotFound(topic.to_owned()))?;
+
+            let mut assignment = TopicPartitionList::new();
+            for partition in partitions {
+                let (_, high) =
+                    consumer.fetch_watermarks(topic, partition.id(), METADATA_TIMEOUT)?;
+                assignment.add_partition_offset(topic, partition.id(), Offset::Offset(high))?;
+            }
+            consumer.assign(&assignment)?;
+            info!("Consuming topic {topic}, to wait for messages");
+            self.consumers.insert(topic.to_owned(), consumer);
+        }
+        Ok(self.consumers.get(topic))
+    }
+
+    /// Polls the topic of `wait` until a message matching it arrives, when the current frame is `frame_number`.
+    /// If none arrives within its timeout, this returns an error if it is set to abort, and otherwise logs a warning.
+    /// In a dry run no message is awaited.
+    pub(crate) fn wait(
+        &mut self,
+        wait: &WaitForMessage,
+        frame_number: FrameNumber,
+    ) -> Result<(), WaitError> {
+        let Some(consumer) = self.consumer(&wait.topic)? else {
+            warn!(
+                "No messages are consumed in a dry run, so the wait for topic {} is skipped",
+                wait.topic
+            );
+            return Ok(());
+        };
+        let deadline = Instant::now() + Duration::from_millis(wait.timeout_ms);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            if let Some(message) = consumer.poll(remaining) {
+                let message = message?;
+                if wait.matches(message.key(), message.payload(), frame_number) {
+                    debug!(
+                        "Matching message arrived on topic {} at offset {}",
+                        wait.topic,
+                        message.offset()
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        match wait.on_timeout {
+            OnTimeout::Continue => {
+                warn!(
+                    "No matching message arrived on topic {} within {} ms, continuing",
+                    wait.topic, wait.timeout_ms
+                );
+                Ok(())
+            }
+            OnTimeout::Abort => Err(WaitError::TimedOut(wait.topic.clone(), wait.timeout_ms)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digital_muon_streaming_types::{
+        dev2_digitizer_event_v2_generated::{
+            DigitizerEventListMessage, DigitizerEventListMessageArgs,
+            finish_digitizer_event_list_message_buffer,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+    use rdkafka::{
+        mocking::MockCluster,
+        producer::{BaseProducer, BaseRecord, DefaultProducerContext, Producer},
+    };
+
+    const TOPIC: &str = "events";
+    const KEY: &str = "Digitiser Events List";
+
+    /// Returns an empty digitiser event list message of frame `frame_number`.
+    fn event_list_message(frame_number: FrameNumber) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let timestamp = GpsTime::new(24, 100, 12, 30, 15, 0, 0, 0);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: 0,
+            },
+        );
+        let message = DigitizerEventListMessage::create(
+            &mut fbb,
+            &DigitizerEventListMessageArgs {
+                digitizer_id: 0,
+                metadata: Some(metadata),
+                ..Default::default()
+            },
+        );
+        finish_digitizer_event_list_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+
+    fn wait_for_frame(timeout_ms: u64, on_timeout: OnTimeout) -> WaitForMessage {
+        WaitForMessage {
+            topic: TOPIC.to_owned(),
+            key_contains: Some("Events".to_owned()),
+            match_frame_number: true,
+            timeout_ms,
+            on_timeout,
+        }
+    }
+
+    /// An in-process Kafka cluster with the topic [TOPIC], and a producer connected to it.
+    struct Cluster {
+        producer: BaseProducer,
+        cluster: MockCluster<'static, DefaultProducerContext>,
+    }
+
+    impl Cluster {
+        fn new() -> Self {
+            let cluster = MockCluster::new(1).unwrap();
+            cluster.create_topic(TOPIC, 1, 1).unwrap();
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", cluster.bootstrap_servers())
+                .create()
+                .unwrap();
+            Self { producer, cluster }
+        }
+
+        fn waiter(&self) -> MessageWaiter {
+            MessageWaiter::new(Some(
+                ClientConfig::new()
+                    .set("bootstrap.servers", self.cluster.bootstrap_servers())
+                    .clone(),
+            ))
+        }
+
+        fn produce(&self, key: &str, frame_number: FrameNumber) {
+            let payload = event_list_message(frame_number);
+            self.producer
+                .send(BaseRecord::to(TOPIC).key(key).payload(&payload))
+                .unwrap();
+            self.producer.flush(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn message_matches_key_and_frame() {
+        let wait = wait_for_frame(0, OnTimeout::Continue);
+        let payload = event_list_message(3);
+        assert!(wait.matches(Some(KEY.as_bytes()), Some(&payload), 3));
+        assert!(!wait.matches(Some(KEY.as_bytes()), Some(&payload), 4));
+        assert!(!wait.matches(Some(b"Simulated Trace"), Some(&payload), 3));
+        assert!(!wait.matches(None, Some(&payload), 3));
+        assert!(!wait.matches(Some(KEY.as_bytes()), Some(b"not a flatbuffer"), 3));
+
+        // Without conditions, any message matches.
+        let wait = WaitForMessage {
+            key_contains: None,
+            match_frame_number: false,
+            ..wait
+        };
+        assert!(wait.matches(None, None, 3));
+    }
+
+    #[test]
+    fn deserialize_wait_for_message() {
+        let wait: WaitForMessage = serde_json::from_str(
+            r#"{ "topic": "events", "match-frame-number": true, "timeout-ms": 500, "on-timeout": "abort" }"#,
+        )
+        .unwrap();
+        assert_eq!(wait.topic, "events");
+        assert_eq!(wait.key_contains, None);
+        assert!(wait.match_frame_number);
+        assert_eq!(wait.timeout_ms, 500);
+        assert_eq!(wait.on_timeout, OnTimeout::Abort);
+
+        let wait: WaitForMessage =
+            serde_json::from_str(r#"{ "topic": "events", "timeout-ms": 500 }"#).unwrap();
+        assert_eq!(wait.on_timeout, OnTimeout::Continue);
+    }
+
+    #[test]
+    fn wait_ends_on_message_of_current_frame() {
+        let cluster = Cluster::new();
+        let mut waiter = waiter_consuming(&cluster);
+        cluster.produce(KEY, 2);
+        cluster.produce("Simulated Trace", 3);
+        cluster.produce(KEY, 3);
+
+        let start = Instant::now();
+        waiter
+            .wait(&wait_for_frame(10_000, OnTimeout::Abort), 3)
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn stale_message_is_not_matched() {
+        let cluster = Cluster::new();
+        cluster.produce(KEY, 3);
+        let mut waiter = waiter_consuming(&cluster);
+        cluster.produce(KEY, 2);
+
+        assert!(matches!(
+            waiter.wait(&wait_for_frame(500, OnTimeout::Abort), 3),
+            Err(WaitError::TimedOut(topic, 500)) if topic == TOPIC
+        ));
+    }
+
+    #[test]
+    fn timeout_continues() {
+        let cluster = Cluster::new();
+        let mut waiter = cluster.waiter();
+
+        let start = Instant::now();
+        waiter
+            .wait(&wait_for_frame(300, OnTimeout::Continue), 3)
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn timeout_aborts() {
+        let cluster = Cluster::new();
+        let mut waiter = cluster.waiter();
+        assert!(matches!(
+            waiter.wait(&wait_for_frame(300, OnTimeout::Abort), 3),
+            Err(WaitError::TimedOut(_, 300))
+        ));
+    }
+
+    #[test]
+    fn dry_run_does_not_wait() {
+        let start = Instant::now();
+        MessageWaiter::new(None)
+            .wait(&wait_for_frame(10_000, OnTimeout::Abort), 3)
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    /// Returns a waiter which is already consuming [TOPIC], so messages produced after this returns can be matched.
+    fn waiter_consuming(cluster: &Cluster) -> MessageWaiter {
+        let mut waiter = cluster.waiter();
+        waiter.consume([TOPIC]).unwrap();
+        assert!(waiter.consumers.contains_key(TOPIC));
+        waiter
+    }
+
+    #[test]
+    fn topics_waited_on_by_the_schedule() {
+        let schedule: Vec<Action> = serde_json::from_str(
+            r#"[
+                { "wait-for-message": { "topic": "events", "timeout-ms": 500 } },
+                { "frame-loop": {
+                    "start": { "const": 0 },
+                    "end": { "const": 1 },
+                    "schedule": [
+                        { "wait-for-message": { "topic": "frames", "timeout-ms": 500 } },
+                        { "wait-for-message": { "topic": "events", "timeout-ms": 500 } }
+                    ]
+                } },
+                { "wait-ms": 10 }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            waited_topics(&schedule),
+            BTreeSet::from(["events", "frames"])
+        );
+    }
+
+    #[test]
+    fn dry_run_consumes_nothing() {
+        let mut waiter = MessageWaiter::new(None);
+        waiter.consume([TOPIC]).unwrap();
+        assert!(waiter.consumers.is_empty());
+    }
+}
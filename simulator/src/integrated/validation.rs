@@ -0,0 +1,792 @@
+//! Checks a deserialized [Simulation] for problems which serde cannot detect,
+//! such as references to pulse templates which do not exist.
+//!
+//! Rather than stopping at the first problem, every problem is collected,
+//! each with a path in the style of a JSON pointer, such as `/event-lists/0/pulses/2/pulse-index`,
+//! locating the offending value in the configuration file.
+use crate::integrated::{
+    simulation::Simulation,
+    simulation_elements::{
+        DigitiserConfig, digitiser_config::DigitiserTiming, event_list::EventListSource,
+    },
+    simulation_engine::{
+        actions::{
+            Action, DigitiserAction, DigitiserDropout, FrameAction, GenerateEventList,
+            GenerateTrace, Loop,
+        },
+        dropout::{DropoutError, Dropouts},
+    },
+};
+use digital_muon_common::{Channel, DigitizerId};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum ValidationProblem {
+    #[error("pulse index {0} is out of range, as there are {1} pulse templates")]
+    PulseIndexOutOfRange(usize, usize),
+    #[error("event list index {0} is out of range, as there are {1} event list templates")]
+    EventListIndexOutOfRange(usize, usize),
+    #[error("weight {0} is not positive and finite")]
+    NonPositiveWeight(f64),
+    #[error("loop end {end} is less than its start {start}")]
+    LoopEndBeforeStart { start: usize, end: usize },
+    #[error("channel interval is empty, as min {0} is greater than max {1}")]
+    EmptyChannelInterval(Channel, Channel),
+    #[error(
+        "channels overlap those of digitiser {0}, set \"allow-overlapping-channels\" to permit this"
+    )]
+    OverlappingChannels(DigitizerId),
+    #[error("digitiser loops require digitisers, but aggregated frame digitiser configs have none")]
+    DigitiserLoopWithoutDigitisers,
+    #[error("target frame rate {0} is not positive and finite")]
+    NonPositiveFrameRate(f64),
+    #[error("target frame rate is only used by frame loops")]
+    FrameRateOutsideFrameLoop,
+    #[error("aggregated frame event lists are only also emitted by frame loops")]
+    AggregatedOutsideFrameLoop,
+    #[error("jitter of magnitude up to {0} ns could exceed half the frame period, {1} ns")]
+    JitterExceedsHalfFramePeriod(f64, f64),
+    #[error("dropout lasts no frames")]
+    EmptyDropout,
+    #[error("digitiser {0} is not configured")]
+    UnknownDropoutDigitiser(DigitizerId),
+    #[error("dropout overlaps an earlier dropout of digitiser {0}, for frames {1}..{2}")]
+    OverlappingDropout(DigitizerId, usize, usize),
+}
+
+/// The frame rate of frame loops which do not set a target frame rate, that of the ISIS accelerator.
+const NOMINAL_FRAME_RATE_HZ: f64 = 50.0;
+
+/// A problem, and the path of the value in the configuration file which causes it.
+#[derive(Debug)]
+pub(crate) struct ValidationError {
+    pub(crate) path: String,
+    pub(crate) problem: ValidationProblem,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// All problems found in a [Simulation], in the order they appear in the configuration file.
+#[derive(Debug, Error)]
+pub(crate) struct ValidationErrors(pub(crate) Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) found in simulation", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Accumulates the problems found in a [Simulation].
+struct Validator<'a> {
+    simulation: &'a Simulation,
+    errors: Vec<ValidationError>,
+}
+
+impl Validator<'_> {
+    fn report(&mut self, path: String, problem: ValidationProblem) {
+        self.errors.push(ValidationError { path, problem });
+    }
+
+    fn validate_event_lists(&mut self) {
+        let num_pulses = self.simulation.pulses.len();
+        for (i, template) in self.simulation.event_lists.iter().enumerate() {
+            let EventListSource::Random { pulses, .. } = &template.source else {
+                continue;
+            };
+            for (j, pulse) in pulses.iter().enumerate() {
+                let path = format!("/event-lists/{i}/pulses/{j}");
+                if pulse.pulse_index >= num_pulses {
+                    self.report(
+                        format!("{path}/pulse-index"),
+                        ValidationProblem::PulseIndexOutOfRange(pulse.pulse_index, num_pulses),
+                    );
+                }
+                if pulse.weight <= 0.0 || !pulse.weight.is_finite() {
+                    self.report(
+                        format!("{path}/weight"),
+                        ValidationProblem::NonPositiveWeight(pulse.weight),
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate_digitiser_config(&mut self) {
+        let digitisers = match &self.simulation.digitiser_config {
+            DigitiserConfig::AutoDigitisers { timing, .. } => {
+                self.validate_timing("/digitiser-config/auto-digitisers".to_owned(), timing);
+                return;
+            }
+            DigitiserConfig::ManualDigitisers(digitisers) => digitisers,
+            _ => return,
+        };
+        for (j, digitiser) in digitisers.iter().enumerate() {
+            self.validate_timing(
+                format!("/digitiser-config/manual-digitisers/{j}"),
+                &digitiser.timing,
+            );
+            let path = format!("/digitiser-config/manual-digitisers/{j}/channels");
+            let (min, max) = (digitiser.channels.min, digitiser.channels.max);
+            if min > max {
+                self.report(path, ValidationProblem::EmptyChannelInterval(min, max));
+                continue;
+            }
+            if digitiser.allow_overlapping_channels {
+                continue;
+            }
+            if let Some(earlier) = digitisers[..j].iter().find(|earlier| {
+                earlier.channels.min <= earlier.channels.max
+                    && earlier.channels.min <= max
+                    && min <= earlier.channels.max
+            }) {
+                self.report(path, ValidationProblem::OverlappingChannels(earlier.id));
+            }
+        }
+    }
+
+    /// Returns the shortest frame period in nanoseconds of the frame loops of the schedule,
+    /// taking that of a loop without a valid target frame rate to be [NOMINAL_FRAME_RATE_HZ].
+    fn shortest_frame_period_ns(&self) -> f64 {
+        let max_frame_rate_hz = self
+            .simulation
+            .schedule
+            .iter()
+            .filter_map(|action| match action {
+                Action::FrameLoop(frame_loop) => Some(
+                    frame_loop
+                        .target_frame_rate_hz
+                        .filter(|rate| *rate > 0.0 && rate.is_finite())
+                        .unwrap_or(NOMINAL_FRAME_RATE_HZ),
+                ),
+                _ => None,
+            })
+            .fold(NOMINAL_FRAME_RATE_HZ, f64::max);
+        1e9 / max_frame_rate_hz
+    }
+
+    /// Checks that the jitter of a digitiser's timing cannot exceed half the frame period,
+    /// in which case its messages could be taken for those of a neighbouring frame.
+    /// Jitter whose parameters are not constants is skipped.
+    fn validate_timing(&mut self, path: String, timing: &DigitiserTiming) {
+        let half_frame_period_ns = self.shortest_frame_period_ns() / 2.0;
+        if let Some(max_jitter_ns) = timing
+            .jitter_ns
+            .as_ref()
+            .and_then(|jitter_ns| jitter_ns.max_magnitude())
+            && max_jitter_ns > half_frame_period_ns
+        {
+            self.report(
+                format!("{path}/timing/jitter-ns"),
+                ValidationProblem::JitterExceedsHalfFramePeriod(
+                    max_jitter_ns,
+                    half_frame_period_ns,
+                ),
+            );
+        }
+    }
+
+    /// Checks the bounds of a loop, skipping any which are read from environment variables
+    /// that cannot yet be resolved, as these are reported when the loop is run.
+    fn validate_loop_bounds<A>(&mut self, path: &str, bounds: &Loop<A>) {
+        if let (Ok(start), Ok(end)) = (bounds.start.value(), bounds.end.value())
+            && end < start
+        {
+            self.report(
+                format!("{path}/end"),
+                ValidationProblem::LoopEndBeforeStart { start, end },
+            );
+        }
+    }
+
+    /// Checks the target frame rate of a frame loop, if present.
+    fn validate_frame_rate<A>(&mut self, path: &str, bounds: &Loop<A>) {
+        if let Some(rate) = bounds.target_frame_rate_hz
+            && (rate <= 0.0 || !rate.is_finite())
+        {
+            self.report(
+                format!("{path}/target-frame-rate-hz"),
+                ValidationProblem::NonPositiveFrameRate(rate),
+            );
+        }
+    }
+
+    /// Reports a target frame rate or aggregated frame event lists on a loop other than a frame loop, as they would be ignored.
+    fn validate_not_frame_loop<A>(&mut self, path: &str, bounds: &Loop<A>) {
+        if bounds.target_frame_rate_hz.is_some() {
+            self.report(
+                format!("{path}/target-frame-rate-hz"),
+                ValidationProblem::FrameRateOutsideFrameLoop,
+            );
+        }
+        if bounds.also_emit_aggregated {
+            self.report(
+                format!("{path}/also-emit-aggregated"),
+                ValidationProblem::AggregatedOutsideFrameLoop,
+            );
+        }
+    }
+
+    fn validate_event_list_index(&mut self, path: String, event_list_index: usize) {
+        let num_event_lists = self.simulation.event_lists.len();
+        if event_list_index >= num_event_lists {
+            self.report(
+                format!("{path}/event-list-index"),
+                ValidationProblem::EventListIndexOutOfRange(event_list_index, num_event_lists),
+            );
+        }
+    }
+
+    fn validate_generate_trace(&mut self, path: String, generate_trace: &GenerateTrace) {
+        self.validate_event_list_index(path, generate_trace.event_list_index);
+    }
+
+    fn validate_generate_event_list(&mut self, path: String, generate_event: &GenerateEventList) {
+        self.validate_event_list_index(path, generate_event.event_list_index);
+    }
+
+    /// Checks the duration and digitisers of a dropout and, if `next_frame` is known,
+    /// that it does not overlap any earlier dropout of the same digitiser in `dropouts`, to which it is added.
+    ///
+    /// # Parameters
+    /// - next_frame: the position of the frame from which the dropout's offset is counted, see [DigitiserDropout::windows].
+    fn validate_dropout(
+        &mut self,
+        path: String,
+        dropout: &DigitiserDropout,
+        next_frame: Option<usize>,
+        dropouts: &mut Dropouts,
+    ) {
+        if dropout.duration_frames == 0 {
+            self.report(
+                format!("{path}/duration-frames"),
+                ValidationProblem::EmptyDropout,
+            );
+        }
+        // Digitisers whose number is read from an unset environment variable are reported when the schedule is run.
+        if let Ok(digitisers) = self.simulation.digitiser_config.generate_digitisers() {
+            for &id in dropout.digitiser_id.ids() {
+                if !digitisers.iter().any(|digitiser| digitiser.id == id) {
+                    self.report(
+                        format!("{path}/digitiser-id"),
+                        ValidationProblem::UnknownDropoutDigitiser(id),
+                    );
+                }
+            }
+        }
+        if let Some(next_frame) = next_frame
+            && let Err(DropoutError::Overlapping(id, first, end)) =
+                dropouts.schedule(&dropout.windows(next_frame))
+        {
+            self.report(
+                format!("{path}/digitiser-id"),
+                ValidationProblem::OverlappingDropout(id, first, end),
+            );
+        }
+    }
+
+    fn validate_schedule(&mut self) {
+        // The position of the next frame to be started, whilst the bounds of every frame loop so far are known.
+        let mut next_frame = Some(0);
+        let mut dropouts = Dropouts::default();
+        for (i, action) in self.simulation.schedule.iter().enumerate() {
+            let path = format!("/schedule/{i}");
+            match action {
+                Action::FrameLoop(frame_loop) => {
+                    let path = format!("{path}/frame-loop");
+                    self.validate_loop_bounds(&path, frame_loop);
+                    self.validate_frame_rate(&path, frame_loop);
+                    for (j, action) in frame_loop.schedule.iter().enumerate() {
+                        self.validate_frame_action(format!("{path}/schedule/{j}"), action);
+                    }
+                    next_frame = match (frame_loop.start.value(), frame_loop.end.value()) {
+                        (Ok(start), Ok(end)) => next_frame.map(|next| next + (start..=end).count()),
+                        _ => None,
+                    };
+                }
+                Action::DigitiserDropout(dropout) => self.validate_dropout(
+                    format!("{path}/digitiser-dropout"),
+                    dropout,
+                    next_frame,
+                    &mut dropouts,
+                ),
+                Action::LogLoop(log_loop) => {
+                    let path = format!("{path}/log-loop");
+                    self.validate_loop_bounds(&path, log_loop);
+                    self.validate_not_frame_loop(&path, log_loop);
+                }
+                Action::GenerateTrace(generate_trace) => {
+                    self.validate_generate_trace(format!("{path}/generate-trace"), generate_trace)
+                }
+                Action::GenerateEventList(generate_event) => self.validate_generate_event_list(
+                    format!("{path}/generate-event-list"),
+                    generate_event,
+                ),
+                _ => (),
+            }
+        }
+    }
+
+    fn validate_frame_action(&mut self, path: String, action: &FrameAction) {
+        match action {
+            FrameAction::DigitiserLoop(digitiser_loop) => {
+                let path = format!("{path}/digitiser-loop");
+                if matches!(
+                    self.simulation.digitiser_config,
+                    DigitiserConfig::AutoAggregatedFrame { .. }
+                        | DigitiserConfig::ManualAggregatedFrame { .. }
+                ) {
+                    self.report(
+                        path.clone(),
+                        ValidationProblem::DigitiserLoopWithoutDigitisers,
+                    );
+                }
+                self.validate_loop_bounds(&path, digitiser_loop);
+                self.validate_not_frame_loop(&path, digitiser_loop);
+                for (k, action) in digitiser_loop.schedule.iter().enumerate() {
+                    self.validate_digitiser_action(format!("{path}/schedule/{k}"), action);
+                }
+            }
+            FrameAction::GenerateTrace(generate_trace) => {
+                self.validate_generate_trace(format!("{path}/generate-trace"), generate_trace)
+            }
+            FrameAction::GenerateEventList(generate_event) => self.validate_generate_event_list(
+                format!("{path}/generate-event-list"),
+                generate_event,
+            ),
+            _ => (),
+        }
+    }
+
+    fn validate_digitiser_action(&mut self, path: String, action: &DigitiserAction) {
+        match action {
+            DigitiserAction::GenerateTrace(generate_trace) => {
+                self.validate_generate_trace(format!("{path}/generate-trace"), generate_trace)
+            }
+            DigitiserAction::GenerateEventList(generate_event) => self
+                .validate_generate_event_list(
+                    format!("{path}/generate-event-list"),
+                    generate_event,
+                ),
+            DigitiserAction::GenerateEventListAndTraces(generate_event) => self
+                .validate_generate_event_list(
+                    format!("{path}/generate-event-list-and-traces"),
+                    generate_event,
+                ),
+            _ => (),
+        }
+    }
+}
+
+impl Simulation {
+    /// Checks the cross-references and values of the simulation which deserialization cannot,
+    /// reporting every problem found rather than only the first.
+    pub(crate) fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut validator = Validator {
+            simulation: self,
+            errors: Vec::new(),
+        };
+        validator.validate_digitiser_config();
+        validator.validate_event_lists();
+        validator.validate_schedule();
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(validator.errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+    {
+        "voltage-transformation": { "scale": 1, "translate": 0 },
+        "time-bins": { "const": 100 },
+        "sample-rate": { "const": 1000000000 },
+        "digitiser-config": {
+            "manual-digitisers": [
+                { "id": 4, "channels": { "min": 0, "max": 7 } },
+                { "id": 5, "channels": { "min": 8, "max": 15 } }
+            ]
+        },
+        "pulses": [{
+            "pulse-type": "gaussian",
+            "height":    { "random-type": "constant-float", "value": { "const": 100 } },
+            "peak-time": { "random-type": "constant-float", "value": { "const": 50 } },
+            "sd":        { "random-type": "constant-float", "value": { "const": 5 } }
+        }],
+        "event-lists": [{
+            "pulses": [{ "weight": 1, "pulse-index": 0 }],
+            "noises": [],
+            "num-pulses": { "random-type": "constant-int", "value": { "const": 2 } }
+        }],
+        "schedule": [
+            { "frame-loop": {
+                "start": { "const": 0 },
+                "end": { "const": 9 },
+                "schedule": [
+                    { "digitiser-loop": {
+                        "start": { "const": 0 },
+                        "end": { "const": 1 },
+                        "schedule": [
+                            { "generate-trace": { "event-list-index": 0, "repeat": 8 } }
+                        ]
+                    } }
+                ]
+            } }
+        ]
+    }
+    "#;
+
+    /// Deserializes [VALID], having replaced each `from` with `to`.
+    fn simulation_with(replacements: &[(&str, &str)]) -> Simulation {
+        let json = replacements
+            .iter()
+            .fold(VALID.to_owned(), |json, (from, to)| {
+                assert!(json.contains(from), "{from} not found");
+                json.replacen(from, to, 1)
+            });
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn error_paths(simulation: &Simulation) -> Vec<String> {
+        simulation
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|error| error.path)
+            .collect()
+    }
+
+    #[test]
+    fn valid_simulation() {
+        simulation_with(&[]).validate().unwrap();
+    }
+
+    #[test]
+    fn pulse_template_fields_accept_snake_and_kebab_case() {
+        simulation_with(&[("\"peak-time\"", "\"peak_time\"")])
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn all_problems_are_reported() {
+        let simulation = simulation_with(&[
+            (
+                r#"{ "weight": 1, "pulse-index": 0 }"#,
+                r#"{ "weight": 0, "pulse-index": 0 }, { "weight": 1, "pulse-index": 3 }"#,
+            ),
+            (r#""end": { "const": 9 }"#, r#""end": { "const": 2 }"#),
+            (r#""event-list-index": 0"#, r#""event-list-index": 1"#),
+        ]);
+        assert_eq!(
+            error_paths(&simulation),
+            [
+                "/event-lists/0/pulses/0/weight",
+                "/event-lists/0/pulses/1/pulse-index",
+                "/schedule/0/frame-loop/end",
+                "/schedule/0/frame-loop/schedule/0/digitiser-loop/schedule/0/generate-trace/event-list-index",
+            ]
+        );
+    }
+
+    #[test]
+    fn problems_are_displayed_with_their_paths() {
+        let simulation = simulation_with(&[(r#""weight": 1"#, r#""weight": -1.5"#)]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(
+            errors.to_string(),
+            "1 problem(s) found in simulation\n  /event-lists/0/pulses/0/weight: weight -1.5 is not positive and finite"
+        );
+    }
+
+    #[test]
+    fn overlapping_channels_are_reported_unless_allowed() {
+        let simulation =
+            simulation_with(&[(r#"{ "min": 8, "max": 15 }"#, r#"{ "min": 7, "max": 15 }"#)]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(
+            errors.0[0].path,
+            "/digitiser-config/manual-digitisers/1/channels"
+        );
+        assert!(matches!(
+            errors.0[0].problem,
+            ValidationProblem::OverlappingChannels(4)
+        ));
+
+        simulation_with(&[(
+            r#"{ "min": 8, "max": 15 }"#,
+            r#"{ "min": 7, "max": 15 }, "allow-overlapping-channels": true"#,
+        )])
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn empty_channel_interval_is_reported() {
+        let simulation =
+            simulation_with(&[(r#"{ "min": 0, "max": 7 }"#, r#"{ "min": 7, "max": 0 }"#)]);
+        assert_eq!(
+            error_paths(&simulation),
+            ["/digitiser-config/manual-digitisers/0/channels"]
+        );
+    }
+
+    #[test]
+    fn loop_bounds_from_unset_environment_variables_are_skipped() {
+        let simulation = simulation_with(&[(
+            r#""end": { "const": 9 }"#,
+            r#""end": { "from-env-var": "SIMULATOR_VALIDATION_TEST_UNSET" }"#,
+        )]);
+        simulation.validate().unwrap();
+    }
+
+    #[test]
+    fn digitiser_loop_under_aggregated_config_is_reported() {
+        let simulation = simulation_with(&[(
+            r#""manual-digitisers": [
+                { "id": 4, "channels": { "min": 0, "max": 7 } },
+                { "id": 5, "channels": { "min": 8, "max": 15 } }
+            ]"#,
+            r#""auto-aggregated-frame": { "num-channels": { "const": 16 } }"#,
+        )]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(
+            errors.0[0].path,
+            "/schedule/0/frame-loop/schedule/0/digitiser-loop"
+        );
+        assert!(matches!(
+            errors.0[0].problem,
+            ValidationProblem::DigitiserLoopWithoutDigitisers
+        ));
+    }
+
+    #[test]
+    fn target_frame_rates_are_validated() {
+        simulation_with(&[(
+            r#""end": { "const": 9 },"#,
+            r#""end": { "const": 9 }, "target-frame-rate-hz": 50,"#,
+        )])
+        .validate()
+        .unwrap();
+
+        let simulation = simulation_with(&[
+            (
+                r#""end": { "const": 9 },"#,
+                r#""end": { "const": 9 }, "target-frame-rate-hz": 0,"#,
+            ),
+            (
+                r#""end": { "const": 1 },"#,
+                r#""end": { "const": 1 }, "target-frame-rate-hz": 50,"#,
+            ),
+        ]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(
+            error_paths(&simulation),
+            [
+                "/schedule/0/frame-loop/target-frame-rate-hz",
+                "/schedule/0/frame-loop/schedule/0/digitiser-loop/target-frame-rate-hz",
+            ]
+        );
+        assert!(matches!(
+            errors.0[0].problem,
+            ValidationProblem::NonPositiveFrameRate(rate) if rate == 0.0
+        ));
+        assert!(matches!(
+            errors.0[1].problem,
+            ValidationProblem::FrameRateOutsideFrameLoop
+        ));
+    }
+
+    #[test]
+    fn aggregated_frame_event_lists_are_only_emitted_by_frame_loops() {
+        simulation_with(&[(
+            r#""end": { "const": 9 },"#,
+            r#""end": { "const": 9 }, "also-emit-aggregated": true,"#,
+        )])
+        .validate()
+        .unwrap();
+
+        let simulation = simulation_with(&[(
+            r#""end": { "const": 1 },"#,
+            r#""end": { "const": 1 }, "also-emit-aggregated": true,"#,
+        )]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(
+            error_paths(&simulation),
+            ["/schedule/0/frame-loop/schedule/0/digitiser-loop/also-emit-aggregated"]
+        );
+        assert!(matches!(
+            errors.0[0].problem,
+            ValidationProblem::AggregatedOutsideFrameLoop
+        ));
+    }
+
+    #[test]
+    fn jitter_exceeding_half_the_frame_period_is_reported() {
+        let jitter = |min: &str, max: &str| {
+            format!(
+                r#"{{ "min": 8, "max": 15 }}, "timing": {{ "jitter-ns": {{ "random-type": "uniform-float", "min": {{ "const": {min} }}, "max": {{ "const": {max} }} }} }}"#
+            )
+        };
+        // The nominal frame period is 20 ms.
+        simulation_with(&[(r#"{ "min": 8, "max": 15 }"#, &jitter("-1e7", "5e6"))])
+            .validate()
+            .unwrap();
+
+        let simulation =
+            simulation_with(&[(r#"{ "min": 8, "max": 15 }"#, &jitter("-5e6", "1.5e7"))]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(
+            error_paths(&simulation),
+            ["/digitiser-config/manual-digitisers/1/timing/jitter-ns"]
+        );
+        assert!(matches!(
+            errors.0[0].problem,
+            ValidationProblem::JitterExceedsHalfFramePeriod(max, half) if max == 1.5e7 && half == 1e7
+        ));
+
+        // A higher target frame rate shortens the frame period.
+        let simulation = simulation_with(&[
+            (r#"{ "min": 8, "max": 15 }"#, &jitter("-1e6", "5e6")),
+            (
+                r#""end": { "const": 9 },"#,
+                r#""end": { "const": 9 }, "target-frame-rate-hz": 200,"#,
+            ),
+        ]);
+        assert_eq!(
+            error_paths(&simulation),
+            ["/digitiser-config/manual-digitisers/1/timing/jitter-ns"]
+        );
+
+        // Unbounded distributions could exceed any frame period.
+        let simulation = simulation_with(&[(
+            r#"{ "min": 8, "max": 15 }"#,
+            r#"{ "min": 8, "max": 15 }, "timing": { "jitter-ns": { "random-type": "normal", "mean": { "const": 0 }, "sd": { "const": 100 } } }"#,
+        )]);
+        assert_eq!(
+            error_paths(&simulation),
+            ["/digitiser-config/manual-digitisers/1/timing/jitter-ns"]
+        );
+    }
+
+    /// Returns a replacement of the top-level schedule's opening, which runs `actions` before its frame loop.
+    fn before_frame_loop(actions: &str) -> (&'static str, String) {
+        (r#""schedule": ["#, format!(r#""schedule": [ {actions},"#))
+    }
+
+    /// Returns a replacement of the top-level schedule's closing, which runs `action` after its frame loop.
+    fn after_frame_loop(action: &str) -> (&'static str, String) {
+        (
+            "            } }\n        ]",
+            format!("            }} }},\n            {action}\n        ]"),
+        )
+    }
+
+    #[test]
+    fn overlapping_dropouts_are_reported() {
+        let first = r#"{ "digitiser-dropout": { "digitiser-id": 4, "duration-frames": 3 } }"#;
+        let (from, to) = before_frame_loop(&format!(
+            r#"{first}, {{ "digitiser-dropout": {{ "digitiser-id": [5, 4], "start-offset-frames": 3, "duration-frames": 2 }} }}"#
+        ));
+        simulation_with(&[(from, &to)]).validate().unwrap();
+
+        let (from, to) = before_frame_loop(&format!(
+            r#"{first}, {{ "digitiser-dropout": {{ "digitiser-id": [5, 4], "start-offset-frames": 2, "duration-frames": 2 }} }}"#
+        ));
+        let simulation = simulation_with(&[(from, &to)]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(
+            error_paths(&simulation),
+            ["/schedule/1/digitiser-dropout/digitiser-id"]
+        );
+        assert!(matches!(
+            errors.0[0].problem,
+            ValidationProblem::OverlappingDropout(4, 0, 3)
+        ));
+    }
+
+    #[test]
+    fn dropout_offsets_count_the_frames_of_earlier_frame_loops() {
+        // The frame loop has ten frames, so the second dropout begins at the same frame as the first.
+        let before = before_frame_loop(
+            r#"{ "digitiser-dropout": { "digitiser-id": 4, "start-offset-frames": 10, "duration-frames": 2 } }"#,
+        );
+        let after = after_frame_loop(
+            r#"{ "digitiser-dropout": { "digitiser-id": 4, "duration-frames": 1 } }"#,
+        );
+        let simulation = simulation_with(&[(before.0, &before.1), (after.0, &after.1)]);
+        assert_eq!(
+            error_paths(&simulation),
+            ["/schedule/2/digitiser-dropout/digitiser-id"]
+        );
+
+        let after = after_frame_loop(
+            r#"{ "digitiser-dropout": { "digitiser-id": 4, "start-offset-frames": 2, "duration-frames": 1 } }"#,
+        );
+        simulation_with(&[(before.0, &before.1), (after.0, &after.1)])
+            .validate()
+            .unwrap();
+
+        // Once the length of a frame loop is unknown, so are the frames of later dropouts.
+        let after = after_frame_loop(
+            r#"{ "digitiser-dropout": { "digitiser-id": 4, "duration-frames": 1 } }"#,
+        );
+        simulation_with(&[
+            (before.0, &before.1),
+            (after.0, &after.1),
+            (
+                r#""end": { "const": 9 }"#,
+                r#""end": { "from-env-var": "SIMULATOR_VALIDATION_TEST_UNSET" }"#,
+            ),
+        ])
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn empty_dropouts_and_unknown_digitisers_are_reported() {
+        let (from, to) = before_frame_loop(
+            r#"{ "digitiser-dropout": { "digitiser-id": [5, 6], "duration-frames": 0 } }"#,
+        );
+        let simulation = simulation_with(&[(from, &to)]);
+        let errors = simulation.validate().unwrap_err();
+        assert_eq!(
+            error_paths(&simulation),
+            [
+                "/schedule/0/digitiser-dropout/duration-frames",
+                "/schedule/0/digitiser-dropout/digitiser-id",
+            ]
+        );
+        assert!(matches!(
+            errors.0[1].problem,
+            ValidationProblem::UnknownDropoutDigitiser(6)
+        ));
+    }
+
+    #[test]
+    fn nested_frame_loops_are_rejected() {
+        // Frame actions do not include frame loops, so a nested frame loop is rejected when the simulation is deserialized.
+        let json = VALID.replacen(r#"{ "digitiser-loop": {"#, r#"{ "frame-loop": {"#, 1);
+        let error = serde_json::from_str::<Simulation>(&json).unwrap_err();
+        assert!(
+            error.to_string().contains("unknown variant `frame-loop`"),
+            "{error}"
+        );
+    }
+}
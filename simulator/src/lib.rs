@@ -0,0 +1,5 @@
+//! The parts of the simulator which are used by other crates.
+//!
+//! With the `archive-reader` feature, the trace archives written by `--trace-archive` can be read,
+//! so that the traces generated by the simulator can be replayed by the tests of other components.
+pub mod trace_archive;
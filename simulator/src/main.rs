@@ -1,10 +1,14 @@
 mod integrated;
+mod message_size;
+#[cfg(test)]
+mod peak_memory;
 pub(crate) mod runs;
 
 use chrono::Utc;
 use clap::{Parser, Subcommand};
 use digital_muon_common::{
-    Channel, CommonKafkaOpts, Intensity, Time, init_tracer,
+    CHANNELS_PER_DIGITIZER, Channel, CommonKafkaOpts, Intensity, ProducerTuning, Time, init_tracer,
+    metrics::names::ACHIEVED_FRAME_RATE_HZ,
     tracer::{FutureRecordTracerExt, TracerEngine, TracerOptions},
 };
 use digital_muon_streaming_types::{
@@ -19,7 +23,13 @@ use digital_muon_streaming_types::{
     flatbuffers::FlatBufferBuilder,
     frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
 };
-use integrated::run_configured_simulation;
+use integrated::{
+    run_configured_simulation,
+    simulation_engine::{pacing::PacingOptions, shard::Shard},
+};
+use message_size::check_trace_message_size;
+use metrics::describe_gauge;
+use metrics_exporter_prometheus::PrometheusBuilder;
 use miette::IntoDiagnostic;
 use rdkafka::{
     producer::{FutureProducer, FutureRecord},
@@ -33,6 +43,7 @@ use runs::{
     },
 };
 use std::{
+    net::SocketAddr,
     path::PathBuf,
     time::{Duration, SystemTime},
 };
@@ -46,6 +57,10 @@ struct Cli {
     #[clap(flatten)]
     common_kafka_options: CommonKafkaOpts,
 
+    /// Options for tuning the Kafka producer.
+    #[clap(flatten)]
+    producer_tuning: ProducerTuning,
+
     /// If set, then OpenTelemetry data is sent to the URL specified, otherwise the standard tracing subscriber is used
     #[clap(long)]
     otel_endpoint: Option<String>,
@@ -137,7 +152,18 @@ struct Continuous {
 #[derive(Clone, Parser)]
 struct Defined {
     /// Path to the json settings file
-    file: PathBuf,
+    #[clap(required_unless_present = "playlist")]
+    file: Option<PathBuf>,
+
+    /// Path to a json playlist file, listing json settings files which are run one after the other.
+    /// This is used in place of a single settings file.
+    #[clap(long, conflicts_with = "file")]
+    playlist: Option<PathBuf>,
+
+    /// If set, a settings file of the playlist which fails is reported and the next is run,
+    /// otherwise the playlist is abandoned.
+    #[clap(long, requires = "playlist")]
+    continue_on_error: bool,
 
     /// Topic to publish analog trace packets to
     #[clap(long)]
@@ -166,6 +192,59 @@ struct Defined {
     /// Topic to publish alarm messages to
     #[clap(long)]
     alarm_topic: String,
+
+    /// If set, the pulses of every generated event list are written to this file, as json lines,
+    /// which can be replayed by an event list with the `from-ground-truth` field.
+    #[clap(long)]
+    ground_truth_file: Option<PathBuf>,
+
+    /// If set, every digitiser trace message generated is also appended to this archive file, whose contents are replaced
+    /// when the simulator starts. Each simulation's records are preceded by a header with the simulator version and the hash of its settings.
+    #[clap(long)]
+    trace_archive: Option<PathBuf>,
+
+    /// If set, for every trace message a digitiser event list message of the pulses injected into
+    /// its traces is published to this topic, with the same key, metadata and channel order.
+    #[clap(long)]
+    ground_truth_topic: Option<String>,
+
+    /// If set to `k/n`, this process simulates only the digitisers whose index is `k` modulo `n`,
+    /// so a scenario can be split between `n` processes. Only shard `0` sends run controls,
+    /// logs, alarms and aggregated frame event lists.
+    #[clap(long, default_value = "0/1")]
+    shard: Shard,
+
+    /// The maximum number of channel traces generated at once, when building the trace messages of a digitiser loop,
+    /// which are built in parallel across digitisers, each generating one channel's trace at a time.
+    /// Lower this to cap memory use when `time-bins` is large, at the cost of parallelism.
+    /// If zero, every trace of the digitiser loop is generated before any message is built.
+    #[clap(long, default_value = "8")]
+    max_materialised_channels: usize,
+
+    /// Options for pacing the frames of frame loops.
+    #[clap(flatten)]
+    pacing: PacingOptions,
+
+    /// If set, OpenMetrics flavour metrics, such as the achieved frame rate, are available on this endpoint.
+    #[clap(long)]
+    observability_address: Option<SocketAddr>,
+
+    /// If set, no messages are produced to Kafka, instead each is written to its own file in this directory,
+    /// named by its topic, a sequence number, and its frame number and digitiser id if it has them.
+    /// Every message written is listed, in order, by the `index.jsonl` file of the directory.
+    #[clap(long)]
+    dry_run_dir: Option<PathBuf>,
+
+    /// If set, actions are also accepted on this TCP address, as json lines in the same format as the schedule,
+    /// and are applied at the next frame boundary, or between the actions of the schedule. Each is replied to with
+    /// `ok`, or `error: ` and the reason it failed.
+    #[clap(long)]
+    control_socket: Option<SocketAddr>,
+
+    /// If set, once the schedule is complete, actions received on the control socket continue to be applied
+    /// until none have been received for this many seconds, so that the simulator can be driven by the socket alone.
+    #[clap(long, requires = "control_socket", conflicts_with = "playlist")]
+    control_linger_s: Option<u64>,
 }
 
 #[tokio::main]
@@ -183,17 +262,48 @@ async fn main() -> miette::Result<()> {
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
+        Some(&cli.producer_tuning),
     );
     let producer = client_config.create().into_diagnostic()?;
+    // The topics waited on by a defined simulation's schedule are consumed with the same Kafka options.
+    let consumer_config = digital_muon_common::generate_kafka_client_config(
+        &kafka_opts.broker,
+        &kafka_opts.username,
+        &kafka_opts.password,
+        None,
+    );
+    let message_max_bytes = cli.producer_tuning.message_max_bytes;
 
     match cli.mode.clone() {
-        Mode::Single(single) => run_single_simulation(tracer.use_otel(), &producer, single).await?,
+        Mode::Single(single) => {
+            check_dummy_trace_message_size(&single.digitiser_topic_options, message_max_bytes)?;
+            run_single_simulation(tracer.use_otel(), &producer, single).await?
+        }
         Mode::Continuous(continuous) => {
+            check_dummy_trace_message_size(&continuous.digitiser_topic_options, message_max_bytes)?;
             run_continuous_simulation(tracer.use_otel(), &producer, continuous).await?
         }
-        Mode::Defined(defined) => run_configured_simulation(tracer.use_otel(), &producer, defined)
+        Mode::Defined(defined) => {
+            if let Some(observability_address) = defined.observability_address {
+                PrometheusBuilder::new()
+                    .with_http_listener(observability_address)
+                    .install()
+                    .into_diagnostic()?;
+                describe_gauge!(
+                    ACHIEVED_FRAME_RATE_HZ,
+                    "Frames produced per second by the latest frame loop"
+                );
+            }
+            run_configured_simulation(
+                tracer.use_otel(),
+                &producer,
+                &consumer_config,
+                defined,
+                message_max_bytes,
+            )
             .await
-            .into_diagnostic()?,
+            .into_diagnostic()?
+        }
         Mode::Start(start) => create_run_start_command(tracer.use_otel(), &producer, start)
             .await
             .into_diagnostic()?,
@@ -215,6 +325,22 @@ async fn main() -> miette::Result<()> {
     Ok(())
 }
 
+/// Checks that the trace messages of single and continuous mode can be produced, if they are sent.
+fn check_dummy_trace_message_size(
+    digitiser_cli_options: &OptionalDigitiserTopics,
+    message_max_bytes: Option<usize>,
+) -> miette::Result<()> {
+    if digitiser_cli_options.digitiser_trace_topic.is_some() {
+        check_trace_message_size(
+            CHANNELS_PER_DIGITIZER,
+            digitiser_cli_options.measurements_per_frame,
+            message_max_bytes,
+        )
+        .into_diagnostic()?;
+    }
+    Ok(())
+}
+
 async fn run_single_simulation(
     use_otel: bool,
     producer: &FutureProducer,
@@ -299,6 +425,8 @@ async fn send(
                 u32::try_from(now.as_millis()).into_diagnostic()?;
                 digitiser_cli_options.events_per_frame
             ])),
+            detector: None,
+            polarity: None,
         };
         let message = DigitizerEventListMessage::create(fbb, &message);
         finish_digitizer_event_list_message_buffer(fbb, message);
@@ -0,0 +1,200 @@
+//! Estimates the size of the trace messages the simulator produces, so a simulation whose messages
+//! would be rejected by the producer is reported before any are sent.
+//! Also estimates the size of aggregated frame event list messages, so their builders can be sized before they are built.
+use digital_muon_common::{Channel, Intensity, Time};
+use thiserror::Error;
+
+/// An upper bound on the bytes of a trace message which do not belong to any channel,
+/// including the metadata and the flatbuffer tables and vtables.
+const TRACE_MESSAGE_OVERHEAD: usize = 256;
+
+/// An upper bound on the bytes of each channel trace which are not its voltages,
+/// including its table, the length of its voltage vector, alignment padding, and its offset in the channel vector.
+const CHANNEL_TRACE_OVERHEAD: usize = 32;
+
+/// An upper bound on the bytes of an aggregated frame event list message which do not belong to any event,
+/// including the metadata, the flatbuffer tables and vtables, and the lengths and alignment padding of the event vectors.
+const AGGREGATED_EVENT_LIST_OVERHEAD: usize = 256;
+
+#[derive(Debug, Error)]
+#[error(
+    "trace messages of {num_channels} channels and {time_bins} time bins may be up to {estimate} bytes, which exceeds message-max-bytes of {message_max_bytes}"
+)]
+pub(crate) struct MessageTooLarge {
+    num_channels: usize,
+    time_bins: usize,
+    estimate: usize,
+    message_max_bytes: usize,
+}
+
+/// Returns an upper bound on the serialised size, in bytes, of a trace message.
+/// # Parameters
+/// - num_channels: the number of channel traces in the message.
+/// - time_bins: the number of voltages in each channel trace.
+pub(crate) fn estimate_trace_message_size(num_channels: usize, time_bins: usize) -> usize {
+    TRACE_MESSAGE_OVERHEAD + num_channels * (CHANNEL_TRACE_OVERHEAD + 2 * time_bins)
+}
+
+/// Returns an upper bound on the serialised size, in bytes, of an aggregated frame event list message,
+/// without any digitisers present.
+/// # Parameters
+/// - num_events: the number of events in the message.
+pub(crate) fn estimate_aggregated_event_list_size(num_events: usize) -> usize {
+    AGGREGATED_EVENT_LIST_OVERHEAD
+        + num_events * (size_of::<Time>() + size_of::<Intensity>() + size_of::<Channel>())
+}
+
+/// Checks that trace messages of the given dimensions can be produced.
+/// # Parameters
+/// - num_channels: the number of channel traces in the largest message.
+/// - time_bins: the number of voltages in each channel trace.
+/// - message_max_bytes: the largest message the producer sends, if this is absent there is nothing to check.
+pub(crate) fn check_trace_message_size(
+    num_channels: usize,
+    time_bins: usize,
+    message_max_bytes: Option<usize>,
+) -> Result<(), MessageTooLarge> {
+    let Some(message_max_bytes) = message_max_bytes else {
+        return Ok(());
+    };
+    let estimate = estimate_trace_message_size(num_channels, time_bins);
+    if estimate > message_max_bytes {
+        Err(MessageTooLarge {
+            num_channels,
+            time_bins,
+            estimate,
+            message_max_bytes,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digital_muon_streaming_types::{
+        aev2_frame_assembled_event_v2_generated::{
+            FrameAssembledEventListMessage, FrameAssembledEventListMessageArgs,
+            finish_frame_assembled_event_list_message_buffer,
+        },
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+            DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+
+    /// Returns the length of a serialised trace message of the given dimensions.
+    fn serialised_size(num_channels: usize, time_bins: usize) -> usize {
+        let mut fbb = FlatBufferBuilder::new();
+        let channels = (0..num_channels)
+            .map(|channel| {
+                let voltage = fbb.create_vector::<Intensity>(&vec![Intensity::MAX; time_bins]);
+                ChannelTrace::create(
+                    &mut fbb,
+                    &ChannelTraceArgs {
+                        channel: channel as Channel,
+                        voltage: Some(voltage),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let timestamp = GpsTime::new(24, 100, 12, 30, 15, 999, 999, 999);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number: u32::MAX,
+                period_number: u64::MAX,
+                protons_per_pulse: u8::MAX,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: u16::MAX,
+            },
+        );
+        let message = DigitizerAnalogTraceMessageArgs {
+            digitizer_id: u8::MAX,
+            metadata: Some(metadata),
+            sample_rate: u64::MAX,
+            channels: Some(fbb.create_vector(&channels)),
+        };
+        let message = DigitizerAnalogTraceMessage::create(&mut fbb, &message);
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().len()
+    }
+
+    /// Returns the length of a serialised aggregated frame event list message of `num_events` events.
+    fn serialised_aggregated_size(num_events: usize) -> usize {
+        let mut fbb = FlatBufferBuilder::new();
+        let timestamp = GpsTime::new(24, 100, 12, 30, 15, 999, 999, 999);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number: u32::MAX,
+                period_number: u64::MAX,
+                protons_per_pulse: u8::MAX,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: u16::MAX,
+            },
+        );
+        let message = FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            time: Some(fbb.create_vector(&vec![Time::MAX; num_events])),
+            voltage: Some(fbb.create_vector(&vec![Intensity::MAX; num_events])),
+            channel: Some(fbb.create_vector(&vec![Channel::MAX; num_events])),
+            complete: true,
+            digitizers_present: None,
+            polarity: None,
+        };
+        let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        fbb.finished_data().len()
+    }
+
+    #[test]
+    fn aggregated_estimate_bounds_serialised_messages() {
+        for num_events in [0, 1, 2, 3, 7, 1000, 100_001] {
+            let size = serialised_aggregated_size(num_events);
+            let estimate = estimate_aggregated_event_list_size(num_events);
+            assert!(size <= estimate, "{num_events} events: {size} > {estimate}");
+        }
+        // The events dominate, so the estimate is within one percent.
+        let size = serialised_aggregated_size(100_000);
+        let estimate = estimate_aggregated_event_list_size(100_000);
+        assert!(estimate - size < estimate / 100);
+    }
+
+    #[test]
+    fn estimate_bounds_serialised_messages() {
+        for num_channels in [0, 1, 7, 8, 64] {
+            for time_bins in [0, 1, 3, 500, 30_000] {
+                let size = serialised_size(num_channels, time_bins);
+                let estimate = estimate_trace_message_size(num_channels, time_bins);
+                assert!(
+                    size <= estimate,
+                    "{num_channels} channels of {time_bins} bins: {size} > {estimate}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn estimate_is_tight_for_large_messages() {
+        let size = serialised_size(8, 30_000);
+        let estimate = estimate_trace_message_size(8, 30_000);
+        // The voltages dominate, so the estimate is within one percent.
+        assert!(estimate - size < estimate / 100);
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let estimate = estimate_trace_message_size(8, 500);
+        assert!(check_trace_message_size(8, 500, None).is_ok());
+        assert!(check_trace_message_size(8, 500, Some(estimate)).is_ok());
+        let error = check_trace_message_size(8, 500, Some(estimate - 1)).unwrap_err();
+        assert_eq!(error.estimate, estimate);
+        assert_eq!(error.message_max_bytes, estimate - 1);
+    }
+}
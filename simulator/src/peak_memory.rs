@@ -0,0 +1,58 @@
+//! Tracks the heap memory allocated by each thread, so that tests can compare the peak memory taken by alternative implementations.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    /// The bytes currently allocated by this thread, and the most allocated at once since it was last reset.
+    /// Memory freed by a thread other than the one which allocated it is counted against the freeing thread.
+    static ALLOCATED: Cell<(isize, isize)> = const { Cell::new((0, 0)) };
+}
+
+/// Adds `bytes` to those currently allocated by this thread.
+fn record(bytes: isize) {
+    // During thread teardown the counts are unavailable, and are not needed.
+    let _ = ALLOCATED.try_with(|allocated| {
+        let (current, peak) = allocated.get();
+        let current = current + bytes;
+        allocated.set((current, peak.max(current)));
+    });
+}
+
+/// Passes every allocation to the system allocator, recording the bytes allocated by each thread.
+struct PeakTrackingAllocator;
+
+unsafe impl GlobalAlloc for PeakTrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size() as isize);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        record(-(layout.size() as isize));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // The old allocation may be held whilst it is copied to the new one.
+        record(new_size as isize);
+        record(-(layout.size() as isize));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakTrackingAllocator = PeakTrackingAllocator;
+
+/// Calls `f`, and returns its result with the most bytes it held allocated at once, on this thread.
+pub(crate) fn peak_allocated_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let start = ALLOCATED.with(|allocated| {
+        let (current, _) = allocated.get();
+        allocated.set((current, current));
+        current
+    });
+    let result = f();
+    let peak = ALLOCATED.with(|allocated| allocated.get().1);
+    (result, (peak - start) as usize)
+}
@@ -1,4 +1,5 @@
 use crate::integrated::simulation_elements::{noise::NoiseSource, utils::JsonValueError};
+use chrono::Utc;
 use clap::ValueEnum;
 use digital_muon_common::Time;
 use digital_muon_streaming_types::flatbuffers::{
@@ -10,6 +11,7 @@ use isis_streaming_data_types::flatbuffers_generated::data_se00::{
     UInt8ArrayArgs, UInt16Array, UInt16ArrayArgs, UInt32Array, UInt32ArrayArgs, UInt64Array,
     UInt64ArrayArgs, ValueUnion,
 };
+use rand::{SeedableRng, rngs::StdRng};
 use serde::Deserialize;
 use std::str::FromStr;
 
@@ -90,11 +92,12 @@ pub(crate) fn generate_value(
     length: usize,
     noise_sources: &[NoiseSource],
 ) -> Result<Vec<String>, JsonValueError> {
+    let mut rng = StdRng::seed_from_u64(Utc::now().timestamp_subsec_nanos() as u64);
     (0..length)
         .map(|time| {
             noise_sources
                 .iter()
-                .map(|ns| ns.sample(time as Time, 0))
+                .map(|ns| ns.sample(time as Time, 0, &mut rng))
                 .sum::<Result<f64, _>>()
         })
         .map(|val| val.map(|val| val.to_string()))
@@ -0,0 +1,352 @@
+//! Writes the digitiser trace messages generated by the simulator to an append-only archive file,
+//! so that they can be replayed by regression tests without Kafka, see [TraceArchiveWriter].
+//!
+//! An archive is a sequence of entries, each a tag byte followed by its content, with integers little-endian:
+//! - `H`, a header, which precedes the records of each simulation run:
+//!   the magic bytes [MAGIC], the [FORMAT_VERSION] as a `u16`, the length of the simulator version as a `u16`
+//!   followed by the version in UTF-8, and the config hash as a `u64`.
+//! - `R`, a record: the length of the payload as a `u32`, followed by the payload, a serialised digitiser trace message.
+#[cfg(any(test, feature = "archive-reader"))]
+use std::io::{BufReader, ErrorKind, Read};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+/// Begins every header, so that files which are not archives are rejected.
+pub const MAGIC: &[u8; 8] = b"DMPTRACE";
+
+/// The version of the layout of the archive, which is incremented whenever it changes.
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_TAG: u8 = b'H';
+const RECORD_TAG: u8 = b'R';
+
+#[derive(Debug, Error)]
+pub enum TraceArchiveError {
+    #[error("IO Error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Record of {0} bytes is too large")]
+    RecordTooLarge(usize),
+    #[error("Simulator version of {0} bytes is too long")]
+    VersionTooLong(usize),
+    #[error("Header does not begin with the archive magic bytes")]
+    BadMagic,
+    #[error("Unsupported archive format version {0}")]
+    UnsupportedFormatVersion(u16),
+    #[error("Simulator version is not valid UTF-8")]
+    InvalidVersion,
+    #[error("Unknown entry tag {0:#04x}")]
+    UnknownTag(u8),
+    #[error("Record precedes any header")]
+    MissingHeader,
+    #[error("Archive ends part way through an entry")]
+    Truncated,
+}
+
+/// Identifies the simulator and the configuration which generated the records following it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveHeader {
+    /// The version of the simulator, as given by its `--version` option.
+    pub simulator_version: String,
+    /// The hash of every setting affecting the traces generated, see [config_hash].
+    pub config_hash: u64,
+}
+
+/// Returns the 64-bit FNV-1a hash of `config`, which should be a canonical form of every setting affecting
+/// the traces generated. Unlike [std::hash::DefaultHasher], this is the same on every platform and Rust release.
+pub fn config_hash(config: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    config.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// Appends a header, and then each record written, to an archive.
+pub struct TraceArchiveWriter<W: Write> {
+    writer: W,
+}
+
+impl TraceArchiveWriter<BufWriter<File>> {
+    /// Opens the archive at `path`, creating it if necessary, and appends `header` to it.
+    pub fn append_to(path: &Path, header: &ArchiveHeader) -> Result<Self, TraceArchiveError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Self::new(BufWriter::new(file), header)
+    }
+}
+
+impl<W: Write> TraceArchiveWriter<W> {
+    /// Writes `header` to `writer`, after which each record is written.
+    pub fn new(mut writer: W, header: &ArchiveHeader) -> Result<Self, TraceArchiveError> {
+        let version = header.simulator_version.as_bytes();
+        let version_len = u16::try_from(version.len())
+            .map_err(|_| TraceArchiveError::VersionTooLong(version.len()))?;
+        writer.write_all(&[HEADER_TAG])?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&version_len.to_le_bytes())?;
+        writer.write_all(version)?;
+        writer.write_all(&header.config_hash.to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    /// Appends `payload`, which should be a serialised digitiser trace message, as a record.
+    pub fn write_record(&mut self, payload: &[u8]) -> Result<(), TraceArchiveError> {
+        let len = u32::try_from(payload.len())
+            .map_err(|_| TraceArchiveError::RecordTooLarge(payload.len()))?;
+        self.writer.write_all(&[RECORD_TAG])?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(payload)?;
+        Ok(())
+    }
+
+    /// Ensures every record written so far is in the archive.
+    pub fn flush(&mut self) -> Result<(), TraceArchiveError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// An entry read from an archive, see [TraceArchiveReader].
+#[cfg(any(test, feature = "archive-reader"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveEntry {
+    /// Precedes the records of a simulation run.
+    Header(ArchiveHeader),
+    /// The payload of a digitiser trace message.
+    Record(Vec<u8>),
+}
+
+/// Iterates over the entries of an archive, which should be created by [read_trace_archive].
+///
+/// The first entry of a valid archive is a header. After an error, no further entries are read.
+#[cfg(any(test, feature = "archive-reader"))]
+pub struct TraceArchiveReader<R: Read> {
+    reader: R,
+    has_header: bool,
+    failed: bool,
+}
+
+/// Opens the archive at `path` for reading.
+#[cfg(any(test, feature = "archive-reader"))]
+pub fn read_trace_archive(
+    path: &Path,
+) -> Result<TraceArchiveReader<BufReader<File>>, TraceArchiveError> {
+    Ok(TraceArchiveReader::new(BufReader::new(File::open(path)?)))
+}
+
+#[cfg(any(test, feature = "archive-reader"))]
+impl<R: Read> TraceArchiveReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            has_header: false,
+            failed: false,
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], TraceArchiveError> {
+        let mut bytes = [0; N];
+        self.reader
+            .read_exact(&mut bytes)
+            .map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => TraceArchiveError::Truncated,
+                _ => TraceArchiveError::Io(e),
+            })?;
+        Ok(bytes)
+    }
+
+    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>, TraceArchiveError> {
+        // The length may be corrupt, so is not preallocated.
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(TraceArchiveError::Truncated);
+        }
+        Ok(bytes)
+    }
+
+    fn read_header(&mut self) -> Result<ArchiveHeader, TraceArchiveError> {
+        if &self.read_array::<8>()? != MAGIC {
+            return Err(TraceArchiveError::BadMagic);
+        }
+        let format_version = u16::from_le_bytes(self.read_array()?);
+        if format_version != FORMAT_VERSION {
+            return Err(TraceArchiveError::UnsupportedFormatVersion(format_version));
+        }
+        let version_len = u16::from_le_bytes(self.read_array()?);
+        let simulator_version = String::from_utf8(self.read_vec(version_len.into())?)
+            .map_err(|_| TraceArchiveError::InvalidVersion)?;
+        let config_hash = u64::from_le_bytes(self.read_array()?);
+        Ok(ArchiveHeader {
+            simulator_version,
+            config_hash,
+        })
+    }
+
+    /// Reads the next entry, or returns `None` if the archive ends before its tag.
+    fn read_entry(&mut self) -> Option<Result<ArchiveEntry, TraceArchiveError>> {
+        let mut tag = [0];
+        match self.reader.read(&mut tag) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e.into())),
+        }
+        Some(match tag[0] {
+            HEADER_TAG => self.read_header().map(|header| {
+                self.has_header = true;
+                ArchiveEntry::Header(header)
+            }),
+            RECORD_TAG if !self.has_header => Err(TraceArchiveError::MissingHeader),
+            RECORD_TAG => self
+                .read_array()
+                .map(u32::from_le_bytes)
+                .and_then(|len| self.read_vec(len as usize))
+                .map(ArchiveEntry::Record),
+            tag => Err(TraceArchiveError::UnknownTag(tag)),
+        })
+    }
+}
+
+#[cfg(any(test, feature = "archive-reader"))]
+impl<R: Read> Iterator for TraceArchiveReader<R> {
+    type Item = Result<ArchiveEntry, TraceArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let entry = self.read_entry();
+        self.failed = matches!(entry, Some(Err(_)));
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(config_hash: u64) -> ArchiveHeader {
+        ArchiveHeader {
+            simulator_version: "v0.1.0 git=abc".to_owned(),
+            config_hash,
+        }
+    }
+
+    fn archive(records: &[&[u8]]) -> Vec<u8> {
+        let mut writer = TraceArchiveWriter::new(Vec::new(), &header(7)).unwrap();
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        writer.writer
+    }
+
+    fn read(bytes: &[u8]) -> Vec<Result<ArchiveEntry, TraceArchiveError>> {
+        TraceArchiveReader::new(bytes).collect()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let bytes = archive(&[b"first", b"", b"third"]);
+        let entries = read(&bytes)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            [
+                ArchiveEntry::Header(header(7)),
+                ArchiveEntry::Record(b"first".to_vec()),
+                ArchiveEntry::Record(Vec::new()),
+                ArchiveEntry::Record(b"third".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_is_stable() {
+        let bytes = archive(&[b"ab"]);
+        let mut expected = b"HDMPTRACE\x01\x00\x0e\x00v0.1.0 git=abc".to_vec();
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        expected.extend_from_slice(b"R\x02\x00\x00\x00ab");
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn appended_runs_have_their_own_headers() {
+        let mut bytes = archive(&[b"a"]);
+        let mut writer = TraceArchiveWriter::new(&mut bytes, &header(8)).unwrap();
+        writer.write_record(b"b").unwrap();
+        let entries = read(&bytes)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            [
+                ArchiveEntry::Header(header(7)),
+                ArchiveEntry::Record(b"a".to_vec()),
+                ArchiveEntry::Header(header(8)),
+                ArchiveEntry::Record(b"b".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_archive_is_an_error() {
+        let bytes = archive(&[b"record"]);
+        // An archive which ends between its header and its record is valid.
+        let header_len = bytes.len() - (1 + 4 + b"record".len());
+        for len in (1..bytes.len()).filter(|&len| len != header_len) {
+            let entries = read(&bytes[..len]);
+            assert!(
+                matches!(entries.last(), Some(Err(TraceArchiveError::Truncated))),
+                "{len}: {entries:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_headers_are_errors() {
+        let bytes = archive(&[]);
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[1] = b'X';
+        assert!(matches!(
+            read(&bad_magic).as_slice(),
+            [Err(TraceArchiveError::BadMagic)]
+        ));
+
+        let mut bad_version = bytes.clone();
+        bad_version[9] = 2;
+        assert!(matches!(
+            read(&bad_version).as_slice(),
+            [Err(TraceArchiveError::UnsupportedFormatVersion(2))]
+        ));
+
+        assert!(matches!(
+            read(b"R\x00\x00\x00\x00").as_slice(),
+            [Err(TraceArchiveError::MissingHeader)]
+        ));
+        let mut unknown_tag = bytes;
+        unknown_tag.push(b'X');
+        assert!(matches!(
+            read(&unknown_tag).as_slice(),
+            [
+                Ok(ArchiveEntry::Header(_)),
+                Err(TraceArchiveError::UnknownTag(b'X'))
+            ]
+        ));
+    }
+
+    #[test]
+    fn config_hash_is_fnv1a() {
+        assert_eq!(config_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(config_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(config_hash(b"ab"), config_hash(b"ba"));
+    }
+}
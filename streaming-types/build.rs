@@ -9,6 +9,7 @@ fn main() {
     let target_dir = target_dir.join("flatbuffer_generated");
 
     let inputs = [
+        "aat2_frame_assembled_analog_trace_v2.fbs",
         "aev2_frame_assembled_event_v2.fbs",
         "dat2_digitizer_analog_trace_v2.fbs",
         "dev2_digitizer_event_v2.fbs",
@@ -89,6 +89,8 @@ mod tests {
             time,
             channel,
             voltage,
+            detector: None,
+            polarity: None,
         };
         let message = DigitizerEventListMessage::create(&mut fbb, &message);
         finish_digitizer_event_list_message_buffer(&mut fbb, message);
@@ -30,6 +30,7 @@ macro_rules! schema {
 }
 
 schema!(frame_metadata_v2_generated);
+schema!(aat2_frame_assembled_analog_trace_v2_generated);
 schema!(aev2_frame_assembled_event_v2_generated);
 schema!(dat2_digitizer_analog_trace_v2_generated);
 schema!(dev2_digitizer_event_v2_generated);
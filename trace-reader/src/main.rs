@@ -170,6 +170,7 @@ async fn main() -> miette::Result<()> {
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
+        None,
     );
 
     match args.mode {
@@ -0,0 +1,176 @@
+//! Measures the time, and the number of allocations, taken to build the event list message of a trace message,
+//! with a new builder for each message, as in previous releases, and with a builder reused from a [BuilderPool].
+//!
+//! The allocations made whilst processing each message are counted by a global allocator,
+//! and reported for each case before it is timed.
+//!
+//! Run with `cargo bench -p trace-to-events --bench builder_pool`.
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use digital_muon_common::{Channel, Intensity};
+use digital_muon_streaming_types::{
+    dat2_digitizer_analog_trace_v2_generated::{
+        ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+        DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+        root_as_digitizer_analog_trace_message,
+    },
+    flatbuffers::FlatBufferBuilder,
+    frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use trace_to_events::{
+    BuilderPool, DetectorSettings, DigitiserMessageProcessor,
+    FixedThresholdDiscriminatorParameters, Mode, Polarity, TimeUnits, trace_generation::TraceSpec,
+};
+
+const NUM_CHANNELS: usize = 8;
+const TRACE_LENGTH: usize = 30_000;
+const BASELINE: Intensity = 100;
+const SEED: u64 = 42;
+/// The number of messages over which the allocations per message are averaged.
+const COUNTED_MESSAGES: usize = 1_000;
+
+/// Counts every allocation and reallocation, before passing it to the system allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns a trace message of [NUM_CHANNELS] channels, each with a pulse every 300 samples, so about 800 events in all.
+fn trace_message() -> Vec<u8> {
+    let trace = TraceSpec {
+        length: TRACE_LENGTH,
+        baseline: BASELINE,
+        pulse_spacing: 300,
+        pulse_amplitude: 2000.0,
+        noise_sigma: 0.0,
+    }
+    .generate(SEED);
+
+    let mut fbb = FlatBufferBuilder::new();
+    let time = GpsTime::new(24, 100, 12, 30, 15, 0, 0, 0);
+    let metadata = FrameMetadataV2::create(
+        &mut fbb,
+        &FrameMetadataV2Args {
+            frame_number: 0,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            timestamp: Some(&time),
+            veto_flags: 0,
+        },
+    );
+    let channels = (0..NUM_CHANNELS)
+        .map(|channel| {
+            let voltage = fbb.create_vector(&trace);
+            ChannelTrace::create(
+                &mut fbb,
+                &ChannelTraceArgs {
+                    channel: channel as Channel,
+                    voltage: Some(voltage),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    let message = DigitizerAnalogTraceMessageArgs {
+        digitizer_id: 0,
+        metadata: Some(metadata),
+        sample_rate: 1_000_000_000,
+        channels: Some(fbb.create_vector(&channels)),
+    };
+    let message = DigitizerAnalogTraceMessage::create(&mut fbb, &message);
+    finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+    fbb.finished_data().to_vec()
+}
+
+fn processor() -> DigitiserMessageProcessor {
+    DigitiserMessageProcessor::new(
+        NUM_CHANNELS,
+        &DetectorSettings {
+            mode: &Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                threshold: 150.0,
+                duration: 1,
+                cool_off: 0,
+                disarm_threshold: None,
+                veto_threshold: None,
+                veto_extend: 0,
+            }),
+            polarity: &Polarity::Positive,
+            baseline: BASELINE,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        },
+    )
+}
+
+/// Returns the mean number of allocations made by `f` over [COUNTED_MESSAGES] calls.
+fn allocations_per_message(mut f: impl FnMut()) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..COUNTED_MESSAGES {
+        f();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / COUNTED_MESSAGES as f64
+}
+
+fn builder_pool(c: &mut Criterion) {
+    let payload = trace_message();
+    let message = root_as_digitizer_analog_trace_message(&payload).unwrap();
+    let mut processor = processor();
+    let pool = BuilderPool::new(1);
+
+    // Metrics are not recorded, so their allocations are not counted.
+    metrics::with_local_recorder(&metrics::NoopRecorder, || {
+        let mut fresh = || {
+            let mut fbb = FlatBufferBuilder::new();
+            processor.process(&mut fbb, &message);
+            black_box(fbb.finished_data().len());
+        };
+        println!(
+            "new builder per message: {:.1} allocations per message",
+            allocations_per_message(&mut fresh)
+        );
+        let mut group = c.benchmark_group("build-event-list");
+        group.throughput(Throughput::Elements(1));
+        group.bench_function("new-builder", |b| b.iter(&mut fresh));
+        group.finish();
+
+        let mut pooled = || {
+            let mut fbb = pool.take();
+            processor.process(&mut fbb, &message);
+            black_box(fbb.finished_data().len());
+        };
+        println!(
+            "pooled builder: {:.1} allocations per message",
+            allocations_per_message(&mut pooled)
+        );
+        let mut group = c.benchmark_group("build-event-list");
+        group.throughput(Throughput::Elements(1));
+        group.bench_function("pooled-builder", |b| b.iter(&mut pooled));
+        group.finish();
+    });
+}
+
+criterion_group!(benches, builder_pool);
+criterion_main!(benches);
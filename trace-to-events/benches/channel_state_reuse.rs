@@ -0,0 +1,159 @@
+//! Measures the time, and the number of allocations, taken to find the events of each channel trace,
+//! with a channel state constructed for each trace, as in previous releases, and with one reset and reused between traces,
+//! as each channel's state is by the `DigitiserMessageProcessor`.
+//!
+//! The allocations made whilst processing each trace are counted by a global allocator,
+//! and reported for each case before it is timed.
+//!
+//! Run with `cargo bench -p trace-to-events --bench channel_state_reuse`, optionally followed by `-- <FILTER>`
+//! to run only the cases whose names contain `FILTER`, for instance `-- smoothing-detector`.
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use digital_muon_common::Intensity;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use trace_to_events::{
+    DetectorSettings, DifferentialThresholdDiscriminatorParameters,
+    FixedThresholdDiscriminatorParameters, Mode, Polarity, SmoothingDetectorParameters, TimeUnits,
+    bench_utils::ReusedChannelState, find_trace_events, trace_generation::TraceSpec,
+};
+
+/// The lengths of the traces, the shorter of which is typical of a channel trace of a single frame.
+const TRACE_LENGTHS: [usize; 2] = [3_000, 30_000];
+const BASELINE: Intensity = 100;
+const SEED: u64 = 42;
+/// The number of traces over which the allocations per trace are averaged.
+const COUNTED_TRACES: usize = 1_000;
+
+/// Counts every allocation and reallocation, before passing it to the system allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns a noisy trace with a pulse, about 360 high, every 300 samples.
+fn trace(length: usize) -> Vec<Intensity> {
+    TraceSpec {
+        length,
+        baseline: BASELINE,
+        pulse_spacing: 300,
+        pulse_amplitude: 2000.0,
+        noise_sigma: 10.0,
+    }
+    .generate(SEED)
+}
+
+/// Returns each case as its name and detector, covering the states which keep windows or buffers between traces.
+fn cases() -> Vec<(&'static str, Mode)> {
+    vec![
+        (
+            "fixed-threshold",
+            Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                threshold: 150.0,
+                duration: 1,
+                cool_off: 0,
+                disarm_threshold: None,
+                veto_threshold: None,
+                veto_extend: 0,
+            }),
+        ),
+        (
+            "differential-threshold",
+            Mode::DifferentialThresholdDiscriminator(
+                DifferentialThresholdDiscriminatorParameters {
+                    begin_threshold: 50.0,
+                    begin_duration: 2,
+                    end_threshold: -50.0,
+                    end_duration: 2,
+                    ..Default::default()
+                },
+            ),
+        ),
+        (
+            "smoothing-detector",
+            Mode::SmoothingDetector(SmoothingDetectorParameters {
+                noise_centile: 50.0,
+                kernel_sigma: 2.0,
+                nsig_noise: 3.0,
+                ..Default::default()
+            }),
+        ),
+    ]
+}
+
+/// Returns the mean number of allocations made by `f` over [COUNTED_TRACES] calls.
+fn allocations_per_trace(mut f: impl FnMut()) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..COUNTED_TRACES {
+        f();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / COUNTED_TRACES as f64
+}
+
+fn channel_state_reuse(c: &mut Criterion) {
+    // Metrics are not recorded, so their allocations are not counted.
+    metrics::with_local_recorder(&metrics::NoopRecorder, || {
+        for (name, mode) in cases() {
+            let settings = DetectorSettings {
+                mode: &mode,
+                polarity: &Polarity::Positive,
+                baseline: BASELINE,
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            };
+            let mut group = c.benchmark_group(name);
+            for length in TRACE_LENGTHS {
+                let trace = trace(length);
+                group.throughput(Throughput::Elements(length as u64));
+
+                let mut fresh = || {
+                    black_box(find_trace_events(black_box(&trace), 1.0, &settings));
+                };
+                println!(
+                    "{name}/{length}, new state per trace: {:.1} allocations per trace",
+                    allocations_per_trace(&mut fresh)
+                );
+                group.bench_function(BenchmarkId::new("new-state", length), |b| {
+                    b.iter(&mut fresh)
+                });
+
+                let mut state = ReusedChannelState::new(&settings, 1.0);
+                let mut reused = || {
+                    black_box(state.find_events(black_box(&trace), 1.0));
+                };
+                println!(
+                    "{name}/{length}, reused state: {:.1} allocations per trace",
+                    allocations_per_trace(&mut reused)
+                );
+                group.bench_function(BenchmarkId::new("reused-state", length), |b| {
+                    b.iter(&mut reused)
+                });
+            }
+            group.finish();
+        }
+    });
+}
+
+criterion_group!(benches, channel_state_reuse);
+criterion_main!(benches);
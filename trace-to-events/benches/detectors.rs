@@ -0,0 +1,113 @@
+//! Measures the throughput of each detector on realistic traces, so that optimisations can be compared.
+//!
+//! The traces are generated deterministically by [TraceSpec], from back-to-back exponential pulses
+//! and seeded gaussian noise, so every run, on every machine, measures the same traces.
+//! Each case is run on traces of 30k and 300k samples, and its throughput is reported in samples per second.
+//!
+//! Run with `cargo bench -p trace-to-events --bench detectors`, optionally followed by `-- <FILTER>`
+//! to run only the cases whose names contain `FILTER`, for instance `-- fixed-threshold`.
+//!
+//! To compare the performance before and after a change, first save a baseline without the change:
+//! ```shell
+//! cargo bench -p trace-to-events --bench detectors -- --save-baseline before
+//! ```
+//! then make the change and compare against that baseline:
+//! ```shell
+//! cargo bench -p trace-to-events --bench detectors -- --baseline before
+//! ```
+//! Criterion then reports the change in time and throughput of each case, and whether it is significant.
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use digital_muon_common::Intensity;
+use std::hint::black_box;
+use trace_to_events::{
+    DetectorSettings, DifferentialThresholdDiscriminatorParameters,
+    FixedThresholdDiscriminatorParameters, Mode, Polarity, SmoothingDetectorParameters, TimeUnits,
+    find_trace_events, trace_generation::TraceSpec,
+};
+
+const TRACE_LENGTHS: [usize; 2] = [30_000, 300_000];
+const BASELINE: Intensity = 100;
+/// The seed of the noise of noisy traces.
+const SEED: u64 = 42;
+
+/// Returns a trace with a pulse, about 360 high, every 300 samples.
+fn trace_spec(length: usize, noise_sigma: f64) -> TraceSpec {
+    TraceSpec {
+        length,
+        baseline: BASELINE,
+        pulse_spacing: 300,
+        pulse_amplitude: 2000.0,
+        noise_sigma,
+    }
+}
+
+/// Returns each case as its name, the detector, and the noise sigma of its traces.
+fn cases() -> Vec<(&'static str, Mode, f64)> {
+    let fixed_threshold =
+        Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 150.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        });
+    let differential_threshold = |begin_duration, end_duration| {
+        Mode::DifferentialThresholdDiscriminator(DifferentialThresholdDiscriminatorParameters {
+            begin_threshold: 50.0,
+            begin_duration,
+            end_threshold: -50.0,
+            end_duration,
+            ..Default::default()
+        })
+    };
+    vec![
+        ("fixed-threshold/clean", fixed_threshold.clone(), 0.0),
+        ("fixed-threshold/noisy", fixed_threshold, 10.0),
+        (
+            "differential-threshold/no-durations",
+            differential_threshold(0, 0),
+            10.0,
+        ),
+        (
+            "differential-threshold/durations",
+            differential_threshold(2, 2),
+            10.0,
+        ),
+        (
+            "smoothing-detector",
+            Mode::SmoothingDetector(SmoothingDetectorParameters {
+                noise_centile: 50.0,
+                kernel_sigma: 2.0,
+                nsig_noise: 3.0,
+                ..Default::default()
+            }),
+            10.0,
+        ),
+    ]
+}
+
+fn detectors(c: &mut Criterion) {
+    for (name, mode, noise_sigma) in cases() {
+        let settings = DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: BASELINE,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        };
+        let mut group = c.benchmark_group(name);
+        for length in TRACE_LENGTHS {
+            let trace = trace_spec(length, noise_sigma).generate(SEED);
+            group.throughput(Throughput::Elements(length as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(length), &trace, |b, trace| {
+                b.iter(|| find_trace_events(black_box(trace), 1.0, &settings))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, detectors);
+criterion_main!(benches);
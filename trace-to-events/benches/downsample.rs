@@ -0,0 +1,92 @@
+//! Measures the throughput of event detection on an oversampled trace, with and without downsampling.
+//!
+//! Run with `cargo bench -p trace-to-events --bench downsample`.
+use digital_muon_common::Intensity;
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+use trace_to_events::{
+    DerivativeEstimator, DetectorSettings, DifferentialThresholdDiscriminatorParameters,
+    FixedThresholdDiscriminatorParameters, Mode, Polarity, TimeUnits, find_trace_events,
+};
+
+const TRACE_LENGTH: usize = 100_000;
+const PULSE_SPACING: usize = 1_000;
+/// The half-width of each pulse, in samples, this is oversampled by a factor of about eight.
+const PULSE_HALF_WIDTH: usize = 32;
+const ITERATIONS: u32 = 50;
+
+/// Returns a trace of triangular pulses on a noisy baseline.
+fn oversampled_trace() -> Vec<Intensity> {
+    (0..TRACE_LENGTH)
+        .map(|t| {
+            let noise = (t * 7919 % 13) as Intensity;
+            let from_peak = (t % PULSE_SPACING).abs_diff(PULSE_SPACING / 2);
+            let pulse = PULSE_HALF_WIDTH.saturating_sub(from_peak) * 1000 / PULSE_HALF_WIDTH;
+            100 + noise + pulse as Intensity
+        })
+        .collect()
+}
+
+/// Returns the mean time taken to find the events of `trace`, and the number found.
+fn measure(trace: &[Intensity], mode: &Mode, downsample_factor: usize) -> (Duration, usize) {
+    let settings = DetectorSettings {
+        mode,
+        polarity: &Polarity::Positive,
+        baseline: 100,
+        downsample_factor,
+        legacy_time_alignment: false,
+        time_units: TimeUnits::Samples,
+    };
+    let num_events = find_trace_events(trace, 1.0, &settings).0.len();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(find_trace_events(black_box(trace), 1.0, &settings));
+    }
+    (start.elapsed() / ITERATIONS, num_events)
+}
+
+fn main() {
+    let trace = oversampled_trace();
+    let modes = [
+        (
+            "fixed-threshold-discriminator",
+            Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                threshold: 500.0,
+                duration: 1,
+                cool_off: 0,
+                disarm_threshold: None,
+                veto_threshold: None,
+                veto_extend: 0,
+            }),
+        ),
+        (
+            "differential-threshold-discriminator (savitzky-golay)",
+            Mode::DifferentialThresholdDiscriminator(
+                DifferentialThresholdDiscriminatorParameters {
+                    begin_threshold: 20.0,
+                    begin_duration: 1,
+                    end_threshold: -20.0,
+                    end_duration: 1,
+                    derivative_estimator: DerivativeEstimator::SavitzkyGolay,
+                    savitzky_golay_window_length: 5,
+                    savitzky_golay_polynomial_order: 2,
+                    ..Default::default()
+                },
+            ),
+        ),
+    ];
+    for (name, mode) in &modes {
+        let (full_rate, full_rate_events) = measure(&trace, mode, 1);
+        println!("{name}:");
+        println!("  factor 1: {full_rate:?} per trace, {full_rate_events} events");
+        for downsample_factor in [2, 4, 8] {
+            let (downsampled, events) = measure(&trace, mode, downsample_factor);
+            println!(
+                "  factor {downsample_factor}: {downsampled:?} per trace, {events} events, speedup {:.2}x",
+                full_rate.as_secs_f64() / downsampled.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Measures the throughput of the smoothing window, which estimates the noise of traces for the adaptive threshold detector,
+//! so that changes to its update of the mean and variance can be compared.
+//!
+//! The traces are generated deterministically by [TraceSpec], as in the `detectors` benchmark.
+//! Each window length is run on traces of 30k and 300k samples, and its throughput is reported in samples per second.
+//!
+//! Run with `cargo bench -p trace-to-events --bench smoothing_window`, optionally followed by `-- <FILTER>`
+//! to run only the cases whose names contain `FILTER`, for instance `-- smoothing-window/8`.
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use trace_to_events::{bench_utils, trace_generation::TraceSpec};
+
+const TRACE_LENGTHS: [usize; 2] = [30_000, 300_000];
+/// The lengths of the window, from the shortest allowed by the adaptive threshold detector to a long noise window.
+const WINDOW_SIZES: [usize; 3] = [2, 8, 100];
+/// The seed of the noise of the traces.
+const SEED: u64 = 42;
+
+/// Returns a noisy trace with a pulse, about 360 high, every 300 samples.
+fn trace_spec(length: usize) -> TraceSpec {
+    TraceSpec {
+        length,
+        baseline: 100,
+        pulse_spacing: 300,
+        pulse_amplitude: 2000.0,
+        noise_sigma: 10.0,
+    }
+}
+
+fn smoothing_window(c: &mut Criterion) {
+    for window_size in WINDOW_SIZES {
+        let mut group = c.benchmark_group(format!("smoothing-window/{window_size}"));
+        for length in TRACE_LENGTHS {
+            let trace = trace_spec(length).generate(SEED);
+            group.throughput(Throughput::Elements(length as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(length), &trace, |b, trace| {
+                b.iter(|| bench_utils::smoothing_window(black_box(trace), window_size))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, smoothing_window);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Estimates the baseline of each channel trace, so that drifting electronics can be monitored.
+use crate::pulse_detection::Real;
+use chrono::{DateTime, Utc};
+use digital_muon_common::{Channel, DigitizerId, FrameNumber};
+use serde::Serialize;
+
+/// Determines how the baseline of each channel trace is estimated.
+///
+/// The estimate is only reported, it does not change the baseline used by the detectors.
+#[derive(Debug, Clone, Copy)]
+pub struct BaselineEstimate {
+    /// The number of samples at the start of each trace, which should precede any pulse, from which the baseline is estimated.
+    pub warm_up: usize,
+    /// The weight given to each sample by the exponential moving average of the warm-up samples.
+    pub smoothing_factor: Real,
+}
+
+/// The baseline estimated for a channel trace.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChannelBaseline {
+    pub channel: Channel,
+    pub baseline: Real,
+}
+
+/// The baselines estimated for the channels of a trace message.
+/// Channels whose traces are malformed, or shorter than the warm-up, are omitted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MessageBaselines {
+    pub digitizer_id: DigitizerId,
+    pub frame_number: FrameNumber,
+    /// The timestamp of the trace message, if it is valid.
+    pub timestamp: Option<DateTime<Utc>>,
+    pub baselines: Vec<ChannelBaseline>,
+}
+
+impl MessageBaselines {
+    /// Serialises the baselines as a JSON payload.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self)
+            .expect("MessageBaselines should serialise, this should never fail.")
+    }
+}
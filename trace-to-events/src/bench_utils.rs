@@ -0,0 +1,49 @@
+//! Exposes internal windows to the benchmarks, which can only use the public interface of the crate.
+//!
+//! This module is only compiled when the `bench-utils` feature is enabled.
+use crate::{
+    DetectorSettings,
+    channels::ChannelState,
+    pulse_detection::{Real, WindowIterable, window::smoothing_window::SmoothingWindow},
+};
+use digital_muon_common::{Intensity, Time};
+
+/// Runs a [SmoothingWindow] of length `window_size` over `trace`, as the adaptive threshold detector does
+/// to estimate the noise of the trace.
+///
+/// Returns the sum of the standard deviations output, so that the benchmarks have a result to consume.
+pub fn smoothing_window(trace: &[Intensity], window_size: usize) -> Real {
+    trace
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i as Real, *v as Real))
+        .window(SmoothingWindow::new(window_size))
+        .map(|(_, stats)| stats.sd())
+        .sum()
+}
+
+/// The state with which the events of a channel's traces are found, kept between traces as each channel's is
+/// by [DigitiserMessageProcessor], so its windows and detectors are reset, rather than constructed, for each trace.
+///
+/// [DigitiserMessageProcessor]: crate::DigitiserMessageProcessor
+pub struct ReusedChannelState(ChannelState);
+
+impl ReusedChannelState {
+    /// Creates the state from `settings`, for traces whose samples are `sample_time` ns apart.
+    pub fn new(settings: &DetectorSettings, sample_time: Real) -> Self {
+        let mut state = ChannelState::new(settings);
+        state.set_sample_rate(Some((1_000_000_000.0 / sample_time).round() as u64));
+        Self(state)
+    }
+
+    /// Finds the events of `trace`, as [find_trace_events] does with a newly constructed state.
+    ///
+    /// [find_trace_events]: crate::find_trace_events
+    pub fn find_events(
+        &mut self,
+        trace: &[Intensity],
+        sample_time: Real,
+    ) -> (Vec<Time>, Vec<Intensity>) {
+        self.0.find_events(trace.iter().copied(), sample_time)
+    }
+}
@@ -0,0 +1,295 @@
+//! Reuses the [FlatBufferBuilder]s in which event list messages are built, see [BuilderPool].
+//!
+//! The producer copies the payload of each message when it is sent, so a builder can be returned to the pool
+//! as soon as its message has been passed to the producer, without waiting for the message to be delivered.
+//! As the payload borrows the builder, a builder cannot be returned whilst its payload is still in use.
+use digital_muon_streaming_types::flatbuffers::FlatBufferBuilder;
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// Holds the builders not currently in use, so that their buffers, which grow to fit the largest message built in them,
+/// are not reallocated for each message.
+pub struct BuilderPool {
+    idle: Mutex<Vec<FlatBufferBuilder<'static>>>,
+    /// The most builders held whilst not in use, any more are dropped when returned.
+    capacity: usize,
+    /// The number of builders taken from the pool, and not yet returned.
+    outstanding: AtomicUsize,
+    /// The number of builders created because none were idle.
+    created: AtomicUsize,
+}
+
+impl BuilderPool {
+    /// Creates an empty pool which holds at most `capacity` idle builders.
+    /// If `capacity` is zero, builders are never reused, so a new one is created for each message.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            idle: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+            outstanding: AtomicUsize::new(0),
+            created: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes an idle builder, or creates one if there are none, which is reset and returned to the pool when dropped.
+    pub fn take(&self) -> PooledBuilder<'_> {
+        let builder = self
+            .idle
+            .lock()
+            .expect("Pool lock is not poisoned")
+            .pop()
+            .unwrap_or_else(|| {
+                self.created.fetch_add(1, Ordering::Relaxed);
+                FlatBufferBuilder::new()
+            });
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        PooledBuilder {
+            builder: Some(builder),
+            pool: self,
+        }
+    }
+
+    /// Resets `builder` and holds it, unless the pool is at capacity.
+    fn give_back(&self, mut builder: FlatBufferBuilder<'static>) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+        if self.capacity == 0 {
+            return;
+        }
+        builder.reset();
+        let mut idle = self.idle.lock().expect("Pool lock is not poisoned");
+        if idle.len() < self.capacity {
+            idle.push(builder);
+        }
+    }
+
+    /// The most builders held whilst not in use.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of builders held whilst not in use, which never exceeds [Self::capacity].
+    pub fn idle(&self) -> usize {
+        self.idle.lock().expect("Pool lock is not poisoned").len()
+    }
+
+    /// The number of builders taken from the pool, and not yet returned.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    /// The number of builders created because none were idle when one was taken.
+    pub fn created(&self) -> usize {
+        self.created.load(Ordering::Relaxed)
+    }
+}
+
+/// A builder taken from a [BuilderPool], to which it is returned when this is dropped.
+pub struct PooledBuilder<'p> {
+    /// This is only [None] once the builder has been returned.
+    builder: Option<FlatBufferBuilder<'static>>,
+    pool: &'p BuilderPool,
+}
+
+impl Deref for PooledBuilder<'_> {
+    type Target = FlatBufferBuilder<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        self.builder
+            .as_ref()
+            .expect("Builder is present until dropped")
+    }
+}
+
+impl DerefMut for PooledBuilder<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.builder
+            .as_mut()
+            .expect("Builder is present until dropped")
+    }
+}
+
+impl Drop for PooledBuilder<'_> {
+    fn drop(&mut self) {
+        if let Some(builder) = self.builder.take() {
+            self.pool.give_back(builder);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DetectorSettings, DigitiserMessageProcessor, FixedThresholdDiscriminatorParameters, Mode,
+        Polarity, TimeUnits,
+    };
+    use digital_muon_common::{Channel, Intensity};
+    use digital_muon_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+            DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+            root_as_digitizer_analog_trace_message,
+        },
+        dev2_digitizer_event_v2_generated::root_as_digitizer_event_list_message,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+    use std::{sync::Arc, thread};
+
+    /// Returns a trace message whose single channel has a pulse every ten samples, starting at `offset`.
+    fn trace_message(offset: usize) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let time = GpsTime::new(22, 205, 10, 0, 0, 0, 0, 0);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number: 0,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&time),
+                veto_flags: 0,
+            },
+        );
+        let intensities = (0..1000)
+            .map(|time| if time % 10 == offset % 10 { 100 } else { 0 })
+            .collect::<Vec<Intensity>>();
+        let voltage = fbb.create_vector(&intensities);
+        let channel = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 0 as Channel,
+                voltage: Some(voltage),
+            },
+        );
+        let channels = fbb.create_vector(&[channel]);
+        let message = DigitizerAnalogTraceMessage::create(
+            &mut fbb,
+            &DigitizerAnalogTraceMessageArgs {
+                digitizer_id: 0,
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels: Some(channels),
+            },
+        );
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+
+    fn processor() -> DigitiserMessageProcessor {
+        DigitiserMessageProcessor::new(
+            1,
+            &DetectorSettings {
+                mode: &Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                    threshold: 50.0,
+                    duration: 1,
+                    cool_off: 0,
+                    disarm_threshold: None,
+                    veto_threshold: None,
+                    veto_extend: 0,
+                }),
+                polarity: &Polarity::Positive,
+                baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            },
+        )
+    }
+
+    #[test]
+    fn builders_are_reused_for_many_messages() {
+        let pool = BuilderPool::new(2);
+        let mut processor = processor();
+        let messages = (0..10).map(trace_message).collect::<Vec<_>>();
+        metrics::with_local_recorder(&metrics::NoopRecorder, || {
+            for (index, message) in messages.iter().cycle().take(10_000).enumerate() {
+                let message = root_as_digitizer_analog_trace_message(message).unwrap();
+                let mut fbb = pool.take();
+                processor.process(&mut fbb, &message);
+                let events = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+                // A reused builder is reset, so only holds the message just built.
+                assert_eq!(events.time().unwrap().len(), 100);
+                assert_eq!(events.time().unwrap().get(0) as usize, index % 10);
+                assert_eq!(pool.outstanding(), 1);
+                drop(fbb);
+                assert_eq!(pool.outstanding(), 0);
+                assert!(pool.idle() <= pool.capacity());
+            }
+        });
+        // Only one builder is ever in use at a time, so only one is created.
+        assert_eq!(pool.created(), 1);
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[test]
+    fn outstanding_builders_are_distinct() {
+        let pool = BuilderPool::new(2);
+        let mut builders = (0..5u8)
+            .map(|value| {
+                let mut fbb = pool.take();
+                let vector = fbb.create_vector(&[value; 16]);
+                fbb.finish_minimal(vector);
+                fbb
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pool.outstanding(), 5);
+        assert_eq!(pool.created(), 5);
+        assert_eq!(pool.idle(), 0);
+
+        // No builder is handed out again whilst its payload is outstanding, so each still holds its own.
+        for (value, fbb) in builders.iter().enumerate() {
+            assert!(fbb.finished_data().ends_with(&[value as u8; 16]));
+        }
+
+        // Returning more builders than the capacity does not grow the pool beyond it.
+        builders.clear();
+        assert_eq!(pool.outstanding(), 0);
+        assert_eq!(pool.idle(), 2);
+
+        let _fbb = pool.take();
+        assert_eq!(pool.created(), 5);
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[test]
+    fn zero_capacity_never_reuses() {
+        let pool = BuilderPool::new(0);
+        for _ in 0..3 {
+            let _fbb = pool.take();
+            assert_eq!(pool.outstanding(), 1);
+        }
+        assert_eq!(pool.created(), 3);
+        assert_eq!(pool.idle(), 0);
+        assert_eq!(pool.outstanding(), 0);
+    }
+
+    #[test]
+    fn pool_invariants_hold_across_threads() {
+        let pool = Arc::new(BuilderPool::new(3));
+        let threads = (0..8u8)
+            .map(|value| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut fbb = pool.take();
+                        let vector = fbb.create_vector(&[value; 64]);
+                        fbb.finish_minimal(vector);
+                        assert!(fbb.finished_data().ends_with(&[value; 64]));
+                        assert!(pool.outstanding() <= 8);
+                        assert!(pool.idle() <= pool.capacity());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(pool.outstanding(), 0);
+        assert!(pool.idle() <= pool.capacity());
+    }
+}
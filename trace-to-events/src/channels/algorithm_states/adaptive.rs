@@ -0,0 +1,170 @@
+//! Provides objects for persisting state for the adaptive threshold detector algorithm.
+use crate::{
+    channels::algorithm_states::AlgorithmState,
+    parameters::AdaptiveThresholdDiscriminatorParameters,
+    pulse_detection::{
+        Detector, EventsIterable, Real, Stats,
+        detectors::adaptive_threshold_detector::{
+            AdaptiveThresholdDetector, AdaptiveThresholdDetectorParameters,
+        },
+        window::{Window, smoothing_window::SmoothingWindow},
+    },
+};
+use digital_muon_common::Intensity;
+use std::mem;
+
+/// Encapsulates all settings and objects in the adaptive threshold algorithm
+/// which persist across digitiser messages.
+#[derive(Clone)]
+pub(crate) struct AdaptiveThresholdDiscriminatorState {
+    /// The adaptive threshold detector, which is reset and reused for each channel trace.
+    pub(crate) detector: AdaptiveThresholdDetector,
+    /// The number of samples used to estimate the noise.
+    pub(crate) noise_window_size: usize,
+    /// The window which estimates the noise, which is reset and reused for each channel trace.
+    pub(crate) noise_window: SmoothingWindow,
+}
+
+impl AdaptiveThresholdDiscriminatorState {
+    /// Creates new instance of detector state.
+    ///
+    /// # Parameters
+    /// - parameters: settings given in the command line.
+    pub(crate) fn new(parameters: &AdaptiveThresholdDiscriminatorParameters) -> Self {
+        Self {
+            detector: AdaptiveThresholdDetector::new(&AdaptiveThresholdDetectorParameters {
+                sigma_threshold: parameters.sigma_threshold,
+                duration: parameters.duration,
+                cool_off: parameters.cool_off,
+            }),
+            noise_window_size: parameters.noise_window_size,
+            noise_window: SmoothingWindow::new(parameters.noise_window_size),
+        }
+    }
+}
+
+impl AlgorithmState for AdaptiveThresholdDiscriminatorState {
+    /// The noise window must be filled before any sample can be tested.
+    fn min_samples(&self) -> usize {
+        self.noise_window_size + 1
+    }
+
+    #[tracing::instrument(skip_all, level = "trace")]
+    fn find_events(
+        &mut self,
+        trace: impl Clone + ExactSizeIterator<Item = Real> + DoubleEndedIterator,
+        polarity_sign: Real,
+        baseline: Real,
+    ) -> (Vec<usize>, Vec<Intensity>) {
+        let raw = (0..trace.len()).zip(trace.map(move |v| polarity_sign * (v as Real - baseline)));
+
+        // Each sample is paired with the noise statistics of the window preceding it,
+        // so that a pulse does not contribute to the noise estimate used to detect it.
+        // Samples for which the window is not yet full are discarded.
+        self.noise_window.reset();
+        let noise = raw
+            .scan(&mut self.noise_window, |window, (time, value)| {
+                let stats = window.output().map(|stats| Stats { value, ..stats });
+                window.push(value);
+                Some(stats.map(|stats| (time, stats)))
+            })
+            .flatten();
+        self.detector.reset();
+        let mut pulses = noise.events(mem::take(&mut self.detector));
+
+        let mut index = Vec::<usize>::new();
+        let mut voltage = Vec::<Intensity>::new();
+        for pulse in pulses.by_ref() {
+            index.push(pulse.0);
+            voltage.push(pulse.1.pulse_height as Intensity);
+        }
+        (_, self.detector) = pulses.into_inner();
+        (index, voltage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngExt, SeedableRng, rngs::StdRng};
+
+    const TRACE_LENGTH: usize = 20_000;
+    const PULSE_SEPARATION: usize = 200;
+
+    /// Generates gaussian white noise of unit variance, with a rectangular pulse of
+    /// height `snr` and width 3 injected every `PULSE_SEPARATION` samples.
+    fn generate_trace(seed: u64, snr: Real) -> Vec<Real> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..TRACE_LENGTH)
+            .map(|i| {
+                // Box-Muller transform.
+                let u1 = 1.0 - rng.random::<Real>();
+                let u2 = rng.random::<Real>();
+                let noise = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                let pulse = if i > PULSE_SEPARATION && i % PULSE_SEPARATION < 3 {
+                    snr
+                } else {
+                    0.0
+                };
+                noise + pulse
+            })
+            .collect()
+    }
+
+    fn count_events(trace: &[Real], sigma_threshold: Real) -> usize {
+        let mut state =
+            AdaptiveThresholdDiscriminatorState::new(&AdaptiveThresholdDiscriminatorParameters {
+                sigma_threshold,
+                noise_window_size: 50,
+                duration: 1,
+                cool_off: 0,
+            });
+        let (index, _) = state.find_events(trace.iter().copied(), 1.0, 0.0);
+        index.len()
+    }
+
+    #[test]
+    fn constant_trace_does_not_trigger() {
+        let trace = vec![100.0; 1000];
+        assert_eq!(count_events(&trace, 3.0), 0);
+    }
+
+    #[test]
+    fn unpopulated_window_does_not_trigger() {
+        // A large value within the first `noise_window_size` samples is ignored.
+        let mut trace = generate_trace(0, 0.0);
+        trace.truncate(100);
+        trace[10] = 40.0;
+        trace[80] = 40.0;
+        let mut state =
+            AdaptiveThresholdDiscriminatorState::new(&AdaptiveThresholdDiscriminatorParameters {
+                sigma_threshold: 5.0,
+                noise_window_size: 50,
+                duration: 1,
+                cool_off: 0,
+            });
+        let (index, _) = state.find_events(trace.iter().copied(), 1.0, 0.0);
+        assert_eq!(index, vec![80]);
+    }
+
+    #[test]
+    fn noise_trigger_rate_falls_with_threshold() {
+        let trace = generate_trace(1, 0.0);
+        let rates = [1.0, 2.0, 3.0, 4.0].map(|sigma| count_events(&trace, sigma));
+        // The false trigger rate should fall monotonically as the threshold rises.
+        assert!(rates.windows(2).all(|pair| pair[0] > pair[1]));
+        // For gaussian noise, very few samples should exceed four sigma.
+        assert!(rates[3] < 10);
+    }
+
+    #[test]
+    fn pulses_found_above_threshold() {
+        let num_pulses = TRACE_LENGTH / PULSE_SEPARATION - 1;
+        // Pulses well above the threshold are all found, with few false triggers.
+        let found = count_events(&generate_trace(2, 10.0), 5.0);
+        assert!(found >= num_pulses && found < num_pulses + 5);
+        // Pulses well below the threshold are rarely found.
+        let found = count_events(&generate_trace(2, 2.0), 5.0);
+        assert!(found < num_pulses / 10);
+    }
+}
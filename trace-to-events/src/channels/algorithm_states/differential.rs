@@ -1,35 +1,48 @@
 //! Provides objects for persisting state for the differential detector algorithm.
 use crate::{
-    channels::algorithm_states::AlgorithmState,
-    parameters::{DifferentialThresholdDiscriminatorParameters, PeakHeightBasis, PeakHeightMode},
+    channels::algorithm_states::{AlgorithmState, NEGATIVE_PULSE, POSITIVE_PULSE},
+    parameters::{
+        DerivativeEstimator, DifferentialThresholdDiscriminatorParameters, PeakHeightBasis,
+    },
     pulse_detection::{
-        EventsIterable, Real, WindowIterable,
+        Detector, EventsIterable, Real, RealArray, WindowIterable,
         detectors::differential_threshold_detector::{
-            DifferentialThresholdDetector, DifferentialThresholdParameters,
+            DifferentialThresholdDetector, DifferentialThresholdParameters, ThresholdEvent,
         },
-        window::FiniteDifferences,
+        window::{FiniteDifferences, SavitzkyGolay, Window, WithRaw},
     },
 };
 use digital_muon_common::Intensity;
+use std::mem;
+
+/// The second order finite differences require three samples to produce any output.
+pub(crate) const DIFFERENTIAL_MIN_SAMPLES: usize = 3;
 
-/// Encapsulates settings to determine how peak heights should be calculated.
+/// The window which estimates the trace derivative.
 #[derive(Clone)]
-pub(crate) struct PeakHeightParameters {
-    /// Determines how the peak height is calculated.
-    pub(crate) mode: PeakHeightMode,
-    /// Determines the peak height baseline.
-    pub(crate) basis: PeakHeightBasis,
+pub(crate) enum DerivativeWindow {
+    /// First Finite Difference Window.
+    FiniteDifferences(FiniteDifferences<2>),
+    /// Savitzky-Golay Window.
+    SavitzkyGolay(SavitzkyGolay),
 }
 
 /// Encapsulates all settings and objects in the differential threshold algorithm which persist across digitiser messages.
 #[derive(Clone)]
 pub(crate) struct DifferentialThresholdDiscriminatorState {
-    /// First Finite Difference Window.
-    pub(crate) finite_differences: FiniteDifferences<2>,
-    /// Parameters for the threshold detector.
-    pub(crate) parameters: DifferentialThresholdParameters,
-    /// Determines how the peak height is calculated.
-    pub(crate) peak_height: PeakHeightParameters,
+    /// Window which estimates the trace derivative, this is reset and reused for each channel trace.
+    pub(crate) derivative: DerivativeWindow,
+    /// The differential threshold detector, which is reset and reused for each channel trace.
+    /// This determines how the peak height is calculated.
+    pub(crate) detector: DifferentialThresholdDetector,
+    /// Determines the peak height baseline.
+    pub(crate) peak_height_basis: PeakHeightBasis,
+    /// If true, peak heights are read from the raw trace values, rather than those output by the derivative window.
+    pub(crate) peak_from_raw: bool,
+    /// If true, the detector is also applied to the inverted trace, to find pulses of the opposite polarity.
+    pub(crate) detect_both_polarities: bool,
+    /// The sign of the pulse of each event found in the last trace, if both polarities are detected.
+    polarities: Option<Vec<i8>>,
 }
 
 impl DifferentialThresholdDiscriminatorState {
@@ -37,26 +50,89 @@ impl DifferentialThresholdDiscriminatorState {
     ///
     /// # Parameters
     /// - parameters: settings given in the command line.
-    pub(crate) fn new(parameters: &DifferentialThresholdDiscriminatorParameters) -> Self {
+    /// - legacy_time_alignment: if true, the finite differences align event times as in previous releases.
+    pub(crate) fn new(
+        parameters: &DifferentialThresholdDiscriminatorParameters,
+        legacy_time_alignment: bool,
+    ) -> Self {
         Self {
-            finite_differences: FiniteDifferences::<2>::new(),
-            parameters: DifferentialThresholdParameters {
-                begin_threshold: parameters.begin_threshold,
-                begin_duration: parameters.begin_duration,
-                end_threshold: parameters.end_threshold,
-                end_duration: parameters.end_duration,
-                cool_off: parameters.cool_off,
-            },
-            peak_height: PeakHeightParameters {
-                mode: parameters.peak_height_mode.clone(),
-                basis: parameters.peak_height_basis.clone(),
+            derivative: match parameters.derivative_estimator {
+                DerivativeEstimator::FiniteDifference => DerivativeWindow::FiniteDifferences(
+                    FiniteDifferences::<2>::new().with_legacy_time_alignment(legacy_time_alignment),
+                ),
+                DerivativeEstimator::SavitzkyGolay => {
+                    DerivativeWindow::SavitzkyGolay(SavitzkyGolay::new(
+                        parameters.savitzky_golay_window_length,
+                        parameters.savitzky_golay_polynomial_order,
+                    ))
+                }
             },
+            detector: DifferentialThresholdDetector::new(
+                &DifferentialThresholdParameters {
+                    begin_threshold: parameters.begin_threshold,
+                    begin_duration: parameters.begin_duration,
+                    end_threshold: parameters.end_threshold,
+                    end_duration: parameters.end_duration,
+                    cool_off: parameters.cool_off,
+                },
+                parameters.peak_height_mode.clone(),
+            ),
+            peak_height_basis: parameters.peak_height_basis.clone(),
+            peak_from_raw: parameters.peak_from_raw,
+            detect_both_polarities: parameters.detect_both_polarities,
+            polarities: None,
             //time_cache,
         }
     }
+
+    /// Finds the pulses of `trace`, after the baseline is subtracted and it is multiplied by `sign`.
+    ///
+    /// # Returns
+    /// The index and peak height of each pulse, in time order.
+    fn find_signed_pulses(
+        &mut self,
+        trace: impl ExactSizeIterator<Item = Real>,
+        sign: Real,
+        baseline: Real,
+    ) -> Vec<(usize, Intensity)> {
+        let raw = (0..trace.len()).zip(trace.map(|v| sign * (v as Real - baseline)));
+
+        let pulses = match &mut self.derivative {
+            DerivativeWindow::FiniteDifferences(window) => {
+                find_pulses(raw, window, &mut self.detector, self.peak_from_raw)
+            }
+            DerivativeWindow::SavitzkyGolay(window) => {
+                find_pulses(raw, window, &mut self.detector, self.peak_from_raw)
+            }
+        };
+
+        pulses
+            .into_iter()
+            .map(|pulse| {
+                let height = match self.peak_height_basis {
+                    PeakHeightBasis::TraceBaseline => pulse.1.peak_height as Intensity,
+                    PeakHeightBasis::PulseBaseline => {
+                        (pulse.1.peak_height - pulse.1.base_height) as Intensity
+                    }
+                };
+                (pulse.0, height)
+            })
+            .collect()
+    }
 }
 
 impl AlgorithmState for DifferentialThresholdDiscriminatorState {
+    /// The finite differences need [DIFFERENTIAL_MIN_SAMPLES] samples, and a Savitzky-Golay window must be filled,
+    /// before any derivative is output.
+    fn min_samples(&self) -> usize {
+        match &self.derivative {
+            DerivativeWindow::FiniteDifferences(_) => DIFFERENTIAL_MIN_SAMPLES,
+            DerivativeWindow::SavitzkyGolay(window) => {
+                DIFFERENTIAL_MIN_SAMPLES.max(window.window_length())
+            }
+        }
+    }
+
     #[tracing::instrument(skip_all, level = "trace")]
     fn find_events(
         &mut self,
@@ -64,27 +140,80 @@ impl AlgorithmState for DifferentialThresholdDiscriminatorState {
         polarity_sign: Real,
         baseline: Real,
     ) -> (Vec<usize>, Vec<Intensity>) {
-        let raw = (0..trace.len()).zip(trace.map(|v| polarity_sign * (v as Real - baseline)));
+        let pulses = self.find_signed_pulses(trace.clone(), polarity_sign, baseline);
+        if !self.detect_both_polarities {
+            return pulses.into_iter().unzip();
+        }
 
-        let pulses = raw
-            .clone()
-            .window(self.finite_differences.clone_only_coefficients())
-            .events(DifferentialThresholdDetector::new(
-                &self.parameters,
-                self.peak_height.mode.clone(),
-            ));
+        // Pulses of the opposite polarity are found as positive pulses of the inverted trace,
+        // so their peak heights are also positive.
+        let mut events = pulses
+            .into_iter()
+            .map(|(index, height)| (index, height, POSITIVE_PULSE))
+            .chain(
+                self.find_signed_pulses(trace, -polarity_sign, baseline)
+                    .into_iter()
+                    .map(|(index, height)| (index, height, NEGATIVE_PULSE)),
+            )
+            .collect::<Vec<_>>();
+        // The sort is stable, so ties keep the positive pulse first.
+        events.sort_by_key(|&(index, _, _)| index);
 
-        let mut index = Vec::<usize>::new();
-        let mut voltage = Vec::<Intensity>::new();
-        for pulse in pulses {
-            index.push(pulse.0);
-            voltage.push(match self.peak_height.basis {
-                PeakHeightBasis::TraceBaseline => pulse.1.peak_height as Intensity,
-                PeakHeightBasis::PulseBaseline => {
-                    (pulse.1.peak_height - pulse.1.base_height) as Intensity
-                }
-            });
+        let mut index = Vec::<usize>::with_capacity(events.len());
+        let mut voltage = Vec::<Intensity>::with_capacity(events.len());
+        let mut polarities = Vec::<i8>::with_capacity(events.len());
+        for (i, v, p) in events {
+            index.push(i);
+            voltage.push(v);
+            polarities.push(p);
         }
+        self.polarities = Some(polarities);
         (index, voltage)
     }
+
+    fn take_polarities(&mut self) -> Option<Vec<i8>> {
+        self.polarities.take()
+    }
+}
+
+/// Applies the derivative window and detector to the trace, after resetting them.
+/// The window and detector are returned to their places afterwards, so their buffers can be reused.
+///
+/// # Parameters
+/// - raw: the trace, with its polarity and baseline applied.
+/// - window: the window which estimates the trace derivative.
+/// - detector: the detector to apply to the derivative.
+/// - peak_from_raw: if true, the detector is given the raw trace value at the time of each derivative,
+///   see [WithRaw], rather than the value output by the window, so peak heights are read from the raw trace.
+///   With [PeakHeightMode::MaxValue], the peak height is then the greatest raw value between the detection's begin and end triggers.
+///
+/// [PeakHeightMode::MaxValue]: crate::parameters::PeakHeightMode::MaxValue
+fn find_pulses<W>(
+    raw: impl Iterator<Item = (usize, Real)>,
+    window: &mut W,
+    detector: &mut DifferentialThresholdDetector,
+    peak_from_raw: bool,
+) -> Vec<ThresholdEvent>
+where
+    W: Window<TimeType = usize, InputType = Real, OutputType = RealArray<2>> + Default,
+{
+    window.reset();
+    detector.reset();
+    if peak_from_raw {
+        let mut derivative = raw.window(WithRaw::new(mem::take(window)));
+        let mut pulses = derivative
+            .by_ref()
+            .map(|(time, (raw, value))| (time, RealArray::new([raw, value[1]])))
+            .events(mem::take(detector));
+        let found = pulses.by_ref().collect();
+        (_, *detector) = pulses.into_inner();
+        *window = derivative.into_inner().1.into_inner();
+        return found;
+    }
+    let mut pulses = raw.window(mem::take(window)).events(mem::take(detector));
+    let found = pulses.by_ref().collect();
+    let (derivative, used_detector) = pulses.into_inner();
+    (_, *window) = derivative.into_inner();
+    *detector = used_detector;
+    found
 }
@@ -1,10 +1,12 @@
 //! Provides objects for persisting state for a specific algorithm.
+mod adaptive;
 mod cache;
 mod differential;
 mod multiscaling;
 mod smoothing;
 mod threshold;
 
+pub(crate) use adaptive::AdaptiveThresholdDiscriminatorState;
 pub(crate) use cache::TimeCache;
 pub(crate) use differential::DifferentialThresholdDiscriminatorState;
 pub(crate) use multiscaling::{LayerProcessingSettings, MultiscalingDetectorState};
@@ -14,10 +16,19 @@ pub(crate) use threshold::ThresholdDetectorState;
 use crate::pulse_detection::Real;
 use digital_muon_common::Intensity;
 
+/// The value of the `polarity` vector of an event list for events whose pulse has the configured polarity.
+pub(crate) const POSITIVE_PULSE: i8 = 1;
+/// The value of the `polarity` vector of an event list for events whose pulse is opposite to the configured polarity.
+pub(crate) const NEGATIVE_PULSE: i8 = -1;
+
 /// Trait implemented for any object which serves as state for a specific algorithm.
 /// This includes containing cache objects as well as settings and machinery for
 /// the algorithm's event detectors.
 pub(crate) trait AlgorithmState {
+    /// The fewest samples a channel trace must contain for this algorithm to detect events.
+    /// Shorter traces are never passed to [Self::find_events].
+    fn min_samples(&self) -> usize;
+
     /// Extract muon events from the given trace, using the fixed threshold discriminator and the given settings.
     /// Returns a pair of equally-sized vectors containing the index of the trace the event occurred at, and its
     /// corresponding intensity respectively.
@@ -31,4 +42,17 @@ pub(crate) trait AlgorithmState {
         polarity_sign: Real,
         baseline: Real,
     ) -> (Vec<usize>, Vec<Intensity>);
+
+    /// Returns the number of pulses discarded by the detector since this was last called, and resets it to zero.
+    /// Only detectors with a veto threshold discard pulses.
+    fn take_vetoed_pulses(&mut self) -> usize {
+        0
+    }
+
+    /// Takes the sign of the pulse of each event found by the last call to [Self::find_events],
+    /// which is one of [POSITIVE_PULSE] or [NEGATIVE_PULSE].
+    /// Only detectors which detect both polarities return any.
+    fn take_polarities(&mut self) -> Option<Vec<i8>> {
+        None
+    }
 }
@@ -4,7 +4,10 @@ use crate::{
         AlgorithmState, DifferentialThresholdDiscriminatorState, SmoothingDetectorState,
         ThresholdDetectorState,
     },
-    parameters::{MultiscalingDetectorMethod, MultiscalingDetectorParameters},
+    parameters::{
+        DifferentialThresholdDiscriminatorParameters, MultiscalingDetectorMethod,
+        MultiscalingDetectorParameters,
+    },
     pulse_detection::{
         Real,
         window::{
@@ -34,19 +37,25 @@ impl MultiscalingMethodAlgorithmState {
     ///
     /// # Parameters
     /// - mode: the `Mode` enum to create the state object from.
-    pub(crate) fn new(mode: &MultiscalingDetectorMethod) -> Self {
+    /// - legacy_time_alignment: if true, the smoothing and differential methods align event times as in previous releases.
+    pub(crate) fn new(mode: &MultiscalingDetectorMethod, legacy_time_alignment: bool) -> Self {
         match mode {
             MultiscalingDetectorMethod::FixedThresholdDiscriminator(parameters) => {
                 Self::FixedThreshold(ThresholdDetectorState::new(parameters))
             }
             MultiscalingDetectorMethod::DifferentialThresholdDiscriminator(parameters) => {
+                // The layers' events carry no polarity, so only pulses of the configured polarity are detected.
                 Self::DifferentialThreshold(DifferentialThresholdDiscriminatorState::new(
-                    parameters,
+                    &DifferentialThresholdDiscriminatorParameters {
+                        detect_both_polarities: false,
+                        ..parameters.clone()
+                    },
+                    legacy_time_alignment,
                 ))
             }
-            MultiscalingDetectorMethod::SmoothingDetector(parameters) => {
-                Self::Smoothing(SmoothingDetectorState::new(parameters))
-            }
+            MultiscalingDetectorMethod::SmoothingDetector(parameters) => Self::Smoothing(
+                SmoothingDetectorState::new(parameters, legacy_time_alignment),
+            ),
         }
     }
 }
@@ -75,6 +84,8 @@ pub(crate) struct MultiscalingDetectorState {
     pub(crate) cache: MultiscalingDetectorCache,
     /// The state of the underlying algorithm.
     pub(crate) method_state: MultiscalingMethodAlgorithmState,
+    /// The number of layers in the pyramid.
+    pub(crate) number_of_layers: usize,
 }
 
 impl MultiscalingDetectorState {
@@ -82,7 +93,11 @@ impl MultiscalingDetectorState {
     ///
     /// # Parameters
     /// - parameters: settings given in the command line.
-    pub(crate) fn new(parameters: &MultiscalingDetectorParameters) -> Self {
+    /// - legacy_time_alignment: if true, the smoothing method aligns event times as in previous releases.
+    pub(crate) fn new(
+        parameters: &MultiscalingDetectorParameters,
+        legacy_time_alignment: bool,
+    ) -> Self {
         // FIXME: Could this be handled directly by Clap? Or if not, moved elsewhere?
         if parameters.denoise {
             assert_eq!(
@@ -145,7 +160,8 @@ impl MultiscalingDetectorState {
         let upsample_smoothing =
             ConvolutionFilter::new(KernelType::ManualCoefficients(upsample_smoothing_coefs));
 
-        let method_state = MultiscalingMethodAlgorithmState::new(&parameters.method);
+        let method_state =
+            MultiscalingMethodAlgorithmState::new(&parameters.method, legacy_time_alignment);
         let cache = MultiscalingDetectorCache {
             pyramid: PyramidLayer::new(
                 layers_settings,
@@ -160,11 +176,36 @@ impl MultiscalingDetectorState {
             upsample_smoothing,
             method_state,
             cache,
+            number_of_layers: parameters.number_of_layers,
         }
     }
 }
 
 impl AlgorithmState for MultiscalingDetectorState {
+    /// Each layer of the pyramid halves the trace, so the apex must still contain a sample,
+    /// and the smoothed trace must satisfy the underlying method.
+    fn min_samples(&self) -> usize {
+        let method_min_samples = match &self.method_state {
+            MultiscalingMethodAlgorithmState::FixedThreshold(state) => state.min_samples(),
+            MultiscalingMethodAlgorithmState::DifferentialThreshold(state) => state.min_samples(),
+            MultiscalingMethodAlgorithmState::Smoothing(state) => state.min_samples(),
+        };
+        1usize
+            .checked_shl(self.number_of_layers as u32)
+            .unwrap_or(usize::MAX)
+            .max(method_min_samples)
+    }
+
+    fn take_vetoed_pulses(&mut self) -> usize {
+        match &mut self.method_state {
+            MultiscalingMethodAlgorithmState::FixedThreshold(state) => state.take_vetoed_pulses(),
+            MultiscalingMethodAlgorithmState::DifferentialThreshold(state) => {
+                state.take_vetoed_pulses()
+            }
+            MultiscalingMethodAlgorithmState::Smoothing(state) => state.take_vetoed_pulses(),
+        }
+    }
+
     #[tracing::instrument(skip_all, level = "trace")]
     fn find_events(
         &mut self,
@@ -272,27 +313,33 @@ mod tests {
 
     #[test]
     fn test_pyramid() {
-        let mut state = MultiscalingDetectorState::new(&MultiscalingDetectorParameters {
-            downsampling_smoothing: vec![0.125, 0.5, 0.75, 0.5, 0.125],
-            smoothing_support: vec![-2, -1, 0, 1, 2],
-            fft_padding: 200,
-            fft_truncation: 5,
-            number_of_layers: 4,
-            denoise: true,
-            denoise_thresholds: vec![2.0, 5.0, 7.0, 20.0],
-            enhance: true,
-            enhance_thresholds: vec![40.0, 30.0, 35.0, 50.0],
-            enhance_factors: vec![1.5, 1.375, 1.25, 1.125],
-            multiply: true,
-            multiply_factors: vec![1.0, 0.7, 0.2, 0.1],
-            method: MultiscalingDetectorMethod::FixedThresholdDiscriminator(
-                FixedThresholdDiscriminatorParameters {
-                    threshold: 10.0,
-                    duration: 2,
-                    cool_off: 0,
-                },
-            ),
-        });
+        let mut state = MultiscalingDetectorState::new(
+            &MultiscalingDetectorParameters {
+                downsampling_smoothing: vec![0.125, 0.5, 0.75, 0.5, 0.125],
+                smoothing_support: vec![-2, -1, 0, 1, 2],
+                fft_padding: 200,
+                fft_truncation: 5,
+                number_of_layers: 4,
+                denoise: true,
+                denoise_thresholds: vec![2.0, 5.0, 7.0, 20.0],
+                enhance: true,
+                enhance_thresholds: vec![40.0, 30.0, 35.0, 50.0],
+                enhance_factors: vec![1.5, 1.375, 1.25, 1.125],
+                multiply: true,
+                multiply_factors: vec![1.0, 0.7, 0.2, 0.1],
+                method: MultiscalingDetectorMethod::FixedThresholdDiscriminator(
+                    FixedThresholdDiscriminatorParameters {
+                        threshold: 10.0,
+                        duration: 2,
+                        cool_off: 0,
+                        disarm_threshold: None,
+                        veto_threshold: None,
+                        veto_extend: 0,
+                    },
+                ),
+            },
+            false,
+        );
         let input = INPUT.map(|x| x * 1000.0).into_iter();
         let (times, intensities) = state.find_events(input, 1.0, 0.0);
         let times = times.into_iter().map(|x| x as Real).collect::<Vec<_>>();
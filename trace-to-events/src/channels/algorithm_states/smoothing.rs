@@ -24,10 +24,21 @@ pub(crate) struct SmoothingDetectorState {
     pub(crate) fin_diff_gaussian: ConvolutionFilter,
     /// This cache is persisted to avoid reallocations on every channel trace.
     pub(crate) cache: SmoothingDetectorCache,
+    /// If true, the trace is padded by half the kernel size at each end, as in previous releases,
+    /// so that event times are one sample later than the pulses they are found in.
+    pub(crate) legacy_time_alignment: bool,
 }
 
 impl SmoothingDetectorState {
-    pub(crate) fn new(parameters: &SmoothingDetectorParameters) -> Self {
+    /// Creates new instance of detector state.
+    ///
+    /// # Parameters
+    /// - parameters: settings given in the command line.
+    /// - legacy_time_alignment: if true, event times are aligned as in previous releases.
+    pub(crate) fn new(
+        parameters: &SmoothingDetectorParameters,
+        legacy_time_alignment: bool,
+    ) -> Self {
         Self {
             parameters: parameters.clone(),
             fin_diff_gaussian: ConvolutionFilter::new(KernelType::Composition {
@@ -37,11 +48,17 @@ impl SmoothingDetectorState {
                 }),
             }),
             cache: Default::default(),
+            legacy_time_alignment,
         }
     }
 }
 
 impl AlgorithmState for SmoothingDetectorState {
+    /// The trace is reflected at each end by less than the kernel size, so must be at least as long as the kernel.
+    fn min_samples(&self) -> usize {
+        self.fin_diff_gaussian.kernel_size()
+    }
+
     #[tracing::instrument(skip_all, level = "trace", fields(std_dev, num_regions))]
     fn find_events(
         &mut self,
@@ -50,13 +67,19 @@ impl AlgorithmState for SmoothingDetectorState {
         baseline: Real,
     ) -> (Vec<usize>, Vec<Intensity>) {
         //self.cache.time.ensure_time_data_written(trace.len(), sample_time);
-        // Get the radius of the kernel by right-bitshifting the size by one
-        // i.e. divide by 2, and round-down.
-        let kernel_radius = self.fin_diff_gaussian.kernel_size() >> 1;
+        // The trace is left-padded by the index of the kernel's centre, so each output is aligned with its input.
+        // Previously, it was padded by the radius of the kernel, i.e. its size right-bitshifted by one,
+        // which is one more than its centre, as the composite kernel has a trailing zero.
+        let left_padding = if self.legacy_time_alignment {
+            self.fin_diff_gaussian.kernel_size() >> 1
+        } else {
+            self.fin_diff_gaussian.kernel_centre()
+        };
+        let right_padding = self.fin_diff_gaussian.kernel_size() - left_padding;
         let padded = trace
             .clone()
             .map(|v| polarity_sign * (v as Real - baseline))
-            .pad_reflect(kernel_radius, kernel_radius);
+            .pad_reflect(left_padding, right_padding);
         self.cache.ensure_cache_lengths(
             trace.len() + self.fin_diff_gaussian.kernel_size(),
             trace.len(),
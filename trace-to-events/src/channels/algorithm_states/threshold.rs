@@ -3,18 +3,23 @@ use crate::{
     channels::algorithm_states::AlgorithmState,
     parameters::FixedThresholdDiscriminatorParameters,
     pulse_detection::{
-        EventsIterable, Real,
+        Detector, EventsIterable, Real,
         threshold_detector::{ThresholdDetector, ThresholdDetectorParameters},
     },
 };
 use digital_muon_common::Intensity;
+use std::mem;
+
+/// A single sample cannot be distinguished from the edge of the trace,
+/// so the detector's closing event would be reported at time zero.
+pub(crate) const THRESHOLD_MIN_SAMPLES: usize = 2;
 
 /// Encapsulates all settings and objects in the differential threshold algorithm
 /// which persist across digitiser messages.
 #[derive(Clone)]
 pub(crate) struct ThresholdDetectorState {
-    /// Parameters for the threshold detector.
-    pub(crate) parameters: ThresholdDetectorParameters,
+    /// The threshold detector, which is reset and reused for each channel trace.
+    pub(crate) detector: ThresholdDetector,
 }
 
 impl ThresholdDetectorState {
@@ -24,16 +29,23 @@ impl ThresholdDetectorState {
     /// - parameters: settings given in the command line.
     pub(crate) fn new(parameters: &FixedThresholdDiscriminatorParameters) -> Self {
         Self {
-            parameters: ThresholdDetectorParameters {
+            detector: ThresholdDetector::new(&ThresholdDetectorParameters {
                 threshold: parameters.threshold,
                 duration: parameters.duration,
                 cool_off: parameters.cool_off,
-            },
+                disarm_threshold: parameters.disarm_threshold,
+                veto_threshold: parameters.veto_threshold,
+                veto_extend: parameters.veto_extend,
+            }),
         }
     }
 }
 
 impl AlgorithmState for ThresholdDetectorState {
+    fn min_samples(&self) -> usize {
+        THRESHOLD_MIN_SAMPLES
+    }
+
     #[tracing::instrument(skip_all, level = "trace")]
     fn find_events(
         &mut self,
@@ -42,14 +54,20 @@ impl AlgorithmState for ThresholdDetectorState {
         baseline: Real,
     ) -> (Vec<usize>, Vec<Intensity>) {
         let raw = (0..trace.len()).zip(trace.map(move |v| polarity_sign * (v as Real - baseline)));
-        let pulses = raw.clone().events(ThresholdDetector::new(&self.parameters));
+        self.detector.reset();
+        let mut pulses = raw.events(mem::take(&mut self.detector));
 
         let mut index = Vec::<usize>::new();
         let mut voltage = Vec::<Intensity>::new();
-        for pulse in pulses {
+        for pulse in pulses.by_ref() {
             index.push(pulse.0);
             voltage.push(pulse.1.pulse_height as Intensity);
         }
+        (_, self.detector) = pulses.into_inner();
         (index, voltage)
     }
+
+    fn take_vetoed_pulses(&mut self) -> usize {
+        self.detector.take_vetoed_pulses()
+    }
 }
@@ -1,20 +1,55 @@
 //! Provides objects for persisting state algorithm-agnostic state.
 use crate::{
+    baselines::BaselineEstimate,
     channels::algorithm_states::{
-        AlgorithmState, DifferentialThresholdDiscriminatorState, MultiscalingDetectorState,
-        SmoothingDetectorState, ThresholdDetectorState, TimeCache,
+        AdaptiveThresholdDiscriminatorState, AlgorithmState,
+        DifferentialThresholdDiscriminatorState, MultiscalingDetectorState, SmoothingDetectorState,
+        ThresholdDetectorState, TimeCache,
     },
-    parameters::{DetectorSettings, Mode, Polarity},
-    pulse_detection::Real,
+    parameters::{DetectorSettings, EventMerge, Mode, Polarity, TimeUnits},
+    pulse_detection::{
+        Real, WindowIterable,
+        iterators::{MergeEventsIterable, within_dead_time},
+        window::{Decimate, Window, baseline::Baseline},
+    },
+};
+use digital_muon_common::{
+    Channel, Intensity, Time, ids::ChannelNo, metrics::failures::FailureKind,
 };
-use digital_muon_common::{Intensity, Time};
 use digital_muon_streaming_types::dat2_digitizer_analog_trace_v2_generated::ChannelTrace;
+use metrics::counter;
+use std::{iter, mem};
+use thiserror::Error;
+use tracing::{Span, debug};
+
+/// The ways in which a channel trace can be malformed, so that no events are found in it.
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum MalformedChannelTrace {
+    #[error("Channel trace has no voltage vector")]
+    MissingVoltage,
+    #[error("Channel trace has no samples")]
+    NoSamples,
+    #[error("Channel trace has {0} samples, but the message's channels have {1}")]
+    InconsistentSamples(usize, usize),
+}
+
+impl MalformedChannelTrace {
+    /// Returns the kind of failure by which the channel is counted.
+    pub fn failure_kind(&self) -> FailureKind {
+        match self {
+            Self::MissingVoltage | Self::NoSamples => FailureKind::MissingChannelData,
+            Self::InconsistentSamples(..) => FailureKind::MalformedChannel,
+        }
+    }
+}
 
 /// Encapsulates settings and objects specific to an algorithm.
 #[derive(Clone)]
 enum ChannelAlgorithmState {
     /// Encapsulates channel state used by the Fixed Threshold algorithm.
     FixedThreshold(ThresholdDetectorState),
+    /// Encapsulates channel state used by the Adaptive Threshold algorithm.
+    AdaptiveThreshold(AdaptiveThresholdDiscriminatorState),
     /// Encapsulates channel state used by the Differential Threshold algorithm.
     DifferentialThreshold(DifferentialThresholdDiscriminatorState),
     /// Encapsulates channel state used by the Smoothing algorithm.
@@ -27,22 +62,82 @@ impl ChannelAlgorithmState {
     /// Creates a new `ChannelAlgorithmState` object defined from `mode`. The state object is specific to the detector chosen.
     /// # Parameters
     /// - mode: the `Mode` enum to create the state object from.
-    pub(crate) fn new(mode: &Mode) -> Self {
+    /// - legacy_time_alignment: if true, the smoothing and differential algorithms align event times as in previous releases.
+    pub(crate) fn new(mode: &Mode, legacy_time_alignment: bool) -> Self {
         match mode {
             Mode::FixedThresholdDiscriminator(parameters) => {
                 Self::FixedThreshold(ThresholdDetectorState::new(parameters))
             }
+            Mode::AdaptiveThresholdDiscriminator(parameters) => {
+                Self::AdaptiveThreshold(AdaptiveThresholdDiscriminatorState::new(parameters))
+            }
             Mode::DifferentialThresholdDiscriminator(parameters) => Self::DifferentialThreshold(
-                DifferentialThresholdDiscriminatorState::new(parameters),
+                DifferentialThresholdDiscriminatorState::new(parameters, legacy_time_alignment),
             ),
-            Mode::SmoothingDetector(parameters) => {
-                Self::Smoothing(SmoothingDetectorState::new(parameters))
-            }
-            Mode::Multiscaling(parameters) => {
-                Self::Multiscaling(MultiscalingDetectorState::new(parameters))
-            }
+            Mode::SmoothingDetector(parameters) => Self::Smoothing(SmoothingDetectorState::new(
+                parameters,
+                legacy_time_alignment,
+            )),
+            Mode::Multiscaling(parameters) => Self::Multiscaling(MultiscalingDetectorState::new(
+                parameters,
+                legacy_time_alignment,
+            )),
         }
     }
+
+    /// Returns the fewest samples a channel trace must contain for the algorithm to be applied.
+    fn min_samples(&self) -> usize {
+        match self {
+            Self::FixedThreshold(state) => state.min_samples(),
+            Self::AdaptiveThreshold(state) => state.min_samples(),
+            Self::DifferentialThreshold(state) => state.min_samples(),
+            Self::Smoothing(state) => state.min_samples(),
+            Self::Multiscaling(state) => state.min_samples(),
+        }
+    }
+
+    /// Returns the number of pulses discarded by the algorithm's detector since this was last called.
+    fn take_vetoed_pulses(&mut self) -> usize {
+        match self {
+            Self::FixedThreshold(state) => state.take_vetoed_pulses(),
+            Self::AdaptiveThreshold(state) => state.take_vetoed_pulses(),
+            Self::DifferentialThreshold(state) => state.take_vetoed_pulses(),
+            Self::Smoothing(state) => state.take_vetoed_pulses(),
+            Self::Multiscaling(state) => state.take_vetoed_pulses(),
+        }
+    }
+
+    /// Returns the sign of the pulse of each event found in the last trace, if the algorithm detects both polarities.
+    fn take_polarities(&mut self) -> Option<Vec<i8>> {
+        match self {
+            Self::FixedThreshold(state) => state.take_polarities(),
+            Self::AdaptiveThreshold(state) => state.take_polarities(),
+            Self::DifferentialThreshold(state) => state.take_polarities(),
+            Self::Smoothing(state) => state.take_polarities(),
+            Self::Multiscaling(state) => state.take_polarities(),
+        }
+    }
+}
+
+/// The times and intensities of the events found in a channel trace by a detector.
+pub(crate) type DetectedEvents = (Vec<Time>, Vec<Intensity>);
+
+/// The events found in a channel trace by the primary detector, and by the secondary detector if there is one.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct ChannelEvents {
+    /// The events found by the primary detector.
+    pub(crate) primary: DetectedEvents,
+    /// The events found by the secondary detector, if there is one.
+    pub(crate) secondary: Option<DetectedEvents>,
+    /// The sign of the pulse of each event found by the primary detector, if it detects both polarities,
+    /// see [DifferentialThresholdDiscriminatorParameters::detect_both_polarities].
+    ///
+    /// [DifferentialThresholdDiscriminatorParameters::detect_both_polarities]: crate::parameters::DifferentialThresholdDiscriminatorParameters::detect_both_polarities
+    pub(crate) polarity: Option<Vec<i8>>,
+    /// The sign of the pulse of each event found by the secondary detector, if there is one and it detects both polarities.
+    pub(crate) secondary_polarity: Option<Vec<i8>>,
+    /// The estimated baseline of the trace, if it is estimated, see [ChannelState::with_baseline_estimate].
+    pub(crate) baseline: Option<Real>,
 }
 
 /// Encapsulates settings and objects for a channel which can be applied to each channel trace.
@@ -54,8 +149,29 @@ pub(crate) struct ChannelState {
     baseline: Real,
     /// Memory in which to persist the time values of the trace.
     time: TimeCache,
+    /// If present, the window which averages blocks of samples before the algorithm is applied.
+    decimate: Option<Decimate>,
     /// The settings and objects specific to the algorithm used.
     algorithm: ChannelAlgorithmState,
+    /// The mode from which [Self::algorithm] is created, with durations in [Self::time_units].
+    mode: Mode,
+    /// The units of the durations of [Self::mode].
+    time_units: TimeUnits,
+    /// If true, the smoothing algorithms align event times as in previous releases.
+    legacy_time_alignment: bool,
+    /// The downsample factor, by which durations in ns are converted to numbers of blocks.
+    downsample_factor: usize,
+    /// If the durations are in ns, the sample rate for which [Self::algorithm] was last created,
+    /// or [None] if the durations were taken to be numbers of samples.
+    sample_rate: Option<u64>,
+    /// The span field to which the number of pulses found is recorded.
+    num_pulses_field: &'static str,
+    /// If present, the state of a second detector, which is applied to the same traces.
+    secondary: Option<Box<ChannelState>>,
+    /// If present, the window which estimates the baseline of each trace.
+    baseline_estimate: Option<Baseline>,
+    /// If present, events closer together than its dead time are merged, see [Self::with_event_merge].
+    event_merge: Option<EventMerge>,
 }
 
 impl ChannelState {
@@ -72,31 +188,313 @@ impl ChannelState {
             polarity_sign,
             baseline: settings.baseline as Real,
             time: Default::default(),
-            algorithm: ChannelAlgorithmState::new(settings.mode),
+            decimate: (settings.downsample_factor > 1)
+                .then(|| Decimate::new(settings.downsample_factor)),
+            algorithm: ChannelAlgorithmState::new(settings.mode, settings.legacy_time_alignment),
+            mode: settings.mode.clone(),
+            time_units: settings.time_units,
+            legacy_time_alignment: settings.legacy_time_alignment,
+            downsample_factor: settings.downsample_factor,
+            sample_rate: None,
+            num_pulses_field: "num_pulses",
+            secondary: None,
+            baseline_estimate: None,
+            event_merge: None,
         }
     }
 
-    /// Extract muon events from the given trace.
+    /// Estimates the baseline of each trace, as given by `estimate`, before the detectors are applied.
+    pub(crate) fn with_baseline_estimate(mut self, estimate: &BaselineEstimate) -> Self {
+        self.baseline_estimate = Some(
+            Baseline::new(estimate.warm_up, estimate.smoothing_factor)
+                .with_legacy_time_alignment(self.legacy_time_alignment),
+        );
+        self
+    }
+
+    /// Adds a secondary detector, defined from `settings`, which is applied to each trace after this one.
+    /// Its number of pulses is recorded to the `secondary_num_pulses` field of the current span.
+    /// Its events are merged as this detector's are, see [Self::with_event_merge].
+    pub(crate) fn with_secondary(mut self, settings: &DetectorSettings) -> Self {
+        self.secondary = Some(Box::new(Self {
+            num_pulses_field: "secondary_num_pulses",
+            event_merge: self.event_merge.clone(),
+            ..Self::new(settings)
+        }));
+        self
+    }
+
+    /// Merges the events found by each detector which are closer together than the dead time of `merge`, see [Self::merge_events].
+    pub(crate) fn with_event_merge(mut self, merge: &EventMerge) -> Self {
+        self.event_merge = Some(merge.clone());
+        self.secondary = self
+            .secondary
+            .map(|secondary| Box::new(secondary.with_event_merge(merge)));
+        self
+    }
+
+    /// Replaces the primary detector with one defined from `settings`.
+    /// The secondary detector, and the estimate of the baseline, are unchanged.
+    pub(crate) fn set_primary(&mut self, settings: &DetectorSettings) {
+        *self = Self {
+            num_pulses_field: self.num_pulses_field,
+            secondary: self.secondary.take(),
+            baseline_estimate: self.baseline_estimate.take(),
+            event_merge: self.event_merge.take(),
+            ..Self::new(settings)
+        };
+    }
+
+    /// Sets the sample rate of the traces to which the detectors are next applied.
+    ///
+    /// If the durations of a detector are in ns, see [TimeUnits::Ns], its algorithm is recreated
+    /// with them converted to numbers of samples at `sample_rate`, unless it was last created for the same sample rate.
+    /// If `sample_rate` is [None], they are taken to be numbers of samples.
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: Option<u64>) {
+        if self.time_units == TimeUnits::Ns && self.sample_rate != sample_rate {
+            let mode = match sample_rate {
+                Some(sample_rate) => self.mode.in_samples(sample_rate, self.downsample_factor),
+                None => self.mode.clone(),
+            };
+            self.algorithm = ChannelAlgorithmState::new(&mode, self.legacy_time_alignment);
+            self.sample_rate = sample_rate;
+        }
+        if let Some(secondary) = self.secondary.as_mut() {
+            secondary.set_sample_rate(sample_rate);
+        }
+    }
+
+    /// Creates the span of a channel, in which [Self::find_channel_events] should be called,
+    /// with the fields it records to.
+    pub(crate) fn span(channel: Channel) -> Span {
+        tracing::info_span!(
+            "find_channel_events",
+            channel,
+            num_pulses = tracing::field::Empty,
+            secondary_num_pulses = tracing::field::Empty,
+            malformed = tracing::field::Empty,
+            baseline = tracing::field::Empty,
+        )
+    }
+
+    /// Extract muon events from the given trace, with the primary detector and, if present, the secondary detector.
+    ///
+    /// Traces with fewer samples than the algorithm's minimum produce no events,
+    /// and the algorithm is not applied.
     ///
     /// # Parameters
     /// - trace: raw trace data.
     /// - sample_time: sample time in ns.
-    #[tracing::instrument(skip_all, fields(channel = trace.channel(), num_pulses))]
+    /// - expected_samples: if set, the number of samples the trace should have.
+    ///
+    /// Any pulses vetoed by the primary detector are counted by [VETOED_PULSES_METRIC], labelled by channel.
+    /// If events are merged, see [Self::with_event_merge], those merged into earlier events by the primary detector
+    /// are counted by [MERGED_EVENTS_METRIC], labelled by channel.
+    /// If the baseline is estimated, see [Self::with_baseline_estimate], it is recorded to the `baseline` field of the current span.
+    ///
+    /// # Errors
+    /// If the trace has no voltage vector, no samples, or not the expected number of samples.
+    /// The error is recorded to the `malformed` field of the current span.
+    ///
+    /// [VETOED_PULSES_METRIC]: crate::VETOED_PULSES_METRIC
+    /// [MERGED_EVENTS_METRIC]: crate::MERGED_EVENTS_METRIC
     pub(crate) fn find_channel_events(
         &mut self,
         trace: &ChannelTrace,
         sample_time: Real,
-    ) -> (Vec<Time>, Vec<Intensity>) {
-        let trace = trace
+        expected_samples: Option<usize>,
+    ) -> Result<ChannelEvents, MalformedChannelTrace> {
+        let voltage = trace
             .voltage()
-            .expect("Trace voltage should be Some, this should never fail.")
+            .ok_or(MalformedChannelTrace::MissingVoltage)
+            .and_then(|voltage| match voltage.len() {
+                0 => Err(MalformedChannelTrace::NoSamples),
+                len => match expected_samples {
+                    Some(expected) if expected != len => {
+                        Err(MalformedChannelTrace::InconsistentSamples(len, expected))
+                    }
+                    _ => Ok(voltage),
+                },
+            })
+            .inspect_err(|e| {
+                tracing::Span::current().record("malformed", e.to_string());
+                tracing::Span::current().record("num_pulses", 0);
+            })?;
+        let baseline = self.baseline_estimate.as_mut().and_then(|window| {
+            window.reset();
+            for value in voltage.iter() {
+                if window.push(value as Real) {
+                    break;
+                }
+            }
+            window.estimate()
+        });
+        if let Some(baseline) = baseline {
+            tracing::Span::current().record("baseline", baseline);
+        }
+        let primary = self.find_events(voltage.iter(), sample_time);
+        let polarity = self.algorithm.take_polarities();
+        let (primary, polarity, merged_events) = self.merge_events(primary, polarity, sample_time);
+        let vetoed_pulses = self.algorithm.take_vetoed_pulses();
+        if vetoed_pulses > 0 {
+            counter!(
+                crate::VETOED_PULSES_METRIC,
+                &[ChannelNo(trace.channel()).label()]
+            )
+            .increment(vetoed_pulses as u64);
+        }
+        if merged_events > 0 {
+            counter!(
+                crate::MERGED_EVENTS_METRIC,
+                &[ChannelNo(trace.channel()).label()]
+            )
+            .increment(merged_events as u64);
+        }
+        let (secondary, secondary_polarity) = self
+            .secondary
+            .as_mut()
+            .map(|secondary| {
+                let events = secondary.find_events(voltage.iter(), sample_time);
+                // Only the primary detector's vetoed pulses and merged events are counted.
+                secondary.algorithm.take_vetoed_pulses();
+                let polarity = secondary.algorithm.take_polarities();
+                let (events, polarity, _) = secondary.merge_events(events, polarity, sample_time);
+                (Some(events), polarity)
+            })
+            .unwrap_or_default();
+        Ok(ChannelEvents {
+            primary,
+            secondary,
+            polarity,
+            secondary_polarity,
+            baseline,
+        })
+    }
+
+    /// Extract muon events from the given trace voltages, see [Self::find_channel_events].
+    ///
+    /// If the trace is decimated, then the algorithm is applied to the means of each block of samples,
+    /// and the time of each event is that of the centre of its block.
+    ///
+    /// The `num_pulses` field, or `secondary_num_pulses` for a secondary detector, is recorded to the current span.
+    pub(crate) fn find_events(
+        &mut self,
+        trace: impl Clone + ExactSizeIterator<Item = Intensity> + DoubleEndedIterator,
+        sample_time: Real,
+    ) -> DetectedEvents {
+        let trace = trace.map(|x| x as Real);
+        let (times, intensities) = match &mut self.decimate {
+            None => {
+                let len = trace.len();
+                let Some((indices, intensities)) = self.apply_algorithm(trace) else {
+                    return Default::default();
+                };
+                self.time.ensure_time_data_written(len, sample_time);
+                (self.time.get_times(indices), intensities)
+            }
+            Some(decimate) => {
+                decimate.reset();
+                let mut decimated = (0..trace.len())
+                    .map(|i| i as Real)
+                    .zip(trace)
+                    .window(mem::take(decimate));
+                let (centres, values): (Vec<Real>, Vec<Real>) = decimated.by_ref().unzip();
+                (_, *decimate) = decimated.into_inner();
+
+                let Some((indices, intensities)) = self.apply_algorithm(values.into_iter()) else {
+                    return Default::default();
+                };
+                let times = indices
+                    .into_iter()
+                    .map(|index| {
+                        let centre = centres
+                            .get(index)
+                            .expect("Element should exist, this should never fail");
+                        (centre * sample_time) as Time
+                    })
+                    .collect();
+                (times, intensities)
+            }
+        };
+        tracing::Span::current().record(self.num_pulses_field, times.len());
+        (times, intensities)
+    }
+
+    /// Merges each run of `events` closer together than the dead time, if events are merged, see [MergeEventsIterable::merge_within].
+    /// If there are any merged events, the number of events is recorded again to the current span.
+    ///
+    /// # Parameters
+    /// - events: the events found by the detector, in time order.
+    /// - polarity: the sign of the pulse of each event, if present, of which that of the first of each run is kept.
+    /// - sample_time: sample time in ns, by which a dead time in samples is converted to ns.
+    ///
+    /// # Returns
+    /// The merged events, their polarities, and the number of events merged into earlier events.
+    fn merge_events(
+        &self,
+        (times, intensities): DetectedEvents,
+        polarity: Option<Vec<i8>>,
+        sample_time: Real,
+    ) -> (DetectedEvents, Option<Vec<i8>>, usize) {
+        let Some(merge) = &self.event_merge else {
+            return ((times, intensities), polarity, 0);
+        };
+        let dead_time = match self.time_units {
+            TimeUnits::Samples => (merge.dead_time as Real * sample_time) as Time,
+            TimeUnits::Ns => Time::try_from(merge.dead_time).unwrap_or(Time::MAX),
+        };
+        let polarity = polarity.map(|polarity| {
+            iter::once(None)
+                .chain(times.iter().map(Some))
+                .zip(&times)
+                .zip(polarity)
+                .filter_map(|((previous, &time), polarity)| {
+                    previous
+                        .is_none_or(|&previous| !within_dead_time(previous, time, dead_time))
+                        .then_some(polarity)
+                })
+                .collect()
+        });
+        let mut merged = times
             .into_iter()
-            .map(|x| x as Real);
-        self.time.ensure_time_data_written(trace.len(), sample_time);
-        let (indices, intensitices) = match &mut self.algorithm {
+            .zip(intensities)
+            .merge_within(dead_time, merge.policy);
+        let events: DetectedEvents = merged.by_ref().unzip();
+        let num_merged = merged.num_merged();
+        if num_merged > 0 {
+            tracing::Span::current().record(self.num_pulses_field, events.0.len());
+        }
+        (events, polarity, num_merged)
+    }
+
+    /// Applies the algorithm to `trace`.
+    ///
+    /// # Returns
+    /// The indices and intensities of the events found,
+    /// or [None] if the trace has fewer samples than the algorithm's minimum, which is counted by [SHORT_TRACES_METRIC].
+    ///
+    /// [SHORT_TRACES_METRIC]: crate::SHORT_TRACES_METRIC
+    fn apply_algorithm(
+        &mut self,
+        trace: impl Clone + ExactSizeIterator<Item = Real> + DoubleEndedIterator,
+    ) -> Option<(Vec<usize>, Vec<Intensity>)> {
+        let min_samples = self.algorithm.min_samples();
+        if trace.len() < min_samples {
+            debug!(
+                "Channel trace has {} samples, fewer than the minimum {min_samples}",
+                trace.len()
+            );
+            counter!(crate::SHORT_TRACES_METRIC).increment(1);
+            tracing::Span::current().record(self.num_pulses_field, 0);
+            return None;
+        }
+        Some(match &mut self.algorithm {
             ChannelAlgorithmState::FixedThreshold(state) => {
                 state.find_events(trace, self.polarity_sign, self.baseline)
             }
+            ChannelAlgorithmState::AdaptiveThreshold(state) => {
+                state.find_events(trace, self.polarity_sign, self.baseline)
+            }
             ChannelAlgorithmState::DifferentialThreshold(state) => {
                 state.find_events(trace, self.polarity_sign, self.baseline)
             }
@@ -106,9 +504,776 @@ impl ChannelState {
             ChannelAlgorithmState::Multiscaling(state) => {
                 state.find_events(trace, self.polarity_sign, self.baseline)
             }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::{
+        AdaptiveThresholdDiscriminatorParameters, DerivativeEstimator,
+        DifferentialThresholdDiscriminatorParameters, FixedThresholdDiscriminatorParameters,
+        MergePolicy, MultiscalingDetectorMethod, MultiscalingDetectorParameters,
+        SmoothingDetectorParameters,
+    };
+    use crate::{
+        channels::{NEGATIVE_PULSE, POSITIVE_PULSE},
+        find_trace_events,
+        test_data::b2bexp,
+        test_subscriber::SpanRecorder,
+    };
+    use digital_muon_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::ChannelTraceArgs,
+        flatbuffers::{self, FlatBufferBuilder},
+    };
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    fn all_modes() -> Vec<Mode> {
+        let fixed = FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        };
+        let differential = DifferentialThresholdDiscriminatorParameters {
+            begin_threshold: 5.0,
+            begin_duration: 1,
+            end_threshold: -5.0,
+            end_duration: 1,
+            ..Default::default()
+        };
+        let smoothing = SmoothingDetectorParameters {
+            noise_centile: 50.0,
+            kernel_sigma: 2.0,
+            nsig_noise: 3.0,
+            ..Default::default()
+        };
+        let multiscaling = |method| {
+            Mode::Multiscaling(MultiscalingDetectorParameters {
+                downsampling_smoothing: vec![0.125, 0.5, 0.75, 0.5, 0.125],
+                smoothing_support: vec![-2, -1, 0, 1, 2],
+                fft_padding: 200,
+                fft_truncation: 5,
+                number_of_layers: 4,
+                method,
+                ..Default::default()
+            })
+        };
+        vec![
+            Mode::FixedThresholdDiscriminator(fixed.clone()),
+            Mode::AdaptiveThresholdDiscriminator(AdaptiveThresholdDiscriminatorParameters {
+                sigma_threshold: 3.0,
+                noise_window_size: 10,
+                duration: 1,
+                cool_off: 0,
+            }),
+            Mode::DifferentialThresholdDiscriminator(differential.clone()),
+            Mode::SmoothingDetector(smoothing.clone()),
+            multiscaling(MultiscalingDetectorMethod::FixedThresholdDiscriminator(
+                fixed,
+            )),
+            multiscaling(
+                MultiscalingDetectorMethod::DifferentialThresholdDiscriminator(differential),
+            ),
+            multiscaling(MultiscalingDetectorMethod::SmoothingDetector(smoothing)),
+        ]
+    }
+
+    /// Returns the total of all counters recorded.
+    fn total_count(recorder: &DebuggingRecorder) -> u64 {
+        recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(_, _, _, value)| match value {
+                DebugValue::Counter(count) => count,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn short_traces_produce_no_events() {
+        for mode in all_modes() {
+            for polarity in [Polarity::Positive, Polarity::Negative] {
+                let mut state = ChannelState::new(&DetectorSettings {
+                    mode: &mode,
+                    polarity: &polarity,
+                    baseline: 1000,
+                    downsample_factor: 1,
+                    legacy_time_alignment: false,
+                    time_units: TimeUnits::Samples,
+                });
+                let min_samples = state.algorithm.min_samples();
+                assert!(min_samples >= 2, "{mode:?}");
+
+                // Traces with no samples are malformed, see `malformed_traces_are_rejected`.
+                let mut lengths = vec![1, 2, min_samples - 1];
+                lengths.retain(|&len| len < min_samples);
+                lengths.dedup();
+                for len in lengths {
+                    // Alternate between extremes, so any samples reaching the detector would trigger.
+                    let voltage = (0..len).map(|i| if i % 2 == 0 { 0 } else { 2000 });
+
+                    let recorder = DebuggingRecorder::new();
+                    let (times, intensities) =
+                        metrics::with_local_recorder(&recorder, || state.find_events(voltage, 1.0));
+                    assert!(times.is_empty(), "{mode:?} {polarity:?} {len}");
+                    assert!(intensities.is_empty(), "{mode:?} {polarity:?} {len}");
+                    assert_eq!(total_count(&recorder), 1, "{mode:?} {polarity:?} {len}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn minimum_length_trace_is_processed() {
+        let mode = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        });
+        let settings = DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: 0,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
         };
-        tracing::Span::current().record("num_pulses", indices.len());
-        let times = self.time.get_times(indices);
-        (times, intensitices)
+
+        let recorder = DebuggingRecorder::new();
+        let (times, _) =
+            metrics::with_local_recorder(&recorder, || find_trace_events(&[0, 10], 1.0, &settings));
+        assert_eq!(times, vec![1]);
+        assert_eq!(total_count(&recorder), 0);
+    }
+
+    #[test]
+    fn malformed_traces_are_rejected() {
+        let mode = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        });
+        let mut state = ChannelState::new(&DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: 0,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        });
+        for (voltage, expected_samples, error) in [
+            (None, None, MalformedChannelTrace::MissingVoltage),
+            (Some(vec![]), None, MalformedChannelTrace::NoSamples),
+            (Some(vec![]), Some(0), MalformedChannelTrace::NoSamples),
+            (
+                Some(vec![0, 10, 0]),
+                Some(4),
+                MalformedChannelTrace::InconsistentSamples(3, 4),
+            ),
+        ] {
+            let mut fbb = FlatBufferBuilder::new();
+            let voltage = voltage.map(|voltage| fbb.create_vector::<Intensity>(&voltage));
+            let trace = ChannelTrace::create(
+                &mut fbb,
+                &ChannelTraceArgs {
+                    channel: 0,
+                    voltage,
+                },
+            );
+            fbb.finish(trace, None);
+            let trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+            let recorder = DebuggingRecorder::new();
+            let result = metrics::with_local_recorder(&recorder, || {
+                state.find_channel_events(&trace, 1.0, expected_samples)
+            });
+            assert_eq!(result, Err(error));
+            assert_eq!(total_count(&recorder), 0);
+        }
+    }
+
+    #[test]
+    fn vetoed_pulses_are_counted_by_channel() {
+        let mode = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: Some(100.0),
+            veto_extend: 0,
+        });
+        let mut state = ChannelState::new(&DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: 0,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        });
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = Some(fbb.create_vector::<Intensity>(&[0, 10, 0, 200, 0, 10, 0, 150, 0]));
+        let trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 3,
+                voltage,
+            },
+        );
+        fbb.finish(trace, None);
+        let trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+        let recorder = DebuggingRecorder::new();
+        let (times, _) = metrics::with_local_recorder(&recorder, || {
+            state
+                .find_channel_events(&trace, 1.0, None)
+                .unwrap()
+                .primary
+        });
+        assert_eq!(times, vec![1, 5]);
+
+        let counters = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels = key
+                    .labels()
+                    .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                    .collect::<Vec<_>>();
+                (key.name().to_owned(), labels, value)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counters,
+            vec![(
+                crate::VETOED_PULSES_METRIC.to_owned(),
+                vec![("channel".to_owned(), "3".to_owned())],
+                DebugValue::Counter(2)
+            )]
+        );
+    }
+
+    /// Returns a trace about a baseline of 1000, with pulses in both directions and a ripple,
+    /// starting `offset` samples into the pattern.
+    fn back_to_back_trace(len: usize, offset: usize) -> Vec<Intensity> {
+        (offset..offset + len)
+            .map(|i| {
+                let pulse = match (i % 37, i % 23) {
+                    (0..3, _) => 300,
+                    (_, 0..2) => -300,
+                    _ => 0,
+                };
+                (995 + pulse + (i * 7 % 11) as i32) as Intensity
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reused_state_matches_new_state() {
+        let savitzky_golay = Mode::DifferentialThresholdDiscriminator(
+            DifferentialThresholdDiscriminatorParameters {
+                begin_threshold: 5.0,
+                begin_duration: 1,
+                end_threshold: -5.0,
+                end_duration: 1,
+                derivative_estimator: DerivativeEstimator::SavitzkyGolay,
+                savitzky_golay_window_length: 5,
+                savitzky_golay_polynomial_order: 2,
+                ..Default::default()
+            },
+        );
+        // The first trace ends as a pulse begins, so the detectors finish mid-detection.
+        let first = back_to_back_trace(186, 0);
+        let second = back_to_back_trace(300, 5);
+        let traces = [&first, &second, &first];
+
+        for mode in all_modes().into_iter().chain([savitzky_golay]) {
+            for (polarity, downsample_factor) in [Polarity::Positive, Polarity::Negative]
+                .into_iter()
+                .flat_map(|polarity| [(polarity, 1), (polarity, 3)])
+            {
+                let settings = DetectorSettings {
+                    mode: &mode,
+                    polarity: &polarity,
+                    baseline: 1000,
+                    downsample_factor,
+                    legacy_time_alignment: false,
+                    time_units: TimeUnits::Samples,
+                };
+                let mut reused = ChannelState::new(&settings);
+                let reused_events =
+                    traces.map(|trace| reused.find_events(trace.iter().copied(), 1.0));
+                let new_events = traces.map(|trace| {
+                    ChannelState::new(&settings).find_events(trace.iter().copied(), 1.0)
+                });
+                assert_eq!(
+                    reused_events, new_events,
+                    "{mode:?} {polarity:?} {downsample_factor}"
+                );
+            }
+        }
+    }
+
+    /// A triangular pulse peaking at 100 at sample 200, which exceeds 50 from samples 196 to 204.
+    fn triangular_pulse() -> Vec<Intensity> {
+        (0..400)
+            .map(|t: i32| (100 - 10 * (t - 200).abs()).max(0) as Intensity)
+            .collect()
+    }
+
+    /// Returns the times of the pulses found in [triangular_pulse] by a threshold detector, with a sample time of 2ns.
+    fn find_pulse_times(duration: usize, downsample_factor: usize) -> Vec<Time> {
+        let mode = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 50.0,
+            duration,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        });
+        let mut state = ChannelState::new(&DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: 0,
+            downsample_factor,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        });
+        state.find_events(triangular_pulse().into_iter(), 2.0).0
+    }
+
+    #[test]
+    fn downsampled_pulse_is_found_at_the_same_time() {
+        assert_eq!(find_pulse_times(1, 1), vec![392]);
+        // The pulse first exceeds the threshold in the block of samples 196 to 199, whose centre is 197.5.
+        assert_eq!(find_pulse_times(1, 4), vec![395]);
+    }
+
+    #[test]
+    fn downsampled_durations_are_in_blocks() {
+        // The pulse exceeds the threshold for nine samples, but only two blocks of four samples.
+        assert_eq!(find_pulse_times(3, 1), vec![392]);
+        assert_eq!(find_pulse_times(2, 4), vec![395]);
+        assert!(find_pulse_times(3, 4).is_empty());
+    }
+
+    /// Returns the times of the pulses found in a single back-to-back exponential pulse, which rises sharply at sample 100.
+    fn find_b2bexp_pulse_times(mode: &Mode, legacy_time_alignment: bool) -> Vec<Time> {
+        let trace = (0..300)
+            .map(|x| b2bexp(x as Real, 1000.0, 0.5, 100.0, 3.0, 0.25))
+            .collect::<Vec<_>>();
+        let mut state = ChannelState::new(&DetectorSettings {
+            mode,
+            polarity: &Polarity::Positive,
+            baseline: 0,
+            downsample_factor: 1,
+            legacy_time_alignment,
+            time_units: TimeUnits::Samples,
+        });
+        state.find_events(trace.into_iter(), 1.0).0
+    }
+
+    #[test]
+    fn pulse_is_found_at_the_same_time_by_each_detector() {
+        let fixed = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 50.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        });
+        let differential = Mode::DifferentialThresholdDiscriminator(
+            DifferentialThresholdDiscriminatorParameters {
+                begin_threshold: 50.0,
+                end_threshold: -10.0,
+                ..Default::default()
+            },
+        );
+        let smoothing = Mode::SmoothingDetector(SmoothingDetectorParameters {
+            noise_centile: 50.0,
+            kernel_sigma: 1.0,
+            nsig_noise: 3.0,
+            ..Default::default()
+        });
+
+        let expected = find_b2bexp_pulse_times(&fixed, false);
+        assert_eq!(expected.len(), 1);
+        for mode in [&differential, &smoothing] {
+            let times = find_b2bexp_pulse_times(mode, false);
+            assert_eq!(times.len(), 1, "{mode:?}");
+            assert!(
+                times[0].abs_diff(expected[0]) <= 1,
+                "{mode:?} {times:?} {expected:?}"
+            );
+        }
+
+        // Legacy time alignment delays the smoothing detector's events by a sample.
+        assert_eq!(
+            find_b2bexp_pulse_times(&smoothing, true),
+            vec![find_b2bexp_pulse_times(&smoothing, false)[0] + 1]
+        );
+    }
+
+    /// Returns the peak heights found in a narrow back-to-back exponential pulse, whose derivative is estimated by a wide Savitzky-Golay window,
+    /// and the greatest value of the trace.
+    fn find_narrow_pulse_heights(peak_from_raw: bool) -> (Vec<Intensity>, Intensity) {
+        let trace = (0..300)
+            .map(|x| b2bexp(x as Real, 1000.0, 0.5, 100.0, 3.0, 1.0))
+            .collect::<Vec<_>>();
+        let mode = Mode::DifferentialThresholdDiscriminator(
+            DifferentialThresholdDiscriminatorParameters {
+                begin_threshold: 5.0,
+                end_threshold: -5.0,
+                derivative_estimator: DerivativeEstimator::SavitzkyGolay,
+                savitzky_golay_window_length: 21,
+                savitzky_golay_polynomial_order: 2,
+                peak_from_raw,
+                ..Default::default()
+            },
+        );
+        let settings = DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: 0,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        };
+        let peak = trace.iter().copied().max().unwrap();
+        (find_trace_events(&trace, 1.0, &settings).1, peak)
+    }
+
+    #[test]
+    fn peak_from_raw_recovers_narrow_pulse_height() {
+        let (smoothed, peak) = find_narrow_pulse_heights(false);
+        assert_eq!(smoothed.len(), 1);
+        // Smoothing lowers the peak of a narrow pulse.
+        assert!(smoothed[0] < peak / 2, "{smoothed:?} {peak}");
+
+        let (raw, peak) = find_narrow_pulse_heights(true);
+        assert_eq!(raw.len(), 1);
+        assert!(raw[0].abs_diff(peak) <= peak / 100, "{raw:?} {peak}");
+    }
+
+    fn positive_settings(mode: &Mode) -> DetectorSettings<'_> {
+        DetectorSettings {
+            mode,
+            polarity: &Polarity::Positive,
+            baseline: 1000,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        }
+    }
+
+    #[test]
+    fn secondary_detector_is_applied_to_the_same_trace() {
+        let modes = all_modes();
+        let trace = back_to_back_trace(300, 0);
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = Some(fbb.create_vector(&trace));
+        let channel_trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 0,
+                voltage,
+            },
+        );
+        fbb.finish(channel_trace, None);
+        let channel_trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+        for primary in &modes {
+            for secondary in &modes {
+                let events = ChannelState::new(&positive_settings(primary))
+                    .with_secondary(&positive_settings(secondary))
+                    .find_channel_events(&channel_trace, 1.0, None)
+                    .unwrap();
+                let expected = |mode| {
+                    ChannelState::new(&positive_settings(mode))
+                        .find_events(trace.iter().copied(), 1.0)
+                };
+                assert_eq!(events.primary, expected(primary), "{primary:?}");
+                assert_eq!(events.secondary, Some(expected(secondary)), "{secondary:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn baseline_is_estimated_from_pre_pulse_samples() {
+        let mode = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 50.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        });
+        let mut state = ChannelState::new(&DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: 100,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        })
+        .with_baseline_estimate(&BaselineEstimate {
+            warm_up: 8,
+            smoothing_factor: 0.1,
+        });
+        let mut fbb = FlatBufferBuilder::new();
+        // A flat region at 100, followed by a pulse, and another flat region at 200, which is after the warm-up.
+        let voltage = Some(fbb.create_vector::<Intensity>(&[
+            100, 100, 100, 100, 100, 100, 100, 100, 300, 300, 100, 200, 200, 200,
+        ]));
+        let trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 0,
+                voltage,
+            },
+        );
+        fbb.finish(trace, None);
+        let trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+        let recorder = SpanRecorder::default();
+        let events = tracing::subscriber::with_default(recorder.clone(), || {
+            ChannelState::span(0).in_scope(|| state.find_channel_events(&trace, 1.0, None))
+        })
+        .unwrap();
+        assert_eq!(events.baseline, Some(100.0));
+        assert_eq!(events.primary.0, vec![8, 11]);
+        assert_eq!(recorder.f64_fields(), vec![("baseline", 100.0)]);
+
+        // Traces shorter than the warm-up have no estimate.
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = Some(fbb.create_vector::<Intensity>(&[100, 100, 300]));
+        let trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 0,
+                voltage,
+            },
+        );
+        fbb.finish(trace, None);
+        let trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+        let events = state.find_channel_events(&trace, 1.0, None).unwrap();
+        assert_eq!(events.baseline, None);
+    }
+
+    /// A trace about a baseline of 1000, with a positive lobe at sample 10 followed by a mirrored negative lobe at sample 30.
+    /// Each lobe has a sharp onset and a gradual recovery, so only its onset passes the begin threshold.
+    fn bipolar_pulse() -> Vec<Intensity> {
+        let lobe = (0..10).map(|i| 100 - 10 * i).collect::<Vec<Intensity>>();
+        let mut trace = vec![1000; 48];
+        for (i, height) in lobe.iter().enumerate() {
+            trace[10 + i] += height;
+            trace[30 + i] -= height;
+        }
+        trace
+    }
+
+    #[test]
+    fn both_lobes_of_bipolar_pulse_are_detected() {
+        let differential = |detect_both_polarities| {
+            Mode::DifferentialThresholdDiscriminator(DifferentialThresholdDiscriminatorParameters {
+                begin_threshold: 50.0,
+                begin_duration: 1,
+                end_threshold: -5.0,
+                end_duration: 1,
+                detect_both_polarities,
+                ..Default::default()
+            })
+        };
+        let trace = bipolar_pulse();
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = Some(fbb.create_vector(&trace));
+        let channel_trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 0,
+                voltage,
+            },
+        );
+        fbb.finish(channel_trace, None);
+        let channel_trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+        // Each lobe alone, found by a detector of its polarity.
+        let single = |polarity| {
+            let mode = differential(false);
+            let events = ChannelState::new(&DetectorSettings {
+                polarity: &polarity,
+                ..positive_settings(&mode)
+            })
+            .find_channel_events(&channel_trace, 1.0, None)
+            .unwrap();
+            assert_eq!(events.polarity, None);
+            events.primary
+        };
+        let (positive_times, positive_intensities) = single(Polarity::Positive);
+        let (negative_times, negative_intensities) = single(Polarity::Negative);
+        assert_eq!(positive_times.len(), 1);
+        assert_eq!(negative_times.len(), 1);
+        // The lobes are mirror images 20 samples apart.
+        assert_eq!(negative_times[0], positive_times[0] + 20);
+        assert_eq!(negative_intensities, positive_intensities);
+
+        let mode = differential(true);
+        let events = ChannelState::new(&positive_settings(&mode))
+            .find_channel_events(&channel_trace, 1.0, None)
+            .unwrap();
+        assert_eq!(
+            events.primary,
+            (
+                vec![positive_times[0], negative_times[0]],
+                vec![positive_intensities[0], negative_intensities[0]]
+            )
+        );
+        assert_eq!(events.polarity, Some(vec![POSITIVE_PULSE, NEGATIVE_PULSE]));
+
+        // With the polarity inverted, the roles of the lobes are swapped.
+        let events = ChannelState::new(&DetectorSettings {
+            polarity: &Polarity::Negative,
+            ..positive_settings(&mode)
+        })
+        .find_channel_events(&channel_trace, 1.0, None)
+        .unwrap();
+        assert_eq!(events.primary.0, vec![positive_times[0], negative_times[0]]);
+        assert_eq!(events.polarity, Some(vec![NEGATIVE_PULSE, POSITIVE_PULSE]));
+    }
+
+    #[test]
+    fn close_events_are_merged_and_counted_by_channel() {
+        let mode = Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            ..Default::default()
+        });
+        let trace = [
+            1000, 1010, 1000, 1012, 1000, 1011, 1000, 1000, 1000, 1000, 1015, 1000,
+        ];
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = Some(fbb.create_vector::<Intensity>(&trace));
+        let channel_trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 3,
+                voltage,
+            },
+        );
+        fbb.finish(channel_trace, None);
+        let channel_trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+        // At 2ns per sample, the events are 4ns apart, then 10ns before the last.
+        let (times, intensities) = ChannelState::new(&positive_settings(&mode))
+            .find_channel_events(&channel_trace, 2.0, None)
+            .unwrap()
+            .primary;
+        assert_eq!(times, vec![2, 6, 10, 20]);
+
+        // A dead time of three samples is 6ns.
+        for (time_units, dead_time) in [(TimeUnits::Samples, 3), (TimeUnits::Ns, 6)] {
+            let merge = EventMerge {
+                dead_time,
+                policy: MergePolicy::First,
+            };
+            let settings = DetectorSettings {
+                time_units,
+                ..positive_settings(&mode)
+            };
+            let mut state = ChannelState::new(&settings)
+                .with_secondary(&settings)
+                .with_event_merge(&merge);
+            let recorder = DebuggingRecorder::new();
+            let events = metrics::with_local_recorder(&recorder, || {
+                state
+                    .find_channel_events(&channel_trace, 2.0, None)
+                    .unwrap()
+            });
+            let expected = (vec![2, 20], vec![intensities[0], intensities[3]]);
+            assert_eq!(events.primary, expected, "{time_units:?}");
+            assert_eq!(events.secondary, Some(expected), "{time_units:?}");
+
+            // Only the events merged by the primary detector are counted.
+            let counters = recorder
+                .snapshotter()
+                .snapshot()
+                .into_vec()
+                .into_iter()
+                .map(|(key, _, _, value)| {
+                    let key = key.key();
+                    let labels = key
+                        .labels()
+                        .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                        .collect::<Vec<_>>();
+                    (key.name().to_owned(), labels, value)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                counters,
+                vec![(
+                    crate::MERGED_EVENTS_METRIC.to_owned(),
+                    vec![("channel".to_owned(), "3".to_owned())],
+                    DebugValue::Counter(2)
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn merged_event_has_polarity_of_first() {
+        let mode = Mode::DifferentialThresholdDiscriminator(
+            DifferentialThresholdDiscriminatorParameters {
+                begin_threshold: 50.0,
+                begin_duration: 1,
+                end_threshold: -5.0,
+                end_duration: 1,
+                detect_both_polarities: true,
+                ..Default::default()
+            },
+        );
+        let trace = bipolar_pulse();
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = Some(fbb.create_vector(&trace));
+        let channel_trace = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 0,
+                voltage,
+            },
+        );
+        fbb.finish(channel_trace, None);
+        let channel_trace = flatbuffers::root::<ChannelTrace>(fbb.finished_data()).unwrap();
+
+        // The lobes are 20 samples apart, so are only merged by a longer dead time.
+        for (dead_time, expected) in [
+            (20, vec![POSITIVE_PULSE, NEGATIVE_PULSE]),
+            (21, vec![POSITIVE_PULSE]),
+        ] {
+            let events = ChannelState::new(&positive_settings(&mode))
+                .with_event_merge(&EventMerge {
+                    dead_time,
+                    policy: MergePolicy::Max,
+                })
+                .find_channel_events(&channel_trace, 1.0, None)
+                .unwrap();
+            assert_eq!(events.primary.0.len(), expected.len());
+            assert_eq!(events.polarity, Some(expected));
+        }
     }
 }
@@ -2,5 +2,6 @@
 mod algorithm_states;
 mod channel_state;
 
-pub(crate) use algorithm_states::LayerProcessingSettings;
-pub(crate) use channel_state::ChannelState;
+pub(crate) use algorithm_states::{LayerProcessingSettings, NEGATIVE_PULSE, POSITIVE_PULSE};
+pub use channel_state::MalformedChannelTrace;
+pub(crate) use channel_state::{ChannelEvents, ChannelState, DetectedEvents};
@@ -0,0 +1,141 @@
+//! Determines when the offsets of consumed trace messages are committed, see [CommitStrategy].
+use clap::ValueEnum;
+use std::collections::{BTreeSet, HashMap};
+
+/// Determines when the offset of each trace message is committed to the consumer group.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CommitStrategy {
+    /// The offset is committed as soon as the message's event list is queued for delivery.
+    /// If the component stops before the event list is delivered, the message is not reprocessed.
+    #[default]
+    Immediate,
+    /// The offset is committed once the message's event list, and those of all earlier messages
+    /// in its partition, have been delivered.
+    /// If the component stops before then, the message is reprocessed when it restarts.
+    AfterDelivery,
+}
+
+/// The deliveries of a single partition.
+#[derive(Default)]
+struct PartitionWatermark {
+    /// The offsets of messages whose event lists are awaiting delivery.
+    pending: BTreeSet<i64>,
+    /// The offset after that of the latest message resolved.
+    next: Option<i64>,
+    /// The offset most recently returned to be committed.
+    committed: Option<i64>,
+}
+
+/// Tracks the messages of each partition whose event lists are awaiting delivery,
+/// so that offsets are only committed up to the earliest message which has not been delivered.
+///
+/// Deliveries may complete in any order, so committing the offset of each as it completes
+/// could commit past an earlier message whose delivery is still pending, or has failed.
+#[derive(Default)]
+pub(crate) struct DeliveryWatermarks {
+    partitions: HashMap<i32, PartitionWatermark>,
+}
+
+impl DeliveryWatermarks {
+    /// Records that the message at `offset` of `partition` has been received, and is awaiting delivery.
+    pub(crate) fn received(&mut self, partition: i32, offset: i64) {
+        self.partitions
+            .entry(partition)
+            .or_default()
+            .pending
+            .insert(offset);
+    }
+
+    /// Records that the message at `offset` of `partition` no longer awaits delivery,
+    /// either because its event list has been delivered, or because it produced none.
+    ///
+    /// A message whose delivery fails should not be resolved, so no later offset of its partition is committed.
+    ///
+    /// # Returns
+    /// The offset to commit to the partition, if it has advanced. This is the offset of the earliest
+    /// message still awaiting delivery, or if there are none, the offset after the latest message resolved.
+    pub(crate) fn resolved(&mut self, partition: i32, offset: i64) -> Option<i64> {
+        let watermark = self.partitions.get_mut(&partition)?;
+        if !watermark.pending.remove(&offset) {
+            return None;
+        }
+        watermark.next = watermark.next.max(Some(offset + 1));
+
+        let commit = watermark.pending.first().copied().or(watermark.next)?;
+        (watermark.committed < Some(commit)).then(|| {
+            watermark.committed = Some(commit);
+            commit
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(
+        watermarks: &mut DeliveryWatermarks,
+        partition: i32,
+        offsets: impl IntoIterator<Item = i64>,
+    ) {
+        for offset in offsets {
+            watermarks.received(partition, offset);
+        }
+    }
+
+    #[test]
+    fn in_order_deliveries_advance_watermark() {
+        let mut watermarks = DeliveryWatermarks::default();
+        received(&mut watermarks, 0, 10..13);
+        assert_eq!(watermarks.resolved(0, 10), Some(11));
+        assert_eq!(watermarks.resolved(0, 11), Some(12));
+        assert_eq!(watermarks.resolved(0, 12), Some(13));
+    }
+
+    #[test]
+    fn out_of_order_deliveries_do_not_commit_past_pending() {
+        let mut watermarks = DeliveryWatermarks::default();
+        received(&mut watermarks, 0, 10..14);
+
+        // Offset 10 is still pending, so nothing beyond it may be committed.
+        assert_eq!(watermarks.resolved(0, 12), Some(10));
+        assert_eq!(watermarks.resolved(0, 13), None);
+
+        // Once 10 is delivered, the watermark only reaches the next pending offset.
+        assert_eq!(watermarks.resolved(0, 10), Some(11));
+        assert_eq!(watermarks.resolved(0, 11), Some(14));
+    }
+
+    #[test]
+    fn undelivered_message_holds_watermark() {
+        let mut watermarks = DeliveryWatermarks::default();
+        received(&mut watermarks, 0, 0..5);
+        assert_eq!(watermarks.resolved(0, 0), Some(1));
+        // The delivery of offset 1 fails, so it is never resolved.
+        for offset in 2..5 {
+            assert_eq!(watermarks.resolved(0, offset), None);
+        }
+        received(&mut watermarks, 0, [5]);
+        assert_eq!(watermarks.resolved(0, 5), None);
+    }
+
+    #[test]
+    fn partitions_are_independent() {
+        let mut watermarks = DeliveryWatermarks::default();
+        received(&mut watermarks, 0, [3, 4]);
+        received(&mut watermarks, 1, [7, 8]);
+        assert_eq!(watermarks.resolved(1, 7), Some(8));
+        assert_eq!(watermarks.resolved(0, 4), Some(3));
+        assert_eq!(watermarks.resolved(1, 8), Some(9));
+        assert_eq!(watermarks.resolved(0, 3), Some(5));
+    }
+
+    #[test]
+    fn unknown_and_repeated_offsets_are_ignored() {
+        let mut watermarks = DeliveryWatermarks::default();
+        assert_eq!(watermarks.resolved(0, 1), None);
+        received(&mut watermarks, 0, [1]);
+        assert_eq!(watermarks.resolved(0, 1), Some(2));
+        assert_eq!(watermarks.resolved(0, 1), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Replaces the settings of the primary detector whilst the component runs, from the messages of [Cli::config_topic],
+//! so that they can be retuned without restarting the component, and rebalancing its consumer group.
+//!
+//! The topic is consumed by its own consumer, which is assigned every partition of the topic, see [create_consumer_at_tail],
+//! so every instance of the component receives every message, and the latest settings are replayed when the component starts.
+//!
+//! [Cli::config_topic]: crate::Cli::config_topic
+//! [create_consumer_at_tail]: digital_muon_common::seek::create_consumer_at_tail
+use const_format::concatcp;
+use digital_muon_common::{metrics::names::METRIC_NAME_PREFIX, seek::ResolveOffsets};
+use metrics::{counter, gauge};
+use rdkafka::{
+    Message,
+    consumer::{Consumer, StreamConsumer},
+    error::KafkaResult,
+};
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+use thiserror::Error;
+use trace_to_events::{DetectorConfig, DigitiserMessageProcessor, InvalidDetectorConfig};
+use tracing::{info, warn};
+
+pub(crate) const CONFIG_RELOADS_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "config_reloads");
+pub(crate) const ACTIVE_CONFIG_HASH_METRIC: &str =
+    concatcp!(METRIC_NAME_PREFIX, "active_config_hash");
+
+#[derive(Debug, Error)]
+pub(crate) enum ConfigReloadError {
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid settings: {0}")]
+    Invalid(#[from] InvalidDetectorConfig),
+}
+
+/// The settings of the primary detector, and the number of times they have been replaced.
+struct VersionedConfig {
+    version: u64,
+    config: Arc<DetectorConfig>,
+}
+
+/// The settings of the primary detector currently in use, which each [DetectorConfigFollower] applies to its message processor.
+#[derive(Clone)]
+pub(crate) struct ActiveDetectorConfig(Arc<RwLock<VersionedConfig>>);
+
+impl ActiveDetectorConfig {
+    /// Creates the active settings from those given on the command line,
+    /// and records their fingerprint to the [ACTIVE_CONFIG_HASH_METRIC] gauge, see [DetectorConfig::fingerprint].
+    pub(crate) fn new(config: DetectorConfig) -> Self {
+        gauge!(ACTIVE_CONFIG_HASH_METRIC).set(f64::from(config.fingerprint()));
+        Self(Arc::new(RwLock::new(VersionedConfig {
+            version: 0,
+            config: Arc::new(config),
+        })))
+    }
+
+    /// Replaces the active settings with those deserialised from `payload`, if they are valid, see [DetectorConfig::validate].
+    ///
+    /// Each replacement is logged, counted by the [CONFIG_RELOADS_METRIC] counter,
+    /// and has its fingerprint recorded to the [ACTIVE_CONFIG_HASH_METRIC] gauge.
+    ///
+    /// # Errors
+    /// If `payload` is not the JSON of valid settings, in which case the active settings are unchanged.
+    pub(crate) fn apply(&self, payload: &[u8]) -> Result<(), ConfigReloadError> {
+        let config: DetectorConfig = serde_json::from_slice(payload)?;
+        config.validate()?;
+        let fingerprint = config.fingerprint();
+        let mut active = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        active.version += 1;
+        active.config = Arc::new(config);
+        info!(
+            version = active.version,
+            fingerprint, "Detector settings replaced: {:?}", active.config
+        );
+        counter!(CONFIG_RELOADS_METRIC).increment(1);
+        gauge!(ACTIVE_CONFIG_HASH_METRIC).set(f64::from(fingerprint));
+        Ok(())
+    }
+
+    /// As [Self::apply], but logs, rather than returns, why the settings of `message` are rejected.
+    pub(crate) fn apply_message(&self, message: &impl Message) {
+        match message.payload().map(|payload| self.apply(payload)) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                warn!("Detector settings rejected, the active settings are unchanged: {e}")
+            }
+            None => warn!("Detector settings message has no payload"),
+        }
+    }
+
+    /// Applies the latest message of each partition of `topic`, if it has any, so that the settings last published
+    /// before the component started are in use before any trace message is processed.
+    ///
+    /// `consumer` should be assigned to the last message of each partition of `topic`, see [create_consumer_at_tail],
+    /// and continues to receive the messages published after them.
+    ///
+    /// # Errors
+    /// If the broker cannot be queried, or returns an error rather than a message.
+    /// If the messages are not received within `timeout`, this is logged, and the settings received so far are kept.
+    ///
+    /// [create_consumer_at_tail]: digital_muon_common::seek::create_consumer_at_tail
+    pub(crate) async fn replay_latest(
+        &self,
+        consumer: &StreamConsumer,
+        topic: &str,
+        timeout: Duration,
+    ) -> KafkaResult<()> {
+        let mut num_pending = 0;
+        for partition in consumer.fetch_partitions(topic, timeout)? {
+            let (low, high) = consumer.fetch_watermarks(topic, partition, timeout)?;
+            if high > low {
+                num_pending += 1;
+            }
+        }
+        while num_pending > 0 {
+            match tokio::time::timeout(timeout, consumer.recv()).await {
+                Ok(message) => self.apply_message(&message?),
+                Err(_) => {
+                    warn!("Timed out replaying the latest detector settings from {topic}");
+                    break;
+                }
+            }
+            num_pending -= 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the version of the active settings, and the settings.
+    fn current(&self) -> (u64, Arc<DetectorConfig>) {
+        let active = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        (active.version, active.config.clone())
+    }
+}
+
+/// Applies the active settings to a message processor whenever they are replaced.
+pub(crate) struct DetectorConfigFollower {
+    active: ActiveDetectorConfig,
+    /// The version of the settings last applied to the message processor, or [None] if none have been applied.
+    applied_version: Option<u64>,
+}
+
+impl DetectorConfigFollower {
+    /// Follows `active`, whose current settings are applied before the first message is processed,
+    /// so the message processor may have been created with others, such as those given on the command line,
+    /// before the latest settings were replayed, see [ActiveDetectorConfig::replay_latest].
+    pub(crate) fn new(active: &ActiveDetectorConfig) -> Self {
+        Self {
+            active: active.clone(),
+            applied_version: None,
+        }
+    }
+
+    /// Replaces the primary detector of `message_processor`, if the active settings have been replaced since they were last applied,
+    /// see [DigitiserMessageProcessor::set_detector]. This should be called before each trace message is processed.
+    pub(crate) fn update(&mut self, message_processor: &mut DigitiserMessageProcessor) {
+        let (version, config) = self.active.current();
+        if self.applied_version != Some(version) {
+            message_processor.set_detector(&config.settings());
+            self.applied_version = Some(version);
+        }
+    }
+}
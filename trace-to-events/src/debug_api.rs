@@ -0,0 +1,427 @@
+//! Serves an endpoint to which trace messages can be posted, returning the events found in them,
+//! so the detector of a running component can be spot checked, or compared against local tuning, see [DebugApi].
+use crate::config_reload::DetectorConfigFollower;
+use clap::Args;
+use digital_muon_common::{Channel, Intensity, Time, tracer::SamplingDecision};
+use digital_muon_streaming_types::dat2_digitizer_analog_trace_v2_generated::root_as_digitizer_analog_trace_message;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use trace_to_events::{DigitiserMessageProcessor, MessageEvents};
+use tracing::{debug, warn};
+
+/// The longest request head read, the request line and headers must be contained within this.
+const MAX_HEAD_LENGTH: usize = 8192;
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct DebugApiOpts {
+    /// If set, a trace message posted to `/process` on this address is processed with the component's detector settings,
+    /// and the events found in each of its channels are returned as JSON. Nothing is consumed from, or produced to, Kafka.
+    #[clap(long)]
+    debug_api_address: Option<SocketAddr>,
+
+    /// The largest trace message, in bytes, accepted by `/process`, see `debug-api-address`.
+    #[clap(long, default_value = "16777216")]
+    debug_api_max_body_bytes: usize,
+}
+
+impl DebugApiOpts {
+    pub(crate) fn debug_api_address(&self) -> Option<SocketAddr> {
+        self.debug_api_address
+    }
+}
+
+/// An event found in a channel, as returned by `/process`.
+#[derive(Serialize)]
+struct Event {
+    time: Time,
+    intensity: Intensity,
+}
+
+/// Returns the events of `events` by channel, in time order, including channels in which no events are found.
+/// Malformed channels contribute no events to event list messages, so are omitted.
+fn events_by_channel(events: &MessageEvents) -> BTreeMap<Channel, Vec<Event>> {
+    let mut by_channel = events
+        .event_counts
+        .iter()
+        .filter(|(_, num_events)| num_events.is_ok())
+        .map(|(channel, _)| (*channel, Vec::new()))
+        .collect::<BTreeMap<_, _>>();
+    for ((&channel, &time), &intensity) in events
+        .events
+        .channel
+        .iter()
+        .zip(&events.events.time)
+        .zip(&events.events.voltage)
+    {
+        by_channel
+            .entry(channel)
+            .or_default()
+            .push(Event { time, intensity });
+    }
+    by_channel
+}
+
+/// Processes the trace messages posted to it with its own [DigitiserMessageProcessor],
+/// which should be created with the same settings as that of the main loop, so is not affected by its state.
+/// Its primary detector follows the same active settings as that of the main loop, see [DetectorConfigFollower].
+///
+/// The metrics recorded whilst processing a posted message on the thread handling it are discarded,
+/// so the component's metrics only concern the messages it consumes.
+#[derive(Clone)]
+pub(crate) struct DebugApi {
+    processor: Arc<Mutex<(DigitiserMessageProcessor, DetectorConfigFollower)>>,
+    max_body_length: usize,
+}
+
+impl DebugApi {
+    pub(crate) fn new(
+        opts: &DebugApiOpts,
+        processor: DigitiserMessageProcessor,
+        detector_config: DetectorConfigFollower,
+    ) -> Self {
+        Self {
+            processor: Arc::new(Mutex::new((processor, detector_config))),
+            max_body_length: opts.debug_api_max_body_bytes,
+        }
+    }
+
+    /// Returns the events found in the serialised trace message `body`, as a JSON object of the events of each channel.
+    fn process(&self, body: &[u8]) -> (&'static str, String) {
+        let message = match root_as_digitizer_analog_trace_message(body) {
+            Ok(message) => message,
+            Err(e) => return ("400 Bad Request", error_body(&e.to_string())),
+        };
+        let events = {
+            let mut guard = self
+                .processor
+                .lock()
+                .expect("The processor is never locked across a panic, this should never fail.");
+            let (processor, detector_config) = &mut *guard;
+            detector_config.update(processor);
+            metrics::with_local_recorder(&metrics::NoopRecorder, || {
+                processor.find_events(&message, SamplingDecision::Unsampled)
+            })
+        };
+        match serde_json::to_string(&events_by_channel(&events)) {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+        }
+    }
+
+    /// Responds to each connection made to `listener`, this never returns.
+    pub(crate) async fn serve(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    let debug_api = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = debug_api.handle(stream).await {
+                            debug!("Debug API request from {address} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Cannot accept debug API connection: {e}"),
+            }
+        }
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let (status, body) = self.respond(&mut stream).await?;
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    /// Reads the request from `stream`, returning the status line and body of the response.
+    async fn respond(&self, stream: &mut TcpStream) -> std::io::Result<(&'static str, String)> {
+        let mut request = Vec::new();
+        let mut buffer = [0; MAX_HEAD_LENGTH];
+        let head_length = loop {
+            if let Some(end) = request.windows(4).position(|end| end == b"\r\n\r\n") {
+                break end + 4;
+            }
+            if request.len() >= MAX_HEAD_LENGTH {
+                return Ok((
+                    "431 Request Header Fields Too Large",
+                    error_body("request head too long"),
+                ));
+            }
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(("400 Bad Request", error_body("incomplete request")));
+            }
+            request.extend_from_slice(buffer.get(..read).unwrap_or_default());
+        };
+
+        // The request line is of the form "POST /process HTTP/1.1", and is followed by the headers.
+        let head = String::from_utf8_lossy(request.get(..head_length).unwrap_or_default());
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let (method, path) = (request_line.next(), request_line.next());
+        if path != Some("/process") {
+            return Ok(("404 Not Found", error_body("not found")));
+        }
+        if method != Some("POST") {
+            return Ok(("405 Method Not Allowed", error_body("only POST is allowed")));
+        }
+        let content_length = lines.find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().ok())
+        });
+        let content_length = match content_length {
+            Some(Some(content_length)) => content_length,
+            Some(None) => return Ok(("400 Bad Request", error_body("invalid content length"))),
+            None => return Ok(("411 Length Required", error_body("content length required"))),
+        };
+        if content_length > self.max_body_length {
+            return Ok((
+                "413 Content Too Large",
+                error_body(&format!(
+                    "body of {content_length} bytes exceeds the limit of {} bytes",
+                    self.max_body_length
+                )),
+            ));
+        }
+
+        let mut body = request.split_off(head_length);
+        body.truncate(content_length);
+        let mut remaining = stream.take((content_length - body.len()) as u64);
+        remaining.read_to_end(&mut body).await?;
+        if body.len() < content_length {
+            return Ok(("400 Bad Request", error_body("incomplete body")));
+        }
+
+        // Processing is parallelised over the channels, so is not done on the runtime's threads.
+        let debug_api = self.clone();
+        Ok(
+            tokio::task::spawn_blocking(move || debug_api.process(&body))
+                .await
+                .unwrap_or_else(|e| ("500 Internal Server Error", error_body(&e.to_string()))),
+        )
+    }
+}
+
+fn error_body(error: &str) -> String {
+    serde_json::json!({ "error": error }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_reload::ActiveDetectorConfig;
+    use digital_muon_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+            DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+        },
+        dev2_digitizer_event_v2_generated::root_as_digitizer_event_list_message,
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+    use trace_to_events::{
+        DetectorConfig, DetectorSettings, FixedThresholdDiscriminatorParameters, Mode, Polarity,
+        TimeUnits,
+    };
+
+    /// Serialises a trace message of three channels, the second of which is flat.
+    fn create_message() -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let timestamp = GpsTime::new(24, 100, 12, 30, 15, 0, 0, 0);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number: 0,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: 0,
+            },
+        );
+        let voltages: [&[Intensity]; 3] = [
+            &[0, 1, 2, 9, 1, 0, 1, 7, 2, 0],
+            &[0; 10],
+            &[0, 8, 1, 0, 0, 12, 11, 1, 0, 6],
+        ];
+        let channels = voltages
+            .iter()
+            .enumerate()
+            .map(|(channel, voltage)| {
+                let voltage = fbb.create_vector(voltage);
+                ChannelTrace::create(
+                    &mut fbb,
+                    &ChannelTraceArgs {
+                        channel: channel as Channel,
+                        voltage: Some(voltage),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let message = DigitizerAnalogTraceMessageArgs {
+            digitizer_id: 3,
+            metadata: Some(metadata),
+            sample_rate: 1_000_000_000,
+            channels: Some(fbb.create_vector(&channels)),
+        };
+        let message = DigitizerAnalogTraceMessage::create(&mut fbb, &message);
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+
+    fn processor() -> DigitiserMessageProcessor {
+        DigitiserMessageProcessor::new(
+            8,
+            &DetectorSettings {
+                polarity: &Polarity::Positive,
+                baseline: 0,
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+                mode: &Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                    threshold: 5.0,
+                    duration: 1,
+                    cool_off: 0,
+                    disarm_threshold: None,
+                    veto_threshold: None,
+                    veto_extend: 0,
+                }),
+            },
+        )
+    }
+
+    /// The settings of a fixed threshold discriminator with the given threshold, as a message of the config topic.
+    fn threshold_config(threshold: f64) -> String {
+        format!(
+            r#"{{"mode": {{"fixed-threshold-discriminator": {{"threshold": {threshold}}}}}, "polarity": "positive"}}"#
+        )
+    }
+
+    /// Returns the active settings of [processor], with a debug API following them.
+    fn debug_api(opts: &DebugApiOpts) -> (ActiveDetectorConfig, DebugApi) {
+        let config: DetectorConfig = serde_json::from_str(&threshold_config(5.0)).unwrap();
+        let active = ActiveDetectorConfig::new(config);
+        let debug_api = DebugApi::new(opts, processor(), DetectorConfigFollower::new(&active));
+        (active, debug_api)
+    }
+
+    async fn post(address: SocketAddr, path: &str, body: &[u8]) -> (String, String) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let head = format!(
+            "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_owned(), body.to_owned())
+    }
+
+    #[tokio::test]
+    async fn posted_message_matches_processing() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let opts = DebugApiOpts {
+            debug_api_address: Some(address),
+            debug_api_max_body_bytes: 1024,
+        };
+        let (_, debug_api) = debug_api(&opts);
+        tokio::spawn(debug_api.serve(listener));
+
+        let message = create_message();
+        let (status, body) = post(address, "/process", &message).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        // The events of the event list message produced by processing the message directly.
+        let mut fbb = FlatBufferBuilder::new();
+        let trace = root_as_digitizer_analog_trace_message(&message).unwrap();
+        processor().process(&mut fbb, &trace);
+        let event_list = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+        let mut expected = serde_json::Map::new();
+        for channel in 0..3 {
+            let events = event_list
+                .channel()
+                .unwrap()
+                .iter()
+                .zip(event_list.time().unwrap().iter())
+                .zip(event_list.voltage().unwrap().iter())
+                .filter(|((c, _), _)| *c == channel)
+                .map(|((_, time), intensity)| {
+                    serde_json::json!({ "time": time, "intensity": intensity })
+                })
+                .collect::<Vec<_>>();
+            expected.insert(channel.to_string(), events.into());
+        }
+        assert_eq!(response, serde_json::Value::Object(expected));
+        // Channels in which no events are found are included.
+        assert_eq!(response["1"], serde_json::json!([]));
+        assert!(!response["2"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let opts = DebugApiOpts {
+            debug_api_address: Some(address),
+            debug_api_max_body_bytes: 16,
+        };
+        let (_, debug_api) = debug_api(&opts);
+        tokio::spawn(debug_api.serve(listener));
+
+        let (status, body) = post(address, "/process", &create_message()).await;
+        assert_eq!(status, "HTTP/1.1 413 Content Too Large");
+        assert!(body.contains("exceeds the limit of 16 bytes"), "{body}");
+
+        let (status, _) = post(address, "/process", b"not a trace").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        let (status, _) = post(address, "/healthz", b"").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn posted_messages_follow_replaced_settings() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let opts = DebugApiOpts {
+            debug_api_address: Some(address),
+            debug_api_max_body_bytes: 1024,
+        };
+        let (active, debug_api) = debug_api(&opts);
+        tokio::spawn(debug_api.serve(listener));
+
+        let num_events = |body: &str| {
+            let response: serde_json::Value = serde_json::from_str(body).unwrap();
+            response
+                .as_object()
+                .unwrap()
+                .values()
+                .map(|events| events.as_array().unwrap().len())
+                .sum::<usize>()
+        };
+        let (_, body) = post(address, "/process", &create_message()).await;
+        assert_ne!(num_events(&body), 0);
+
+        // No voltage of the message reaches the replaced threshold.
+        active.apply(threshold_config(20.0).as_bytes()).unwrap();
+        let (status, body) = post(address, "/process", &create_message()).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(num_events(&body), 0);
+    }
+}
@@ -0,0 +1,386 @@
+//! Serves liveness and readiness endpoints, for use by orchestrators such as Kubernetes, see [Health].
+use clap::Args;
+use rdkafka::producer::{FutureProducer, Producer};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, warn};
+
+/// How often the main loop, and [Health::check_broker], update the health.
+pub(crate) const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for the broker when checking the producer can reach it.
+const BROKER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request read, the request line must be contained within this.
+const MAX_REQUEST_LENGTH: usize = 1024;
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct HealthOpts {
+    /// If set, `/healthz` (liveness) and `/readyz` (readiness) endpoints are served on this address.
+    #[clap(long)]
+    health_address: Option<SocketAddr>,
+
+    /// The component is not live once this many consecutive Kafka errors have been received.
+    #[clap(long, default_value = "10")]
+    live_max_consecutive_errors: usize,
+
+    /// The component is not ready if no trace message has been processed for this many seconds.
+    #[clap(long, default_value = "60")]
+    ready_staleness_s: u64,
+
+    /// The component is not ready if the send eventlist buffer has been saturated for this many seconds.
+    #[clap(long, default_value = "10")]
+    ready_saturation_s: u64,
+}
+
+impl HealthOpts {
+    pub(crate) fn health_address(&self) -> Option<SocketAddr> {
+        self.health_address
+    }
+}
+
+/// The observations of the main loop from which liveness and readiness are determined.
+struct HealthState {
+    consecutive_kafka_errors: usize,
+    /// Whether the consumer has been assigned any partitions.
+    assigned: bool,
+    /// Whether the producer reached the broker when last checked, or absent if it has not been checked.
+    broker_reachable: Option<bool>,
+    /// The time a trace message was last processed, or the component started if none has been.
+    last_processed: Instant,
+    /// The time since which the send eventlist buffer has been saturated, if it is.
+    saturated_since: Option<Instant>,
+}
+
+/// The shared state from which liveness and readiness are determined.
+///
+/// This is updated by the main loop, and read by [Self::serve], so can be cheaply cloned.
+#[derive(Clone)]
+pub(crate) struct Health {
+    state: Arc<Mutex<HealthState>>,
+    max_consecutive_errors: usize,
+    staleness: Duration,
+    saturation: Duration,
+}
+
+impl Health {
+    pub(crate) fn new(opts: &HealthOpts) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(HealthState {
+                consecutive_kafka_errors: 0,
+                assigned: false,
+                broker_reachable: None,
+                last_processed: Instant::now(),
+                saturated_since: None,
+            })),
+            max_consecutive_errors: opts.live_max_consecutive_errors,
+            staleness: Duration::from_secs(opts.ready_staleness_s),
+            saturation: Duration::from_secs(opts.ready_saturation_s),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HealthState> {
+        self.state
+            .lock()
+            .expect("The state is never locked across a panic, this should never fail.")
+    }
+
+    /// Records that a trace message has been received and processed.
+    pub(crate) fn message_processed(&self) {
+        let mut state = self.lock();
+        state.consecutive_kafka_errors = 0;
+        state.last_processed = Instant::now();
+    }
+
+    /// Records that the consumer received an error instead of a message.
+    pub(crate) fn kafka_error(&self) {
+        self.lock().consecutive_kafka_errors += 1;
+    }
+
+    /// Records whether the consumer has been assigned any partitions.
+    pub(crate) fn set_assigned(&self, assigned: bool) {
+        self.lock().assigned = assigned;
+    }
+
+    fn set_broker_reachable(&self, reachable: bool) {
+        self.lock().broker_reachable = Some(reachable);
+    }
+
+    /// Records the occupancy of the send eventlist buffer.
+    /// The buffer is saturated if less than a tenth of its capacity remains.
+    /// # Parameters
+    /// - capacity: the number of further event lists which can be buffered.
+    /// - max_capacity: the size of the buffer.
+    pub(crate) fn set_send_buffer_capacity(&self, capacity: usize, max_capacity: usize) {
+        let saturated = capacity * 10 < max_capacity;
+        let mut state = self.lock();
+        match (saturated, state.saturated_since) {
+            (true, None) => state.saturated_since = Some(Instant::now()),
+            (false, Some(_)) => state.saturated_since = None,
+            _ => {}
+        }
+    }
+
+    /// Returns whether the component is live, and the body describing why.
+    fn liveness(&self) -> (bool, String) {
+        let errors = self.lock().consecutive_kafka_errors;
+        let live = errors < self.max_consecutive_errors;
+        (
+            live,
+            format!(r#"{{"live":{live},"consecutive_kafka_errors":{errors}}}"#),
+        )
+    }
+
+    /// Returns whether the component is ready at `now`, and the body describing why.
+    fn readiness(&self, now: Instant) -> (bool, String) {
+        let state = self.lock();
+        let since_processed = now.saturating_duration_since(state.last_processed);
+        let saturated_for = state
+            .saturated_since
+            .map(|since| now.saturating_duration_since(since));
+        let broker_reachable = state.broker_reachable.unwrap_or_default();
+        let ready = state.assigned
+            && broker_reachable
+            && since_processed < self.staleness
+            && saturated_for.is_none_or(|saturated_for| saturated_for < self.saturation);
+        (
+            ready,
+            format!(
+                r#"{{"ready":{ready},"assigned":{},"broker_reachable":{broker_reachable},"seconds_since_last_message":{},"send_buffer_saturated":{}}}"#,
+                state.assigned,
+                since_processed.as_secs(),
+                saturated_for.is_some()
+            ),
+        )
+    }
+
+    /// Returns the status line and body of the response to a request for `path`.
+    fn respond(&self, path: &str) -> (&'static str, String) {
+        let (healthy, body) = match path {
+            "/healthz" => self.liveness(),
+            "/readyz" => self.readiness(Instant::now()),
+            _ => return ("404 Not Found", r#"{"error":"not found"}"#.to_owned()),
+        };
+        if healthy {
+            ("200 OK", body)
+        } else {
+            ("503 Service Unavailable", body)
+        }
+    }
+
+    /// Responds to each connection made to `listener`, this never returns.
+    pub(crate) async fn serve(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    let health = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = health.handle(stream).await {
+                            debug!("Health request from {address} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Cannot accept health connection: {e}"),
+            }
+        }
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buffer = [0; MAX_REQUEST_LENGTH];
+        while !request.windows(2).any(|line_end| line_end == b"\r\n")
+            && request.len() < MAX_REQUEST_LENGTH
+        {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(buffer.get(..read).unwrap_or_default());
+        }
+        // The request line is of the form "GET /path HTTP/1.1".
+        let request = String::from_utf8_lossy(&request);
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        let (status, body) = self.respond(path);
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    /// Checks whether `producer` can reach the broker every `interval`, this never returns.
+    pub(crate) async fn check_broker(self, producer: FutureProducer, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let producer = producer.clone();
+            let reachable = tokio::task::spawn_blocking(move || {
+                producer
+                    .client()
+                    .fetch_metadata(None, BROKER_TIMEOUT)
+                    .is_ok()
+            })
+            .await
+            .unwrap_or_default();
+            self.set_broker_reachable(reachable);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health() -> Health {
+        Health::new(&HealthOpts {
+            health_address: None,
+            live_max_consecutive_errors: 3,
+            ready_staleness_s: 60,
+            ready_saturation_s: 10,
+        })
+    }
+
+    /// Returns a health whose consumer is assigned, and whose producer can reach the broker.
+    fn ready_health() -> Health {
+        let health = health();
+        health.set_assigned(true);
+        health.set_broker_reachable(true);
+        health.message_processed();
+        health
+    }
+
+    fn later(seconds: u64) -> Instant {
+        Instant::now() + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn consecutive_errors_end_liveness() {
+        let health = health();
+        assert_eq!(
+            health.respond("/healthz"),
+            (
+                "200 OK",
+                r#"{"live":true,"consecutive_kafka_errors":0}"#.to_owned()
+            )
+        );
+        for _ in 0..3 {
+            health.kafka_error();
+        }
+        assert_eq!(
+            health.respond("/healthz"),
+            (
+                "503 Service Unavailable",
+                r#"{"live":false,"consecutive_kafka_errors":3}"#.to_owned()
+            )
+        );
+        health.message_processed();
+        assert!(health.liveness().0);
+    }
+
+    #[test]
+    fn ready_when_assigned_reachable_and_fresh() {
+        let health = ready_health();
+        assert_eq!(
+            health.respond("/readyz"),
+            (
+                "200 OK",
+                r#"{"ready":true,"assigned":true,"broker_reachable":true,"seconds_since_last_message":0,"send_buffer_saturated":false}"#.to_owned()
+            )
+        );
+
+        health.set_assigned(false);
+        assert_eq!(
+            health.respond("/readyz"),
+            (
+                "503 Service Unavailable",
+                r#"{"ready":false,"assigned":false,"broker_reachable":true,"seconds_since_last_message":0,"send_buffer_saturated":false}"#.to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn unchecked_broker_is_not_ready() {
+        let health = health();
+        health.set_assigned(true);
+        assert!(!health.readiness(Instant::now()).0);
+        health.set_broker_reachable(false);
+        assert!(!health.readiness(Instant::now()).0);
+    }
+
+    #[test]
+    fn stale_messages_end_readiness() {
+        let health = ready_health();
+        assert!(health.readiness(later(59)).0);
+        let (ready, body) = health.readiness(later(61));
+        assert!(!ready);
+        assert!(
+            body.contains(r#""seconds_since_last_message":61"#),
+            "{body}"
+        );
+    }
+
+    #[test]
+    fn prolonged_saturation_ends_readiness() {
+        let health = ready_health();
+        health.set_send_buffer_capacity(10, 100);
+        assert!(health.lock().saturated_since.is_none());
+
+        health.set_send_buffer_capacity(9, 100);
+        let (ready, body) = health.readiness(Instant::now());
+        assert!(ready);
+        assert!(body.contains(r#""send_buffer_saturated":true"#), "{body}");
+
+        // Remaining saturated does not reset the time since which it has been.
+        health.set_send_buffer_capacity(0, 100);
+        assert!(health.readiness(later(9)).0);
+        assert!(!health.readiness(later(11)).0);
+
+        health.set_send_buffer_capacity(100, 100);
+        assert!(health.readiness(later(11)).0);
+    }
+
+    #[tokio::test]
+    async fn endpoints_are_served() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let health = ready_health();
+        tokio::spawn(health.clone().serve(listener));
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/healthz").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with(r#"{"live":true,"consecutive_kafka_errors":0}"#));
+
+        health.set_assigned(false);
+        let response = get("/readyz").await;
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{response}"
+        );
+        assert!(response.ends_with(r#""send_buffer_saturated":false}"#));
+
+        let response = get("/metrics").await;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{response}"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Determines the key, and optionally the partition, of each event list message, see [EventListKey] and [DigitiserPartitioner].
+use digital_muon_common::{DigitizerId, ids::DigitiserName};
+use rdkafka::producer::FutureProducer;
+use std::{
+    borrow::Cow,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// The key given to every event list message by previous releases.
+pub(crate) const LEGACY_KEY: &str = "Digitiser Events List";
+
+/// How long to wait for the metadata of the event topic.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Determines the key of each event list message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EventListKey {
+    /// The key encodes the id of the digitiser, so the event lists of each digitiser are hashed to the same partition,
+    /// and so remain in order.
+    Digitiser,
+    /// Every event list has the key [LEGACY_KEY], so the partition of each is arbitrary.
+    Legacy,
+}
+
+impl EventListKey {
+    /// Returns how event lists are keyed, given the `--key-by-digitiser` and `--legacy-key` options.
+    pub(crate) fn new(key_by_digitiser: bool, legacy_key: bool) -> Self {
+        if key_by_digitiser && !legacy_key {
+            Self::Digitiser
+        } else {
+            Self::Legacy
+        }
+    }
+
+    /// Returns the key of an event list of digitiser `digitiser_id`.
+    pub(crate) fn key(&self, digitiser_id: DigitizerId) -> Cow<'static, str> {
+        match self {
+            Self::Digitiser => Cow::Owned(DigitiserName(digitiser_id).to_string()),
+            Self::Legacy => Cow::Borrowed(LEGACY_KEY),
+        }
+    }
+}
+
+/// Returns the partition of an event list of digitiser `digitiser_id`, in a topic with `partition_count` partitions.
+/// If the number of partitions is unknown, there is no partition, and the producer's partitioner is used.
+pub(crate) fn partition_for(
+    digitiser_id: DigitizerId,
+    partition_count: Option<i32>,
+) -> Option<i32> {
+    partition_count
+        .filter(|&count| count > 0)
+        .map(|count| i32::from(digitiser_id) % count)
+}
+
+/// Assigns each event list to the partition of the event topic given by its digitiser id, see [partition_for].
+///
+/// The number of partitions is fetched when this is created, and refetched in the background by [Self::refresh],
+/// which should be called if the partition of an event list is unknown to the producer.
+pub(crate) struct DigitiserPartitioner {
+    topic: Arc<str>,
+    /// The number of partitions of [Self::topic], or zero if unknown.
+    partition_count: Arc<AtomicI32>,
+    /// True whilst the number of partitions is being refetched, so that only one refetch is made at a time.
+    refreshing: Arc<AtomicBool>,
+}
+
+impl DigitiserPartitioner {
+    pub(crate) fn new(producer: &FutureProducer, topic: &str) -> Self {
+        let topic = Arc::<str>::from(topic);
+        let partition_count = Arc::new(AtomicI32::new(fetch_partition_count(producer, &topic)));
+        Self {
+            topic,
+            partition_count,
+            refreshing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Returns the partition of an event list of digitiser `digitiser_id`, if the number of partitions is known.
+    pub(crate) fn partition(&self, digitiser_id: DigitizerId) -> Option<i32> {
+        partition_for(
+            digitiser_id,
+            Some(self.partition_count.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// Refetches the number of partitions of the event topic on a blocking thread, unless it is already being refetched.
+    /// Until the refetch completes, the number of partitions is unknown, so the producer's partitioner is used.
+    pub(crate) fn refresh(&self, producer: &FutureProducer) {
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.partition_count.store(0, Ordering::Relaxed);
+        let producer = producer.clone();
+        let topic = self.topic.clone();
+        let partition_count = self.partition_count.clone();
+        let refreshing = self.refreshing.clone();
+        tokio::task::spawn_blocking(move || {
+            partition_count.store(fetch_partition_count(&producer, &topic), Ordering::Relaxed);
+            refreshing.store(false, Ordering::Release);
+        });
+    }
+}
+
+/// Fetches the number of partitions of `topic`, returning zero if this fails.
+fn fetch_partition_count(producer: &FutureProducer, topic: &str) -> i32 {
+    let partition_count = producer
+        .client()
+        .fetch_metadata(Some(topic), METADATA_TIMEOUT)
+        .map_err(|e| e.to_string())
+        .and_then(|metadata| {
+            metadata
+                .topics()
+                .iter()
+                .find(|metadata| metadata.name() == topic)
+                .map(|metadata| metadata.partitions().len() as i32)
+                .filter(|&count| count > 0)
+                .ok_or_else(|| format!("Topic {topic} has no partitions"))
+        });
+    match partition_count {
+        Ok(count) => {
+            info!("Event topic {topic} has {count} partitions");
+            count
+        }
+        Err(e) => {
+            warn!("Could not fetch the partitions of event topic: {e}");
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_encode_digitiser_id() {
+        let key = EventListKey::new(true, false);
+        assert_eq!(key, EventListKey::Digitiser);
+        assert_eq!(key.key(0), "digitiser-0");
+        assert_eq!(key.key(37), "digitiser-37");
+        assert_eq!(key.key(DigitizerId::MAX), "digitiser-255");
+    }
+
+    #[test]
+    fn legacy_key_is_static() {
+        for key in [
+            EventListKey::new(false, false),
+            EventListKey::new(true, true),
+        ] {
+            assert_eq!(key, EventListKey::Legacy);
+            assert_eq!(key.key(0), LEGACY_KEY);
+            assert_eq!(key.key(37), LEGACY_KEY);
+        }
+    }
+
+    #[test]
+    fn partition_is_digitiser_id_modulo_partition_count() {
+        assert_eq!(partition_for(3, Some(8)), Some(3));
+        assert_eq!(partition_for(7, Some(8)), Some(7));
+        // More digitisers than partitions.
+        assert_eq!(partition_for(8, Some(8)), Some(0));
+        assert_eq!(partition_for(13, Some(4)), Some(1));
+        assert_eq!(partition_for(DigitizerId::MAX, Some(1)), Some(0));
+        assert_eq!(partition_for(DigitizerId::MAX, Some(7)), Some(3));
+    }
+
+    #[test]
+    fn unknown_partition_count_has_no_partition() {
+        assert_eq!(partition_for(3, None), None);
+        assert_eq!(partition_for(3, Some(0)), None);
+        assert_eq!(partition_for(3, Some(-1)), None);
+    }
+}
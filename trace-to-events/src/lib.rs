@@ -0,0 +1,55 @@
+//! The event formation algorithms of the Trace to Events component.
+//!
+//! These are used by the `trace-to-events` binary, and by the trace viewer to run detectors on individual traces.
+mod baselines;
+#[cfg(feature = "bench-utils")]
+pub mod bench_utils;
+mod builder_pool;
+mod channels;
+mod parameters;
+mod processing;
+mod pulse_detection;
+mod self_test;
+#[cfg(test)]
+mod test_data;
+#[cfg(test)]
+mod test_subscriber;
+mod timestamps;
+#[cfg(any(test, feature = "bench-utils"))]
+pub mod trace_generation;
+
+use const_format::concatcp;
+use digital_muon_common::metrics::names::METRIC_NAME_PREFIX;
+
+pub use baselines::{BaselineEstimate, ChannelBaseline, MessageBaselines};
+pub use builder_pool::{BuilderPool, PooledBuilder};
+pub use channels::MalformedChannelTrace;
+pub use parameters::{
+    AdaptiveThresholdDiscriminatorParameters, DEFAULT_DOWNSAMPLE_FACTOR, DerivativeEstimator,
+    DetectorConfig, DetectorSettings, DifferentialThresholdDiscriminatorParameters, EventMerge,
+    FixedThresholdDiscriminatorParameters, InvalidDetectorConfig, MergePolicy, Mode,
+    MultiscalingDetectorMethod, MultiscalingDetectorParameters, PeakHeightBasis, PeakHeightMode,
+    Polarity, SecondaryOutput, SmoothingDetectorParameters, TimeUnits, parse_mode,
+};
+pub use processing::{
+    DigitiserMessageProcessor, EmptyExpectedEventRate, ExpectedEventRate, MessageEvents,
+    TraceMessage, find_trace_events, message_failed,
+};
+pub use pulse_detection::Real;
+pub use self_test::{ChannelSummary, SelfTestError, check_summaries, summarise_channels};
+pub use timestamps::{BadTimestampPolicy, TimestampCheck, TimestampVerdict, reference_time};
+
+pub const EVENTS_FOUND_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "events_found");
+pub const SHORT_TRACES_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "short_channel_traces");
+pub const EVENTS_PER_FRAME_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "events_per_frame");
+pub const EVENT_RATE_ANOMALIES_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "event_rate_anomalies");
+pub const VETOED_PULSES_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "vetoed_pulses");
+pub const MERGED_EVENTS_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "merged_events");
+pub const CHANNEL_BASELINE_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "channel_baseline");
+pub const BAD_TIMESTAMPS_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "bad_timestamps");
+pub const STAGE_DURATION_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "stage_duration_seconds");
+
+/// The upper bounds, in seconds, of the buckets of the [STAGE_DURATION_METRIC] histogram.
+pub const STAGE_DURATION_BUCKETS: [f64; 12] = [
+    0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
@@ -5,69 +5,186 @@
 //! * Runs persistantly, and awaits broker messages issued by the DAQs.
 //! * Consumes digitisier trace messages, and applies the user specified event formation algorithm on it.
 //! * For each trace message, produces a digitiser event list message to an "event list" topic, specified by the user.
+//! * Optionally consumes aggregated frame trace messages from the same topic, and produces an aggregated frame event list message
+//!   for each to a "frame event list" topic, specified by the user.
 //!
-mod channels;
-mod parameters;
-mod processing;
-mod pulse_detection;
-#[cfg(test)]
-mod test_data;
+mod commit;
+mod config_reload;
+mod debug_api;
+mod health;
+mod keying;
+mod shutdown;
 
-use crate::processing::DigitiserMessageProcessor;
 use chrono::{DateTime, Utc};
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use commit::{CommitStrategy, DeliveryWatermarks};
+use config_reload::{
+    ACTIVE_CONFIG_HASH_METRIC, ActiveDetectorConfig, CONFIG_RELOADS_METRIC, DetectorConfigFollower,
+};
 use const_format::concatcp;
+use debug_api::{DebugApi, DebugApiOpts};
 use digital_muon_common::{
-    CommonKafkaOpts, Intensity, init_tracer,
+    Channel, CommonKafkaOpts, Intensity, ResilientConsumer, ResilientConsumerOpts, failure,
+    ids::{DIGITIZER_ID_LABEL, DigitiserName},
+    init_tracer,
     metrics::{
         component_info_metric,
-        failures::{self, FailureKind},
+        failures::FailureKind,
         messages_received::{self, MessageKind},
         names::{
-            FAILURES, LAST_MESSAGE_FRAME_NUMBER, LAST_MESSAGE_TIMESTAMP, MESSAGES_PROCESSED,
-            MESSAGES_RECEIVED, METRIC_NAME_PREFIX,
+            CONSUMER_DEGRADED_SECONDS, CONSUMER_RECONNECTS, FAILURES, LAST_MESSAGE_FRAME_NUMBER,
+            LAST_MESSAGE_TIMESTAMP, MESSAGES_PROCESSED, MESSAGES_RECEIVED, METRIC_NAME_PREFIX,
         },
     },
     record_metadata_fields_to_span,
-    tracer::{FutureRecordTracerExt, OptionalHeaderTracerExt, TracerEngine, TracerOptions},
+    seek::{ResolveOffsets, create_consumer_at_tail},
+    tracer::{
+        FrameCorrelation, FutureRecordTracerExt, LogFormat, OptionalHeaderTracerExt,
+        OtelSamplingOpts, SamplingDecision, SpanSampler, TracerEngine, TracerOptions,
+    },
 };
 use digital_muon_streaming_types::{
     FrameMetadata,
+    aat2_frame_assembled_analog_trace_v2_generated::{
+        FrameAssembledAnalogTraceMessage,
+        frame_assembled_analog_trace_message_buffer_has_identifier,
+        root_as_frame_assembled_analog_trace_message,
+    },
     dat2_digitizer_analog_trace_v2_generated::{
         DigitizerAnalogTraceMessage, digitizer_analog_trace_message_buffer_has_identifier,
         root_as_digitizer_analog_trace_message,
     },
     flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer},
 };
-use metrics::{counter, describe_counter, describe_gauge, gauge};
-use metrics_exporter_prometheus::PrometheusBuilder;
+use futures::{FutureExt, StreamExt, future::BoxFuture, stream::FuturesUnordered};
+use health::{HEALTH_CHECK_INTERVAL, Health, HealthOpts};
+use keying::{DigitiserPartitioner, EventListKey};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
 use miette::IntoDiagnostic;
-use parameters::{DetectorSettings, Mode, Polarity};
 use rdkafka::{
-    Message,
-    consumer::{CommitMode, Consumer},
+    Message, Offset, TopicPartitionList,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    error::{KafkaResult, RDKafkaErrorCode},
     message::BorrowedMessage,
     producer::{DeliveryFuture, FutureProducer, FutureRecord},
 };
-use std::net::SocketAddr;
+use shutdown::{
+    PUSHGATEWAY_TIMEOUT, ShutdownOpts, ShutdownSignal, drain_producer_task, push_metrics,
+};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 use tokio::{
     select,
-    signal::unix::{Signal, SignalKind, signal},
-    sync::mpsc::{Receiver, Sender, error::TrySendError},
+    sync::{
+        mpsc::{Receiver, Sender, UnboundedSender, error::TrySendError},
+        oneshot,
+    },
     task::JoinHandle,
 };
+use trace_to_events::{
+    BAD_TIMESTAMPS_METRIC, BadTimestampPolicy, BaselineEstimate, BuilderPool,
+    CHANNEL_BASELINE_METRIC, DEFAULT_DOWNSAMPLE_FACTOR, DetectorConfig, DetectorSettings,
+    DigitiserMessageProcessor, EVENT_RATE_ANOMALIES_METRIC, EVENTS_FOUND_METRIC,
+    EVENTS_PER_FRAME_METRIC, EventMerge, ExpectedEventRate, MERGED_EVENTS_METRIC,
+    MalformedChannelTrace, MergePolicy, Mode, Polarity, SHORT_TRACES_METRIC,
+    STAGE_DURATION_BUCKETS, STAGE_DURATION_METRIC, SecondaryOutput, TimeUnits, TimestampCheck,
+    TimestampVerdict, TraceMessage, VETOED_PULSES_METRIC, check_summaries, message_failed,
+    parse_mode, reference_time, summarise_channels,
+};
 use tracing::{debug, error, info, info_span, instrument, trace, warn};
 
 type InstrumentedDeliveryFuture = tracing::instrument::Instrumented<DeliveryFuture>;
-type DigitiserEventListToBufferSender = Sender<InstrumentedDeliveryFuture>;
-type TrySendDigitiserEventListError = TrySendError<InstrumentedDeliveryFuture>;
+type DigitiserEventListToBufferSender = Sender<EventListDelivery>;
+type TrySendDigitiserEventListError = TrySendError<EventListDelivery>;
+/// Sends the partition and offset of each trace message whose event list has been delivered, see [CommitStrategy::AfterDelivery].
+type DeliveredOffsetSender = UnboundedSender<(i32, i64)>;
 
-const EVENTS_FOUND_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "events_found");
+const DELIVERY_LATENCY_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "delivery_latency_seconds");
+
+/// The upper bounds, in seconds, of the buckets of the [DELIVERY_LATENCY_METRIC] histogram.
+const DELIVERY_LATENCY_BUCKETS: [f64; 10] =
+    [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0];
+
+/// The key of each aggregated frame event list message, which is that given by the digitiser aggregator to those it produces.
+const FRAME_EVENT_LIST_KEY: &str = "Frame Events List";
+
+/// How long the self-test waits for the broker, and for each sample trace message, see [Cli::self_test_from_topic].
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the broker, and for the latest message of the config topic when the component starts, see [Cli::config_topic].
+const CONFIG_TOPIC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A message passed to the producer, which is sent to the producer task to await its delivery, see [produce_to_kafka].
+struct EventListDelivery {
+    /// Resolves once the broker acknowledges the message, or it fails to be delivered.
+    future: InstrumentedDeliveryFuture,
+    /// When the message was passed to the producer, from which its delivery latency is measured.
+    enqueued: Instant,
+    /// If present, the partition and offset of the trace message, and where to send them once the message is delivered, see [report_delivery].
+    delivered_offset: Option<((i32, i64), Option<DeliveredOffsetSender>)>,
+}
+
+impl EventListDelivery {
+    /// Creates a delivery of a message just passed to the producer, instrumented by the current span.
+    fn new(future: DeliveryFuture) -> Self {
+        Self {
+            future: tracing::Instrument::instrument(future, tracing::Span::current()),
+            enqueued: Instant::now(),
+            delivered_offset: None,
+        }
+    }
+
+    /// Reports the partition and offset of the trace message the event list was produced from, once it is delivered.
+    fn with_delivered_offset(
+        self,
+        partition_offset: (i32, i64),
+        delivered_offsets: Option<DeliveredOffsetSender>,
+    ) -> Self {
+        Self {
+            delivered_offset: Some((partition_offset, delivered_offsets)),
+            ..self
+        }
+    }
+}
+
+impl IntoFuture for EventListDelivery {
+    type Output = ();
+    type IntoFuture = BoxFuture<'static, ()>;
+
+    /// Awaits the delivery and records the outcome, see [produce_eventlist_to_kafka].
+    fn into_future(self) -> Self::IntoFuture {
+        let delivery = produce_eventlist_to_kafka(self.future, self.enqueued);
+        match self.delivered_offset {
+            Some((partition_offset, delivered_offsets)) => {
+                report_delivery(delivery, partition_offset, delivered_offsets).boxed()
+            }
+            None => delivery.map(|_| ()).boxed(),
+        }
+    }
+}
 
 struct SenderParameters<'a> {
     event_topic: &'a str,
+    /// If present, aggregated frame trace messages are processed, and their event lists published here, see [Cli::frame_event_topic].
+    frame_event_topic: Option<&'a str>,
+    /// If present, the secondary detector's event lists are published here, see [SecondaryOutput::Topic].
+    secondary_event_topic: Option<&'a str>,
+    /// If present, the baselines estimated for each trace message are published here as JSON.
+    baselines_topic: Option<&'a str>,
     sender: &'a DigitiserEventListToBufferSender,
     producer: &'a FutureProducer,
+    /// If present, the offset of each message is sent here once its event list is delivered.
+    delivered_offsets: Option<&'a DeliveredOffsetSender>,
+    event_list_key: EventListKey,
+    /// If present, each event list is published to the partition of `event_topic` given by its digitiser id.
+    partitioner: Option<&'a DigitiserPartitioner>,
+    /// The builders in which event lists are built, each is returned once its event list is passed to the producer.
+    builder_pool: &'a BuilderPool,
 }
 
 /// [clap] derived struct to handle command line parameters.
@@ -89,6 +206,18 @@ struct Cli {
     #[clap(long)]
     event_topic: String,
 
+    /// If set, every partition of this topic is consumed by each instance, outside of the consumer group, and each message on it
+    /// replaces the settings of the primary detector, those given by the subcommand, `polarity`, `baseline`, `downsample-factor`,
+    /// `legacy-time-alignment` and `time-units`, for every subsequent trace message. The latest message is applied on startup.
+    /// Each message is a JSON object of the settings, as described in the README. Invalid settings are logged and ignored.
+    #[clap(long)]
+    config_topic: Option<String>,
+
+    /// If set, aggregated frame trace messages, assembled from the traces of every digitiser in a frame, are also consumed from `trace-topic`,
+    /// and an aggregated frame event list message is published to this topic for each. Otherwise they are counted as unexpected messages.
+    #[clap(long)]
+    frame_event_topic: Option<String>,
+
     /// Determines whether events should register as positive or negative intensity
     #[clap(long)]
     polarity: Polarity,
@@ -97,11 +226,128 @@ struct Cli {
     #[clap(long, default_value = "0")]
     baseline: Intensity,
 
+    /// If greater than one, each block of this many samples is averaged before the detector is applied, reducing the processing required of oversampled traces.
+    /// The detector's durations, cool-offs and window sizes are then in blocks, rather than samples, though event times remain in sample time units.
+    #[clap(long, default_value_t = DEFAULT_DOWNSAMPLE_FACTOR, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    downsample_factor: usize,
+
+    /// If set, the smoothing detector, including as the method of the multiscaling detector, reports event times one sample
+    /// later than the pulses they are found in, as in previous releases. This is provided for comparison, and will be removed in the next release.
+    #[clap(long)]
+    legacy_time_alignment: bool,
+
+    /// The units of the detectors' durations and cool-offs. If `samples`, they are numbers of samples, whatever the sample rate of the trace.
+    /// If `ns`, they are in nanoseconds, and converted to numbers of samples, rounding up, with the sample rate of each trace message.
+    #[clap(long, default_value = "samples")]
+    time_units: TimeUnits,
+
+    /// If set, consecutive events found in a channel trace whose times differ by less than this dead time are merged into one,
+    /// at the time of the first, as they are physically one pulse. This is in the units given by `time-units`, though in samples of the trace,
+    /// rather than blocks, if it is downsampled. Events are merged after they are found by any detector, including the secondary detector.
+    #[clap(long)]
+    merge_dead_time: Option<usize>,
+
+    /// Determines the intensity of each merged event, see `merge-dead-time`.
+    /// If `max`, it is the greatest of the merged events' intensities, if `sum` their sum, and if `first` that of the first.
+    #[clap(long, value_enum, default_value_t = MergePolicy::Max, requires = "merge_dead_time")]
+    merge_policy: MergePolicy,
+
+    /// If set, a second detector is applied to every channel trace alongside the one given by the subcommand, so the two can be compared.
+    /// This is a detector subcommand and its options, for instance `--secondary-mode "fixed-threshold-discriminator --threshold 10"`.
+    /// The secondary detector has the same polarity, baseline and downsample factor as the primary.
+    #[clap(long, value_parser = parse_mode)]
+    secondary_mode: Option<Mode>,
+
+    /// Determines how the events of the secondary detector are published.
+    /// If `topic`, they are published in their own event list messages to `secondary-event-topic`.
+    /// If `tagged`, they are merged into the event list messages with a `detector` vector giving the detector of each event,
+    /// which downstream components ignore, so they count the events of both detectors.
+    #[clap(long, value_enum, default_value_t = SecondaryOutput::Topic, requires = "secondary_mode")]
+    secondary_output: SecondaryOutput,
+
+    /// Topic to publish the secondary detector's event messages to, required if `secondary-mode` is set and `secondary-output` is `topic`.
+    #[clap(long)]
+    secondary_event_topic: Option<String>,
+
+    /// If set, the baseline of each channel trace is estimated from this many samples at its start, which should precede any pulse.
+    /// The estimates are recorded to the `channel_baseline` metric and the `baseline` field of each channel's span, but do not change
+    /// the baseline used by the detectors.
+    #[clap(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    baseline_estimate_samples: Option<usize>,
+
+    /// The weight given to each sample by the exponential moving average that estimates the baseline, see `baseline-estimate-samples`.
+    #[clap(long, default_value = "0.1", requires = "baseline_estimate_samples")]
+    baseline_smoothing_factor: f64,
+
+    /// If set, the baselines estimated for the channels of each trace message are published to this topic as a JSON payload.
+    #[clap(long, requires = "baseline_estimate_samples")]
+    publish_baselines_topic: Option<String>,
+
     /// Size of the send eventlist buffer.
     /// If this limit is exceeded, the component will exit.
     #[clap(long, default_value = "1024")]
     send_eventlist_buffer_size: usize,
 
+    /// The most flatbuffer builders kept for reuse once their event lists have been passed to the producer,
+    /// so that their buffers are not reallocated for each message. If zero, a new builder is created for each message.
+    #[clap(long, default_value = "1")]
+    builder_pool_size: usize,
+
+    /// The maximum number of event list deliveries to await acknowledgement of concurrently.
+    #[clap(long, default_value = "64", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_inflight_acks: usize,
+
+    /// Determines when the offset of each trace message is committed.
+    /// If `immediate`, it is committed once its event list is queued for delivery. If `after-delivery`, it is only committed
+    /// once its event list, and those of all earlier messages in its partition, have been delivered, so no message is lost if the component stops.
+    #[clap(long, value_enum, default_value_t = CommitStrategy::Immediate)]
+    commit_strategy: CommitStrategy,
+
+    /// If true, each event list message is keyed by its digitiser id, as `digitiser-<ID>`, so the event lists of each digitiser
+    /// are published to the same partition, and so remain in order.
+    #[clap(long, default_value_t = true, action = ArgAction::Set)]
+    key_by_digitiser: bool,
+
+    /// If set, every event list message is keyed `Digitiser Events List`, as in previous releases.
+    #[clap(long, conflicts_with = "key_by_digitiser")]
+    legacy_key: bool,
+
+    /// If set, each event list message is published to the partition of `event-topic` given by its digitiser id modulo the number of partitions.
+    /// The number of partitions is fetched at startup, and refetched in the background if the partition of a message is unknown to the producer.
+    #[clap(long)]
+    partition_by_digitiser: bool,
+
+    /// If set, a channel finding fewer than this many events in a frame is warned of, and counted as an anomaly.
+    #[clap(long)]
+    min_expected_events_per_frame: Option<usize>,
+
+    /// If set, a channel finding more than this many events in a frame is warned of, and counted as an anomaly.
+    #[clap(long)]
+    max_expected_events_per_frame: Option<usize>,
+
+    /// If set, a trace message whose timestamp differs by more than this many seconds from the timestamp of its Kafka message,
+    /// or the current time if it has none, is handled according to `bad-timestamp-policy`.
+    #[clap(long)]
+    max_timestamp_skew_s: Option<u32>,
+
+    /// Determines how a trace message whose timestamp is implausible, see `max-timestamp-skew-s`, is handled.
+    /// If `reject`, it is dropped and counted as a failure. If `rewrite`, its timestamp is replaced by that of its Kafka message,
+    /// or the current time, in its event list messages. If `forward`, it is processed as any other.
+    /// In each case it is counted by the `bad_timestamps` metric.
+    #[clap(long, value_enum, default_value_t = BadTimestampPolicy::Forward, requires = "max_timestamp_skew_s")]
+    bad_timestamp_policy: BadTimestampPolicy,
+
+    /// If set, the detector is applied to the serialised trace message in this file before any traces are consumed,
+    /// and the component exits if no events are found in any of its channels, or its pulses are opposite to the polarity.
+    #[clap(long)]
+    self_test: Option<PathBuf>,
+
+    /// If set, the detector is applied to the latest this many trace messages of the trace topic before any traces are consumed,
+    /// and the component exits if no events are found in any of their channels, or their pulses are opposite to the polarity.
+    /// The messages are consumed by the consumer group `<CONSUMER_GROUP>-self-test`, so the offsets of `consumer-group` are unaffected.
+    #[clap(long, conflicts_with = "self_test", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    self_test_from_topic: Option<usize>,
+
     /// Endpoint on which OpenMetrics flavour metrics are available
     #[clap(long, env, default_value = "127.0.0.1:9090")]
     observability_address: SocketAddr,
@@ -114,44 +360,120 @@ struct Cli {
     #[clap(long, default_value = "")]
     otel_namespace: String,
 
+    #[clap(flatten)]
+    otel_sampling: OtelSamplingOpts,
+
+    /// The format of the log lines written to stdout. JSON is only used if `otel-endpoint` is not given.
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    #[clap(flatten)]
+    health: HealthOpts,
+
+    #[clap(flatten)]
+    debug_api: DebugApiOpts,
+
+    #[clap(flatten)]
+    consumer_resilience: ResilientConsumerOpts,
+
+    #[clap(flatten)]
+    shutdown: ShutdownOpts,
+
     #[command(subcommand)]
     pub(crate) mode: Mode,
 }
 
+impl Cli {
+    /// Returns the settings of the primary detector given on the command line.
+    fn detector_config(&self) -> DetectorConfig {
+        DetectorConfig {
+            mode: self.mode.clone(),
+            polarity: self.polarity,
+            baseline: self.baseline,
+            downsample_factor: self.downsample_factor,
+            legacy_time_alignment: self.legacy_time_alignment,
+            time_units: self.time_units,
+        }
+    }
+
+    /// Returns the range of the number of events per frame expected of each channel given on the command line.
+    fn expected_event_rate(&self) -> ExpectedEventRate {
+        ExpectedEventRate {
+            min: self.min_expected_events_per_frame,
+            max: self.max_expected_events_per_frame,
+        }
+    }
+}
+
 #[tokio::main]
-async fn main() -> miette::Result<()> {
+async fn main() -> miette::Result<ExitCode> {
     let args = Cli::parse();
+    if args.secondary_mode.is_some()
+        && args.secondary_output == SecondaryOutput::Topic
+        && args.secondary_event_topic.is_none()
+    {
+        return Err(miette::miette!(
+            "--secondary-event-topic is required when --secondary-output is topic"
+        ));
+    }
 
-    let tracer = init_tracer!(TracerOptions::new(
-        args.otel_endpoint.as_deref(),
-        args.otel_namespace.clone()
-    ));
+    let tracer = init_tracer!(
+        TracerOptions::new(args.otel_endpoint.as_deref(), args.otel_namespace.clone())
+            .with_sampling(args.otel_sampling.clone())
+            .with_log_format(args.log_format)
+    );
+    let mut span_sampler = tracer.span_sampler();
 
     let kafka_opts = &args.common_kafka_options;
 
+    let detector_config = args.detector_config();
+    detector_config.validate().into_diagnostic()?;
+    args.expected_event_rate().validate().into_diagnostic()?;
+
+    let samples = fetch_self_test_samples(&args).await?;
+    if !samples.is_empty() {
+        run_self_test(&samples, &detector_config.settings())?;
+    }
+
     let client_config = digital_muon_common::generate_kafka_client_config(
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
+        None,
     );
 
     let producer: FutureProducer = client_config.create().into_diagnostic()?;
 
-    let consumer = digital_muon_common::create_default_consumer(
+    let mut consumer = ResilientConsumer::create_default(
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
         &args.consumer_group,
-        Some(&[args.trace_topic.as_str()]),
+        &[args.trace_topic.as_str()],
+        &args.consumer_resilience,
     )
     .into_diagnostic()?;
 
     // Install exporter and register metrics
     let builder = PrometheusBuilder::new();
-    builder
+    let (recorder, exporter) = builder
         .with_http_listener(args.observability_address)
-        .install()
+        .set_buckets_for_metric(
+            Matcher::Full(STAGE_DURATION_METRIC.to_owned()),
+            &STAGE_DURATION_BUCKETS,
+        )
+        .into_diagnostic()?
+        .set_buckets_for_metric(
+            Matcher::Full(DELIVERY_LATENCY_METRIC.to_owned()),
+            &DELIVERY_LATENCY_BUCKETS,
+        )
+        .into_diagnostic()?
+        .build()
         .into_diagnostic()?;
+    // The handle is kept so that a final snapshot of the metrics can be pushed on shutdown.
+    let metrics_handle = recorder.handle();
+    metrics::set_global_recorder(recorder).into_diagnostic()?;
+    tokio::spawn(exporter);
 
     describe_counter!(
         MESSAGES_RECEIVED,
@@ -181,109 +503,499 @@ async fn main() -> miette::Result<()> {
         metrics::Unit::Count,
         "Number of events found per channel"
     );
+    describe_gauge!(
+        EVENTS_PER_FRAME_METRIC,
+        "Number of events found in the last frame of each channel"
+    );
+    describe_gauge!(
+        CHANNEL_BASELINE_METRIC,
+        "Baseline estimated from the last trace of each channel"
+    );
+    describe_counter!(
+        EVENT_RATE_ANOMALIES_METRIC,
+        metrics::Unit::Count,
+        "Number of frames in which a channel found a number of events outside the expected range"
+    );
+    describe_histogram!(
+        DELIVERY_LATENCY_METRIC,
+        metrics::Unit::Seconds,
+        "Time from enqueuing an event list message to its delivery being acknowledged"
+    );
+    describe_histogram!(
+        STAGE_DURATION_METRIC,
+        metrics::Unit::Seconds,
+        "Time taken by each stage of processing a trace message: decoding it, detecting its events, and building the event list message"
+    );
+    describe_counter!(
+        CONSUMER_RECONNECTS,
+        metrics::Unit::Count,
+        "Number of times the consumer was recreated after repeated fatal errors"
+    );
+    describe_gauge!(
+        CONSUMER_DEGRADED_SECONDS,
+        metrics::Unit::Seconds,
+        "Total time the consumer received errors rather than messages"
+    );
+    describe_counter!(
+        SHORT_TRACES_METRIC,
+        metrics::Unit::Count,
+        "Number of channel traces too short for the detector to be applied"
+    );
+    describe_counter!(
+        BAD_TIMESTAMPS_METRIC,
+        metrics::Unit::Count,
+        "Number of trace messages whose timestamps were implausible, by the policy with which they were handled"
+    );
+    describe_counter!(
+        VETOED_PULSES_METRIC,
+        metrics::Unit::Count,
+        "Number of pulses discarded per channel for passing the veto threshold"
+    );
+    describe_counter!(
+        MERGED_EVENTS_METRIC,
+        metrics::Unit::Count,
+        "Number of events per channel merged into an earlier event for being within the dead time"
+    );
+    describe_counter!(
+        CONFIG_RELOADS_METRIC,
+        metrics::Unit::Count,
+        "Number of times the settings of the primary detector were replaced from the config topic"
+    );
+    describe_gauge!(
+        ACTIVE_CONFIG_HASH_METRIC,
+        "Fingerprint of the settings of the primary detector in use"
+    );
+
+    let (sender, shutdown_producer, producer_task_handle) =
+        create_producer_task(args.send_eventlist_buffer_size, args.max_inflight_acks);
 
-    let (sender, producer_task_handle) =
-        create_producer_task(args.send_eventlist_buffer_size).into_diagnostic()?;
+    let health = Health::new(&args.health);
+    if let Some(health_address) = args.health.health_address() {
+        let listener = tokio::net::TcpListener::bind(health_address)
+            .await
+            .into_diagnostic()?;
+        tokio::spawn(health.clone().serve(listener));
+        tokio::spawn(
+            health
+                .clone()
+                .check_broker(producer.clone(), HEALTH_CHECK_INTERVAL),
+        );
+    }
+    let mut health_check = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+
+    let active_detector_config = ActiveDetectorConfig::new(detector_config);
+    let config_consumer = match &args.config_topic {
+        Some(config_topic) => {
+            let config_consumer = create_consumer_at_tail(
+                &kafka_opts.broker,
+                &kafka_opts.username,
+                &kafka_opts.password,
+                &format!("{}-config", args.consumer_group),
+                &[config_topic.as_str()],
+                1,
+                CONFIG_TOPIC_TIMEOUT,
+            )
+            .into_diagnostic()?;
+            active_detector_config
+                .replay_latest(&config_consumer, config_topic, CONFIG_TOPIC_TIMEOUT)
+                .await
+                .into_diagnostic()?;
+            Some(config_consumer)
+        }
+        None => None,
+    };
+
+    if let Some(debug_api_address) = args.debug_api.debug_api_address() {
+        let listener = tokio::net::TcpListener::bind(debug_api_address)
+            .await
+            .into_diagnostic()?;
+        let debug_api = DebugApi::new(
+            &args.debug_api,
+            create_message_processor(&args),
+            DetectorConfigFollower::new(&active_detector_config),
+        );
+        tokio::spawn(debug_api.serve(listener));
+    }
 
-    // Is used to await any sigint signals
-    let mut sigint = signal(SignalKind::interrupt()).into_diagnostic()?;
+    // Is used to await any sigint or sigterm signals
+    let mut shutdown_signal = ShutdownSignal::new().into_diagnostic()?;
 
     component_info_metric("trace-to-events");
 
-    let mut message_processor = DigitiserMessageProcessor::new(
-        8,
-        &DetectorSettings {
-            polarity: &args.polarity,
-            baseline: args.baseline,
-            mode: &args.mode,
-        },
-    );
+    let mut message_processor = create_message_processor(&args);
+    let mut detector_config_follower = DetectorConfigFollower::new(&active_detector_config);
+    if let Some(max_timestamp_skew_s) = args.max_timestamp_skew_s {
+        message_processor = message_processor.with_timestamp_check(TimestampCheck {
+            max_skew: chrono::TimeDelta::seconds(max_timestamp_skew_s.into()),
+            policy: args.bad_timestamp_policy,
+        });
+    }
+    let (delivered_offsets, mut delivered_offsets_recv) = tokio::sync::mpsc::unbounded_channel();
+    let mut watermarks = DeliveryWatermarks::default();
+    let after_delivery = args.commit_strategy == CommitStrategy::AfterDelivery;
+    let partitioner = args
+        .partition_by_digitiser
+        .then(|| DigitiserPartitioner::new(&producer, &args.event_topic));
+    let builder_pool = BuilderPool::new(args.builder_pool_size);
     let sender_parameters = SenderParameters {
         event_topic: &args.event_topic,
+        frame_event_topic: args.frame_event_topic.as_deref(),
+        secondary_event_topic: args.secondary_event_topic.as_deref(),
+        baselines_topic: args.publish_baselines_topic.as_deref(),
         sender: &sender,
         producer: &producer,
+        delivered_offsets: after_delivery.then_some(&delivered_offsets),
+        event_list_key: EventListKey::new(args.key_by_digitiser, args.legacy_key),
+        partitioner: partitioner.as_ref(),
+        builder_pool: &builder_pool,
     };
     loop {
+        if let Err(e) = consumer.recreate_if_required() {
+            warn!("Could not recreate consumer: {e}");
+        }
         tokio::select! {
+            Some(msg) = recv_config(config_consumer.as_ref()) => match msg {
+                Ok(m) => active_detector_config.apply_message(&m),
+                Err(e) => warn!("Kafka error consuming detector settings: {e}"),
+            },
             msg = consumer.recv() => match msg {
                 Ok(m) => {
                     let span = info_span!("message_received");
                     m.headers().conditional_extract_to_span(tracer.use_otel(), &span);
                     let _guard = span.enter();
-                    process_kafka_message(
-                        &tracer,
+                    if after_delivery {
+                        watermarks.received(m.partition(), m.offset());
+                    }
+                    let queued = process_kafka_message(
+                        tracer.use_otel(),
+                        &mut span_sampler,
                         &sender_parameters,
                         &mut message_processor,
+                        Some(&mut detector_config_follower),
                         &m,
                     ).into_diagnostic()?;
+                    health.message_processed();
 
-                    consumer.commit_message(&m, CommitMode::Async).unwrap();
+                    if !after_delivery {
+                        consumer.commit_message(&m, CommitMode::Async).unwrap();
+                    } else if !queued {
+                        // The message produced no event list, so there is nothing to await.
+                        let offset = watermarks.resolved(m.partition(), m.offset());
+                        commit_offset(&consumer, &args.trace_topic, m.partition(), offset, CommitMode::Async);
+                    }
+                }
+                Err(e) => {
+                    warn!("Kafka error: {}", e);
+                    health.kafka_error();
                 }
-                Err(e) => warn!("Kafka error: {}", e)
             },
-            _ = sigint.recv() => {
-                //  Wait for the channel to close and
-                //  all pending production tasks to finish
-                producer_task_handle.await.into_diagnostic()?;
-                return Ok(());
+            _ = health_check.tick() => {
+                health.set_assigned(consumer.assignment().is_ok_and(|assignment| assignment.count() > 0));
+                health.set_send_buffer_capacity(sender.capacity(), sender.max_capacity());
+            },
+            Some((partition, offset)) = delivered_offsets_recv.recv() => {
+                let offset = watermarks.resolved(partition, offset);
+                commit_offset(&consumer, &args.trace_topic, partition, offset, CommitMode::Async);
+            },
+            signal = shutdown_signal.recv() => {
+                // No further messages are consumed, and any message received has already been processed.
+                info!("{signal} received, shutting down");
+                //  Close the channel, and wait for all pending production tasks to finish
+                if shutdown_producer.send(()).is_err() {
+                    warn!("Producer task ended before shutdown");
+                }
+                let outcome = drain_producer_task(producer_task_handle, args.shutdown.shutdown_timeout())
+                    .await
+                    .into_diagnostic()?;
+                // Builders are returned before their event lists are queued, so none are held by the flushed deliveries.
+                if builder_pool.outstanding() != 0 {
+                    warn!("{} flatbuffer builders not returned to the pool", builder_pool.outstanding());
+                }
+                if after_delivery {
+                    // Commit the offsets of event lists delivered whilst the channel was flushed.
+                    while let Ok((partition, offset)) = delivered_offsets_recv.try_recv() {
+                        let offset = watermarks.resolved(partition, offset);
+                        commit_offset(&consumer, &args.trace_topic, partition, offset, CommitMode::Sync);
+                    }
+                } else if let Err(e) = consumer.commit_consumer_state(CommitMode::Sync) {
+                    warn!("Failed to commit final offsets: {e}");
+                }
+                if let Some(pushgateway) = args.shutdown.metrics_pushgateway() {
+                    match tokio::time::timeout(PUSHGATEWAY_TIMEOUT, push_metrics(pushgateway, metrics_handle.render())).await {
+                        Ok(Ok(())) => info!("Final metrics pushed to {pushgateway}"),
+                        Ok(Err(e)) => warn!("Failed to push final metrics to {pushgateway}: {e}"),
+                        Err(_) => warn!("Timed out pushing final metrics to {pushgateway}"),
+                    }
+                }
+                return Ok(ExitCode::from(outcome.exit_code()));
+            }
+        }
+    }
+}
+
+/// Receives the next message of the config topic, see [Cli::config_topic], if it is consumed, otherwise never returns.
+async fn recv_config(
+    config_consumer: Option<&StreamConsumer>,
+) -> Option<KafkaResult<BorrowedMessage<'_>>> {
+    match config_consumer {
+        Some(config_consumer) => Some(config_consumer.recv().await),
+        None => std::future::pending().await,
+    }
+}
+
+/// Fetches the sample trace messages of the self-test, see [Cli::self_test] and [Cli::self_test_from_topic].
+///
+/// # Returns
+/// The payloads of the sample messages, which are empty if no self-test is requested.
+async fn fetch_self_test_samples(args: &Cli) -> miette::Result<Vec<Vec<u8>>> {
+    if let Some(path) = &args.self_test {
+        return Ok(vec![std::fs::read(path).into_diagnostic()?]);
+    }
+    let Some(num_messages) = args.self_test_from_topic else {
+        return Ok(Vec::new());
+    };
+
+    let kafka_opts = &args.common_kafka_options;
+    let consumer = digital_muon_common::create_default_consumer(
+        &kafka_opts.broker,
+        &kafka_opts.username,
+        &kafka_opts.password,
+        &format!("{}-self-test", args.consumer_group),
+        None,
+    )
+    .into_diagnostic()?;
+    let mut offsets = TopicPartitionList::new();
+    for partition in consumer
+        .fetch_partitions(&args.trace_topic, SELF_TEST_TIMEOUT)
+        .into_diagnostic()?
+    {
+        offsets
+            .add_partition_offset(
+                &args.trace_topic,
+                partition,
+                Offset::OffsetTail(num_messages as i64),
+            )
+            .into_diagnostic()?;
+    }
+    consumer.assign(&offsets).into_diagnostic()?;
+
+    let mut samples = Vec::with_capacity(num_messages);
+    while samples.len() < num_messages {
+        match tokio::time::timeout(SELF_TEST_TIMEOUT, consumer.recv()).await {
+            Ok(Ok(message)) => {
+                if let Some(payload) = message.payload()
+                    && digitizer_analog_trace_message_buffer_has_identifier(payload)
+                {
+                    samples.push(payload.to_vec());
+                }
             }
+            Ok(Err(e)) => warn!("Kafka error whilst fetching self-test samples: {e}"),
+            Err(_) => break,
         }
     }
+    if samples.is_empty() {
+        return Err(miette::miette!(
+            "No trace messages were received from {} for the self-test",
+            args.trace_topic
+        ));
+    }
+    Ok(samples)
+}
+
+/// Creates the processor which finds the events of each trace message, with the detectors and settings given by `args`.
+/// Timestamps are not checked, see [DigitiserMessageProcessor::with_timestamp_check].
+fn create_message_processor(args: &Cli) -> DigitiserMessageProcessor {
+    let mut message_processor = DigitiserMessageProcessor::new(
+        8,
+        &DetectorSettings {
+            polarity: &args.polarity,
+            baseline: args.baseline,
+            downsample_factor: args.downsample_factor,
+            legacy_time_alignment: args.legacy_time_alignment,
+            time_units: args.time_units,
+            mode: &args.mode,
+        },
+    )
+    .with_expected_event_rate(args.expected_event_rate());
+    if let Some(secondary_mode) = &args.secondary_mode {
+        message_processor = message_processor.with_secondary_detector(
+            &DetectorSettings {
+                polarity: &args.polarity,
+                baseline: args.baseline,
+                downsample_factor: args.downsample_factor,
+                legacy_time_alignment: args.legacy_time_alignment,
+                time_units: args.time_units,
+                mode: secondary_mode,
+            },
+            args.secondary_output,
+        );
+    }
+    if let Some(warm_up) = args.baseline_estimate_samples {
+        message_processor = message_processor.with_baseline_estimate(&BaselineEstimate {
+            warm_up,
+            smoothing_factor: args.baseline_smoothing_factor,
+        });
+    }
+    if let Some(dead_time) = args.merge_dead_time {
+        message_processor = message_processor.with_event_merge(&EventMerge {
+            dead_time,
+            policy: args.merge_policy,
+        });
+    }
+    message_processor
+}
+
+/// Applies the detector to the sample trace messages, logs a summary of each channel trace,
+/// and checks that the summaries are consistent with the detector settings, see [check_summaries].
+/// # Parameters
+/// - samples: the payloads of the sample trace messages.
+/// - settings: settings to use for the detector.
+fn run_self_test(samples: &[Vec<u8>], settings: &DetectorSettings) -> miette::Result<()> {
+    let messages = samples
+        .iter()
+        .map(|payload| root_as_digitizer_analog_trace_message(payload))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+    let summaries = summarise_channels(messages, settings);
+    for summary in &summaries {
+        info!("Self-test {summary}");
+    }
+    check_summaries(&summaries, settings.polarity).into_diagnostic()?;
+    info!("Self-test passed on {} channel traces", summaries.len());
+    Ok(())
+}
+
+/// Commits `offset` to the given partition of `topic`, if present, see [DeliveryWatermarks::resolved].
+/// # Parameters
+/// - consumer: the consumer whose group the offset is committed to.
+/// - topic: the topic from which the consumer receives trace messages.
+/// - partition: the partition of `topic` to commit to.
+/// - offset: the offset of the next message to consume from the partition, if it has advanced.
+/// - mode: whether the commit is awaited.
+fn commit_offset(
+    consumer: &StreamConsumer,
+    topic: &str,
+    partition: i32,
+    offset: Option<i64>,
+    mode: CommitMode,
+) {
+    let Some(offset) = offset else {
+        return;
+    };
+    let mut offsets = TopicPartitionList::new();
+    if let Err(e) = offsets
+        .add_partition_offset(topic, partition, Offset::Offset(offset))
+        .and_then(|_| consumer.commit(&offsets, mode))
+    {
+        warn!("Failed to commit offset {offset} of partition {partition}: {e}");
+    }
 }
 
 ///  This function wraps the [root_as_digitizer_analog_trace_message] function, allowing it to be instrumented.
+/// The time taken is recorded to the [STAGE_DURATION_METRIC] metric as the `decode` stage.
 #[instrument(skip_all, level = "trace", err(level = "warn"))]
 fn spanned_root_as_digitizer_analog_trace_message(
     payload: &[u8],
 ) -> Result<DigitizerAnalogTraceMessage<'_>, InvalidFlatbuffer> {
-    root_as_digitizer_analog_trace_message(payload)
+    let started = Instant::now();
+    let message = root_as_digitizer_analog_trace_message(payload);
+    histogram!(STAGE_DURATION_METRIC, "stage" => "decode").record(started.elapsed().as_secs_f64());
+    message
 }
 
-/// Extracts the payload of a Kafka message and passes it to [process_digitiser_trace_message]
+/// As [spanned_root_as_digitizer_analog_trace_message], but wraps the [root_as_frame_assembled_analog_trace_message] function.
+#[instrument(skip_all, level = "trace", err(level = "warn"))]
+fn spanned_root_as_frame_assembled_analog_trace_message(
+    payload: &[u8],
+) -> Result<FrameAssembledAnalogTraceMessage<'_>, InvalidFlatbuffer> {
+    let started = Instant::now();
+    let message = root_as_frame_assembled_analog_trace_message(payload);
+    histogram!(STAGE_DURATION_METRIC, "stage" => "decode").record(started.elapsed().as_secs_f64());
+    message
+}
+
+/// Extracts the payload of a Kafka message and passes it to [process_digitiser_trace_message],
+/// or to [process_frame_trace_message] if it is an aggregated frame trace message and these are processed, see [Cli::frame_event_topic].
 /// # Parameters
-/// - tracer: the tracer object, this is used to call the [TracerEngine::user_otel()] method, this could be replaced by a [bool].
+/// - use_otel: whether OpenTelemetry is used, see [TracerEngine::use_otel].
+/// - span_sampler: decides whether the message is traced in full, which is recorded to the `sampled` field of the span.
 /// - args: the user-specified Cli arguments.
 /// - sender: send channel which takes [DeliveryFuture] objects to dispatch.
 /// - producer: the Kafka producer which dispatches event lists to the broker.
+/// - detector_config: if present, replaces the primary detector of `message_processor` whenever its settings are replaced, see [Cli::config_topic].
 /// - m: the message.
 ///
+/// # Returns
+/// Whether an event list was queued for delivery.
+///
 /// [Span]: tracing::Span
-#[instrument(skip_all, level = "info", err(level = "warn"))]
+#[instrument(skip_all, level = "info", fields(sampled), err(level = "warn"))]
 fn process_kafka_message(
-    tracer: &TracerEngine,
+    use_otel: bool,
+    span_sampler: &mut SpanSampler,
     sender_parameters: &SenderParameters,
     message_processor: &mut DigitiserMessageProcessor,
-    message: &BorrowedMessage,
-) -> Result<(), TrySendDigitiserEventListError> {
+    detector_config: Option<&mut DetectorConfigFollower>,
+    message: &impl Message,
+) -> Result<bool, TrySendDigitiserEventListError> {
     debug!(
-        "key: '{:?}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
-        message.key(),
-        message.topic(),
-        message.partition(),
-        message.offset(),
-        message.timestamp()
+        key = ?message.key(),
+        topic = message.topic(),
+        partition = message.partition(),
+        offset = message.offset(),
+        timestamp = ?message.timestamp(),
+        "Received message"
     );
 
+    let sampling = span_sampler.decide();
+    tracing::Span::current().record("sampled", sampling.is_sampled(false));
     if let Some(payload) = message.payload() {
         if digitizer_analog_trace_message_buffer_has_identifier(payload) {
             match spanned_root_as_digitizer_analog_trace_message(payload) {
                 Ok(trace_message) => {
                     let kafka_timestamp_ms = message.timestamp().to_millis().unwrap_or(-1);
-                    process_digitiser_trace_message(
-                        tracer,
+                    return process_digitiser_trace_message(
+                        use_otel,
                         kafka_timestamp_ms,
+                        message.headers().and_then(FrameCorrelation::from_headers),
+                        (message.partition(), message.offset()),
                         sender_parameters,
                         message_processor,
+                        detector_config,
                         trace_message,
-                    )?
+                        sampling,
+                    );
                 }
                 Err(e) => {
+                    // The message has no child spans, besides that of the decoding, so is traced in full.
+                    tracing::Span::current().record("sampled", sampling.is_sampled(true));
                     warn!("Failed to parse message: {}", e);
-                    counter!(
-                        FAILURES,
-                        &[failures::get_label(FailureKind::UnableToDecodeMessage)]
-                    )
-                    .increment(1);
+                    failure!(FailureKind::UnableToDecodeMessage);
+                }
+            }
+        } else if frame_assembled_analog_trace_message_buffer_has_identifier(payload)
+            && let Some(frame_event_topic) = sender_parameters.frame_event_topic
+        {
+            match spanned_root_as_frame_assembled_analog_trace_message(payload) {
+                Ok(trace_message) => {
+                    let kafka_timestamp_ms = message.timestamp().to_millis().unwrap_or(-1);
+                    return process_frame_trace_message(
+                        use_otel,
+                        kafka_timestamp_ms,
+                        message.headers().and_then(FrameCorrelation::from_headers),
+                        (message.partition(), message.offset()),
+                        frame_event_topic,
+                        sender_parameters,
+                        message_processor,
+                        detector_config,
+                        trace_message,
+                        sampling,
+                    );
+                }
+                Err(e) => {
+                    tracing::Span::current().record("sampled", sampling.is_sampled(true));
+                    warn!("Failed to parse message: {}", e);
+                    failure!(FailureKind::UnableToDecodeMessage);
                 }
             }
         } else {
@@ -295,114 +1007,348 @@ fn process_kafka_message(
             .increment(1);
         }
     }
-    Ok(())
+    Ok(false)
 }
 
 /// Processes a [DigitizerAnalogTraceMessage].
 /// # Parameters
-/// - tracer: the tracer object, this is used to call the [TracerEngine::user_otel()] method, this could be replaced by a [bool].
+/// - use_otel: whether OpenTelemetry is used, see [TracerEngine::use_otel].
 /// - headers: the Kafka header of the message.
 /// - args: the user-specified Cli arguments.
 /// - sender: send channel which takes [DeliveryFuture] objects to dispatch.
-/// - kafka_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header. Only used for tracing.
+/// - kafka_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header, against which the message's
+///   timestamp is checked, see [DigitiserMessageProcessor::check_timestamp].
+/// - correlation: the frame correlation of the Kafka message's header, if it has one, otherwise it is derived from the message's metadata.
+/// - partition_offset: the partition and offset of the Kafka message, reported once its event list is delivered.
+/// - detector_config: if present, applies any replaced settings of the primary detector to `message_processor` before the message is processed.
 /// - message: the digitiser message.
+/// - sampling: whether the message is traced in full, see [DigitiserMessageProcessor::process_sampled].
+///
+/// The span is linked to the frame correlation, which is recorded to its `frame_correlation` field, and injected into the header of each message produced,
+/// so that the correlation survives a rewritten timestamp.
+///
+/// A message whose timestamp is implausible has the policy with which it is handled recorded to the `bad_timestamp_policy` field of the span.
+/// If it is rejected, it is counted as a failure, and no event list is produced.
+///
+/// # Returns
+/// Whether an event list was queued for delivery.
 #[instrument(
     skip_all,
     fields(
         digitiser_id = message.digitizer_id(),
         kafka_message_timestamp_ms = kafka_timestamp_ms,
-        send_digitiser_eventlist_buffer_capcacity,
+        send_digitiser_eventlist_buffer_capacity,
         metadata_timestamp,
         metadata_frame_number,
         metadata_period_number,
         metadata_veto_flags,
         metadata_protons_per_pulse,
         metadata_running,
+        frame_correlation,
+        bad_timestamp_policy,
         num_total_pulses,
+        sampled,
     )
 )]
 fn process_digitiser_trace_message(
-    tracer: &TracerEngine,
+    use_otel: bool,
     kafka_timestamp_ms: i64,
+    correlation: Option<FrameCorrelation>,
+    partition_offset: (i32, i64),
     sender_parameters: &SenderParameters,
     message_processor: &mut DigitiserMessageProcessor,
+    detector_config: Option<&mut DetectorConfigFollower>,
     message: DigitizerAnalogTraceMessage,
-) -> Result<(), TrySendDigitiserEventListError> {
-    let did = format!("{}", message.digitizer_id());
+    sampling: SamplingDecision,
+) -> Result<bool, TrySendDigitiserEventListError> {
+    if let Some(detector_config) = detector_config {
+        detector_config.update(message_processor);
+    }
+    let digitiser_id = message.digitizer_id();
+    let labels = [
+        messages_received::get_label(MessageKind::Trace),
+        (
+            DIGITIZER_ID_LABEL,
+            DigitiserName(digitiser_id).label_value(),
+        ),
+    ];
+    if !check_trace_message(kafka_timestamp_ms, message_processor, &message, &labels) {
+        return Ok(false);
+    }
+    let correlation = link_frame_correlation(correlation, &message);
 
-    counter!(
-        MESSAGES_RECEIVED,
-        &[
-            messages_received::get_label(MessageKind::Trace),
-            ("digitizer_id", did.clone())
-        ]
+    let key = sender_parameters.event_list_key.key(digitiser_id);
+    let partition = sender_parameters
+        .partitioner
+        .and_then(|partitioner| partitioner.partition(digitiser_id));
+    produce_event_list(
+        use_otel,
+        partition_offset,
+        sender_parameters,
+        (sender_parameters.event_topic, &*key, partition),
+        correlation.as_ref(),
+        sampling,
+        |fbb| message_processor.process_sampled(fbb, &message, sampling),
+    )?;
+
+    // The secondary detector's event list does not hold up the commit of the trace message's offset.
+    if let Some(secondary_event_list) = message_processor.take_secondary_event_list()
+        && let Some(secondary_event_topic) = sender_parameters.secondary_event_topic
+    {
+        let future_record = FutureRecord::to(secondary_event_topic)
+            .payload(&secondary_event_list)
+            .conditional_inject_current_span_into_headers(use_otel)
+            .conditional_inject_frame_correlation(use_otel, correlation.as_ref())
+            .key(&*key);
+
+        let future = sender_parameters
+            .producer
+            .send_result(future_record)
+            .expect("Producer sends");
+        try_send_delivery(sender_parameters.sender, EventListDelivery::new(future))?;
+    }
+
+    // Neither do the estimated baselines.
+    if let Some(baselines) = message_processor.take_baselines()
+        && let Some(baselines_topic) = sender_parameters.baselines_topic
+    {
+        let payload = baselines.to_json();
+        let future_record = FutureRecord::to(baselines_topic)
+            .payload(&payload)
+            .conditional_inject_current_span_into_headers(use_otel)
+            .conditional_inject_frame_correlation(use_otel, correlation.as_ref())
+            .key(&*key);
+
+        let future = sender_parameters
+            .producer
+            .send_result(future_record)
+            .expect("Producer sends");
+        try_send_delivery(sender_parameters.sender, EventListDelivery::new(future))?;
+    }
+    Ok(true)
+}
+
+/// Processes a [FrameAssembledAnalogTraceMessage], as [process_digitiser_trace_message] does a [DigitizerAnalogTraceMessage],
+/// except that its aggregated frame event list message is published to `frame_event_topic`, keyed by [FRAME_EVENT_LIST_KEY].
+///
+/// Its metrics are labelled by the [MessageKind::AggregatedTrace] message kind, rather than by digitiser id,
+/// and no secondary event list or estimated baselines are published, see [DigitiserMessageProcessor::process_aggregated].
+///
+/// # Returns
+/// Whether an event list was queued for delivery.
+#[instrument(
+    skip_all,
+    fields(
+        kafka_message_timestamp_ms = kafka_timestamp_ms,
+        send_digitiser_eventlist_buffer_capacity,
+        metadata_timestamp,
+        metadata_frame_number,
+        metadata_period_number,
+        metadata_veto_flags,
+        metadata_protons_per_pulse,
+        metadata_running,
+        frame_correlation,
+        bad_timestamp_policy,
+        num_total_pulses,
+        sampled,
     )
-    .increment(1);
+)]
+fn process_frame_trace_message(
+    use_otel: bool,
+    kafka_timestamp_ms: i64,
+    correlation: Option<FrameCorrelation>,
+    partition_offset: (i32, i64),
+    frame_event_topic: &str,
+    sender_parameters: &SenderParameters,
+    message_processor: &mut DigitiserMessageProcessor,
+    detector_config: Option<&mut DetectorConfigFollower>,
+    message: FrameAssembledAnalogTraceMessage,
+    sampling: SamplingDecision,
+) -> Result<bool, TrySendDigitiserEventListError> {
+    if let Some(detector_config) = detector_config {
+        detector_config.update(message_processor);
+    }
+    let labels = [messages_received::get_label(MessageKind::AggregatedTrace)];
+    if !check_trace_message(kafka_timestamp_ms, message_processor, &message, &labels) {
+        return Ok(false);
+    }
+    let correlation = link_frame_correlation(correlation, &message);
+
+    produce_event_list(
+        use_otel,
+        partition_offset,
+        sender_parameters,
+        (frame_event_topic, FRAME_EVENT_LIST_KEY, None),
+        correlation.as_ref(),
+        sampling,
+        |fbb| message_processor.process_aggregated(fbb, &message, sampling),
+    )?;
+    Ok(true)
+}
 
-    let timestamp: Option<DateTime<Utc>> = message
+/// Counts the receipt of a trace message, and checks its timestamp, see [DigitiserMessageProcessor::check_timestamp].
+/// The policy with which an implausible timestamp is handled is recorded to the `bad_timestamp_policy` field of the current span.
+/// # Parameters
+/// - kafka_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header.
+/// - message_processor: the processor which checks the timestamp.
+/// - message: the trace message.
+/// - labels: the labels of the message's metrics, which include its message kind.
+///
+/// # Returns
+/// Whether the message should be processed, which it should not be if its timestamp is rejected.
+fn check_trace_message<'a>(
+    kafka_timestamp_ms: i64,
+    message_processor: &mut DigitiserMessageProcessor,
+    message: &impl TraceMessage<'a>,
+    labels: &[(&'static str, String)],
+) -> bool {
+    counter!(MESSAGES_RECEIVED, labels).increment(1);
+
+    let mut timestamp: Option<DateTime<Utc>> = message
         .metadata()
         .timestamp()
         .copied()
         .and_then(|v| v.try_into().ok());
+    let verdict =
+        message_processor.check_timestamp(message, reference_time(kafka_timestamp_ms, Utc::now()));
+    if let Some(policy) = verdict.policy() {
+        tracing::Span::current().record("bad_timestamp_policy", policy.as_str());
+    }
+    match verdict {
+        TimestampVerdict::Reject => {
+            failure!(
+                FailureKind::ImplausibleTimestamp,
+                (DIGITIZER_ID_LABEL, message.source())
+            );
+            return false;
+        }
+        TimestampVerdict::Rewrite(rewritten) => timestamp = Some(rewritten),
+        _ => {}
+    }
     if let Some(timestamp) = timestamp {
-        gauge!(
-            LAST_MESSAGE_TIMESTAMP,
-            &[
-                messages_received::get_label(MessageKind::Trace),
-                ("digitizer_id", did.clone())
-            ]
-        )
-        // `timestamp_nanos_opt` returns `None` when the year is >2262. This is long after this
-        // software will be of use.
-        .set(timestamp.timestamp_nanos_opt().unwrap() as f64);
+        gauge!(LAST_MESSAGE_TIMESTAMP, labels)
+            // `timestamp_nanos_opt` returns `None` when the year is >2262. This is long after this
+            // software will be of use.
+            .set(timestamp.timestamp_nanos_opt().unwrap() as f64);
     } else {
         warn!(
             "Failed to update {LAST_MESSAGE_TIMESTAMP} metric due to malformed message/timestamp"
         );
+        failure!(
+            FailureKind::InvalidTimestamp,
+            (DIGITIZER_ID_LABEL, message.source())
+        );
     }
 
-    gauge!(
-        LAST_MESSAGE_FRAME_NUMBER,
-        &[
-            messages_received::get_label(MessageKind::Trace),
-            ("digitizer_id", did)
-        ]
-    )
-    .set(message.metadata().frame_number() as f64);
+    gauge!(LAST_MESSAGE_FRAME_NUMBER, labels).set(message.metadata().frame_number() as f64);
+    true
+}
 
-    message
+/// Records the metadata of a trace message to the `metadata_*` fields of the current span, and links the span to the message's frame correlation,
+/// which is recorded to its `frame_correlation` field.
+/// # Parameters
+/// - correlation: the frame correlation of the Kafka message's header, if it has one, otherwise it is derived from the message's metadata.
+/// - message: the trace message.
+///
+/// # Returns
+/// The frame correlation, or [None] if the message has none and its metadata is invalid.
+fn link_frame_correlation<'a>(
+    correlation: Option<FrameCorrelation>,
+    message: &impl TraceMessage<'a>,
+) -> Option<FrameCorrelation> {
+    let metadata = message
         .metadata()
         .try_into()
         .inspect(|metadata: &FrameMetadata| {
             record_metadata_fields_to_span!(metadata, tracing::Span::current());
         })
         .ok();
+    let correlation = correlation.or_else(|| metadata.as_ref().map(FrameCorrelation::new));
+    if let Some(correlation) = &correlation {
+        correlation.link_span(&tracing::Span::current());
+        tracing::Span::current().record("frame_correlation", correlation.to_string());
+    }
+    correlation
+}
 
-    let mut fbb: FlatBufferBuilder<'_> = FlatBufferBuilder::new();
-    let num_total_pulses = message_processor.process(&mut fbb, &message);
+/// Builds the event list of a trace message with `process`, and passes it to the producer,
+/// whose delivery reports the partition and offset of the trace message.
+///
+/// The outcome of processing is recorded to the `num_total_pulses` and `sampled` fields of the current span,
+/// and the remaining capacity of the producer task's channel to its `send_digitiser_eventlist_buffer_capacity` field.
+/// # Parameters
+/// - use_otel: whether OpenTelemetry is used, see [TracerEngine::use_otel].
+/// - partition_offset: the partition and offset of the Kafka message, reported once its event list is delivered.
+/// - destination: the topic, key, and if present, partition to which the event list is published.
+///   If the partition is no longer known, the partitions are refreshed and the event list sent to the partition chosen by the producer.
+/// - correlation: the frame correlation injected into the header of the event list.
+/// - sampling: whether the message is traced in full.
+/// - process: builds the event list in the given builder, returning the number of events found in each channel.
+fn produce_event_list(
+    use_otel: bool,
+    partition_offset: (i32, i64),
+    sender_parameters: &SenderParameters,
+    (topic, key, partition): (&str, &str, Option<i32>),
+    correlation: Option<&FrameCorrelation>,
+    sampling: SamplingDecision,
+    process: impl FnOnce(
+        &mut FlatBufferBuilder<'static>,
+    ) -> Vec<(Channel, Result<usize, MalformedChannelTrace>)>,
+) -> Result<(), TrySendDigitiserEventListError> {
+    let mut fbb = sender_parameters.builder_pool.take();
+    let event_counts = process(&mut *fbb);
+    let num_total_pulses: usize = event_counts
+        .iter()
+        .filter_map(|(_, num_events)| num_events.as_ref().ok())
+        .sum();
     tracing::Span::current().record("num_total_pulses", num_total_pulses);
     tracing::Span::current().record(
-        "send_digitiser_eventlist_buffer_capcacity",
+        "sampled",
+        sampling.is_sampled(message_failed(&event_counts)),
+    );
+    tracing::Span::current().record(
+        "send_digitiser_eventlist_buffer_capacity",
         sender_parameters.sender.capacity(),
     );
 
-    let future_record = FutureRecord::to(sender_parameters.event_topic)
+    let mut future_record = FutureRecord::to(topic)
         .payload(fbb.finished_data())
-        .conditional_inject_current_span_into_headers(tracer.use_otel())
-        .key("Digitiser Events List");
-
-    let future = sender_parameters
-        .producer
-        .send_result(future_record)
-        .expect("Producer sends");
-
-    if let Err(e) = sender_parameters
-        .sender
-        .try_send(tracing::Instrument::instrument(
-            future,
-            tracing::Span::current(),
-        ))
-    {
+        .conditional_inject_current_span_into_headers(use_otel)
+        .conditional_inject_frame_correlation(use_otel, correlation)
+        .key(key);
+    future_record.partition = partition;
+
+    let future = match sender_parameters.producer.send_result(future_record) {
+        // The number of partitions may have decreased, so it is refetched in the background,
+        // and the event list is sent again, to the partition chosen by the producer.
+        Err((e, mut future_record))
+            if e.rdkafka_error_code() == Some(RDKafkaErrorCode::UnknownPartition) =>
+        {
+            warn!("Failed to send event list to partition, refreshing partitions: {e}");
+            if let Some(partitioner) = sender_parameters.partitioner {
+                partitioner.refresh(sender_parameters.producer);
+            }
+            future_record.partition = None;
+            sender_parameters.producer.send_result(future_record)
+        }
+        result => result,
+    }
+    .expect("Producer sends");
+    // The producer copies the payload when it is sent, so the builder can be reused before the event list is delivered.
+    drop(fbb);
+    let delivery = EventListDelivery::new(future).with_delivered_offset(
+        partition_offset,
+        sender_parameters.delivered_offsets.cloned(),
+    );
+    try_send_delivery(sender_parameters.sender, delivery)
+}
+
+/// Passes `delivery` to the producer task, reporting if its channel is closed or full.
+fn try_send_delivery(
+    sender: &DigitiserEventListToBufferSender,
+    delivery: EventListDelivery,
+) -> Result<(), TrySendDigitiserEventListError> {
+    if let Err(e) = sender.try_send(delivery) {
         match &e {
             TrySendError::Closed(_) => {
                 error!("Send-Frame Channel Closed");
@@ -422,19 +1368,34 @@ fn process_digitiser_trace_message(
 /// Create a new thread and setup the producer task.
 /// # Parameters
 /// - send_digitiser_eventlist_buffer_size: the maximum number of [DeliveryFuture] objects to store in the channel's buffer. If the buffer is filled, then sending another frame will block until there is sufficient space in the buffer.
+/// - max_inflight_acks: the maximum number of [DeliveryFuture] objects to await concurrently.
+///
+/// # Returns
+/// The sender of the channel, the sender which shuts the task down, and the handle of the task, which finishes once its channel is flushed.
 fn create_producer_task(
     send_digitiser_eventlist_buffer_size: usize,
-) -> std::io::Result<(DigitiserEventListToBufferSender, JoinHandle<()>)> {
-    let (channel_send, channel_recv) = tokio::sync::mpsc::channel::<InstrumentedDeliveryFuture>(
-        send_digitiser_eventlist_buffer_size,
-    );
+    max_inflight_acks: usize,
+) -> (
+    DigitiserEventListToBufferSender,
+    oneshot::Sender<()>,
+    JoinHandle<()>,
+) {
+    let (channel_send, channel_recv) =
+        tokio::sync::mpsc::channel::<EventListDelivery>(send_digitiser_eventlist_buffer_size);
 
-    let sigint = signal(SignalKind::interrupt())?;
-    let handle = tokio::spawn(produce_to_kafka(channel_recv, sigint));
-    Ok((channel_send, handle))
+    let (shutdown_send, shutdown_recv) = oneshot::channel();
+    let handle = tokio::spawn(produce_to_kafka(
+        channel_recv,
+        shutdown_recv,
+        max_inflight_acks,
+    ));
+    (channel_send, shutdown_send, handle)
 }
 
-/// Runs infinitely, and waits on any [DeliveryFuture]s received through the given receive channel.
+/// Runs infinitely, and waits on any deliveries received through the given receive channel.
+///
+/// Up to `max_inflight_acks` deliveries are awaited concurrently, so a slow acknowledgement
+/// does not hold up those behind it.
 ///
 /// Calling this function returns a Future, which should be passed to a async task,
 /// as in function [create_producer_task]. The general form of this is:
@@ -442,28 +1403,32 @@ fn create_producer_task(
 /// let join_handle = tokio::spawn(produce_to_kafka(...))?;
 /// ```
 /// # Parameters
-/// - channel_recv: receive channel that can receive [DeliveryFuture] objects.
-/// - sigint: triggers when the os sends a signal to the process.
-async fn produce_to_kafka(
-    mut channel_recv: Receiver<InstrumentedDeliveryFuture>,
-    mut sigint: Signal,
+/// - channel_recv: receive channel that can receive deliveries, such as [EventListDelivery].
+/// - shutdown: triggers when the process is shutting down, or its sender is dropped, after which the channel is flushed and the task finishes.
+/// - max_inflight_acks: the maximum number of deliveries to await concurrently.
+async fn produce_to_kafka<D: IntoFuture<Output = ()>>(
+    mut channel_recv: Receiver<D>,
+    mut shutdown: oneshot::Receiver<()>,
+    max_inflight_acks: usize,
 ) {
+    let mut in_flight = FuturesUnordered::new();
     loop {
-        // Blocks until a frame is received
+        // Blocks until a frame is received, or a delivery completes
         select! {
-            message = channel_recv.recv() => {
+            message = channel_recv.recv(), if in_flight.len() < max_inflight_acks => {
                 match message {
-                    Some(future) => {
-                        produce_eventlist_to_kafka(future).await
-                    },
+                    Some(delivery) => in_flight.push(delivery.into_future()),
                     None => {
                         info!("Send-Eventlist channel closed");
+                        while in_flight.next().await.is_some() {}
                         return;
                     }
                 }
             },
-            _ = sigint.recv() => {
-                close_and_flush_producer_channel(&mut channel_recv).await;
+            Some(()) = in_flight.next(), if !in_flight.is_empty() => {},
+            _ = &mut shutdown => {
+                close_and_flush_producer_channel(&mut channel_recv, &mut in_flight, max_inflight_acks).await;
+                return;
             }
         }
     }
@@ -472,46 +1437,890 @@ async fn produce_to_kafka(
 /// Dispatches the given eventlist to the Kafka broker by waiting the [DeliveryFuture].
 /// # Parameters
 /// - future: the future which produces the message.
+/// - enqueued: the time the message was passed to the producer, used to record the delivery latency.
+///
+/// # Returns
+/// Whether the eventlist was delivered.
 #[instrument(skip_all, parent = future.span())]
-async fn produce_eventlist_to_kafka(future: InstrumentedDeliveryFuture) {
-    match future.await {
+async fn produce_eventlist_to_kafka(future: InstrumentedDeliveryFuture, enqueued: Instant) -> bool {
+    let result = future.await;
+    histogram!(DELIVERY_LATENCY_METRIC).record(enqueued.elapsed().as_secs_f64());
+    match result {
         Ok(_) => {
             trace!("Published event message");
             counter!(MESSAGES_PROCESSED).increment(1);
+            true
         }
         Err(e) => {
             error!("{:?}", e);
-            counter!(
-                FAILURES,
-                &[failures::get_label(FailureKind::KafkaPublishFailed)]
-            )
-            .increment(1);
+            failure!(FailureKind::KafkaPublishFailed);
+            false
         }
     }
 }
 
-/// Closes the producer channel and dispatch all [DeliveryFuture]s remaining in the channel.
+/// Awaits the given delivery and, if it succeeds, sends the partition and offset of the trace message it was produced from.
+/// # Parameters
+/// - delivery: resolves to whether the eventlist was delivered.
+/// - partition_offset: the partition and offset of the trace message.
+/// - delivered_offsets: where to send `partition_offset`, if [CommitStrategy::AfterDelivery] is used.
+async fn report_delivery(
+    delivery: impl Future<Output = bool>,
+    partition_offset: (i32, i64),
+    delivered_offsets: Option<DeliveredOffsetSender>,
+) {
+    if delivery.await
+        && let Some(delivered_offsets) = delivered_offsets
+        && delivered_offsets.send(partition_offset).is_err()
+    {
+        warn!("Delivered offset channel closed");
+    }
+}
+
+/// Closes the producer channel and dispatch all deliveries remaining in the channel,
+/// as well as those already in flight, up to `max_inflight_acks` at a time.
 /// # Parameters
-/// - channel_recv: receive channel that can receive [DeliveryFuture] objects.
-#[tracing::instrument(skip_all, name = "Closing", level = "info", fields(capactity = channel_recv.capacity(), max_capactity = channel_recv.max_capacity()))]
-async fn close_and_flush_producer_channel(
-    channel_recv: &mut Receiver<InstrumentedDeliveryFuture>,
-) -> Option<()> {
+/// - channel_recv: receive channel that can receive deliveries.
+/// - in_flight: the deliveries currently being awaited.
+/// - max_inflight_acks: the maximum number of deliveries to await concurrently.
+#[tracing::instrument(skip_all, name = "Closing", level = "info", fields(capactity = channel_recv.capacity(), max_capactity = channel_recv.max_capacity(), in_flight = in_flight.len()))]
+async fn close_and_flush_producer_channel<D: IntoFuture<Output = ()>>(
+    channel_recv: &mut Receiver<D>,
+    in_flight: &mut FuturesUnordered<D::IntoFuture>,
+    max_inflight_acks: usize,
+) {
     channel_recv.close();
 
     loop {
-        let future = channel_recv.recv().await?;
-        flush_eventlist(future).await?;
+        select! {
+            message = channel_recv.recv(), if in_flight.len() < max_inflight_acks => {
+                match message {
+                    Some(delivery) => in_flight.push(delivery.into_future()),
+                    None => break,
+                }
+            },
+            Some(()) = in_flight.next(), if !in_flight.is_empty() => {},
+        }
     }
+    while in_flight.next().await.is_some() {}
 }
 
-/// Dispatches the given future to the Kafka broker by calling and awaiting [produce_eventlist_to_kafka()].
-///
-/// This function exists just to encapsulate [produce_eventlist_to_kafka] in a span, it might be better to do this directly in [close_and_flush_producer_channel].
-/// # Parameters
-/// - future: the future to dispatch.
-#[tracing::instrument(skip_all, name = "Flush Eventlist")]
-async fn flush_eventlist(future: InstrumentedDeliveryFuture) -> Option<()> {
-    produce_eventlist_to_kafka(future).await;
-    Some(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_reload::ConfigReloadError;
+    use digital_muon_common::{Channel, metrics::names::FAILURES};
+    use digital_muon_streaming_types::{
+        aat2_frame_assembled_analog_trace_v2_generated::{
+            FrameAssembledAnalogTraceMessageArgs,
+            finish_frame_assembled_analog_trace_message_buffer,
+        },
+        aev2_frame_assembled_event_v2_generated::{
+            frame_assembled_event_list_message_buffer_has_identifier,
+            root_as_frame_assembled_event_list_message,
+        },
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessageArgs,
+            finish_digitizer_analog_trace_message_buffer,
+        },
+        dev2_digitizer_event_v2_generated::{
+            digitizer_event_list_message_buffer_has_identifier,
+            root_as_digitizer_event_list_message,
+        },
+        flatbuffers::{FlatBufferBuilder, ForwardsUOffset, Vector, WIPOffset},
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+    use metrics_util::{
+        CompositeKey,
+        debugging::{DebugValue, DebuggingRecorder},
+    };
+    use rdkafka::{
+        ClientConfig,
+        message::{OwnedMessage, Timestamp},
+    };
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::sync::oneshot;
+    use trace_to_events::{FixedThresholdDiscriminatorParameters, Real};
+
+    /// Records how many fake deliveries are being awaited, and the order they complete in.
+    #[derive(Clone, Default)]
+    struct Probe {
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+        completed: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Probe {
+        /// Creates a delivery which is acknowledged when the returned sender is fired.
+        fn delivery(&self, id: usize) -> (oneshot::Sender<()>, impl Future<Output = ()> + use<>) {
+            let (ack, acknowledged) = oneshot::channel();
+            let probe = self.clone();
+            let delivery = async move {
+                let active = probe.active.fetch_add(1, Ordering::SeqCst) + 1;
+                probe.max_active.fetch_max(active, Ordering::SeqCst);
+                acknowledged.await.ok();
+                probe.active.fetch_sub(1, Ordering::SeqCst);
+                probe.completed.lock().unwrap().push(id);
+            };
+            (ack, delivery)
+        }
+
+        fn active(&self) -> usize {
+            self.active.load(Ordering::SeqCst)
+        }
+
+        fn max_active(&self) -> usize {
+            self.max_active.load(Ordering::SeqCst)
+        }
+
+        fn completed(&self) -> Vec<usize> {
+            self.completed.lock().unwrap().clone()
+        }
+    }
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrency_bounded_by_max_inflight_acks() {
+        let probe = Probe::default();
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let mut acks = Vec::new();
+        for id in 0..10 {
+            let (ack, delivery) = probe.delivery(id);
+            acks.push(ack);
+            sender.try_send(delivery).unwrap();
+        }
+        drop(sender);
+
+        let (_shutdown, shutdown) = oneshot::channel();
+        let handle = tokio::spawn(produce_to_kafka(receiver, shutdown, 3));
+        settle().await;
+        assert_eq!(probe.active(), 3);
+
+        // Acknowledge in reverse order, so most acks arrive before their delivery is awaited.
+        while let Some(ack) = acks.pop() {
+            ack.send(()).unwrap();
+            settle().await;
+            assert!(probe.active() <= 3);
+        }
+        handle.await.unwrap();
+
+        assert_eq!(probe.max_active(), 3);
+        assert_eq!(probe.completed().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn out_of_order_acks_are_not_blocked() {
+        let probe = Probe::default();
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let mut acks = Vec::new();
+        for id in 0..4 {
+            let (ack, delivery) = probe.delivery(id);
+            acks.push(Some(ack));
+            sender.try_send(delivery).unwrap();
+        }
+        drop(sender);
+
+        let (_shutdown, shutdown) = oneshot::channel();
+        let handle = tokio::spawn(produce_to_kafka(receiver, shutdown, 4));
+        settle().await;
+
+        for id in [2, 0, 3, 1] {
+            acks[id].take().unwrap().send(()).unwrap();
+            settle().await;
+            // A pending acknowledgement ahead of this one should not delay it.
+            assert_eq!(probe.completed().last(), Some(&id));
+        }
+        handle.await.unwrap();
+        assert_eq!(probe.completed(), vec![2, 0, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn flush_drains_pending_and_completed_deliveries() {
+        let probe = Probe::default();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let mut in_flight = FuturesUnordered::new();
+        let mut pending_acks = Vec::new();
+
+        // Deliveries already in flight, one of which has been acknowledged.
+        for id in 0..2 {
+            let (ack, delivery) = probe.delivery(id);
+            in_flight.push(delivery);
+            if id == 0 {
+                ack.send(()).unwrap();
+            } else {
+                pending_acks.push(ack);
+            }
+        }
+        // Deliveries remaining in the channel, half of which have been acknowledged.
+        for id in 2..8 {
+            let (ack, delivery) = probe.delivery(id);
+            sender.try_send(delivery).unwrap();
+            if id % 2 == 0 {
+                ack.send(()).unwrap();
+            } else {
+                pending_acks.push(ack);
+            }
+        }
+
+        let acknowledge_pending = async {
+            settle().await;
+            for ack in pending_acks {
+                ack.send(()).unwrap();
+                settle().await;
+            }
+        };
+        tokio::join!(
+            close_and_flush_producer_channel(&mut receiver, &mut in_flight, 2),
+            acknowledge_pending
+        );
+
+        assert!(sender.is_closed());
+        assert!(in_flight.is_empty());
+        assert!(probe.max_active() <= 2);
+        let mut completed = probe.completed();
+        completed.sort();
+        assert_eq!(completed, (0..8).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_queued_deliveries_before_returning() {
+        let probe = Probe::default();
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let mut acks = Vec::new();
+        for id in 0..6 {
+            let (ack, delivery) = probe.delivery(id);
+            acks.push(ack);
+            sender.try_send(delivery).unwrap();
+        }
+
+        let (shutdown_send, shutdown) = oneshot::channel();
+        let handle = tokio::spawn(produce_to_kafka(receiver, shutdown, 2));
+        settle().await;
+        shutdown_send.send(()).unwrap();
+
+        let acknowledge = async {
+            for ack in acks {
+                settle().await;
+                ack.send(()).unwrap();
+            }
+        };
+        let (outcome, ()) = tokio::join!(
+            drain_producer_task(handle, Duration::from_secs(10)),
+            acknowledge
+        );
+
+        // Every queued delivery completes before the drain, and so before the final offsets are committed.
+        assert_eq!(outcome.unwrap(), shutdown::ShutdownOutcome::Drained);
+        assert_eq!(probe.completed(), (0..6).collect::<Vec<_>>());
+        assert!(probe.max_active() <= 2);
+        assert!(sender.is_closed());
+    }
+
+    #[tokio::test]
+    async fn shutdown_times_out_on_unacknowledged_delivery() {
+        let probe = Probe::default();
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let (ack, delivery) = probe.delivery(0);
+        sender.try_send(delivery).unwrap();
+        // Its acknowledgement is held, but never sent.
+        let (_stuck_ack, stuck) = probe.delivery(1);
+        sender.try_send(stuck).unwrap();
+
+        let (shutdown_send, shutdown) = oneshot::channel();
+        let handle = tokio::spawn(produce_to_kafka(receiver, shutdown, 4));
+        ack.send(()).unwrap();
+        shutdown_send.send(()).unwrap();
+
+        let outcome = drain_producer_task(handle, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(outcome, shutdown::ShutdownOutcome::TimedOut);
+        assert_ne!(outcome.exit_code(), 0);
+        assert_eq!(probe.completed(), [0]);
+    }
+
+    #[tokio::test]
+    async fn offsets_committed_only_after_delivery() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let (delivered_offsets, mut delivered_offsets_recv) =
+            tokio::sync::mpsc::unbounded_channel();
+        let mut watermarks = DeliveryWatermarks::default();
+        let mut acks = Vec::new();
+        for offset in 0..4 {
+            watermarks.received(0, offset);
+            let (ack, acknowledged) = oneshot::channel::<bool>();
+            acks.push(Some(ack));
+            let delivery = async move { acknowledged.await.unwrap_or(false) };
+            sender
+                .try_send(
+                    report_delivery(delivery, (0, offset), Some(delivered_offsets.clone())).boxed(),
+                )
+                .unwrap();
+        }
+        drop(sender);
+
+        let (_shutdown, shutdown) = oneshot::channel();
+        let handle = tokio::spawn(produce_to_kafka(receiver, shutdown, 4));
+        settle().await;
+
+        let mut commits = Vec::new();
+        // The delivery of offset 1 fails, so nothing after it should be committed.
+        for (offset, delivered) in [(3, true), (2, true), (1, false), (0, true)] {
+            acks[offset].take().unwrap().send(delivered).unwrap();
+            settle().await;
+            while let Ok((partition, offset)) = delivered_offsets_recv.try_recv() {
+                commits.extend(watermarks.resolved(partition, offset));
+            }
+        }
+        handle.await.unwrap();
+
+        assert_eq!(commits, [0, 1]);
+    }
+
+    /// Returns the values recorded to the histogram `name` with the given labels.
+    fn histogram_values(
+        recorder: &DebuggingRecorder,
+        name: &str,
+        labels: &[(&str, &str)],
+    ) -> Vec<f64> {
+        recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let key = CompositeKey::key(&key);
+                let matches = key.name() == name
+                    && key
+                        .labels()
+                        .map(|label| (label.key(), label.value()))
+                        .eq(labels.iter().copied());
+                match value {
+                    DebugValue::Histogram(values) if matches => {
+                        Some(values.into_iter().map(|value| value.into_inner()))
+                    }
+                    _ => None,
+                }
+            })
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn decode_duration_is_recorded() {
+        let recorder = DebuggingRecorder::new();
+        let result = metrics::with_local_recorder(&recorder, || {
+            spanned_root_as_digitizer_analog_trace_message(&[0; 4]).map(|_| ())
+        });
+        assert!(result.is_err());
+        let durations = histogram_values(&recorder, STAGE_DURATION_METRIC, &[("stage", "decode")]);
+        assert_eq!(durations.len(), 1);
+        assert!(durations[0] >= 0.0);
+    }
+
+    #[test]
+    fn delivery_carries_enqueue_time() {
+        // There is no broker, so the message times out, but only once it has been enqueued.
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set("message.timeout.ms", "50")
+            .create()
+            .unwrap();
+        let before = Instant::now();
+        let future = producer
+            .send_result(FutureRecord::<(), _>::to("events").payload("event list"))
+            .unwrap();
+        let delivery = EventListDelivery::new(future);
+        let after = Instant::now();
+        assert!(before <= delivery.enqueued && delivery.enqueued <= after);
+        assert!(delivery.delivered_offset.is_none());
+
+        let (delivered_offsets, mut delivered_offsets_recv) =
+            tokio::sync::mpsc::unbounded_channel();
+        let delivery = delivery.with_delivered_offset((2, 7), Some(delivered_offsets));
+        assert!(matches!(delivery.delivered_offset, Some(((2, 7), Some(_)))));
+        assert!(before <= delivery.enqueued && delivery.enqueued <= after);
+
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            futures::executor::block_on(delivery.into_future())
+        });
+        let latencies = histogram_values(&recorder, DELIVERY_LATENCY_METRIC, &[]);
+        assert_eq!(latencies.len(), 1);
+        assert!(latencies[0] > 0.0);
+        assert!(latencies[0] <= after.elapsed().as_secs_f64());
+
+        // The message was not delivered, so its offset is not reported.
+        assert!(delivered_offsets_recv.try_recv().is_err());
+        assert!(
+            recorder
+                .snapshotter()
+                .snapshot()
+                .into_vec()
+                .iter()
+                .any(|(key, _, _, _)| CompositeKey::key(key).name() == FAILURES)
+        );
+    }
+
+    /// Returns the frame metadata, and the traces of `num_channels` channels numbered from `first_channel`,
+    /// each of which has a pulse every ten samples.
+    fn frame_metadata_and_channels<'a>(
+        fbb: &mut FlatBufferBuilder<'a>,
+        first_channel: Channel,
+        num_channels: Channel,
+    ) -> (
+        WIPOffset<FrameMetadataV2<'a>>,
+        WIPOffset<Vector<'a, ForwardsUOffset<ChannelTrace<'a>>>>,
+    ) {
+        let time: GpsTime = Utc::now().into();
+        let metadata = FrameMetadataV2::create(
+            fbb,
+            &FrameMetadataV2Args {
+                frame_number: 7,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&time),
+                veto_flags: 0,
+            },
+        );
+        let intensities = (0..100)
+            .map(|time| if time % 10 == 0 { 100 } else { 0 })
+            .collect::<Vec<Intensity>>();
+        let channels = (first_channel..first_channel + num_channels)
+            .map(|channel| {
+                let voltage = fbb.create_vector(&intensities);
+                ChannelTrace::create(
+                    fbb,
+                    &ChannelTraceArgs {
+                        channel,
+                        voltage: Some(voltage),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        (metadata, fbb.create_vector(&channels))
+    }
+
+    /// Returns the payload of a trace message of digitiser 3, whose two channels each have ten pulses.
+    fn digitiser_trace_payload() -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let (metadata, channels) = frame_metadata_and_channels(&mut fbb, 0, 2);
+        let message = DigitizerAnalogTraceMessage::create(
+            &mut fbb,
+            &DigitizerAnalogTraceMessageArgs {
+                digitizer_id: 3,
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels: Some(channels),
+            },
+        );
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+
+    /// Returns the payload of an aggregated frame trace message of digitisers 3 and 4, whose four channels each have ten pulses.
+    fn frame_trace_payload() -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let (metadata, channels) = frame_metadata_and_channels(&mut fbb, 0, 4);
+        let digitizers_present = fbb.create_vector(&[3u8, 4]);
+        let message = FrameAssembledAnalogTraceMessage::create(
+            &mut fbb,
+            &FrameAssembledAnalogTraceMessageArgs {
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels: Some(channels),
+                complete: true,
+                digitizers_present: Some(digitizers_present),
+            },
+        );
+        finish_frame_assembled_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+
+    fn kafka_message(payload: Vec<u8>, offset: i64) -> OwnedMessage {
+        OwnedMessage::new(
+            Some(payload),
+            None,
+            "traces".to_owned(),
+            Timestamp::NotAvailable,
+            0,
+            offset,
+            None,
+        )
+    }
+
+    /// Returns the topic, key and payload of each message passed to the producer, which has no broker, so each delivery fails with its message.
+    fn undelivered_messages(
+        receiver: &mut Receiver<EventListDelivery>,
+    ) -> Vec<(String, Option<Vec<u8>>, Vec<u8>)> {
+        let mut messages = Vec::new();
+        while let Ok(delivery) = receiver.try_recv() {
+            match futures::executor::block_on(delivery.future.into_inner()) {
+                Ok(Err((_, message))) => messages.push((
+                    message.topic().to_owned(),
+                    message.key().map(<[u8]>::to_vec),
+                    message.payload().unwrap_or_default().to_vec(),
+                )),
+                _ => panic!("Message should not be delivered"),
+            }
+        }
+        messages
+    }
+
+    /// Returns the values of the [MESSAGES_RECEIVED] counters, by message kind.
+    fn messages_received(recorder: &DebuggingRecorder) -> Vec<(String, u64)> {
+        let mut messages_received = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let key = CompositeKey::key(&key);
+                let message_kind = key
+                    .labels()
+                    .find(|label| label.key() == "message_kind")?
+                    .value()
+                    .to_owned();
+                match value {
+                    DebugValue::Counter(count) if key.name() == MESSAGES_RECEIVED => {
+                        Some((message_kind, count))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        messages_received.sort();
+        messages_received
+    }
+
+    fn message_processor() -> DigitiserMessageProcessor {
+        DigitiserMessageProcessor::new(
+            8,
+            &DetectorSettings {
+                mode: &Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                    threshold: 50.0,
+                    duration: 1,
+                    cool_off: 0,
+                    disarm_threshold: None,
+                    veto_threshold: None,
+                    veto_extend: 0,
+                }),
+                polarity: &Polarity::Positive,
+                baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            },
+        )
+    }
+
+    #[test]
+    fn trace_messages_are_routed_by_identifier() {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set("message.timeout.ms", "50")
+            .create()
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let builder_pool = BuilderPool::new(1);
+        let sender_parameters = SenderParameters {
+            event_topic: "events",
+            frame_event_topic: Some("frame-events"),
+            secondary_event_topic: None,
+            baselines_topic: None,
+            sender: &sender,
+            producer: &producer,
+            delivered_offsets: None,
+            event_list_key: EventListKey::new(true, false),
+            partitioner: None,
+            builder_pool: &builder_pool,
+        };
+        let mut span_sampler = SpanSampler::always();
+        let mut message_processor = message_processor();
+
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            for (offset, payload) in [digitiser_trace_payload(), frame_trace_payload()]
+                .into_iter()
+                .enumerate()
+            {
+                let queued = process_kafka_message(
+                    false,
+                    &mut span_sampler,
+                    &sender_parameters,
+                    &mut message_processor,
+                    None,
+                    &kafka_message(payload, offset as i64),
+                )
+                .unwrap();
+                assert!(queued);
+            }
+        });
+        assert_eq!(
+            messages_received(&recorder),
+            [("aggregated_trace".to_owned(), 1), ("trace".to_owned(), 1)]
+        );
+
+        let messages = undelivered_messages(&mut receiver);
+        assert_eq!(messages.len(), 2);
+
+        let (topic, key, payload) = &messages[0];
+        assert_eq!(topic, "events");
+        assert_eq!(key.as_deref(), Some(b"digitiser-3".as_slice()));
+        assert!(digitizer_event_list_message_buffer_has_identifier(payload));
+        let event_list = root_as_digitizer_event_list_message(payload).unwrap();
+        assert_eq!(event_list.digitizer_id(), 3);
+        assert_eq!(event_list.time().unwrap().len(), 20);
+
+        let (topic, key, payload) = &messages[1];
+        assert_eq!(topic, "frame-events");
+        assert_eq!(key.as_deref(), Some(FRAME_EVENT_LIST_KEY.as_bytes()));
+        assert!(frame_assembled_event_list_message_buffer_has_identifier(
+            payload
+        ));
+        let event_list = root_as_frame_assembled_event_list_message(payload).unwrap();
+        assert_eq!(event_list.metadata().frame_number(), 7);
+        assert_eq!(event_list.time().unwrap().len(), 40);
+        assert_eq!(
+            event_list.channel().unwrap().iter().collect::<Vec<_>>(),
+            (0..4).flat_map(|channel| [channel; 10]).collect::<Vec<_>>()
+        );
+        assert!(event_list.complete());
+        assert_eq!(event_list.digitizers_present().unwrap().bytes(), [3, 4]);
+    }
+
+    #[test]
+    fn frame_trace_messages_are_unexpected_without_frame_event_topic() {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .create()
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let builder_pool = BuilderPool::new(1);
+        let sender_parameters = SenderParameters {
+            event_topic: "events",
+            frame_event_topic: None,
+            secondary_event_topic: None,
+            baselines_topic: None,
+            sender: &sender,
+            producer: &producer,
+            delivered_offsets: None,
+            event_list_key: EventListKey::new(true, false),
+            partitioner: None,
+            builder_pool: &builder_pool,
+        };
+
+        let recorder = DebuggingRecorder::new();
+        let queued = metrics::with_local_recorder(&recorder, || {
+            process_kafka_message(
+                false,
+                &mut SpanSampler::always(),
+                &sender_parameters,
+                &mut message_processor(),
+                None,
+                &kafka_message(frame_trace_payload(), 0),
+            )
+            .unwrap()
+        });
+        assert!(!queued);
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(messages_received(&recorder), [("unexpected".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn deserialised_detector_config_defaults_are_those_of_the_command_line() {
+        let args = Cli::try_parse_from([
+            "trace-to-events",
+            "--broker",
+            "localhost:19092",
+            "--consumer-group",
+            "trace-to-events",
+            "--trace-topic",
+            "traces",
+            "--event-topic",
+            "events",
+            "--polarity",
+            "negative",
+            "differential-threshold-discriminator",
+            "--begin-threshold",
+            "2",
+            "--end-threshold",
+            "1",
+            "--peak-height-mode",
+            "max-value",
+            "--peak-height-basis",
+            "pulse-baseline",
+        ])
+        .unwrap();
+        let deserialised: DetectorConfig = serde_json::from_str(
+            r#"{"mode": {"differential-threshold-discriminator": {"begin-threshold": 2, "end-threshold": 1,
+                "peak-height-mode": "max-value", "peak-height-basis": "pulse-baseline"}}, "polarity": "negative"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{deserialised:?}"),
+            format!("{:?}", args.detector_config())
+        );
+    }
+
+    #[test]
+    fn detector_settings_are_replaced_between_messages() {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .create()
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let builder_pool = BuilderPool::new(1);
+        let sender_parameters = SenderParameters {
+            event_topic: "events",
+            frame_event_topic: None,
+            secondary_event_topic: None,
+            baselines_topic: None,
+            sender: &sender,
+            producer: &producer,
+            delivered_offsets: None,
+            event_list_key: EventListKey::new(true, false),
+            partitioner: None,
+            builder_pool: &builder_pool,
+        };
+        // The pulses of each channel are of height 100, so are found with a threshold of 50, but not of 150.
+        let threshold = |threshold: Real| {
+            format!(
+                r#"{{"mode": {{"fixed-threshold-discriminator": {{"threshold": {threshold}}}}}, "polarity": "positive"}}"#
+            )
+        };
+        let initial: DetectorConfig = serde_json::from_str(&threshold(50.0)).unwrap();
+        let fingerprint = initial.fingerprint();
+        let mut message_processor = message_processor();
+
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            let active = ActiveDetectorConfig::new(initial);
+            let mut follower = DetectorConfigFollower::new(&active);
+            let mut process = |offset| {
+                process_kafka_message(
+                    false,
+                    &mut SpanSampler::always(),
+                    &sender_parameters,
+                    &mut message_processor,
+                    Some(&mut follower),
+                    &kafka_message(digitiser_trace_payload(), offset),
+                )
+                .unwrap()
+            };
+            assert!(process(0));
+            active.apply(threshold(150.0).as_bytes()).unwrap();
+            assert!(process(1));
+
+            // Invalid settings are rejected, so the active settings are unchanged.
+            assert!(matches!(
+                active.apply(b"{\"mode\": "),
+                Err(ConfigReloadError::Json(_))
+            ));
+            assert!(matches!(
+                active.apply(
+                    br#"{"mode": {"fixed-threshold-discriminator": {"threshold": 50}}, "polarity": "positive", "downsample-factor": 0}"#
+                ),
+                Err(ConfigReloadError::Invalid(_))
+            ));
+            assert!(process(2));
+
+            active.apply(threshold(50.0).as_bytes()).unwrap();
+            assert!(process(3));
+        });
+
+        let num_events = undelivered_messages(&mut receiver)
+            .iter()
+            .map(|(_, _, payload)| {
+                root_as_digitizer_event_list_message(payload)
+                    .unwrap()
+                    .time()
+                    .unwrap()
+                    .len()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(num_events, [20, 0, 0, 20]);
+
+        let snapshot = recorder.snapshotter().snapshot().into_vec();
+        let value = |name| {
+            snapshot
+                .iter()
+                .find(|(key, _, _, _)| CompositeKey::key(key).name() == name)
+                .map(|(_, _, _, value)| value.clone())
+        };
+        assert_eq!(value(CONFIG_RELOADS_METRIC), Some(DebugValue::Counter(2)));
+        assert_eq!(
+            value(ACTIVE_CONFIG_HASH_METRIC),
+            Some(DebugValue::Gauge(f64::from(fingerprint).into()))
+        );
+    }
+
+    #[test]
+    fn detector_settings_replayed_at_startup_are_applied() {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .create()
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let builder_pool = BuilderPool::new(1);
+        let sender_parameters = SenderParameters {
+            event_topic: "events",
+            frame_event_topic: None,
+            secondary_event_topic: None,
+            baselines_topic: None,
+            sender: &sender,
+            producer: &producer,
+            delivered_offsets: None,
+            event_list_key: EventListKey::new(true, false),
+            partitioner: None,
+            builder_pool: &builder_pool,
+        };
+        // The message processor is created with the settings given on the command line, with a threshold of 50,
+        // whereas the settings replayed from the config topic have a threshold of 150, with which no pulses are found.
+        let command_line: DetectorConfig = serde_json::from_str(
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 50}}, "polarity": "positive"}"#,
+        )
+        .unwrap();
+        let active = ActiveDetectorConfig::new(command_line);
+        active
+            .apply(
+                br#"{"mode": {"fixed-threshold-discriminator": {"threshold": 150}}, "polarity": "positive"}"#,
+            )
+            .unwrap();
+
+        let mut message_processor = message_processor();
+        let mut follower = DetectorConfigFollower::new(&active);
+        assert!(
+            process_kafka_message(
+                false,
+                &mut SpanSampler::always(),
+                &sender_parameters,
+                &mut message_processor,
+                Some(&mut follower),
+                &kafka_message(digitiser_trace_payload(), 0),
+            )
+            .unwrap()
+        );
+
+        let num_events = undelivered_messages(&mut receiver)
+            .iter()
+            .map(|(_, _, payload)| {
+                root_as_digitizer_event_list_message(payload)
+                    .unwrap()
+                    .time()
+                    .unwrap()
+                    .len()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(num_events, [0]);
+    }
 }
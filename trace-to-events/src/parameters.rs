@@ -1,21 +1,53 @@
 //! Defines the parameters used by the various detectors defined in this component.
 use crate::pulse_detection::Real;
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum, builder::RangedU64ValueParser};
 use digital_muon_common::Intensity;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 #[derive(Debug)]
-pub(crate) struct DetectorSettings<'a> {
+pub struct DetectorSettings<'a> {
     /// The type of detector to use.
-    pub(crate) mode: &'a Mode,
+    pub mode: &'a Mode,
     /// The polarity of the trace signal.
-    pub(crate) polarity: &'a Polarity,
+    pub polarity: &'a Polarity,
     /// The baseline of the trace signal.
-    pub(crate) baseline: Intensity,
+    pub baseline: Intensity,
+    /// If greater than one, the detector is applied to the mean of each block of this many samples,
+    /// in which case its durations, cool-offs and window sizes are in blocks, rather than samples.
+    /// Event times remain in the time units of the original samples.
+    pub downsample_factor: usize,
+    /// If true, the smoothing detector reports event times one sample later, as in previous releases.
+    pub legacy_time_alignment: bool,
+    /// The units of the detector's durations and cool-offs.
+    pub time_units: TimeUnits,
+}
+
+/// Determines the units of the durations and cool-offs of the detectors.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeUnits {
+    /// Durations and cool-offs are numbers of samples, whatever the sample rate of the trace.
+    #[default]
+    Samples,
+    /// Durations and cool-offs are in nanoseconds, and are converted to numbers of samples with the sample rate of each trace message.
+    Ns,
+}
+
+/// Converts `duration`, in ns, to a number of samples, or of blocks of `downsample_factor` samples, at `sample_rate`.
+///
+/// The result is rounded up, so a zero duration remains zero, and any other duration is at least one sample.
+fn ns_to_samples(duration: usize, sample_rate: u64, downsample_factor: usize) -> usize {
+    (duration as u128 * sample_rate as u128)
+        .div_ceil(1_000_000_000 * downsample_factor.max(1) as u128)
+        .try_into()
+        .unwrap_or(usize::MAX)
 }
 
 /// Defines the polarity of the signal, i.e. whether events cause positive or negative signals.
-#[derive(Clone, Copy, Debug, ValueEnum)]
-pub(crate) enum Polarity {
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Polarity {
     /// Detection events register as positive signals.
     Positive,
     /// Detection events register as negative signals.
@@ -23,24 +55,68 @@ pub(crate) enum Polarity {
 }
 
 /// Encapsulates the parameters specific to the Fixed Threshold Discriminator detector.
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct FixedThresholdDiscriminatorParameters {
+#[derive(Default, Debug, Clone, Parser, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FixedThresholdDiscriminatorParameters {
     /// If the detector is armed, an event is registered when the trace passes this value for the given duration.
     #[clap(long)]
-    pub(crate) threshold: Real,
+    pub threshold: Real,
+
+    /// The duration, in samples, that the trace must exceed the threshold for.
+    #[clap(long, default_value_t = defaults::DURATION)]
+    #[serde(default = "serde_defaults::duration")]
+    pub duration: usize,
+
+    /// After an event is registered, the detector disarms until the trace drops back under the disarm threshold, and then for this many samples.
+    #[clap(long, default_value = "0")]
+    #[serde(default)]
+    pub cool_off: usize,
+
+    /// If present, after an event is registered the detector only rearms once the trace drops back under this value, rather than the threshold,
+    /// so ringing on the tail of a pulse does not register further events. This should be closer to the baseline than the threshold.
+    #[clap(long)]
+    #[serde(default)]
+    pub disarm_threshold: Option<Real>,
+
+    /// If present, an event during which the trace passes this value is discarded, rather than registered.
+    #[clap(long)]
+    #[serde(default)]
+    pub veto_threshold: Option<Real>,
+
+    /// After an event is discarded, the detector disarms until the trace drops back under the disarm threshold, and then for this many samples.
+    #[clap(long, default_value = "0")]
+    #[serde(default)]
+    pub veto_extend: usize,
+}
+
+/// Encapsulates the parameters specific to the Adaptive Threshold Discriminator detector.
+#[derive(Default, Debug, Clone, Parser, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AdaptiveThresholdDiscriminatorParameters {
+    /// If the detector is armed, an event is registered when the trace passes this many multiples of the local noise sigma, for the given duration.
+    #[clap(long)]
+    pub sigma_threshold: Real,
+
+    /// The number of preceding samples from which the local noise sigma is estimated, this must be at least 2.
+    /// No events are registered in the first this many samples of a trace.
+    #[clap(long, value_parser = RangedU64ValueParser::<usize>::new().range(2..))]
+    pub noise_window_size: usize,
 
     /// The duration, in samples, that the trace must exceed the threshold for.
-    #[clap(long, default_value = "1")]
-    pub(crate) duration: usize,
+    #[clap(long, default_value_t = defaults::DURATION)]
+    #[serde(default = "serde_defaults::duration")]
+    pub duration: usize,
 
     /// After an event is registered, the detector disarms for this many samples.
     #[clap(long, default_value = "0")]
-    pub(crate) cool_off: usize,
+    #[serde(default)]
+    pub cool_off: usize,
 }
 
 /// Determines how the peak height is calculated.
-#[derive(Default, Debug, Clone, ValueEnum)]
-pub(crate) enum PeakHeightMode {
+#[derive(Default, Debug, Clone, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeakHeightMode {
     /// Take the maximum trace value between begin trigger time and end trigger time.
     #[default]
     MaxValue,
@@ -49,8 +125,9 @@ pub(crate) enum PeakHeightMode {
 }
 
 /// Determines the peak height baseline.
-#[derive(Default, Debug, Clone, ValueEnum)]
-pub(crate) enum PeakHeightBasis {
+#[derive(Default, Debug, Clone, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeakHeightBasis {
     /// The peak height is relative to the trace's baseline.
     #[default]
     TraceBaseline,
@@ -58,109 +135,169 @@ pub(crate) enum PeakHeightBasis {
     PulseBaseline,
 }
 
+/// Determines how the trace derivative is estimated.
+#[derive(Default, Debug, Clone, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DerivativeEstimator {
+    /// Take the difference between consecutive trace values.
+    #[default]
+    FiniteDifference,
+    /// Take the derivative of a polynomial fitted, by least squares, to a window of trace values.
+    /// This is less sensitive to noise than the finite difference, at the cost of broadening pulses.
+    SavitzkyGolay,
+}
+
 /// Encapsulates the parameters specific to the Differential Threshold Discriminator detector.
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct DifferentialThresholdDiscriminatorParameters {
+#[derive(Default, Debug, Clone, Parser, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DifferentialThresholdDiscriminatorParameters {
     /// If the detector is armed, an event is registered when the trace derivative passes this value for the given duration.
     #[clap(long)]
-    pub(crate) begin_threshold: Real,
+    pub begin_threshold: Real,
 
     /// The duration, in samples, that the trace derivative must exceed the begin threshold for a detection to begin.
     #[clap(long, default_value = "0")]
-    pub(crate) begin_duration: usize,
+    #[serde(default)]
+    pub begin_duration: usize,
 
     /// If a detection is in progress, an event is concluded when the trace derivative passes below this value for the given duration.
     #[clap(long)]
-    pub(crate) end_threshold: Real,
+    pub end_threshold: Real,
 
     /// The duration, in samples, that the trace derivative must drop below the end threshold for a detection to end.
     #[clap(long, default_value = "0")]
-    pub(crate) end_duration: usize,
+    #[serde(default)]
+    pub end_duration: usize,
 
     /// After an event is registered, the detector disarms for this many samples.
     #[clap(long, default_value = "0")]
-    pub(crate) cool_off: usize,
+    #[serde(default)]
+    pub cool_off: usize,
 
     /// Determines how the peak height is computed.
     #[clap(long)]
-    pub(crate) peak_height_mode: PeakHeightMode,
+    pub peak_height_mode: PeakHeightMode,
 
     /// Determines how the peak height is computed.
     #[clap(long)]
-    pub(crate) peak_height_basis: PeakHeightBasis,
+    pub peak_height_basis: PeakHeightBasis,
+
+    /// Determines how the trace derivative is estimated.
+    #[clap(long, default_value = "finite-difference")]
+    #[serde(default)]
+    pub derivative_estimator: DerivativeEstimator,
+
+    /// The number of trace values, which must be odd, to which the Savitzky-Golay polynomial is fitted.
+    /// This is only used if `derivative_estimator` is `savitzky-golay`.
+    #[clap(long, default_value_t = defaults::SAVITZKY_GOLAY_WINDOW_LENGTH, value_parser = parse_savitzky_golay_window_length)]
+    #[serde(default = "serde_defaults::savitzky_golay_window_length")]
+    pub savitzky_golay_window_length: usize,
+
+    /// The order of the Savitzky-Golay polynomial, which must be at least one, and less than `savitzky_golay_window_length`.
+    /// This is only used if `derivative_estimator` is `savitzky-golay`.
+    #[clap(long, default_value_t = defaults::SAVITZKY_GOLAY_POLYNOMIAL_ORDER, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    #[serde(default = "serde_defaults::savitzky_golay_polynomial_order")]
+    pub savitzky_golay_polynomial_order: usize,
+
+    /// If set, peak heights are read from the raw trace, at the times of the derivative estimates, rather than from the values
+    /// fitted by the derivative estimator, which smooths narrow pulses, so underestimates their heights.
+    /// With `peak-height-mode` `max-value`, the peak height is then the greatest raw value between the detection's begin and end triggers.
+    #[clap(long)]
+    #[serde(default)]
+    pub peak_from_raw: bool,
+
+    /// If set, pulses opposite to the configured polarity are also detected, by applying the detector to the inverted trace,
+    /// and the sign of each event is given by the `polarity` vector of the event list.
+    /// This is ignored if the detector is used as a multiscaling method.
+    #[clap(long)]
+    #[serde(default)]
+    pub detect_both_polarities: bool,
 }
 
 /// Encapsulates the parameters specific to the Smoothing detector.
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct SmoothingDetectorParameters {
+#[derive(Default, Debug, Clone, Parser, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SmoothingDetectorParameters {
     /// Centile of x to use for noise estimation.
     #[clap(long)]
-    pub(crate) noise_centile: Real,
+    pub noise_centile: Real,
     /// Sigma of the Gaussian kernel for smoothing.
     #[clap(long)]
-    pub(crate) kernel_sigma: Real,
+    pub kernel_sigma: Real,
     /// Number of standard deviations above noise to use as threshold.
     #[clap(long)]
-    pub(crate) nsig_noise: Real,
+    pub nsig_noise: Real,
     /// Minimum size of region to consider a peak, if absent all regions are considered.
     #[clap(long)]
-    pub(crate) min_size: Option<usize>,
+    #[serde(default)]
+    pub min_size: Option<usize>,
     /// If set, then any region at or above this size will be converted to a list of local arg minima, rather than the global arg minimum.
     #[clap(long)]
-    pub(crate) use_local_for_sizes_ge: Option<usize>,
+    #[serde(default)]
+    pub use_local_for_sizes_ge: Option<usize>,
 }
 
 /// Encapsulates the parameters specific to the Multiscaling detector.
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct MultiscalingDetectorParameters {
+#[derive(Default, Debug, Clone, Parser, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MultiscalingDetectorParameters {
     /// Coefficients of the smoothing kernel to apply on downsampling.
-    #[clap(
-        long,
-        default_value = "0.125,0.5,0.75,0.5,0.125",
-        value_delimiter = ','
-    )]
-    pub(crate) downsampling_smoothing: Vec<Real>,
+    #[clap(long, default_values_t = defaults::DOWNSAMPLING_SMOOTHING, value_delimiter = ',')]
+    #[serde(default = "serde_defaults::downsampling_smoothing")]
+    pub downsampling_smoothing: Vec<Real>,
     /// Support of the `downsampling_smoothing` kernel used to compute the `upsampling_smoothing` kernel.
-    #[clap(long, default_value = "-2,-1,0,1,2", value_delimiter = ',')]
-    pub(crate) smoothing_support: Vec<i32>,
+    #[clap(long, default_values_t = defaults::SMOOTHING_SUPPORT, value_delimiter = ',')]
+    #[serde(default = "serde_defaults::smoothing_support")]
+    pub smoothing_support: Vec<i32>,
     /// Amount of padding to use when calculating the `upsampling_smoothing` kernel.
-    #[clap(long, default_value = "200")]
-    pub(crate) fft_padding: usize,
+    #[clap(long, default_value_t = defaults::FFT_PADDING)]
+    #[serde(default = "serde_defaults::fft_padding")]
+    pub fft_padding: usize,
     /// Size of the computed `upsampling_smoothing` kernel.
-    #[clap(long, default_value = "20")]
-    pub(crate) fft_truncation: usize,
+    #[clap(long, default_value_t = defaults::FFT_TRUNCATION)]
+    #[serde(default = "serde_defaults::fft_truncation")]
+    pub fft_truncation: usize,
     /// Number of pyramid layers.
-    #[clap(long, default_value = "4")]
-    pub(crate) number_of_layers: usize,
+    #[clap(long, default_value_t = defaults::NUMBER_OF_LAYERS)]
+    #[serde(default = "serde_defaults::number_of_layers")]
+    pub number_of_layers: usize,
     /// Applies denoise processing if true.
-    #[clap(long, default_value = "true")]
-    pub(crate) denoise: bool,
+    #[clap(long, default_value_t = defaults::DENOISE)]
+    #[serde(default = "serde_defaults::denoise")]
+    pub denoise: bool,
     /// Layer denoise thresholds (if `denoise` is given, then provide `number_of_layers` values in descending order, starting from apex layer).
-    #[clap(long, default_value = "2,2,2,2", value_delimiter = ',')]
-    pub(crate) denoise_thresholds: Vec<Real>,
+    #[clap(long, default_values_t = defaults::DENOISE_THRESHOLDS, value_delimiter = ',')]
+    #[serde(default = "serde_defaults::denoise_thresholds")]
+    pub denoise_thresholds: Vec<Real>,
     /// Applies enhance processing if true.
     #[clap(long, default_value = "false")]
-    pub(crate) enhance: bool,
+    #[serde(default)]
+    pub enhance: bool,
     /// Layer enhance thresholds (if `enhance` is given, then provide `number_of_layers` values in descending order, starting from apex layer).
-    #[clap(long, default_value = "1,1,1,1", value_delimiter = ',')]
-    pub(crate) enhance_thresholds: Vec<Real>,
+    #[clap(long, default_values_t = defaults::ENHANCE_THRESHOLDS, value_delimiter = ',')]
+    #[serde(default = "serde_defaults::enhance_thresholds")]
+    pub enhance_thresholds: Vec<Real>,
     /// Layer enhance factors (if `enhance` is given, then provide `number_of_layers` values in descending order, starting from apex layer).
-    #[clap(long, default_value = "1.1,1.1,1.1,1.1", value_delimiter = ',')]
-    pub(crate) enhance_factors: Vec<Real>,
+    #[clap(long, default_values_t = defaults::LAYER_FACTORS, value_delimiter = ',')]
+    #[serde(default = "serde_defaults::layer_factors")]
+    pub enhance_factors: Vec<Real>,
     /// Applies multiply processing if true.
     #[clap(long)]
-    pub(crate) multiply: bool,
+    #[serde(default)]
+    pub multiply: bool,
     /// Layer multiplication factors (if `multiply` is given, then provide `number_of_layers` values in descending order, starting from apex layer).
-    #[clap(long, default_value = "1.1,1.1,1.1,1.1", value_delimiter = ',')]
-    pub(crate) multiply_factors: Vec<Real>,
+    #[clap(long, default_values_t = defaults::LAYER_FACTORS, value_delimiter = ',')]
+    #[serde(default = "serde_defaults::layer_factors")]
+    pub multiply_factors: Vec<Real>,
     /// The underlying detector method to apply after the multiscaling smoothing has been applied.
     #[command(subcommand)]
-    pub(crate) method: MultiscalingDetectorMethod,
+    pub method: MultiscalingDetectorMethod,
 }
 
 /// Encapsulates the parameters specific to the Smoothing detector.
-#[derive(Debug, Clone, Parser)]
-pub(crate) enum MultiscalingDetectorMethod {
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiscalingDetectorMethod {
     /// Detects events using a fixed threshold discriminator. Event lists consist of time and voltage values.
     FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters),
     /// Detects events using a differential threshold discriminator. Event lists consist of time and voltage values.
@@ -169,6 +306,45 @@ pub(crate) enum MultiscalingDetectorMethod {
     SmoothingDetector(SmoothingDetectorParameters),
 }
 
+impl FixedThresholdDiscriminatorParameters {
+    /// Returns these parameters with each duration converted by `to_samples`.
+    /// The trace must exceed the threshold for at least one sample, so `duration` is at least one.
+    fn in_samples(&self, to_samples: impl Fn(usize) -> usize) -> Self {
+        Self {
+            duration: to_samples(self.duration).max(1),
+            cool_off: to_samples(self.cool_off),
+            veto_extend: to_samples(self.veto_extend),
+            ..self.clone()
+        }
+    }
+}
+
+impl AdaptiveThresholdDiscriminatorParameters {
+    /// Returns these parameters with each duration converted by `to_samples`.
+    /// The trace must exceed the threshold for at least one sample, so `duration` is at least one.
+    /// The noise window size is a number of samples, so is not converted.
+    fn in_samples(&self, to_samples: impl Fn(usize) -> usize) -> Self {
+        Self {
+            duration: to_samples(self.duration).max(1),
+            cool_off: to_samples(self.cool_off),
+            ..self.clone()
+        }
+    }
+}
+
+impl DifferentialThresholdDiscriminatorParameters {
+    /// Returns these parameters with each duration converted by `to_samples`.
+    /// The Savitzky-Golay window length is a number of samples, so is not converted.
+    fn in_samples(&self, to_samples: impl Fn(usize) -> usize) -> Self {
+        Self {
+            begin_duration: to_samples(self.begin_duration),
+            end_duration: to_samples(self.end_duration),
+            cool_off: to_samples(self.cool_off),
+            ..self.clone()
+        }
+    }
+}
+
 impl Default for MultiscalingDetectorMethod {
     fn default() -> Self {
         Self::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters::default())
@@ -176,10 +352,13 @@ impl Default for MultiscalingDetectorMethod {
 }
 
 /// Specifies which detector is to be used, and wraps the detector-specific options in each variant.
-#[derive(Subcommand, Debug)]
-pub(crate) enum Mode {
+#[derive(Subcommand, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
     /// Detects events using a fixed threshold discriminator. Event lists consist of time and voltage values.
     FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters),
+    /// Detects events using a threshold discriminator relative to the local noise level. Event lists consist of time and voltage values.
+    AdaptiveThresholdDiscriminator(AdaptiveThresholdDiscriminatorParameters),
     /// Detects events using a differential threshold discriminator. Event lists consist of time and voltage values.
     DifferentialThresholdDiscriminator(DifferentialThresholdDiscriminatorParameters),
     /// Detects events using a smoothed second derivative. Event lists consist of time and voltage values.
@@ -187,3 +366,518 @@ pub(crate) enum Mode {
     /// Detects events using one of the other methods after applying multiscaling pyramid smoothing. Event lists consist of time and voltage values.
     Multiscaling(MultiscalingDetectorParameters),
 }
+
+impl Mode {
+    /// Returns the mode with its durations and cool-offs, given in ns, converted to numbers of samples of a trace
+    /// with the given sample rate, see [TimeUnits::Ns]. If `downsample_factor` is greater than one,
+    /// they are converted to numbers of blocks of this many samples.
+    ///
+    /// Each duration is rounded up, so a zero duration remains zero, and any other duration is at least one sample,
+    /// except for the `duration` of the fixed and adaptive threshold discriminators, which is always at least one sample.
+    /// The smoothing detector has no durations, so is unchanged.
+    pub(crate) fn in_samples(&self, sample_rate: u64, downsample_factor: usize) -> Self {
+        let to_samples = |duration| ns_to_samples(duration, sample_rate, downsample_factor);
+        match self {
+            Mode::FixedThresholdDiscriminator(parameters) => {
+                Mode::FixedThresholdDiscriminator(parameters.in_samples(to_samples))
+            }
+            Mode::AdaptiveThresholdDiscriminator(parameters) => {
+                Mode::AdaptiveThresholdDiscriminator(parameters.in_samples(to_samples))
+            }
+            Mode::DifferentialThresholdDiscriminator(parameters) => {
+                Mode::DifferentialThresholdDiscriminator(parameters.in_samples(to_samples))
+            }
+            Mode::SmoothingDetector(_) => self.clone(),
+            Mode::Multiscaling(parameters) => Mode::Multiscaling(MultiscalingDetectorParameters {
+                method: match &parameters.method {
+                    MultiscalingDetectorMethod::FixedThresholdDiscriminator(parameters) => {
+                        MultiscalingDetectorMethod::FixedThresholdDiscriminator(
+                            parameters.in_samples(to_samples),
+                        )
+                    }
+                    MultiscalingDetectorMethod::DifferentialThresholdDiscriminator(parameters) => {
+                        MultiscalingDetectorMethod::DifferentialThresholdDiscriminator(
+                            parameters.in_samples(to_samples),
+                        )
+                    }
+                    MultiscalingDetectorMethod::SmoothingDetector(_) => parameters.method.clone(),
+                },
+                ..parameters.clone()
+            }),
+        }
+    }
+
+    /// Returns true if the detector also finds pulses opposite to the configured polarity,
+    /// see [DifferentialThresholdDiscriminatorParameters::detect_both_polarities].
+    pub(crate) fn detects_both_polarities(&self) -> bool {
+        matches!(self, Mode::DifferentialThresholdDiscriminator(parameters) if parameters.detect_both_polarities)
+    }
+}
+
+/// The default of [DetectorSettings::downsample_factor], and of the command line option which gives it.
+pub const DEFAULT_DOWNSAMPLE_FACTOR: usize = 1;
+
+/// The settings of a detector, as [DetectorSettings], but owning its mode, so that it can be replaced whilst the component runs.
+///
+/// It is deserialised from a JSON object whose fields are the kebab-case names of the command line options,
+/// in which `mode` is an object with the detector subcommand as its only key, for instance
+/// `{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}, "polarity": "positive"}`.
+/// Omitted options take the defaults of their command line options.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DetectorConfig {
+    pub mode: Mode,
+    pub polarity: Polarity,
+    #[serde(default)]
+    pub baseline: Intensity,
+    #[serde(default = "serde_defaults::downsample_factor")]
+    pub downsample_factor: usize,
+    #[serde(default)]
+    pub legacy_time_alignment: bool,
+    #[serde(default)]
+    pub time_units: TimeUnits,
+}
+
+/// A setting of a [DetectorConfig] which the detector cannot be created with.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidDetectorConfig {
+    #[error("downsample-factor must be at least one")]
+    DownsampleFactor,
+    #[error("noise-window-size must be at least 2, but is {0}")]
+    NoiseWindowSize(usize),
+    #[error("savitzky-golay-window-length must be odd, but is {0}")]
+    SavitzkyGolayWindowLength(usize),
+    #[error(
+        "savitzky-golay-polynomial-order must be at least one, and less than the window length {1}, but is {0}"
+    )]
+    SavitzkyGolayPolynomialOrder(usize, usize),
+    #[error("{0} must have number-of-layers {1} values, but has {2}")]
+    LayerValues(&'static str, usize, usize),
+}
+
+impl DetectorConfig {
+    /// Returns the settings with which the detector is created.
+    pub fn settings(&self) -> DetectorSettings<'_> {
+        DetectorSettings {
+            mode: &self.mode,
+            polarity: &self.polarity,
+            baseline: self.baseline,
+            downsample_factor: self.downsample_factor,
+            legacy_time_alignment: self.legacy_time_alignment,
+            time_units: self.time_units,
+        }
+    }
+
+    /// Checks the settings which would otherwise cause the detector to panic when it is created,
+    /// and which the command line options check as they are parsed.
+    pub fn validate(&self) -> Result<(), InvalidDetectorConfig> {
+        if self.downsample_factor == 0 {
+            return Err(InvalidDetectorConfig::DownsampleFactor);
+        }
+        match &self.mode {
+            Mode::FixedThresholdDiscriminator(_) | Mode::SmoothingDetector(_) => Ok(()),
+            Mode::AdaptiveThresholdDiscriminator(parameters) => {
+                if parameters.noise_window_size < 2 {
+                    return Err(InvalidDetectorConfig::NoiseWindowSize(
+                        parameters.noise_window_size,
+                    ));
+                }
+                Ok(())
+            }
+            Mode::DifferentialThresholdDiscriminator(parameters) => parameters.validate(),
+            Mode::Multiscaling(parameters) => parameters.validate(),
+        }
+    }
+
+    /// Returns a hash of the settings, which is the same for equal settings in every instance of the component,
+    /// so that dashboards can show which settings each is using. The hash is 32 bits, so that it is exactly representable by a gauge.
+    pub fn fingerprint(&self) -> u32 {
+        serde_json::to_string(self)
+            .unwrap_or_default()
+            .bytes()
+            .fold(0x811c_9dc5, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            })
+    }
+}
+
+impl DifferentialThresholdDiscriminatorParameters {
+    /// Checks the Savitzky-Golay window, if it is used, see [DetectorConfig::validate].
+    fn validate(&self) -> Result<(), InvalidDetectorConfig> {
+        if let DerivativeEstimator::SavitzkyGolay = self.derivative_estimator {
+            let window_length = self.savitzky_golay_window_length;
+            check_savitzky_golay_window_length(window_length)?;
+            let order = self.savitzky_golay_polynomial_order;
+            if order < 1 || order >= window_length {
+                return Err(InvalidDetectorConfig::SavitzkyGolayPolynomialOrder(
+                    order,
+                    window_length,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a Savitzky-Golay window length is odd, as the window is centred on a trace value.
+fn check_savitzky_golay_window_length(window_length: usize) -> Result<(), InvalidDetectorConfig> {
+    if window_length.is_multiple_of(2) {
+        return Err(InvalidDetectorConfig::SavitzkyGolayWindowLength(
+            window_length,
+        ));
+    }
+    Ok(())
+}
+
+/// Parses the `savitzky-golay-window-length` option, see [check_savitzky_golay_window_length].
+///
+/// The polynomial order is checked against the window length by [DetectorConfig::validate],
+/// as a value parser sees only the one option.
+fn parse_savitzky_golay_window_length(
+    value: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let window_length = value.parse()?;
+    check_savitzky_golay_window_length(window_length)?;
+    Ok(window_length)
+}
+
+impl MultiscalingDetectorParameters {
+    /// Checks that there is a value for each layer of each processing step used, and the settings of the method,
+    /// see [DetectorConfig::validate].
+    fn validate(&self) -> Result<(), InvalidDetectorConfig> {
+        for (used, name, values) in [
+            (self.denoise, "denoise-thresholds", &self.denoise_thresholds),
+            (self.enhance, "enhance-thresholds", &self.enhance_thresholds),
+            (self.enhance, "enhance-factors", &self.enhance_factors),
+            (self.multiply, "multiply-factors", &self.multiply_factors),
+        ] {
+            if used && values.len() != self.number_of_layers {
+                return Err(InvalidDetectorConfig::LayerValues(
+                    name,
+                    self.number_of_layers,
+                    values.len(),
+                ));
+            }
+        }
+        match &self.method {
+            MultiscalingDetectorMethod::DifferentialThresholdDiscriminator(parameters) => {
+                parameters.validate()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Wraps a [Mode], so that it can be parsed from the value of a single command line option.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct ModeArgs {
+    #[command(subcommand)]
+    mode: Mode,
+}
+
+/// Parses a [Mode] from a detector subcommand and its options, separated by whitespace,
+/// for instance `fixed-threshold-discriminator --threshold 10`.
+pub fn parse_mode(value: &str) -> Result<Mode, clap::Error> {
+    ModeArgs::try_parse_from(value.split_whitespace()).map(|args| args.mode)
+}
+
+/// Determines the intensity of the event formed by merging events closer together than the dead time, see [EventMerge].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MergePolicy {
+    /// The intensity is the greatest of those of the merged events.
+    #[default]
+    Max,
+    /// The intensity is the sum of those of the merged events, saturating at the largest intensity.
+    Sum,
+    /// The intensity is that of the first of the merged events.
+    First,
+}
+
+/// Determines how events found closer together than the dead time of the electronics, which are physically one pulse, are merged.
+#[derive(Clone, Debug)]
+pub struct EventMerge {
+    /// Consecutive events whose times differ by less than this are merged into one, at the time of the first.
+    /// This is in the units of the detector's durations, see [TimeUnits], though in samples of the trace, rather than blocks, if it is downsampled.
+    pub dead_time: usize,
+    /// Determines the intensity of each merged event.
+    pub policy: MergePolicy,
+}
+
+/// Determines how the events found by a secondary detector are published.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SecondaryOutput {
+    /// The events of both detectors are merged into the one event list, in time order,
+    /// and the `detector` vector of the event list gives the detector which found each event.
+    /// Consumers which ignore the `detector` vector count the events found by both detectors.
+    Tagged,
+    /// The events of the secondary detector are published in a separate event list message, to their own topic.
+    #[default]
+    Topic,
+}
+
+/// The defaults of the parameters which are not those of their types, shared by their command line options and their deserialisation,
+/// so that omitted settings are the same whichever way the detector is configured.
+mod defaults {
+    use super::Real;
+
+    pub(super) const DURATION: usize = 1;
+    pub(super) const SAVITZKY_GOLAY_WINDOW_LENGTH: usize = 11;
+    pub(super) const SAVITZKY_GOLAY_POLYNOMIAL_ORDER: usize = 2;
+    pub(super) const DOWNSAMPLING_SMOOTHING: [Real; 5] = [0.125, 0.5, 0.75, 0.5, 0.125];
+    pub(super) const SMOOTHING_SUPPORT: [i32; 5] = [-2, -1, 0, 1, 2];
+    pub(super) const FFT_PADDING: usize = 200;
+    pub(super) const FFT_TRUNCATION: usize = 20;
+    pub(super) const NUMBER_OF_LAYERS: usize = 4;
+    pub(super) const DENOISE: bool = true;
+    pub(super) const DENOISE_THRESHOLDS: [Real; NUMBER_OF_LAYERS] = [2.0; NUMBER_OF_LAYERS];
+    pub(super) const ENHANCE_THRESHOLDS: [Real; NUMBER_OF_LAYERS] = [1.0; NUMBER_OF_LAYERS];
+    pub(super) const LAYER_FACTORS: [Real; NUMBER_OF_LAYERS] = [1.1; NUMBER_OF_LAYERS];
+}
+
+/// The functions by which the deserialised parameters take their [defaults].
+mod serde_defaults {
+    use super::{Real, defaults};
+
+    pub(super) fn duration() -> usize {
+        defaults::DURATION
+    }
+
+    pub(super) fn downsample_factor() -> usize {
+        super::DEFAULT_DOWNSAMPLE_FACTOR
+    }
+
+    pub(super) fn savitzky_golay_window_length() -> usize {
+        defaults::SAVITZKY_GOLAY_WINDOW_LENGTH
+    }
+
+    pub(super) fn savitzky_golay_polynomial_order() -> usize {
+        defaults::SAVITZKY_GOLAY_POLYNOMIAL_ORDER
+    }
+
+    pub(super) fn downsampling_smoothing() -> Vec<Real> {
+        defaults::DOWNSAMPLING_SMOOTHING.to_vec()
+    }
+
+    pub(super) fn smoothing_support() -> Vec<i32> {
+        defaults::SMOOTHING_SUPPORT.to_vec()
+    }
+
+    pub(super) fn fft_padding() -> usize {
+        defaults::FFT_PADDING
+    }
+
+    pub(super) fn fft_truncation() -> usize {
+        defaults::FFT_TRUNCATION
+    }
+
+    pub(super) fn number_of_layers() -> usize {
+        defaults::NUMBER_OF_LAYERS
+    }
+
+    pub(super) fn denoise() -> bool {
+        defaults::DENOISE
+    }
+
+    pub(super) fn denoise_thresholds() -> Vec<Real> {
+        defaults::DENOISE_THRESHOLDS.to_vec()
+    }
+
+    pub(super) fn enhance_thresholds() -> Vec<Real> {
+        defaults::ENHANCE_THRESHOLDS.to_vec()
+    }
+
+    pub(super) fn layer_factors() -> Vec<Real> {
+        defaults::LAYER_FACTORS.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_is_parsed_from_subcommand() {
+        let mode = parse_mode("fixed-threshold-discriminator --threshold 10 --cool-off 2").unwrap();
+        let Mode::FixedThresholdDiscriminator(parameters) = mode else {
+            panic!("{mode:?}");
+        };
+        assert_eq!(parameters.threshold, 10.0);
+        assert_eq!(parameters.duration, 1);
+        assert_eq!(parameters.cool_off, 2);
+
+        assert!(parse_mode("").is_err());
+        assert!(parse_mode("fixed-threshold-discriminator").is_err());
+        assert!(parse_mode("no-such-detector --threshold 10").is_err());
+    }
+
+    #[test]
+    fn savitzky_golay_options_are_checked_as_parsed() {
+        let mode = |options: &str| {
+            parse_mode(&format!(
+                "differential-threshold-discriminator --begin-threshold 1 --end-threshold 0 --peak-height-mode max-value --peak-height-basis trace-baseline --derivative-estimator savitzky-golay {options}"
+            ))
+        };
+        assert!(
+            mode("--savitzky-golay-window-length 7 --savitzky-golay-polynomial-order 3").is_ok()
+        );
+        assert!(mode("--savitzky-golay-window-length 10").is_err());
+        assert!(mode("--savitzky-golay-window-length seven").is_err());
+        assert!(mode("--savitzky-golay-polynomial-order 0").is_err());
+    }
+
+    #[test]
+    fn ns_durations_are_rounded_up_to_samples() {
+        // At 500MHz each sample is 2ns.
+        assert_eq!(ns_to_samples(0, 500_000_000, 1), 0);
+        assert_eq!(ns_to_samples(1, 500_000_000, 1), 1);
+        assert_eq!(ns_to_samples(4, 500_000_000, 1), 2);
+        assert_eq!(ns_to_samples(5, 500_000_000, 1), 3);
+        assert_eq!(ns_to_samples(5, 500_000_000, 2), 2);
+        // At 300MHz, 10ns is exactly three samples.
+        assert_eq!(ns_to_samples(10, 300_000_000, 1), 3);
+
+        let mode = parse_mode(
+            "differential-threshold-discriminator --begin-threshold 1 --begin-duration 4 --end-threshold 0 --end-duration 0 --cool-off 7 --peak-height-mode max-value --peak-height-basis trace-baseline",
+        )
+        .unwrap();
+        let Mode::DifferentialThresholdDiscriminator(parameters) = mode.in_samples(500_000_000, 1)
+        else {
+            panic!("{mode:?}");
+        };
+        assert_eq!(parameters.begin_duration, 2);
+        assert_eq!(parameters.end_duration, 0);
+        assert_eq!(parameters.cool_off, 4);
+        assert_eq!(parameters.savitzky_golay_window_length, 11);
+    }
+
+    fn detector_config(json: &str) -> DetectorConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn deserialised_defaults_are_those_of_the_command_line() {
+        for (json, command_line) in [
+            (
+                r#"{"fixed-threshold-discriminator": {"threshold": 10}}"#,
+                "fixed-threshold-discriminator --threshold 10",
+            ),
+            (
+                r#"{"adaptive-threshold-discriminator": {"sigma-threshold": 3, "noise-window-size": 8}}"#,
+                "adaptive-threshold-discriminator --sigma-threshold 3 --noise-window-size 8",
+            ),
+            (
+                r#"{"differential-threshold-discriminator": {"begin-threshold": 2, "end-threshold": 1,
+                    "peak-height-mode": "max-value", "peak-height-basis": "pulse-baseline"}}"#,
+                "differential-threshold-discriminator --begin-threshold 2 --end-threshold 1 --peak-height-mode max-value --peak-height-basis pulse-baseline",
+            ),
+            (
+                r#"{"smoothing-detector": {"noise-centile": 0.5, "kernel-sigma": 2, "nsig-noise": 3}}"#,
+                "smoothing-detector --noise-centile 0.5 --kernel-sigma 2 --nsig-noise 3",
+            ),
+            (
+                r#"{"multiscaling": {"method": {"fixed-threshold-discriminator": {"threshold": 10}}}}"#,
+                "multiscaling fixed-threshold-discriminator --threshold 10",
+            ),
+            (
+                r#"{"multiscaling": {"method": {"differential-threshold-discriminator": {"begin-threshold": 2, "end-threshold": 1,
+                    "peak-height-mode": "value-at-end-trigger", "peak-height-basis": "trace-baseline"}}}}"#,
+                "multiscaling differential-threshold-discriminator --begin-threshold 2 --end-threshold 1 --peak-height-mode value-at-end-trigger --peak-height-basis trace-baseline",
+            ),
+            (
+                r#"{"multiscaling": {"method": {"smoothing-detector": {"noise-centile": 0.5, "kernel-sigma": 2, "nsig-noise": 3}}}}"#,
+                "multiscaling smoothing-detector --noise-centile 0.5 --kernel-sigma 2 --nsig-noise 3",
+            ),
+        ] {
+            let deserialised: Mode = serde_json::from_str(json).unwrap();
+            assert_eq!(
+                format!("{deserialised:?}"),
+                format!("{:?}", parse_mode(command_line).unwrap()),
+                "{json}"
+            );
+        }
+
+        let config = detector_config(
+            r#"{"mode": {"smoothing-detector": {"noise-centile": 0.5, "kernel-sigma": 2, "nsig-noise": 3}}, "polarity": "negative"}"#,
+        );
+        assert!(matches!(config.polarity, Polarity::Negative));
+        assert_eq!(config.baseline, 0);
+        assert_eq!(config.downsample_factor, DEFAULT_DOWNSAMPLE_FACTOR);
+        assert!(!config.legacy_time_alignment);
+        assert_eq!(config.time_units, TimeUnits::Samples);
+    }
+
+    #[test]
+    fn unknown_and_missing_settings_are_rejected() {
+        for json in [
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}}"#,
+            r#"{"mode": {"fixed-threshold-discriminator": {}}, "polarity": "positive"}"#,
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshhold": 10}}, "polarity": "positive"}"#,
+            r#"{"mode": {"no-such-detector": {}}, "polarity": "positive"}"#,
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}, "polarity": "sideways"}"#,
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}, "polarity": "positive", "baseline": -1}"#,
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}, "polarity": "positive", "basline": 1}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<DetectorConfig>(json).is_err(),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn settings_which_would_panic_are_invalid() {
+        for (json, error) in [
+            (
+                r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}, "polarity": "positive", "downsample-factor": 0}"#,
+                InvalidDetectorConfig::DownsampleFactor,
+            ),
+            (
+                r#"{"mode": {"adaptive-threshold-discriminator": {"sigma-threshold": 3, "noise-window-size": 1}}, "polarity": "positive"}"#,
+                InvalidDetectorConfig::NoiseWindowSize(1),
+            ),
+            (
+                r#"{"mode": {"differential-threshold-discriminator": {"begin-threshold": 2, "end-threshold": 1,
+                    "peak-height-mode": "max-value", "peak-height-basis": "pulse-baseline",
+                    "derivative-estimator": "savitzky-golay", "savitzky-golay-window-length": 10}}, "polarity": "positive"}"#,
+                InvalidDetectorConfig::SavitzkyGolayWindowLength(10),
+            ),
+            (
+                r#"{"mode": {"multiscaling": {"method": {"differential-threshold-discriminator": {"begin-threshold": 2, "end-threshold": 1,
+                    "peak-height-mode": "max-value", "peak-height-basis": "pulse-baseline",
+                    "derivative-estimator": "savitzky-golay", "savitzky-golay-polynomial-order": 11}}}}, "polarity": "positive"}"#,
+                InvalidDetectorConfig::SavitzkyGolayPolynomialOrder(11, 11),
+            ),
+            (
+                r#"{"mode": {"multiscaling": {"method": {"fixed-threshold-discriminator": {"threshold": 10}},
+                    "number-of-layers": 3}}, "polarity": "positive"}"#,
+                InvalidDetectorConfig::LayerValues("denoise-thresholds", 3, 4),
+            ),
+        ] {
+            assert_eq!(detector_config(json).validate(), Err(error), "{json}");
+        }
+
+        // The Savitzky-Golay window is only checked if it is used.
+        let config = detector_config(
+            r#"{"mode": {"differential-threshold-discriminator": {"begin-threshold": 2, "end-threshold": 1,
+                "peak-height-mode": "max-value", "peak-height-basis": "pulse-baseline",
+                "savitzky-golay-window-length": 10}}, "polarity": "positive"}"#,
+        );
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn fingerprint_identifies_settings() {
+        let config = detector_config(
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 10}}, "polarity": "positive"}"#,
+        );
+        let reordered = detector_config(
+            r#"{"polarity": "positive", "baseline": 0, "mode": {"fixed-threshold-discriminator": {"duration": 1, "threshold": 10}}}"#,
+        );
+        let changed = detector_config(
+            r#"{"mode": {"fixed-threshold-discriminator": {"threshold": 11}}, "polarity": "positive"}"#,
+        );
+        assert_eq!(config.fingerprint(), reordered.fingerprint());
+        assert_ne!(config.fingerprint(), changed.fingerprint());
+    }
+}
@@ -1,41 +1,413 @@
 //! Provides the [process] function which extracts muon events, creates the flatbuffer eventlist messages.
 //!
 //! The function then creates a [DeliveryFuture], and passes it to the kafka producer task.
-use crate::{channels::ChannelState, parameters::DetectorSettings, pulse_detection::Real};
+use crate::{
+    BAD_TIMESTAMPS_METRIC, CHANNEL_BASELINE_METRIC, EVENT_RATE_ANOMALIES_METRIC,
+    EVENTS_FOUND_METRIC, EVENTS_PER_FRAME_METRIC, STAGE_DURATION_METRIC,
+    baselines::{BaselineEstimate, ChannelBaseline, MessageBaselines},
+    channels::{
+        ChannelEvents, ChannelState, DetectedEvents, MalformedChannelTrace, POSITIVE_PULSE,
+    },
+    parameters::{DetectorSettings, EventMerge, SecondaryOutput, TimeUnits},
+    pulse_detection::Real,
+    timestamps::{TimestampCheck, TimestampVerdict},
+};
+use chrono::{DateTime, Utc};
 use digital_muon_common::{
-    Channel, EventData,
+    Channel, EventData, Intensity, Time, failure,
+    ids::{AGGREGATED_DIGITIZER_ID, ChannelNo, DIGITIZER_ID_LABEL, DigitiserName},
+    metrics::failures::FailureKind,
     spanned::{SpanWrapper, Spanned},
+    tracer::SamplingDecision,
 };
 use digital_muon_streaming_types::{
-    dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage,
+    aat2_frame_assembled_analog_trace_v2_generated::FrameAssembledAnalogTraceMessage,
+    aev2_frame_assembled_event_v2_generated::{
+        FrameAssembledEventListMessage, FrameAssembledEventListMessageArgs,
+        finish_frame_assembled_event_list_message_buffer,
+    },
+    dat2_digitizer_analog_trace_v2_generated::{ChannelTrace, DigitizerAnalogTraceMessage},
     dev2_digitizer_event_v2_generated::{
         DigitizerEventListMessage, DigitizerEventListMessageArgs,
         finish_digitizer_event_list_message_buffer,
     },
-    flatbuffers::FlatBufferBuilder,
-    frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args},
+    flatbuffers::{FlatBufferBuilder, ForwardsUOffset, Vector},
+    frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
 };
-use metrics::counter;
+use metrics::{counter, gauge, histogram};
 use rayon::prelude::*;
-use tracing::debug;
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{Span, debug, warn};
+
+/// Returns the label of the bucket of numbers of channels containing `num_channels`,
+/// by which the durations of the `detect` stage are labelled in the [STAGE_DURATION_METRIC] metric.
+pub(crate) fn channel_count_bucket(num_channels: usize) -> &'static str {
+    match num_channels {
+        0 => "0",
+        1..=8 => "1-8",
+        9..=16 => "9-16",
+        17..=32 => "17-32",
+        _ => "33+",
+    }
+}
+
+/// Extracts muon events from a single trace using the provided settings.
+///
+/// The events are those which [DigitiserMessageProcessor] finds in a channel trace with the same voltages, settings and sample time,
+/// so detectors can be tested, or tuned offline, without building trace messages.
+///
+/// # Returns
+/// The times and intensities of the events found.
+///
+/// # Parameters
+/// - trace: the voltages of the trace.
+/// - sample_time: sample time in ns, by which any durations in ns are converted to numbers of samples.
+/// - settings: settings to use for the detector.
+pub fn find_trace_events(
+    trace: &[Intensity],
+    sample_time: Real,
+    settings: &DetectorSettings,
+) -> (Vec<Time>, Vec<Intensity>) {
+    let mut state = ChannelState::new(settings);
+    state.set_sample_rate(Some((1_000_000_000.0 / sample_time).round() as u64));
+    state.find_events(trace.iter().copied(), sample_time)
+}
+
+/// The value of the `detector` vector of an event list for events found by the primary detector.
+pub(crate) const PRIMARY_DETECTOR: u8 = 0;
+/// The value of the `detector` vector of an event list for events found by the secondary detector.
+pub(crate) const SECONDARY_DETECTOR: u8 = 1;
+
+/// Merges the events found in a channel trace by the primary and secondary detectors into time order.
+/// Events found at the same time by both detectors are ordered with those of the primary detector first.
+///
+/// # Parameters
+/// - primary, primary_polarity: the events found by the primary detector, and the sign of the pulse of each.
+/// - secondary, secondary_polarity: the events found by the secondary detector, and the sign of the pulse of each.
+///
+/// # Returns
+/// The times, intensities and polarities of the merged events, and the detector which found each,
+/// which is one of [PRIMARY_DETECTOR] or [SECONDARY_DETECTOR].
+fn merge_detector_events(
+    (primary_time, primary_voltage): DetectedEvents,
+    primary_polarity: Vec<i8>,
+    (secondary_time, secondary_voltage): DetectedEvents,
+    secondary_polarity: Vec<i8>,
+) -> (Vec<Time>, Vec<Intensity>, Vec<u8>, Vec<i8>) {
+    let mut merged = primary_time
+        .into_iter()
+        .zip(primary_voltage)
+        .zip(primary_polarity)
+        .map(|((time, voltage), polarity)| (time, voltage, PRIMARY_DETECTOR, polarity))
+        .chain(
+            secondary_time
+                .into_iter()
+                .zip(secondary_voltage)
+                .zip(secondary_polarity)
+                .map(|((time, voltage), polarity)| (time, voltage, SECONDARY_DETECTOR, polarity)),
+        )
+        .collect::<Vec<_>>();
+    // The sort is stable, so ties keep the primary detector's events first.
+    merged.sort_by_key(|&(time, _, _, _)| time);
+
+    let mut time = Vec::with_capacity(merged.len());
+    let mut voltage = Vec::with_capacity(merged.len());
+    let mut detector = Vec::with_capacity(merged.len());
+    let mut polarity = Vec::with_capacity(merged.len());
+    for (t, v, d, p) in merged {
+        time.push(t);
+        voltage.push(v);
+        detector.push(d);
+        polarity.push(p);
+    }
+    (time, voltage, detector, polarity)
+}
+
+/// Returns the sign of the pulse of each of `num_events` events, as found by a detector.
+/// If the detector does not detect both polarities, every pulse has the configured polarity.
+fn polarity_or_positive(polarity: Option<Vec<i8>>, num_events: usize) -> Vec<i8> {
+    polarity.unwrap_or_else(|| vec![POSITIVE_PULSE; num_events])
+}
+
+/// Returns the number of samples expected of every channel of a message.
+/// This is the most common number among `lengths`, ignoring zero, with ties going to the earliest.
+fn expected_samples(lengths: &[usize]) -> Option<usize> {
+    lengths
+        .iter()
+        .rev()
+        .filter(|&&len| len > 0)
+        .max_by_key(|&&len| lengths.iter().filter(|&&other| other == len).count())
+        .copied()
+}
+
+/// A flatbuffer message of channel traces, in which [DigitiserMessageProcessor] finds events.
+/// This is either the trace message of a single digitiser, or an aggregated frame trace message, assembled from those of every digitiser in a frame.
+pub trait TraceMessage<'a> {
+    /// The metadata of the frame in which the traces were taken.
+    fn metadata(&self) -> FrameMetadataV2<'a>;
+    /// The number of samples per second, or zero if it is not known.
+    fn sample_rate(&self) -> u64;
+    /// The traces of the message's channels.
+    fn channels(&self) -> Option<Vector<'a, ForwardsUOffset<ChannelTrace<'a>>>>;
+    /// Identifies the message in warnings, and is the `digitizer_id` label of its metrics.
+    /// This is the digitiser id, or `aggregated` for an aggregated frame trace message.
+    fn source(&self) -> String;
+}
+
+impl<'a> TraceMessage<'a> for DigitizerAnalogTraceMessage<'a> {
+    fn metadata(&self) -> FrameMetadataV2<'a> {
+        DigitizerAnalogTraceMessage::metadata(self)
+    }
+
+    fn sample_rate(&self) -> u64 {
+        DigitizerAnalogTraceMessage::sample_rate(self)
+    }
+
+    fn channels(&self) -> Option<Vector<'a, ForwardsUOffset<ChannelTrace<'a>>>> {
+        DigitizerAnalogTraceMessage::channels(self)
+    }
+
+    fn source(&self) -> String {
+        DigitiserName(self.digitizer_id()).label_value()
+    }
+}
+
+impl<'a> TraceMessage<'a> for FrameAssembledAnalogTraceMessage<'a> {
+    fn metadata(&self) -> FrameMetadataV2<'a> {
+        FrameAssembledAnalogTraceMessage::metadata(self)
+    }
+
+    fn sample_rate(&self) -> u64 {
+        FrameAssembledAnalogTraceMessage::sample_rate(self)
+    }
+
+    fn channels(&self) -> Option<Vector<'a, ForwardsUOffset<ChannelTrace<'a>>>> {
+        FrameAssembledAnalogTraceMessage::channels(self)
+    }
+
+    fn source(&self) -> String {
+        AGGREGATED_DIGITIZER_ID.to_owned()
+    }
+}
+
+/// The range of the number of events per frame expected of each channel.
+/// A channel which finds fewer or more events than this in a frame is reported as an anomaly.
+#[derive(Default, Debug, Clone)]
+pub struct ExpectedEventRate {
+    /// If set, finding fewer than this many events in a frame is an anomaly.
+    pub min: Option<usize>,
+    /// If set, finding more than this many events in a frame is an anomaly.
+    pub max: Option<usize>,
+}
+
+/// The minimum of an [ExpectedEventRate] is greater than its maximum, so every frame would be an anomaly.
+#[derive(Debug, Error)]
+#[error("min-expected-events-per-frame {0} is greater than max-expected-events-per-frame {1}")]
+pub struct EmptyExpectedEventRate(pub usize, pub usize);
+
+impl ExpectedEventRate {
+    /// Checks that the minimum, if set, is no greater than the maximum, if set.
+    pub fn validate(&self) -> Result<(), EmptyExpectedEventRate> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min > max => Err(EmptyExpectedEventRate(min, max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true if `num_events` lies within the expected range.
+    fn contains(&self, num_events: usize) -> bool {
+        self.min.is_none_or(|min| min <= num_events) && self.max.is_none_or(|max| num_events <= max)
+    }
+}
+
+/// The events found in a trace message by [DigitiserMessageProcessor::find_events], from which its event list messages are built.
+pub struct MessageEvents {
+    /// The events of the event list message, in the order the channels appear in the trace message.
+    pub events: EventData,
+    /// If present, the detector which found each event, see [SecondaryOutput::Tagged].
+    pub detector: Option<Vec<u8>>,
+    /// If present, the sign of the pulse of each event.
+    pub polarity: Option<Vec<i8>>,
+    /// The number of events found in each channel, or how its trace is malformed, in the order the channels appear in the message.
+    pub event_counts: Vec<(Channel, Result<usize, MalformedChannelTrace>)>,
+    /// The events of the secondary detector's event list message, and the sign of the pulse of each if present, see [SecondaryOutput::Topic].
+    secondary: Option<(EventData, Option<Vec<i8>>)>,
+    baselines: Vec<ChannelBaseline>,
+    num_total_pulses: usize,
+    num_total_secondary_pulses: usize,
+}
 
 /// Encapsulates the state objects for multiple channels, and the methods for processing digitiser messages.
-pub(crate) struct DigitiserMessageProcessor {
+pub struct DigitiserMessageProcessor {
     /// Vector of channel states that can be assigned to different cores to be run in parallel.
     channels: Vec<ChannelState>,
+    /// The number of events per frame expected of each channel.
+    expected_event_rate: ExpectedEventRate,
+    /// If present, each channel also has a secondary detector, whose events are published in this way.
+    secondary_output: Option<SecondaryOutput>,
+    /// The secondary detector's event list message of the last trace message processed, if [SecondaryOutput::Topic] is used.
+    secondary_event_list: Option<Vec<u8>>,
+    /// The units of the durations of the primary detector.
+    time_units: TimeUnits,
+    /// If true, the primary detector also finds pulses opposite to the configured polarity.
+    detects_both_polarities: bool,
+    /// If true, the secondary detector also finds pulses opposite to its configured polarity.
+    secondary_detects_both_polarities: bool,
+    /// If true, the baseline of each channel trace is estimated, see [Self::with_baseline_estimate].
+    estimates_baselines: bool,
+    /// The baselines estimated for the last trace message processed, if they are estimated.
+    baselines: Option<MessageBaselines>,
+    /// If present, the timestamp of each trace message is checked, see [Self::check_timestamp].
+    timestamp_check: Option<TimestampCheck>,
+    /// If present, replaces the timestamp of the next trace message processed, see [Self::check_timestamp].
+    rewritten_timestamp: Option<GpsTime>,
 }
 
 impl DigitiserMessageProcessor {
     /// Creates a new `DigitiserMessageProcessor` object, from the given `settings`, and expected number of channels.
     /// # Parameters
     /// - expected_num_channels: the expected number of channels.
-    pub(crate) fn new(expected_num_channels: usize, settings: &DetectorSettings) -> Self {
+    pub fn new(expected_num_channels: usize, settings: &DetectorSettings) -> Self {
         if expected_num_channels == 0 {
             panic!("expected_num_channels should be nonzero, this should never fail.");
         }
         Self {
             channels: vec![ChannelState::new(settings); expected_num_channels],
+            expected_event_rate: Default::default(),
+            secondary_output: None,
+            secondary_event_list: None,
+            time_units: settings.time_units,
+            detects_both_polarities: settings.mode.detects_both_polarities(),
+            secondary_detects_both_polarities: false,
+            estimates_baselines: false,
+            baselines: None,
+            timestamp_check: None,
+            rewritten_timestamp: None,
+        }
+    }
+
+    /// Adds a secondary detector, defined from `settings`, which is applied to every channel trace alongside the primary detector.
+    /// Metrics and the returned number of events of each channel only concern the primary detector.
+    /// # Parameters
+    /// - settings: the settings of the secondary detector.
+    /// - secondary_output: how the secondary detector's events are published.
+    pub fn with_secondary_detector(
+        mut self,
+        settings: &DetectorSettings,
+        secondary_output: SecondaryOutput,
+    ) -> Self {
+        self.channels = self
+            .channels
+            .into_iter()
+            .map(|channel| channel.with_secondary(settings))
+            .collect();
+        self.secondary_output = Some(secondary_output);
+        self.secondary_detects_both_polarities = settings.mode.detects_both_polarities();
+        self
+    }
+
+    /// Replaces the primary detector of every channel with one defined from `settings`, which is used for the next trace message processed.
+    /// The secondary detector, and the estimates of the baselines, are unchanged.
+    pub fn set_detector(&mut self, settings: &DetectorSettings) {
+        for channel in &mut self.channels {
+            channel.set_primary(settings);
+        }
+        self.time_units = settings.time_units;
+        self.detects_both_polarities = settings.mode.detects_both_polarities();
+    }
+
+    /// Takes the secondary detector's event list message of the last trace message processed.
+    /// This is only present if the secondary detector is used with [SecondaryOutput::Topic].
+    pub fn take_secondary_event_list(&mut self) -> Option<Vec<u8>> {
+        self.secondary_event_list.take()
+    }
+
+    /// Estimates the baseline of every channel trace, as given by `estimate`.
+    ///
+    /// Each channel's estimate is recorded to the [CHANNEL_BASELINE_METRIC] metric, and the estimates of the
+    /// last trace message processed are taken by [Self::take_baselines].
+    pub fn with_baseline_estimate(mut self, estimate: &BaselineEstimate) -> Self {
+        self.channels = self
+            .channels
+            .into_iter()
+            .map(|channel| channel.with_baseline_estimate(estimate))
+            .collect();
+        self.estimates_baselines = true;
+        self
+    }
+
+    /// Merges the events found in each channel trace, by both detectors, which are closer together than the dead time of `merge`.
+    /// The events merged into earlier events by the primary detector are counted by the [MERGED_EVENTS_METRIC] metric, labelled by channel.
+    ///
+    /// [MERGED_EVENTS_METRIC]: crate::MERGED_EVENTS_METRIC
+    pub fn with_event_merge(mut self, merge: &EventMerge) -> Self {
+        self.channels = self
+            .channels
+            .into_iter()
+            .map(|channel| channel.with_event_merge(merge))
+            .collect();
+        self
+    }
+
+    /// Takes the baselines estimated for the channels of the last trace message processed.
+    /// These are only present if the baselines are estimated, see [Self::with_baseline_estimate].
+    pub fn take_baselines(&mut self) -> Option<MessageBaselines> {
+        self.baselines.take()
+    }
+
+    /// Sets the number of events per frame expected of each channel, by default any number is expected.
+    pub fn with_expected_event_rate(mut self, expected_event_rate: ExpectedEventRate) -> Self {
+        self.expected_event_rate = expected_event_rate;
+        self
+    }
+
+    /// Checks the timestamp of each trace message before it is processed, see [Self::check_timestamp].
+    pub fn with_timestamp_check(mut self, timestamp_check: TimestampCheck) -> Self {
+        self.timestamp_check = Some(timestamp_check);
+        self
+    }
+
+    /// Checks the timestamp of `trace` against `reference`, see [TimestampCheck::check], if the timestamps are checked.
+    ///
+    /// An implausible timestamp is counted by the [BAD_TIMESTAMPS_METRIC] metric, labelled by [TraceMessage::source] and policy.
+    /// If the verdict is [TimestampVerdict::Rewrite], the timestamp is replaced in the metadata of the event list messages
+    /// of `trace`, which should be the next message processed.
+    ///
+    /// # Returns
+    /// The verdict on the timestamp, which is [TimestampVerdict::Plausible] if the timestamps are not checked.
+    pub fn check_timestamp<'a>(
+        &mut self,
+        trace: &impl TraceMessage<'a>,
+        reference: DateTime<Utc>,
+    ) -> TimestampVerdict {
+        let Some(timestamp_check) = &self.timestamp_check else {
+            return TimestampVerdict::Plausible;
+        };
+        let timestamp = trace
+            .metadata()
+            .timestamp()
+            .copied()
+            .and_then(|timestamp| timestamp.try_into().ok());
+        let verdict = timestamp_check.check(timestamp, reference);
+        if verdict != TimestampVerdict::Plausible {
+            warn!(
+                "Digitiser {} message has implausible timestamp {timestamp:?}, the reference time is {reference}",
+                trace.source()
+            );
+            counter!(
+                BAD_TIMESTAMPS_METRIC,
+                &[
+                    (DIGITIZER_ID_LABEL, trace.source()),
+                    ("policy", timestamp_check.policy.as_str().to_owned()),
+                ]
+            )
+            .increment(1);
+        }
+        if let TimestampVerdict::Rewrite(rewritten) = verdict {
+            self.rewritten_timestamp = Some(rewritten.into());
         }
+        verdict
     }
 
     /// Checks whether the number of channel states is sufficient and resizes if necessary.
@@ -56,29 +428,235 @@ impl DigitiserMessageProcessor {
     /// Extracts a flatbuffer trace message, converts its contents into events using the provided settings,
     /// and creates a flatbuffer eventlist message.
     ///
+    /// Each channel's number of events is recorded to the [EVENTS_FOUND_METRIC] and [EVENTS_PER_FRAME_METRIC] metrics,
+    /// and any channel whose number lies outside the expected event rate is warned of, and counted by [EVENT_RATE_ANOMALIES_METRIC].
+    ///
+    /// A channel whose trace is malformed, that is it has no voltage vector, no samples, or a different number of samples
+    /// from most channels of the message, contributes no events to the event list message. It is warned of,
+    /// and counted by the [FAILURES] metric, and the other channels are processed as normal.
+    /// A message with no channel vector is also counted by the [FAILURES] metric, and produces an empty event list message.
+    ///
+    /// If there is a secondary detector, see [Self::with_secondary_detector], its events are either merged into the event list message,
+    /// with the `detector` vector tagging each event, or built into a separate event list message with the same metadata,
+    /// which is taken by [Self::take_secondary_event_list].
+    ///
     /// # Returns
-    /// The total number of pulses found in all channels.
+    /// The number of events found in each channel, or how its trace is malformed, in the order the channels appear in the message.
     ///
     /// # Parameters
     /// - fbb: a flatbuffer builder object which creates the event list messages.
     /// - trace: the flatbuffer message of the trace.
     /// - detector_settings: settings to use for the detector.
-    #[tracing::instrument(skip_all, fields(num_total_pulses))]
-    pub(crate) fn process<'a>(
+    ///
+    /// If the detectors' durations are in ns, see [TimeUnits::Ns], they are converted to numbers of samples
+    /// with the sample rate of the message. A message with no sample rate is warned of, and counted by the [FAILURES] metric,
+    /// and its durations are taken to be numbers of samples.
+    ///
+    /// If the baselines are estimated, see [Self::with_baseline_estimate], each channel's estimate is set to the
+    /// [CHANNEL_BASELINE_METRIC] metric, labelled by digitiser id and channel, and those of the message are taken by [Self::take_baselines].
+    ///
+    /// The time taken to find the events, labelled by [channel_count_bucket], and to build the event list messages,
+    /// are recorded to the [STAGE_DURATION_METRIC] metric as the `detect` and `build` stages.
+    ///
+    /// [FAILURES]: digital_muon_common::metrics::names::FAILURES
+    pub fn process(
+        &mut self,
+        fbb: &mut FlatBufferBuilder<'_>,
+        trace: &DigitizerAnalogTraceMessage,
+    ) -> Vec<(Channel, Result<usize, MalformedChannelTrace>)> {
+        self.process_sampled(fbb, trace, SamplingDecision::Sampled)
+    }
+
+    /// As [Self::process], but each channel is only processed in its own span if `sampling` is [SamplingDecision::Sampled].
+    ///
+    /// If `sampling` is [SamplingDecision::SampledOnFailure], and the message fails, see [message_failed],
+    /// a span recording the outcome of each channel is created once all channels are processed.
+    /// The durations of these spans do not reflect the time spent processing the channels.
+    #[tracing::instrument(skip_all, fields(num_total_pulses, num_total_secondary_pulses))]
+    pub fn process_sampled(
         &mut self,
-        fbb: &mut FlatBufferBuilder<'a>,
-        trace: &'a DigitizerAnalogTraceMessage,
-    ) -> usize {
-        debug!(
-            "Dig ID: {}, Metadata: {:?}",
-            trace.digitizer_id(),
-            trace.metadata()
+        fbb: &mut FlatBufferBuilder<'_>,
+        trace: &DigitizerAnalogTraceMessage,
+        sampling: SamplingDecision,
+    ) -> Vec<(Channel, Result<usize, MalformedChannelTrace>)> {
+        let MessageEvents {
+            events,
+            detector,
+            polarity,
+            event_counts,
+            secondary,
+            baselines,
+            num_total_pulses,
+            num_total_secondary_pulses,
+        } = self.find_events(trace, sampling);
+
+        let started = Instant::now();
+        let rewritten_timestamp = self.rewritten_timestamp.take();
+        let timestamp = rewritten_timestamp
+            .as_ref()
+            .or_else(|| trace.metadata().timestamp());
+        finish_event_list_message(
+            fbb,
+            trace,
+            timestamp,
+            &events,
+            detector.as_deref(),
+            polarity.as_deref(),
         );
+        if let Some((secondary_events, secondary_polarity)) = secondary {
+            let mut secondary_fbb = FlatBufferBuilder::new();
+            finish_event_list_message(
+                &mut secondary_fbb,
+                trace,
+                timestamp,
+                &secondary_events,
+                None,
+                secondary_polarity.as_deref(),
+            );
+            self.secondary_event_list = Some(secondary_fbb.finished_data().to_vec());
+        }
+        histogram!(STAGE_DURATION_METRIC, "stage" => "build")
+            .record(started.elapsed().as_secs_f64());
+        if self.estimates_baselines {
+            self.baselines = Some(MessageBaselines {
+                digitizer_id: trace.digitizer_id(),
+                frame_number: trace.metadata().frame_number(),
+                timestamp: timestamp
+                    .copied()
+                    .and_then(|timestamp| timestamp.try_into().ok()),
+                baselines,
+            });
+        }
+
+        tracing::Span::current().record("num_total_pulses", num_total_pulses);
+        if self.secondary_output.is_some() {
+            tracing::Span::current()
+                .record("num_total_secondary_pulses", num_total_secondary_pulses);
+        }
+        record_failed_channel_spans(sampling, &event_counts);
+        event_counts
+    }
+
+    /// As [Self::process_sampled], but finds the events of an aggregated frame trace message,
+    /// and builds them into an aggregated frame event list message, with the metadata, completeness and digitisers present of `trace`.
+    ///
+    /// The aggregated frame event list message has no `detector` vector, so if there is a secondary detector
+    /// its events are not published, but its `polarity` vector is given as for digitiser messages. The baselines, if estimated,
+    /// are recorded to the [CHANNEL_BASELINE_METRIC] metric, but are not kept for [Self::take_baselines].
+    /// The metrics of the message are labelled by the `digitizer_id` `aggregated`, see [TraceMessage::source].
+    #[tracing::instrument(skip_all, fields(num_total_pulses))]
+    pub fn process_aggregated(
+        &mut self,
+        fbb: &mut FlatBufferBuilder<'_>,
+        trace: &FrameAssembledAnalogTraceMessage,
+        sampling: SamplingDecision,
+    ) -> Vec<(Channel, Result<usize, MalformedChannelTrace>)> {
+        let MessageEvents {
+            events,
+            detector,
+            polarity,
+            event_counts,
+            num_total_pulses,
+            ..
+        } = self.find_events(trace, sampling);
+
+        let started = Instant::now();
+        let rewritten_timestamp = self.rewritten_timestamp.take();
+        let timestamp = rewritten_timestamp
+            .as_ref()
+            .or_else(|| trace.metadata().timestamp());
+        let (events, polarity) = match detector {
+            // Only the primary detector's events are published, as they cannot be tagged.
+            Some(detector) => {
+                let mut primary_events = EventData::default();
+                let mut primary_polarity = polarity.as_ref().map(|_| Vec::new());
+                for (index, _) in detector
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &detector)| detector == PRIMARY_DETECTOR)
+                {
+                    primary_events.time.push(events.time[index]);
+                    primary_events.voltage.push(events.voltage[index]);
+                    primary_events.channel.push(events.channel[index]);
+                    if let (Some(primary_polarity), Some(polarity)) =
+                        (primary_polarity.as_mut(), polarity.as_ref())
+                    {
+                        primary_polarity.push(polarity[index]);
+                    }
+                }
+                (primary_events, primary_polarity)
+            }
+            None => (events, polarity),
+        };
+        finish_frame_event_list_message(fbb, trace, timestamp, &events, polarity.as_deref());
+        histogram!(STAGE_DURATION_METRIC, "stage" => "build")
+            .record(started.elapsed().as_secs_f64());
+
+        tracing::Span::current().record("num_total_pulses", num_total_pulses);
+        record_failed_channel_spans(sampling, &event_counts);
+        event_counts
+    }
+
+    /// Extracts the events of a flatbuffer trace message, as [Self::process_sampled] does, without building the event list messages.
+    ///
+    /// The same metrics are recorded, and failures warned of, as by [Self::process], except that the time taken to build
+    /// the event list messages is not recorded, and the baselines, if estimated, are not kept for [Self::take_baselines].
+    ///
+    /// # Returns
+    /// The events which the event list messages of `trace` would contain.
+    ///
+    /// # Parameters
+    /// - trace: the flatbuffer message of the trace.
+    /// - sampling: whether each channel is processed in its own span, see [Self::process_sampled].
+    pub fn find_events<'a>(
+        &mut self,
+        trace: &impl TraceMessage<'a>,
+        sampling: SamplingDecision,
+    ) -> MessageEvents {
+        let started = Instant::now();
+        let source = trace.source();
+        debug!("Dig ID: {source}, Metadata: {:?}", trace.metadata());
 
         let sample_time_in_ns: Real = 1_000_000_000.0 / trace.sample_rate() as Real;
+        let sample_rate = match trace.sample_rate() {
+            0 => {
+                if self.time_units == TimeUnits::Ns {
+                    warn!(
+                        "Digitiser {source} message has no sample rate, so durations are taken to be numbers of samples"
+                    );
+                    failure!(
+                        FailureKind::MissingSampleRate,
+                        (DIGITIZER_ID_LABEL, source.clone())
+                    );
+                }
+                None
+            }
+            sample_rate => Some(sample_rate),
+        };
 
-        let channels = trace.channels().unwrap(); // FIXME: We should handle this error
+        let channels = trace.channels().unwrap_or_else(|| {
+            warn!("Digitiser {source} message has no channels");
+            failure!(
+                FailureKind::MissingChannelData,
+                (DIGITIZER_ID_LABEL, source.clone())
+            );
+            Default::default()
+        });
         self.ensure_sufficient_channels(channels.len());
+        for channel in &mut self.channels {
+            channel.set_sample_rate(sample_rate);
+        }
+        let expected_samples = expected_samples(
+            &channels
+                .iter()
+                .map(|channel| {
+                    channel
+                        .voltage()
+                        .map(|voltage| voltage.len())
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>(),
+        );
 
         let vec: Vec<(Channel, _)> = channels
             .iter()
@@ -94,66 +672,298 @@ impl DigitiserMessageProcessor {
 
                 channel_span.in_scope(|| {
                     let channel = spanned_channel_trace.channel();
-                    let events = channel_processor
-                        .find_channel_events(spanned_channel_trace, sample_time_in_ns);
+                    let span = if sampling == SamplingDecision::Sampled {
+                        ChannelState::span(channel)
+                    } else {
+                        Span::none()
+                    };
+                    let events = span.in_scope(|| {
+                        channel_processor.find_channel_events(
+                            spanned_channel_trace,
+                            sample_time_in_ns,
+                            expected_samples,
+                        )
+                    });
                     (channel, events)
                 })
             })
             .collect();
 
         let mut events = EventData::default();
-        for (channel, (time, voltage)) in vec {
+        let mut detector = Vec::new();
+        let mut polarity = Vec::new();
+        let mut secondary_events = EventData::default();
+        let mut secondary_polarity = Vec::new();
+        let mut num_total_pulses = 0;
+        let mut num_total_secondary_pulses = 0;
+        let mut event_counts = Vec::with_capacity(vec.len());
+        let mut baselines = Vec::new();
+        for (channel, channel_events) in vec {
+            let labels = [
+                (DIGITIZER_ID_LABEL, source.clone()),
+                ChannelNo(channel).label(),
+            ];
+            let ChannelEvents {
+                primary: (time, voltage),
+                secondary,
+                polarity: channel_polarity,
+                secondary_polarity: channel_secondary_polarity,
+                baseline,
+            } = match channel_events {
+                Ok(channel_events) => channel_events,
+                Err(e) => {
+                    warn!("Digitiser {source} channel {channel} is malformed: {e}");
+                    let [digitizer_id, channel_label] = labels;
+                    failure!(e.failure_kind(), digitizer_id, channel_label);
+                    event_counts.push((channel, Err(e)));
+                    continue;
+                }
+            };
+            if let Some(baseline) = baseline {
+                gauge!(CHANNEL_BASELINE_METRIC, &labels).set(baseline);
+                baselines.push(ChannelBaseline { channel, baseline });
+            }
             let num_events = voltage.len();
-            counter!(
-                crate::EVENTS_FOUND_METRIC,
-                &[
-                    ("digitizer_id", format!("{}", trace.digitizer_id())),
-                    ("channel", format!("{channel}"))
-                ]
-            )
-            .increment(num_events as u64);
+            counter!(EVENTS_FOUND_METRIC, &labels).increment(num_events as u64);
+            gauge!(EVENTS_PER_FRAME_METRIC, &labels).set(num_events as f64);
+            if !self.expected_event_rate.contains(num_events) {
+                warn!(
+                    "Digitiser {source} channel {channel} found {num_events} events, outside the expected range {:?}",
+                    self.expected_event_rate
+                );
+                counter!(EVENT_RATE_ANOMALIES_METRIC, &labels).increment(1);
+            }
+            event_counts.push((channel, Ok(num_events)));
+            num_total_pulses += num_events;
 
-            events.channel.extend_from_slice(&vec![channel; time.len()]);
-            events.time.extend_from_slice(&time);
-            events.voltage.extend_from_slice(&voltage);
+            let channel_polarity = polarity_or_positive(channel_polarity, num_events);
+            match (secondary, self.secondary_output) {
+                (Some(secondary), Some(SecondaryOutput::Tagged)) => {
+                    num_total_secondary_pulses += secondary.0.len();
+                    let channel_secondary_polarity =
+                        polarity_or_positive(channel_secondary_polarity, secondary.0.len());
+                    let (time, voltage, channel_detector, channel_polarity) = merge_detector_events(
+                        (time, voltage),
+                        channel_polarity,
+                        secondary,
+                        channel_secondary_polarity,
+                    );
+                    push_channel_events(&mut events, channel, &time, &voltage);
+                    detector.extend_from_slice(&channel_detector);
+                    polarity.extend_from_slice(&channel_polarity);
+                }
+                (Some((secondary_time, secondary_voltage)), _) => {
+                    num_total_secondary_pulses += secondary_time.len();
+                    push_channel_events(&mut events, channel, &time, &voltage);
+                    polarity.extend_from_slice(&channel_polarity);
+                    push_channel_events(
+                        &mut secondary_events,
+                        channel,
+                        &secondary_time,
+                        &secondary_voltage,
+                    );
+                    secondary_polarity.extend_from_slice(&polarity_or_positive(
+                        channel_secondary_polarity,
+                        secondary_time.len(),
+                    ));
+                }
+                (None, _) => {
+                    push_channel_events(&mut events, channel, &time, &voltage);
+                    polarity.extend_from_slice(&channel_polarity);
+                }
+            }
         }
 
-        let metadata = FrameMetadataV2Args {
-            frame_number: trace.metadata().frame_number(),
-            period_number: trace.metadata().period_number(),
-            running: trace.metadata().running(),
-            protons_per_pulse: trace.metadata().protons_per_pulse(),
-            timestamp: trace.metadata().timestamp(),
-            veto_flags: trace.metadata().veto_flags(),
-        };
-        let metadata = FrameMetadataV2::create(fbb, &metadata);
+        histogram!(
+            STAGE_DURATION_METRIC,
+            &[
+                ("stage", "detect"),
+                ("channels", channel_count_bucket(channels.len()))
+            ]
+        )
+        .record(started.elapsed().as_secs_f64());
 
-        let time = Some(fbb.create_vector(&events.time));
-        let voltage = Some(fbb.create_vector(&events.voltage));
-        let channel = Some(fbb.create_vector(&events.channel));
+        let detector = (self.secondary_output == Some(SecondaryOutput::Tagged)).then_some(detector);
+        // The polarity vector is present if any of the message's events could be of either polarity.
+        let polarity = (self.detects_both_polarities
+            || (self.secondary_detects_both_polarities
+                && self.secondary_output == Some(SecondaryOutput::Tagged)))
+        .then_some(polarity);
+        let secondary = (self.secondary_output == Some(SecondaryOutput::Topic)).then(|| {
+            (
+                secondary_events,
+                self.secondary_detects_both_polarities
+                    .then_some(secondary_polarity),
+            )
+        });
+        MessageEvents {
+            events,
+            detector,
+            polarity,
+            event_counts,
+            secondary,
+            baselines,
+            num_total_pulses,
+            num_total_secondary_pulses,
+        }
+    }
+}
 
-        let message = DigitizerEventListMessageArgs {
-            digitizer_id: trace.digitizer_id(),
-            metadata: Some(metadata),
-            time,
-            voltage,
-            channel,
-        };
-        let message = DigitizerEventListMessage::create(fbb, &message);
-        finish_digitizer_event_list_message_buffer(fbb, message);
+/// Returns true if a message has failed, that is if any of its channels is malformed, or no events are found in it.
+/// # Parameters
+/// - event_counts: the number of events found in each channel of the message, as returned by [DigitiserMessageProcessor::process].
+pub fn message_failed(event_counts: &[(Channel, Result<usize, MalformedChannelTrace>)]) -> bool {
+    event_counts
+        .iter()
+        .any(|(_, num_events)| num_events.is_err())
+        || event_counts
+            .iter()
+            .filter_map(|(_, num_events)| num_events.as_ref().ok())
+            .sum::<usize>()
+            == 0
+}
 
-        tracing::Span::current().record("num_total_pulses", events.channel.len());
-        events.channel.len()
+/// If `sampling` is [SamplingDecision::SampledOnFailure], and the message has failed, see [message_failed],
+/// creates a span recording the outcome of each channel, see [DigitiserMessageProcessor::process_sampled].
+fn record_failed_channel_spans(
+    sampling: SamplingDecision,
+    event_counts: &[(Channel, Result<usize, MalformedChannelTrace>)],
+) {
+    if sampling == SamplingDecision::SampledOnFailure && message_failed(event_counts) {
+        for (channel, num_events) in event_counts {
+            let span = ChannelState::span(*channel);
+            match num_events {
+                Ok(num_events) => span.record("num_pulses", num_events),
+                Err(e) => span.record("malformed", e.to_string()),
+            };
+        }
     }
 }
 
+/// Appends the events found in `channel` to `events`.
+fn push_channel_events(
+    events: &mut EventData,
+    channel: Channel,
+    time: &[Time],
+    voltage: &[Intensity],
+) {
+    events.channel.extend_from_slice(&vec![channel; time.len()]);
+    events.time.extend_from_slice(time);
+    events.voltage.extend_from_slice(voltage);
+}
+
+/// Builds an event list message of `events`, with the digitiser id and metadata of `trace`.
+/// # Parameters
+/// - fbb: the flatbuffer builder in which the message is finished.
+/// - trace: the trace message in which the events were found.
+/// - timestamp: the timestamp of the message, which is that of `trace` unless it has been rewritten.
+/// - events: the events of the message.
+/// - detector: if present, the detector which found each event.
+/// - polarity: if present, the sign of the pulse of each event.
+fn finish_event_list_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    trace: &DigitizerAnalogTraceMessage,
+    timestamp: Option<&GpsTime>,
+    events: &EventData,
+    detector: Option<&[u8]>,
+    polarity: Option<&[i8]>,
+) {
+    let metadata = FrameMetadataV2Args {
+        frame_number: trace.metadata().frame_number(),
+        period_number: trace.metadata().period_number(),
+        running: trace.metadata().running(),
+        protons_per_pulse: trace.metadata().protons_per_pulse(),
+        timestamp,
+        veto_flags: trace.metadata().veto_flags(),
+    };
+    let metadata = FrameMetadataV2::create(fbb, &metadata);
+
+    let time = Some(fbb.create_vector(&events.time));
+    let voltage = Some(fbb.create_vector(&events.voltage));
+    let channel = Some(fbb.create_vector(&events.channel));
+    let detector = detector.map(|detector| fbb.create_vector(detector));
+    let polarity = polarity.map(|polarity| fbb.create_vector(polarity));
+
+    let message = DigitizerEventListMessageArgs {
+        digitizer_id: trace.digitizer_id(),
+        metadata: Some(metadata),
+        time,
+        voltage,
+        channel,
+        detector,
+        polarity,
+    };
+    let message = DigitizerEventListMessage::create(fbb, &message);
+    finish_digitizer_event_list_message_buffer(fbb, message);
+}
+
+/// Builds an aggregated frame event list message of `events`, with the metadata, completeness and digitisers present of `trace`.
+/// # Parameters
+/// - fbb: the flatbuffer builder in which the message is finished.
+/// - trace: the aggregated frame trace message in which the events were found.
+/// - timestamp: the timestamp of the message, which is that of `trace` unless it has been rewritten.
+/// - events: the events of the message.
+/// - polarity: if present, the sign of the pulse of each event.
+fn finish_frame_event_list_message(
+    fbb: &mut FlatBufferBuilder<'_>,
+    trace: &FrameAssembledAnalogTraceMessage,
+    timestamp: Option<&GpsTime>,
+    events: &EventData,
+    polarity: Option<&[i8]>,
+) {
+    let metadata = FrameMetadataV2Args {
+        frame_number: trace.metadata().frame_number(),
+        period_number: trace.metadata().period_number(),
+        running: trace.metadata().running(),
+        protons_per_pulse: trace.metadata().protons_per_pulse(),
+        timestamp,
+        veto_flags: trace.metadata().veto_flags(),
+    };
+    let metadata = FrameMetadataV2::create(fbb, &metadata);
+
+    let time = Some(fbb.create_vector(&events.time));
+    let voltage = Some(fbb.create_vector(&events.voltage));
+    let channel = Some(fbb.create_vector(&events.channel));
+    let digitizers_present = trace
+        .digitizers_present()
+        .map(|digitizers_present| fbb.create_vector(digitizers_present.bytes()));
+    let polarity = polarity.map(|polarity| fbb.create_vector(polarity));
+
+    let message = FrameAssembledEventListMessageArgs {
+        metadata: Some(metadata),
+        time,
+        voltage,
+        channel,
+        complete: trace.complete(),
+        digitizers_present,
+        polarity,
+    };
+    let message = FrameAssembledEventListMessage::create(fbb, &message);
+    finish_frame_assembled_event_list_message_buffer(fbb, message);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Mode, Polarity, parameters::FixedThresholdDiscriminatorParameters};
-    use chrono::Utc;
-    use digital_muon_common::Intensity;
+    use crate::{
+        BadTimestampPolicy, Mode, Polarity,
+        channels::NEGATIVE_PULSE,
+        parameters::{
+            DifferentialThresholdDiscriminatorParameters, FixedThresholdDiscriminatorParameters,
+            PeakHeightBasis, SmoothingDetectorParameters,
+        },
+        test_subscriber::SpanRecorder,
+        trace_generation::TraceSpec,
+    };
+    use chrono::{DateTime, TimeDelta, Utc};
+    use digital_muon_common::{Intensity, metrics::names::FAILURES};
     use digital_muon_streaming_types::{
+        aat2_frame_assembled_analog_trace_v2_generated::{
+            FrameAssembledAnalogTraceMessageArgs,
+            finish_frame_assembled_analog_trace_message_buffer,
+            root_as_frame_assembled_analog_trace_message,
+        },
+        aev2_frame_assembled_event_v2_generated::root_as_frame_assembled_event_list_message,
         dat2_digitizer_analog_trace_v2_generated::{
             ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
             DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
@@ -165,11 +975,39 @@ mod tests {
         },
         frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
     };
+    use metrics_util::{
+        CompositeKey,
+        debugging::{DebugValue, DebuggingRecorder},
+    };
 
     fn create_message(
         fbb: &mut FlatBufferBuilder<'_>,
         channel_intensities: &[&[Intensity]],
         time: &GpsTime,
+    ) {
+        let channel_intensities = channel_intensities
+            .iter()
+            .copied()
+            .map(Some)
+            .collect::<Vec<_>>();
+        create_message_with_voltages(fbb, &channel_intensities, time);
+    }
+
+    /// Creates a trace message whose channels have the given voltages, a channel with `None` has no voltage vector.
+    fn create_message_with_voltages(
+        fbb: &mut FlatBufferBuilder<'_>,
+        channel_intensities: &[Option<&[Intensity]>],
+        time: &GpsTime,
+    ) {
+        create_message_with_sample_rate(fbb, channel_intensities, time, 1_000_000_000);
+    }
+
+    /// As [create_message_with_voltages], but with the given sample rate.
+    fn create_message_with_sample_rate(
+        fbb: &mut FlatBufferBuilder<'_>,
+        channel_intensities: &[Option<&[Intensity]>],
+        time: &GpsTime,
+        sample_rate: u64,
     ) {
         let metadata = FrameMetadataV2Args {
             frame_number: 0,
@@ -183,7 +1021,7 @@ mod tests {
 
         let channel_vectors: Vec<_> = channel_intensities
             .iter()
-            .map(|intensities| Some(fbb.create_vector::<u16>(intensities)))
+            .map(|intensities| intensities.map(|intensities| fbb.create_vector::<u16>(intensities)))
             .collect();
         let channel_traces: Vec<_> = channel_vectors
             .iter()
@@ -202,7 +1040,7 @@ mod tests {
         let message = DigitizerAnalogTraceMessageArgs {
             digitizer_id: 0,
             metadata: Some(metadata),
-            sample_rate: 1_000_000_000,
+            sample_rate,
             channels: Some(fbb.create_vector(&channel_traces)),
         };
         let message = DigitizerAnalogTraceMessage::create(fbb, &message);
@@ -224,6 +1062,9 @@ mod tests {
             threshold: 5.0,
             duration: 1,
             cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         };
         let mut fbb = FlatBufferBuilder::new();
         DigitiserMessageProcessor::new(
@@ -232,6 +1073,9 @@ mod tests {
                 mode: &Mode::FixedThresholdDiscriminator(test_parameters),
                 polarity: &Polarity::Positive,
                 baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
             },
         )
         .process(&mut fbb, &message);
@@ -274,6 +1118,9 @@ mod tests {
             threshold: 5.0,
             duration: 1,
             cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         };
         let mut fbb = FlatBufferBuilder::new();
         DigitiserMessageProcessor::new(
@@ -282,6 +1129,9 @@ mod tests {
                 mode: &Mode::FixedThresholdDiscriminator(test_parameters),
                 polarity: &Polarity::Positive,
                 baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
             },
         )
         .process(&mut fbb, &message);
@@ -321,6 +1171,9 @@ mod tests {
             threshold: 5.0,
             duration: 1,
             cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         };
         let mut fbb = FlatBufferBuilder::new();
         DigitiserMessageProcessor::new(
@@ -329,6 +1182,9 @@ mod tests {
                 mode: &Mode::FixedThresholdDiscriminator(test_parameters),
                 polarity: &Polarity::Positive,
                 baseline: 3,
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
             },
         )
         .process(&mut fbb, &message);
@@ -368,6 +1224,9 @@ mod tests {
             threshold: 5.0,
             duration: 1,
             cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         };
         let mut fbb = FlatBufferBuilder::new();
         DigitiserMessageProcessor::new(
@@ -376,6 +1235,9 @@ mod tests {
                 mode: &Mode::FixedThresholdDiscriminator(test_parameters),
                 polarity: &Polarity::Negative,
                 baseline: 10,
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
             },
         )
         .process(&mut fbb, &message);
@@ -400,4 +1262,1270 @@ mod tests {
             event_message.voltage().unwrap().iter().collect::<Vec<_>>()
         );
     }
+
+    /// The number of samples of the traces created by [spikes].
+    const SPIKES_SAMPLES: usize = 32;
+
+    /// Creates a trace with `num_spikes` spikes, each of which the fixed threshold discriminator registers as an event.
+    fn spikes(num_spikes: usize) -> Vec<Intensity> {
+        (0..num_spikes)
+            .flat_map(|_| [0, 8, 0])
+            .chain(std::iter::repeat(0))
+            .take(SPIKES_SAMPLES)
+            .collect()
+    }
+
+    /// Processes a message whose channels have the given voltages, with a fixed threshold discriminator,
+    /// and returns the event counts and the event list message created.
+    fn process_voltages(
+        voltages: &[Option<Vec<Intensity>>],
+        expected_event_rate: ExpectedEventRate,
+    ) -> (
+        Vec<(Channel, Result<usize, MalformedChannelTrace>)>,
+        Vec<u8>,
+    ) {
+        process_voltages_sampled(voltages, expected_event_rate, SamplingDecision::Sampled)
+    }
+
+    /// As [process_voltages], with the given sampling decision.
+    fn process_voltages_sampled(
+        voltages: &[Option<Vec<Intensity>>],
+        expected_event_rate: ExpectedEventRate,
+        sampling: SamplingDecision,
+    ) -> (
+        Vec<(Channel, Result<usize, MalformedChannelTrace>)>,
+        Vec<u8>,
+    ) {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = Utc::now().into();
+        let channels = voltages
+            .iter()
+            .map(|voltage| voltage.as_deref())
+            .collect::<Vec<_>>();
+        create_message_with_voltages(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        let event_counts = DigitiserMessageProcessor::new(
+            voltages.len(),
+            &DetectorSettings {
+                mode: &Mode::FixedThresholdDiscriminator(test_parameters),
+                polarity: &Polarity::Positive,
+                baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            },
+        )
+        .with_expected_event_rate(expected_event_rate)
+        .process_sampled(&mut fbb, &message, sampling);
+        (event_counts, fbb.finished_data().to_vec())
+    }
+
+    /// Processes a message whose channels have the given numbers of spikes, see [process_voltages].
+    fn process_spikes(
+        num_spikes: &[usize],
+        expected_event_rate: ExpectedEventRate,
+    ) -> Vec<(Channel, Result<usize, MalformedChannelTrace>)> {
+        let voltages = num_spikes
+            .iter()
+            .map(|&num_spikes| Some(spikes(num_spikes)))
+            .collect::<Vec<_>>();
+        process_voltages(&voltages, expected_event_rate).0
+    }
+
+    /// Returns the channels counted by the counter `name`, and their counts.
+    fn counted_channels(recorder: &DebuggingRecorder, name: &str) -> Vec<(String, u64)> {
+        let mut counted = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let key = CompositeKey::key(&key);
+                let channel = key
+                    .labels()
+                    .find(|label| label.key() == "channel")
+                    .map(|label| label.value().to_owned())?;
+                match value {
+                    DebugValue::Counter(count) if key.name() == name => Some((channel, count)),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        counted.sort();
+        counted
+    }
+
+    /// Returns the channels counted by the [FAILURES] metric, and the kind of failure of each.
+    fn failure_kinds(recorder: &DebuggingRecorder) -> Vec<(String, String)> {
+        let mut kinds = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, _)| {
+                let key = CompositeKey::key(&key);
+                let label = |name| {
+                    key.labels()
+                        .find(|label| label.key() == name)
+                        .map(|label| label.value().to_owned())
+                };
+                (key.name() == FAILURES).then(|| label("channel").zip(label("failure_kind")))?
+            })
+            .collect::<Vec<_>>();
+        kinds.sort();
+        kinds
+    }
+
+    /// Returns the channels counted by the [EVENT_RATE_ANOMALIES_METRIC], and their counts.
+    fn anomalies(recorder: &DebuggingRecorder) -> Vec<(String, u64)> {
+        counted_channels(recorder, EVENT_RATE_ANOMALIES_METRIC)
+    }
+
+    #[test]
+    fn process_returns_event_count_of_each_channel() {
+        let event_counts = process_spikes(&[1, 3, 0, 2], Default::default());
+        assert_eq!(
+            event_counts,
+            vec![(0, Ok(1)), (1, Ok(3)), (2, Ok(0)), (3, Ok(2))]
+        );
+    }
+
+    #[test]
+    fn expected_event_rate_is_validated() {
+        let rate = |min, max| ExpectedEventRate { min, max };
+        assert!(rate(None, None).validate().is_ok());
+        assert!(rate(Some(3), None).validate().is_ok());
+        assert!(rate(None, Some(0)).validate().is_ok());
+        assert!(rate(Some(3), Some(3)).validate().is_ok());
+        assert!(rate(Some(4), Some(3)).validate().is_err());
+    }
+
+    #[test]
+    fn no_anomalies_without_expected_event_rate() {
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            process_spikes(&[0, 5], Default::default());
+        });
+        assert!(anomalies(&recorder).is_empty());
+    }
+
+    #[test]
+    fn anomalies_are_counted_outside_expected_event_rate() {
+        let recorder = DebuggingRecorder::new();
+        let event_counts = metrics::with_local_recorder(&recorder, || {
+            process_spikes(
+                &[0, 1, 2, 3, 4],
+                ExpectedEventRate {
+                    min: Some(1),
+                    max: Some(3),
+                },
+            )
+        });
+        assert_eq!(
+            event_counts,
+            vec![(0, Ok(0)), (1, Ok(1)), (2, Ok(2)), (3, Ok(3)), (4, Ok(4))]
+        );
+        // The bounds themselves are expected.
+        assert_eq!(
+            anomalies(&recorder),
+            vec![("0".to_owned(), 1), ("4".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn anomalies_are_counted_below_minimum_only() {
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            process_spikes(
+                &[1, 2, 9],
+                ExpectedEventRate {
+                    min: Some(2),
+                    max: None,
+                },
+            );
+        });
+        assert_eq!(anomalies(&recorder), vec![("0".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn events_per_frame_gauge_is_set() {
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            process_spikes(&[2, 0], Default::default());
+        });
+        let mut gauges = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match value {
+                DebugValue::Gauge(value)
+                    if CompositeKey::key(&key).name() == EVENTS_PER_FRAME_METRIC =>
+                {
+                    Some(value.into_inner())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        gauges.sort_by(f64::total_cmp);
+        assert_eq!(gauges, vec![0.0, 2.0]);
+    }
+
+    /// Returns the labels of each value recorded to the [STAGE_DURATION_METRIC] histogram, with the number of values.
+    fn stage_durations(recorder: &DebuggingRecorder) -> Vec<(Vec<String>, usize)> {
+        let mut durations = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let key = CompositeKey::key(&key);
+                match value {
+                    DebugValue::Histogram(values) if key.name() == STAGE_DURATION_METRIC => {
+                        assert!(values.iter().all(|value| value.into_inner() >= 0.0));
+                        let labels = key
+                            .labels()
+                            .map(|label| format!("{}={}", label.key(), label.value()))
+                            .collect();
+                        Some((labels, values.len()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        durations.sort();
+        durations
+    }
+
+    #[test]
+    fn stage_durations_are_recorded() {
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            process_spikes(&[2, 0], Default::default());
+            process_spikes(&[1; 12], Default::default());
+        });
+        assert_eq!(
+            stage_durations(&recorder),
+            vec![
+                (vec!["stage=build".to_owned()], 2),
+                (
+                    vec!["stage=detect".to_owned(), "channels=1-8".to_owned()],
+                    1
+                ),
+                (
+                    vec!["stage=detect".to_owned(), "channels=9-16".to_owned()],
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_counts_are_bucketed() {
+        let buckets = [0, 1, 8, 9, 16, 17, 32, 33, 1000].map(channel_count_bucket);
+        assert_eq!(
+            buckets,
+            [
+                "0", "1-8", "1-8", "9-16", "9-16", "17-32", "17-32", "33+", "33+"
+            ]
+        );
+    }
+
+    #[test]
+    fn baselines_are_estimated_from_pre_pulse_samples() {
+        let mut fbb = FlatBufferBuilder::new();
+        let timestamp = "2026-01-01T12:00:00.123456789Z"
+            .parse::<DateTime<Utc>>()
+            .unwrap();
+        let channels: Vec<&[Intensity]> = vec![
+            &[100, 100, 100, 100, 180, 100, 100],
+            &[50, 50, 50, 50, 50, 130, 50],
+        ];
+        create_message(&mut fbb, &channels, &timestamp.into());
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let mut processor = DigitiserMessageProcessor::new(
+            2,
+            &DetectorSettings {
+                mode: &fixed_threshold(5.0, 0),
+                polarity: &Polarity::Positive,
+                baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            },
+        )
+        .with_baseline_estimate(&BaselineEstimate {
+            warm_up: 4,
+            smoothing_factor: 0.1,
+        });
+        let recorder = DebuggingRecorder::new();
+        metrics::with_local_recorder(&recorder, || {
+            processor.process(&mut FlatBufferBuilder::new(), &message);
+        });
+
+        let baselines = processor.take_baselines().unwrap();
+        assert_eq!(
+            baselines,
+            MessageBaselines {
+                digitizer_id: 0,
+                frame_number: 0,
+                timestamp: Some(timestamp),
+                baselines: vec![
+                    ChannelBaseline {
+                        channel: 0,
+                        baseline: 100.0
+                    },
+                    ChannelBaseline {
+                        channel: 1,
+                        baseline: 50.0
+                    },
+                ],
+            }
+        );
+        assert_eq!(processor.take_baselines(), None);
+
+        let payload: serde_json::Value = serde_json::from_slice(&baselines.to_json()).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "digitizer_id": 0,
+                "frame_number": 0,
+                "timestamp": "2026-01-01T12:00:00.123456789Z",
+                "baselines": [
+                    { "channel": 0, "baseline": 100.0 },
+                    { "channel": 1, "baseline": 50.0 },
+                ],
+            })
+        );
+
+        let mut gauges = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match value {
+                DebugValue::Gauge(value)
+                    if CompositeKey::key(&key).name() == CHANNEL_BASELINE_METRIC =>
+                {
+                    let channel = CompositeKey::key(&key)
+                        .labels()
+                        .find(|label| label.key() == "channel")
+                        .map(|label| label.value().to_owned());
+                    Some((channel, value.into_inner()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        gauges.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            gauges,
+            vec![(Some("0".to_owned()), 100.0), (Some("1".to_owned()), 50.0)]
+        );
+    }
+
+    #[test]
+    fn expected_samples_is_most_common_length() {
+        assert_eq!(expected_samples(&[]), None);
+        assert_eq!(expected_samples(&[0, 0]), None);
+        assert_eq!(expected_samples(&[5, 7, 0, 7]), Some(7));
+        assert_eq!(expected_samples(&[0, 10, 32]), Some(10));
+    }
+
+    #[test]
+    fn malformed_channels_are_skipped() {
+        let mut short = spikes(1);
+        short.truncate(10);
+        let recorder = DebuggingRecorder::new();
+        let (event_counts, event_list) = metrics::with_local_recorder(&recorder, || {
+            process_voltages(
+                &[
+                    Some(spikes(2)),
+                    None,
+                    Some(Vec::new()),
+                    Some(short),
+                    Some(spikes(3)),
+                ],
+                Default::default(),
+            )
+        });
+
+        assert_eq!(
+            event_counts,
+            vec![
+                (0, Ok(2)),
+                (1, Err(MalformedChannelTrace::MissingVoltage)),
+                (2, Err(MalformedChannelTrace::NoSamples)),
+                (
+                    3,
+                    Err(MalformedChannelTrace::InconsistentSamples(
+                        10,
+                        SPIKES_SAMPLES
+                    ))
+                ),
+                (4, Ok(3)),
+            ]
+        );
+        assert_eq!(
+            counted_channels(&recorder, FAILURES),
+            vec![
+                ("1".to_owned(), 1),
+                ("2".to_owned(), 1),
+                ("3".to_owned(), 1)
+            ]
+        );
+        assert_eq!(
+            failure_kinds(&recorder),
+            vec![
+                ("1".to_owned(), "missing_channel_data".to_owned()),
+                ("2".to_owned(), "missing_channel_data".to_owned()),
+                ("3".to_owned(), "malformed_channel".to_owned())
+            ]
+        );
+
+        // The malformed channels contribute no events to the event list message.
+        let event_message = root_as_digitizer_event_list_message(&event_list).unwrap();
+        assert_eq!(
+            event_message.channel().unwrap().iter().collect::<Vec<_>>(),
+            vec![0, 0, 4, 4, 4]
+        );
+    }
+
+    #[test]
+    fn message_of_only_empty_channels_is_processed() {
+        let recorder = DebuggingRecorder::new();
+        let (event_counts, event_list) = metrics::with_local_recorder(&recorder, || {
+            process_voltages(&[Some(Vec::new()), Some(Vec::new())], Default::default())
+        });
+        assert_eq!(
+            event_counts,
+            vec![
+                (0, Err(MalformedChannelTrace::NoSamples)),
+                (1, Err(MalformedChannelTrace::NoSamples)),
+            ]
+        );
+        assert_eq!(counted_channels(&recorder, FAILURES).len(), 2);
+
+        let event_message = root_as_digitizer_event_list_message(&event_list).unwrap();
+        assert!(event_message.channel().unwrap().is_empty());
+    }
+
+    #[test]
+    fn message_without_channels_is_processed() {
+        let mut fbb = FlatBufferBuilder::new();
+        let time: GpsTime = Utc::now().into();
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number: 0,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&time),
+                veto_flags: 0,
+            },
+        );
+        let message = DigitizerAnalogTraceMessage::create(
+            &mut fbb,
+            &DigitizerAnalogTraceMessageArgs {
+                digitizer_id: 0,
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels: None,
+            },
+        );
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let recorder = DebuggingRecorder::new();
+        let mut fbb = FlatBufferBuilder::new();
+        let event_counts = metrics::with_local_recorder(&recorder, || {
+            DigitiserMessageProcessor::new(
+                1,
+                &DetectorSettings {
+                    mode: &Mode::FixedThresholdDiscriminator(
+                        FixedThresholdDiscriminatorParameters {
+                            threshold: 5.0,
+                            duration: 1,
+                            cool_off: 0,
+                            disarm_threshold: None,
+                            veto_threshold: None,
+                            veto_extend: 0,
+                        },
+                    ),
+                    polarity: &Polarity::Positive,
+                    baseline: Intensity::default(),
+                    downsample_factor: 1,
+                    legacy_time_alignment: false,
+                    time_units: TimeUnits::Samples,
+                },
+            )
+            .process(&mut fbb, &message)
+        });
+        assert!(event_counts.is_empty());
+
+        let failures = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _, _, _)| {
+                let key = CompositeKey::key(key);
+                key.name() == FAILURES
+                    && key.labels().any(|label| {
+                        label.key() == "failure_kind" && label.value() == "missing_channel_data"
+                    })
+            })
+            .count();
+        assert_eq!(failures, 1);
+
+        let event_message = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+        assert!(event_message.channel().unwrap().is_empty());
+    }
+
+    /// Samples, at `sample_rate`, 60ns of a trace with pulses of height 10 at 10-12ns, 20-26ns, 36-42ns and 50-56ns.
+    fn physical_trace(sample_rate: u64) -> Vec<Intensity> {
+        let sample_time = 1_000_000_000 / sample_rate;
+        (0..60 / sample_time)
+            .map(|index| {
+                let time = index * sample_time;
+                let in_pulse = [(10, 12), (20, 26), (36, 42), (50, 56)]
+                    .iter()
+                    .any(|&(start, end)| (start..end).contains(&time));
+                if in_pulse { 10 } else { 0 }
+            })
+            .collect()
+    }
+
+    /// Returns the times of the events found in a trace message of [physical_trace], sampled at each of `sample_rates` in turn,
+    /// by one processor whose fixed threshold discriminator has a duration of 4 and a cool-off of 20, in `time_units`.
+    fn find_physical_event_times(time_units: TimeUnits, sample_rates: &[u64]) -> Vec<Vec<Time>> {
+        let mut processor = DigitiserMessageProcessor::new(
+            1,
+            &DetectorSettings {
+                mode: &Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                    threshold: 5.0,
+                    duration: 4,
+                    cool_off: 20,
+                    disarm_threshold: None,
+                    veto_threshold: None,
+                    veto_extend: 0,
+                }),
+                polarity: &Polarity::Positive,
+                baseline: Intensity::default(),
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units,
+            },
+        );
+        sample_rates
+            .iter()
+            .map(|&sample_rate| {
+                let mut fbb = FlatBufferBuilder::new();
+                let time: GpsTime = Utc::now().into();
+                let trace = physical_trace(sample_rate);
+                create_message_with_sample_rate(&mut fbb, &[Some(&trace)], &time, sample_rate);
+                let message = fbb.finished_data().to_vec();
+                let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+                let mut fbb = FlatBufferBuilder::new();
+                processor.process(&mut fbb, &message);
+                root_as_digitizer_event_list_message(fbb.finished_data())
+                    .unwrap()
+                    .time()
+                    .unwrap()
+                    .iter()
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ns_durations_are_equivalent_at_each_sample_rate() {
+        // The 2ns pulse is shorter than the duration, and the pulse at 36ns is within the cool-off of that at 20ns.
+        let event_times =
+            find_physical_event_times(TimeUnits::Ns, &[1_000_000_000, 500_000_000, 1_000_000_000]);
+        assert_eq!(event_times, vec![vec![20, 50]; 3]);
+
+        // In samples, the durations at 500MHz are twice as long, so the 6ns pulses are too short.
+        let event_times =
+            find_physical_event_times(TimeUnits::Samples, &[1_000_000_000, 500_000_000]);
+        assert_eq!(event_times, vec![vec![20, 50], vec![]]);
+    }
+
+    #[test]
+    fn missing_sample_rate_falls_back_to_samples() {
+        let mut fbb = FlatBufferBuilder::new();
+        let time: GpsTime = Utc::now().into();
+        let trace = physical_trace(1_000_000_000);
+        create_message_with_sample_rate(&mut fbb, &[Some(&trace)], &time, 0);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let recorder = DebuggingRecorder::new();
+        let mut fbb = FlatBufferBuilder::new();
+        let event_counts = metrics::with_local_recorder(&recorder, || {
+            DigitiserMessageProcessor::new(
+                1,
+                &DetectorSettings {
+                    mode: &Mode::FixedThresholdDiscriminator(
+                        FixedThresholdDiscriminatorParameters {
+                            threshold: 5.0,
+                            duration: 4,
+                            cool_off: 20,
+                            disarm_threshold: None,
+                            veto_threshold: None,
+                            veto_extend: 0,
+                        },
+                    ),
+                    polarity: &Polarity::Positive,
+                    baseline: Intensity::default(),
+                    downsample_factor: 1,
+                    legacy_time_alignment: false,
+                    time_units: TimeUnits::Ns,
+                },
+            )
+            .process(&mut fbb, &message)
+        });
+        // The pulses at 20ns and 50ns are found, as with a sample rate of 1GHz.
+        assert_eq!(event_counts, vec![(0, Ok(2))]);
+
+        let failures = recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _, _, _)| {
+                let key = CompositeKey::key(key);
+                key.name() == FAILURES
+                    && key.labels().any(|label| {
+                        label.key() == "failure_kind" && label.value() == "missing_sample_rate"
+                    })
+            })
+            .count();
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn detector_events_are_merged_in_time_order() {
+        let (time, voltage, detector, polarity) = merge_detector_events(
+            (vec![1, 4, 9], vec![10, 40, 90]),
+            vec![POSITIVE_PULSE, NEGATIVE_PULSE, POSITIVE_PULSE],
+            (vec![0, 4, 5, 12], vec![1, 4, 5, 12]),
+            vec![POSITIVE_PULSE; 4],
+        );
+        assert_eq!(time, vec![0, 1, 4, 4, 5, 9, 12]);
+        assert_eq!(voltage, vec![1, 10, 40, 4, 5, 90, 12]);
+        // Each event keeps the polarity of its pulse.
+        assert_eq!(polarity, vec![1, 1, NEGATIVE_PULSE, 1, 1, 1, 1]);
+        // The primary detector's event comes first at time 4.
+        assert_eq!(
+            detector,
+            vec![
+                SECONDARY_DETECTOR,
+                PRIMARY_DETECTOR,
+                PRIMARY_DETECTOR,
+                SECONDARY_DETECTOR,
+                SECONDARY_DETECTOR,
+                PRIMARY_DETECTOR,
+                SECONDARY_DETECTOR
+            ]
+        );
+    }
+
+    fn fixed_threshold(threshold: Real, cool_off: usize) -> Mode {
+        Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold,
+            duration: 1,
+            cool_off,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        })
+    }
+
+    /// A trace whose spikes alternate in height, so the two detectors of [process_with_secondary] find different events.
+    fn alternating_spikes(offset: usize) -> Vec<Intensity> {
+        (0..SPIKES_SAMPLES)
+            .map(|i| match (i + offset) % 5 {
+                1 => 8,
+                3 => 20,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    /// Processes a message whose channels have the given voltages, with a primary detector,
+    /// and a secondary detector if `secondary` is present.
+    ///
+    /// # Returns
+    /// The event list message, and the secondary detector's event list message if there is one.
+    fn process_with_secondary(
+        voltages: &[Vec<Intensity>],
+        primary: &Mode,
+        secondary: Option<(&Mode, SecondaryOutput)>,
+    ) -> (Vec<u8>, Option<Vec<u8>>) {
+        let mut fbb = FlatBufferBuilder::new();
+        let time: GpsTime = Utc::now().into();
+        let channels = voltages.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        create_message(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let settings = |mode| DetectorSettings {
+            mode,
+            polarity: &Polarity::Positive,
+            baseline: Intensity::default(),
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        };
+        let mut processor = DigitiserMessageProcessor::new(voltages.len(), &settings(primary));
+        if let Some((mode, secondary_output)) = secondary {
+            processor = processor.with_secondary_detector(&settings(mode), secondary_output);
+        }
+        let mut fbb = FlatBufferBuilder::new();
+        processor.process(&mut fbb, &message);
+        (
+            fbb.finished_data().to_vec(),
+            processor.take_secondary_event_list(),
+        )
+    }
+
+    /// The events of an event list message, as `(channel, time, voltage)`, and the detector vector if present.
+    type EventListVectors = (Vec<(Channel, Time, Intensity)>, Option<Vec<u8>>);
+
+    fn event_list_vectors(event_list: &[u8]) -> EventListVectors {
+        let message = root_as_digitizer_event_list_message(event_list).unwrap();
+        let events = message
+            .channel()
+            .unwrap()
+            .iter()
+            .zip(message.time().unwrap().iter())
+            .zip(message.voltage().unwrap().iter())
+            .map(|((channel, time), voltage)| (channel, time, voltage))
+            .collect();
+        let detector = message.detector().map(|detector| detector.iter().collect());
+        (events, detector)
+    }
+
+    #[test]
+    fn tagged_secondary_events_are_merged_into_event_list() {
+        let voltages = [alternating_spikes(0), alternating_spikes(2)];
+        let primary = fixed_threshold(5.0, 0);
+        let secondary = fixed_threshold(10.0, 0);
+        let (event_list, secondary_event_list) = process_with_secondary(
+            &voltages,
+            &primary,
+            Some((&secondary, SecondaryOutput::Tagged)),
+        );
+        assert!(secondary_event_list.is_none());
+
+        let (events, detector) = event_list_vectors(&event_list);
+        let detector = detector.unwrap();
+        assert_eq!(events.len(), detector.len());
+        // Each channel's events are in time order, and both detectors contribute to each channel.
+        for channel in [0, 1] {
+            let times = events
+                .iter()
+                .filter(|(c, _, _)| *c == channel)
+                .map(|(_, time, _)| *time)
+                .collect::<Vec<_>>();
+            assert!(times.is_sorted(), "{times:?}");
+        }
+        assert!(detector.contains(&PRIMARY_DETECTOR));
+        assert!(detector.contains(&SECONDARY_DETECTOR));
+
+        // The events tagged by each detector are exactly those it finds alone.
+        for (mode, tag) in [
+            (&primary, PRIMARY_DETECTOR),
+            (&secondary, SECONDARY_DETECTOR),
+        ] {
+            let (alone, alone_detector) =
+                event_list_vectors(&process_with_secondary(&voltages, mode, None).0);
+            assert!(alone_detector.is_none());
+            let tagged = events
+                .iter()
+                .zip(&detector)
+                .filter(|(_, d)| **d == tag)
+                .map(|(event, _)| *event)
+                .collect::<Vec<_>>();
+            assert_eq!(tagged, alone);
+        }
+    }
+
+    #[test]
+    fn secondary_events_are_routed_to_separate_event_list() {
+        let voltages = [alternating_spikes(0), alternating_spikes(2)];
+        let primary = fixed_threshold(5.0, 0);
+        let secondary = fixed_threshold(10.0, 0);
+        let (event_list, secondary_event_list) = process_with_secondary(
+            &voltages,
+            &primary,
+            Some((&secondary, SecondaryOutput::Topic)),
+        );
+        let secondary_event_list = secondary_event_list.unwrap();
+
+        let alone = |mode| event_list_vectors(&process_with_secondary(&voltages, mode, None).0);
+        assert_eq!(event_list_vectors(&event_list), alone(&primary));
+        assert_eq!(event_list_vectors(&secondary_event_list), alone(&secondary));
+
+        let message = root_as_digitizer_event_list_message(&event_list).unwrap();
+        let secondary_message =
+            root_as_digitizer_event_list_message(&secondary_event_list).unwrap();
+        assert_eq!(message.metadata(), secondary_message.metadata());
+        assert_eq!(message.digitizer_id(), secondary_message.digitizer_id());
+    }
+
+    /// Processes a trace message with timestamp `time`, after checking it against `reference` with `policy`.
+    ///
+    /// # Returns
+    /// The verdict, the timestamps of the event list and secondary event list messages, and those of a second message
+    /// processed without a check.
+    fn process_checked_timestamp(
+        time: DateTime<Utc>,
+        reference: DateTime<Utc>,
+        policy: BadTimestampPolicy,
+    ) -> (TimestampVerdict, [DateTime<Utc>; 3]) {
+        let mut fbb = FlatBufferBuilder::new();
+        create_message(&mut fbb, &[alternating_spikes(0).as_slice()], &time.into());
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let mode = fixed_threshold(5.0, 0);
+        let settings = DetectorSettings {
+            mode: &mode,
+            polarity: &Polarity::Positive,
+            baseline: Intensity::default(),
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        };
+        let mut processor = DigitiserMessageProcessor::new(1, &settings)
+            .with_secondary_detector(&settings, SecondaryOutput::Topic)
+            .with_timestamp_check(TimestampCheck {
+                max_skew: TimeDelta::seconds(60),
+                policy,
+            });
+        let timestamp = |event_list: &[u8]| -> DateTime<Utc> {
+            let message = root_as_digitizer_event_list_message(event_list).unwrap();
+            (*message.metadata().timestamp().unwrap())
+                .try_into()
+                .unwrap()
+        };
+
+        let verdict = processor.check_timestamp(&message, reference);
+        let mut fbb = FlatBufferBuilder::new();
+        processor.process(&mut fbb, &message);
+        let primary = timestamp(fbb.finished_data());
+        let secondary = timestamp(&processor.take_secondary_event_list().unwrap());
+        let mut fbb = FlatBufferBuilder::new();
+        processor.process(&mut fbb, &message);
+        (
+            verdict,
+            [primary, secondary, timestamp(fbb.finished_data())],
+        )
+    }
+
+    #[test]
+    fn implausible_timestamps_are_handled_by_policy() {
+        let reference: DateTime<Utc> = "2025-06-01T12:00:00Z".parse().unwrap();
+        // The earliest time a `GpsTime` can represent, and ten years after the reference.
+        let implausible: [DateTime<Utc>; 2] = [
+            "2000-01-01T00:00:00Z".parse().unwrap(),
+            "2035-06-01T12:00:00Z".parse().unwrap(),
+        ];
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            for time in implausible {
+                // A rewrite applies to the event lists of the message checked, but not to later messages.
+                assert_eq!(
+                    process_checked_timestamp(time, reference, BadTimestampPolicy::Rewrite),
+                    (
+                        TimestampVerdict::Rewrite(reference),
+                        [reference, reference, time]
+                    )
+                );
+                for (policy, verdict) in [
+                    (BadTimestampPolicy::Reject, TimestampVerdict::Reject),
+                    (BadTimestampPolicy::Forward, TimestampVerdict::Forward),
+                ] {
+                    assert_eq!(
+                        process_checked_timestamp(time, reference, policy),
+                        (verdict, [time; 3])
+                    );
+                }
+            }
+            assert_eq!(
+                process_checked_timestamp(reference, reference, BadTimestampPolicy::Rewrite),
+                (TimestampVerdict::Plausible, [reference; 3])
+            );
+        });
+
+        let mut counted = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let key: CompositeKey = key;
+                (key.key().name() == BAD_TIMESTAMPS_METRIC).then(|| {
+                    let policy = key
+                        .key()
+                        .labels()
+                        .find(|label| label.key() == "policy")
+                        .unwrap()
+                        .value()
+                        .to_owned();
+                    (policy, value)
+                })
+            })
+            .collect::<Vec<_>>();
+        counted.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            counted,
+            [
+                ("forward".to_owned(), DebugValue::Counter(2)),
+                ("reject".to_owned(), DebugValue::Counter(2)),
+                ("rewrite".to_owned(), DebugValue::Counter(2)),
+            ]
+        );
+    }
+
+    /// A trace with a positive lobe at sample 10 followed by a mirrored negative lobe at sample 30, about a level of 1000.
+    fn bipolar_pulse() -> Vec<Intensity> {
+        (0..48)
+            .map(|i| match i {
+                10..20 => 1100 - 10 * (i - 10),
+                30..40 => 900 + 10 * (i - 30),
+                _ => 1000,
+            })
+            .collect()
+    }
+
+    /// A differential detector for [bipolar_pulse], whose peak heights are relative to the pulse's baseline,
+    /// as [process_with_secondary] uses a trace baseline of zero.
+    fn bipolar_differential(detect_both_polarities: bool) -> Mode {
+        Mode::DifferentialThresholdDiscriminator(DifferentialThresholdDiscriminatorParameters {
+            begin_threshold: 50.0,
+            begin_duration: 1,
+            end_threshold: -5.0,
+            end_duration: 1,
+            peak_height_basis: PeakHeightBasis::PulseBaseline,
+            detect_both_polarities,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the polarity vector of an event list message, if present.
+    fn event_list_polarity(event_list: &[u8]) -> Option<Vec<i8>> {
+        root_as_digitizer_event_list_message(event_list)
+            .unwrap()
+            .polarity()
+            .map(|polarity| polarity.iter().collect())
+    }
+
+    #[test]
+    fn event_polarities_are_published() {
+        let voltages = [bipolar_pulse(), bipolar_pulse()];
+
+        let (event_list, _) = process_with_secondary(&voltages, &bipolar_differential(false), None);
+        assert_eq!(event_list_polarity(&event_list), None);
+        assert_eq!(event_list_vectors(&event_list).0.len(), 2);
+
+        let (event_list, _) = process_with_secondary(&voltages, &bipolar_differential(true), None);
+        let (events, _) = event_list_vectors(&event_list);
+        let times = events.iter().map(|(_, time, _)| *time).collect::<Vec<_>>();
+        assert_eq!(times[1], times[0] + 20);
+        assert_eq!(times[2..], times[..2]);
+        // The intensities of both lobes are their magnitudes.
+        assert!(events.iter().all(|(_, _, voltage)| *voltage == events[0].2));
+        assert_eq!(
+            event_list_polarity(&event_list),
+            Some(vec![
+                POSITIVE_PULSE,
+                NEGATIVE_PULSE,
+                POSITIVE_PULSE,
+                NEGATIVE_PULSE
+            ])
+        );
+
+        // A secondary detector's polarities are published with its events.
+        let (event_list, secondary_event_list) = process_with_secondary(
+            &voltages,
+            &bipolar_differential(false),
+            Some((&bipolar_differential(true), SecondaryOutput::Topic)),
+        );
+        assert_eq!(event_list_polarity(&event_list), None);
+        assert_eq!(
+            event_list_polarity(&secondary_event_list.unwrap()).map(|polarity| polarity.len()),
+            Some(4)
+        );
+
+        let (event_list, _) = process_with_secondary(
+            &voltages,
+            &bipolar_differential(false),
+            Some((&bipolar_differential(true), SecondaryOutput::Tagged)),
+        );
+        let (_, detector) = event_list_vectors(&event_list);
+        let tags = detector
+            .unwrap()
+            .into_iter()
+            .zip(event_list_polarity(&event_list).unwrap())
+            .collect::<Vec<_>>();
+        let channel_tags = vec![
+            (PRIMARY_DETECTOR, POSITIVE_PULSE),
+            (SECONDARY_DETECTOR, POSITIVE_PULSE),
+            (SECONDARY_DETECTOR, NEGATIVE_PULSE),
+        ];
+        assert_eq!(tags, [channel_tags.clone(), channel_tags].concat());
+    }
+
+    #[test]
+    fn aggregated_event_polarities_are_published() {
+        let voltages = [bipolar_pulse(), bipolar_pulse()];
+        let mut fbb = FlatBufferBuilder::new();
+        let time: GpsTime = Utc::now().into();
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                timestamp: Some(&time),
+                ..Default::default()
+            },
+        );
+        let channels = voltages
+            .iter()
+            .enumerate()
+            .map(|(channel, voltage)| {
+                let voltage = Some(fbb.create_vector(voltage));
+                ChannelTrace::create(
+                    &mut fbb,
+                    &ChannelTraceArgs {
+                        channel: channel as Channel,
+                        voltage,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let channels = Some(fbb.create_vector(&channels));
+        let message = FrameAssembledAnalogTraceMessage::create(
+            &mut fbb,
+            &FrameAssembledAnalogTraceMessageArgs {
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels,
+                complete: true,
+                digitizers_present: None,
+            },
+        );
+        finish_frame_assembled_analog_trace_message_buffer(&mut fbb, message);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_frame_assembled_analog_trace_message(&message).unwrap();
+
+        let settings = |mode| DetectorSettings {
+            mode,
+            polarity: &Polarity::Positive,
+            baseline: Intensity::default(),
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units: TimeUnits::Samples,
+        };
+        let aggregated_polarity = |processor: &mut DigitiserMessageProcessor| {
+            let mut fbb = FlatBufferBuilder::new();
+            processor.process_aggregated(&mut fbb, &message, SamplingDecision::Sampled);
+            root_as_frame_assembled_event_list_message(fbb.finished_data())
+                .unwrap()
+                .polarity()
+                .map(|polarity| polarity.iter().collect::<Vec<_>>())
+        };
+
+        let mut processor =
+            DigitiserMessageProcessor::new(voltages.len(), &settings(&bipolar_differential(false)));
+        assert_eq!(aggregated_polarity(&mut processor), None);
+
+        let mut processor =
+            DigitiserMessageProcessor::new(voltages.len(), &settings(&bipolar_differential(true)));
+        let channel_polarity = [POSITIVE_PULSE, NEGATIVE_PULSE];
+        assert_eq!(
+            aggregated_polarity(&mut processor),
+            Some(channel_polarity.repeat(2))
+        );
+
+        // Only the polarities of the primary detector's events are published with them.
+        let mut processor =
+            DigitiserMessageProcessor::new(voltages.len(), &settings(&bipolar_differential(false)))
+                .with_secondary_detector(
+                    &settings(&bipolar_differential(true)),
+                    SecondaryOutput::Tagged,
+                );
+        assert_eq!(
+            aggregated_polarity(&mut processor),
+            Some(vec![POSITIVE_PULSE; 2])
+        );
+    }
+
+    #[test]
+    fn message_failures() {
+        assert!(!message_failed(&[(0, Ok(2)), (1, Ok(0))]));
+        assert!(message_failed(&[(0, Ok(0)), (1, Ok(0))]));
+        assert!(message_failed(&[
+            (0, Ok(2)),
+            (1, Err(MalformedChannelTrace::NoSamples))
+        ]));
+        assert!(message_failed(&[]));
+    }
+
+    /// Returns the number of channel spans created on the current thread whilst processing the given voltages.
+    /// Spans created by the threads which process each channel are not seen.
+    fn num_channel_spans(voltages: &[Option<Vec<Intensity>>], sampling: SamplingDecision) -> usize {
+        let recorder = SpanRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            process_voltages_sampled(voltages, Default::default(), sampling)
+        });
+        recorder
+            .names()
+            .into_iter()
+            .filter(|&name| name == "find_channel_events")
+            .count()
+    }
+
+    #[test]
+    fn failures_force_channel_spans() {
+        let healthy = [Some(spikes(2)), Some(spikes(1))];
+        let malformed = [Some(spikes(2)), None];
+        let no_events = [Some(spikes(0)), Some(spikes(0))];
+
+        assert_eq!(
+            num_channel_spans(&healthy, SamplingDecision::SampledOnFailure),
+            0
+        );
+        assert_eq!(
+            num_channel_spans(&malformed, SamplingDecision::SampledOnFailure),
+            2
+        );
+        assert_eq!(
+            num_channel_spans(&no_events, SamplingDecision::SampledOnFailure),
+            2
+        );
+
+        assert_eq!(
+            num_channel_spans(&malformed, SamplingDecision::Unsampled),
+            0
+        );
+        assert_eq!(
+            num_channel_spans(&no_events, SamplingDecision::Unsampled),
+            0
+        );
+    }
+
+    /// Returns the events found in each channel of a message of noisy traces by [DigitiserMessageProcessor],
+    /// and by [find_trace_events] applied to the voltages of each channel in turn.
+    fn message_and_trace_events(
+        mode: &Mode,
+        time_units: TimeUnits,
+        sample_rate: u64,
+    ) -> (
+        Vec<(Channel, Time, Intensity)>,
+        Vec<(Channel, Time, Intensity)>,
+    ) {
+        let settings = DetectorSettings {
+            mode,
+            polarity: &Polarity::Negative,
+            baseline: 2000,
+            downsample_factor: 1,
+            legacy_time_alignment: false,
+            time_units,
+        };
+        let voltages = (0..4)
+            .map(|seed| {
+                let trace = TraceSpec {
+                    length: 1000,
+                    baseline: 0,
+                    pulse_spacing: 60 + 20 * seed as usize,
+                    pulse_amplitude: 1000.0,
+                    noise_sigma: 3.0,
+                }
+                .generate(seed);
+                // The pulses are inverted below the baseline, as negative polarity traces are.
+                trace.into_iter().map(|v| 2000 - v).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut fbb = FlatBufferBuilder::new();
+        let time: GpsTime = Utc::now().into();
+        let channels = voltages
+            .iter()
+            .map(|voltage| Some(voltage.as_slice()))
+            .collect::<Vec<_>>();
+        create_message_with_sample_rate(&mut fbb, &channels, &time, sample_rate);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        DigitiserMessageProcessor::new(voltages.len(), &settings).process(&mut fbb, &message);
+        let (message_events, _) = event_list_vectors(fbb.finished_data());
+
+        let sample_time = 1_000_000_000.0 / sample_rate as Real;
+        let trace_events = voltages
+            .iter()
+            .enumerate()
+            .flat_map(|(channel, voltage)| {
+                let (times, intensities) = find_trace_events(voltage, sample_time, &settings);
+                times
+                    .into_iter()
+                    .zip(intensities)
+                    .map(move |(time, intensity)| (channel as Channel, time, intensity))
+            })
+            .collect();
+        (message_events, trace_events)
+    }
+
+    #[test]
+    fn message_events_match_trace_events() {
+        let modes = [
+            fixed_threshold(40.0, 4),
+            Mode::DifferentialThresholdDiscriminator(
+                DifferentialThresholdDiscriminatorParameters {
+                    begin_threshold: 20.0,
+                    end_threshold: -10.0,
+                    ..Default::default()
+                },
+            ),
+            Mode::SmoothingDetector(SmoothingDetectorParameters {
+                noise_centile: 50.0,
+                kernel_sigma: 1.0,
+                nsig_noise: 3.0,
+                ..Default::default()
+            }),
+        ];
+        for mode in &modes {
+            for time_units in [TimeUnits::Samples, TimeUnits::Ns] {
+                for sample_rate in [1_000_000_000, 500_000_000] {
+                    let (message_events, trace_events) =
+                        message_and_trace_events(mode, time_units, sample_rate);
+                    assert!(
+                        !trace_events.is_empty(),
+                        "{mode:?} {time_units:?} {sample_rate}"
+                    );
+                    assert_eq!(
+                        message_events, trace_events,
+                        "{mode:?} {time_units:?} {sample_rate}"
+                    );
+                }
+            }
+        }
+    }
 }
@@ -74,7 +74,6 @@ pub(crate) type RealArray<const N: usize> = TraceArray<N, Real>;
 #[allow(unused)] // FIXME
 #[derive(Default, Clone, Debug)]
 pub(crate) struct Stats {
-    #[allow(unused)] // FIXME
     /// The current value.
     pub(crate) value: Real,
     /// The arithmetic mean.
@@ -84,10 +83,16 @@ pub(crate) struct Stats {
     /// The variance.
     ///
     /// This may have been calculated from applying a window to a range of values.
-    #[allow(unused)] // FIXME
     pub(crate) variance: Real,
 }
 
+impl Stats {
+    /// Returns the standard deviation, which is zero if the variance is not positive.
+    pub(crate) fn sd(&self) -> Real {
+        self.variance.max(0.0).sqrt()
+    }
+}
+
 impl From<Real> for Stats {
     fn from(value: Real) -> Self {
         Stats {
@@ -0,0 +1,283 @@
+//! This detector registers an event whenever the input stream passes a threshold,
+//! expressed as a multiple of the local noise sigma, for a given time.
+//!
+//! The input stream is expected to consist of [Stats] values, where `value` is the current
+//! sample and `variance` is the noise variance estimated from the samples preceding it.
+//! The detector also implements a cool-down period to wait before another detection is registered.
+use super::{Detector, EventData, Real};
+use crate::pulse_detection::{Stats, TracePoint};
+
+/// Helper Type for the time type used for by the detector.
+type DetectorTime = <<AdaptiveThresholdDetector as Detector>::TracePointType as TracePoint>::Time;
+
+/// The time-independnt data of the detector's event.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Data {
+    pub(crate) pulse_height: Real,
+}
+
+impl EventData for Data {}
+
+/// The current state of the detector.
+#[derive(Default, Clone)]
+enum DetectorState {
+    /// The detector is waiting for the trace to exceed the noise-adjusted threshold.
+    #[default]
+    Waiting,
+    /// The trace has been over `trigger_level` for less than `duration`.
+    Beginning {
+        time_begun: DetectorTime,
+        trigger_level: Real,
+    },
+    /// The trace has been over `trigger_level` for at least `duration`.
+    Detected { trigger_level: Real },
+    /// The detector has just completed an event detection and is waiting to cool down, before being able to detect another.
+    CoolingDown { time_ended: DetectorTime },
+}
+
+/// The triggering parameters of the adaptive threshold detector.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct AdaptiveThresholdDetectorParameters {
+    /// The number of noise sigmas the trace must exceed to trigger the detector.
+    pub(crate) sigma_threshold: Real,
+    /// How long the trace must be above the threshold to begin the detection.
+    pub(crate) duration: usize,
+    /// Minimum time between end of last pulse and detection of a new one.
+    pub(crate) cool_off: usize,
+}
+
+/// This detector triggers an event when the trace exceeds a multiple of the local noise sigma.
+///
+/// The trigger level is fixed for the duration of a detection, at the value it took when
+/// the trace first crossed it, so the pulse itself does not raise the level needed to sustain it.
+#[derive(Default, Clone)]
+pub(crate) struct AdaptiveThresholdDetector {
+    /// The detection parameters.
+    parameters: AdaptiveThresholdDetectorParameters,
+    /// The current state of the detector.
+    state: DetectorState,
+    /// The state of a detection in progress.
+    partial_event: Option<AdaptiveThresholdEvent>,
+}
+
+impl AdaptiveThresholdDetector {
+    /// Creates a new detector with the given triggering parameters.
+    /// # Parameters
+    /// - parameters: the triggering parameters.
+    pub(crate) fn new(parameters: &AdaptiveThresholdDetectorParameters) -> Self {
+        Self {
+            parameters: parameters.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the level the trace must exceed to trigger, given the noise statistics.
+    ///
+    /// If the noise variance is not strictly positive (e.g. the trace is constant),
+    /// there is no meaningful level, so `None` is returned and the detector does not trigger.
+    fn trigger_level(&self, stats: &Stats) -> Option<Real> {
+        (stats.variance > 0.0).then(|| self.parameters.sigma_threshold * stats.sd())
+    }
+
+    fn complete_detection(&mut self, time: DetectorTime) {
+        if self.parameters.cool_off.eq(&0) {
+            self.state = DetectorState::Waiting;
+        } else {
+            self.state = DetectorState::CoolingDown { time_ended: time };
+        }
+    }
+
+    fn update_state(&mut self, time: DetectorTime, stats: &Stats) {
+        match &self.state {
+            DetectorState::Waiting => {
+                if let Some(trigger_level) = self.trigger_level(stats)
+                    && stats.value > trigger_level
+                {
+                    self.partial_event = Some((
+                        time,
+                        Data {
+                            pulse_height: stats.value,
+                        },
+                    ));
+                    if self.parameters.duration <= 1 {
+                        self.state = DetectorState::Detected { trigger_level };
+                    } else {
+                        self.state = DetectorState::Beginning {
+                            time_begun: time,
+                            trigger_level,
+                        };
+                    }
+                }
+            }
+            DetectorState::Beginning {
+                time_begun,
+                trigger_level,
+            } => {
+                let trigger_level = *trigger_level;
+                if time == self.parameters.duration + *time_begun {
+                    // Potential detection has persisted for long enough to become a partial detection.
+                    if stats.value <= trigger_level {
+                        // The detection is complete.
+                        self.complete_detection(time);
+                    } else {
+                        // The detection is partial.
+                        self.state = DetectorState::Detected { trigger_level };
+                    }
+                } else if stats.value <= trigger_level {
+                    self.partial_event = None;
+                    self.state = DetectorState::Waiting;
+                }
+            }
+            DetectorState::Detected { trigger_level } => {
+                if stats.value <= *trigger_level {
+                    self.complete_detection(time);
+                }
+            }
+            DetectorState::CoolingDown { time_ended } => {
+                if time == *time_ended + self.parameters.cool_off {
+                    self.state = DetectorState::Waiting;
+                }
+            }
+        }
+    }
+
+    /// If a partial event is in progress, take ownership of it as long as the state
+    /// is `CoolingDown` or `Waiting`, otherwise return `None`.
+    fn try_take_completed_event(&mut self) -> Option<AdaptiveThresholdEvent> {
+        match self.state {
+            DetectorState::CoolingDown { .. } | DetectorState::Waiting => self.partial_event.take(),
+            _ => None,
+        }
+    }
+}
+
+/// The time-dependent event of the adaptive threshold detector.
+pub(crate) type AdaptiveThresholdEvent = (DetectorTime, Data);
+
+impl Detector for AdaptiveThresholdDetector {
+    type TracePointType = (usize, Stats);
+    type EventPointType = AdaptiveThresholdEvent;
+
+    fn signal(&mut self, time: usize, stats: Stats) -> Option<AdaptiveThresholdEvent> {
+        self.update_state(time, &stats);
+
+        if let Some(partial_event) = self.partial_event.as_mut() {
+            partial_event.1.pulse_height = partial_event.1.pulse_height.max(stats.value);
+        }
+        self.try_take_completed_event()
+    }
+
+    fn finish(&mut self) -> Option<Self::EventPointType> {
+        self.partial_event.take()
+    }
+
+    fn reset(&mut self) {
+        self.state = DetectorState::Waiting;
+        self.partial_event = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::EventsIterable;
+
+    fn stats(value: Real, sigma: Real) -> Stats {
+        Stats {
+            value,
+            mean: 0.0,
+            variance: sigma.powi(2),
+        }
+    }
+
+    fn detect(
+        data: &[(Real, Real)],
+        parameters: AdaptiveThresholdDetectorParameters,
+    ) -> Vec<AdaptiveThresholdEvent> {
+        data.iter()
+            .map(|&(value, sigma)| stats(value, sigma))
+            .enumerate()
+            .events(AdaptiveThresholdDetector::new(&parameters))
+            .collect()
+    }
+
+    #[test]
+    fn zero_data() {
+        let events = detect(
+            &[],
+            AdaptiveThresholdDetectorParameters {
+                sigma_threshold: 3.0,
+                duration: 1,
+                cool_off: 0,
+            },
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn threshold_scales_with_sigma() {
+        // The same value of 5 exceeds 3 sigma when sigma is 1, but not when sigma is 2.
+        let data = [(0.0, 1.0), (5.0, 1.0), (0.0, 1.0), (5.0, 2.0), (0.0, 2.0)];
+        let events = detect(
+            &data,
+            AdaptiveThresholdDetectorParameters {
+                sigma_threshold: 3.0,
+                duration: 1,
+                cool_off: 0,
+            },
+        );
+        assert_eq!(events, vec![(1, Data { pulse_height: 5.0 })]);
+    }
+
+    #[test]
+    fn zero_sigma_does_not_trigger() {
+        let data = [(0.0, 0.0), (5.0, 0.0), (5.0, 0.0), (0.0, 0.0)];
+        let events = detect(
+            &data,
+            AdaptiveThresholdDetectorParameters {
+                sigma_threshold: 3.0,
+                duration: 1,
+                cool_off: 0,
+            },
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn trigger_level_held_during_detection() {
+        // Sigma rises as the pulse enters the noise window, but this should not end the detection early.
+        let data = [(0.0, 1.0), (4.0, 1.0), (6.0, 3.0), (5.0, 4.0), (1.0, 4.0)];
+        let events = detect(
+            &data,
+            AdaptiveThresholdDetectorParameters {
+                sigma_threshold: 3.0,
+                duration: 1,
+                cool_off: 0,
+            },
+        );
+        assert_eq!(events, vec![(1, Data { pulse_height: 6.0 })]);
+    }
+
+    #[test]
+    fn duration_and_cool_off() {
+        let data = [
+            (4.0, 1.0),
+            (0.0, 1.0),
+            (4.0, 1.0),
+            (4.0, 1.0),
+            (0.0, 1.0),
+            (4.0, 1.0),
+            (4.0, 1.0),
+            (0.0, 1.0),
+        ];
+        let events = detect(
+            &data,
+            AdaptiveThresholdDetectorParameters {
+                sigma_threshold: 3.0,
+                duration: 2,
+                cool_off: 2,
+            },
+        );
+        assert_eq!(events, vec![(2, Data { pulse_height: 4.0 })]);
+    }
+}
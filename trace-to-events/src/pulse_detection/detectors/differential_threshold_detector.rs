@@ -251,13 +251,21 @@ impl Detector for DifferentialThresholdDetector {
             .map(|partial_event| partial_event.into_event());
         None
     }
+
+    fn reset(&mut self) {
+        self.state = DetectorState::Waiting;
+        self.partial_event = None;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        pulse_detection::{EventsIterable, Real, WindowIterable, window::FiniteDifferences},
+        pulse_detection::{
+            EventsIterable, Real, WindowIterable,
+            window::{FiniteDifferences, SavitzkyGolay},
+        },
         test_data::{assert_iters_approx_equal, assert_iters_equal, pyramid::INPUT},
     };
     use digital_muon_common::Intensity;
@@ -576,7 +584,28 @@ mod tests {
 
     mod b2b {
         use super::*;
-        use crate::test_data::b2bexp;
+        use crate::{
+            pulse_detection::{RealArray, window::Window},
+            test_data::b2bexp,
+        };
+
+        fn event_times<W>(
+            values: &[Real],
+            window: W,
+            detector: DifferentialThresholdDetector,
+        ) -> Vec<DetectorTime>
+        where
+            W: Window<TimeType = DetectorTime, InputType = Real, OutputType = RealArray<2>>,
+        {
+            values
+                .iter()
+                .copied()
+                .enumerate()
+                .window(window)
+                .events(detector)
+                .map(|(time, _)| time)
+                .collect()
+        }
 
         #[test]
         fn test_b2bexp() {
@@ -604,5 +633,41 @@ mod tests {
             assert_eq!(iter.next(), some_new_event(77 as DetectorTime, 3.0, 111.0));
             assert_eq!(iter.next(), None);
         }
+
+        #[test]
+        fn test_noisy_b2bexp_savitzky_golay() {
+            let data = (0..100)
+                .map(|x| {
+                    b2bexp(x as Real, 1000.0, 3.5, 20.0, 3.5, 2.25)
+                        + b2bexp(x as Real, 1000.0, 3.5, 54.0, 4.5, 5.5)
+                        + b2bexp(x as Real, 1000.0, 3.5, 81.0, 1.5, 3.25)
+                })
+                .map(|v| v as Real)
+                .collect::<Vec<_>>();
+            // Deterministic noise, taking each integer value from -5 to 5.
+            let noisy = data
+                .iter()
+                .enumerate()
+                .map(|(x, v)| v + ((x * 37) % 11) as Real - 5.0)
+                .collect::<Vec<_>>();
+
+            let detector = DifferentialThresholdDetector::new(
+                &DifferentialThresholdParameters {
+                    begin_threshold: 3.0,
+                    end_threshold: 0.0,
+                    ..Default::default()
+                },
+                Default::default(),
+            );
+
+            // The raw finite difference is dominated by the noise.
+            let finite_differences = FiniteDifferences::<2>::new();
+            assert!(event_times(&noisy, finite_differences, detector.clone()).len() > 3);
+
+            let savitzky_golay = SavitzkyGolay::new(11, 2);
+            let noiseless = event_times(&data, savitzky_golay.clone(), detector.clone());
+            assert_eq!(noiseless.len(), 3);
+            assert_eq!(event_times(&noisy, savitzky_golay, detector), noiseless);
+        }
     }
 }
@@ -80,6 +80,11 @@ impl Detector for LocalArgMinDetector {
     fn finish(&mut self) -> Option<Self::EventPointType> {
         self.default.take()
     }
+
+    /// This detector has no parameters, so is recreated.
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
 }
 
 #[cfg(test)]
@@ -1,5 +1,6 @@
 //! Detectors are applied by [EventIter] iterators to a stream of trace inputs.
 //! They register detections in the form of a stream of events.
+pub mod adaptive_threshold_detector;
 pub mod differential_threshold_detector;
 pub mod local_arg_min_detector;
 pub mod region_detector;
@@ -25,4 +26,8 @@ pub(crate) trait Detector: Default + Clone {
 
     /// Call when the the trace signal has completed. If an event is in progress, it is dispatched.
     fn finish(&mut self) -> Option<Self::EventPointType>;
+
+    /// Returns the detector to the state it was created in, keeping its parameters,
+    /// so it can be applied to another trace.
+    fn reset(&mut self);
 }
@@ -60,6 +60,10 @@ impl Detector for RegionDetector {
     fn finish(&mut self) -> Option<Self::EventPointType> {
         self.filter_partial_region()
     }
+
+    fn reset(&mut self) {
+        self.partial_region = None;
+    }
 }
 
 #[cfg(test)]
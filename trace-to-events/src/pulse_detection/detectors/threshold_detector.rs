@@ -2,6 +2,13 @@
 //! value for a given time.
 //!
 //! The detector also implements a cool-down period to wait before another detection is registered.
+//!
+//! If a disarm threshold lower than the threshold is given, the detector has hysteresis: once a detection completes,
+//! it only rearms after the trace falls to the disarm threshold, so that ringing on the tail of a large pulse,
+//! which repeatedly crosses the threshold, does not register further events. Any cool-down begins once the trace has fallen to the disarm threshold.
+//!
+//! If a veto threshold is given, a detection during which the trace exceeds it is discarded,
+//! so that very large pulses, such as those from cosmic rays, are excluded from the event list rather than clipped.
 
 use super::{Detector, EventData, Real};
 use crate::pulse_detection::TracePoint;
@@ -22,15 +29,24 @@ impl EventData for Data {}
 /// The current state of the detector.
 #[derive(Default, Clone)]
 enum DetectorState {
-    /// The detector is waiting for the trace to exceed `begin_threshold`.
+    /// The detector is waiting for the trace to exceed `threshold`.
     #[default]
-    Waiting,
-    /// The trace has been over `begin_threshold` for less than `begin_duration`.
+    Armed,
+    /// The trace has been over `threshold` for less than `duration`.
+    /// If it drops back under `threshold`, the detection is abandoned and the detector remains armed.
     Beginning { time_begun: DetectorTime },
-    /// The trace has been over `begin_threshold` for at least `begin_duration`.
-    Detected,
-    /// The detector has just completed an event detection and is waiting to cool down, before being able to detect another.
-    CoolingDown { time_ended: DetectorTime },
+    /// The trace has been over `threshold` for at least `duration`.
+    Triggered,
+    /// The detector has just completed an event detection, when the trace dropped back under `threshold`,
+    /// and is waiting for it to drop under `disarm_threshold`, before cooling down.
+    WaitingToRearm,
+    /// The trace dropped under `disarm_threshold` at `time_disarmed`, and the detector is waiting to cool down,
+    /// before being able to detect another.
+    CoolingDown { time_disarmed: DetectorTime },
+    /// The trace exceeded `veto_threshold` during a detection, which has been discarded.
+    /// The detector waits until the trace drops back under `disarm_threshold`, at `time_dropped`,
+    /// and then for `veto_extend` samples, before being able to detect another.
+    Vetoed { time_dropped: Option<DetectorTime> },
 }
 
 /// The triggering parameters of the threshold detector.
@@ -40,8 +56,15 @@ pub(crate) struct ThresholdDetectorParameters {
     pub(crate) threshold: DetectorValue,
     /// How long the trace must be above the `threshold` to begin the detection.
     pub(crate) duration: usize,
-    /// Minimum time between end of last pulse and detection of a new one.
+    /// Minimum time between the trace dropping under `disarm_threshold` after the last pulse, and detection of a new one.
     pub(crate) cool_off: usize,
+    /// If present, the trace must drop under this, rather than `threshold`, after a pulse before the detector rearms.
+    /// This should be no greater than `threshold`, a greater value has the same effect as `threshold`.
+    pub(crate) disarm_threshold: Option<DetectorValue>,
+    /// If present, a detection during which the trace exceeds this is discarded.
+    pub(crate) veto_threshold: Option<DetectorValue>,
+    /// How long after a vetoed pulse drops under `disarm_threshold` until a new one can be detected.
+    pub(crate) veto_extend: usize,
 }
 
 /// This detector triggers an event when the trace exceeds the threshold.
@@ -53,6 +76,8 @@ pub(crate) struct ThresholdDetector {
     state: DetectorState,
     /// The state of a detection in progress.
     partial_event: Option<ThresholdEvent>,
+    /// The number of detections discarded since [Self::take_vetoed_pulses] was last called.
+    vetoed_pulses: usize,
 }
 
 impl ThresholdDetector {
@@ -65,18 +90,58 @@ impl ThresholdDetector {
         }
     }
 
-    fn complete_detection(&mut self, time: DetectorTime) {
-        if self.parameters.cool_off.eq(&0) {
-            self.state = DetectorState::Waiting;
-        } else {
-            self.state = DetectorState::CoolingDown { time_ended: time };
+    /// Returns the number of detections discarded because the trace exceeded the veto threshold,
+    /// since this was last called, and resets it to zero.
+    pub(crate) fn take_vetoed_pulses(&mut self) -> usize {
+        std::mem::take(&mut self.vetoed_pulses)
+    }
+
+    /// Returns true if `value` is low enough for the detector to rearm after a pulse.
+    fn is_disarmed(&self, value: DetectorValue) -> bool {
+        value
+            <= self
+                .parameters
+                .disarm_threshold
+                .unwrap_or(self.parameters.threshold)
+    }
+
+    fn exceeds_veto_threshold(&self, value: DetectorValue) -> bool {
+        self.parameters
+            .veto_threshold
+            .is_some_and(|veto_threshold| value > veto_threshold)
+    }
+
+    fn veto_detection(&mut self) {
+        self.partial_event = None;
+        self.vetoed_pulses += 1;
+        self.state = DetectorState::Vetoed { time_dropped: None };
+    }
+
+    /// Completes the detection in progress, as the trace has dropped under the threshold to `value`.
+    fn complete_detection(&mut self, time: DetectorTime, value: DetectorValue) {
+        self.state = DetectorState::WaitingToRearm;
+        self.rearm_if_disarmed(time, value);
+    }
+
+    /// If `value` is under the disarm threshold, begins cooling down, or rearms if there is no cool-off.
+    fn rearm_if_disarmed(&mut self, time: DetectorTime, value: DetectorValue) {
+        if self.is_disarmed(value) {
+            if self.parameters.cool_off.eq(&0) {
+                self.state = DetectorState::Armed;
+            } else {
+                self.state = DetectorState::CoolingDown {
+                    time_disarmed: time,
+                };
+            }
         }
     }
 
     fn update_state(&mut self, time: DetectorTime, value: DetectorValue) {
         match &self.state {
-            DetectorState::Waiting => {
-                if value > self.parameters.threshold {
+            DetectorState::Armed => {
+                if value > self.parameters.threshold && self.exceeds_veto_threshold(value) {
+                    self.veto_detection();
+                } else if value > self.parameters.threshold {
                     self.partial_event = Some((
                         time,
                         Data {
@@ -84,45 +149,70 @@ impl ThresholdDetector {
                         },
                     ));
                     if self.parameters.duration.eq(&1) {
-                        self.state = DetectorState::Detected;
+                        self.state = DetectorState::Triggered;
                     } else {
                         self.state = DetectorState::Beginning { time_begun: time };
                     }
                 }
             }
             DetectorState::Beginning { time_begun } => {
-                if time == self.parameters.duration as DetectorTime + *time_begun {
+                if self.exceeds_veto_threshold(value) {
+                    self.veto_detection();
+                } else if time == self.parameters.duration as DetectorTime + *time_begun {
                     // Potential detection has persisted for long enough to become a partial detection.
                     if value <= self.parameters.threshold {
                         // The detection is complete.
-                        self.complete_detection(time);
+                        self.complete_detection(time, value);
                     } else {
                         // The detection is partial.
-                        self.state = DetectorState::Detected;
+                        self.state = DetectorState::Triggered;
                     }
                 } else if value <= self.parameters.threshold {
                     self.partial_event = None;
-                    self.state = DetectorState::Waiting;
+                    self.state = DetectorState::Armed;
                 }
             }
-            DetectorState::Detected => {
-                if value <= self.parameters.threshold {
-                    self.complete_detection(time);
+            DetectorState::Triggered => {
+                if self.exceeds_veto_threshold(value) {
+                    self.veto_detection();
+                } else if value <= self.parameters.threshold {
+                    self.complete_detection(time, value);
+                }
+            }
+            DetectorState::WaitingToRearm => self.rearm_if_disarmed(time, value),
+            DetectorState::CoolingDown { time_disarmed } => {
+                if time == *time_disarmed + self.parameters.cool_off as DetectorTime {
+                    self.state = DetectorState::Armed;
+                }
+            }
+            DetectorState::Vetoed { time_dropped: None } => {
+                if self.is_disarmed(value) {
+                    if self.parameters.veto_extend == 0 {
+                        self.state = DetectorState::Armed;
+                    } else {
+                        self.state = DetectorState::Vetoed {
+                            time_dropped: Some(time),
+                        };
+                    }
                 }
             }
-            DetectorState::CoolingDown { time_ended } => {
-                if time == *time_ended + self.parameters.cool_off as DetectorTime {
-                    self.state = DetectorState::Waiting;
+            DetectorState::Vetoed {
+                time_dropped: Some(time_dropped),
+            } => {
+                if time == *time_dropped + self.parameters.veto_extend as DetectorTime {
+                    self.state = DetectorState::Armed;
                 }
             }
         }
     }
 
     /// If a partial event is in progress, take ownership of it as long as the state
-    /// is `CoolingDown` or `Waiting`, otherwise return `None`.
+    /// is `WaitingToRearm`, `CoolingDown` or `Armed`, otherwise return `None`.
     fn try_take_completed_event(&mut self) -> Option<ThresholdEvent> {
         match self.state {
-            DetectorState::CoolingDown { .. } | DetectorState::Waiting => self.partial_event.take(),
+            DetectorState::WaitingToRearm
+            | DetectorState::CoolingDown { .. }
+            | DetectorState::Armed => self.partial_event.take(),
             _ => None,
         }
     }
@@ -151,6 +241,12 @@ impl Detector for ThresholdDetector {
     fn finish(&mut self) -> Option<Self::EventPointType> {
         self.partial_event.take()
     }
+
+    fn reset(&mut self) {
+        self.state = DetectorState::Armed;
+        self.partial_event = None;
+        self.vetoed_pulses = 0;
+    }
 }
 
 #[cfg(test)]
@@ -168,6 +264,9 @@ mod tests {
             threshold: 2.0,
             cool_off: 0,
             duration: 2,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
         let mut iter = data
             .into_iter()
@@ -184,6 +283,9 @@ mod tests {
             threshold: 2.0,
             cool_off: 0,
             duration: 2,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
         let mut iter = data
             .into_iter()
@@ -236,6 +338,9 @@ mod tests {
             threshold: -2.5,
             cool_off: 0,
             duration: 2,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
         let mut iter = data
             .into_iter()
@@ -260,6 +365,9 @@ mod tests {
             threshold: -2.5,
             cool_off: 0,
             duration: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
         let mut iter = data
             .into_iter()
@@ -286,6 +394,9 @@ mod tests {
             threshold: -2.5,
             cool_off: 2,
             duration: 1,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
         let mut iter = data
             .iter()
@@ -307,6 +418,9 @@ mod tests {
             threshold: -2.5,
             cool_off: 1,
             duration: 1,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
 
         let mut iter = data
@@ -332,6 +446,9 @@ mod tests {
             threshold: -2.5,
             cool_off: 0,
             duration: 1,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         });
 
         let mut iter = data
@@ -360,6 +477,9 @@ mod tests {
             threshold: 15.0,
             duration: 2,
             cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
         };
         let detector = ThresholdDetector::new(&parameters);
         let events = INPUT
@@ -380,4 +500,195 @@ mod tests {
             expected_heights.iter(),
         );
     }
+
+    /// Runs a detector with the given parameters over `data`,
+    /// returning the events it registers and the number of pulses it vetoes.
+    fn detect_with_veto(
+        parameters: ThresholdDetectorParameters,
+        data: &[i32],
+    ) -> (Vec<ThresholdEvent>, usize) {
+        let mut iter = data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| (i as DetectorTime, v as DetectorValue))
+            .events(ThresholdDetector::new(&parameters));
+        let events = iter.by_ref().collect();
+        let (_, mut detector) = iter.into_inner();
+        (events, detector.take_vetoed_pulses())
+    }
+
+    fn event(time: DetectorTime, pulse_height: DetectorValue) -> ThresholdEvent {
+        (time, Data { pulse_height })
+    }
+
+    #[test]
+    fn pulse_grazing_veto_threshold_is_detected() {
+        let parameters = ThresholdDetectorParameters {
+            threshold: 2.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: Some(10.0),
+            veto_extend: 2,
+        };
+        let (events, vetoed) = detect_with_veto(parameters, &[0, 5, 10, 3, 0, 4, 0]);
+        assert_eq!(events, [event(1, 10.0), event(5, 4.0)]);
+        assert_eq!(vetoed, 0);
+    }
+
+    #[test]
+    fn pulse_exceeding_veto_threshold_is_discarded() {
+        let parameters = ThresholdDetectorParameters {
+            threshold: 2.0,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: Some(10.0),
+            veto_extend: 2,
+        };
+        // The vetoed pulse drops under the threshold at 4, so the pulse at 6 is also missed.
+        let (events, vetoed) =
+            detect_with_veto(parameters.clone(), &[0, 5, 12, 3, 0, 0, 4, 0, 0, 0, 4, 0]);
+        assert_eq!(events, [event(10, 4.0)]);
+        assert_eq!(vetoed, 1);
+
+        // A pulse which begins above the veto threshold is discarded too.
+        let (events, vetoed) = detect_with_veto(parameters, &[20, 3, 0, 0, 0, 4, 0]);
+        assert_eq!(events, [event(5, 4.0)]);
+        assert_eq!(vetoed, 1);
+    }
+
+    #[test]
+    fn veto_while_beginning() {
+        let parameters = ThresholdDetectorParameters {
+            threshold: 2.0,
+            duration: 3,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: Some(10.0),
+            veto_extend: 0,
+        };
+        let (events, vetoed) = detect_with_veto(parameters, &[0, 5, 11, 5, 0, 5, 5, 5, 0]);
+        assert_eq!(events, [event(5, 5.0)]);
+        assert_eq!(vetoed, 1);
+    }
+
+    #[test]
+    fn veto_interacts_with_cool_off() {
+        let parameters = ThresholdDetectorParameters {
+            threshold: 2.0,
+            duration: 1,
+            cool_off: 3,
+            disarm_threshold: None,
+            veto_threshold: Some(10.0),
+            veto_extend: 1,
+        };
+        // The trace only exceeds the veto threshold whilst cooling down, when no detection is in progress.
+        let (events, vetoed) = detect_with_veto(parameters.clone(), &[5, 0, 20, 0, 0, 5, 0]);
+        assert_eq!(events, [event(0, 5.0), event(5, 5.0)]);
+        assert_eq!(vetoed, 0);
+
+        // A vetoed pulse is followed by `veto_extend` samples, not `cool_off`, before the detector rearms.
+        let (events, vetoed) = detect_with_veto(parameters, &[20, 0, 0, 5, 0]);
+        assert_eq!(events, [event(3, 5.0)]);
+        assert_eq!(vetoed, 1);
+    }
+
+    /// Returns the parameters of a detector with threshold 10, and the given disarm threshold and cool-off.
+    fn hysteresis(
+        disarm_threshold: Option<DetectorValue>,
+        cool_off: usize,
+    ) -> ThresholdDetectorParameters {
+        ThresholdDetectorParameters {
+            threshold: 10.0,
+            duration: 1,
+            cool_off,
+            disarm_threshold,
+            veto_threshold: None,
+            veto_extend: 0,
+        }
+    }
+
+    /// A pulse at 1 rings on its tail, crossing the threshold at 4 and 6, before a second pulse at 12.
+    const RINGING_PULSE: [i32; 14] = [0, 20, 15, 8, 12, 6, 11, 4, 9, 2, 0, 0, 15, 0];
+
+    #[test]
+    fn ringing_tail_retriggers_without_hysteresis() {
+        let (events, _) = detect_with_veto(hysteresis(None, 0), &RINGING_PULSE);
+        assert_eq!(
+            events,
+            [
+                event(1, 20.0),
+                event(4, 12.0),
+                event(6, 11.0),
+                event(12, 15.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn ringing_tail_is_one_event_with_hysteresis() {
+        let (events, _) = detect_with_veto(hysteresis(Some(3.0), 0), &RINGING_PULSE);
+        assert_eq!(events, [event(1, 20.0), event(12, 15.0)]);
+    }
+
+    #[test]
+    fn disarm_threshold_equal_to_threshold_is_no_hysteresis() {
+        for cool_off in [0, 1, 2] {
+            assert_eq!(
+                detect_with_veto(hysteresis(Some(10.0), cool_off), &RINGING_PULSE),
+                detect_with_veto(hysteresis(None, cool_off), &RINGING_PULSE)
+            );
+        }
+    }
+
+    #[test]
+    fn trace_reaching_disarm_threshold_rearms() {
+        // The trace drops to exactly the disarm threshold at 2, so the pulse at 3 is detected.
+        let (events, _) = detect_with_veto(hysteresis(Some(3.0), 0), &[0, 20, 3, 15, 0]);
+        assert_eq!(events, [event(1, 20.0), event(3, 15.0)]);
+
+        let (events, _) = detect_with_veto(hysteresis(Some(3.0), 0), &[0, 20, 4, 15, 0]);
+        assert_eq!(events, [event(1, 20.0)]);
+    }
+
+    #[test]
+    fn cool_off_begins_once_disarmed() {
+        let data = [0, 20, 8, 5, 2, 15, 0, 15, 0];
+        // Without hysteresis, the cool-off begins when the trace drops under the threshold at 2, so the pulse at 5 is detected,
+        // and the pulse at 7 is missed.
+        let (events, _) = detect_with_veto(hysteresis(None, 2), &data);
+        assert_eq!(events, [event(1, 20.0), event(5, 15.0)]);
+
+        // With hysteresis, it begins when the trace drops under the disarm threshold at 4, so the pulse at 5 is missed.
+        let (events, _) = detect_with_veto(hysteresis(Some(3.0), 2), &data);
+        assert_eq!(events, [event(1, 20.0), event(7, 15.0)]);
+    }
+
+    #[test]
+    fn hysteresis_with_duration() {
+        let parameters = ThresholdDetectorParameters {
+            duration: 2,
+            ..hysteresis(Some(3.0), 0)
+        };
+        // The pulse at 4 is over the threshold for long enough, but the detector has not rearmed since the pulse at 1.
+        let (events, _) =
+            detect_with_veto(parameters.clone(), &[0, 20, 20, 8, 12, 12, 2, 12, 12, 0]);
+        assert_eq!(events, [event(1, 20.0), event(7, 12.0)]);
+
+        // A pulse too short to be detected does not need the trace to drop to the disarm threshold.
+        let (events, _) = detect_with_veto(parameters, &[0, 20, 5, 20, 20, 0]);
+        assert_eq!(events, [event(3, 20.0)]);
+    }
+
+    #[test]
+    fn vetoed_pulse_waits_for_disarm_threshold() {
+        let parameters = ThresholdDetectorParameters {
+            veto_threshold: Some(30.0),
+            ..hysteresis(Some(3.0), 0)
+        };
+        let (events, vetoed) = detect_with_veto(parameters, &[0, 40, 8, 15, 2, 15, 0]);
+        assert_eq!(events, [event(5, 15.0)]);
+        assert_eq!(vetoed, 1);
+    }
 }
@@ -15,6 +15,17 @@ where
     detector: D,
 }
 
+impl<I, D> EventIter<I, D>
+where
+    I: Iterator<Item = D::TracePointType>,
+    D: Detector,
+{
+    /// Consumes the iterator, returning its source and detector, so the detector can be reused.
+    pub(crate) fn into_inner(self) -> (I, D) {
+        (self.source, self.detector)
+    }
+}
+
 impl<I, D> Iterator for EventIter<I, D>
 where
     I: Iterator<Item = D::TracePointType>,
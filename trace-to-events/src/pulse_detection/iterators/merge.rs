@@ -0,0 +1,153 @@
+//! Provides an iterator adapter which merges events closer together than a dead time into one event.
+use crate::parameters::MergePolicy;
+use digital_muon_common::{Intensity, Time};
+use std::iter::Peekable;
+
+/// Returns true if an event at time `next` is closer than `dead_time` to the event at `previous`,
+/// so that the two are merged. Events exactly `dead_time` apart are not merged.
+pub(crate) fn within_dead_time(previous: Time, next: Time, dead_time: Time) -> bool {
+    previous.abs_diff(next) < dead_time
+}
+
+impl MergePolicy {
+    /// Returns the intensity of the event formed by merging an event of intensity `next` into one of intensity `merged`.
+    fn combine(self, merged: Intensity, next: Intensity) -> Intensity {
+        match self {
+            Self::Max => merged.max(next),
+            Self::Sum => merged.saturating_add(next),
+            Self::First => merged,
+        }
+    }
+}
+
+/// Should be implemented for any iterator of events which supports the `merge_within` method.
+pub(crate) trait MergeEventsIterable: Iterator<Item = (Time, Intensity)> + Sized {
+    /// Create a [MergeEvents] iterator, which merges events as it is consumed.
+    ///
+    /// # Parameters
+    /// - dead_time: consecutive events whose times differ by less than this are merged, see [within_dead_time].
+    /// - policy: determines the intensity of each merged event.
+    fn merge_within(self, dead_time: Time, policy: MergePolicy) -> MergeEvents<Self>;
+}
+
+impl<I> MergeEventsIterable for I
+where
+    I: Iterator<Item = (Time, Intensity)>,
+{
+    fn merge_within(self, dead_time: Time, policy: MergePolicy) -> MergeEvents<Self> {
+        MergeEvents {
+            source: self.peekable(),
+            dead_time,
+            policy,
+            num_merged: 0,
+        }
+    }
+}
+
+/// Merges each run of consecutive events, in which each is closer than the dead time to the one before,
+/// into one event at the time of the first of the run, with the intensity given by the [MergePolicy].
+///
+/// Merging is transitive, so a run may span more than the dead time, so long as each event is within it of the last.
+/// Should be created by [MergeEventsIterable::merge_within].
+pub(crate) struct MergeEvents<I: Iterator> {
+    source: Peekable<I>,
+    dead_time: Time,
+    policy: MergePolicy,
+    /// The number of events merged into an earlier event so far.
+    num_merged: usize,
+}
+
+impl<I: Iterator> MergeEvents<I> {
+    /// Returns the number of events merged into an earlier event so far, which are not output.
+    pub(crate) fn num_merged(&self) -> usize {
+        self.num_merged
+    }
+}
+
+impl<I> Iterator for MergeEvents<I>
+where
+    I: Iterator<Item = (Time, Intensity)>,
+{
+    type Item = (Time, Intensity);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (time, mut intensity) = self.source.next()?;
+        let mut last_time = time;
+        while let Some((next_time, next_intensity)) = self
+            .source
+            .next_if(|&(next_time, _)| within_dead_time(last_time, next_time, self.dead_time))
+        {
+            intensity = self.policy.combine(intensity, next_intensity);
+            last_time = next_time;
+            self.num_merged += 1;
+        }
+        Some((time, intensity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(
+        events: &[(Time, Intensity)],
+        dead_time: Time,
+        policy: MergePolicy,
+    ) -> (Vec<(Time, Intensity)>, usize) {
+        let mut merged = events.iter().copied().merge_within(dead_time, policy);
+        let events = merged.by_ref().collect();
+        (events, merged.num_merged())
+    }
+
+    #[test]
+    fn chains_of_close_events_are_merged_transitively() {
+        // Each event is within the dead time of the last, though the first and last are not within it of each other.
+        let events = [(10, 3), (14, 7), (18, 5), (40, 2)];
+        assert_eq!(
+            merge(&events, 5, MergePolicy::Max),
+            (vec![(10, 7), (40, 2)], 2)
+        );
+    }
+
+    #[test]
+    fn events_exactly_the_dead_time_apart_are_not_merged() {
+        let events = [(10, 3), (15, 7), (19, 5)];
+        assert_eq!(
+            merge(&events, 5, MergePolicy::Max),
+            (vec![(10, 3), (15, 7)], 1)
+        );
+        assert_eq!(merge(&events, 4, MergePolicy::Max), (events.to_vec(), 0));
+    }
+
+    #[test]
+    fn intensity_is_given_by_policy() {
+        let events = [(10, 3), (12, 7), (14, 5), (30, 2), (31, 1)];
+        for (policy, expected) in [
+            (MergePolicy::Max, [(10, 7), (30, 2)]),
+            (MergePolicy::Sum, [(10, 15), (30, 3)]),
+            (MergePolicy::First, [(10, 3), (30, 2)]),
+        ] {
+            assert_eq!(
+                merge(&events, 3, policy),
+                (expected.to_vec(), 3),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn summed_intensity_saturates() {
+        let events = [(10, Intensity::MAX - 1), (11, 2)];
+        assert_eq!(
+            merge(&events, 2, MergePolicy::Sum),
+            (vec![(10, Intensity::MAX)], 1)
+        );
+    }
+
+    #[test]
+    fn nothing_is_merged_without_dead_time() {
+        let events = [(10, 3), (10, 7), (11, 5)];
+        assert_eq!(merge(&events, 0, MergePolicy::Sum), (events.to_vec(), 0));
+        assert_eq!(merge(&[], 5, MergePolicy::Sum), (vec![], 0));
+    }
+}
@@ -1,9 +1,11 @@
 //! Provides iterators to convert raw trace data into events and pulses.
 pub(crate) mod event;
+pub(crate) mod merge;
 pub(crate) mod padding;
 pub(crate) mod window;
 
 use super::{Detector, TracePoint};
 pub(crate) use event::EventsIterable;
+pub(crate) use merge::{MergeEventsIterable, within_dead_time};
 pub(crate) use padding::{PaddingIterable, ZeroPaddingIterable};
 pub(crate) use window::WindowIterable;
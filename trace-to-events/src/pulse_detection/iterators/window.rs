@@ -32,6 +32,11 @@ where
         }
     }
 
+    /// Consumes the iterator, returning its source and window, so the window can be reused.
+    pub fn into_inner(self) -> (I, W) {
+        (self.source, self.window_function)
+    }
+
     #[cfg(test)]
     pub fn get_window(&self) -> &W {
         &self.window_function
@@ -3,7 +3,7 @@
 //!
 //! A raw trace takes the form of a Vec (or some other similar container)
 //! of scalar values. Typical usage of this crate may look like:
-//! ```rust
+//! ```ignore
 //! let events = trace.iter()
 //!     .enumerate()
 //!     .map(|(i, v)| (i as Real * sample_time, v as Real))        // converts to (Real,Real) format.
@@ -26,4 +26,4 @@ pub(crate) use detectors::{Detector, threshold_detector};
 pub(crate) use iterators::{EventsIterable, WindowIterable};
 
 /// Standard type to use for real numbers.
-pub(crate) type Real = f64;
+pub type Real = f64;
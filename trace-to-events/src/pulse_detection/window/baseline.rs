@@ -1,9 +1,10 @@
-//! [TODO]
+//! Estimates the baseline of a trace from its first samples, and subtracts it from the rest.
 use crate::pulse_detection::window::TimeShift;
 
 use super::{Real, Window};
 
-#[allow(unused)] // FIXME
+/// Estimates the baseline as the exponential moving average of the first `warm_up` samples,
+/// and outputs each subsequent sample less the estimate.
 #[derive(Default, Clone)]
 pub(crate) struct Baseline {
     baseline: Real,
@@ -11,10 +12,11 @@ pub(crate) struct Baseline {
     smoothing_factor: Real,
     warm_up: usize,
     time: usize,
+    /// If true, the time of each output is shifted back by `warm_up`, as in previous releases.
+    legacy_time_alignment: bool,
 }
 
 impl Baseline {
-    #[allow(unused)] // FIXME
     pub(crate) fn new(warm_up: usize, smoothing_factor: Real) -> Self {
         Baseline {
             warm_up,
@@ -22,11 +24,30 @@ impl Baseline {
             ..Default::default()
         }
     }
+
+    /// Sets whether the time of each output is shifted back by `warm_up`, as in previous releases,
+    /// rather than being that of the value output.
+    pub(crate) fn with_legacy_time_alignment(mut self, legacy_time_alignment: bool) -> Self {
+        self.legacy_time_alignment = legacy_time_alignment;
+        self
+    }
+
+    /// Returns the estimated baseline, once the first `warm_up` samples have been pushed,
+    /// or [None] if fewer have been pushed, or `warm_up` is zero, in which case no estimate is made.
+    pub(crate) fn estimate(&self) -> Option<Real> {
+        (self.warm_up > 0 && self.time == self.warm_up).then_some(self.baseline)
+    }
 }
 
 impl TimeShift<Real> for Baseline {
+    /// Each output depends only on the most recent value, once the baseline has warmed up, so its time is unchanged.
+    /// With legacy time alignment, it is shifted back by `warm_up`.
     fn apply_time_shift(&self, time: Real) -> Real {
-        time - (self.warm_up as Real)
+        if self.legacy_time_alignment {
+            time - (self.warm_up as Real)
+        } else {
+            time
+        }
     }
 }
 
@@ -53,6 +74,12 @@ impl Window for Baseline {
     fn output(&self) -> Option<Real> {
         (self.time == self.warm_up).then_some(self.value)
     }
+
+    fn reset(&mut self) {
+        self.baseline = Real::default();
+        self.value = Real::default();
+        self.time = 0;
+    }
 }
 
 #[cfg(test)]
@@ -86,7 +113,7 @@ mod tests {
             .into_iter()
             .enumerate()
             .map(|(i, v)| (i as Real, v as Real))
-            .window(Baseline::new(3, 0.1))
+            .window(Baseline::new(3, 0.1).with_legacy_time_alignment(true))
             .collect();
 
         assert_eq!(output.len(), 4);
@@ -103,7 +130,7 @@ mod tests {
             .into_iter()
             .enumerate()
             .map(|(i, v)| (i as Real, v as Real))
-            .window(Baseline::new(3, 0.1))
+            .window(Baseline::new(3, 0.1).with_legacy_time_alignment(true))
             //.map(|(_, x)| x)
             .collect();
 
@@ -113,6 +140,22 @@ mod tests {
         assert_eq!(output[3], (3., 2.));
     }
 
+    #[test]
+    fn times_are_unshifted() {
+        let input: Vec<Real> = vec![1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 3.0];
+        let output: Vec<_> = input
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v as Real))
+            .window(Baseline::new(3, 0.1))
+            .collect();
+
+        assert_eq!(output[0], (3., 0.));
+        assert_eq!(output[1], (4., 0.));
+        assert_eq!(output[2], (5., 1.));
+        assert_eq!(output[3], (6., 2.));
+    }
+
     #[test]
     fn varying_data() {
         let input: Vec<Real> = vec![1.0, 2.0, 0.0, 0.0, 1.0, 2.0, 3.0];
@@ -129,4 +172,48 @@ mod tests {
         assert_approx_eq!(output[2], 1.04, 1e-8);
         assert_approx_eq!(output[3], 2.04, 1e-8);
     }
+
+    #[test]
+    fn estimate_is_exposed_once_warmed_up() {
+        let mut window = Baseline::new(3, 0.2);
+        assert_eq!(window.estimate(), None);
+        window.push(1.0);
+        window.push(2.0);
+        assert_eq!(window.estimate(), None);
+        window.push(0.0);
+        assert_approx_eq!(window.estimate().unwrap(), 0.96, 1e-8);
+        window.push(5.0);
+        assert_approx_eq!(window.estimate().unwrap(), 0.96, 1e-8);
+
+        window.reset();
+        assert_eq!(window.estimate(), None);
+        assert_eq!(Baseline::new(0, 0.2).estimate(), None);
+    }
+
+    #[test]
+    fn reset_window_matches_new_window() {
+        let traces: [Vec<Real>; 2] = [
+            vec![1.0, 2.0, 0.0, 0.0, 1.0, 2.0],
+            vec![3.0, 1.0, 2.0, 2.0, 0.0],
+        ];
+        let mut window = Baseline::new(3, 0.2);
+        for trace in &traces {
+            window.reset();
+            let mut output = trace
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i as Real, *v))
+                .window(window);
+            let reused = output.by_ref().collect::<Vec<_>>();
+            (_, window) = output.into_inner();
+
+            let new = trace
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i as Real, *v))
+                .window(Baseline::new(3, 0.2))
+                .collect::<Vec<_>>();
+            assert_eq!(reused, new);
+        }
+    }
 }
@@ -7,7 +7,7 @@
 //!
 //! The following example applies a convolution window of length five to a raw
 //! data stream.
-//! ```rust
+//! ```ignore
 //!     let smoothed = raw
 //!        .window(ConvolutionFilter::new(KernelType::Gaussian {sigma: 2.0 }));
 //! ```
@@ -30,6 +30,7 @@ pub(crate) enum KernelType {
         order: usize,
     },
     /// A composition of two specified kernels, whose size is the sum of the component kernels.
+    /// As the convolution of the components has one fewer coefficient than this, the final coefficient is zero.
     Composition {
         /// Component kernel.
         left: Box<KernelType>,
@@ -47,6 +48,24 @@ impl Default for KernelType {
 }
 
 impl KernelType {
+    /// Returns the index of the coefficient at the kernel's centre.
+    /// Where the centre falls between two coefficients, the later is returned.
+    fn centre(&self) -> usize {
+        match self {
+            KernelType::Gaussian { sigma } => {
+                if *sigma <= 0.0 {
+                    0
+                } else {
+                    i32::max(1, Real::ceil(4.0 * sigma) as i32) as usize
+                }
+            }
+            KernelType::FiniteDifference { order } => order.div_ceil(2),
+            // The centre of a convolution is the sum of the centres of its components.
+            KernelType::Composition { left, right } => left.centre() + right.centre(),
+            KernelType::ManualCoefficients(coefs) => coefs.len() / 2,
+        }
+    }
+
     /// Generates a vector containing the kernel's coefficients.
     fn generate_kernel(self) -> Vec<Real> {
         match self {
@@ -108,6 +127,8 @@ pub(crate) struct ConvolutionFilter {
     size: Real,
     /// Kernel coefficients, should be generated from a [KernelType].
     kernel: Vec<Real>,
+    /// The index of the coefficient at the kernel's centre.
+    centre: usize,
     /// Current window of the input stream to convolve on.
     window: VecDeque<Real>,
 }
@@ -118,11 +139,13 @@ impl ConvolutionFilter {
     /// # Parameters
     /// - kernel_type: the kernel used in the convolution.
     pub(crate) fn new(kernel_type: KernelType) -> Self {
+        let centre = kernel_type.centre();
         let kernel = kernel_type.generate_kernel();
         let size = kernel.len() as Real;
         ConvolutionFilter {
             window: VecDeque::<Real>::with_capacity(kernel.len()),
             kernel,
+            centre,
             size,
             ..Default::default()
         }
@@ -138,6 +161,15 @@ impl ConvolutionFilter {
         self.kernel.len()
     }
 
+    /// Get the index of the coefficient at the kernel's centre.
+    ///
+    /// When applied to a slice, each output is aligned with the input at this offset from the start of its window,
+    /// so a slice should be left-padded by this many values, and right-padded by the rest of the kernel size.
+    /// This differs from half the kernel size for [KernelType::Composition] kernels, which have a trailing zero.
+    pub(crate) fn kernel_centre(&self) -> usize {
+        self.centre
+    }
+
     /// Convolve the kernel with the given slice.
     ///
     /// This method assumed the given slice is of size no larger then the kernel size.
@@ -189,6 +221,11 @@ impl Window for ConvolutionFilter {
             None
         }
     }
+
+    fn reset(&mut self) {
+        self.value = Real::default();
+        self.window.clear();
+    }
 }
 
 impl SliceWindow for ConvolutionFilter {
@@ -472,4 +509,23 @@ mod tests {
             assert_approx_eq!(a, b);
         }
     }
+
+    #[test]
+    fn test_convolution_composition_centre() {
+        let gaussian = ConvolutionFilter::new(KernelType::Gaussian { sigma: 1.0 });
+        assert_eq!(gaussian.kernel_size(), 9);
+        assert_eq!(gaussian.kernel_centre(), 4);
+
+        let composition = ConvolutionFilter::new(KernelType::Composition {
+            left: Box::new(KernelType::FiniteDifference { order: 2 }),
+            right: Box::new(KernelType::Gaussian { sigma: 1.0 }),
+        });
+        assert_eq!(composition.kernel_size(), 12);
+        assert_eq!(composition.kernel_centre(), 5);
+        // The composition is symmetric about its centre, apart from the trailing zero.
+        assert_eq!(composition.kernel[11], 0.0);
+        for i in 0..5 {
+            assert_approx_eq!(composition.kernel[5 - i - 1], composition.kernel[5 + i + 1]);
+        }
+    }
 }
@@ -0,0 +1,121 @@
+//! Reduces the sample rate of a waveform by averaging blocks of consecutive values.
+//!
+//! # Example
+//!
+//! The following example averages each block of four values of a raw data stream.
+//! The time of each output is the centre of its block, so the time values of the
+//! decimated stream are in the same units as those of the raw stream.
+//! ```ignore
+//!     let decimated = raw
+//!        .map(|(i, v)| (i as Real, v))
+//!        .window(Decimate::new(4));
+//! ```
+use super::{Real, TimeShift, Window};
+
+/// Outputs the mean of each consecutive block of `factor` values, one output per block.
+/// Values after the last complete block of a waveform are discarded.
+#[derive(Default, Clone)]
+pub(crate) struct Decimate {
+    factor: usize,
+    /// The number of values pushed to the current block.
+    count: usize,
+    /// The sum of the values pushed to the current block.
+    sum: Real,
+    /// The mean of the last complete block.
+    value: Option<Real>,
+}
+
+impl Decimate {
+    pub(crate) fn new(factor: usize) -> Self {
+        if factor < 1 {
+            panic!("Factor must be >= 1");
+        }
+        Decimate {
+            factor,
+            ..Default::default()
+        }
+    }
+}
+
+impl TimeShift<Real> for Decimate {
+    /// Shifts the time of the last value of a block to the time of the block's centre.
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time - (self.factor as Real - 1.0) / 2.0
+    }
+}
+
+impl Window for Decimate {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        self.sum += value;
+        self.count += 1;
+        if self.count == self.factor {
+            self.value = Some(self.sum / self.factor as Real);
+            self.sum = 0.0;
+            self.count = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn output(&self) -> Option<Real> {
+        self.value
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.sum = 0.0;
+        self.value = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::iterators::WindowIterable;
+
+    fn decimate(input: &[Real], window: Decimate) -> Vec<(Real, Real)> {
+        input
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| (i as Real, v))
+            .window(window)
+            .collect()
+    }
+
+    #[test]
+    fn blocks_are_averaged_at_their_centre() {
+        let input = [0., 4., 2., 2., 8., 8., 1., 3., 9.];
+        // The last value does not complete a block, so is discarded.
+        assert_eq!(
+            decimate(&input, Decimate::new(4)),
+            vec![(1.5, 2.), (5.5, 5.)]
+        );
+        assert_eq!(
+            decimate(&input, Decimate::new(3)),
+            vec![(1., 2.), (4., 6.), (7., 13. / 3.)]
+        );
+    }
+
+    #[test]
+    fn factor_one_is_identity() {
+        let input = [0., 4., 2., 7.];
+        assert_eq!(
+            decimate(&input, Decimate::new(1)),
+            vec![(0., 0.), (1., 4.), (2., 2.), (3., 7.)]
+        );
+    }
+
+    #[test]
+    fn reset_discards_incomplete_block() {
+        let mut window = Decimate::new(2);
+        window.push(10.0);
+        window.reset();
+        assert_eq!(window.output(), None);
+        assert_eq!(decimate(&[2., 4.], window), vec![(0.5, 3.)]);
+    }
+}
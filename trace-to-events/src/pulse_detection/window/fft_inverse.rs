@@ -2,7 +2,7 @@
 //!
 //! # Example
 //!
-//! ```rust
+//! ```ignore
 //!    let padding_size = 200;
 //!    let input = vec![0.125, 0.5, 0.75, 0.5, 0.125];
 //!    let support = vec![-2, -1, 0, 1, 2];
@@ -8,12 +8,12 @@
 //! data stream.
 //! Note that a [FiniteDifference<N>] window outputs a static array type of length `N`, so we need to extract
 //! the a value at an index to convert it to a scalar stream.
-//! ```rust
+//! ```ignore
 //!     let differential = raw
 //!        .window(FiniteDifference::<2>::new())
 //!        .map(|(i,fd)| (i, fd[1]));
 //! ```
-use crate::pulse_detection::{Temporal, window::TimeShift};
+use crate::pulse_detection::window::TimeShift;
 
 use super::{Real, RealArray, Window};
 use num::integer::binomial;
@@ -24,6 +24,8 @@ pub(crate) struct FiniteDifferences<const N: usize> {
     coefficients: Vec<Vec<Real>>,
     values: VecDeque<Real>,
     diffs: Vec<Real>,
+    /// If true, the time of each output is that of the most recent value, as in previous releases.
+    legacy_time_alignment: bool,
 }
 
 impl<const N: usize> FiniteDifferences<N> {
@@ -38,16 +40,15 @@ impl<const N: usize> FiniteDifferences<N> {
                 })
                 .collect(),
             diffs: vec![Real::default(); N],
+            legacy_time_alignment: false,
         }
     }
 
-    /// Creates a new `FiniteDifferences` with the coefficients cloned this this instance.
-    pub(crate) fn clone_only_coefficients(&self) -> Self {
-        Self {
-            coefficients: self.coefficients.clone(),
-            values: VecDeque::<Real>::with_capacity(N),
-            diffs: vec![Real::default(); N],
-        }
+    /// Sets whether the time of each output is that of the most recent value, as in previous releases,
+    /// rather than that of the centre of the values the output depends on.
+    pub(crate) fn with_legacy_time_alignment(mut self, legacy_time_alignment: bool) -> Self {
+        self.legacy_time_alignment = legacy_time_alignment;
+        self
     }
 
     fn nth_difference(&self, n: usize) -> Real {
@@ -57,9 +58,16 @@ impl<const N: usize> FiniteDifferences<N> {
     }
 }
 
-impl<const N: usize, T: Temporal> TimeShift<T> for FiniteDifferences<N> {
-    fn apply_time_shift(&self, time: T) -> T {
-        time
+impl<const N: usize> TimeShift<usize> for FiniteDifferences<N> {
+    /// The differences depend on the most recent `N` values, so the time is shifted to the centre of these,
+    /// rounded towards the most recent, i.e. the first difference is unshifted.
+    /// With legacy time alignment, the time is unchanged.
+    fn apply_time_shift(&self, time: usize) -> usize {
+        if self.legacy_time_alignment {
+            time
+        } else {
+            time - (N - 1) / 2
+        }
     }
 }
 
@@ -86,6 +94,11 @@ impl<const N: usize> Window for FiniteDifferences<N> {
         (self.values.len() + 1 == N)
             .then_some(RealArray::new(self.diffs.as_slice().try_into().ok()?))
     }
+
+    fn reset(&mut self) {
+        self.values.clear();
+        self.diffs.fill(Real::default());
+    }
 }
 
 #[cfg(test)]
@@ -111,4 +124,25 @@ mod tests {
         assert_eq!(output.next(), Some(RealArray::new([0., -1., 1.])));
         assert!(output.next().is_none());
     }
+
+    #[test]
+    fn times_are_centred() {
+        fn times<const N: usize>(input: &[Intensity], legacy_time_alignment: bool) -> Vec<usize> {
+            input
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i, *v as Real))
+                .window(
+                    FiniteDifferences::<N>::new().with_legacy_time_alignment(legacy_time_alignment),
+                )
+                .map(|(i, _)| i)
+                .collect()
+        }
+        let input: Vec<Intensity> = vec![0, 6, 2, 1, 3, 1, 0];
+        // The first difference is rounded towards the most recent value.
+        assert_eq!(times::<2>(&input, false), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(times::<3>(&input, false), vec![1, 2, 3, 4, 5]);
+        // With legacy time alignment, the time is that of the most recent value.
+        assert_eq!(times::<3>(&input, true), vec![2, 3, 4, 5, 6]);
+    }
 }
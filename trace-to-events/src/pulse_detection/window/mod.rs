@@ -4,7 +4,7 @@
 //!
 //! The following example applies a baseline window, a smoothing window of length five,
 //! and then a finite difference window to a raw data stream.
-//! ```rust
+//! ```ignore
 //!     let smoothed = raw
 //!        .window(Baseline::new(4, 0.1))
 //!        .window(SmoothingWindow::new(5))
@@ -12,20 +12,33 @@
 //!        .window(FiniteDifferences::<2>::new())
 //!        .map(|(i,fd)| (i, fd[1]));
 //! ```
+//!
+//! # Time Alignment
+//!
+//! A window outputs a value each time one is pushed, which depends on the most recent values pushed.
+//! The time of each output is that of the centre of these values, rather than that of the most recent,
+//! so that chaining windows does not delay the times of the features they find.
+//! Where the centre falls between two samples and the time type is an integer, it is rounded towards the most recent.
 
 pub(crate) mod baseline;
 pub(crate) mod convolution_filter;
+pub(crate) mod decimate;
 pub(crate) mod fft_inverse;
 pub(crate) mod finite_differences;
 pub(crate) mod pyramid;
+pub(crate) mod savitzky_golay;
 pub(crate) mod smoothing_window;
+pub(crate) mod with_raw;
 
 use super::{Real, RealArray, Stats, Temporal};
+pub(crate) use decimate::Decimate;
 pub(crate) use finite_differences::FiniteDifferences;
+pub(crate) use savitzky_golay::SavitzkyGolay;
+pub(crate) use with_raw::WithRaw;
 
 /// Consumes values from a waveform, and outputs a waveform after processing.
 pub(crate) trait TimeShift<TimeType: Temporal>: Clone {
-    /// Shifts the time value by half the window's size.
+    /// Shifts the time of the most recent value pushed to the time of the centre of the values the output depends on.
     fn apply_time_shift(&self, time: TimeType) -> TimeType;
 }
 
@@ -40,6 +53,10 @@ pub(crate) trait Window: TimeShift<Self::TimeType> {
 
     /// Extracts the window's current processed value.
     fn output(&self) -> Option<Self::OutputType>;
+
+    /// Returns the window to the state it was created in, so it can be applied to another trace.
+    /// Internal buffers are cleared, but retain their capacity.
+    fn reset(&mut self);
 }
 
 /// Consumes values from a waveform, and outputs a waveform after processing.[TODO]
@@ -20,7 +20,7 @@ use std::ops::{AddAssign, Deref, DerefMut};
 /// - The results of the convolution are read by immutably dereferencing [ConvolutionCache], which returns an immutable slice to [Self::convolved].
 ///
 /// # Example
-/// ```rust
+/// ```ignore
 /// let mut cache = ConvolutionCache::new(10);
 /// cache.init_size(100);
 /// write_stuff_to_vec(&mut cache);
@@ -0,0 +1,206 @@
+//! Implements the [SavitzkyGolay] window.
+//!
+//! This fits a polynomial, by least squares, to a window of values, and outputs the value and first derivative
+//! of the polynomial at the centre of the window.
+//! As this is equivalent to convolving the window with fixed coefficients, these are computed once at construction.
+//!
+//! # Example
+//!
+//! The following example estimates the derivative of a raw data stream, by fitting a quadratic to eleven samples.
+//! Note that, like [FiniteDifferences::<2>], a [SavitzkyGolay] window outputs a static array of length two,
+//! so can be used in its place.
+//! ```ignore
+//!     let differential = raw
+//!        .window(SavitzkyGolay::new(11, 2))
+//!        .map(|(i, sg)| (i, sg[1]));
+//! ```
+//!
+//! [FiniteDifferences::<2>]: super::FiniteDifferences
+use super::{Real, RealArray, TimeShift, Window};
+use std::collections::VecDeque;
+
+#[derive(Default, Clone)]
+pub(crate) struct SavitzkyGolay {
+    /// Coefficients which, convolved with the window, give the smoothed value at its centre.
+    value_coefficients: Vec<Real>,
+    /// Coefficients which, convolved with the window, give the smoothed derivative at its centre.
+    derivative_coefficients: Vec<Real>,
+    /// The values in the window, oldest first.
+    values: VecDeque<Real>,
+}
+
+impl SavitzkyGolay {
+    /// Creates a new window.
+    /// # Parameters
+    /// - window_length: the number of values to which the polynomial is fitted, this must be odd.
+    /// - polynomial_order: the order of the polynomial, this must be at least one, and less than `window_length`.
+    pub(crate) fn new(window_length: usize, polynomial_order: usize) -> Self {
+        if window_length.is_multiple_of(2) {
+            panic!("Window length must be odd");
+        }
+        if polynomial_order < 1 || polynomial_order >= window_length {
+            panic!("Polynomial order must be at least one, and less than the window length");
+        }
+        let [value_coefficients, derivative_coefficients] =
+            least_squares_coefficients(window_length, polynomial_order);
+        Self {
+            value_coefficients,
+            derivative_coefficients,
+            values: VecDeque::with_capacity(window_length),
+        }
+    }
+
+    /// The number of values to which the polynomial is fitted.
+    pub(crate) fn window_length(&self) -> usize {
+        self.value_coefficients.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.values.len() == self.window_length()
+    }
+
+    fn convolve(&self, coefficients: &[Real]) -> Real {
+        coefficients
+            .iter()
+            .zip(&self.values)
+            .map(|(coefficient, value)| coefficient * value)
+            .sum()
+    }
+}
+
+/// Computes the coefficients of the least squares polynomial's value and first derivative at the centre of the window.
+///
+/// These are the first two rows of `(AᵀA)⁻¹Aᵀ`, where `A` is the window's Vandermonde matrix, with times relative to the centre.
+/// They are found by Gauss-Jordan elimination of the normal matrix `AᵀA`, augmented by `Aᵀ`.
+fn least_squares_coefficients(window_length: usize, polynomial_order: usize) -> [Vec<Real>; 2] {
+    let half_length = (window_length / 2) as Real;
+    let times = (0..window_length)
+        .map(|i| i as Real - half_length)
+        .collect::<Vec<_>>();
+    let terms = polynomial_order + 1;
+
+    let mut rows = (0..terms)
+        .map(|j| {
+            (0..terms)
+                .map(|k| times.iter().map(|t| t.powi((j + k) as i32)).sum())
+                .chain(times.iter().map(|t| t.powi(j as i32)))
+                .collect::<Vec<Real>>()
+        })
+        .collect::<Vec<_>>();
+
+    for column in 0..terms {
+        let pivot = (column..terms)
+            .max_by(|&a, &b| rows[a][column].abs().total_cmp(&rows[b][column].abs()))
+            .expect("Range should be non-empty, this should never fail");
+        rows.swap(column, pivot);
+        let pivot_row = rows[column].clone();
+        for (r, row) in rows.iter_mut().enumerate() {
+            if r != column {
+                let factor = row[column] / pivot_row[column];
+                for (x, p) in row.iter_mut().zip(&pivot_row) {
+                    *x -= factor * p;
+                }
+            }
+        }
+    }
+
+    [0, 1].map(|r| rows[r][terms..].iter().map(|x| x / rows[r][r]).collect())
+}
+
+impl TimeShift<usize> for SavitzkyGolay {
+    fn apply_time_shift(&self, time: usize) -> usize {
+        time - self.window_length() / 2
+    }
+}
+
+impl Window for SavitzkyGolay {
+    type TimeType = usize;
+    type InputType = Real;
+    type OutputType = RealArray<2>;
+
+    fn push(&mut self, value: Self::InputType) -> bool {
+        if self.is_full() {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+        self.is_full()
+    }
+
+    fn output(&self) -> Option<Self::OutputType> {
+        self.is_full().then(|| {
+            RealArray::new([
+                self.convolve(&self.value_coefficients),
+                self.convolve(&self.derivative_coefficients),
+            ])
+        })
+    }
+
+    fn reset(&mut self) {
+        self.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pulse_detection::iterators::WindowIterable, test_data::assert_slices_approx_equal,
+    };
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn coefficients_of_five_point_quadratic() {
+        let window = SavitzkyGolay::new(5, 2);
+        assert_slices_approx_equal(
+            &window.value_coefficients,
+            &[-3.0, 12.0, 17.0, 12.0, -3.0].map(|x| x / 35.0),
+        );
+        assert_slices_approx_equal(
+            &window.derivative_coefficients,
+            &[-2.0, -1.0, 0.0, 1.0, 2.0].map(|x| x / 10.0),
+        );
+    }
+
+    #[test]
+    fn no_output_until_window_is_full() {
+        let mut window = SavitzkyGolay::new(7, 2);
+        for i in 0..6 {
+            assert!(!window.push(i as Real));
+            assert!(window.output().is_none());
+        }
+        assert!(window.push(6.0));
+        assert!(window.output().is_some());
+    }
+
+    #[test]
+    fn noiseless_quadratic() {
+        let quadratic = |t: Real| 0.25 * t.powi(2) - 3.0 * t + 7.0;
+        let slope = |t: Real| 0.5 * t - 3.0;
+
+        let output = (0..100)
+            .map(|i| (i, quadratic(i as Real)))
+            .window(SavitzkyGolay::new(11, 2))
+            .collect::<Vec<_>>();
+
+        // The first output is centred on the sixth value.
+        assert_eq!(output.len(), 90);
+        assert_eq!(output.first().map(|(i, _)| *i), Some(5));
+        for (i, sg) in output {
+            assert_approx_eq!(sg[0], quadratic(i as Real), 1e-8);
+            assert_approx_eq!(sg[1], slope(i as Real), 1e-8);
+        }
+    }
+
+    #[test]
+    fn noiseless_cubic_with_cubic_polynomial() {
+        let cubic = |t: Real| 0.01 * t.powi(3) - 0.5 * t.powi(2) + t;
+        let slope = |t: Real| 0.03 * t.powi(2) - t + 1.0;
+
+        let output = (0..50)
+            .map(|i| (i, cubic(i as Real)))
+            .window(SavitzkyGolay::new(9, 3));
+        for (i, sg) in output {
+            assert_approx_eq!(sg[1], slope(i as Real), 1e-8);
+        }
+    }
+}
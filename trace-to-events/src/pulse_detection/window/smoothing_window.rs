@@ -5,7 +5,7 @@
 //! data stream.
 //! Note that a [SmoothingWindow] outputs a [Stats] type, so we need to extract
 //! the [Stats::mean] value to convert to a scalar stream.
-//! ```rust
+//! ```ignore
 //!     let smoothed = raw
 //!        .window(SmoothingWindow::new(5))
 //!        .map(|(i, stats)| (i, stats.mean));
@@ -15,18 +15,27 @@ use crate::pulse_detection::window::TimeShift;
 use super::{Real, Stats, Window};
 use std::collections::VecDeque;
 
-#[allow(unused)] // FIXME
+/// Computes the mean and variance of a sliding window of values.
+///
+/// The variance is updated incrementally by Welford's algorithm, with each removal from a full window
+/// reversing the addition of the removed value. To avoid the loss of precision when the values are
+/// large (e.g. a trace sitting at a large baseline) compared with their variance, the values are
+/// shifted by the first value pushed to the window, so the running statistics are kept about zero.
 #[derive(Default, Clone)]
 pub(crate) struct SmoothingWindow {
     value: Real,
-    sum: Real,
-    sum_of_squares: Real,
+    /// The first value pushed since the window was created or reset, this is subtracted from every value.
+    shift: Real,
+    /// The mean of the shifted values in the window.
+    shifted_mean: Real,
+    /// The sum of the squared differences between the values in the window and their mean.
+    sum_of_squared_deviations: Real,
     size: Real,
+    /// The shifted values in the window.
     window: VecDeque<Real>,
 }
 
 impl SmoothingWindow {
-    #[allow(unused)] // FIXME
     pub(crate) fn new(size: usize) -> Self {
         if size < 1 {
             panic!("Size must be >= 1");
@@ -44,12 +53,12 @@ impl SmoothingWindow {
 
     #[cfg(test)]
     fn test_mean(&self) -> Real {
-        self.window.iter().sum::<f64>() / self.size
+        self.shift + self.window.iter().sum::<f64>() / self.size
     }
 
     #[cfg(test)]
     fn test_variance(&self) -> Real {
-        let mean = self.test_mean();
+        let mean = self.window.iter().sum::<f64>() / self.size;
         self.window.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (self.size - 1.0)
     }
 }
@@ -70,13 +79,23 @@ impl Window for SmoothingWindow {
         if self.size == 1.0 {
             return true;
         }
+        if self.window.is_empty() {
+            self.shift = value;
+        }
+        let value = value - self.shift;
+        let old_mean = self.shifted_mean;
         if self.is_full() {
+            // Replace the oldest value, leaving the number of values unchanged.
             let old = self.window.pop_front().unwrap_or_default();
-            self.sum -= old;
-            self.sum_of_squares -= old.powi(2);
+            self.shifted_mean += (value - old) / self.size;
+            self.sum_of_squared_deviations +=
+                (value - old) * (value - self.shifted_mean + old - old_mean);
+        } else {
+            self.shifted_mean += (value - old_mean) / (self.window.len() + 1) as Real;
+            self.sum_of_squared_deviations += (value - old_mean) * (value - self.shifted_mean);
         }
-        self.sum += value;
-        self.sum_of_squares += value.powi(2);
+        // Rounding errors can leave the sum slightly negative when the values are (almost) constant.
+        self.sum_of_squared_deviations = self.sum_of_squared_deviations.max(0.0);
         self.window.push_back(value);
         self.is_full()
     }
@@ -91,13 +110,21 @@ impl Window for SmoothingWindow {
         } else if self.is_full() {
             Some(Stats {
                 value: self.value,
-                mean: self.sum / self.size,
-                variance: (self.sum_of_squares - self.sum.powi(2) / self.size) / (self.size - 1.0),
+                mean: self.shift + self.shifted_mean,
+                variance: self.sum_of_squared_deviations / (self.size - 1.0),
             })
         } else {
             None
         }
     }
+
+    fn reset(&mut self) {
+        self.value = Real::default();
+        self.shift = Real::default();
+        self.shifted_mean = Real::default();
+        self.sum_of_squared_deviations = Real::default();
+        self.window.clear();
+    }
 }
 
 #[cfg(test)]
@@ -314,4 +341,106 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn reset_window_matches_new_window() {
+        let traces: [Vec<Real>; 2] = [vec![4.0, 3.0, 1.0, 5.0, 3.0, 9.0], vec![2.0, 6.0, 1.0, 1.0]];
+        let mut window = SmoothingWindow::new(3);
+        for trace in &traces {
+            window.reset();
+            let mut output = trace
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i as Real, *v))
+                .window(window);
+            let reused = output
+                .by_ref()
+                .map(|(i, stats)| (i, stats.mean, stats.variance))
+                .collect::<Vec<_>>();
+            (_, window) = output.into_inner();
+            // The window's buffer keeps its capacity.
+            assert!(window.window.capacity() >= 4);
+
+            let new = trace
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i as Real, *v))
+                .window(SmoothingWindow::new(3))
+                .map(|(i, stats)| (i, stats.mean, stats.variance))
+                .collect::<Vec<_>>();
+            assert_eq!(reused, new);
+        }
+    }
+
+    #[test]
+    fn constant_large_offset_has_zero_variance() {
+        for num in [3800.0, 1e6 + 0.3, -4.2e9] {
+            let data: Vec<Real> = vec![num; 200];
+            for window_size in 2..20 {
+                let itr = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (i as Real, *v))
+                    .window(SmoothingWindow::new(window_size));
+                for (_, stats) in itr {
+                    assert_eq!(stats.mean, num);
+                    assert_eq!(stats.variance, 0.0);
+                    assert_eq!(stats.sd(), 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn variance_is_unaffected_by_large_offset() {
+        // The values are multiples of 1/8, so are exactly representable when offset.
+        let data: Vec<Real> = (0..500).map(|i| ((i * 7) % 11) as Real / 8.0).collect();
+        for window_size in 2..50 {
+            let stats = |offset: Real| {
+                data.iter()
+                    .enumerate()
+                    .map(|(i, v)| (i as Real, v + offset))
+                    .window(SmoothingWindow::new(window_size))
+                    .map(|(_, stats)| stats)
+                    .collect::<Vec<_>>()
+            };
+            for (unoffset, offset) in stats(0.0).into_iter().zip(stats(1e6)) {
+                assert_approx_eq!(unoffset.mean + 1e6, offset.mean, 1e-9);
+                assert_approx_eq!(unoffset.variance, offset.variance, 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn random_walk_variance_is_never_negative() {
+        use rand::{RngExt, SeedableRng, rngs::StdRng};
+        let mut rng = StdRng::seed_from_u64(0);
+        for start in [0.0, 3800.0, 1e6] {
+            let data: Vec<Real> = (0..10_000)
+                .scan(start, |value, _| {
+                    // Mostly small steps, with the occasional flat run or large jump.
+                    *value += match rng.random_range(0..10) {
+                        0..=2 => 0.0,
+                        3 => rng.random_range(-500.0..500.0),
+                        _ => rng.random_range(-1.0..1.0),
+                    };
+                    Some(*value)
+                })
+                .collect();
+            for window_size in [2, 3, 8, 33, 100] {
+                let mut itr = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (i as Real, *v))
+                    .window(SmoothingWindow::new(window_size));
+                while let Some((_, stats)) = itr.next() {
+                    assert!(stats.variance >= 0.0, "{}", stats.variance);
+                    assert!(stats.sd().is_finite());
+                    assert_approx_eq!(stats.mean, itr.get_window().test_mean(), 1e-6);
+                    let expected = itr.get_window().test_variance();
+                    assert_approx_eq!(stats.variance, expected, 1e-6 * expected.max(1.0));
+                }
+            }
+        }
+    }
 }
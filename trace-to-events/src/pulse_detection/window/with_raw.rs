@@ -0,0 +1,138 @@
+//! Pairs the output of a window with the raw value at the same time.
+//!
+//! # Example
+//!
+//! The following example estimates the derivative of a raw data stream with a Savitzky-Golay window,
+//! and pairs each estimate with the raw value at its time, rather than the smoothed value.
+//! ```ignore
+//!     let differential = raw
+//!        .window(WithRaw::new(SavitzkyGolay::new(11, 2)))
+//!        .map(|(i, (raw, sg))| (i, RealArray::new([raw, sg[1]])));
+//! ```
+use super::{TimeShift, Window};
+use std::collections::VecDeque;
+
+/// Any time no less than a window's time shift will do to find its delay, see [WithRaw::new].
+const DELAY_PROBE: usize = usize::MAX;
+
+/// Wraps a window, and outputs each of its outputs with the raw value pushed at the output's time.
+///
+/// This allows a detector to be triggered by a smoothed or differenced signal,
+/// whilst reading the heights of the pulses it finds from the raw values.
+#[derive(Clone)]
+pub(crate) struct WithRaw<W: Window> {
+    window: W,
+    /// The number of values pushed since that whose time is the time of the window's output.
+    delay: usize,
+    /// The most recent `delay + 1` values pushed, oldest first.
+    raw: VecDeque<W::InputType>,
+}
+
+impl<W: Window<TimeType = usize>> WithRaw<W> {
+    /// Wraps `window`, whose time shift determines which raw value is output with each of its outputs.
+    pub(crate) fn new(window: W) -> Self {
+        // The time shift of a window is a constant offset, so it can be found by shifting any large enough time.
+        let delay = DELAY_PROBE - window.apply_time_shift(DELAY_PROBE);
+        Self {
+            window,
+            delay,
+            raw: VecDeque::with_capacity(delay + 1),
+        }
+    }
+
+    /// Returns the wrapped window, so it can be reused.
+    pub(crate) fn into_inner(self) -> W {
+        self.window
+    }
+}
+
+impl<W: Window<TimeType = usize>> TimeShift<usize> for WithRaw<W> {
+    fn apply_time_shift(&self, time: usize) -> usize {
+        self.window.apply_time_shift(time)
+    }
+}
+
+impl<W: Window<TimeType = usize>> Window for WithRaw<W> {
+    type TimeType = usize;
+    type InputType = W::InputType;
+    type OutputType = (W::InputType, W::OutputType);
+
+    fn push(&mut self, value: Self::InputType) -> bool {
+        if self.raw.len() > self.delay {
+            self.raw.pop_front();
+        }
+        self.raw.push_back(value);
+        self.window.push(value)
+    }
+
+    fn output(&self) -> Option<Self::OutputType> {
+        Some((*self.raw.front()?, self.window.output()?))
+    }
+
+    fn reset(&mut self) {
+        self.window.reset();
+        self.raw.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::{
+        Real,
+        iterators::WindowIterable,
+        window::{FiniteDifferences, SavitzkyGolay},
+    };
+
+    #[test]
+    fn raw_values_are_aligned_with_output_times() {
+        let trace = [0.0, 1.0, 5.0, 2.0, 0.0, 3.0, 9.0, 4.0, 1.0, 0.0];
+        let output = trace
+            .iter()
+            .copied()
+            .enumerate()
+            .window(WithRaw::new(SavitzkyGolay::new(5, 2)))
+            .collect::<Vec<_>>();
+
+        let unwrapped = trace
+            .iter()
+            .copied()
+            .enumerate()
+            .window(SavitzkyGolay::new(5, 2))
+            .collect::<Vec<_>>();
+        assert_eq!(output.len(), unwrapped.len());
+        for ((time, (raw, value)), (unwrapped_time, unwrapped_value)) in
+            output.iter().zip(unwrapped)
+        {
+            assert_eq!(*time, unwrapped_time);
+            assert_eq!(*raw, trace[*time]);
+            assert_eq!(value[0], unwrapped_value[0]);
+            assert_eq!(value[1], unwrapped_value[1]);
+        }
+    }
+
+    #[test]
+    fn unshifted_window_outputs_latest_raw_value() {
+        let output = (0..5)
+            .map(|i| (i, (i * i) as Real))
+            .window(WithRaw::new(FiniteDifferences::<2>::new()))
+            .map(|(time, (raw, _))| (time, raw))
+            .collect::<Vec<_>>();
+        assert_eq!(output, [(1, 1.0), (2, 4.0), (3, 9.0), (4, 16.0)]);
+    }
+
+    #[test]
+    fn reset_clears_raw_values() {
+        let mut window = WithRaw::new(SavitzkyGolay::new(3, 1));
+        for value in [1.0, 2.0, 3.0] {
+            window.push(value);
+        }
+        assert_eq!(window.output().map(|(raw, _)| raw), Some(2.0));
+        window.reset();
+        assert!(window.output().is_none());
+        for value in [7.0, 8.0, 9.0] {
+            window.push(value);
+        }
+        assert_eq!(window.output().map(|(raw, _)| raw), Some(8.0));
+    }
+}
@@ -0,0 +1,372 @@
+//! Checks the detector settings against sample trace messages, so that settings which cannot find events,
+//! such as an inverted polarity or a threshold beyond the pulses, are reported before any traces are processed.
+use crate::{DetectorSettings, Polarity, Real, find_trace_events};
+use digital_muon_common::{Channel, DigitizerId, Intensity};
+use digital_muon_streaming_types::dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage;
+use std::fmt;
+use thiserror::Error;
+
+/// Samples which deviate from the baseline by more than this many multiples of the noise sigma are taken to be part of a pulse.
+const EXCURSION_SIGMAS: Real = 5.0;
+
+/// The ratio of the standard deviation of normally distributed noise to its median absolute deviation.
+const MAD_TO_SIGMA: Real = 1.4826;
+
+/// The properties of a single channel trace of a sample message, and of the events found in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelSummary {
+    pub digitizer_id: DigitizerId,
+    pub channel: Channel,
+    /// The median of the voltages of the trace.
+    pub baseline: Real,
+    /// The noise sigma of the trace, estimated from the median absolute deviation of its voltages from `baseline`.
+    pub noise_sigma: Real,
+    /// The mean signed deviation from `baseline` of the voltages which deviate by more than [EXCURSION_SIGMAS] noise sigmas,
+    /// this is positive if the pulses of the trace are positive, and zero if there are none.
+    pub mean_excursion: Real,
+    /// The number of events found by the detector.
+    pub num_events: usize,
+    /// The least intensity of the events found, if there are any.
+    pub min_amplitude: Option<Intensity>,
+    /// The greatest intensity of the events found, if there are any.
+    pub max_amplitude: Option<Intensity>,
+}
+
+impl fmt::Display for ChannelSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "digitiser {} channel {}: baseline {:.1}, noise sigma {:.2}, mean excursion {:.1}, {} events",
+            self.digitizer_id,
+            self.channel,
+            self.baseline,
+            self.noise_sigma,
+            self.mean_excursion,
+            self.num_events
+        )?;
+        if let (Some(min), Some(max)) = (self.min_amplitude, self.max_amplitude) {
+            write!(f, ", amplitudes {min} to {max}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SelfTestError {
+    #[error("the sample trace messages have no channel traces")]
+    NoChannels,
+    #[error("the pulses of the sample traces are {observed:?}, but the polarity is {configured:?}")]
+    InvertedPolarity {
+        configured: Polarity,
+        observed: Polarity,
+    },
+    #[error("no events were found in any of the {num_channels} sample channel traces")]
+    NoEvents { num_channels: usize },
+}
+
+/// Returns the median of `values`, which are sorted in place, or zero if there are none.
+fn median(values: &mut [Real]) -> Real {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(Real::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Applies the detector to a single channel trace, and summarises it.
+/// # Parameters
+/// - digitizer_id: the digitiser of the trace.
+/// - channel: the channel of the trace.
+/// - voltage: the voltages of the trace.
+/// - sample_time: sample time in ns.
+/// - settings: settings to use for the detector.
+fn summarise_channel(
+    digitizer_id: DigitizerId,
+    channel: Channel,
+    voltage: &[Intensity],
+    sample_time: Real,
+    settings: &DetectorSettings,
+) -> ChannelSummary {
+    let mut values = voltage.iter().map(|&v| v as Real).collect::<Vec<_>>();
+    let baseline = median(&mut values);
+    let mut deviations = values
+        .iter()
+        .map(|v| (v - baseline).abs())
+        .collect::<Vec<_>>();
+    let noise_sigma = MAD_TO_SIGMA * median(&mut deviations);
+
+    let excursions = voltage
+        .iter()
+        .map(|&v| v as Real - baseline)
+        .filter(|deviation| deviation.abs() > EXCURSION_SIGMAS * noise_sigma)
+        .collect::<Vec<_>>();
+    let mean_excursion = if excursions.is_empty() {
+        0.0
+    } else {
+        excursions.iter().sum::<Real>() / excursions.len() as Real
+    };
+
+    let (_, intensities) = find_trace_events(voltage, sample_time, settings);
+    ChannelSummary {
+        digitizer_id,
+        channel,
+        baseline,
+        noise_sigma,
+        mean_excursion,
+        num_events: intensities.len(),
+        min_amplitude: intensities.iter().min().copied(),
+        max_amplitude: intensities.iter().max().copied(),
+    }
+}
+
+/// Applies the detector to every channel trace of `messages`, and summarises each.
+/// Channel traces with no voltage vector are skipped.
+/// # Parameters
+/// - messages: the sample trace messages.
+/// - settings: settings to use for the detector.
+pub fn summarise_channels<'a>(
+    messages: impl IntoIterator<Item = DigitizerAnalogTraceMessage<'a>>,
+    settings: &DetectorSettings,
+) -> Vec<ChannelSummary> {
+    let mut summaries = Vec::new();
+    for message in messages {
+        let sample_time = 1_000_000_000.0 / message.sample_rate() as Real;
+        let Some(channels) = message.channels() else {
+            continue;
+        };
+        for trace in channels {
+            let Some(voltage) = trace.voltage() else {
+                continue;
+            };
+            summaries.push(summarise_channel(
+                message.digitizer_id(),
+                trace.channel(),
+                &voltage.iter().collect::<Vec<_>>(),
+                sample_time,
+                settings,
+            ));
+        }
+    }
+    summaries
+}
+
+/// Checks that the summaries of the sample traces are consistent with the detector settings.
+/// The polarity is judged inverted if the mean excursions of the channels sum to a deviation in the opposite direction to `polarity`,
+/// and is not judged if there are no excursions.
+/// # Parameters
+/// - summaries: the summaries of the sample channel traces.
+/// - polarity: the configured polarity of the detector.
+pub fn check_summaries(
+    summaries: &[ChannelSummary],
+    polarity: &Polarity,
+) -> Result<(), SelfTestError> {
+    if summaries.is_empty() {
+        return Err(SelfTestError::NoChannels);
+    }
+    let total_excursion = summaries.iter().map(|s| s.mean_excursion).sum::<Real>();
+    let observed = if total_excursion > 0.0 {
+        Some(Polarity::Positive)
+    } else if total_excursion < 0.0 {
+        Some(Polarity::Negative)
+    } else {
+        None
+    };
+    if let Some(observed) = observed
+        && !matches!(
+            (polarity, observed),
+            (Polarity::Positive, Polarity::Positive) | (Polarity::Negative, Polarity::Negative)
+        )
+    {
+        return Err(SelfTestError::InvertedPolarity {
+            configured: *polarity,
+            observed,
+        });
+    }
+    if summaries.iter().all(|s| s.num_events == 0) {
+        return Err(SelfTestError::NoEvents {
+            num_channels: summaries.len(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FixedThresholdDiscriminatorParameters, Mode, TimeUnits};
+    use digital_muon_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessageArgs,
+            finish_digitizer_analog_trace_message_buffer, root_as_digitizer_analog_trace_message,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+
+    /// Returns a trace about a baseline of 100, with a ripple of one either side,
+    /// and pulses of height `pulse_height` every hundred samples.
+    fn pulsed_trace(pulse_height: i32) -> Vec<Intensity> {
+        (0..1000)
+            .map(|i| {
+                let ripple = if i % 2 == 0 { 1 } else { -1 };
+                let pulse = if i % 100 >= 50 && i % 100 < 55 {
+                    pulse_height
+                } else {
+                    0
+                };
+                (100 + ripple + pulse) as Intensity
+            })
+            .collect()
+    }
+
+    /// Serialises a trace message of two channels with the given voltages.
+    fn create_message(fbb: &mut FlatBufferBuilder<'_>, voltage: &[Intensity]) {
+        let timestamp = GpsTime::new(24, 100, 12, 30, 15, 0, 0, 0);
+        let metadata = FrameMetadataV2::create(
+            fbb,
+            &FrameMetadataV2Args {
+                frame_number: 0,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: 0,
+            },
+        );
+        let channels = (0..2)
+            .map(|channel| {
+                let voltage = fbb.create_vector(voltage);
+                ChannelTrace::create(
+                    fbb,
+                    &ChannelTraceArgs {
+                        channel,
+                        voltage: Some(voltage),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let message = DigitizerAnalogTraceMessageArgs {
+            digitizer_id: 3,
+            metadata: Some(metadata),
+            sample_rate: 1_000_000_000,
+            channels: Some(fbb.create_vector(&channels)),
+        };
+        let message = DigitizerAnalogTraceMessage::create(fbb, &message);
+        finish_digitizer_analog_trace_message_buffer(fbb, message);
+    }
+
+    fn fixed_threshold(threshold: Real) -> Mode {
+        Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+            threshold,
+            duration: 1,
+            cool_off: 0,
+            disarm_threshold: None,
+            veto_threshold: None,
+            veto_extend: 0,
+        })
+    }
+
+    fn summarise(voltage: &[Intensity], mode: &Mode, polarity: Polarity) -> Vec<ChannelSummary> {
+        let mut fbb = FlatBufferBuilder::new();
+        create_message(&mut fbb, voltage);
+        let message = root_as_digitizer_analog_trace_message(fbb.finished_data()).unwrap();
+        summarise_channels(
+            [message],
+            &DetectorSettings {
+                mode,
+                polarity: &polarity,
+                baseline: 100,
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            },
+        )
+    }
+
+    #[test]
+    fn correct_settings_pass() {
+        let summaries = summarise(
+            &pulsed_trace(50),
+            &fixed_threshold(20.0),
+            Polarity::Positive,
+        );
+        assert_eq!(summaries.len(), 2);
+        for (channel, summary) in summaries.iter().enumerate() {
+            assert_eq!(summary.digitizer_id, 3);
+            assert_eq!(summary.channel, channel as Channel);
+            assert!((summary.baseline - 100.0).abs() <= 1.0);
+            assert!(summary.noise_sigma > 0.0 && summary.noise_sigma < 5.0);
+            assert!(summary.mean_excursion > 45.0);
+            assert_eq!(summary.num_events, 10);
+            assert!(summary.min_amplitude.is_some_and(|min| min >= 49));
+            assert!(summary.max_amplitude.is_some_and(|max| max <= 51));
+        }
+        assert!(check_summaries(&summaries, &Polarity::Positive).is_ok());
+    }
+
+    #[test]
+    fn inverted_polarity_fails() {
+        let summaries = summarise(
+            &pulsed_trace(50),
+            &fixed_threshold(20.0),
+            Polarity::Negative,
+        );
+        assert!(summaries.iter().all(|summary| summary.num_events == 0));
+        assert!(matches!(
+            check_summaries(&summaries, &Polarity::Negative),
+            Err(SelfTestError::InvertedPolarity {
+                configured: Polarity::Negative,
+                observed: Polarity::Positive
+            })
+        ));
+
+        let summaries = summarise(
+            &pulsed_trace(-50),
+            &fixed_threshold(20.0),
+            Polarity::Positive,
+        );
+        assert!(
+            summaries
+                .iter()
+                .all(|summary| summary.mean_excursion < -45.0)
+        );
+        assert!(matches!(
+            check_summaries(&summaries, &Polarity::Positive),
+            Err(SelfTestError::InvertedPolarity {
+                configured: Polarity::Positive,
+                observed: Polarity::Negative
+            })
+        ));
+    }
+
+    #[test]
+    fn threshold_beyond_pulses_fails() {
+        let summaries = summarise(
+            &pulsed_trace(50),
+            &fixed_threshold(80.0),
+            Polarity::Positive,
+        );
+        assert!(
+            summaries
+                .iter()
+                .all(|summary| summary.num_events == 0 && summary.min_amplitude.is_none())
+        );
+        assert!(matches!(
+            check_summaries(&summaries, &Polarity::Positive),
+            Err(SelfTestError::NoEvents { num_channels: 2 })
+        ));
+    }
+
+    #[test]
+    fn no_channels_fails() {
+        assert!(matches!(
+            check_summaries(&[], &Polarity::Positive),
+            Err(SelfTestError::NoChannels)
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+//! Shuts the component down gracefully when it receives SIGINT or SIGTERM, see [ShutdownSignal].
+use clap::Args;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::{
+    signal::unix::{Signal, SignalKind, signal},
+    task::JoinError,
+};
+use tracing::{error, info};
+use url::Url;
+
+/// How long to wait for the Pushgateway to accept the final metrics snapshot.
+pub(crate) const PUSHGATEWAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The content type of metrics in the Prometheus text format.
+const PROMETHEUS_TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct ShutdownOpts {
+    /// On SIGINT or SIGTERM, the event lists awaiting delivery are given this many seconds to be delivered,
+    /// after which the component exits with a non-zero exit code, whether or not they have been.
+    #[clap(long, default_value = "30")]
+    shutdown_timeout_s: u64,
+
+    /// If set, a snapshot of the metrics is pushed to this Pushgateway URL on shutdown, so the final values are not lost,
+    /// for instance `http://pushgateway:9091/metrics/job/trace-to-events`. Only `http` URLs are supported.
+    #[clap(long)]
+    metrics_pushgateway: Option<Url>,
+}
+
+impl ShutdownOpts {
+    pub(crate) fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_s)
+    }
+
+    pub(crate) fn metrics_pushgateway(&self) -> Option<&Url> {
+        self.metrics_pushgateway.as_ref()
+    }
+}
+
+/// Resolves when the process is sent either SIGINT, as from the terminal, or SIGTERM, as from Kubernetes.
+pub(crate) struct ShutdownSignal {
+    interrupt: Signal,
+    terminate: Signal,
+}
+
+impl ShutdownSignal {
+    pub(crate) fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Waits for the next signal, and returns its name.
+    pub(crate) async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.interrupt.recv() => "SIGINT",
+            _ = self.terminate.recv() => "SIGTERM",
+        }
+    }
+}
+
+/// Whether the event lists awaiting delivery were delivered before the shutdown timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ShutdownOutcome {
+    /// Every event list was delivered, or failed to be, before the timeout.
+    Drained,
+    /// The timeout was reached with deliveries still pending, so their event lists may be lost.
+    TimedOut,
+}
+
+impl ShutdownOutcome {
+    /// The exit code of the process, which is non-zero if the timeout was reached.
+    pub(crate) fn exit_code(self) -> u8 {
+        match self {
+            Self::Drained => 0,
+            Self::TimedOut => 1,
+        }
+    }
+}
+
+/// Awaits the producer task, which flushes the producer channel once shut down, for no longer than `timeout`.
+/// # Parameters
+/// - producer_task: resolves once the producer task has flushed its channel, see [close_and_flush_producer_channel].
+/// - timeout: the longest to wait for the producer task.
+///
+/// # Returns
+/// Whether the producer task finished in time, or the error if it panicked.
+///
+/// [close_and_flush_producer_channel]: crate::close_and_flush_producer_channel
+pub(crate) async fn drain_producer_task(
+    producer_task: impl Future<Output = Result<(), JoinError>>,
+    timeout: Duration,
+) -> Result<ShutdownOutcome, JoinError> {
+    match tokio::time::timeout(timeout, producer_task).await {
+        Ok(result) => {
+            result?;
+            info!("Producer channel drained");
+            Ok(ShutdownOutcome::Drained)
+        }
+        Err(_) => {
+            error!(
+                "Producer channel not drained within {}s, pending event lists may be lost",
+                timeout.as_secs_f64()
+            );
+            Ok(ShutdownOutcome::TimedOut)
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum PushError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+/// Pushes `metrics`, in the Prometheus text format, to the Pushgateway at `url`,
+/// replacing any metrics previously pushed to the same grouping key.
+pub(crate) async fn push_metrics(url: &Url, metrics: String) -> Result<(), PushError> {
+    reqwest::Client::new()
+        .put(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, PROMETHEUS_TEXT_FORMAT)
+        .body(metrics)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serves a single request with the given status line, and returns the request received.
+    async fn pushgateway(status: &'static str) -> (Url, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/metrics/job/trace-to-events",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            // The request is complete once the body, which follows a blank line, has been read.
+            while !String::from_utf8_lossy(&request).ends_with("metric 1\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn exit_code_is_nonzero_on_timeout() {
+        assert_eq!(ShutdownOutcome::Drained.exit_code(), 0);
+        assert_ne!(ShutdownOutcome::TimedOut.exit_code(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_times_out() {
+        let stuck = tokio::spawn(std::future::pending::<()>());
+        let outcome = drain_producer_task(stuck, Duration::from_millis(10)).await;
+        assert_eq!(outcome.unwrap(), ShutdownOutcome::TimedOut);
+
+        let finished = tokio::spawn(async {});
+        let outcome = drain_producer_task(finished, Duration::from_secs(10)).await;
+        assert_eq!(outcome.unwrap(), ShutdownOutcome::Drained);
+    }
+
+    #[tokio::test]
+    async fn drain_reports_panicked_producer_task() {
+        let panicked = tokio::spawn(async { panic!("producer task panicked") });
+        assert!(
+            drain_producer_task(panicked, Duration::from_secs(10))
+                .await
+                .unwrap_err()
+                .is_panic()
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_are_put_to_pushgateway() {
+        let (url, request) = pushgateway("200 OK").await;
+        push_metrics(&url, "metric 1\n".to_owned()).await.unwrap();
+        let request = request.await.unwrap();
+        assert!(
+            request.starts_with("PUT /metrics/job/trace-to-events HTTP/1.1\r\n"),
+            "{request}"
+        );
+        // Header names are case-insensitive.
+        let headers = request.to_lowercase();
+        assert!(headers.contains("content-length: 9\r\n"), "{request}");
+        assert!(
+            headers.contains("content-type: text/plain; version=0.0.4\r\n"),
+            "{request}"
+        );
+        assert!(request.ends_with("\r\n\r\nmetric 1\n"), "{request}");
+    }
+
+    #[tokio::test]
+    async fn rejected_push_is_an_error() {
+        let (url, _request) = pushgateway("400 Bad Request").await;
+        let PushError::Http(error) = push_metrics(&url, "metric 1\n".to_owned())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn https_is_unsupported() {
+        let url = Url::parse("https://pushgateway/metrics/job/trace-to-events").unwrap();
+        assert!(push_metrics(&url, String::new()).await.is_err());
+    }
+}
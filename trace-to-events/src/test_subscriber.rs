@@ -0,0 +1,59 @@
+//! A subscriber with which tests check the spans created, and the fields recorded to them.
+use std::sync::{Arc, Mutex};
+use tracing::{
+    Event, Metadata, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+
+/// Records the names of the spans created, and the floating point fields recorded to them,
+/// whilst it is the default subscriber of the current thread.
+#[derive(Clone, Default)]
+pub(crate) struct SpanRecorder {
+    names: Arc<Mutex<Vec<&'static str>>>,
+    f64_fields: Arc<Mutex<Vec<(&'static str, f64)>>>,
+}
+
+impl SpanRecorder {
+    /// Returns the names of the spans created, in the order they were created.
+    pub(crate) fn names(&self) -> Vec<&'static str> {
+        self.names.lock().unwrap().clone()
+    }
+
+    /// Returns the names and values of the floating point fields recorded to spans after they were created, in the order they were recorded.
+    pub(crate) fn f64_fields(&self) -> Vec<(&'static str, f64)> {
+        self.f64_fields.lock().unwrap().clone()
+    }
+}
+
+impl Visit for SpanRecorder {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.f64_fields.lock().unwrap().push((field.name(), value));
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut names = self.names.lock().unwrap();
+        names.push(span.metadata().name());
+        Id::from_u64(names.len() as u64)
+    }
+
+    fn record(&self, _: &Id, values: &Record<'_>) {
+        values.record(&mut self.clone());
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
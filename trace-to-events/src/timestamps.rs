@@ -0,0 +1,181 @@
+//! Checks the timestamps of trace messages, so that those of a misconfigured digitiser, for instance in 1970 or
+//! the far future, do not propagate downstream.
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::ValueEnum;
+
+/// Determines how a trace message whose timestamp is implausible is handled.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BadTimestampPolicy {
+    /// The message is dropped, and counted as a failure.
+    Reject,
+    /// The message is processed, with its timestamp replaced by the reference time, that of the Kafka message if present.
+    Rewrite,
+    /// The message is processed as any other.
+    #[default]
+    Forward,
+}
+
+impl BadTimestampPolicy {
+    /// The value recorded to the `bad_timestamp_policy` field of the message's span, and the label of the [BAD_TIMESTAMPS_METRIC].
+    ///
+    /// [BAD_TIMESTAMPS_METRIC]: crate::BAD_TIMESTAMPS_METRIC
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::Rewrite => "rewrite",
+            Self::Forward => "forward",
+        }
+    }
+}
+
+/// Determines which timestamps are implausible, and how their messages are handled.
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampCheck {
+    /// The furthest a timestamp may be from the reference time, before or after it, to be plausible.
+    pub max_skew: TimeDelta,
+    pub policy: BadTimestampPolicy,
+}
+
+/// The outcome of checking the timestamp of a trace message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampVerdict {
+    /// The timestamp is within the maximum skew of the reference time.
+    Plausible,
+    /// The timestamp is implausible, and the message should be dropped.
+    Reject,
+    /// The timestamp is implausible, and should be replaced by the given time.
+    Rewrite(DateTime<Utc>),
+    /// The timestamp is implausible, but the message should be processed as any other.
+    Forward,
+}
+
+impl TimestampVerdict {
+    /// The policy with which the message was handled, if its timestamp is implausible.
+    pub fn policy(&self) -> Option<BadTimestampPolicy> {
+        match self {
+            Self::Plausible => None,
+            Self::Reject => Some(BadTimestampPolicy::Reject),
+            Self::Rewrite(_) => Some(BadTimestampPolicy::Rewrite),
+            Self::Forward => Some(BadTimestampPolicy::Forward),
+        }
+    }
+}
+
+impl TimestampCheck {
+    /// Checks `timestamp` against `reference`, which should be the timestamp of the Kafka message, or the current time if it has none.
+    ///
+    /// A missing timestamp is not checked, as it is already counted as an invalid timestamp.
+    pub fn check(
+        &self,
+        timestamp: Option<DateTime<Utc>>,
+        reference: DateTime<Utc>,
+    ) -> TimestampVerdict {
+        match timestamp {
+            Some(timestamp) if (timestamp - reference).abs() > self.max_skew => match self.policy {
+                BadTimestampPolicy::Reject => TimestampVerdict::Reject,
+                BadTimestampPolicy::Rewrite => TimestampVerdict::Rewrite(reference),
+                BadTimestampPolicy::Forward => TimestampVerdict::Forward,
+            },
+            _ => TimestampVerdict::Plausible,
+        }
+    }
+}
+
+/// Returns the time against which the timestamp of a trace message is checked, see [TimestampCheck::check].
+/// This is the timestamp of the Kafka message, given in milliseconds, or `now` if it is negative, as when it is unavailable.
+pub fn reference_time(kafka_timestamp_ms: i64, now: DateTime<Utc>) -> DateTime<Utc> {
+    (kafka_timestamp_ms >= 0)
+        .then(|| DateTime::from_timestamp_millis(kafka_timestamp_ms))
+        .flatten()
+        .unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Months;
+
+    fn reference() -> DateTime<Utc> {
+        "2025-06-01T12:00:00Z".parse().unwrap()
+    }
+
+    fn implausible_timestamps() -> [DateTime<Utc>; 2] {
+        [
+            DateTime::UNIX_EPOCH,
+            reference().checked_add_months(Months::new(120)).unwrap(),
+        ]
+    }
+
+    fn check(policy: BadTimestampPolicy) -> TimestampCheck {
+        TimestampCheck {
+            max_skew: TimeDelta::seconds(60),
+            policy,
+        }
+    }
+
+    #[test]
+    fn timestamps_within_skew_are_plausible() {
+        for policy in [
+            BadTimestampPolicy::Reject,
+            BadTimestampPolicy::Rewrite,
+            BadTimestampPolicy::Forward,
+        ] {
+            for timestamp in [
+                reference() - TimeDelta::seconds(60),
+                reference(),
+                reference() + TimeDelta::seconds(59),
+                reference() + TimeDelta::seconds(60),
+            ] {
+                assert_eq!(
+                    check(policy).check(Some(timestamp), reference()),
+                    TimestampVerdict::Plausible
+                );
+            }
+            assert_eq!(
+                check(policy).check(None, reference()),
+                TimestampVerdict::Plausible
+            );
+        }
+    }
+
+    #[test]
+    fn implausible_timestamps_are_rejected() {
+        for timestamp in implausible_timestamps() {
+            assert_eq!(
+                check(BadTimestampPolicy::Reject).check(Some(timestamp), reference()),
+                TimestampVerdict::Reject
+            );
+        }
+    }
+
+    #[test]
+    fn implausible_timestamps_are_rewritten() {
+        for timestamp in implausible_timestamps() {
+            assert_eq!(
+                check(BadTimestampPolicy::Rewrite).check(Some(timestamp), reference()),
+                TimestampVerdict::Rewrite(reference())
+            );
+        }
+    }
+
+    #[test]
+    fn implausible_timestamps_are_forwarded() {
+        for timestamp in implausible_timestamps() {
+            assert_eq!(
+                check(BadTimestampPolicy::Forward).check(Some(timestamp), reference()),
+                TimestampVerdict::Forward
+            );
+        }
+    }
+
+    #[test]
+    fn reference_time_falls_back_to_now() {
+        let now = reference();
+        let kafka_timestamp = reference() - TimeDelta::seconds(5);
+        assert_eq!(
+            reference_time(kafka_timestamp.timestamp_millis(), now),
+            kafka_timestamp
+        );
+        assert_eq!(reference_time(-1, now), now);
+    }
+}
@@ -0,0 +1,149 @@
+//! Generates realistic traces deterministically, for use by tests and benchmarks.
+//!
+//! This module is only compiled for tests, or when the `bench-utils` feature is enabled.
+use crate::pulse_detection::Real;
+use digital_muon_common::Intensity;
+use rand::{RngExt, SeedableRng, rngs::StdRng};
+
+/// The standard deviation, in samples, of the gaussian with which the pulses of [TraceSpec] are convolved.
+const PULSE_SPREAD: Real = 0.5;
+/// The rate of the rising exponential of the pulses of [TraceSpec].
+const PULSE_RISING: Real = 3.0;
+/// The rate of the falling exponential of the pulses of [TraceSpec].
+const PULSE_FALLING: Real = 0.25;
+
+/// Returns the value at `x` of a back-to-back exponential pulse convolved with a gaussian,
+/// which approximates the shape of a muon pulse.
+/// # Parameters
+/// - ampl: the amplitude of the pulse, its peak is somewhat less than this.
+/// - spread: the standard deviation of the gaussian.
+/// - x0: the onset of the pulse.
+/// - rising: the rate of the rising exponential.
+/// - falling: the rate of the falling exponential.
+pub fn b2bexp(
+    x: Real,
+    ampl: Real,
+    spread: Real,
+    x0: Real,
+    rising: Real,
+    falling: Real,
+) -> Intensity {
+    let normalising_factor = ampl * 0.5 * (rising * falling) / (rising + falling);
+    let rising_spread = rising * spread.powi(2);
+    let falling_spread = falling * spread.powi(2);
+    let x_shift = x - x0;
+    let rising_exp = Real::exp(rising * 0.5 * (rising_spread + 2.0 * x_shift));
+    let rising_erfc = libm::erfc((rising_spread + x_shift) / (Real::sqrt(2.0) * spread));
+    let falling_exp = Real::exp(falling * 0.5 * (falling_spread - 2.0 * x_shift));
+    let falling_erfc = libm::erfc((falling_spread - x_shift) / (Real::sqrt(2.0) * spread));
+    (normalising_factor * (rising_exp * rising_erfc + falling_exp * falling_erfc)) as Intensity
+}
+
+/// Describes a trace of evenly spaced [b2bexp] pulses on a constant baseline, with optional gaussian noise.
+#[derive(Clone, Debug)]
+pub struct TraceSpec {
+    /// The number of samples in the trace.
+    pub length: usize,
+    /// The value of the trace in the absence of pulses and noise.
+    pub baseline: Intensity,
+    /// The number of samples between the onsets of consecutive pulses.
+    pub pulse_spacing: usize,
+    /// The amplitude of each pulse, as given to [b2bexp]. The height of each pulse is about `0.18` times this.
+    pub pulse_amplitude: Real,
+    /// The standard deviation of the noise, if this is zero the trace is clean.
+    pub noise_sigma: Real,
+}
+
+impl TraceSpec {
+    /// Returns the onset of each pulse, the first being half of [Self::pulse_spacing] into the trace.
+    pub fn pulse_onsets(&self) -> impl Iterator<Item = usize> {
+        (self.pulse_spacing / 2..self.length).step_by(self.pulse_spacing.max(1))
+    }
+
+    /// Returns the value of the pulses, excluding the baseline and noise, at sample `index`.
+    fn pulses_at(&self, index: usize) -> Real {
+        // Pulses decay to nothing well within one spacing, so only the nearest onsets contribute.
+        let spacing = self.pulse_spacing.max(1);
+        let nearest = index / spacing;
+        (nearest.saturating_sub(1)..=nearest + 1)
+            .map(|pulse| pulse * spacing + spacing / 2)
+            .filter(|&onset| onset < self.length)
+            .map(|onset| {
+                b2bexp(
+                    index as Real,
+                    self.pulse_amplitude,
+                    PULSE_SPREAD,
+                    onset as Real,
+                    PULSE_RISING,
+                    PULSE_FALLING,
+                ) as Real
+            })
+            .sum()
+    }
+
+    /// Generates the trace, the noise of which is determined by `seed`.
+    pub fn generate(&self, seed: u64) -> Vec<Intensity> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..self.length)
+            .map(|index| {
+                // Box-Muller transform, the noise is drawn even for clean traces so that it is independent of `noise_sigma`.
+                let u1 = 1.0 - rng.random::<Real>();
+                let u2 = rng.random::<Real>();
+                let noise = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                let value =
+                    self.baseline as Real + self.pulses_at(index) + self.noise_sigma * noise;
+                value.round().clamp(0.0, Intensity::MAX as Real) as Intensity
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(noise_sigma: Real) -> TraceSpec {
+        TraceSpec {
+            length: 1_000,
+            baseline: 100,
+            pulse_spacing: 200,
+            pulse_amplitude: 1000.0,
+            noise_sigma,
+        }
+    }
+
+    #[test]
+    fn clean_trace() {
+        let spec = spec(0.0);
+        assert_eq!(
+            spec.pulse_onsets().collect::<Vec<_>>(),
+            [100, 300, 500, 700, 900]
+        );
+
+        let trace = spec.generate(0);
+        assert_eq!(trace.len(), 1_000);
+        assert_eq!(trace[0], 100);
+        // Each pulse peaks shortly after its onset.
+        for onset in spec.pulse_onsets() {
+            let peak = (onset..onset + 10)
+                .max_by_key(|&index| trace[index])
+                .unwrap();
+            assert_eq!(peak, onset + 1);
+            assert_eq!(trace[peak], 279);
+        }
+    }
+
+    #[test]
+    fn noise_is_determined_by_seed() {
+        let noisy = spec(10.0);
+        assert_eq!(noisy.generate(42), noisy.generate(42));
+        assert_ne!(noisy.generate(42), noisy.generate(43));
+        // The noise is centred on the clean trace.
+        let clean = spec(0.0).generate(42);
+        let mean_deviation = Iterator::zip(noisy.generate(42).iter(), clean.iter())
+            .map(|(&noisy, &clean)| noisy as Real - clean as Real)
+            .sum::<Real>()
+            / clean.len() as Real;
+        assert!(mean_deviation.abs() < 1.5, "{mean_deviation}");
+    }
+}
@@ -0,0 +1,55 @@
+//! Measures selecting and paging through the messages of a large session, so the indexed queries
+//! can be compared against linear scans of the session's messages.
+//!
+//! Each query is made near the start, middle and end of a session of 10k messages, as returned by a dragnet search.
+//! The indexed queries take the same time wherever they are made, whereas the scans grow with the position.
+//!
+//! Run with `cargo bench -p trace-viewer --features bench-utils --bench session_cache`,
+//! optionally followed by `-- <FILTER>` to run only the cases whose names contain `FILTER`, for instance `-- select`.
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+use trace_viewer::sessions::bench_utils::SyntheticSession;
+
+const NUM_MESSAGES: usize = 10_000;
+const NUM_DIGITISERS: u8 = 8;
+/// The number of messages on each page, the largest page size offered by the results section.
+const PAGE_SIZE: usize = 100;
+/// The positions in the results list at which the queries are made.
+const POSITIONS: [usize; 3] = [0, NUM_MESSAGES / 2, NUM_MESSAGES - PAGE_SIZE];
+
+fn session_cache(c: &mut Criterion) {
+    let session = SyntheticSession::new(NUM_MESSAGES, NUM_DIGITISERS);
+
+    let mut group = c.benchmark_group("select");
+    for position in POSITIONS {
+        group.bench_with_input(
+            BenchmarkId::new("indexed", position),
+            &position,
+            |b, &position| b.iter(|| session.select(black_box(position))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("scan", position),
+            &position,
+            |b, &position| b.iter(|| session.select_by_scan(black_box(position))),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("page");
+    for position in POSITIONS {
+        group.bench_with_input(
+            BenchmarkId::new("indexed", position),
+            &position,
+            |b, &position| b.iter(|| session.page(black_box(position), PAGE_SIZE)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("scan", position),
+            &position,
+            |b, &position| b.iter(|| session.page_by_scan(black_box(position), PAGE_SIZE)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, session_cache);
+criterion_main!(benches);
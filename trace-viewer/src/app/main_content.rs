@@ -4,8 +4,14 @@ use leptos_use::use_interval;
 use crate::{
     Uuid,
     app::{
-        sections::{BrokerSection, ResultsSection, SearchSection},
-        server_functions::{AwaitSearch, CreateNewSearch, FetchSearchSummaries, RefreshSession},
+        sections::{
+            AdminSection, AggregateSection, BrokerSection, LiveSection, ResultsSection,
+            SearchSection,
+        },
+        server_functions::{
+            AwaitSearch, CreateNewSearch, FetchSearchSummaries, LoadSession, RefreshSession,
+            SaveSession,
+        },
     },
 };
 
@@ -17,6 +23,8 @@ pub(crate) struct MainLevelContext {
     pub(crate) create_new_search: ServerAction<CreateNewSearch>,
     pub(crate) await_search: ServerAction<AwaitSearch>,
     pub(crate) fetch_search_search: ServerAction<FetchSearchSummaries>,
+    pub(crate) load_session: ServerAction<LoadSession>,
+    pub(crate) save_session: ServerAction<SaveSession>,
     pub(crate) uuid: Signal<Uuid>,
 }
 
@@ -26,29 +34,30 @@ pub(crate) struct MainLevelContext {
 #[component]
 pub(crate) fn Main() -> impl IntoView {
     let create_new_search = ServerAction::<CreateNewSearch>::new();
-    // Derived signal which collects the `Uuid` when `create_new_search` finishes, and
-    // emits a warning if the result is an `Err`.
-    let uuid = Signal::derive(move || {
-        create_new_search
-            .value()
-            .get()
-            .and_then(|uuid| uuid.inspect_err(|e| logging::warn!("{e}")).ok())
-    });
+    let load_session = ServerAction::<LoadSession>::new();
+    // Collects the `Uuid` when `create_new_search` or `load_session` finishes.
+    let uuid = RwSignal::<Uuid>::new(None);
     provide_context(MainLevelContext {
         create_new_search,
-        uuid,
+        uuid: uuid.into(),
         await_search: ServerAction::new(),
         fetch_search_search: ServerAction::new(),
+        load_session,
+        save_session: ServerAction::new(),
     });
 
-    init_search_control_effects();
+    init_search_control_effects(uuid);
+    init_load_session_effects(uuid);
     init_refresh_session_effect();
 
     view! {
         <div class = "main">
             <SearchSection />
             <ResultsSection />
+            <AggregateSection />
+            <LiveSection />
             <BrokerSection />
+            <AdminSection />
         </div>
     }
 }
@@ -56,15 +65,14 @@ pub(crate) fn Main() -> impl IntoView {
 /// Creates the [ServerAction]s which create, run, and collect results from, a search job,
 /// and the [Effect]s through which they interact.
 /// - When `create_new_search` is pending, then `await_search` and `fetch_search_summaries` are cleared.
-/// - When `uuid` updates, then `await_search` is dispatched. Note that `uuid` updates whenever `create_new_search` completes.
+/// - When `create_new_search` completes, then (after error handling), `uuid` is set and `await_search` is dispatched.
 /// - When `await_search` finishes, then (after error handling), `fetch_search_summaries` is dispatched.
-fn init_search_control_effects() {
+fn init_search_control_effects(uuid: RwSignal<Uuid>) {
     let main_context = use_context::<MainLevelContext>()
         .expect("MainLevelContext should be provided, this should never fail.");
     let create_new_search = main_context.create_new_search;
     let await_search = main_context.await_search;
     let fetch_search_summaries = main_context.fetch_search_search;
-    let uuid = main_context.uuid;
 
     // Clear await_search and fetch_search_summaries when a new search is created.
     Effect::new(move || {
@@ -75,10 +83,13 @@ fn init_search_control_effects() {
     });
 
     // Call await search when a new uuid is created.
-    Effect::new(move || {
-        if let Some(uuid) = uuid.get() {
-            await_search.dispatch(AwaitSearch { uuid });
+    Effect::new(move || match create_new_search.value().get() {
+        Some(Ok(new_uuid)) => {
+            uuid.set(Some(new_uuid.clone()));
+            await_search.dispatch(AwaitSearch { uuid: new_uuid });
         }
+        Some(Err(e)) => logging::warn!("{e}"),
+        _ => {}
     });
 
     // Fetch summaries when await_search is finished.
@@ -91,6 +102,34 @@ fn init_search_control_effects() {
     });
 }
 
+/// Creates the [Effect]s through which a saved session is loaded.
+/// - When `load_session` is pending, then `await_search` and `fetch_search_summaries` are cleared.
+/// - When `load_session` completes, then (after error handling), `uuid` is set and `fetch_search_summaries` is dispatched.
+///   As the loaded session has no search to await, `await_search` is skipped.
+fn init_load_session_effects(uuid: RwSignal<Uuid>) {
+    let main_context = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.");
+    let load_session = main_context.load_session;
+    let await_search = main_context.await_search;
+    let fetch_search_summaries = main_context.fetch_search_search;
+
+    Effect::new(move || {
+        if load_session.pending().get() {
+            await_search.clear();
+            fetch_search_summaries.clear();
+        }
+    });
+
+    Effect::new(move || match load_session.value().get() {
+        Some(Ok(new_uuid)) => {
+            uuid.set(Some(new_uuid.clone()));
+            fetch_search_summaries.dispatch(FetchSearchSummaries { uuid: new_uuid });
+        }
+        Some(Err(e)) => logging::warn!("{e}"),
+        _ => {}
+    });
+}
+
 /// Creates: the [ServerAction] to refresh a session with a given `uuid`,
 /// an interval timer which triggers every 30,000 ms, and
 /// an effect which dispatches the action when the timer triggers.
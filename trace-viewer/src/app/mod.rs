@@ -17,6 +17,7 @@ use topbar::TopBar;
 
 cfg_if! {
     if #[cfg(feature = "ssr")] {
+        pub use server_functions::{export_plot_html_route, export_session_raw_route};
         pub(crate) use server_functions::{ServerError, SessionError};
     }
 }
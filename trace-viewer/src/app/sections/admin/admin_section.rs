@@ -0,0 +1,89 @@
+use crate::{
+    app::{
+        components::{DisplayErrors, Section},
+        server_functions::GetEngineStatus,
+    },
+    structs::{EngineStatus, SessionStatus},
+};
+use leptos::{IntoView, component, either::Either, ev::MouseEvent, prelude::*, view};
+
+/// Formats a number of bytes in MiB.
+fn format_mib(bytes: usize) -> String {
+    format!("{:.2} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+#[component]
+pub(crate) fn AdminSection() -> impl IntoView {
+    let get_engine_status = ServerAction::<GetEngineStatus>::new();
+
+    let on_refresh = move |_: MouseEvent| {
+        get_engine_status.dispatch(GetEngineStatus {});
+    };
+
+    view! {
+        <Section text = "Admin" id = "admin">
+            <div class = "content admin-control">
+                <input type = "button" class = "engine-status-button" value = "Get Engine Status"
+                    disabled = move || get_engine_status.pending().get()
+                    on:click = on_refresh
+                />
+            </div>
+            {move || {
+                if get_engine_status.pending().get() {
+                    Either::Left(view! {<p> "Loading Engine Status..."</p>})
+                } else {
+                    Either::Right(get_engine_status.value().get().map(move |status| {
+                        let status = status.map(|status| view! { <EngineStatusTable status /> });
+                        view! {
+                            <ErrorBoundary fallback = move |errors| view!{ <DisplayErrors errors /> }>
+                                {status}
+                            </ErrorBoundary>
+                        }
+                    }))
+                }
+            }}
+        </Section>
+    }
+}
+
+#[component]
+fn EngineStatusTable(status: EngineStatus) -> impl IntoView {
+    let cap = status
+        .memory_cap_bytes
+        .map(format_mib)
+        .unwrap_or_else(|| "none".to_owned());
+    let sessions = status.sessions;
+    let plot_cache = status.plot_cache;
+    view! {
+        <div class = "engine-status">
+            <div class = "engine-status-summary">
+                {sessions.len()} " session(s), holding " {format_mib(status.memory_used_bytes)}
+                " of a cap of " {cap} ". Sessions expire " {status.session_ttl_sec} " s after their last refresh."
+            </div>
+            <div class = "engine-status-summary">
+                "The plot cache holds " {plot_cache.entries} " of at most " {plot_cache.max_entries} " plot(s), "
+                {format_mib(plot_cache.size_bytes)} " of " {format_mib(plot_cache.max_bytes)} ", with "
+                {plot_cache.hits} " hit(s) and " {plot_cache.misses} " miss(es)."
+            </div>
+            <div class = "table">
+                <div class = "topic-data-header">"Session"</div>
+                <div class = "topic-data-header">"Size"</div>
+                <div class = "topic-data-header">"Age (s)"</div>
+                <div class = "topic-data-header">"Idle (s)"</div>
+                <For each = move || sessions.clone() key = |session| session.key_prefix.clone() let(session)>
+                    <SessionStatusRow session />
+                </For>
+            </div>
+        </div>
+    }
+}
+
+#[component]
+fn SessionStatusRow(session: SessionStatus) -> impl IntoView {
+    view! {
+        <div class = "topic-data-item">{session.key_prefix}</div>
+        <div class = "topic-data-item">{format_mib(session.size_bytes)}</div>
+        <div class = "topic-data-item">{session.age_sec}</div>
+        <div class = "topic-data-item">{session.idle_sec}</div>
+    }
+}
@@ -0,0 +1,4 @@
+//! Implements the [Section] which shows the sessions held by the server, and the memory they use.
+mod admin_section;
+
+pub(crate) use admin_section::AdminSection;
@@ -0,0 +1,167 @@
+use crate::{
+    Channel,
+    app::{
+        TopLevelContext,
+        components::{DisplayErrors, Section},
+        main_content::MainLevelContext,
+        sections::results::DisplayGraph,
+        server_functions::CreateHistogramPlotly,
+    },
+    structs::{HistogramQuantity, SearchSummary},
+};
+use leptos::{IntoView, component, ev::MouseEvent, logging, prelude::*, view};
+use strum::IntoEnumIterator;
+
+/// The number of bins into which a histogram is divided, until changed.
+const DEFAULT_HISTOGRAM_BINS: usize = 100;
+
+#[component]
+pub(crate) fn AggregateSection() -> impl IntoView {
+    let fetch_search_summaries = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .fetch_search_search;
+
+    let create_histogram_plotly = ServerAction::<CreateHistogramPlotly>::new();
+
+    // Errors fetching the summaries are displayed by the results section.
+    move || {
+        create_histogram_plotly.clear();
+        fetch_search_summaries
+            .value()
+            .get()
+            .and_then(Result::ok)
+            .map(|search_summary| view! { <DisplayAggregate search_summary create_histogram_plotly /> })
+    }
+}
+
+/// Parses a comma separated list of channels, which is [None] if the list is empty, so that every channel is included.
+fn parse_channels(value: &str) -> Result<Option<Vec<Channel>>, String> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    value
+        .split(',')
+        .map(|channel| {
+            channel
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid channel: {channel}, {e}"))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Allows a histogram of the events of every message of the search results to be plotted,
+/// filtered by channel and eventlist topic.
+#[component]
+fn DisplayAggregate(
+    search_summary: SearchSummary,
+    create_histogram_plotly: ServerAction<CreateHistogramPlotly>,
+) -> impl IntoView {
+    let eventlist_topics = use_context::<TopLevelContext>()
+        .expect("TopLevelContext should be provided, this should never fail.")
+        .client_side_data
+        .eventlist_topics;
+
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let topics = search_summary
+        .eventlist_topic_indices
+        .iter()
+        .map(|&index| {
+            let topic = eventlist_topics
+                .get(index)
+                .cloned()
+                .unwrap_or_else(|| format!("topic {index}"));
+            (index, topic)
+        })
+        .collect::<Vec<_>>();
+
+    let quantity = RwSignal::new(HistogramQuantity::default());
+    let topic_index = RwSignal::new(
+        search_summary
+            .eventlist_topic_indices
+            .first()
+            .copied()
+            .unwrap_or_default(),
+    );
+    let channels = RwSignal::new(String::new());
+    let bins = RwSignal::new(DEFAULT_HISTOGRAM_BINS);
+
+    let on_click = move |_: MouseEvent| {
+        let Some(uuid) = uuid.get() else {
+            return;
+        };
+        match parse_channels(&channels.get()) {
+            Ok(channel_filter) => {
+                create_histogram_plotly.dispatch(CreateHistogramPlotly {
+                    uuid,
+                    channel_filter,
+                    topic_index: topic_index.get(),
+                    bins: bins.get(),
+                    quantity: quantity.get(),
+                });
+            }
+            Err(e) => logging::warn!("{e}"),
+        }
+    };
+
+    view! {
+        <Section id = "aggregate" text = "Aggregate">
+            <div class = "content aggregate-control">
+                <label class = "results-settings-input" for = "histogram-quantity">
+                    "Quantity:"
+                    <select class = "results-settings-input" name = "histogram-quantity" id = "histogram-quantity"
+                        on:change = move |ev| quantity.set(
+                            event_target_value(&ev)
+                                .parse()
+                                .expect("HistogramQuantity value should parse, this should never fail.")
+                        )
+                    >
+                        <For each = HistogramQuantity::iter
+                            key = ToOwned::to_owned
+                            let(value)
+                        >
+                            <option selected={quantity.get() == value} value = {value.to_string()}> {value.to_string()} </option>
+                        </For>
+                    </select>
+                </label>
+                <label class = "results-settings-input" for = "histogram-topic">
+                    "Eventlist topic:"
+                    <select class = "results-settings-input" name = "histogram-topic" id = "histogram-topic"
+                        on:change = move |ev| if let Ok(index) = event_target_value(&ev).parse() { topic_index.set(index) }
+                    >
+                        <For each = move || topics.clone() key = |(index, _)| *index let((index, topic))>
+                            <option selected={topic_index.get() == index} value = index> {topic} </option>
+                        </For>
+                    </select>
+                </label>
+                <label class = "results-settings-input" for = "histogram-channels">
+                    "Channels:"
+                    <input class = "results-settings-input" name = "histogram-channels" id = "histogram-channels" type = "text"
+                        placeholder = "All"
+                        bind:value = channels
+                    />
+                </label>
+                <label class = "results-settings-input" for = "histogram-bins">
+                    "Bins:"
+                    <input class = "results-settings-input" name = "histogram-bins" id = "histogram-bins" type = "text"
+                        value = {move ||bins.get().to_string()}
+                        on:change = {move |ev|if let Ok(parsed) = event_target_value(&ev).parse() { bins.set(parsed) }}
+                    />
+                </label>
+                <input type = "button" value = "Plot Histogram"
+                    disabled = move || uuid.get().is_none() || create_histogram_plotly.pending().get()
+                    on:click = on_click
+                />
+            </div>
+            {move || create_histogram_plotly.value().get().map(|histogram| view! {
+                <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                    {histogram.map(|trace_plotly| view!{ <DisplayGraph trace_plotly graph_id = "aggregate-graph" /> })}
+                </ErrorBoundary>
+            })}
+        </Section>
+    }
+}
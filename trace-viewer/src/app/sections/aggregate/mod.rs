@@ -0,0 +1,4 @@
+//! Implements the [Section] which plots histograms of the events of every message in the results of a search.
+mod aggregate_section;
+
+pub(crate) use aggregate_section::AggregateSection;
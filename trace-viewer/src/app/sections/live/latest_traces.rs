@@ -0,0 +1,63 @@
+use crate::{
+    app::{components::DisplayErrors, sections::live::live_section::LiveLevelContext},
+    structs::TraceSummary,
+};
+use leptos::{IntoView, component, prelude::*, view};
+
+/// Lists the most recent message of each digitiser received by the live tail,
+/// and allows a channel of one of them to be selected for plotting.
+#[component]
+pub(super) fn LatestTraces() -> impl IntoView {
+    let get_latest_traces = use_context::<LiveLevelContext>()
+        .expect("LiveLevelContext should be provided, this should never fail.")
+        .get_latest_traces;
+
+    move || {
+        get_latest_traces.value().get().map(|trace_summaries| view! {
+            <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                {trace_summaries.map(|trace_summaries| view! {
+                    <div class = "latest-traces">
+                        {trace_summaries.into_iter().map(|trace_summary| view!{ <LatestTrace trace_summary /> }).collect::<Vec<_>>()}
+                    </div>
+                })}
+            </ErrorBoundary>
+        })
+    }
+}
+
+#[component]
+fn LatestTrace(trace_summary: TraceSummary) -> impl IntoView {
+    let selected = use_context::<LiveLevelContext>()
+        .expect("LiveLevelContext should be provided, this should never fail.")
+        .selected;
+
+    let id = trace_summary.id;
+    let mut channels = trace_summary.channels;
+    channels.sort();
+
+    let selected_pred = move || {
+        selected
+            .get()
+            .is_some_and(|(selected_id, _)| selected_id == id)
+    };
+
+    view! {
+        <div class = "digitiser-message" class = ("selected", selected_pred)>
+            <div class = "digitiser-message-id"> "Id: " {id}</div>
+            <div> "Frame " {trace_summary.frame_number} " at " {trace_summary.time} </div>
+            <div class = "channel-list">
+                {channels.into_iter().map(|channel| {
+                    let selected_pred = move || selected.get() == Some((id, channel));
+                    view! {
+                        <div class = "channel"
+                            class = ("selected", selected_pred)
+                            on:click = move |_| selected.set(Some((id, channel)))
+                        >
+                            {channel}
+                        </div>
+                    }
+                }).collect::<Vec<_>>()}
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    Channel, DigitizerId, Uuid,
+    app::{
+        components::{DisplayErrors, Section},
+        sections::{live::latest_traces::LatestTraces, results::DisplayGraph},
+        server_functions::{
+            CreateAndFetchLivePlotly, GetLatestTraces, StartLiveTail, StopLiveTail,
+        },
+    },
+};
+use leptos::{IntoView, component, ev::MouseEvent, logging, prelude::*, view};
+use leptos_use::use_interval;
+
+/// The interval, in ms, at which the client polls the live tail for its latest traces.
+const POLL_INTERVAL_MS: u64 = 2_000;
+
+/// This struct enable a degree of type-checking for the [use_context]/[use_context] functions.
+/// Any component making use of the following fields should call `use_context::<LiveLevelContext>()`
+/// and select the desired field.
+#[derive(Clone)]
+pub(super) struct LiveLevelContext {
+    pub(super) get_latest_traces: ServerAction<GetLatestTraces>,
+    pub(super) create_and_fetch_live_plotly: ServerAction<CreateAndFetchLivePlotly>,
+    /// The digitiser and channel whose latest trace is plotted.
+    pub(super) selected: RwSignal<Option<(DigitizerId, Channel)>>,
+}
+
+#[component]
+pub(crate) fn LiveSection() -> impl IntoView {
+    let start_live_tail = ServerAction::<StartLiveTail>::new();
+    let stop_live_tail = ServerAction::<StopLiveTail>::new();
+    let get_latest_traces = ServerAction::<GetLatestTraces>::new();
+    let create_and_fetch_live_plotly = ServerAction::<CreateAndFetchLivePlotly>::new();
+    // Collects the `Uuid` of the live tail when `start_live_tail` finishes.
+    let uuid = RwSignal::<Uuid>::new(None);
+    let selected = RwSignal::new(None);
+    provide_context(LiveLevelContext {
+        get_latest_traces,
+        create_and_fetch_live_plotly,
+        selected,
+    });
+
+    init_start_live_tail_effect(start_live_tail, uuid);
+    init_poll_live_tail_effect(uuid);
+
+    let on_start = move |_: MouseEvent| {
+        start_live_tail.dispatch(StartLiveTail {});
+    };
+
+    let on_stop = move |_: MouseEvent| {
+        if let Some(uuid) = uuid.get() {
+            stop_live_tail.dispatch(StopLiveTail { uuid });
+        }
+        uuid.set(None);
+        selected.set(None);
+        get_latest_traces.clear();
+        create_and_fetch_live_plotly.clear();
+    };
+
+    view! {
+        <Section text = "Live" id = "live">
+            <div class = "content live-control">
+                <input type = "button" class = "start-live-tail-button" value = "Start"
+                    disabled = move || uuid.get().is_some() || start_live_tail.pending().get()
+                    on:click = on_start
+                />
+                <input type = "button" class = "stop-live-tail-button" value = "Stop"
+                    disabled = move || uuid.get().is_none()
+                    on:click = on_stop
+                />
+            </div>
+            <LatestTraces />
+            <DisplayLiveTrace />
+        </Section>
+    }
+}
+
+/// Creates the [Effect] which sets `uuid` when `start_live_tail` completes.
+fn init_start_live_tail_effect(start_live_tail: ServerAction<StartLiveTail>, uuid: RwSignal<Uuid>) {
+    Effect::new(move || match start_live_tail.value().get() {
+        Some(Ok(new_uuid)) => uuid.set(Some(new_uuid)),
+        Some(Err(e)) => logging::warn!("{e}"),
+        _ => {}
+    });
+}
+
+/// Creates an interval timer which triggers every [POLL_INTERVAL_MS] ms, and an [Effect] which,
+/// whilst the live tail is running, dispatches `get_latest_traces` and, if a channel is selected,
+/// `create_and_fetch_live_plotly` when the timer triggers or the selected channel changes.
+fn init_poll_live_tail_effect(uuid: RwSignal<Uuid>) {
+    let live_level_context = use_context::<LiveLevelContext>()
+        .expect("LiveLevelContext should be provided, this should never fail.");
+    let get_latest_traces = live_level_context.get_latest_traces;
+    let create_and_fetch_live_plotly = live_level_context.create_and_fetch_live_plotly;
+    let selected = live_level_context.selected;
+
+    let poll_interval = use_interval(POLL_INTERVAL_MS);
+    Effect::new(move || {
+        if let Some(uuid) = uuid.get() {
+            poll_interval.counter.track();
+            get_latest_traces.dispatch(GetLatestTraces { uuid: uuid.clone() });
+            if let Some((digitiser_id, channel)) = selected.get() {
+                create_and_fetch_live_plotly.dispatch(CreateAndFetchLivePlotly {
+                    uuid,
+                    digitiser_id,
+                    channel,
+                });
+            }
+        }
+    });
+}
+
+#[component]
+fn DisplayLiveTrace() -> impl IntoView {
+    let create_and_fetch_live_plotly = use_context::<LiveLevelContext>()
+        .expect("LiveLevelContext should be provided, this should never fail.")
+        .create_and_fetch_live_plotly;
+
+    move || {
+        create_and_fetch_live_plotly.value().get().map(|trace| view! {
+            <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                {trace.map(|trace_plotly| view!{ <DisplayGraph trace_plotly graph_id = "live-trace-graph" /> })}
+            </ErrorBoundary>
+        })
+    }
+}
@@ -0,0 +1,6 @@
+//! Implements the [Section] which follows the trace topic in near real time,
+//! and displays the most recent trace of a chosen digitiser and channel.
+mod latest_traces;
+mod live_section;
+
+pub(crate) use live_section::LiveSection;
@@ -1,8 +1,14 @@
 //! Defines collapsible top-level containers used to present data and allow data entry.
+mod admin;
+mod aggregate;
 mod broker_poll;
+mod live;
 mod results;
 mod search;
 
+pub(crate) use admin::AdminSection;
+pub(crate) use aggregate::AggregateSection;
 pub(crate) use broker_poll::BrokerSection;
+pub(crate) use live::LiveSection;
 pub(crate) use results::ResultsSection;
 pub(crate) use search::SearchSection;
@@ -1,4 +1,9 @@
-use crate::app::server_functions::CreateAndFetchPlotly;
+use crate::{
+    app::server_functions::{
+        CompareEventlists, CreateAndFetchMultiPlotly, CreateAndFetchPlotly, RunDetectorOnTrace,
+    },
+    structs::EventFilter,
+};
 use leptos::prelude::*;
 
 /// This struct enable a degree of type-checking for the [use_context]/[use_context] functions.
@@ -7,5 +12,13 @@ use leptos::prelude::*;
 #[derive(Clone)]
 pub(super) struct ResultsLevelContext {
     pub(super) create_and_fetch_plotly: ServerAction<CreateAndFetchPlotly>,
+    pub(super) create_and_fetch_multi_plotly: ServerAction<CreateAndFetchMultiPlotly>,
+    pub(super) run_detector_on_trace: ServerAction<RunDetectorOnTrace>,
+    pub(super) compare_eventlists: ServerAction<CompareEventlists>,
     pub(super) selected_channels_only: RwSignal<bool>,
+    pub(super) event_filter: RwSignal<EventFilter>,
+    /// If present, traces are plotted with at most this many points, see [TraceView::max_points].
+    ///
+    /// [TraceView::max_points]: crate::structs::TraceView::max_points
+    pub(super) max_points: RwSignal<Option<usize>>,
 }
@@ -1,22 +1,72 @@
 use crate::{
-    app::{components::DisplayErrors, sections::results::context::ResultsLevelContext},
-    structs::TracePlotly,
+    Channel,
+    app::{
+        components::DisplayErrors, sections::results::context::ResultsLevelContext,
+        server_functions::CreateAndFetchPlotly,
+    },
+    structs::{MultiTracePlotly, TimeRange, TracePlotly},
 };
-use leptos::{IntoView, component, prelude::*, view};
+use leptos::{IntoView, component, logging, prelude::*, view};
 
 #[component]
 pub(crate) fn DisplayTrace() -> impl IntoView {
-    let create_and_fetch_plotly = use_context::<ResultsLevelContext>()
-        .expect("ResultsLevelContext should be provided, this should never fail")
-        .create_and_fetch_plotly;
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail");
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+    let create_and_fetch_multi_plotly = result_level_context.create_and_fetch_multi_plotly;
+    let run_detector_on_trace = result_level_context.run_detector_on_trace;
+    let compare_eventlists = result_level_context.compare_eventlists;
+    let max_points = result_level_context.max_points;
+
+    // The request of the plot displayed, which is repeated for the visible range when the plot is zoomed.
+    let plotted = RwSignal::new(None::<CreateAndFetchPlotly>);
+    Effect::new(move || {
+        if let Some(input) = create_and_fetch_plotly.input().get() {
+            plotted.set(Some(input));
+        }
+    });
 
     view! {
         <Transition fallback = ||view!("Loading Graph")>
             {move ||create_and_fetch_plotly.value().get().map(|trace| view!{
                 <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
-                    {trace.map(|trace_plotly|
-                        view!{ <DisplayGraph trace_plotly /> }
-                    )}
+                    {trace.map(|mut trace_plotly| {
+                        // Overlay the events found by the detector, if it has been run on this trace.
+                        if let Some(Ok(detector_events)) = run_detector_on_trace.value().get()
+                            && plotted.get_untracked().is_some_and(|plotted| plotted.index_and_channel == detector_events.index_and_channel)
+                        {
+                            trace_plotly.eventlist_data.push(detector_events.eventlist_data);
+                        }
+                        let displayed = trace_plotly.clone();
+                        let on_zoom = Callback::new(move |zoomed| {
+                            if let Some(input) = plotted.get_untracked()
+                                && let Some(view) = displayed.zoomed_view(zoomed, max_points.get_untracked())
+                            {
+                                create_and_fetch_plotly.dispatch(CreateAndFetchPlotly { view, ..input });
+                            }
+                        });
+                        view!{ <DisplayGraph trace_plotly on_zoom /> }
+                    })}
+                </ErrorBoundary>
+            })}
+            {move ||run_detector_on_trace.value().get().map(|detector_events| view!{
+                <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                    {detector_events.map(|_| ())}
+                </ErrorBoundary>
+            })}
+            {move ||compare_eventlists.value().get().map(|comparison| view!{
+                <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                    {comparison.map(|comparison| view!{
+                        <DisplayGraph trace_plotly = comparison.trace_plotly graph_id = "comparison-graph" />
+                    })}
+                </ErrorBoundary>
+            })}
+            {move ||create_and_fetch_multi_plotly.value().get().map(|trace| view!{
+                <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                    {trace.map(|MultiTracePlotly { trace_plotly, missing_channels }| view!{
+                        <DisplayMissingChannels missing_channels />
+                        <DisplayGraph trace_plotly />
+                    })}
                 </ErrorBoundary>
             })}
         </Transition>
@@ -24,31 +74,102 @@ pub(crate) fn DisplayTrace() -> impl IntoView {
 }
 
 #[component]
-pub(crate) fn DisplayGraph(trace_plotly: TracePlotly) -> impl IntoView {
+fn DisplayMissingChannels(missing_channels: Vec<Channel>) -> impl IntoView {
+    (!missing_channels.is_empty()).then(|| {
+        let missing_channels = missing_channels
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        view! {
+            <div class = "trace-graph-warning">
+                "Channels not found in the trace message: " {missing_channels}
+            </div>
+        }
+    })
+}
+
+/// Parses the time range written by the `plotly_relayout` handler of [DisplayGraph], which is empty if the axes are reset.
+fn parse_zoomed_range(value: &str) -> Result<Option<TimeRange>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let (start, end) = value
+        .split_once(',')
+        .ok_or_else(|| format!("Invalid zoomed range: {value}"))?;
+    let parse = |bound: &str| {
+        bound
+            .parse()
+            .map_err(|e| format!("Invalid zoomed range: {value}, {e}"))
+    };
+    Ok(Some(TimeRange {
+        start: parse(start)?,
+        end: parse(end)?,
+    }))
+}
+
+/// Displays the plot in the element with the given `graph_id`,
+/// which must be distinct from that of any other graph displayed at the same time.
+///
+/// If `on_zoom` is given, it is called with the time bins visible whenever the x-axis is zoomed or panned,
+/// or with [None] when the axes are reset.
+#[component]
+pub(crate) fn DisplayGraph(
+    trace_plotly: TracePlotly,
+    #[prop(default = "trace-graph")] graph_id: &'static str,
+    #[prop(optional, into)] on_zoom: Option<Callback<Option<TimeRange>>>,
+) -> impl IntoView {
     let TracePlotly {
         title,
         trace_data,
         eventlist_data,
         layout,
+        ..
     } = trace_plotly;
 
-    let data = if eventlist_data.is_empty() {
-        trace_data
-    } else {
-        format!("{trace_data}, {}", eventlist_data.join(","))
-    };
+    let data = trace_data
+        .into_iter()
+        .chain(eventlist_data)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // The visible range is passed from plotly to `on_zoom` through a hidden input, by firing its change event.
+    let zoom_id = format!("{graph_id}-zoom");
+    let zoom_handler = on_zoom.map(|_| format!("
+                document.getElementById('{graph_id}').on('plotly_relayout', function(event) {{
+                    var input = document.getElementById('{zoom_id}');
+                    var range = event['xaxis.range'] || (event['xaxis.range[0]'] !== undefined && [event['xaxis.range[0]'], event['xaxis.range[1]']]);
+                    if (range) {{
+                        input.value = Math.max(0, Math.floor(range[0])) + ',' + Math.max(0, Math.ceil(range[1]) + 1);
+                    }} else if (event['xaxis.autorange']) {{
+                        input.value = '';
+                    }} else {{
+                        return;
+                    }}
+                    input.dispatchEvent(new Event('change', {{ bubbles: true }}));
+                }});
+    ")).unwrap_or_default();
 
     view! {
-        <div class = "content trace-graph" id = "trace-graph">
+        <div class = "content trace-graph" id = graph_id>
             <div class = "trace-graph-title">
                 {title}
             </div>
-            <div id="trace-graph" class="plotly-graph-div"></div>
+            {on_zoom.map(|on_zoom| view! {
+                <input type = "hidden" id = zoom_id
+                    on:change = move |ev| match parse_zoomed_range(&event_target_value(&ev)) {
+                        Ok(zoomed) => on_zoom.run(zoomed),
+                        Err(e) => logging::warn!("{e}"),
+                    }
+                />
+            })}
+            <div id = graph_id class="plotly-graph-div"></div>
             <script type="text/javascript" inner_html = {format!("
                 var data = [{data}];
                 var layout = {layout};
                 var config = {{ 'scrollZoom': true}};
-                Plotly.newPlot('trace-graph', data, layout, config);
+                Plotly.newPlot('{graph_id}', data, layout, config);
+                {zoom_handler}
             ")}>
             </script>
         </div>
@@ -5,4 +5,5 @@ mod display_trace_graph;
 mod results_section;
 mod search_results;
 
+pub(crate) use display_trace_graph::DisplayGraph;
 pub(crate) use results_section::ResultsSection;
@@ -6,12 +6,17 @@ use crate::{
             context::ResultsLevelContext, display_trace_graph::DisplayTrace,
             search_results::SearchResultsPanel,
         },
-        server_functions::CreateAndFetchPlotly,
+        server_functions::{
+            CompareEventlists, CreateAndFetchMultiPlotly, CreateAndFetchPlotly, RunDetectorOnTrace,
+        },
     },
     structs::SearchSummary,
 };
 use leptos::{IntoView, component, prelude::*, view};
 
+/// The most points with which a trace is plotted, until changed in the results settings.
+const DEFAULT_MAX_PLOT_POINTS: usize = 10_000;
+
 #[component]
 pub(crate) fn ResultsSection() -> impl IntoView {
     let main_context = use_context::<MainLevelContext>()
@@ -19,13 +24,24 @@ pub(crate) fn ResultsSection() -> impl IntoView {
     let fetch_search_summaries = main_context.fetch_search_search;
 
     let create_and_fetch_plotly = ServerAction::<CreateAndFetchPlotly>::new();
+    let create_and_fetch_multi_plotly = ServerAction::<CreateAndFetchMultiPlotly>::new();
+    let run_detector_on_trace = ServerAction::<RunDetectorOnTrace>::new();
+    let compare_eventlists = ServerAction::<CompareEventlists>::new();
     provide_context(ResultsLevelContext {
         create_and_fetch_plotly,
+        create_and_fetch_multi_plotly,
+        run_detector_on_trace,
+        compare_eventlists,
         selected_channels_only: RwSignal::new(false),
+        event_filter: RwSignal::new(Default::default()),
+        max_points: RwSignal::new(Some(DEFAULT_MAX_PLOT_POINTS)),
     });
 
     move || {
         create_and_fetch_plotly.clear();
+        create_and_fetch_multi_plotly.clear();
+        run_detector_on_trace.clear();
+        compare_eventlists.clear();
         fetch_search_summaries.value()
             .get()
             .map(|search_summary| view!{
@@ -0,0 +1,155 @@
+use crate::{
+    app::{
+        components::DisplayErrors,
+        main_content::MainLevelContext,
+        sections::results::{
+            context::ResultsLevelContext, search_results::SelectTraceLevelContext,
+        },
+        server_functions::{AddAnnotation, CreateAndFetchPlotly, DeleteAnnotation, GetAnnotations},
+    },
+    structs::{Annotation, TraceView},
+};
+use leptos::{IntoView, component, logging, prelude::*, view};
+
+/// Lists the annotations of the selected channel, and allows the user to add and delete them.
+/// Whenever an annotation is added or deleted, the list and plot are refetched.
+#[component]
+pub(crate) fn AnnotationsPanel() -> impl IntoView {
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+    let selected_trace_index = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .select_trace_index;
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+    let event_filter = result_level_context.event_filter;
+    let max_points = result_level_context.max_points;
+
+    let add_annotation = ServerAction::<AddAnnotation>::new();
+    let delete_annotation = ServerAction::<DeleteAnnotation>::new();
+    let get_annotations = ServerAction::<GetAnnotations>::new();
+    Effect::new(move || {
+        add_annotation.version().track();
+        delete_annotation.version().track();
+        if let (Some(uuid), Some(index_and_channel)) = (uuid.get(), selected_trace_index.get()) {
+            get_annotations.dispatch(GetAnnotations {
+                uuid,
+                index_and_channel,
+            });
+        }
+    });
+    // Replot the selected trace once an annotation has been added or deleted, so its markers are updated.
+    Effect::new(move |previous: Option<(usize, usize)>| {
+        let versions = (
+            add_annotation.version().get(),
+            delete_annotation.version().get(),
+        );
+        if previous.is_some_and(|previous| previous != versions)
+            && let (Some(uuid), Some(index_and_channel)) =
+                (uuid.get_untracked(), selected_trace_index.get_untracked())
+        {
+            create_and_fetch_plotly.dispatch(CreateAndFetchPlotly {
+                uuid,
+                index_and_channel,
+                event_filter: event_filter.get_untracked(),
+                view: TraceView::whole_trace(max_points.get_untracked()),
+            });
+        }
+        versions
+    });
+    let annotations = Signal::derive(move || {
+        get_annotations
+            .value()
+            .get()
+            .and_then(|annotations| annotations.inspect_err(|e| logging::warn!("{e}")).ok())
+            .unwrap_or_default()
+    });
+
+    let time_bin = RwSignal::new(0);
+    let text = RwSignal::new(String::new());
+    let author = RwSignal::new(String::new());
+
+    let on_click = move |_| {
+        if let (Some(uuid), Some(index_and_channel)) = (uuid.get(), selected_trace_index.get()) {
+            add_annotation.dispatch(AddAnnotation {
+                uuid,
+                index_and_channel,
+                time_bin: time_bin.get(),
+                text: text.get(),
+                author: author.get(),
+            });
+        }
+    };
+
+    move || {
+        selected_trace_index.get().map(|_| view! {
+            <div class = "annotations">
+                <label class = "results-settings-input" for = "annotation-time-bin">
+                    "Time bin:"
+                    <input class = "results-settings-input" name = "annotation-time-bin" id = "annotation-time-bin" type = "number" min = "0"
+                        value = move || time_bin.get()
+                        on:change = move |ev| match event_target_value(&ev).parse() {
+                            Ok(value) => time_bin.set(value),
+                            Err(e) => logging::warn!("Invalid time bin: {e}"),
+                        }
+                    />
+                </label>
+                <label class = "results-settings-input" for = "annotation-text">
+                    "Comment:"
+                    <input class = "results-settings-input" name = "annotation-text" id = "annotation-text" type = "text"
+                        bind:value = text
+                    />
+                </label>
+                <label class = "results-settings-input" for = "annotation-author">
+                    "Author:"
+                    <input class = "results-settings-input" name = "annotation-author" id = "annotation-author" type = "text"
+                        bind:value = author
+                    />
+                </label>
+                <input type = "button" value = "Add Annotation"
+                    prop:disabled = move || add_annotation.pending().get()
+                    on:click = on_click
+                />
+                {move ||add_annotation.value().get().map(|added| view!{
+                    <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                        {added.map(|_| ())}
+                    </ErrorBoundary>
+                })}
+                <ul class = "annotations-list">
+                    <For
+                        each = move || annotations.get()
+                        key = |annotation| annotation.id
+                        let(annotation)
+                    >
+                        <AnnotationItem annotation delete_annotation />
+                    </For>
+                </ul>
+            </div>
+        })
+    }
+}
+
+#[component]
+fn AnnotationItem(
+    annotation: Annotation,
+    delete_annotation: ServerAction<DeleteAnnotation>,
+) -> impl IntoView {
+    let Annotation {
+        id,
+        time_bin,
+        text,
+        author,
+        created,
+    } = annotation;
+    view! {
+        <li class = "annotation">
+            {format!("Time bin {time_bin}: {text} ({author}, {})", created.format("%y-%m-%d %H:%M:%S"))}
+            <input type = "button" value = "Delete"
+                prop:disabled = move || delete_annotation.pending().get()
+                on:click = move |_| { delete_annotation.dispatch(DeleteAnnotation { id }); }
+            />
+        </li>
+    }
+}
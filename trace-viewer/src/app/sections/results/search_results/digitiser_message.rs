@@ -1,11 +1,13 @@
 use crate::{
     app::{
+        TopLevelContext,
         components::toggle_closed,
         sections::results::search_results::{
-            SelectTraceLevelContext, select_channel::SelectChannels,
+            SelectTraceLevelContext,
+            select_channel::{CompareChannels, SelectChannels},
         },
     },
-    structs::TraceSummary,
+    structs::{TraceStatistics, TraceSummary},
 };
 use leptos::{IntoView, component, prelude::*, view};
 
@@ -26,7 +28,12 @@ pub(super) fn DigitiserMessage(trace_summary: TraceSummary) -> impl IntoView {
     view! {
         <div class = "digitiser-message" class = ("selected", selected_pred)>
             <div class = "digitiser-message-id"> "Id: " {trace_summary.id}</div>
+            <Statistics index = trace_summary.index />
             <SelectChannels
+                index = trace_summary.index
+                channels = trace_summary.channels.clone()
+            />
+            <CompareChannels
                 index = trace_summary.index
                 channels = trace_summary.channels
             />
@@ -49,7 +56,61 @@ fn Metadata(trace_summary: TraceSummary) -> impl IntoView {
               <div> "Protons per Pulse: " {trace_summary.protons_per_pulse} </div>
               <div> "Running: "           {trace_summary.running} </div>
               <div> "VetoFlags: "         {trace_summary.veto_flags} </div>
+              <div> "Run: "               {trace_summary.run.to_string()} </div>
             </div>
         </div>
     }
 }
+
+/// Displays the maximum amplitude and total number of events of the message at `index`.
+/// The per-channel breakdown is shown in a tooltip.
+#[component]
+fn Statistics(index: usize) -> impl IntoView {
+    let eventlist_topics = use_context::<TopLevelContext>()
+        .expect("TopLevelContext should be provided, this should never fail.")
+        .client_side_data
+        .eventlist_topics;
+
+    let trace_statistics = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .trace_statistics;
+
+    let tooltip = move |statistics: &TraceStatistics| {
+        statistics
+            .channels
+            .iter()
+            .map(|channel| {
+                let events = channel
+                    .events
+                    .iter()
+                    .map(|(topic_index, count)| {
+                        let topic = eventlist_topics
+                            .get(*topic_index)
+                            .map(String::as_str)
+                            .unwrap_or_default();
+                        format!(", {topic}: {count} events")
+                    })
+                    .collect::<String>();
+                format!(
+                    "Channel {}: min {}, max {}, mean {:.1}, noise {:.1}{events}",
+                    channel.channel, channel.min, channel.max, channel.mean, channel.rms_noise
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    move || {
+        trace_statistics
+            .get()
+            .and_then(|trace_statistics| trace_statistics.get(index).cloned())
+            .map(|statistics| {
+                view! {
+                    <div class = "digitiser-message-statistics" data-tooltip = tooltip(&statistics)>
+                        <div> "Max: " {statistics.max_amplitude()} </div>
+                        <div> "Events: " {statistics.total_events()} </div>
+                    </div>
+                }
+            })
+    }
+}
@@ -0,0 +1,74 @@
+use crate::app::{
+    TopLevelContext, main_content::MainLevelContext,
+    sections::results::search_results::SelectTraceLevelContext,
+    server_functions::GetRawExportProgress,
+};
+use leptos::{IntoView, component, logging, prelude::*, view};
+use leptos_use::use_interval;
+
+/// How often the progress of an export is fetched.
+const PROGRESS_INTERVAL_MS: u64 = 500;
+
+/// Allows the user to download every trace message of the results, as received, as a zip archive.
+/// While the archive is downloading, the number of messages written to it so far is displayed.
+#[component]
+pub(crate) fn ExportSessionPanel() -> impl IntoView {
+    let public_url = use_context::<TopLevelContext>()
+        .expect("TopLevelContext should be provided, this should never fail.")
+        .client_side_data
+        .public_url;
+    let num_results = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .num_results;
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let href = move || {
+        let uuid = uuid.get()?;
+        Some(format!(
+            "{}/export-raw/{uuid}",
+            public_url.as_str().trim_end_matches('/')
+        ))
+    };
+
+    let exporting = RwSignal::new(false);
+    let get_raw_export_progress = ServerAction::<GetRawExportProgress>::new();
+    let progress_interval = use_interval(PROGRESS_INTERVAL_MS);
+    Effect::new(move || {
+        if exporting.get()
+            && let Some(uuid) = uuid.get()
+        {
+            progress_interval.counter.track();
+            get_raw_export_progress.dispatch(GetRawExportProgress { uuid });
+        }
+    });
+    let exported = Signal::derive(move || {
+        get_raw_export_progress
+            .value()
+            .get()
+            .and_then(|progress| progress.inspect_err(|e| logging::warn!("{e}")).ok())
+            .flatten()
+    });
+    Effect::new(move || {
+        if exported
+            .get()
+            .is_some_and(|exported| exported >= num_results)
+        {
+            exporting.set(false);
+        }
+    });
+
+    view! {
+        <div class = "export-session">
+            <a href = href download = "" on:click = move |_| exporting.set(true)>
+                {format!("Export {num_results} Messages as Zip")}
+            </a>
+            {move || exported.get().map(|exported| view! {
+                <div class = "export-session-status">
+                    {format!("Exported {exported} of {num_results} messages")}
+                </div>
+            })}
+        </div>
+    }
+}
@@ -0,0 +1,263 @@
+//! Allows the results list to be navigated with the arrow keys:
+//! left and right select the previous and next message of the current page,
+//! up and down select the previous and next channel of the selected message.
+use crate::{
+    Channel,
+    app::{
+        main_content::MainLevelContext,
+        sections::results::{
+            context::ResultsLevelContext, search_results::SelectTraceLevelContext,
+        },
+        server_functions::CreateAndFetchPlotly,
+    },
+    structs::{HeldModifiers, SelectedTraceIndex, TraceView},
+};
+use leptos::{IntoView, component, ev, prelude::*, web_sys::Element};
+use leptos_use::{use_debounce_fn_with_arg, use_event_listener, use_window};
+
+/// The delay, in milliseconds, after the last key press before the selected trace is fetched,
+/// so that holding down a key does not fetch every trace passed over.
+const FETCH_DEBOUNCE_MS: f64 = 250.0;
+
+/// Keyboard events targeting these elements are left to the element.
+const IGNORED_TAGS: [&str; 3] = ["INPUT", "SELECT", "TEXTAREA"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NavigationCommand {
+    PreviousResult,
+    NextResult,
+    PreviousChannel,
+    NextChannel,
+}
+
+impl NavigationCommand {
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "ArrowLeft" => Some(Self::PreviousResult),
+            "ArrowRight" => Some(Self::NextResult),
+            "ArrowUp" => Some(Self::PreviousChannel),
+            "ArrowDown" => Some(Self::NextChannel),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the position in a list of `len` items which is one step from `position`, wrapping around at either end.
+/// If there is no current position, the first item is stepped forwards to, and the last backwards to.
+fn step(len: usize, position: Option<usize>, forward: bool) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some(match (position, forward) {
+        (None, true) => 0,
+        (None, false) => len - 1,
+        (Some(position), true) => (position + 1) % len,
+        (Some(position), false) => (position + len - 1) % len,
+    })
+}
+
+/// Returns the trace to select when `command` is given whilst `selected` is selected, if any.
+/// # Parameters
+/// - ordered_results: the index and channels of each message of the current page, in the order they are displayed.
+/// - channels: the channels of the selected message.
+///
+/// When moving to another message, its channel is the same as the selected one, if it has it, otherwise its first.
+/// Channels cannot be moved between until a message is selected, nor if `channels` is not of the selected message.
+fn navigate(
+    command: NavigationCommand,
+    selected: Option<&SelectedTraceIndex>,
+    ordered_results: &[(usize, Vec<Channel>)],
+    channels: &[Channel],
+) -> Option<SelectedTraceIndex> {
+    match command {
+        NavigationCommand::PreviousResult | NavigationCommand::NextResult => {
+            let position = selected.and_then(|selected| {
+                ordered_results
+                    .iter()
+                    .position(|(index, _)| *index == selected.index)
+            });
+            let (index, channels) = &ordered_results[step(
+                ordered_results.len(),
+                position,
+                command == NavigationCommand::NextResult,
+            )?];
+            let channel = selected
+                .map(|selected| selected.channel)
+                .filter(|channel| channels.contains(channel))
+                .or_else(|| channels.first().copied())?;
+            Some(SelectedTraceIndex {
+                index: *index,
+                channel,
+            })
+        }
+        NavigationCommand::PreviousChannel | NavigationCommand::NextChannel => {
+            let selected = selected?;
+            let position = channels
+                .iter()
+                .position(|&channel| channel == selected.channel)?;
+            let position = step(
+                channels.len(),
+                Some(position),
+                command == NavigationCommand::NextChannel,
+            )?;
+            Some(SelectedTraceIndex {
+                index: selected.index,
+                channel: channels[position],
+            })
+        }
+    }
+}
+
+/// Listens for the arrow keys anywhere in the page, except in form fields, and changes the selected trace accordingly.
+/// The selection is updated immediately, but the trace is only fetched once the keys have come to rest.
+#[component]
+pub(super) fn KeyboardNavigation() -> impl IntoView {
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+    let create_and_fetch_multi_plotly = result_level_context.create_and_fetch_multi_plotly;
+    let run_detector_on_trace = result_level_context.run_detector_on_trace;
+    let event_filter = result_level_context.event_filter;
+    let max_points = result_level_context.max_points;
+
+    let select_trace_level_context = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+    let selected_trace_index = select_trace_level_context.select_trace_index;
+    let compared_channels = select_trace_level_context.compared_channels;
+    let ordered_results = select_trace_level_context.ordered_results;
+    let available_channels = select_trace_level_context.available_channels;
+
+    let fetch_plotly = use_debounce_fn_with_arg(
+        move |index_and_channel: SelectedTraceIndex| {
+            if let Some(uuid) = uuid.get_untracked() {
+                create_and_fetch_plotly.dispatch(CreateAndFetchPlotly {
+                    uuid,
+                    index_and_channel,
+                    event_filter: event_filter.get_untracked(),
+                    view: TraceView::whole_trace(max_points.get_untracked()),
+                });
+            }
+        },
+        FETCH_DEBOUNCE_MS,
+    );
+
+    let _ = use_event_listener(use_window(), ev::keydown, move |ev| {
+        if HeldModifiers::from(&ev).any()
+            || IGNORED_TAGS.contains(&event_target::<Element>(&ev).tag_name().as_str())
+        {
+            return;
+        }
+        let Some(command) = NavigationCommand::from_key(&ev.key()) else {
+            return;
+        };
+        ev.prevent_default();
+        let Some(index_and_channel) = navigate(
+            command,
+            selected_trace_index.get_untracked().as_ref(),
+            &ordered_results.get_untracked(),
+            &available_channels.get_untracked(),
+        ) else {
+            return;
+        };
+        selected_trace_index.set(Some(index_and_channel.clone()));
+        compared_channels.set(None);
+        create_and_fetch_multi_plotly.clear();
+        run_detector_on_trace.clear();
+        fetch_plotly(index_and_channel);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected(index: usize, channel: Channel) -> SelectedTraceIndex {
+        SelectedTraceIndex { index, channel }
+    }
+
+    /// Three messages, displayed in the order of indices 4, 2, 7.
+    fn ordered_results() -> Vec<(usize, Vec<Channel>)> {
+        vec![(4, vec![0, 1]), (2, vec![1, 3]), (7, vec![2])]
+    }
+
+    #[test]
+    fn keys_are_mapped_to_commands() {
+        assert_eq!(
+            NavigationCommand::from_key("ArrowLeft"),
+            Some(NavigationCommand::PreviousResult)
+        );
+        assert_eq!(
+            NavigationCommand::from_key("ArrowDown"),
+            Some(NavigationCommand::NextChannel)
+        );
+        assert_eq!(NavigationCommand::from_key("Enter"), None);
+    }
+
+    #[test]
+    fn step_wraps_around() {
+        assert_eq!(step(3, Some(1), true), Some(2));
+        assert_eq!(step(3, Some(2), true), Some(0));
+        assert_eq!(step(3, Some(0), false), Some(2));
+        assert_eq!(step(3, None, true), Some(0));
+        assert_eq!(step(3, None, false), Some(2));
+        assert_eq!(step(0, None, true), None);
+    }
+
+    #[test]
+    fn results_are_navigated_in_display_order() {
+        let results = ordered_results();
+        let next = |selected: Option<&SelectedTraceIndex>| {
+            navigate(NavigationCommand::NextResult, selected, &results, &[])
+        };
+        let previous = |selected: Option<&SelectedTraceIndex>| {
+            navigate(NavigationCommand::PreviousResult, selected, &results, &[])
+        };
+
+        assert_eq!(next(None), Some(selected(4, 0)));
+        assert_eq!(previous(None), Some(selected(7, 2)));
+        // The channel is kept, if the next message has it.
+        assert_eq!(next(Some(&selected(4, 1))), Some(selected(2, 1)));
+        assert_eq!(next(Some(&selected(4, 0))), Some(selected(2, 1)));
+        // Wrap around at both ends.
+        assert_eq!(next(Some(&selected(7, 2))), Some(selected(4, 0)));
+        assert_eq!(previous(Some(&selected(4, 1))), Some(selected(7, 2)));
+        // A message not on the page is treated as no selection.
+        assert_eq!(next(Some(&selected(9, 1))), Some(selected(4, 1)));
+        assert_eq!(
+            navigate(NavigationCommand::NextResult, None, &[], &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn channels_are_navigated_with_wrap_around() {
+        let results = ordered_results();
+        let channels = [1, 3, 5];
+        let navigate = |command, selected: Option<&SelectedTraceIndex>| {
+            navigate(command, selected, &results, &channels)
+        };
+
+        assert_eq!(
+            navigate(NavigationCommand::NextChannel, Some(&selected(2, 1))),
+            Some(selected(2, 3))
+        );
+        assert_eq!(
+            navigate(NavigationCommand::NextChannel, Some(&selected(2, 5))),
+            Some(selected(2, 1))
+        );
+        assert_eq!(
+            navigate(NavigationCommand::PreviousChannel, Some(&selected(2, 1))),
+            Some(selected(2, 5))
+        );
+        assert_eq!(navigate(NavigationCommand::NextChannel, None), None);
+        // The channels are not yet those of the selected message.
+        assert_eq!(
+            navigate(NavigationCommand::NextChannel, Some(&selected(2, 4))),
+            None
+        );
+    }
+}
@@ -1,20 +1,34 @@
+mod annotations;
 mod digitiser_message;
+mod export_session;
+mod keyboard_navigation;
+mod page_controls;
 mod results_settings;
+mod save_session;
 mod select_channel;
 
 use crate::{
+    Channel,
     app::{
         TopLevelContext,
+        main_content::MainLevelContext,
         sections::results::search_results::{
-            digitiser_message::DigitiserMessage, results_settings::ResultsSettingsPanel,
+            annotations::AnnotationsPanel,
+            digitiser_message::DigitiserMessage,
+            export_session::ExportSessionPanel,
+            keyboard_navigation::KeyboardNavigation,
+            page_controls::{PAGE_SIZES, PageControls},
+            results_settings::ResultsSettingsPanel,
+            save_session::SaveSessionPanel,
         },
+        server_functions::{FetchTraceStatistics, GetResultsPage},
     },
     structs::{
         SearchSummary, SearchTarget, SearchTargetBy, SearchTargetMode, SelectedTraceIndex,
-        TraceSummary,
+        SortResultsBy, TraceStatistics, TraceSummary,
     },
 };
-use leptos::{IntoView, component, either::Either, prelude::*, view};
+use leptos::{IntoView, component, either::Either, logging, prelude::*, view};
 use std::collections::BTreeMap;
 
 type TraceSummariesByTime = Vec<(String, Vec<TraceSummary>)>;
@@ -37,6 +51,28 @@ fn sort_trace_summaries(trace_summaries: Vec<TraceSummary>) -> Vec<(String, Trac
         .collect::<Vec<_>>()
 }
 
+/// Returns the index and channels of each message of a page, in the order in which they are displayed when sorted by `sort_by`.
+fn display_order(
+    sort_by: SortResultsBy,
+    trace_summaries: Vec<TraceSummary>,
+) -> Vec<(usize, Vec<Channel>)> {
+    match sort_by {
+        SortResultsBy::Timestamp => sort_trace_summaries(trace_summaries)
+            .into_iter()
+            .flat_map(|(_, by_time)| by_time)
+            .flat_map(|(_, mut trace_summaries)| {
+                trace_summaries.sort_by_key(|summary| summary.id);
+                trace_summaries
+            })
+            .map(|summary| (summary.index, summary.channels))
+            .collect(),
+        _ => trace_summaries
+            .into_iter()
+            .map(|summary| (summary.index, summary.channels))
+            .collect(),
+    }
+}
+
 /// This struct enable a degree of type-checking for the [use_context]/[use_context] functions.
 /// Any component making use of the following fields should call `use_context::<SelectTraceLevelContext>()`
 /// and select the desired field.
@@ -46,29 +82,141 @@ struct SelectTraceLevelContext {
     target: SearchTarget,
     num_results: usize,
     select_trace_index: RwSignal<Option<SelectedTraceIndex>>,
+    /// The index of the trace message, and its channels, currently plotted together, if any.
+    compared_channels: RwSignal<Option<(usize, Vec<Channel>)>>,
+    /// Whether the statistics of each trace message are displayed, which requires every message to be decoded.
+    show_statistics: RwSignal<bool>,
+    /// The statistics of each trace message, in order of index, once fetched.
+    trace_statistics: Signal<Option<Vec<TraceStatistics>>>,
+    sort_by: RwSignal<SortResultsBy>,
+    /// The position in the ordered results list of the first message displayed.
+    page_offset: RwSignal<usize>,
+    /// The maximum number of messages displayed at once.
+    page_size: RwSignal<usize>,
+    /// The index and channels of each message of the current page, in the order they are displayed.
+    ordered_results: Signal<Vec<(usize, Vec<Channel>)>>,
+    /// The channels, in ascending order, of the selected message, if it is on the current page.
+    available_channels: Signal<Vec<Channel>>,
 }
 
 #[component]
 pub(crate) fn SearchResultsPanel(search_summary: SearchSummary) -> impl IntoView {
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let sort_by = RwSignal::new(SortResultsBy::default());
+    let show_statistics = RwSignal::new(false);
+
+    // Computing the statistics decodes every message, so they are only fetched once they are displayed,
+    // or the results are sorted by one, in which case the server has computed them anyway.
+    let statistics_wanted =
+        Memo::new(move |_| show_statistics.get() || sort_by.get() != SortResultsBy::Timestamp);
+    let fetch_trace_statistics = ServerAction::<FetchTraceStatistics>::new();
+    Effect::new(move || {
+        if statistics_wanted.get()
+            && !fetch_trace_statistics.pending().get_untracked()
+            && fetch_trace_statistics.value().get_untracked().is_none()
+            && let Some(uuid) = uuid.get_untracked()
+        {
+            fetch_trace_statistics.dispatch(FetchTraceStatistics { uuid });
+        }
+    });
+    let trace_statistics = Signal::derive(move || {
+        show_statistics
+            .get()
+            .then(|| fetch_trace_statistics.value().get())
+            .flatten()
+            .and_then(|statistics| statistics.inspect_err(|e| logging::warn!("{e}")).ok())
+    });
+    let page_offset = RwSignal::new(0);
+    let page_size = RwSignal::new(PAGE_SIZES[1]);
+
+    // Only the messages of the current page are fetched, these are refetched whenever the page changes.
+    let get_results_page = ServerAction::<GetResultsPage>::new();
+    Effect::new(move || {
+        if let Some(uuid) = uuid.get() {
+            get_results_page.dispatch(GetResultsPage {
+                uuid,
+                offset: page_offset.get(),
+                limit: page_size.get(),
+                sort_key: sort_by.get(),
+            });
+        }
+    });
+    let results_page = Signal::derive(move || {
+        get_results_page
+            .value()
+            .get()
+            .and_then(|page| page.inspect_err(|e| logging::warn!("{e}")).ok())
+    });
+    let ordered_results = Signal::derive(move || {
+        results_page
+            .get()
+            .map(|page| display_order(sort_by.get(), page.traces))
+            .unwrap_or_default()
+    });
+
+    // The channels of the selected message are taken from its summary on the current page,
+    // and only change when another message is selected.
+    let select_trace_index = RwSignal::<Option<SelectedTraceIndex>>::new(None);
+    let selected_index =
+        Memo::new(move |_| select_trace_index.get().map(|selected| selected.index));
+    let available_channels = Signal::derive(move || {
+        let selected = selected_index.get();
+        ordered_results.with(|results| {
+            results
+                .iter()
+                .find(|(index, _)| Some(*index) == selected)
+                .map(|(_, channels)| {
+                    let mut channels = channels.clone();
+                    channels.sort_unstable();
+                    channels
+                })
+                .unwrap_or_default()
+        })
+    });
+
     provide_context(SelectTraceLevelContext {
         eventlist_topic_indices: search_summary.eventlist_topic_indices,
         target: search_summary.target,
-        num_results: search_summary.traces.len(),
-        select_trace_index: RwSignal::<Option<SelectedTraceIndex>>::new(None),
+        num_results: search_summary.num_results,
+        select_trace_index,
+        compared_channels: RwSignal::new(None),
+        show_statistics,
+        trace_statistics,
+        sort_by,
+        page_offset,
+        page_size,
+        ordered_results,
+        available_channels,
     });
 
-    let trace_by_date_and_time = sort_trace_summaries(search_summary.traces);
-
     view! {
         <div class = "content search-results" id = "search-results">
             <SearchSummary />
+            <KeyboardNavigation />
             <ResultsSettingsPanel />
-            <For
-                each = move ||trace_by_date_and_time.clone().into_iter()
-                key = |(date,_)|date.clone()
-                let((date, trace_summaries_by_time))>
-                    <SearchResultsByDate date trace_summaries_by_time/>
-            </For>
+            <AnnotationsPanel />
+            <SaveSessionPanel />
+            <ExportSessionPanel />
+            <PageControls />
+            {move || results_page.get().map(|page| match sort_by.get() {
+                SortResultsBy::Timestamp => {
+                    let trace_by_date_and_time = sort_trace_summaries(page.traces);
+                    Either::Left(view! {
+                        <For
+                            each = move ||trace_by_date_and_time.clone().into_iter()
+                            key = |(date,_)|date.clone()
+                            let((date, trace_summaries_by_time))>
+                                <SearchResultsByDate date trace_summaries_by_time/>
+                        </For>
+                    })
+                },
+                _ => Either::Right(view! {
+                    <SearchResultsByStatistic trace_summaries = page.traces />
+                }),
+            })}
         </div>
     }
 }
@@ -84,6 +232,14 @@ pub(crate) fn SearchSummary() -> impl IntoView {
         target,
         num_results,
         select_trace_index: _,
+        compared_channels: _,
+        show_statistics: _,
+        trace_statistics: _,
+        sort_by: _,
+        page_offset: _,
+        page_size: _,
+        ordered_results: _,
+        available_channels: _,
     } = use_context::<SelectTraceLevelContext>().expect("");
 
     let eventlist_topic_indices = eventlist_topic_indices
@@ -105,15 +261,18 @@ pub(crate) fn SearchSummary() -> impl IntoView {
                     </ul>
                 </li>
                 {match target.mode {
-                    SearchTargetMode::Timestamp { timestamp } => view! {
+                    SearchTargetMode::Timestamp { timestamp } => Either::Left(view! {
                         <li> {format!("At or after: {} {}", timestamp.date_naive(), timestamp.time())} </li>
-                    },
-                    SearchTargetMode::Dragnet {timestamp, backstep, forward_distance } => view! {
+                    }),
+                    SearchTargetMode::Dragnet {timestamp, backstep, forward_distance, until } => Either::Right(view! {
                         <li> {format!(
                             "Around: {} {}, message range: [{backstep}, {forward_distance}]",
                             timestamp.date_naive(), timestamp.time())
                         } </li>
-                    }
+                        {until.map(|until| view! {
+                            <li> {format!("At or before: {} {}", until.date_naive(), until.time())} </li>
+                        })}
+                    })
                 }}
                 {match target.by {
                     SearchTargetBy::All => Either::Left(()),
@@ -170,3 +329,21 @@ fn SearchResultsByTime(time: String, mut trace_summaries: Vec<TraceSummary>) ->
         </div>
     }
 }
+
+/// Lists the messages of a page in the order they were fetched,
+/// which is descending order of the statistic by which the results list is sorted.
+#[component]
+fn SearchResultsByStatistic(trace_summaries: Vec<TraceSummary>) -> impl IntoView {
+    view! {
+        <div class = "search-results-by-statistic">
+            <For
+                each = move ||trace_summaries.clone().into_iter()
+                key = ToOwned::to_owned
+                let(trace_summary)
+            >
+                <div class = "search-results-time"> {format!("{} {}", trace_summary.date, trace_summary.time)} </div>
+                <DigitiserMessage trace_summary />
+            </For>
+        </div>
+    }
+}
@@ -0,0 +1,54 @@
+//! Steps through the results list a page at a time.
+use crate::app::sections::results::search_results::SelectTraceLevelContext;
+use leptos::{IntoView, component, prelude::*, view};
+
+/// The numbers of messages which can be displayed on each page of the results list.
+pub(super) const PAGE_SIZES: [usize; 4] = [10, 25, 50, 100];
+
+/// Displays the range of messages on the current page, with controls to move between pages and set their size.
+#[component]
+pub(super) fn PageControls() -> impl IntoView {
+    let SelectTraceLevelContext {
+        num_results,
+        page_offset,
+        page_size,
+        ..
+    } = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+
+    let page_end = move || (page_offset.get() + page_size.get()).min(num_results);
+
+    view! {
+        <div class = "search-results-pages">
+            <input type = "button" value = "Previous"
+                prop:disabled = move || page_offset.get() == 0
+                on:click = move |_| page_offset.set(page_offset.get().saturating_sub(page_size.get()))
+            />
+            <div> {move || format!("Showing {} to {} of {num_results}", (page_offset.get() + 1).min(num_results), page_end())} </div>
+            <input type = "button" value = "Next"
+                prop:disabled = move || page_end() >= num_results
+                on:click = move |_| page_offset.set(page_offset.get() + page_size.get())
+            />
+            <label class = "results-settings-input" for = "page-size">
+                "Per page:"
+                <select class = "results-settings-input" name = "page-size" id = "page-size"
+                    on:change = move |ev| {
+                        page_size.set(
+                            event_target_value(&ev)
+                                .parse()
+                                .expect("Page size should parse, this should never fail.")
+                        );
+                        page_offset.set(0);
+                    }
+                >
+                    <For each = move || PAGE_SIZES
+                        key = ToOwned::to_owned
+                        let(size)
+                    >
+                        <option selected={page_size.get() == size} value = {size.to_string()}> {size} </option>
+                    </For>
+                </select>
+            </label>
+        </div>
+    }
+}
@@ -1,10 +1,30 @@
 use crate::{
-    app::sections::results::{
-        context::ResultsLevelContext, search_results::SelectTraceLevelContext,
+    Time,
+    app::{
+        TopLevelContext,
+        main_content::MainLevelContext,
+        sections::results::{
+            context::ResultsLevelContext, search_results::SelectTraceLevelContext,
+        },
+        server_functions::{
+            CompareEventlists, CreateAndFetchMultiPlotly, CreateAndFetchPlotly, RunDetectorOnTrace,
+        },
     },
-    structs::SearchTargetBy,
+    structs::{
+        DetectorConfig, DetectorMode, DetectorPolarity, EventFilter, HeldModifiers, PlotlyJs,
+        SearchTargetBy, SelectedTraceIndex, SortResultsBy, TraceView,
+    },
+};
+use leptos::{
+    IntoView, component,
+    either::{Either, EitherOf3},
+    ev, logging,
+    prelude::*,
+    view,
 };
-use leptos::{IntoView, component, either::Either, prelude::*, view};
+use leptos_use::{use_event_listener, use_window};
+use std::str::FromStr;
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 
 #[component]
 pub(crate) fn ResultsSettingsPanel() -> impl IntoView {
@@ -14,11 +34,67 @@ pub(crate) fn ResultsSettingsPanel() -> impl IntoView {
 
     view! {
         <div class = "search-results-settings">
+            <SortResults />
+            <ShowStatistics />
             <ShowSelectedChannelsOnly by = target.by />
+            <EventFilterSettings />
+            <MaxPlotPoints />
+            <DetectorSettings />
+            <CompareEventlistsSettings />
+            <ExportPlot />
         </div>
     }
 }
 
+/// Sets the order of the results list, returning to its first page.
+#[component]
+fn SortResults() -> impl IntoView {
+    let select_trace_level_context = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+    let sort_by = select_trace_level_context.sort_by;
+    let page_offset = select_trace_level_context.page_offset;
+
+    view! {
+        <label class = "results-settings-input" for = "sort-results-by">
+            "Sort by:"
+            <select class = "results-settings-input" name = "sort-results-by" id = "sort-results-by"
+                on:change = move |ev| {
+                    sort_by.set(
+                        event_target_value(&ev)
+                            .parse()
+                            .expect("SortResultsBy value should parse, this should never fail.")
+                    );
+                    page_offset.set(0);
+                }
+            >
+                <For each = SortResultsBy::iter
+                    key = ToOwned::to_owned
+                    let(value)
+                >
+                    <option selected={sort_by.get() == value} value = {value.to_string()}> {value.to_string()} </option>
+                </For>
+            </select>
+        </label>
+    }
+}
+
+/// Displays the statistics of each message, which are fetched the first time they are displayed.
+#[component]
+fn ShowStatistics() -> impl IntoView {
+    let show_statistics = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .show_statistics;
+
+    view! {
+        <label class = "results-settings-input" for = "show-statistics">
+            "Show statistics:"
+            <input class = "results-settings-input" name = "show-statistics" id = "show-statistics" type = "checkbox"
+                bind:checked = show_statistics
+            />
+        </label>
+    }
+}
+
 #[component]
 pub(crate) fn ShowSelectedChannelsOnly(by: SearchTargetBy) -> impl IntoView {
     let result_level_context = use_context::<ResultsLevelContext>()
@@ -36,3 +112,446 @@ pub(crate) fn ShowSelectedChannelsOnly(by: SearchTargetBy) -> impl IntoView {
         _ => Either::Right(()),
     }
 }
+
+#[component]
+pub(crate) fn EventFilterSettings() -> impl IntoView {
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let event_filter = result_level_context.event_filter;
+    let max_points = result_level_context.max_points;
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+    let create_and_fetch_multi_plotly = result_level_context.create_and_fetch_multi_plotly;
+
+    let select_trace_level_context = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+    let selected_trace_index = select_trace_level_context.select_trace_index;
+    let compared_channels = select_trace_level_context.compared_channels;
+
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    // Applies `set_bound` to the filter, then refetches the selected trace or compared channels, if any, with the new filter.
+    let update_filter = move |set_bound: &dyn Fn(&mut EventFilter)| {
+        event_filter.update(set_bound);
+        let Some(uuid) = uuid.get() else {
+            return;
+        };
+        if let Some(index_and_channel) = selected_trace_index.get() {
+            create_and_fetch_plotly.dispatch(CreateAndFetchPlotly {
+                uuid,
+                index_and_channel,
+                event_filter: event_filter.get(),
+                view: TraceView::whole_trace(max_points.get()),
+            });
+        } else if let Some((index, channels)) = compared_channels.get() {
+            create_and_fetch_multi_plotly.dispatch(CreateAndFetchMultiPlotly {
+                uuid,
+                index,
+                channels,
+                event_filter: event_filter.get(),
+            });
+        }
+    };
+
+    fn display(bound: Option<impl ToString>) -> String {
+        bound.map(|bound| bound.to_string()).unwrap_or_default()
+    }
+
+    view! {
+        <label class = "results-settings-input" for = "min-intensity">
+            "Intensity range:"
+            <input class = "results-settings-input" name = "min-intensity" id = "min-intensity" type = "text"
+                value = {move ||display(event_filter.get().min_intensity)}
+                on:change = {move |ev|update_filter(&|filter|filter.min_intensity = event_target_value(&ev).parse().ok())}
+            />
+            "to"
+            <input class = "results-settings-input" name = "max-intensity" id = "max-intensity" type = "text"
+                value = {move ||display(event_filter.get().max_intensity)}
+                on:change = {move |ev|update_filter(&|filter|filter.max_intensity = event_target_value(&ev).parse().ok())}
+            />
+        </label>
+        <label class = "results-settings-input" for = "min-time">
+            "Time range:"
+            <input class = "results-settings-input" name = "min-time" id = "min-time" type = "text"
+                value = {move ||display(event_filter.get().min_time)}
+                on:change = {move |ev|update_filter(&|filter|filter.min_time = event_target_value(&ev).parse().ok())}
+            />
+            "to"
+            <input class = "results-settings-input" name = "max-time" id = "max-time" type = "text"
+                value = {move ||display(event_filter.get().max_time)}
+                on:change = {move |ev|update_filter(&|filter|filter.max_time = event_target_value(&ev).parse().ok())}
+            />
+        </label>
+    }
+}
+
+/// Sets the most points with which the selected trace is plotted, and refetches it, if any.
+/// A longer trace, or zoomed range of it, is decimated by the server, and an empty value plots every sample.
+#[component]
+fn MaxPlotPoints() -> impl IntoView {
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let max_points = result_level_context.max_points;
+    let event_filter = result_level_context.event_filter;
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+
+    let selected_trace_index = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .select_trace_index;
+
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let on_change = move |ev| {
+        let value = event_target_value(&ev);
+        match value.trim() {
+            "" => max_points.set(None),
+            value => match value.parse() {
+                Ok(value) => max_points.set(Some(value)),
+                Err(e) => {
+                    logging::warn!("Invalid maximum number of points: {e}");
+                    return;
+                }
+            },
+        }
+        if let (Some(uuid), Some(index_and_channel)) = (uuid.get(), selected_trace_index.get()) {
+            create_and_fetch_plotly.dispatch(CreateAndFetchPlotly {
+                uuid,
+                index_and_channel,
+                event_filter: event_filter.get(),
+                view: TraceView::whole_trace(max_points.get()),
+            });
+        }
+    };
+
+    view! {
+        <label class = "results-settings-input" for = "max-plot-points">
+            "Max plotted points:"
+            <input class = "results-settings-input" name = "max-plot-points" id = "max-plot-points" type = "text"
+                value = {move ||max_points.get().map(|max_points| max_points.to_string()).unwrap_or_default()}
+                on:change = on_change
+            />
+        </label>
+    }
+}
+
+#[derive(Default, Clone, EnumString, Display, EnumIter, PartialEq, Eq, Hash, Copy)]
+enum DetectorKind {
+    #[default]
+    #[strum(to_string = "Fixed Threshold")]
+    Fixed,
+    #[strum(to_string = "Adaptive Threshold")]
+    Adaptive,
+    #[strum(to_string = "Differential Threshold")]
+    Differential,
+}
+
+/// Renders a text input which sets `value` whenever its contents parse.
+fn parameter_input<T>(label: &'static str, id: &'static str, value: RwSignal<T>) -> impl IntoView
+where
+    T: FromStr + ToString + Clone + Send + Sync + 'static,
+{
+    view! {
+        <label class = "results-settings-input" for = id>
+            {label}
+            <input class = "results-settings-input" name = id id = id type = "text"
+                value = {move ||value.get().to_string()}
+                on:change = {move |ev|if let Ok(parsed) = event_target_value(&ev).parse() { value.set(parsed) }}
+            />
+        </label>
+    }
+}
+
+/// Allows one of the trace-to-events detectors to be run on the selected trace,
+/// the events it finds are overlaid on the graph.
+#[component]
+pub(crate) fn DetectorSettings() -> impl IntoView {
+    let run_detector_on_trace = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.")
+        .run_detector_on_trace;
+
+    let selected_trace_index = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .select_trace_index;
+
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let kind = RwSignal::new(DetectorKind::default());
+    let polarity = RwSignal::new(DetectorPolarity::default());
+    let baseline = RwSignal::new(0);
+    let threshold = RwSignal::new(10.0);
+    let sigma_threshold = RwSignal::new(3.0);
+    let noise_window_size = RwSignal::new(100);
+    let begin_threshold = RwSignal::new(10.0);
+    let begin_duration = RwSignal::new(1);
+    let end_threshold = RwSignal::new(0.0);
+    let end_duration = RwSignal::new(1);
+    let duration = RwSignal::new(1);
+    let cool_off = RwSignal::new(0);
+
+    let detector_config = move || DetectorConfig {
+        mode: match kind.get() {
+            DetectorKind::Fixed => DetectorMode::FixedThreshold {
+                threshold: threshold.get(),
+                duration: duration.get(),
+                cool_off: cool_off.get(),
+            },
+            DetectorKind::Adaptive => DetectorMode::AdaptiveThreshold {
+                sigma_threshold: sigma_threshold.get(),
+                noise_window_size: noise_window_size.get(),
+                duration: duration.get(),
+                cool_off: cool_off.get(),
+            },
+            DetectorKind::Differential => DetectorMode::DifferentialThreshold {
+                begin_threshold: begin_threshold.get(),
+                begin_duration: begin_duration.get(),
+                end_threshold: end_threshold.get(),
+                end_duration: end_duration.get(),
+                cool_off: cool_off.get(),
+            },
+        },
+        polarity: polarity.get(),
+        baseline: baseline.get(),
+    };
+
+    let on_click = move |_| {
+        if let (Some(uuid), Some(index_and_channel)) = (uuid.get(), selected_trace_index.get()) {
+            run_detector_on_trace.dispatch(RunDetectorOnTrace {
+                uuid,
+                index_and_channel,
+                detector_config: detector_config(),
+            });
+        }
+    };
+
+    view! {
+        <div class = "detector-settings">
+            <label class = "results-settings-input" for = "detector-kind">
+                "Detector:"
+                <select class = "results-settings-input" name = "detector-kind" id = "detector-kind"
+                    on:change = move |ev| kind.set(
+                        event_target_value(&ev)
+                            .parse()
+                            .expect("DetectorKind value should parse, this should never fail.")
+                    )
+                >
+                    <For each = DetectorKind::iter
+                        key = ToOwned::to_owned
+                        let(mode)
+                    >
+                        <option selected={kind.get() == mode} value = {mode.to_string()}> {mode.to_string()} </option>
+                    </For>
+                </select>
+            </label>
+            {move || match kind.get() {
+                DetectorKind::Fixed => EitherOf3::A(view! {
+                    {parameter_input("Threshold:", "detector-threshold", threshold)}
+                    {parameter_input("Duration:", "detector-duration", duration)}
+                }),
+                DetectorKind::Adaptive => EitherOf3::B(view! {
+                    {parameter_input("Sigma threshold:", "detector-sigma-threshold", sigma_threshold)}
+                    {parameter_input("Noise window size:", "detector-noise-window-size", noise_window_size)}
+                    {parameter_input("Duration:", "detector-duration", duration)}
+                }),
+                DetectorKind::Differential => EitherOf3::C(view! {
+                    {parameter_input("Begin threshold:", "detector-begin-threshold", begin_threshold)}
+                    {parameter_input("Begin duration:", "detector-begin-duration", begin_duration)}
+                    {parameter_input("End threshold:", "detector-end-threshold", end_threshold)}
+                    {parameter_input("End duration:", "detector-end-duration", end_duration)}
+                }),
+            }}
+            {parameter_input("Cool off:", "detector-cool-off", cool_off)}
+            <label class = "results-settings-input" for = "detector-polarity">
+                "Polarity:"
+                <select class = "results-settings-input" name = "detector-polarity" id = "detector-polarity"
+                    on:change = move |ev| polarity.set(
+                        event_target_value(&ev)
+                            .parse()
+                            .expect("DetectorPolarity value should parse, this should never fail.")
+                    )
+                >
+                    <For each = DetectorPolarity::iter
+                        key = ToOwned::to_owned
+                        let(value)
+                    >
+                        <option selected={polarity.get() == value} value = {value.to_string()}> {value.to_string()} </option>
+                    </For>
+                </select>
+            </label>
+            {parameter_input("Baseline:", "detector-baseline", baseline)}
+            <input type = "button" value = "Run Detector"
+                prop:disabled = move || selected_trace_index.get().is_none()
+                on:click = on_click
+            />
+        </div>
+    }
+}
+
+/// The greatest difference in time, in ns, between events of the compared topics which are matched, until changed.
+const DEFAULT_TIME_TOLERANCE: Time = 2;
+
+/// Renders a select input of the eventlist topics captured by the search, which sets `topic_index` to the selected topic.
+fn topic_input(
+    label: &'static str,
+    id: &'static str,
+    topics: Vec<(usize, String)>,
+    topic_index: RwSignal<usize>,
+) -> impl IntoView {
+    view! {
+        <label class = "results-settings-input" for = id>
+            {label}
+            <select class = "results-settings-input" name = id id = id
+                on:change = move |ev| if let Ok(index) = event_target_value(&ev).parse() { topic_index.set(index) }
+            >
+                <For each = move || topics.clone() key = |(index, _)| *index let((index, topic))>
+                    <option selected={topic_index.get() == index} value = index> {topic} </option>
+                </For>
+            </select>
+        </label>
+    }
+}
+
+/// Allows the events of the selected trace captured from two eventlist topics to be compared,
+/// the events found in only one of the topics are emphasised on a separate graph.
+#[component]
+fn CompareEventlistsSettings() -> impl IntoView {
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let compare_eventlists = result_level_context.compare_eventlists;
+    let max_points = result_level_context.max_points;
+
+    let select_trace_level_context = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+    let selected_trace_index = select_trace_level_context.select_trace_index;
+
+    let eventlist_topics = use_context::<TopLevelContext>()
+        .expect("TopLevelContext should be provided, this should never fail.")
+        .client_side_data
+        .eventlist_topics;
+
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let topic_indices = select_trace_level_context.eventlist_topic_indices;
+    let topics = topic_indices
+        .iter()
+        .map(|&index| {
+            let topic = eventlist_topics
+                .get(index)
+                .cloned()
+                .unwrap_or_else(|| format!("topic {index}"));
+            (index, topic)
+        })
+        .collect::<Vec<_>>();
+
+    let topic_a = RwSignal::new(topic_indices.first().copied().unwrap_or_default());
+    let topic_b = RwSignal::new(
+        topic_indices
+            .get(1)
+            .or(topic_indices.first())
+            .copied()
+            .unwrap_or_default(),
+    );
+    let time_tolerance = RwSignal::new(DEFAULT_TIME_TOLERANCE);
+
+    let on_click = move |_| {
+        if let (Some(uuid), Some(index_and_channel)) = (uuid.get(), selected_trace_index.get()) {
+            compare_eventlists.dispatch(CompareEventlists {
+                uuid,
+                index_and_channel,
+                topic_a: topic_a.get(),
+                topic_b: topic_b.get(),
+                time_tolerance: time_tolerance.get(),
+                view: TraceView::whole_trace(max_points.get()),
+            });
+        }
+    };
+
+    view! {
+        <div class = "compare-eventlists-settings">
+            {topic_input("Compare topic:", "compare-topic-a", topics.clone(), topic_a)}
+            {topic_input("with:", "compare-topic-b", topics, topic_b)}
+            {parameter_input("Time tolerance (samples):", "compare-time-tolerance", time_tolerance)}
+            <input type = "button" value = "Compare Events"
+                prop:disabled = move || selected_trace_index.get().is_none() || compare_eventlists.pending().get()
+                on:click = on_click
+            />
+        </div>
+    }
+}
+
+/// Links to the plot of the selected trace, exported by the server as a standalone html file.
+/// The plot can also be exported by pressing the keyboard shortcut set by the `--export-shortcut` option.
+#[component]
+fn ExportPlot() -> impl IntoView {
+    let client_side_data = use_context::<TopLevelContext>()
+        .expect("TopLevelContext should be provided, this should never fail.")
+        .client_side_data;
+    let public_url = client_side_data.public_url;
+    let export_shortcut = client_side_data.export_shortcut;
+
+    let selected_trace_index = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.")
+        .select_trace_index;
+
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let plotly_js = RwSignal::new(PlotlyJs::default());
+
+    let href = move || {
+        let uuid = uuid.get()?;
+        let SelectedTraceIndex { index, channel } = selected_trace_index.get()?;
+        let plotly_js = match plotly_js.get() {
+            PlotlyJs::Cdn => "cdn",
+            PlotlyJs::Inline => "inline",
+        };
+        Some(format!(
+            "{}/export/{uuid}/{index}/{channel}?plotly-js={plotly_js}",
+            public_url.as_str().trim_end_matches('/')
+        ))
+    };
+
+    let title = format!("Shortcut: {export_shortcut}");
+    let shortcut_href = href.clone();
+    let _ = use_event_listener(use_window(), ev::keydown, move |ev| {
+        if !export_shortcut.matches(&ev.key(), HeldModifiers::from(&ev)) {
+            return;
+        }
+        if let Some(href) = shortcut_href() {
+            ev.prevent_default();
+            if let Err(e) = window().location().set_href(&href) {
+                logging::warn!("{e:?}");
+            }
+        }
+    });
+
+    view! {
+        <div class = "export-plot">
+            <label class = "results-settings-input" for = "export-plotly-js">
+                "Export plotly.js:"
+                <select class = "results-settings-input" name = "export-plotly-js" id = "export-plotly-js"
+                    on:change = move |ev| plotly_js.set(
+                        event_target_value(&ev)
+                            .parse()
+                            .expect("PlotlyJs value should parse, this should never fail.")
+                    )
+                >
+                    <For each = PlotlyJs::iter
+                        key = ToOwned::to_owned
+                        let(value)
+                    >
+                        <option selected={plotly_js.get() == value} value = {value.to_string()}> {value.to_string()} </option>
+                    </For>
+                </select>
+            </label>
+            <a href = href download = "" title = title>"Export Plot as HTML"</a>
+        </div>
+    }
+}
@@ -0,0 +1,52 @@
+use crate::app::{
+    components::DisplayErrors, main_content::MainLevelContext, server_functions::SaveSession,
+};
+use leptos::{IntoView, component, prelude::*, view};
+
+/// Allows the user to save the current results to disk, so they can be loaded later.
+#[component]
+pub(crate) fn SaveSessionPanel() -> impl IntoView {
+    let main_context = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.");
+    let save_session = main_context.save_session;
+    let uuid = main_context.uuid;
+
+    let name = RwSignal::new(String::new());
+    let overwrite = RwSignal::new(false);
+
+    let on_click = move |_| {
+        if let Some(uuid) = uuid.get() {
+            save_session.dispatch(SaveSession {
+                uuid,
+                name: name.get(),
+                overwrite: overwrite.get(),
+            });
+        }
+    };
+
+    view! {
+        <div class = "save-session">
+            <label class = "results-settings-input" for = "save-session-name">
+                "Save as:"
+                <input class = "results-settings-input" name = "save-session-name" id = "save-session-name" type = "text"
+                    bind:value = name
+                />
+            </label>
+            <label class = "results-settings-input" for = "save-session-overwrite">
+                "Overwrite:"
+                <input class = "results-settings-input" name = "save-session-overwrite" id = "save-session-overwrite" type = "checkbox"
+                    bind:checked = overwrite
+                />
+            </label>
+            <input type = "button" value = "Save Session"
+                prop:disabled = move || save_session.pending().get()
+                on:click = on_click
+            />
+            {move ||save_session.value().get().map(|saved| view!{
+                <ErrorBoundary fallback = |errors| view!{ <DisplayErrors errors /> }>
+                    {saved.map(|name| view!{ <div class = "save-session-status"> "Saved as " {name} </div> })}
+                </ErrorBoundary>
+            })}
+        </div>
+    }
+}
@@ -1,12 +1,13 @@
 use crate::{
     app::{
+        components::toggle_closed,
         main_content::MainLevelContext,
         sections::results::{
             context::ResultsLevelContext, search_results::SelectTraceLevelContext,
         },
-        server_functions::CreateAndFetchPlotly,
+        server_functions::{CreateAndFetchMultiPlotly, CreateAndFetchPlotly},
     },
-    structs::{SearchTargetBy, SelectedTraceIndex},
+    structs::{SearchTargetBy, SelectedTraceIndex, TraceView},
 };
 use leptos::{IntoView, component, ev::MouseEvent, prelude::*, view};
 
@@ -47,13 +48,18 @@ fn Channel(this_index_and_channel: SelectedTraceIndex) -> impl IntoView {
     let main_context = use_context::<MainLevelContext>()
         .expect("MainLevelContext should be provided, this should never fail.");
 
-    let create_and_fetch_plotly = use_context::<ResultsLevelContext>()
-        .expect("ResultsLevelContext should be provided, this should never fail.")
-        .create_and_fetch_plotly;
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+    let create_and_fetch_multi_plotly = result_level_context.create_and_fetch_multi_plotly;
+    let run_detector_on_trace = result_level_context.run_detector_on_trace;
+    let event_filter = result_level_context.event_filter;
+    let max_points = result_level_context.max_points;
 
-    let selected_trace_index = use_context::<SelectTraceLevelContext>()
-        .expect("SelectTraceLevelContext should be provided, this should never fail.")
-        .select_trace_index;
+    let select_trace_level_context = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+    let selected_trace_index = select_trace_level_context.select_trace_index;
+    let compared_channels = select_trace_level_context.compared_channels;
 
     let uuid = main_context.uuid;
 
@@ -64,9 +70,14 @@ fn Channel(this_index_and_channel: SelectedTraceIndex) -> impl IntoView {
         move |_: MouseEvent| {
             if let Some(uuid) = uuid.get() {
                 selected_trace_index.set(Some(this_index_and_channel.clone()));
+                compared_channels.set(None);
+                create_and_fetch_multi_plotly.clear();
+                run_detector_on_trace.clear();
                 create_and_fetch_plotly.dispatch(CreateAndFetchPlotly {
                     uuid,
                     index_and_channel: this_index_and_channel.clone(),
+                    event_filter: event_filter.get(),
+                    view: TraceView::whole_trace(max_points.get()),
                 });
             }
         }
@@ -87,3 +98,80 @@ fn Channel(this_index_and_channel: SelectedTraceIndex) -> impl IntoView {
         </div>
     }
 }
+
+/// Allows several channels of a trace message to be chosen and plotted together.
+#[component]
+pub(super) fn CompareChannels(index: usize, mut channels: Vec<u32>) -> impl IntoView {
+    let uuid = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.")
+        .uuid;
+
+    let result_level_context = use_context::<ResultsLevelContext>()
+        .expect("ResultsLevelContext should be provided, this should never fail.");
+    let create_and_fetch_plotly = result_level_context.create_and_fetch_plotly;
+    let create_and_fetch_multi_plotly = result_level_context.create_and_fetch_multi_plotly;
+    let run_detector_on_trace = result_level_context.run_detector_on_trace;
+    let event_filter = result_level_context.event_filter;
+
+    let select_trace_level_context = use_context::<SelectTraceLevelContext>()
+        .expect("SelectTraceLevelContext should be provided, this should never fail.");
+    let selected_trace_index = select_trace_level_context.select_trace_index;
+    let compared_channels = select_trace_level_context.compared_channels;
+
+    let chosen_channels = RwSignal::<Vec<u32>>::new(Vec::new());
+
+    let on_click = move |_: MouseEvent| {
+        if let Some(uuid) = uuid.get() {
+            let channels = chosen_channels.get();
+            if channels.is_empty() {
+                return;
+            }
+            selected_trace_index.set(None);
+            compared_channels.set(Some((index, channels.clone())));
+            create_and_fetch_plotly.clear();
+            run_detector_on_trace.clear();
+            create_and_fetch_multi_plotly.dispatch(CreateAndFetchMultiPlotly {
+                uuid,
+                index,
+                channels,
+                event_filter: event_filter.get(),
+            });
+        }
+    };
+
+    let selected_pred = move || {
+        compared_channels
+            .get()
+            .is_some_and(|(compared_index, _)| compared_index == index)
+    };
+
+    channels.sort();
+    view! {
+        <div class = "compare-channels closable-container closed" class = ("selected", selected_pred)>
+            <div class = "compare-channels-title closable-control"
+                    on:click:target = move |e| toggle_closed(e.target().parent_element())>
+                "Compare"
+            </div>
+            <div class = "compare-channels-content closable">
+                <For each = move ||channels.clone().into_iter()
+                    key = ToOwned::to_owned
+                    let(channel)
+                >
+                    <label class = "compare-channels-input">
+                        <input type = "checkbox"
+                            on:change = move |ev| chosen_channels.update(|chosen| {
+                                if event_target_checked(&ev) {
+                                    chosen.push(channel);
+                                } else {
+                                    chosen.retain(|&c| c != channel);
+                                }
+                            })
+                        />
+                        {channel}
+                    </label>
+                </For>
+                <input type = "button" value = "Plot Selected" on:click = on_click />
+            </div>
+        </div>
+    }
+}
@@ -16,6 +16,8 @@ pub(crate) struct SearchLevelContext {
     pub(crate) search_by: RwSignal<SearchBy>,
     pub(crate) date: RwSignal<NaiveDate>,
     pub(crate) time: RwSignal<NaiveTime>,
+    /// If present, a dragnet search only matches traces timestamped at or before this.
+    pub(crate) until: RwSignal<Option<Timestamp>>,
     pub(crate) channels: RwSignal<Vec<Channel>>,
     pub(crate) digitiser_ids: RwSignal<Vec<DigitizerId>>,
     pub(crate) number: RwSignal<usize>,
@@ -47,6 +49,7 @@ impl SearchLevelContext {
             digitiser_ids: RwSignal::new(default_data.digitiser_ids.clone().unwrap_or_default()),
             date: RwSignal::new(default_date),
             time: RwSignal::new(default_time),
+            until: RwSignal::new(None),
             number: RwSignal::new(default_data.number.unwrap_or(1)),
             backstep: RwSignal::new(100),
             forward_distance: RwSignal::new(400),
@@ -1,8 +1,10 @@
 //! Implements the [Section] which enables the user to define search parameters and initiate a search.
 mod context;
+mod saved_sessions;
 mod search_control;
 mod search_section;
 mod search_settings;
+mod select_run;
 
 pub(crate) use context::SearchLevelContext;
 pub(crate) use search_section::SearchSection;
@@ -0,0 +1,64 @@
+use crate::app::{
+    main_content::MainLevelContext,
+    server_functions::{ListSavedSessions, LoadSession},
+};
+use leptos::{IntoView, component, prelude::*, view};
+
+/// Allows the user to replace the current results with those of a saved session.
+#[component]
+pub(crate) fn LoadSavedSession() -> impl IntoView {
+    let main_context = use_context::<MainLevelContext>()
+        .expect("MainLevelContext should be provided, this should never fail.");
+    let load_session = main_context.load_session;
+    let save_session = main_context.save_session;
+
+    let list_saved_sessions = ServerAction::<ListSavedSessions>::new();
+    // Refresh the list when the component is created, and whenever a session is saved.
+    Effect::new(move || {
+        save_session.value().track();
+        list_saved_sessions.dispatch(ListSavedSessions {});
+    });
+
+    let selected_name = RwSignal::<Option<String>>::new(None);
+
+    let on_click = move |_| {
+        if let Some(name) = selected_name.get() {
+            load_session.dispatch(LoadSession { name });
+        }
+    };
+
+    move || {
+        let names = list_saved_sessions
+            .value()
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        (!names.is_empty()).then(|| {
+            if selected_name
+                .get_untracked()
+                .is_none_or(|name| !names.contains(&name))
+            {
+                selected_name.set(names.first().cloned());
+            }
+            view! {
+                <label class = "panel-item" for = "saved-session">
+                    "Saved Session: "
+                    <select name = "saved-session" id = "saved-session" class = "panel-item"
+                        on:change = move |ev| selected_name.set(Some(event_target_value(&ev)))
+                    >
+                        <For each = move ||names.clone()
+                            key = ToOwned::to_owned
+                            let(name)
+                        >
+                            <option selected = {let name = name.clone(); move || selected_name.get().as_ref() == Some(&name)} value = {name.clone()}> {name.clone()} </option>
+                        </For>
+                    </select>
+                </label>
+                <input type = "button" class = "load-session-button" value = "Load"
+                    prop:disabled = move || load_session.pending().get()
+                    on:click = on_click
+                />
+            }
+        })
+    }
+}
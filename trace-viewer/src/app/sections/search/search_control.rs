@@ -1,5 +1,15 @@
-use crate::app::{main_content::MainLevelContext, server_functions::CancelSearch};
-use leptos::{IntoView, component, either::Either, prelude::*, view};
+use crate::{
+    app::{
+        main_content::MainLevelContext,
+        server_functions::{CancelSearch, GetSearchProgress},
+    },
+    structs::SearchProgress,
+};
+use leptos::{IntoView, component, either::Either, logging, prelude::*, view};
+use leptos_use::use_interval;
+
+/// How often the progress of a search is fetched.
+const PROGRESS_INTERVAL_MS: u64 = 500;
 
 #[component]
 pub(crate) fn SearchControl() -> impl IntoView {
@@ -10,12 +20,38 @@ pub(crate) fn SearchControl() -> impl IntoView {
 
     let cancel_search_server_action = ServerAction::<CancelSearch>::new();
 
+    let get_search_progress = ServerAction::<GetSearchProgress>::new();
+    let progress_interval = use_interval(PROGRESS_INTERVAL_MS);
+    Effect::new(move || {
+        if await_search.pending().get()
+            && let Some(uuid) = uuid.get()
+        {
+            progress_interval.counter.track();
+            get_search_progress.dispatch(GetSearchProgress { uuid });
+        } else {
+            // The progress of the previous search is not shown when the next begins.
+            get_search_progress.clear();
+        }
+    });
+    let progress = Signal::derive(move || {
+        get_search_progress
+            .value()
+            .get()
+            .and_then(|progress| progress.inspect_err(|e| logging::warn!("{e}")).ok())
+    });
+
     move || {
         if await_search.pending().get() {
             Either::Left(
                 uuid.get().map(move |uuid|view! {
-                    <div class = "searching">Searching...</div>
+                    <div class = "searching">
+                        {move || match progress.get() {
+                            Some(progress) => Either::Left(view! { <SearchProgressBar progress /> }),
+                            None => Either::Right("Searching..."),
+                        }}
+                    </div>
                     <input type = "button" class = "cancel-button" value = "Cancel"
+                        disabled = move || progress.get().is_some_and(|progress| progress.cancelled)
                         on:click = move |_| { cancel_search_server_action.dispatch(CancelSearch { uuid: uuid.clone() }); }
                     />
                 })
@@ -27,3 +63,26 @@ pub(crate) fn SearchControl() -> impl IntoView {
         }
     }
 }
+
+/// Displays the estimated fraction of a search complete, and the messages it has scanned and found.
+#[component]
+fn SearchProgressBar(progress: SearchProgress) -> impl IntoView {
+    let status = if progress.cancelled {
+        "Cancelling..."
+    } else {
+        "Searching..."
+    };
+    let position = progress
+        .position
+        .map(|position| format!(", at {}", position.format("%y-%m-%d %H:%M:%S.%f")))
+        .unwrap_or_default();
+    view! {
+        <progress class = "search-progress" max = "1" value = progress.fraction_complete></progress>
+        <div class = "search-progress-status">
+            {format!(
+                "{status} {} messages scanned, {} found{position}",
+                progress.messages_scanned, progress.matches_found
+            )}
+        </div>
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
         main_content::MainLevelContext,
         sections::search::{
             SearchLevelContext,
+            saved_sessions::LoadSavedSession,
             search_control::SearchControl,
             search_settings::{SearchBy, SearchMode, SearchSettings},
         },
@@ -40,6 +41,7 @@ pub(crate) fn SearchSection() -> impl IntoView {
                     timestamp: search_level_context.get_timestamp_with_utc(),
                     backstep: search_level_context.backstep.get(),
                     forward_distance: search_level_context.forward_distance.get(),
+                    until: search_level_context.until.get(),
                 },
             },
             by: match search_level_context.search_by.get() {
@@ -75,6 +77,7 @@ pub(crate) fn SearchSection() -> impl IntoView {
                 </div>
                 <div class = "content" id = "search-controls">
                     <SearchControl />
+                    <LoadSavedSession />
                 </div>
             </Section>
         </form>
@@ -1,4 +1,7 @@
-use crate::app::{TopLevelContext, sections::search::context::SearchLevelContext};
+use crate::app::{
+    TopLevelContext,
+    sections::search::{context::SearchLevelContext, select_run::SelectRun},
+};
 use leptos::{IntoView, component, either::EitherOf3, prelude::*, view};
 use std::str::FromStr;
 use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
@@ -25,6 +28,7 @@ pub(crate) fn SearchSettings() -> impl IntoView {
         </div>
         <div class = "content" id = "search-setup-mode">
             <SearchMode />
+            <SelectRun />
             <label for = "date">
                 "Date:"
                 <input name = "date" id = "date" type = "date"
@@ -54,6 +58,14 @@ pub(crate) fn SearchSettings() -> impl IntoView {
                         on:change = {move |ev|search_level_context.forward_distance.set(event_target_value(&ev).parse().expect("Forward Distance should parse, this should never fail."))}
                     />
                 </label>
+                <label for = "until">
+                    "Until:"
+                    <input name = "until" id = "until" type = "text"
+                        data-tooltip = "Only traces timestamped at or before this are matched. Leave empty to match traces of any time."
+                        value = {move ||search_level_context.until.get().map(|until| until.to_rfc3339()).unwrap_or_default()}
+                        on:change = {move |ev|search_level_context.until.set(event_target_value(&ev).parse().ok())}
+                    />
+                </label>
             </Show>
         </div>
 
@@ -0,0 +1,59 @@
+use crate::app::{sections::search::context::SearchLevelContext, server_functions::GetRuns};
+use leptos::{IntoView, component, prelude::*, view};
+
+/// Allows the user to search from the start of a run recorded on the control topic.
+/// Selecting a run sets the search timestamp to the run's start, and the time until which a dragnet search matches
+/// to the run's stop, or clears it if the run is ongoing.
+///
+/// The runs are those found when the control topic was last scanned, the refresh button scans it again.
+#[component]
+pub(crate) fn SelectRun() -> impl IntoView {
+    let search_level_context = use_context::<SearchLevelContext>()
+        .expect("SearchLevelContext should be provided, this should never fail.");
+
+    let get_runs = ServerAction::<GetRuns>::new();
+    get_runs.dispatch(GetRuns { refresh: false });
+
+    move || {
+        let runs = get_runs
+            .value()
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        (!runs.is_empty()).then(|| {
+            let options = runs.iter().map(ToString::to_string).enumerate().collect::<Vec<_>>();
+            let on_change = move |ev| {
+                if let Some(run) = event_target_value(&ev)
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| runs.get(index))
+                {
+                    search_level_context.date.set(run.start.date_naive());
+                    search_level_context.time.set(run.start.time());
+                    search_level_context.until.set(run.stop);
+                }
+            };
+            view! {
+                <label class = "panel-item" for = "run">
+                    "Run: "
+                    <select name = "run" id = "run" class = "panel-item"
+                        data-tooltip = "Choose a run from the control topic to search from its start."
+                        on:change = on_change
+                    >
+                        <option selected = true value = ""> "None" </option>
+                        <For each = move ||options.clone()
+                            key = ToOwned::to_owned
+                            let((index, run))
+                        >
+                            <option value = {index.to_string()}> {run} </option>
+                        </For>
+                    </select>
+                </label>
+                <input type = "button" class = "refresh-runs-button" value = "Refresh"
+                    prop:disabled = move || get_runs.pending().get()
+                    on:click = move |_| { get_runs.dispatch(GetRuns { refresh: true }); }
+                />
+            }
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    Time,
+    structs::{Annotation, AnnotationId, SelectedTraceIndex},
+};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::ServerSideData;
+        use tracing::debug;
+    }
+}
+
+/// Attaches a comment on `time_bin` to the given channel of the given trace message of the session with the given [Uuid],
+/// and returns the new annotation. The annotation outlives the session, and is shown whenever the trace is plotted.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn add_annotation(
+    uuid: String,
+    index_and_channel: SelectedTraceIndex,
+    time_bin: Time,
+    text: String,
+    author: String,
+) -> Result<Annotation, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let (annotation, write) = session_engine_arc_mutex.lock().await.add_annotation(
+        &uuid,
+        &index_and_channel,
+        time_bin,
+        &text,
+        &author,
+    )?;
+    write.persist().await?;
+    debug!("Annotation {} added by {author}.", annotation.id);
+    Ok(annotation)
+}
+
+/// Returns the annotations of the given channel of the given trace message of the session with the given [Uuid], in order of time bin.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn get_annotations(
+    uuid: String,
+    index_and_channel: SelectedTraceIndex,
+) -> Result<Vec<Annotation>, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    Ok(session_engine.get_annotations(&uuid, &index_and_channel)?)
+}
+
+/// Removes the annotation with the given `id`.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn delete_annotation(id: AnnotationId) -> Result<(), ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let write = session_engine_arc_mutex
+        .lock()
+        .await
+        .delete_annotation(id)?;
+    write.persist().await?;
+    debug!("Annotation {id} deleted.");
+    Ok(())
+}
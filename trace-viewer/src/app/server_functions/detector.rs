@@ -0,0 +1,205 @@
+use crate::structs::{DetectorConfig, DetectorEvents, SelectedTraceIndex};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn run_detector_on_trace(
+    uuid: String,
+    index_and_channel: SelectedTraceIndex,
+    detector_config: DetectorConfig,
+) -> Result<DetectorEvents, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    let (_, digitiser_traces) = session_engine
+        .session(&uuid)?
+        .get_selected_trace(index_and_channel.index)?;
+
+    let trace = digitiser_traces
+        .traces
+        .get(&index_and_channel.channel)
+        .ok_or(SessionError::ChannelNotFound)?;
+
+    Ok(run_detector(index_and_channel, trace, &detector_config)?)
+}
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::{
+            app::SessionError,
+            structs::{DetectorMode, ServerSideData, Trace as MuonTrace},
+        };
+        use plotly::{
+            Scatter, Trace,
+            color::NamedColor,
+            common::{Marker, MarkerSymbol, Mode},
+        };
+        use tracing::info;
+        use trace_to_events::{DetectorSettings, Polarity, TimeUnits, find_trace_events};
+
+        /// The plot's time axis is in samples, so the detector is run with a sample time of one.
+        const SAMPLE_TIME: f64 = 1.0;
+
+        /// Runs the detector given by `detector_config` on `trace`, and creates a plot of the events found.
+        fn run_detector(index_and_channel: SelectedTraceIndex, trace: &MuonTrace, detector_config: &DetectorConfig) -> Result<DetectorEvents, SessionError> {
+            info!("run_detector_on_server");
+
+            if let DetectorMode::AdaptiveThreshold { noise_window_size, .. } = detector_config.mode && noise_window_size < 2 {
+                return Err(SessionError::InvalidDetectorSettings(format!("noise window size must be at least 2, found {noise_window_size}")));
+            }
+
+            let mode = detector_config.mode.to_mode();
+            let polarity = Polarity::from(detector_config.polarity);
+            let (times, intensities) = find_trace_events(trace, SAMPLE_TIME, &DetectorSettings {
+                mode: &mode,
+                polarity: &polarity,
+                baseline: detector_config.baseline,
+                downsample_factor: 1,
+                legacy_time_alignment: false,
+                time_units: TimeUnits::Samples,
+            });
+
+            let name = detector_config.legend_name(times.len());
+            let eventlist = Scatter::new(times, intensities)
+                .mode(Mode::Markers)
+                .marker(Marker::new().color(NamedColor::Black).symbol(MarkerSymbol::StarOpen).opacity(0.75))
+                .name(name);
+
+            Ok(DetectorEvents {
+                index_and_channel,
+                eventlist_data: eventlist.to_json(),
+            })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::structs::{Cache, DetectorPolarity};
+    use digital_muon_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+            DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+            root_as_digitizer_analog_trace_message,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+
+    /// Returns a cache containing a single trace message, whose channel 3 has two pulses above a baseline of 10.
+    fn cache() -> Cache {
+        let mut fbb = FlatBufferBuilder::new();
+        let voltage = fbb.create_vector::<u16>(&[10, 10, 50, 60, 10, 10, 10, 40, 10, 10]);
+        let channel = ChannelTrace::create(
+            &mut fbb,
+            &ChannelTraceArgs {
+                channel: 3,
+                voltage: Some(voltage),
+            },
+        );
+        let timestamp = GpsTime::new(22, 205, 10, 55, 30, 0, 1, 5);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number: 0,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: 0,
+            },
+        );
+        let channels = fbb.create_vector(&[channel]);
+        let message = DigitizerAnalogTraceMessage::create(
+            &mut fbb,
+            &DigitizerAnalogTraceMessageArgs {
+                digitizer_id: 1,
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels: Some(channels),
+            },
+        );
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+
+        let payload = fbb.finished_data();
+        let mut cache = Cache::new();
+        cache
+            .push_trace(
+                &root_as_digitizer_analog_trace_message(payload).unwrap(),
+                payload,
+            )
+            .unwrap();
+        cache
+    }
+
+    fn fixed_threshold(threshold: f64) -> DetectorConfig {
+        DetectorConfig {
+            mode: DetectorMode::FixedThreshold {
+                threshold,
+                duration: 1,
+                cool_off: 0,
+            },
+            polarity: DetectorPolarity::Positive,
+            baseline: 10,
+        }
+    }
+
+    #[test]
+    fn fixed_threshold_on_stored_trace() {
+        let cache = cache();
+        let (_, digitiser_traces) = cache.get(0).unwrap();
+        let index_and_channel = SelectedTraceIndex {
+            index: 0,
+            channel: 3,
+        };
+        let trace = digitiser_traces.traces.get(&3).unwrap();
+
+        let events =
+            run_detector(index_and_channel.clone(), trace, &fixed_threshold(20.0)).unwrap();
+        assert_eq!(events.index_and_channel, index_and_channel);
+        assert!(
+            events.eventlist_data.contains(r#""x":[2,7]"#),
+            "{}",
+            events.eventlist_data
+        );
+        assert!(events.eventlist_data.contains(
+            r#""name":"Fixed Threshold: threshold 20, duration 1, cool off 0, Positive polarity, baseline 10 (2 events)""#
+        ));
+
+        // Only the larger pulse exceeds the higher threshold.
+        let events = run_detector(index_and_channel, trace, &fixed_threshold(35.0)).unwrap();
+        assert!(
+            events.eventlist_data.contains(r#""x":[2]"#),
+            "{}",
+            events.eventlist_data
+        );
+    }
+
+    #[test]
+    fn invalid_noise_window_is_rejected() {
+        let detector_config = DetectorConfig {
+            mode: DetectorMode::AdaptiveThreshold {
+                sigma_threshold: 1.0,
+                noise_window_size: 1,
+                duration: 1,
+                cool_off: 0,
+            },
+            polarity: DetectorPolarity::Positive,
+            baseline: 0,
+        };
+        let index_and_channel = SelectedTraceIndex {
+            index: 0,
+            channel: 3,
+        };
+        assert!(matches!(
+            run_detector(index_and_channel, &vec![0; 10], &detector_config),
+            Err(SessionError::InvalidDetectorSettings(_))
+        ));
+    }
+}
@@ -0,0 +1,23 @@
+use crate::structs::EngineStatus;
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::ServerSideData;
+    }
+}
+
+/// Returns the sessions held by the server, with their sizes and ages, and the memory cap.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn get_engine_status() -> Result<EngineStatus, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    Ok(session_engine.status())
+}
@@ -3,12 +3,16 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[allow(dead_code)]
-#[derive(Debug, Error, Serialize, Deserialize)]
+#[derive(Clone, Debug, Error, Serialize, Deserialize)]
 pub enum SessionError {
     #[error("No such session exists.")]
     DoesNotExist,
+    #[error("No such live tail exists.")]
+    LiveTailDoesNotExist,
     #[error("The session's search body has already been taken.")]
     BodyAlreadyTaken,
+    #[error("The session's search is already being awaited.")]
+    SearchInProgress,
     #[error("The session's results have not been registered.")]
     ResultsMissing,
     #[error("The session's search was cancelled by the user.")]
@@ -17,10 +21,38 @@ pub enum SessionError {
     TraceNotFound,
     #[error("The requested channel does not exist in the trace message.")]
     ChannelNotFound,
+    #[error("No eventlist topic with index {0} exists.")]
+    EventTopicNotFound(usize),
+    #[error("Invalid detector settings: {0}")]
+    InvalidDetectorSettings(String),
+    #[error(
+        "Saved session names must be nonempty, and contain only alphanumerics, '-' and '_', found: {0}"
+    )]
+    InvalidSavedSessionName(String),
+    #[error("A saved session named {0} already exists.")]
+    SavedSessionExists(String),
+    #[error("No saved session named {0} exists.")]
+    SavedSessionNotFound(String),
+    #[error("Saved Session IO Error: {0}")]
+    SavedSessionIo(String),
+    #[error("Saved Session Format Error: {0}")]
+    SavedSessionFormat(String),
+    #[error("Annotations must have nonempty text.")]
+    EmptyAnnotation,
+    #[error("No annotation with id {0} exists.")]
+    AnnotationNotFound(u64),
+    #[error("Annotation IO Error: {0}")]
+    AnnotationIo(String),
+    #[error("Annotation Format Error: {0}")]
+    AnnotationFormat(String),
     #[error("Two cancel requests were made.")]
     AttemptedToCancelTwice,
-    #[error("Could not send the cancel signal.")]
-    CouldNotSendCancelSignal,
+    #[error(
+        "The server's sessions hold an estimated {used} bytes, which has reached its cap of {cap} bytes. Try again once older sessions have expired."
+    )]
+    MemoryCapExceeded { used: usize, cap: usize },
+    #[error("Timed out reading the control topic.")]
+    ControlTopicTimeout,
     #[error("Kafka Error Code: {0}")]
     Kafka(String),
     #[error("Search Engine Error: {0}")]
@@ -0,0 +1,315 @@
+//! Plots the distributions of the events of every message held by a session.
+use crate::{
+    Channel,
+    structs::{HistogramQuantity, TracePlotly},
+};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+/// Plots a histogram of `quantity` over the events, from the eventlist topic with index `topic_index`,
+/// of every message of the session with the given [Uuid], divided into at most `bins` bins.
+/// If `channel_filter` is present, only the events of these channels are included.
+///
+/// The number of events, and their mean and median, are given in the title.
+/// If no events are selected, the plot is empty, rather than an error.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn create_histogram_plotly(
+    uuid: String,
+    channel_filter: Option<Vec<Channel>>,
+    topic_index: usize,
+    bins: usize,
+    quantity: HistogramQuantity,
+) -> Result<TracePlotly, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    // The values are copied out of the session, so the engine is not locked whilst they are binned and plotted.
+    let (values, topic) = {
+        let session_engine = session_engine_arc_mutex.lock().await;
+
+        let values = event_values(
+            session_engine
+                .session(&uuid)?
+                .get_event_lists(topic_index)?,
+            channel_filter.as_deref(),
+            quantity,
+        );
+
+        let topic = session_engine
+            .settings()
+            .topics
+            .digitiser_event_topic
+            .get(topic_index)
+            .cloned()
+            .unwrap_or_else(|| format!("topic {topic_index}"));
+        (values, topic)
+    };
+
+    Ok(create_histogram(values, bins, quantity, &topic))
+}
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::{DigitiserEventList, ServerSideData};
+        use plotly::{
+            Bar, Layout, Trace,
+            color::NamedColor,
+            common::Marker,
+            layout::{Axis, ModeBar},
+        };
+        use tracing::warn;
+
+        /// The most bins into which a histogram is divided, larger requests are reduced to this.
+        const MAX_HISTOGRAM_BINS: usize = 10_000;
+
+        /// Returns `quantity` of each event of `event_lists`, of the channels in `channel_filter`, if given.
+        fn event_values<'a>(event_lists: impl Iterator<Item = &'a DigitiserEventList>, channel_filter: Option<&[Channel]>, quantity: HistogramQuantity) -> Vec<u32> {
+            event_lists
+                .flat_map(|event_list| event_list.iter())
+                .filter(|&(channel, _)| channel_filter.is_none_or(|channels| channels.contains(channel)))
+                .flat_map(|(_, events)| events)
+                .map(|event| match quantity {
+                    HistogramQuantity::Time => event.time,
+                    HistogramQuantity::Amplitude => event.intensity as u32,
+                })
+                .collect()
+        }
+
+        /// The number of values in each of a sequence of equal, adjacent bins.
+        #[derive(Debug, PartialEq)]
+        struct Histogram {
+            /// The lowest value of the first bin.
+            start: u64,
+            /// The number of integer values in each bin.
+            width: u64,
+            counts: Vec<usize>,
+        }
+
+        impl Histogram {
+            /// Bins `values` into at most `bins` bins, which are capped at [MAX_HISTOGRAM_BINS],
+            /// spanning the lowest to the highest value. Returns [None] if `values` is empty.
+            ///
+            /// As the values are integers, each bin is at least one wide, so there are fewer bins if the values span fewer than `bins`.
+            fn new(values: &[u32], bins: usize) -> Option<Self> {
+                let min = *values.iter().min()? as u64;
+                let max = *values.iter().max()? as u64;
+                if bins > MAX_HISTOGRAM_BINS {
+                    warn!("Requested {bins} histogram bins, reduced to {MAX_HISTOGRAM_BINS}.");
+                }
+                let bins = bins.clamp(1, MAX_HISTOGRAM_BINS) as u64;
+                let span = max - min + 1;
+                let width = span.div_ceil(bins);
+                let mut counts = vec![0; span.div_ceil(width) as usize];
+                for &value in values {
+                    counts[((value as u64 - min) / width) as usize] += 1;
+                }
+                Some(Self { start: min, width, counts })
+            }
+
+            /// Returns the midpoint of each bin.
+            fn centres(&self) -> Vec<f64> {
+                (0..self.counts.len() as u64)
+                    .map(|bin| (self.start + bin * self.width) as f64 + (self.width - 1) as f64 / 2.0)
+                    .collect()
+            }
+        }
+
+        /// The number, mean and median of a nonempty list of values.
+        #[derive(Debug, PartialEq)]
+        struct Summary {
+            count: usize,
+            mean: f64,
+            median: f64,
+        }
+
+        impl Summary {
+            /// Summarises `values`, which are sorted in the process. Returns [None] if `values` is empty.
+            fn new(values: &mut [u32]) -> Option<Self> {
+                if values.is_empty() {
+                    return None;
+                }
+                values.sort_unstable();
+                let count = values.len();
+                let mean = values.iter().map(|&value| value as f64).sum::<f64>() / count as f64;
+                let median = if count % 2 == 0 {
+                    (values[count / 2 - 1] as f64 + values[count / 2] as f64) / 2.0
+                } else {
+                    values[count / 2] as f64
+                };
+                Some(Self { count, mean, median })
+            }
+        }
+
+        /// Plots the histogram of `values`, which are `quantity` of the events from `topic`, as a bar chart.
+        fn create_histogram(mut values: Vec<u32>, bins: usize, quantity: HistogramQuantity, topic: &str) -> TracePlotly {
+            let title = match Summary::new(&mut values) {
+                Some(Summary { count, mean, median }) => format!("Event {quantity} from {topic}: {count} events, mean {mean:.2}, median {median}"),
+                None => format!("Event {quantity} from {topic}: no events match the selection"),
+            };
+            let (centres, counts) = Histogram::new(&values, bins)
+                .map(|histogram| (histogram.centres(), histogram.counts))
+                .unwrap_or_default();
+
+            let bar = Bar::new(centres, counts)
+                .name("Events")
+                .marker(Marker::new().color(NamedColor::CadetBlue));
+            let layout = Layout::new()
+                .title(title.clone())
+                .mode_bar(ModeBar::new().background_color(NamedColor::LightGrey))
+                .show_legend(false)
+                .auto_size(true)
+                .bar_gap(0.0)
+                .x_axis(Axis::new().title(quantity.axis_title()))
+                .y_axis(Axis::new().title("Events"));
+
+            TracePlotly {
+                title,
+                trace_data: vec![bar.to_json()],
+                eventlist_data: Vec::new(),
+                layout: layout.to_json(),
+                range: None,
+                decimation_factor: 1,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::structs::Event;
+    use std::collections::HashMap;
+
+    #[test]
+    fn values_are_hand_binned() {
+        assert_eq!(
+            Histogram::new(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 9], 5),
+            Some(Histogram {
+                start: 0,
+                width: 2,
+                counts: vec![2, 2, 2, 2, 3]
+            })
+        );
+        // A span of six values is divided into two bins of three, from the lowest value.
+        let histogram = Histogram::new(&[15, 10, 12], 2).unwrap();
+        assert_eq!(
+            histogram,
+            Histogram {
+                start: 10,
+                width: 3,
+                counts: vec![2, 1]
+            }
+        );
+        assert_eq!(histogram.centres(), vec![11.0, 14.0]);
+        // Bins are never narrower than one value, so there are fewer than requested.
+        assert_eq!(
+            Histogram::new(&[5, 5, 6], 10),
+            Some(Histogram {
+                start: 5,
+                width: 1,
+                counts: vec![2, 1]
+            })
+        );
+        assert_eq!(Histogram::new(&[], 10), None);
+    }
+
+    #[test]
+    fn bins_are_capped() {
+        let histogram = Histogram::new(&[0, u32::MAX], usize::MAX).unwrap();
+        assert!(histogram.counts.len() <= MAX_HISTOGRAM_BINS);
+        assert_eq!(histogram.counts.first(), Some(&1));
+        assert_eq!(histogram.counts.last(), Some(&1));
+        assert_eq!(histogram.counts.iter().sum::<usize>(), 2);
+
+        // Zero bins is taken to be one.
+        let histogram = Histogram::new(&[3, 7], 0).unwrap();
+        assert_eq!(histogram.counts, vec![2]);
+    }
+
+    #[test]
+    fn summary_statistics() {
+        assert_eq!(
+            Summary::new(&mut [4, 1, 10]),
+            Some(Summary {
+                count: 3,
+                mean: 5.0,
+                median: 4.0
+            })
+        );
+        assert_eq!(
+            Summary::new(&mut [4, 1, 10, 2]),
+            Some(Summary {
+                count: 4,
+                mean: 4.25,
+                median: 3.0
+            })
+        );
+        assert_eq!(Summary::new(&mut []), None);
+    }
+
+    #[test]
+    fn events_are_filtered_by_channel() {
+        let events = |pairs: &[(u32, u16)]| {
+            pairs
+                .iter()
+                .map(|&(time, intensity)| Event { time, intensity })
+                .collect::<Vec<_>>()
+        };
+        let event_lists = [
+            HashMap::from([
+                (1, events(&[(10, 100), (20, 200)])),
+                (2, events(&[(30, 300)])),
+            ]),
+            HashMap::from([(1, events(&[(40, 400)]))]),
+        ];
+
+        let mut times = event_values(event_lists.iter(), None, HistogramQuantity::Time);
+        times.sort();
+        assert_eq!(times, vec![10, 20, 30, 40]);
+
+        let mut amplitudes = event_values(
+            event_lists.iter(),
+            Some(&[1][..]),
+            HistogramQuantity::Amplitude,
+        );
+        amplitudes.sort();
+        assert_eq!(amplitudes, vec![100, 200, 400]);
+
+        assert!(
+            event_values(event_lists.iter(), Some(&[3][..]), HistogramQuantity::Time).is_empty()
+        );
+    }
+
+    #[test]
+    fn histogram_is_plotted_with_summary() {
+        let plotly = create_histogram(vec![1, 2, 2, 7], 3, HistogramQuantity::Time, "daq");
+        assert_eq!(
+            plotly.title,
+            "Event Time from daq: 4 events, mean 3.00, median 2"
+        );
+        assert_eq!(plotly.trace_data.len(), 1);
+        let bar: serde_json::Value = serde_json::from_str(&plotly.trace_data[0]).unwrap();
+        assert_eq!(bar["type"], "bar");
+        assert_eq!(bar["x"], serde_json::json!([2.0, 5.0, 8.0]));
+        assert_eq!(bar["y"], serde_json::json!([3, 0, 1]));
+        assert!(plotly.layout.contains("Time (ns)"));
+    }
+
+    #[test]
+    fn empty_selection_is_empty_plot() {
+        let plotly = create_histogram(Vec::new(), 100, HistogramQuantity::Amplitude, "daq");
+        assert_eq!(
+            plotly.title,
+            "Event Amplitude from daq: no events match the selection"
+        );
+        let bar: serde_json::Value = serde_json::from_str(&plotly.trace_data[0]).unwrap();
+        assert_eq!(bar["type"], "bar");
+        assert_eq!(bar["x"], serde_json::json!([]));
+        assert_eq!(bar["y"], serde_json::json!([]));
+        assert!(plotly.eventlist_data.is_empty());
+        assert!(plotly.layout.contains("Intensity"));
+    }
+}
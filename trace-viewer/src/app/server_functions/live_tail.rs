@@ -0,0 +1,64 @@
+use crate::structs::TraceSummary;
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::ServerSideData;
+        use tracing::debug;
+    }
+}
+
+/// Starts following the trace topic from its end, and returns the [Uuid] of the live tail.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn start_live_tail() -> Result<String, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let live_tail_start = session_engine_arc_mutex.lock().await.live_tail_start();
+
+    // The lock is not held whilst the consumer is created and assigned, so other requests are not blocked.
+    let live_tail = live_tail_start.start()?;
+    let uuid = session_engine_arc_mutex
+        .lock()
+        .await
+        .insert_live_tail(live_tail);
+    debug!("New live tail has uuid: {uuid}");
+    Ok(uuid)
+}
+
+/// Stops the live tail with the given [Uuid].
+/// Returns an error if no such live tail exists.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn stop_live_tail(uuid: String) -> Result<(), ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let mut session_engine = session_engine_arc_mutex.lock().await;
+
+    session_engine.stop_live_tail(&uuid)?;
+    debug!("Live tail {uuid} stopped.");
+    Ok(())
+}
+
+/// Fetches summaries of the most recent message of each digitiser received by the live tail with the given [Uuid],
+/// ordered by digitiser id. As this is polled by the client, it also refreshes the live tail.
+/// Returns an error if no such live tail exists.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn get_latest_traces(uuid: String) -> Result<Vec<TraceSummary>, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let mut session_engine = session_engine_arc_mutex.lock().await;
+
+    let live_tail = session_engine.live_tail_mut(&uuid)?;
+    live_tail.refresh();
+    Ok(live_tail.get_latest_traces())
+}
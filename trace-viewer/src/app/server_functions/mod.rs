@@ -1,6 +1,14 @@
 //! All server functions appear here.
+mod annotations;
+mod detector;
+mod engine_status;
 mod errors;
+mod histogram;
+mod live_tail;
 mod plotly;
+mod raw_export;
+mod runs;
+mod saved_sessions;
 mod search;
 
 use crate::structs::{BrokerInfo, ClientSideData};
@@ -8,14 +16,29 @@ use cfg_if::cfg_if;
 use leptos::prelude::*;
 use tracing::instrument;
 
-pub use plotly::CreateAndFetchPlotly;
-pub use search::{AwaitSearch, CancelSearch, CreateNewSearch, FetchSearchSummaries};
+pub use annotations::{AddAnnotation, DeleteAnnotation, GetAnnotations};
+pub use detector::RunDetectorOnTrace;
+pub use engine_status::GetEngineStatus;
+pub use histogram::CreateHistogramPlotly;
+pub use live_tail::{GetLatestTraces, StartLiveTail, StopLiveTail};
+pub use plotly::{
+    CompareEventlists, CreateAndFetchLivePlotly, CreateAndFetchMultiPlotly, CreateAndFetchPlotly,
+};
+pub use raw_export::GetRawExportProgress;
+pub use runs::GetRuns;
+pub use saved_sessions::{ListSavedSessions, LoadSession, SaveSession};
+pub use search::{
+    AwaitSearch, CancelSearch, CreateNewSearch, FetchSearchSummaries, FetchTraceStatistics,
+    GetResultsPage, GetSearchProgress,
+};
 
 cfg_if! {
     if #[cfg(feature = "ssr")] {
         use crate::structs::ServerSideData;
         use tracing::debug;
 
+        pub use plotly::export_plot_html_route;
+        pub use raw_export::export_session_raw_route;
         pub(crate) use errors::{SessionError, ServerError};
     }
 }
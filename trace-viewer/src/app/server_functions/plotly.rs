@@ -1,18 +1,95 @@
-use crate::structs::{SelectedTraceIndex, TracePlotly};
+use crate::{
+    Channel, DigitizerId, Time,
+    structs::{
+        EventFilter, EventListComparison, MultiTracePlotly, PlotlyJs, SelectedTraceIndex,
+        TracePlotly, TraceView,
+    },
+};
 use cfg_if::cfg_if;
 use leptos::prelude::*;
 use tracing::instrument;
 
+/// Plots the given channel of the given trace message of the session with the given [Uuid].
+/// Only the part of the trace given by `view` is plotted, decimated if it has more than `view.max_points` samples,
+/// whilst its events and annotations are always plotted in full.
+///
+/// The plot is served from the engine's plot cache if the same channel of the same message has been plotted in the same way,
+/// by any session, and is otherwise generated and cached, see [SessionEngine::cached_plot].
 #[server]
 #[instrument(skip_all, err(level = "warn"))]
 pub async fn create_and_fetch_plotly(
     uuid: String,
     index_and_channel: SelectedTraceIndex,
+    event_filter: EventFilter,
+    view: TraceView,
 ) -> Result<TracePlotly, ServerFnError> {
     let session_engine_arc_mutex = use_context::<ServerSideData>()
         .expect("ServerSideData should be provided, this should never fail.")
         .session_engine;
 
+    let mut session_engine = session_engine_arc_mutex.lock().await;
+
+    let key = session_engine.plot_key(&uuid, &index_and_channel, &event_filter, &view)?;
+    if let Some(trace_plotly) = session_engine.cached_plot(&uuid, &key) {
+        return Ok(trace_plotly);
+    }
+
+    let trace_plotly = plot_selected_trace(
+        &session_engine,
+        &uuid,
+        &index_and_channel,
+        &event_filter,
+        &view,
+    )?;
+    session_engine.cache_plot(&uuid, key, trace_plotly.clone());
+    Ok(trace_plotly)
+}
+
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn create_and_fetch_multi_plotly(
+    uuid: String,
+    index: usize,
+    channels: Vec<Channel>,
+    event_filter: EventFilter,
+) -> Result<MultiTracePlotly, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    let (metadata, digitiser_traces) = session_engine.session(&uuid)?.get_selected_trace(index)?;
+
+    Ok(create_multi_plotly(
+        metadata,
+        &digitiser_traces,
+        &channels,
+        &session_engine.settings().topics.digitiser_event_topic,
+        &event_filter,
+    ))
+}
+
+/// Matches the events of the given channel of the given trace message captured from the eventlist topics
+/// with indices `topic_a` and `topic_b`, pairing those whose times differ by no more than `time_tolerance`, see [EventMatching::new].
+/// A topic from which no events of the channel were captured is compared as an empty list.
+/// The trace is plotted with both topics' events, as by [create_and_fetch_plotly], and the unmatched events are emphasised.
+///
+/// [EventMatching::new]: crate::structs::EventMatching::new
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn compare_eventlists(
+    uuid: String,
+    index_and_channel: SelectedTraceIndex,
+    topic_a: usize,
+    topic_b: usize,
+    time_tolerance: Time,
+    view: TraceView,
+) -> Result<EventListComparison, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
     let session_engine = session_engine_arc_mutex.lock().await;
 
     let (metadata, digitiser_traces) = session_engine
@@ -24,87 +101,860 @@ pub async fn create_and_fetch_plotly(
         .get(&index_and_channel.channel)
         .ok_or(SessionError::ChannelNotFound)?;
 
-    let eventlists = digitiser_traces
-        .events
-        .iter()
-        .flat_map(|(&topic_idx, events)| {
-            events.get(&index_and_channel.channel).map(|events| {
-                (
-                    session_engine
-                        .settings()
-                        .topics
-                        .digitiser_event_topic
-                        .get(topic_idx)
-                        .expect("Daq eventlist topic index should exist, this should never fail.")
-                        .clone(),
-                    events,
-                )
-            })
-        })
-        .collect::<Vec<_>>();
+    let topics = &session_engine.settings().topics.digitiser_event_topic;
+    let eventlist = |topic_index: usize| {
+        let topic = topics
+            .get(topic_index)
+            .ok_or(SessionError::EventTopicNotFound(topic_index))?;
+        let events = digitiser_traces
+            .events
+            .get(&topic_index)
+            .and_then(|events| events.get(&index_and_channel.channel))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        Ok::<_, SessionError>((topic.as_str(), events))
+    };
+
+    let eventlist_a = eventlist(topic_a)?;
+    let eventlist_b = eventlist(topic_b)?;
+    let matching = EventMatching::new(eventlist_a.1, eventlist_b.1, time_tolerance);
+    let trace_plotly = create_comparison_plotly(
+        metadata,
+        index_and_channel.channel,
+        trace,
+        eventlist_a,
+        eventlist_b,
+        &matching,
+        time_tolerance,
+        &view,
+    )?;
+
+    Ok(EventListComparison {
+        index_and_channel,
+        matching,
+        trace_plotly,
+    })
+}
+
+/// Plots the given channel of the most recent message of the given digitiser, received by the live tail with the given [Uuid].
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn create_and_fetch_live_plotly(
+    uuid: String,
+    digitiser_id: DigitizerId,
+    channel: Channel,
+) -> Result<TracePlotly, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    // The message is copied out of the live tail's buffer, so the buffer is not locked while plotting.
+    let (metadata, digitiser_traces) = session_engine
+        .live_tail(&uuid)?
+        .get_latest_trace(digitiser_id)?;
+
+    let trace = digitiser_traces
+        .traces
+        .get(&channel)
+        .ok_or(SessionError::ChannelNotFound)?;
+
+    create_plotly(
+        &metadata,
+        channel,
+        trace,
+        Vec::new(),
+        &[],
+        &EventFilter::default(),
+        &TraceView::default(),
+    )
+}
+
+/// Renders the plot of the given channel of the given trace message as a standalone html document,
+/// which includes plotly.js from its CDN or inline, according to `plotly_js`.
+/// The document is also served as a file by [export_plot_html_route].
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn export_plot_html(
+    uuid: String,
+    index_and_channel: SelectedTraceIndex,
+    plotly_js: PlotlyJs,
+) -> Result<String, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
 
-    create_plotly(metadata, index_and_channel.channel, trace, eventlists)
+    Ok(export_plot(&session_engine, &uuid, &index_and_channel, plotly_js)?.html)
 }
 
 cfg_if! {
     if #[cfg(feature = "ssr")] {
         use crate::{
+            Intensity,
             app::SessionError,
-            structs::{DigitiserMetadata, Trace as MuonTrace, EventList, ServerSideData},
-            Channel
+            sessions::SessionEngine,
+            structs::{Annotation, DigitiserMetadata, DigitiserTrace, Event, EventMatching, Trace as MuonTrace, ServerSideData, TimeRange},
         };
+        use actix_web::{HttpResponse, http::header::{ContentDisposition, ContentType}, web};
         use plotly::{
-            Layout, Scatter, Trace,
+            Layout, Plot, Scatter, Trace,
             color::NamedColor,
-            common::{Line, Marker, MarkerSymbol, Mode},
-            layout::{Axis, ModeBar},
+            common::{DashType, Line, Marker, MarkerSymbol, Mode, Position},
+            layout::{Axis, ModeBar, Shape, ShapeLine, ShapeType},
         };
-        use tracing::info;
+        use serde::Deserialize;
+        use tracing::{info, warn};
         const COLOURS: [NamedColor; 6] = [NamedColor::IndianRed, NamedColor::DarkGreen, NamedColor::Indigo, NamedColor::MediumSpringGreen, NamedColor::HotPink, NamedColor::YellowGreen];
         const MARKERS: [MarkerSymbol; 5] = [MarkerSymbol::CircleOpen, MarkerSymbol::SquareOpen, MarkerSymbol::Cross, MarkerSymbol::DiamondOpen, MarkerSymbol::X];
+        /// The size of the markers of unmatched events, which is larger than plotly's default of six.
+        const UNMATCHED_MARKER_SIZE: usize = 12;
 
-        fn create_plotly<'a>(metadata: &DigitiserMetadata, channel: Channel, trace: &'a MuonTrace, eventlists: Vec<(String, &'a EventList)>) -> Result<TracePlotly, ServerFnError> {
-            info!("create_plotly_on_server");
+        /// Returns the event lists of `channel` paired with the name of the topic they were captured from.
+        fn channel_eventlists<'a>(digitiser_traces: &'a DigitiserTrace, channel: Channel, event_topics: &'a [String]) -> Vec<(&'a str, &'a [Event])> {
+            digitiser_traces
+                .events
+                .iter()
+                .flat_map(|(&topic_idx, events)| {
+                    events.get(&channel).map(|events| {
+                        (
+                            event_topics
+                                .get(topic_idx)
+                                .expect("Daq eventlist topic index should exist, this should never fail.")
+                                .as_str(),
+                            events.as_slice(),
+                        )
+                    })
+                })
+                .collect()
+        }
 
-            let date = metadata.timestamp.date_naive().to_string();
-            let time = metadata.timestamp.time().to_string();
-            let layout = Layout::new()
-                .title(format!("Channel {channel}, digitiser {}, in frame {} at<br>{time} on {date}.", metadata.id, metadata.frame_number))
+        fn create_layout(title: String) -> Layout {
+            Layout::new()
+                .title(title)
                 .mode_bar(ModeBar::new().background_color(NamedColor::LightGrey))
                 .show_legend(true)
                 .auto_size(true)
                 .x_axis(Axis::new().title("Time (ns)"))
-                .y_axis(Axis::new().title("Intensity"));
+                .y_axis(Axis::new().title("Intensity"))
+        }
 
-            let trace = Scatter::new(
+        fn create_trace(trace: &MuonTrace, name: &str, colour: NamedColor) -> Box<Scatter<usize, u16>> {
+            Scatter::new(
                 (0..trace.len()).collect::<Vec<_>>(),
                 trace.clone(),
             )
             .mode(Mode::Lines)
-            .name("Trace")
-            .line(Line::new().color(NamedColor::CadetBlue));
+            .name(name)
+            .line(Line::new().color(colour))
+        }
+
+        /// Returns the length of the intervals into which [decimate] divides a trace of `len` points.
+        fn decimation_interval(len: usize, max_points: usize) -> usize {
+            len.div_ceil((max_points / 2).max(1))
+        }
 
-            let eventlists = eventlists.into_iter()
+        /// Reduces `trace` to at most `max_points` points, which should be at least 2,
+        /// by keeping the lowest and highest point of each of `max_points / 2` equal intervals, so that pulses remain visible.
+        /// # Returns
+        /// The times and intensities of the points kept, in order, or [None] if `trace` has no more than `max_points` points.
+        fn decimate(trace: &[Intensity], max_points: usize) -> Option<(Vec<usize>, Vec<Intensity>)> {
+            if trace.len() <= max_points {
+                return None;
+            }
+            let interval = decimation_interval(trace.len(), max_points);
+            let mut times = Vec::with_capacity(max_points);
+            let mut intensities = Vec::with_capacity(max_points);
+            for (start, values) in (0..trace.len()).step_by(interval).zip(trace.chunks(interval)) {
+                let points = values.iter().enumerate();
+                let (min_time, &min) = points.clone().min_by_key(|(_, value)| **value).expect("Chunk should be nonempty, this should never fail.");
+                let (max_time, &max) = points.max_by_key(|(_, value)| **value).expect("Chunk should be nonempty, this should never fail.");
+                let mut extremes = vec![(min_time, min), (max_time, max)];
+                extremes.sort_by_key(|(time, _)| *time);
+                extremes.dedup_by_key(|(time, _)| *time);
+                for (time, value) in extremes {
+                    times.push(start + time);
+                    intensities.push(value);
+                }
+            }
+            Some((times, intensities))
+        }
+
+        fn create_eventlist(eventlist: &[Event], name: impl Fn(usize, usize) -> String, colour: NamedColor, symbol: MarkerSymbol, event_filter: &EventFilter) -> Box<Scatter<u32, u16>> {
+            let (shown, hidden) = event_filter.apply(eventlist);
+            Scatter::new(
+                shown.iter().map(|event| event.time).collect::<Vec<_>>(),
+                shown
+                    .iter()
+                    .map(|event| event.intensity)
+                    .collect::<Vec<_>>(),
+            )
+            .mode(Mode::Markers)
+            .marker(Marker::new().color(colour).symbol(symbol).opacity(0.75))
+            .name(name(shown.len(), hidden))
+        }
+
+        /// Marks the time bin of each of `annotations` by a vertical line across the plot,
+        /// and by a marker labelled with its author and text, at the intensity of `trace` in that bin.
+        fn create_annotations(trace: &MuonTrace, annotations: &[Annotation]) -> (Vec<Shape>, Box<Scatter<u32, u16>>) {
+            let lines = annotations
+                .iter()
+                .map(|annotation| {
+                    Shape::new()
+                        .shape_type(ShapeType::Line)
+                        .x_ref("x")
+                        .y_ref("paper")
+                        .x0(annotation.time_bin)
+                        .x1(annotation.time_bin)
+                        .y0(0)
+                        .y1(1)
+                        .line(ShapeLine::new().color(NamedColor::DarkOrange).dash(DashType::Dash))
+                })
+                .collect();
+            let labels = annotations
+                .iter()
+                .map(|annotation| match annotation.author.as_str() {
+                    "" => annotation.text.clone(),
+                    author => format!("{author}: {}", annotation.text),
+                })
+                .collect::<Vec<_>>();
+            let markers = Scatter::new(
+                annotations.iter().map(|annotation| annotation.time_bin).collect::<Vec<_>>(),
+                annotations
+                    .iter()
+                    .map(|annotation| trace.get(annotation.time_bin as usize).copied().unwrap_or_default())
+                    .collect::<Vec<_>>(),
+            )
+            .mode(Mode::MarkersText)
+            .text_array(labels)
+            .text_position(Position::TopCenter)
+            .marker(Marker::new().color(NamedColor::DarkOrange).symbol(MarkerSymbol::TriangleDown))
+            .name("Annotations");
+            (lines, markers)
+        }
+
+        /// Plots `trace`, its `eventlists` and its `annotations`.
+        /// If `view` has a range, only the samples of `trace` within it are plotted, and the x-axis is set to it, though the events and annotations are plotted in full.
+        /// If `view` has a maximum number of points, then more samples are decimated by [decimate], and this is noted in the titles.
+        fn create_plotly<'a>(metadata: &DigitiserMetadata, channel: Channel, trace: &'a MuonTrace, eventlists: Vec<(&'a str, &'a [Event])>, annotations: &[Annotation], event_filter: &EventFilter, view: &TraceView) -> Result<TracePlotly, ServerFnError> {
+            create_noted_plotly(metadata, channel, trace, eventlists, annotations, event_filter, view, None)
+        }
+
+        /// As [create_plotly], but with `note`, if given, added to the titles.
+        #[allow(clippy::too_many_arguments)]
+        fn create_noted_plotly<'a>(metadata: &DigitiserMetadata, channel: Channel, trace: &'a MuonTrace, eventlists: Vec<(&'a str, &'a [Event])>, annotations: &[Annotation], event_filter: &EventFilter, view: &TraceView, note: Option<&str>) -> Result<TracePlotly, ServerFnError> {
+            info!("create_plotly_on_server");
+
+            let date = metadata.timestamp.date_naive().to_string();
+            let time = metadata.timestamp.time().to_string();
+            let mut title = format!("Channel {} from Digitiser {}", channel, metadata.id);
+            let mut layout_title = format!("Channel {channel}, digitiser {}, in frame {} at<br>{time} on {date}.", metadata.id, metadata.frame_number);
+            if let Some(note) = note {
+                title = format!("{title} ({note})");
+                layout_title = format!("{layout_title}<br>{note}");
+            }
+
+            // The range is clamped to the trace, so is empty if it lies beyond it.
+            let range = view.range.map(|range| {
+                let end = (range.end as usize).min(trace.len());
+                (range.start as usize).min(end)..end
+            });
+            let samples = &trace[range.clone().unwrap_or(0..trace.len())];
+            let start = range.as_ref().map(|range| range.start).unwrap_or_default();
+
+            let decimated = view.max_points.and_then(|max_points| {
+                decimate(samples, max_points).map(|(times, intensities)| (times, intensities, decimation_interval(samples.len(), max_points)))
+            });
+            let (times, intensities, decimation_factor) = match decimated {
+                Some((times, intensities, interval)) => {
+                    let note = format!("Decimated from {} to {} points.", samples.len(), times.len());
+                    title = format!("{title} ({note})");
+                    layout_title = format!("{layout_title}<br>{note}");
+                    (times.into_iter().map(|time| start + time).collect(), intensities, interval)
+                }
+                None => ((start..start + samples.len()).collect::<Vec<_>>(), samples.to_vec(), 1),
+            };
+            let trace_data = Scatter::new(times, intensities)
+                .mode(Mode::Lines)
+                .name("Trace")
+                .line(Line::new().color(NamedColor::CadetBlue));
+            let mut layout = create_layout(layout_title);
+            let range = range.map(|range| TimeRange { start: range.start as Time, end: range.end as Time });
+            if let Some(range) = range {
+                layout = layout.x_axis(Axis::new().title("Time (ns)").range(vec![range.start, range.end]));
+            }
+
+            let mut eventlist_data = eventlists.into_iter()
                 .zip(COLOURS.iter().cycle().zip(MARKERS.iter().cycle()))
-                .map(|((event_topic, eventlist), (colour, symbol))|
-                Scatter::new(
-                    eventlist.iter().map(|event| event.time).collect::<Vec<_>>(),
-                    eventlist
-                        .iter()
-                        .map(|event| event.intensity)
-                        .collect::<Vec<_>>(),
-                )
-                .mode(Mode::Markers)
-                .marker(Marker::new().color(*colour).symbol(symbol.clone()).opacity(0.75))
-                .name(format!{"Events: {event_topic}"})
-            );
+                .map(|((event_topic, eventlist), (colour, symbol))| {
+                    create_eventlist(eventlist, |shown, hidden| event_filter.legend_name(event_topic, shown, hidden), *colour, symbol.clone(), event_filter).to_json()
+                })
+                .collect::<Vec<_>>();
+
+            if !annotations.is_empty() {
+                let (lines, markers) = create_annotations(trace, annotations);
+                layout = layout.shapes(lines);
+                eventlist_data.push(markers.to_json());
+            }
 
             Ok(TracePlotly {
-                title: format!("Channel {} from Digitiser {}", channel, metadata.id),
-                trace_data: trace.to_json(),
-                eventlist_data: eventlists.map(|eventlist|eventlist.to_json()).collect(),
+                title,
+                trace_data: vec![trace_data.to_json()],
+                eventlist_data,
                 layout: layout.to_json(),
+                range,
+                decimation_factor,
+            })
+        }
+
+        /// Plots the unmatched events of one of the event lists of a comparison, with larger markers than [create_eventlist], so they stand out.
+        fn create_unmatched_eventlist(events: &[Event], name: String, colour: NamedColor, symbol: MarkerSymbol) -> Box<Scatter<u32, u16>> {
+            Scatter::new(
+                events.iter().map(|event| event.time).collect::<Vec<_>>(),
+                events.iter().map(|event| event.intensity).collect::<Vec<_>>(),
+            )
+            .mode(Mode::Markers)
+            .marker(Marker::new().color(colour).symbol(symbol).size(UNMATCHED_MARKER_SIZE))
+            .name(name)
+        }
+
+        /// Plots `trace` with the events of the topics of `eventlist_a` and `eventlist_b`, as [create_plotly] does,
+        /// and overlays the events of each which are not matched by `matching`, with the statistics of `matching` in the titles.
+        #[allow(clippy::too_many_arguments)]
+        fn create_comparison_plotly<'a>(metadata: &DigitiserMetadata, channel: Channel, trace: &'a MuonTrace, eventlist_a: (&'a str, &'a [Event]), eventlist_b: (&'a str, &'a [Event]), matching: &EventMatching, time_tolerance: Time, view: &TraceView) -> Result<TracePlotly, ServerFnError> {
+            let (topic_a, _) = eventlist_a;
+            let (topic_b, _) = eventlist_b;
+            let summary = matching.summary(topic_a, topic_b, time_tolerance);
+            let mut trace_plotly = create_noted_plotly(metadata, channel, trace, vec![eventlist_a, eventlist_b], &[], &EventFilter::default(), view, Some(&summary))?;
+            trace_plotly.eventlist_data.extend([
+                create_unmatched_eventlist(&matching.only_in_a, format!("Only in {topic_a}"), NamedColor::Crimson, MarkerSymbol::Diamond).to_json(),
+                create_unmatched_eventlist(&matching.only_in_b, format!("Only in {topic_b}"), NamedColor::DodgerBlue, MarkerSymbol::Square).to_json(),
+            ]);
+            Ok(trace_plotly)
+        }
+
+        /// Plots each of `channels` which exist in the trace message, in the given order.
+        /// Each channel's trace and events share a colour, and each channel's events have a distinct marker symbol.
+        fn create_multi_plotly(metadata: &DigitiserMetadata, digitiser_traces: &DigitiserTrace, channels: &[Channel], event_topics: &[String], event_filter: &EventFilter) -> MultiTracePlotly {
+            info!("create_multi_plotly_on_server");
+
+            let (present, missing_channels): (Vec<_>, Vec<_>) = channels
+                .iter()
+                .partition(|channel| digitiser_traces.traces.contains_key(channel));
+
+            let date = metadata.timestamp.date_naive().to_string();
+            let time = metadata.timestamp.time().to_string();
+            let channel_list = present.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+            let layout = create_layout(format!("Channels {channel_list}, digitiser {}, in frame {} at<br>{time} on {date}.", metadata.id, metadata.frame_number));
+
+            let mut trace_data = Vec::new();
+            let mut eventlist_data = Vec::new();
+            for (&channel, (colour, symbol)) in present.iter().zip(COLOURS.iter().cycle().zip(MARKERS.iter().cycle())) {
+                let trace = digitiser_traces.traces.get(&channel).expect("Channel should exist, this should never fail.");
+                trace_data.push(create_trace(trace, &format!("Channel {channel}"), *colour).to_json());
+
+                for (event_topic, eventlist) in channel_eventlists(digitiser_traces, channel, event_topics) {
+                    let name = |shown, hidden| format!("Channel {channel} {}", event_filter.legend_name(event_topic, shown, hidden));
+                    eventlist_data.push(create_eventlist(eventlist, name, *colour, symbol.clone(), event_filter).to_json());
+                }
+            }
+
+            MultiTracePlotly {
+                trace_plotly: TracePlotly {
+                    title: format!("Channels {channel_list} from Digitiser {}", metadata.id),
+                    trace_data,
+                    eventlist_data,
+                    layout: layout.to_json(),
+                    range: None,
+                    decimation_factor: 1,
+                },
+                missing_channels,
+            }
+        }
+
+        /// Returns the name of the file of an exported plot of `channel` of the message with `metadata`.
+        fn export_file_name(metadata: &DigitiserMetadata, channel: Channel) -> String {
+            format!(
+                "digitiser_{}_channel_{channel}_frame_{}_{}.html",
+                metadata.id,
+                metadata.frame_number,
+                metadata.timestamp.format("%Y-%m-%dT%H-%M-%S%.9f")
+            )
+        }
+
+        /// Renders `trace_plotly` as a standalone html document, which displays the plot as [DisplayGraph] does.
+        fn render_html(trace_plotly: &TracePlotly, plotly_js: PlotlyJs) -> String {
+            let TracePlotly { title, trace_data, eventlist_data, layout, .. } = trace_plotly;
+            let data = trace_data.iter().chain(eventlist_data).map(String::as_str).collect::<Vec<_>>().join(",");
+            let scripts = match plotly_js {
+                PlotlyJs::Cdn => Plot::online_cdn_js(),
+                PlotlyJs::Inline => Plot::offline_js_sources(),
+            };
+            format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8" />
+<title>{title}</title>
+{scripts}
+</head>
+<body>
+<div id="trace-graph" class="plotly-graph-div"></div>
+<script type="text/javascript">
+var data = [{data}];
+var layout = {layout};
+var config = {{ 'scrollZoom': true}};
+Plotly.newPlot('trace-graph', data, layout, config);
+</script>
+</body>
+</html>
+"#)
+        }
+
+        /// A plot rendered as a standalone html document, with the name of the file to save it as.
+        struct PlotHtml {
+            file_name: String,
+            html: String,
+        }
+
+        /// Plots the given channel of the given trace message of the session with `uuid`, with its events and annotations, see [create_plotly].
+        fn plot_selected_trace(session_engine: &SessionEngine, uuid: &str, index_and_channel: &SelectedTraceIndex, event_filter: &EventFilter, view: &TraceView) -> Result<TracePlotly, ServerFnError> {
+            let (metadata, digitiser_traces) = session_engine
+                .session(uuid)?
+                .get_selected_trace(index_and_channel.index)?;
+
+            let trace = digitiser_traces
+                .traces
+                .get(&index_and_channel.channel)
+                .ok_or(SessionError::ChannelNotFound)?;
+
+            let eventlists = channel_eventlists(
+                &digitiser_traces,
+                index_and_channel.channel,
+                &session_engine.settings().topics.digitiser_event_topic,
+            );
+
+            let annotations = session_engine
+                .annotations_of(metadata, index_and_channel.channel)
+                .unwrap_or_else(|e| {
+                    warn!("Plotting without annotations: {e}");
+                    Vec::new()
+                });
+
+            create_plotly(
+                metadata,
+                index_and_channel.channel,
+                trace,
+                eventlists,
+                &annotations,
+                event_filter,
+                view,
+            )
+        }
+
+        /// Renders the plot of the given channel of the given trace message of the session with `uuid`.
+        /// All events are plotted, and the trace is decimated to the engine's [export_max_points] setting.
+        ///
+        /// [export_max_points]: crate::sessions::SessionEngineSettings::export_max_points
+        fn export_plot(session_engine: &SessionEngine, uuid: &str, index_and_channel: &SelectedTraceIndex, plotly_js: PlotlyJs) -> Result<PlotHtml, ServerFnError> {
+            let metadata = session_engine
+                .session(uuid)?
+                .get_metadata(index_and_channel.index, index_and_channel.channel)?;
+
+            let trace_plotly = plot_selected_trace(
+                session_engine,
+                uuid,
+                index_and_channel,
+                &EventFilter::default(),
+                &TraceView::whole_trace(Some(session_engine.settings().export_max_points)),
+            )?;
+
+            Ok(PlotHtml {
+                file_name: export_file_name(metadata, index_and_channel.channel),
+                html: render_html(&trace_plotly, plotly_js),
+            })
+        }
+
+        /// The query string of [export_plot_html_route].
+        #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        pub struct ExportQuery {
+            /// Either `cdn` or `inline`, defaults to `cdn`.
+            #[serde(default)]
+            plotly_js: PlotlyJs,
+        }
+
+        /// Serves `GET /export/{uuid}/{index}/{channel}` with the document rendered by [export_plot_html], as a file to download.
+        pub async fn export_plot_html_route(path: web::Path<(String, usize, Channel)>, query: web::Query<ExportQuery>, server_side_data: web::Data<ServerSideData>) -> HttpResponse {
+            let (uuid, index, channel) = path.into_inner();
+            let session_engine = server_side_data.session_engine.lock().await;
+
+            match export_plot(&session_engine, &uuid, &SelectedTraceIndex { index, channel }, query.plotly_js) {
+                Ok(PlotHtml { file_name, html }) => HttpResponse::Ok()
+                    .content_type(ContentType::html())
+                    .insert_header(ContentDisposition::attachment(file_name))
+                    .body(html),
+                Err(e) => {
+                    warn!("Cannot export plot: {e}");
+                    HttpResponse::NotFound().body(e.to_string())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::structs::Event;
+    use chrono::DateTime;
+    use std::collections::HashMap;
+
+    fn metadata() -> DigitiserMetadata {
+        DigitiserMetadata {
+            timestamp: DateTime::from_timestamp_millis(0).unwrap(),
+            id: 4,
+            frame_number: 0,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            veto_flags: 0,
+        }
+    }
+
+    fn digitiser_traces() -> DigitiserTrace {
+        let event = |time| Event {
+            time,
+            intensity: 10,
+        };
+        DigitiserTrace {
+            traces: HashMap::from([(1, vec![0, 10, 0]), (2, vec![0, 0, 20])]),
+            events: HashMap::from([(0, HashMap::from([(1, vec![event(1)]), (2, vec![event(2)])]))]),
+        }
+    }
+
+    fn topics() -> Vec<String> {
+        vec!["daq".to_owned()]
+    }
+
+    #[test]
+    fn two_channels() {
+        let plotly = create_multi_plotly(
+            &metadata(),
+            &digitiser_traces(),
+            &[2, 1],
+            &topics(),
+            &EventFilter::default(),
+        );
+        assert!(plotly.missing_channels.is_empty());
+
+        let TracePlotly {
+            title,
+            trace_data,
+            eventlist_data,
+            ..
+        } = plotly.trace_plotly;
+        assert_eq!(title, "Channels 2, 1 from Digitiser 4");
+        assert_eq!(trace_data.len(), 2);
+        assert!(trace_data[0].contains(r#""name":"Channel 2""#));
+        assert!(trace_data[0].contains(r#""color":"indianred""#));
+        assert!(trace_data[1].contains(r#""name":"Channel 1""#));
+        assert!(trace_data[1].contains(r#""color":"darkgreen""#));
+
+        assert_eq!(eventlist_data.len(), 2);
+        assert!(eventlist_data[0].contains(r#""name":"Channel 2 Events: daq""#));
+        assert!(eventlist_data[0].contains(r#""symbol":"circle-open""#));
+        assert!(eventlist_data[1].contains(r#""name":"Channel 1 Events: daq""#));
+        assert!(eventlist_data[1].contains(r#""symbol":"square-open""#));
+    }
+
+    #[test]
+    fn missing_channels_are_skipped() {
+        let plotly = create_multi_plotly(
+            &metadata(),
+            &digitiser_traces(),
+            &[5, 1, 7],
+            &topics(),
+            &EventFilter::default(),
+        );
+        assert_eq!(plotly.missing_channels, vec![5, 7]);
+        assert_eq!(plotly.trace_plotly.trace_data.len(), 1);
+        assert!(plotly.trace_plotly.trace_data[0].contains(r#""name":"Channel 1""#));
+        assert_eq!(plotly.trace_plotly.eventlist_data.len(), 1);
+    }
+
+    #[test]
+    fn decimation_keeps_extremes_in_order() {
+        let trace = vec![5, 1, 9, 3, 4, 2, 8, 8, 0, 6];
+        assert_eq!(
+            decimate(&trace, 4),
+            Some((vec![1, 2, 7, 8], vec![1, 9, 8, 0]))
+        );
+        // The last interval has a single point, which is kept once.
+        assert_eq!(
+            decimate(&(0..7).collect::<Vec<_>>(), 6),
+            Some((vec![0, 2, 3, 5, 6], vec![0, 2, 3, 5, 6]))
+        );
+        assert_eq!(decimate(&trace, 10), None);
+    }
+
+    #[test]
+    fn exported_html_contains_plot() {
+        let digitiser_traces = digitiser_traces();
+        let trace_plotly = create_plotly(
+            &metadata(),
+            1,
+            &digitiser_traces.traces[&1],
+            channel_eventlists(&digitiser_traces, 1, &topics()),
+            &[],
+            &EventFilter::default(),
+            &TraceView {
+                max_points: Some(100),
+                range: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(trace_plotly.title, "Channel 1 from Digitiser 4");
+
+        let html = render_html(&trace_plotly, PlotlyJs::Cdn);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(r#"<div id="trace-graph" class="plotly-graph-div"></div>"#));
+        assert!(html.contains("Plotly.newPlot('trace-graph', data, layout, config);"));
+        assert!(html.contains(r#""x":[0,1,2]"#));
+        assert!(html.contains(r#""y":[0,10,0]"#));
+        assert!(html.contains(r#""name":"Events: daq""#));
+        assert!(html.contains(r#""x":[1]"#));
+        assert!(html.contains("https://cdn.plot.ly/"));
+        assert!(!html.contains("Decimated"));
+
+        let html = render_html(&trace_plotly, PlotlyJs::Inline);
+        assert!(!html.contains("https://cdn.plot.ly/"));
+        assert!(html.contains(r#""y":[0,10,0]"#));
+    }
+
+    #[test]
+    fn long_trace_is_decimated_when_exported() {
+        let trace = (0..1000).map(|time| (time % 100) as Intensity).collect();
+        let trace_plotly = create_plotly(
+            &metadata(),
+            1,
+            &trace,
+            Vec::new(),
+            &[],
+            &EventFilter::default(),
+            &TraceView {
+                max_points: Some(10),
+                range: None,
+            },
+        )
+        .unwrap();
+        let note = "Decimated from 1000 to 10 points.";
+        assert_eq!(
+            trace_plotly.title,
+            format!("Channel 1 from Digitiser 4 ({note})")
+        );
+
+        // Each interval of 200 points contains two sawteeth, of which the first minimum and last maximum are kept.
+        let html = render_html(&trace_plotly, PlotlyJs::Cdn);
+        assert!(html.contains(note));
+        assert!(html.contains(r#""x":[0,199,200,399,400,599,600,799,800,999]"#));
+        assert!(html.contains(r#""y":[0,99,0,99,0,99,0,99,0,99]"#));
+    }
+
+    #[test]
+    fn annotations_are_marked_on_plot() {
+        let annotation = |id, time_bin, text: &str, author: &str| Annotation {
+            id,
+            time_bin,
+            text: text.to_owned(),
+            author: author.to_owned(),
+            created: DateTime::from_timestamp_millis(0).unwrap(),
+        };
+        let digitiser_traces = digitiser_traces();
+        let trace_plotly = create_plotly(
+            &metadata(),
+            1,
+            &digitiser_traces.traces[&1],
+            Vec::new(),
+            &[
+                annotation(0, 1, "ringing starts here", "alice"),
+                annotation(1, 7, "end", ""),
+            ],
+            &EventFilter::default(),
+            &TraceView::default(),
+        )
+        .unwrap();
+
+        assert_eq!(trace_plotly.eventlist_data.len(), 1);
+        let markers = &trace_plotly.eventlist_data[0];
+        assert!(markers.contains(r#""name":"Annotations""#));
+        assert!(markers.contains(r#""x":[1,7]"#));
+        // A time bin beyond the end of the trace is marked at zero intensity.
+        assert!(markers.contains(r#""y":[10,0]"#));
+        assert!(markers.contains(r#""text":["alice: ringing starts here","end"]"#));
+
+        assert_eq!(trace_plotly.layout.matches(r#""type":"line""#).count(), 2);
+        assert!(trace_plotly.layout.contains(r#""x0":7"#));
+    }
+
+    /// Plots `trace` with `view`, returning the plot and the json of its trace.
+    fn plot_view(trace: &MuonTrace, view: TraceView) -> (TracePlotly, serde_json::Value) {
+        let trace_plotly = create_plotly(
+            &metadata(),
+            1,
+            trace,
+            Vec::new(),
+            &[],
+            &EventFilter::default(),
+            &view,
+        )
+        .unwrap();
+        let trace_data = serde_json::from_str(&trace_plotly.trace_data[0]).unwrap();
+        (trace_plotly, trace_data)
+    }
+
+    #[test]
+    fn single_sample_spike_survives_decimation() {
+        let mut trace = vec![100; 500_000];
+        trace[123_457] = 4000;
+        let (trace_plotly, trace_data) = plot_view(
+            &trace,
+            TraceView {
+                max_points: Some(1000),
+                range: None,
+            },
+        );
+        assert_eq!(trace_plotly.range, None);
+        assert_eq!(trace_plotly.decimation_factor, 1000);
+
+        // The spike is the maximum of its interval, so is kept at its own time bin.
+        let times = trace_data["x"].as_array().unwrap();
+        let intensities = trace_data["y"].as_array().unwrap();
+        assert!(times.len() <= 1000);
+        let spike = intensities.iter().position(|y| y == 4000).unwrap();
+        assert_eq!(times[spike], 123_457);
+        assert_eq!(intensities.iter().filter(|y| **y == 4000).count(), 1);
+    }
+
+    #[test]
+    fn narrow_range_is_fetched_at_full_resolution() {
+        let trace = (0..500_000)
+            .map(|time| (time % 1000) as Intensity)
+            .collect();
+        let (trace_plotly, trace_data) = plot_view(
+            &trace,
+            TraceView {
+                max_points: Some(1000),
+                range: Some(TimeRange {
+                    start: 1500,
+                    end: 1600,
+                }),
+            },
+        );
+        assert_eq!(
+            trace_plotly.range,
+            Some(TimeRange {
+                start: 1500,
+                end: 1600
+            })
+        );
+        assert_eq!(trace_plotly.decimation_factor, 1);
+        assert!(!trace_plotly.title.contains("Decimated"));
+        assert_eq!(
+            trace_data["x"],
+            serde_json::json!((1500..1600).collect::<Vec<_>>())
+        );
+        assert_eq!(
+            trace_data["y"],
+            serde_json::json!((500..600).collect::<Vec<_>>())
+        );
+        assert!(trace_plotly.layout.contains(r#""range":[1500,1600]"#));
+
+        // A range beyond the end of the trace is clamped to it.
+        let (trace_plotly, trace_data) = plot_view(
+            &trace,
+            TraceView {
+                max_points: Some(1000),
+                range: Some(TimeRange {
+                    start: 499_990,
+                    end: 600_000,
+                }),
+            },
+        );
+        assert_eq!(
+            trace_plotly.range,
+            Some(TimeRange {
+                start: 499_990,
+                end: 500_000
             })
+        );
+        assert_eq!(trace_data["x"].as_array().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn comparison_plot_emphasises_unmatched_events() {
+        let event = |time, intensity| Event { time, intensity };
+        let old = [event(10, 5), event(40, 6), event(70, 7)];
+        let new = [event(11, 8), event(70, 9), event(90, 10)];
+        let matching = EventMatching::new(&old, &new, 2);
+        let trace = vec![0; 100];
+        let trace_plotly = create_comparison_plotly(
+            &metadata(),
+            1,
+            &trace,
+            ("old", &old),
+            ("new", &new),
+            &matching,
+            2,
+            &TraceView::default(),
+        )
+        .unwrap();
+
+        let summary = "2 matched within 2 samples, 1 only in old, 1 only in new.";
+        assert_eq!(
+            trace_plotly.title,
+            format!("Channel 1 from Digitiser 4 ({summary})")
+        );
+        assert!(trace_plotly.layout.contains(summary));
+        assert_eq!(trace_plotly.trace_data.len(), 1);
+
+        // Both event lists are plotted in full, followed by the unmatched events of each.
+        let series = trace_plotly
+            .eventlist_data
+            .iter()
+            .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+            .collect::<Vec<_>>();
+        let names = series
+            .iter()
+            .map(|series| series["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Events: old", "Events: new", "Only in old", "Only in new"]
+        );
+        assert_eq!(series[0]["x"], serde_json::json!([10, 40, 70]));
+        assert_eq!(series[1]["x"], serde_json::json!([11, 70, 90]));
+        assert_eq!(series[2]["x"], serde_json::json!([40]));
+        assert_eq!(series[2]["y"], serde_json::json!([6]));
+        assert_eq!(series[3]["x"], serde_json::json!([90]));
+        assert_eq!(series[3]["y"], serde_json::json!([10]));
+        for unmatched in &series[2..] {
+            assert_eq!(unmatched["marker"]["size"], UNMATCHED_MARKER_SIZE);
         }
+        assert_ne!(series[2]["marker"]["symbol"], series[3]["marker"]["symbol"]);
+    }
+
+    #[test]
+    fn export_file_name_identifies_message() {
+        let metadata = DigitiserMetadata {
+            timestamp: DateTime::from_timestamp_millis(1_700_000_000_123).unwrap(),
+            frame_number: 17,
+            ..metadata()
+        };
+        assert_eq!(
+            export_file_name(&metadata, 3),
+            "digitiser_4_channel_3_frame_17_2023-11-14T22-13-20.123000000.html"
+        );
     }
 }
@@ -0,0 +1,102 @@
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+/// Returns the number of messages written by the most recent raw export of the session with the given [Uuid],
+/// see [export_session_raw_route], or [None] if none has started.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn get_raw_export_progress(uuid: String) -> Result<Option<usize>, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    Ok(session_engine.session(&uuid)?.raw_export_progress())
+}
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::{
+            sessions::{RawExportError, RawExportWriter},
+            structs::ServerSideData,
+        };
+        use actix_web::{HttpResponse, http::header::ContentDisposition, web::{self, Bytes}};
+        use futures::stream;
+        use tracing::warn;
+
+        /// The header of [export_session_raw_route] which gives the number of messages in the archive.
+        const ENTRY_COUNT_HEADER: &str = "X-Entry-Count";
+
+        /// Serves `GET /export-raw/{uuid}` with a zip archive of the trace messages of the session, as received,
+        /// and a `manifest.json` describing them, as a file to download.
+        ///
+        /// The archive is streamed a message at a time, and the session engine is only locked while each message is written,
+        /// so other requests are served during the export. Its progress is given by [get_raw_export_progress].
+        pub async fn export_session_raw_route(path: web::Path<String>, server_side_data: web::Data<ServerSideData>) -> HttpResponse {
+            let uuid = path.into_inner();
+            let session_engine_arc_mutex = server_side_data.session_engine.clone();
+
+            let (num_entries, eventlist_topics) = {
+                let mut session_engine = session_engine_arc_mutex.lock().await;
+                let topics = session_engine.settings().topics.digitiser_event_topic.clone();
+                let summary = session_engine.session_mut(&uuid).and_then(|session| {
+                    let summary = session.get_search_summaries()?;
+                    session.set_raw_export_progress(0);
+                    Ok(summary)
+                });
+                match summary {
+                    Ok(summary) => (
+                        summary.num_results,
+                        summary
+                            .eventlist_topic_indices
+                            .into_iter()
+                            .filter_map(|index| topics.get(index).cloned())
+                            .collect::<Vec<_>>(),
+                    ),
+                    Err(e) => {
+                        warn!("Cannot export session: {e}");
+                        return HttpResponse::NotFound().body(e.to_string());
+                    }
+                }
+            };
+
+            let file_name = format!("session_{uuid}.zip");
+            let writer = RawExportWriter::new(uuid.clone(), eventlist_topics);
+            let body = stream::unfold(Some((writer, 0)), move |state| {
+                let session_engine_arc_mutex = session_engine_arc_mutex.clone();
+                let uuid = uuid.clone();
+                async move {
+                    let (mut writer, index) = state?;
+                    if index == num_entries {
+                        return Some((writer.finish().map(Bytes::from), None));
+                    }
+                    let mut session_engine = session_engine_arc_mutex.lock().await;
+                    let chunk = session_engine
+                        .session_mut(&uuid)
+                        .map_err(RawExportError::from)
+                        .and_then(|session| {
+                            let (metadata, channels, payload) = session.get_raw_trace(index)?;
+                            let chunk = writer.add_entry(index, metadata, channels, payload)?;
+                            session.set_raw_export_progress(index + 1);
+                            Ok(chunk)
+                        });
+                    match chunk {
+                        Ok(chunk) => Some((Ok(Bytes::from(chunk)), Some((writer, index + 1)))),
+                        Err(e) => {
+                            warn!("Cannot export message {index} of session {uuid}: {e}");
+                            Some((Err(e), None))
+                        }
+                    }
+                }
+            });
+
+            HttpResponse::Ok()
+                .content_type("application/zip")
+                .insert_header(ContentDisposition::attachment(file_name))
+                .insert_header((ENTRY_COUNT_HEADER, num_entries.to_string()))
+                .streaming(body)
+        }
+    }
+}
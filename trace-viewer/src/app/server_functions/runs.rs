@@ -0,0 +1,41 @@
+use crate::structs::RunInfo;
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::ServerSideData;
+        use tracing::debug;
+    }
+}
+
+/// Returns the runs recorded on the control topic, ordered by start time.
+///
+/// The control topic is only scanned if it has not been already, or if `refresh` is set,
+/// otherwise the runs found by the last scan are returned. The runs are kept, so that the results
+/// of subsequent searches are annotated with them. Returns an empty list if no control topic is set.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn get_runs(refresh: bool) -> Result<Vec<RunInfo>, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let run_scan = {
+        let session_engine = session_engine_arc_mutex.lock().await;
+        if !refresh && let Some(runs) = session_engine.runs() {
+            return Ok(runs.to_vec());
+        }
+        session_engine.run_scan()
+    };
+    let Some(run_scan) = run_scan else {
+        return Ok(Vec::new());
+    };
+
+    // The lock is not held whilst the control topic is scanned, so other requests are not blocked.
+    let runs = run_scan.scan().await?;
+    debug!("Found {} run(s).", runs.len());
+    session_engine_arc_mutex.lock().await.set_runs(runs.clone());
+    Ok(runs)
+}
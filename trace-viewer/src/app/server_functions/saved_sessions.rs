@@ -0,0 +1,65 @@
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use tracing::instrument;
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::ServerSideData;
+        use tracing::debug;
+    }
+}
+
+/// Saves the results of the session with the given [Uuid] to disk under `name`.
+/// Returns an error if a saved session called `name` already exists, unless `overwrite` is set.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn save_session(
+    uuid: String,
+    name: String,
+    overwrite: bool,
+) -> Result<String, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let write = session_engine_arc_mutex
+        .lock()
+        .await
+        .save_session(&uuid, &name, overwrite)?;
+    // The lock is not held whilst the session is written, so other requests are not blocked.
+    write.persist().await?;
+    debug!("Session {uuid} saved as {name}.");
+    Ok(name)
+}
+
+/// Creates a new session from the saved session `name` and returns its [Uuid].
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn load_session(name: String) -> Result<String, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let read = session_engine_arc_mutex.lock().await.load_session(&name)?;
+    // The lock is not held whilst the session is read, so other requests are not blocked.
+    let saved = read.read().await?;
+    let uuid = session_engine_arc_mutex
+        .lock()
+        .await
+        .insert_saved_session(saved)?;
+    debug!("Saved session {name} loaded with uuid: {uuid}");
+    Ok(uuid)
+}
+
+/// Returns the names of all saved sessions.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn list_saved_sessions() -> Result<Vec<String>, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    Ok(session_engine.list_saved_sessions()?)
+}
@@ -1,4 +1,6 @@
-use crate::structs::{SearchSummary, SearchTarget};
+use crate::structs::{
+    ResultsPage, SearchProgress, SearchSummary, SearchTarget, SortResultsBy, TraceStatistics,
+};
 use cfg_if::cfg_if;
 use leptos::prelude::*;
 use tracing::instrument;
@@ -6,7 +8,7 @@ use tracing::instrument;
 cfg_if! {
     if #[cfg(feature = "ssr")] {
         use crate::structs::{SearchResults, ServerSideData};
-        use tracing::{debug, error};
+        use tracing::debug;
     }
 }
 
@@ -33,9 +35,12 @@ pub async fn create_new_search(
     Ok(uuid)
 }
 
-/// Sends the one-shop cancel message to the [Session] with the given [Uuid].
-/// Returns an error if no such session exists.
+/// Cancels the search of the [Session] with the given [Uuid].
+/// The search stops before the next message it receives, and its results are those found so far,
+/// which are registered by [await_search] as for a completed search.
+/// Returns an error if no such session exists, or its search has already been cancelled.
 #[server]
+#[instrument(skip_all, err(level = "warn"))]
 pub async fn cancel_search(uuid: String) -> Result<(), ServerFnError> {
     // The mutex should be in scope to apply a lock.
     let session_engine_arc_mutex = use_context::<ServerSideData>()
@@ -48,20 +53,31 @@ pub async fn cancel_search(uuid: String) -> Result<(), ServerFnError> {
     Ok(())
 }
 
-/// Takes ownership of the search body of the [Session] with the given [Uuid],
-/// and waits for it's [JoinHandle] field to complete, or is cancelled.
-/// If it completes then it registers the results with the original [Session].
+/// Fetches the progress of the search of the [Session] with the given [Uuid], which the client polls whilst awaiting it.
 /// Returns an error if no such session exists.
 #[server]
 #[instrument(skip_all, err(level = "warn"))]
+pub async fn get_search_progress(uuid: String) -> Result<SearchProgress, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    Ok(session_engine.session(&uuid)?.search_progress())
+}
+
+/// Takes ownership of the search body of the [Session] with the given [Uuid],
+/// and waits for its [JoinHandle] field to complete, including when it is cancelled by [cancel_search].
+/// It then registers the results with the original [Session].
+/// Returns an error if no such session exists, or its search is already being awaited.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
 pub async fn await_search(uuid: String) -> Result<String, ServerFnError> {
     use crate::sessions::SessionSearchBody;
 
     // Obtain SessionSearchBody without locking SessionEngine for too long.
-    let SessionSearchBody {
-        handle,
-        cancel_recv,
-    } = {
+    let SessionSearchBody { handle } = {
         let session_engine_arc_mutex = use_context::<ServerSideData>()
             .expect("ServerSideData should be provided, this should never fail.")
             .session_engine;
@@ -73,35 +89,31 @@ pub async fn await_search(uuid: String) -> Result<String, ServerFnError> {
     };
 
     // Run Future
-    tokio::select! {
-        results = handle => {
-            let results = results
-                .inspect(|_| debug!("Successfully found results."))
-                .or_else(|e| if e.is_cancelled() { Ok(Ok(SearchResults::Cancelled)) } else { Err(e) })??;
-
-            // Register results with SessionEngine and return results.
-            let session_engine_arc_mutex = use_context::<ServerSideData>()
-                .expect("ServerSideData should be provided, this should never fail.")
-                .session_engine;
-
-            let mut session_engine = session_engine_arc_mutex
-                .lock()
-                .await;
-
-            session_engine.session_mut(&uuid)?
-                .register_results(results);
-        }
-        result = cancel_recv => {
-            if let Err(e) = result {
-                error!("{}",e);
+    let results = handle
+        .await
+        .inspect(|_| debug!("Successfully found results."))
+        .or_else(|e| {
+            if e.is_cancelled() {
+                Ok(Ok(SearchResults::Cancelled))
+            } else {
+                Err(e)
             }
-        }
-    }
+        })??;
+
+    // Register results with SessionEngine and return results.
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let mut session_engine = session_engine_arc_mutex.lock().await;
+
+    session_engine.session_mut(&uuid)?.register_results(results);
 
     Ok(uuid)
 }
 
-/// Fetches the list of summaries of messages in the cache of the session with the given [Uuid].
+/// Fetches the summary of the search of the session with the given [Uuid].
+/// The messages found are fetched separately, by [get_results_page].
 /// Returns an error if no such session exists.
 #[server]
 #[instrument(skip_all, err(level = "warn"))]
@@ -116,3 +128,43 @@ pub async fn fetch_search_summaries(uuid: String) -> Result<SearchSummary, Serve
 
     Ok(session.get_search_summaries()?)
 }
+
+/// Fetches summaries of at most `limit` messages in the cache of the session with the given [Uuid],
+/// starting at position `offset` of the results list ordered by `sort_key`.
+/// Each message is annotated with its run, if the control topic has been scanned by [get_runs].
+/// Returns an error if no such session exists.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn get_results_page(
+    uuid: String,
+    offset: usize,
+    limit: usize,
+    sort_key: SortResultsBy,
+) -> Result<ResultsPage, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    let session = session_engine.session(&uuid)?;
+
+    Ok(session.get_results_page(offset, limit, sort_key, session_engine.runs())?)
+}
+
+/// Fetches the statistics of each message in the cache of the session with the given [Uuid].
+/// The statistics are computed when first requested, and are in the same order as the result indices.
+/// Returns an error if no such session exists.
+#[server]
+#[instrument(skip_all, err(level = "warn"))]
+pub async fn fetch_trace_statistics(uuid: String) -> Result<Vec<TraceStatistics>, ServerFnError> {
+    let session_engine_arc_mutex = use_context::<ServerSideData>()
+        .expect("ServerSideData should be provided, this should never fail.")
+        .session_engine;
+
+    let session_engine = session_engine_arc_mutex.lock().await;
+
+    let session = session_engine.session(&uuid)?;
+
+    Ok(session.get_trace_statistics()?)
+}
@@ -2,7 +2,9 @@
 //!
 //! This module is only included in the server build.
 mod search_engine;
+mod search_monitor;
 mod task;
 mod topic_searcher;
 
 pub(crate) use search_engine::{SearchEngine, SearchEngineError};
+pub(crate) use search_monitor::SearchMonitor;
@@ -1,7 +1,8 @@
 use crate::{
     finder::{
+        SearchMonitor,
         task::{BinarySearchByTimestamp, Dragnet, SearchTask},
-        topic_searcher::{Searcher, SearcherError},
+        topic_searcher::SearcherError,
     },
     structs::{
         BrokerInfo, BrokerTopicInfo, EventListMessage, FBMessage, SearchResults, SearchTarget,
@@ -10,12 +11,14 @@ use crate::{
 };
 use chrono::Utc;
 use rdkafka::{
+    Offset, TopicPartitionList,
     consumer::{Consumer, StreamConsumer},
     error::KafkaError,
     util::Timeout,
 };
 use std::time::Duration;
 use thiserror::Error;
+use tokio::time::timeout;
 use tracing::{debug, instrument};
 
 #[derive(Error, Debug)]
@@ -55,9 +58,10 @@ impl SearchEngine {
         topic: &str,
         poll_broker_timeout_ms: u64,
     ) -> Result<BrokerTopicInfo, SearchEngineError> {
+        let partition = 0;
         let offsets = consumer.fetch_watermarks(
             topic,
-            0,
+            partition,
             Timeout::After(Duration::from_millis(poll_broker_timeout_ms)),
         )?;
         debug!("Topic {topic}: (High, Low) offsets: {offsets:?}");
@@ -68,9 +72,9 @@ impl SearchEngine {
                 timestamps: None,
             })
         } else {
-            let mut searcher = Searcher::<M, StreamConsumer>::new(consumer, topic, offsets.0)?;
-            let begin = searcher.message(offsets.0).await?;
-            let end = searcher.message(offsets.1 - 1).await?;
+            let begin = Self::fetch_message_at::<M>(consumer, topic, partition, offsets.0).await?;
+            let end =
+                Self::fetch_message_at::<M>(consumer, topic, partition, offsets.1 - 1).await?;
 
             Ok(BrokerTopicInfo {
                 offsets,
@@ -79,6 +83,25 @@ impl SearchEngine {
         }
     }
 
+    /// Assigns the consumer to the given offset of the topic's partition, and receives the message there.
+    async fn fetch_message_at<'a, M: FBMessage<'a>>(
+        consumer: &'a StreamConsumer,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> Result<M, SearchEngineError> {
+        const MESSAGE_TIMEOUT: Duration = Duration::from_millis(5000);
+
+        let mut tpl = TopicPartitionList::with_capacity(1);
+        tpl.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+        consumer.assign(&tpl)?;
+
+        let msg = timeout(MESSAGE_TIMEOUT, consumer.recv())
+            .await
+            .map_err(|_| SearcherError::BrokerTimeout)??;
+        Ok(M::try_from(msg).map_err(SearcherError::from)?)
+    }
+
     #[instrument(skip_all)]
     pub(crate) async fn poll_broker(
         &self,
@@ -111,10 +134,12 @@ impl SearchEngine {
         })
     }
 
+    /// Searches the broker for `target`, recording its progress to `monitor`, which also cancels it.
     #[instrument(skip_all)]
     pub(crate) async fn search(
         &mut self,
         target: SearchTarget,
+        monitor: &SearchMonitor,
     ) -> Result<SearchResults, SearchEngineError> {
         Ok(match target.mode {
             SearchTargetMode::Timestamp { timestamp } => {
@@ -122,6 +147,7 @@ impl SearchEngine {
                     &self.consumer,
                     &self.topics,
                     self.events_topic_indices.clone(),
+                    monitor,
                 )
                 .search(timestamp, target.by, target.number)
                 .await?
@@ -130,16 +156,19 @@ impl SearchEngine {
                 timestamp,
                 backstep,
                 forward_distance,
+                until,
             } => {
                 SearchTask::<Dragnet>::new(
                     &self.consumer,
                     &self.topics,
                     self.events_topic_indices.clone(),
+                    monitor,
                 )
                 .search(
                     timestamp,
                     backstep,
                     forward_distance,
+                    until,
                     target.by,
                     target.number,
                 )
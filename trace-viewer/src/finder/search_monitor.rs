@@ -0,0 +1,139 @@
+//! Shares the progress of a search with the session which started it, and allows the session to cancel it.
+use crate::{Timestamp, structs::SearchProgress};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Default)]
+struct MonitorState {
+    progress: SearchProgress,
+    /// True once the search has been asked to stop.
+    cancel_requested: bool,
+    /// The number of messages the search expects to receive, from which its fraction complete is estimated.
+    expected_steps: usize,
+    /// The number of messages the search has tried to receive, including those which could not be received.
+    steps: usize,
+}
+
+/// Held by both the task which runs a search and the session which started it.
+///
+/// The task records its progress here, and checks between messages whether the session has cancelled it.
+#[derive(Clone, Default)]
+pub(crate) struct SearchMonitor {
+    state: Arc<Mutex<MonitorState>>,
+}
+
+impl SearchMonitor {
+    fn lock(&self) -> MutexGuard<'_, MonitorState> {
+        self.state
+            .lock()
+            .expect("The state is never locked across a panic, this should never fail.")
+    }
+
+    /// Returns a copy of the search's progress.
+    pub(crate) fn progress(&self) -> SearchProgress {
+        self.lock().progress.clone()
+    }
+
+    /// Asks the search to stop at its next message, and returns false if this has already been asked.
+    pub(crate) fn cancel(&self) -> bool {
+        let mut state = self.lock();
+        if state.cancel_requested {
+            return false;
+        }
+        state.cancel_requested = true;
+        if !state.progress.finished {
+            state.progress.cancelled = true;
+        }
+        true
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.lock().cancel_requested
+    }
+
+    /// Sets the number of messages the search expects to try to receive, from which its fraction complete is estimated.
+    pub(crate) fn expect_messages(&self, expected: usize) {
+        self.lock().expected_steps = expected;
+    }
+
+    /// Records an attempt to receive a message.
+    ///
+    /// # Parameters
+    /// - message: the timestamp of the message received, and whether it matches the search, or [None] if none was received.
+    pub(crate) fn record_message(&self, message: Option<(Timestamp, bool)>) {
+        let mut state = self.lock();
+        state.steps += 1;
+        if let Some((timestamp, matched)) = message {
+            state.progress.messages_scanned += 1;
+            state.progress.position = Some(timestamp);
+            if matched {
+                state.progress.matches_found += 1;
+            }
+        }
+        if state.expected_steps > 0 {
+            state.progress.fraction_complete =
+                (state.steps as f64 / state.expected_steps as f64).min(1.0);
+        }
+    }
+
+    /// Records that the search has stopped, after which its progress no longer changes.
+    pub(crate) fn finish(&self) {
+        let mut state = self.lock();
+        if !state.progress.cancelled {
+            state.progress.fraction_complete = 1.0;
+        }
+        state.progress.finished = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn fraction_complete_is_estimated_from_expected_messages() {
+        let monitor = SearchMonitor::default();
+        monitor.expect_messages(4);
+        let timestamp = DateTime::from_timestamp_millis(20).unwrap();
+        monitor.record_message(Some((timestamp, true)));
+        monitor.record_message(None);
+        monitor.record_message(Some((timestamp, false)));
+        assert_eq!(
+            monitor.progress(),
+            SearchProgress {
+                messages_scanned: 2,
+                matches_found: 1,
+                position: Some(timestamp),
+                fraction_complete: 0.75,
+                cancelled: false,
+                finished: false,
+            }
+        );
+
+        // A search may stop before receiving every message it expects, such as when no traces are found.
+        monitor.finish();
+        let progress = monitor.progress();
+        assert_eq!(progress.fraction_complete, 1.0);
+        assert!(progress.finished);
+
+        // Cancelling a finished search does not change its progress.
+        assert!(monitor.cancel());
+        assert!(!monitor.progress().cancelled);
+    }
+
+    #[test]
+    fn cancelled_searches_are_not_complete() {
+        let monitor = SearchMonitor::default();
+        monitor.expect_messages(4);
+        monitor.record_message(None);
+        assert!(!monitor.is_cancelled());
+        assert!(monitor.cancel());
+        assert!(!monitor.cancel());
+        assert!(monitor.is_cancelled());
+
+        monitor.finish();
+        let progress = monitor.progress();
+        assert_eq!(progress.fraction_complete, 0.25);
+        assert!(progress.cancelled && progress.finished);
+    }
+}
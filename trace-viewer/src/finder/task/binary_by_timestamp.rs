@@ -6,7 +6,7 @@ use crate::{
     },
     structs::{Cache, EventListMessage, FBMessage, SearchResults, SearchTargetBy, TraceMessage},
 };
-use rdkafka::consumer::StreamConsumer;
+use rdkafka::{Message, consumer::StreamConsumer};
 use tracing::{info, instrument};
 
 /// Size of each backstep when a target timestamp has been found
@@ -37,6 +37,10 @@ impl<'a> SearchTask<'a, BinarySearchByTimestamp> {
         }
         info!("Beginning Binary Search.");
         loop {
+            if self.monitor.is_cancelled() {
+                info!("Search cancelled.");
+                return None;
+            }
             if iter
                 .bisect()
                 .await
@@ -100,6 +104,9 @@ impl<'a> SearchTask<'a, BinarySearchByTimestamp> {
                     &trace
                         .try_unpacked_message()
                         .expect("Cannot Unpack Trace. TODO should be handled"),
+                    trace
+                        .payload()
+                        .expect("Unpacked trace has a payload, this should never fail."),
                 )?;
             }
 
@@ -6,7 +6,7 @@ use crate::{
     },
     structs::{Cache, EventListMessage, FBMessage, SearchResults, SearchTargetBy, TraceMessage},
 };
-use rdkafka::consumer::StreamConsumer;
+use rdkafka::{Message, consumer::StreamConsumer};
 use tracing::{info, instrument};
 pub(crate) struct Dragnet;
 impl TaskClass for Dragnet {}
@@ -35,6 +35,10 @@ impl<'a> SearchTask<'a, Dragnet> {
         }
         info!("Beginning Binary Search.");
         loop {
+            if self.monitor.is_cancelled() {
+                info!("Search cancelled.");
+                return None;
+            }
             if iter
                 .bisect()
                 .await
@@ -50,7 +54,7 @@ impl<'a> SearchTask<'a, Dragnet> {
         info!("Beginning Dragnet Search.");
         let mut iter = searcher.iter_dragnet(number);
         iter.backstep_by(backstep)
-            .acquire_matches(forward_distance, acquire_matches)
+            .acquire_matches(forward_distance, acquire_matches, self.monitor)
             .await;
         let (searcher, timestamps) = iter.collect();
         let results: Vec<M> = searcher.into();
@@ -59,8 +63,13 @@ impl<'a> SearchTask<'a, Dragnet> {
     }
 
     /// Performs a binary tree search.
+    ///
+    /// At most `forward_distance` messages are scanned on the trace topic, and on each event list topic,
+    /// from which the fraction of the search complete is estimated. If the search is cancelled,
+    /// its results are the traces found before it was, and any event lists found for them.
     /// # Parameters
     /// - target: what to search for.
+    /// - until: if present, traces timestamped after this are not matched.
     /// - by:
     #[instrument(skip_all)]
     pub(crate) async fn search(
@@ -68,9 +77,13 @@ impl<'a> SearchTask<'a, Dragnet> {
         target_timestamp: Timestamp,
         backstep: i64,
         forward_distance: usize,
+        until: Option<Timestamp>,
         search_by: SearchTargetBy,
         number: usize,
     ) -> Result<SearchResults, SearcherError> {
+        self.monitor
+            .expect_messages(forward_distance * (1 + self.events_topic_indices.len()));
+
         // Find Digitiser Traces
         let searcher = Searcher::new(self.consumer, &self.topics.trace_topic, 1)?;
 
@@ -81,7 +94,9 @@ impl<'a> SearchTask<'a, Dragnet> {
                 backstep,
                 forward_distance,
                 number,
-                |msg: &TraceMessage| msg.filter_by(&search_by),
+                |msg: &TraceMessage| {
+                    msg.filter_by(&search_by) && until.is_none_or(|until| msg.timestamp() <= until)
+                },
             )
             .await;
 
@@ -94,10 +109,16 @@ impl<'a> SearchTask<'a, Dragnet> {
                     &trace
                         .try_unpacked_message()
                         .expect("Cannot Unpack Trace. TODO should be handled"),
+                    trace
+                        .payload()
+                        .expect("Unpacked trace has a payload, this should never fail."),
                 )?;
             }
 
             for &index in self.events_topic_indices.iter() {
+                if self.monitor.is_cancelled() {
+                    break;
+                }
                 let event_topic = self
                     .topics
                     .digitiser_event_topic
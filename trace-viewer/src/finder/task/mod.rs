@@ -4,6 +4,7 @@ mod dragnet;
 
 use crate::{
     DigitizerId,
+    finder::SearchMonitor,
     structs::{FBMessage, Topics, TraceMessage},
 };
 use rdkafka::consumer::StreamConsumer;
@@ -19,6 +20,8 @@ pub(crate) struct SearchTask<'a, C: TaskClass> {
     consumer: &'a StreamConsumer,
     topics: &'a Topics,
     events_topic_indices: Vec<usize>,
+    /// Records the progress of the search, and whether it is cancelled.
+    monitor: &'a SearchMonitor,
     phantom: PhantomData<C>,
 }
 
@@ -27,11 +30,13 @@ impl<'a, C: TaskClass> SearchTask<'a, C> {
         consumer: &'a StreamConsumer,
         topics: &'a Topics,
         events_topic_indices: Vec<usize>,
+        monitor: &'a SearchMonitor,
     ) -> Self {
         Self {
             consumer,
             topics,
             events_topic_indices,
+            monitor,
             phantom: PhantomData,
         }
     }
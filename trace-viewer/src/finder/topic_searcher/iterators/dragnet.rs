@@ -1,6 +1,10 @@
-use crate::{Timestamp, finder::topic_searcher::Searcher, structs::FBMessage};
+use crate::{
+    Timestamp,
+    finder::{SearchMonitor, topic_searcher::Searcher},
+    structs::FBMessage,
+};
 use rdkafka::consumer::StreamConsumer;
-use tracing::{debug, instrument, warn};
+use tracing::{debug, instrument};
 
 /// Performs a dragnet search on the broker from the searcher's offset.
 ///
@@ -35,36 +39,245 @@ where
     }
 
     /// Steps forward, message by message, ignoring timestamp order, acquiring messages which satisfy the predicate,
-    /// until the given number of messages have been tested.
+    /// until the given number of messages have been tested, or the search is cancelled, see [acquire_matches_from].
     ///
     /// # Parameters
     /// - f: a predicte taking a timestamp, it should return true if a message satisfies the matching criteria.
+    /// - monitor: records the progress of the search, and whether it is cancelled.
     #[instrument(skip_all)]
     pub(crate) async fn acquire_matches<F: Fn(&M) -> bool>(
         &mut self,
         message_num: usize,
         f: F,
+        monitor: &SearchMonitor,
     ) -> &mut Self {
-        for _ in 0..message_num {
-            if let Some(msg) = self
-                .inner
-                .recv()
-                .await
-                .map(TryFrom::try_from)
-                .and_then(Result::ok)
-            {
-                if f(&msg) {
-                    if self.timestamps.contains(&msg.timestamp()) {
-                        debug!("Message with existing timestamp found");
-                        self.inner.results.push(msg);
-                    } else if self.timestamps.len() < self.timestamps.capacity() {
-                        debug!("Message with new timestamp found");
-                        self.timestamps.push(msg.timestamp());
-                        self.inner.results.push(msg);
-                    }
-                }
+        let matches =
+            acquire_matches_from(&self.inner, message_num, &mut self.timestamps, f, monitor).await;
+        self.inner.results.extend(matches);
+        self
+    }
+}
+
+/// A source from which a dragnet search receives messages one at a time, see [acquire_matches_from].
+pub(crate) trait MessageSource {
+    type Message;
+
+    /// Receives the next message, or returns [None] if none could be received.
+    async fn next_message(&self) -> Option<Self::Message>;
+
+    fn timestamp(message: &Self::Message) -> Timestamp;
+}
+
+impl<'a, M> MessageSource for Searcher<'a, M, StreamConsumer>
+where
+    M: FBMessage<'a>,
+{
+    type Message = M;
+
+    async fn next_message(&self) -> Option<M> {
+        self.recv()
+            .await
+            .map(TryFrom::try_from)
+            .and_then(Result::ok)
+    }
+
+    fn timestamp(message: &M) -> Timestamp {
+        message.timestamp()
+    }
+}
+
+/// Tries to receive `message_num` messages from `source`, returning those which satisfy the predicate `f`,
+/// and whose timestamps are either in `timestamps`, or there is capacity in `timestamps` to add.
+///
+/// The progress of the search is recorded to `monitor` after each message, and if it is cancelled,
+/// no more messages are received, so the messages returned are those acquired before it was.
+pub(crate) async fn acquire_matches_from<S, F>(
+    source: &S,
+    message_num: usize,
+    timestamps: &mut Vec<Timestamp>,
+    f: F,
+    monitor: &SearchMonitor,
+) -> Vec<S::Message>
+where
+    S: MessageSource,
+    F: Fn(&S::Message) -> bool,
+{
+    let mut matches = Vec::new();
+    for _ in 0..message_num {
+        if monitor.is_cancelled() {
+            debug!("Search cancelled");
+            break;
+        }
+        let Some(msg) = source.next_message().await else {
+            monitor.record_message(None);
+            continue;
+        };
+        let timestamp = S::timestamp(&msg);
+        let mut matched = false;
+        if f(&msg) {
+            if timestamps.contains(&timestamp) {
+                debug!("Message with existing timestamp found");
+                matched = true;
+            } else if timestamps.len() < timestamps.capacity() {
+                debug!("Message with new timestamp found");
+                timestamps.push(timestamp);
+                matched = true;
             }
         }
-        self
+        monitor.record_message(Some((timestamp, matched)));
+        if matched {
+            matches.push(msg);
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta};
+    use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+    /// A message of [SlowSource], which matches the search if its value is even.
+    type MockMessage = (Timestamp, u32);
+
+    /// Yields its messages in order, waiting before each, as a broker might.
+    struct SlowSource {
+        messages: Mutex<VecDeque<MockMessage>>,
+        delay: Duration,
+    }
+
+    impl SlowSource {
+        /// Creates a source of `num` messages, 20 ms apart, whose values count up from zero.
+        fn new(num: u32, delay: Duration) -> Self {
+            let start = DateTime::from_timestamp_millis(0).unwrap();
+            Self {
+                messages: Mutex::new(
+                    (0..num)
+                        .map(|value| (start + TimeDelta::milliseconds(20 * value as i64), value))
+                        .collect(),
+                ),
+                delay,
+            }
+        }
+    }
+
+    impl MessageSource for SlowSource {
+        type Message = MockMessage;
+
+        async fn next_message(&self) -> Option<MockMessage> {
+            tokio::time::sleep(self.delay).await;
+            self.messages.lock().unwrap().pop_front()
+        }
+
+        fn timestamp(message: &MockMessage) -> Timestamp {
+            message.0
+        }
+    }
+
+    /// Spawns a search of `source`, for at most `number` even messages, and returns its handle.
+    fn spawn_search(
+        source: SlowSource,
+        message_num: usize,
+        number: usize,
+        monitor: &SearchMonitor,
+    ) -> tokio::task::JoinHandle<(Vec<MockMessage>, Vec<Timestamp>)> {
+        let monitor = monitor.clone();
+        monitor.expect_messages(message_num);
+        tokio::task::spawn(async move {
+            let mut timestamps = Vec::with_capacity(number);
+            let matches = acquire_matches_from(
+                &source,
+                message_num,
+                &mut timestamps,
+                |(_, value): &MockMessage| value % 2 == 0,
+                &monitor,
+            )
+            .await;
+            monitor.finish();
+            (matches, timestamps)
+        })
+    }
+
+    #[tokio::test]
+    async fn progress_is_monotonic() {
+        let monitor = SearchMonitor::default();
+        // The source runs out before the search, so the last messages cannot be received.
+        let handle = spawn_search(
+            SlowSource::new(12, Duration::from_millis(2)),
+            16,
+            16,
+            &monitor,
+        );
+
+        let mut snapshots = vec![monitor.progress()];
+        while !snapshots.last().unwrap().finished {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            snapshots.push(monitor.progress());
+        }
+        let (matches, timestamps) = handle.await.unwrap();
+
+        for pair in snapshots.windows(2) {
+            let (before, after) = (&pair[0], &pair[1]);
+            assert!(before.messages_scanned <= after.messages_scanned);
+            assert!(before.matches_found <= after.matches_found);
+            assert!(before.position <= after.position);
+            assert!(before.fraction_complete <= after.fraction_complete);
+        }
+        // Some snapshots were taken whilst the search was running.
+        assert!(
+            snapshots.iter().any(|progress| {
+                progress.messages_scanned > 0 && progress.fraction_complete < 1.0
+            })
+        );
+
+        let progress = monitor.progress();
+        assert_eq!(progress.messages_scanned, 12);
+        assert_eq!(progress.matches_found, 6);
+        assert_eq!(progress.fraction_complete, 1.0);
+        assert!(!progress.cancelled);
+        assert_eq!(matches.len(), 6);
+        assert_eq!(timestamps.len(), 6);
+        assert_eq!(
+            progress.position,
+            Some(matches[5].0 + TimeDelta::milliseconds(20))
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_search_keeps_consistent_results() {
+        let monitor = SearchMonitor::default();
+        let handle = spawn_search(
+            SlowSource::new(1000, Duration::from_millis(5)),
+            1000,
+            1000,
+            &monitor,
+        );
+        while monitor.progress().messages_scanned < 5 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(monitor.cancel());
+        let (matches, timestamps) = handle.await.unwrap();
+
+        let progress = monitor.progress();
+        assert!(progress.cancelled && progress.finished);
+        assert!(progress.messages_scanned < 1000);
+        assert!(progress.fraction_complete < 1.0);
+
+        // The results are exactly the matches of the messages scanned before the search stopped.
+        assert_eq!(matches.len(), progress.matches_found);
+        assert_eq!(matches.len(), progress.messages_scanned.div_ceil(2));
+        assert!(matches.iter().all(|(_, value)| value % 2 == 0));
+        assert_eq!(
+            timestamps,
+            matches
+                .iter()
+                .map(|(timestamp, _)| *timestamp)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            progress.position,
+            DateTime::from_timestamp_millis(20 * (progress.messages_scanned as i64 - 1))
+        );
     }
 }
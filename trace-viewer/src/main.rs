@@ -4,9 +4,9 @@ use leptos::prelude::*;
 cfg_if! {
     if #[cfg(feature = "ssr")] {
         use clap::Parser;
-        use std::net::SocketAddr;
+        use std::{net::SocketAddr, path::PathBuf};
         use digital_muon_common::CommonKafkaOpts;
-        use trace_viewer::{structs::{ClientSideData, DefaultData, ServerSideData, Topics}, sessions::{SessionEngineSettings}, shell};
+        use trace_viewer::{structs::{ClientSideData, DefaultData, KeyboardShortcut, ServerSideData, Topics}, sessions::{SessionEngineSettings}, shell};
         use tracing::info;
         use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt};
         use url::Url;
@@ -59,10 +59,44 @@ cfg_if! {
             #[clap(long, default_value = "300")]
             refresh_session_interval_sec: u64,
 
-            /// Specifies the time-to-live of a user session. Any session whose time since last refresh is older than this is removed during a session purge cycle.
+            /// Specifies the time-to-live of a user session. Any session whose time since creation or last refresh is older than this is removed during a session purge cycle.
             #[clap(long, default_value = "600")]
             session_ttl_sec: i64,
 
+            /// Directory in which saved sessions are stored. This is created when a session is first saved.
+            #[clap(long, default_value = "saved_sessions")]
+            saved_sessions_dir: PathBuf,
+
+            /// File in which annotations of traces are stored. This is created when an annotation is first added.
+            #[clap(long, default_value = "annotations.json")]
+            annotations_file: PathBuf,
+
+            /// The number of most recent trace messages of each digitiser kept by a live tail.
+            #[clap(long, default_value = "16")]
+            live_tail_capacity: usize,
+
+            /// The most points of a trace included in a plot exported as html, longer traces are decimated to this many points. Should be at least 2.
+            #[clap(long, default_value = "100000")]
+            export_max_points: usize,
+
+            /// If set, new searches are rejected whilst the results of all sessions hold an estimated this many MiB,
+            /// and during a session purge cycle, the least recently refreshed sessions are removed until they hold no more than this.
+            #[clap(long)]
+            session_memory_cap_mib: Option<usize>,
+
+            /// The most plots of channels kept by the plot cache, which is shared by every session. If zero, plots are not cached.
+            #[clap(long, default_value = "256")]
+            plot_cache_max_entries: usize,
+
+            /// The most MiB of plots kept by the plot cache. The least recently used plots are removed to keep within this and `plot-cache-max-entries`.
+            #[clap(long, default_value = "64")]
+            plot_cache_max_mib: usize,
+
+            /// The keyboard shortcut, of the form `<MODIFIER>+<KEY>`, which exports the plot of the selected channel.
+            /// The modifier is one of `ctrl`, `alt`, `shift` or `meta`.
+            #[clap(long, default_value = "alt+e")]
+            export_shortcut: KeyboardShortcut,
+
             /// Name to apply to this particular instance.
             #[clap(long)]
             name: Option<String>,
@@ -73,7 +107,7 @@ cfg_if! {
             use actix_files::Files;
             use leptos_actix::{generate_route_list, LeptosRoutes};
             use miette::IntoDiagnostic;
-            use trace_viewer::{App, sessions::SessionEngine};
+            use trace_viewer::{App, app::{export_plot_html_route, export_session_raw_route}, sessions::SessionEngine};
 
             // set up logging
             console_error_panic_hook::set_once();
@@ -102,6 +136,13 @@ cfg_if! {
                 password: args.common_kafka_options.password.clone(),
                 consumer_group: args.consumer_group.clone(),
                 session_ttl_sec: args.session_ttl_sec,
+                saved_sessions_dir: args.saved_sessions_dir,
+                annotations_file: args.annotations_file,
+                live_tail_capacity: args.live_tail_capacity,
+                export_max_points: args.export_max_points,
+                memory_cap_bytes: args.session_memory_cap_mib.map(|mib| mib * 1024 * 1024),
+                plot_cache_max_entries: args.plot_cache_max_entries,
+                plot_cache_max_bytes: args.plot_cache_max_mib * 1024 * 1024,
             });
 
             let server_side_data = ServerSideData {
@@ -115,6 +156,7 @@ cfg_if! {
                 refresh_session_interval_sec: args.refresh_session_interval_sec,
                 public_url: args.public_url,
                 eventlist_topics: args.topics.digitiser_event_topic.clone(),
+                export_shortcut: args.export_shortcut,
             };
 
             // Spawn the "purge expired sessions" task.
@@ -138,6 +180,8 @@ cfg_if! {
                 info!("listening on http://{}", &addr);
                 actix_web::App::new()
                     .service(Files::new("/pkg", format!("{site_root}/pkg")))
+                    .route("/export/{uuid}/{index}/{channel}", actix_web::web::get().to(export_plot_html_route))
+                    .route("/export-raw/{uuid}", actix_web::web::get().to(export_session_raw_route))
                     .leptos_routes_with_context(routes, {
                         let server_side_data = server_side_data.clone();
                         let client_side_data = client_side_data.clone();
@@ -150,6 +194,7 @@ cfg_if! {
                         move ||shell(leptos_options.clone())
                     })
                     .app_data(actix_web::web::Data::new(leptos_options.to_owned()))
+                    .app_data(actix_web::web::Data::new(server_side_data.clone()))
             })
             .bind(&addr)
             .into_diagnostic()?
@@ -0,0 +1,231 @@
+//! Stores the annotations users attach to traces on disk, so they outlive the sessions in which they were made.
+//!
+//! Annotations are keyed by the metadata of the trace message and the channel, so they are found again
+//! whenever the same trace is viewed, by any session.
+//! The file is read once, when the [SessionEngine] starts, after which annotations are served from memory.
+//! Each change returns an [AnnotationsWrite], which replaces the file once the engine has been unlocked.
+//!
+//! [SessionEngine]: crate::sessions::SessionEngine
+use crate::{
+    Channel, Time, Timestamp,
+    app::SessionError,
+    structs::{Annotation, AnnotationId, DigitiserMetadata},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+/// An annotation, with the trace to which it is attached.
+#[derive(Serialize, Deserialize)]
+struct StoredAnnotation {
+    metadata: DigitiserMetadata,
+    channel: Channel,
+    annotation: Annotation,
+}
+
+/// The contents of the annotations file.
+#[derive(Default, Serialize, Deserialize)]
+struct AnnotationFile {
+    /// The id given to the next annotation added, ids are never reused.
+    next_id: AnnotationId,
+    annotations: Vec<StoredAnnotation>,
+}
+
+fn io_error(error: std::io::Error) -> SessionError {
+    SessionError::AnnotationIo(error.to_string())
+}
+
+/// Reads the annotations file at `path`. If it does not exist yet, no annotations have been added.
+fn read(path: &Path) -> Result<AnnotationFile, SessionError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(AnnotationFile::default()),
+        Err(e) => return Err(io_error(e)),
+    };
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| SessionError::AnnotationFormat(e.to_string()))
+}
+
+/// Replaces the annotations file at `path` with the serialised `contents`.
+///
+/// The contents are written to a temporary file which is then renamed, so the file is never left partially written.
+fn write(path: &Path, contents: &[u8]) -> Result<(), SessionError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path).map_err(io_error)?);
+    writer.write_all(contents).map_err(io_error)?;
+    writer.flush().map_err(io_error)?;
+    fs::rename(&temp_path, path).map_err(io_error)
+}
+
+/// The annotations of every trace, read from the annotations file once, and held in memory thereafter.
+pub(crate) struct Annotations {
+    path: PathBuf,
+    /// The annotations read from the file, or the error which prevented them from being read.
+    /// If the file could not be read, it is never replaced, so its annotations are not lost.
+    contents: Result<AnnotationFile, SessionError>,
+    /// Incremented by every change to [Self::contents].
+    version: u64,
+    /// The version most recently written to the file, shared by every [AnnotationsWrite].
+    written: Arc<Mutex<u64>>,
+}
+
+impl Default for Annotations {
+    fn default() -> Self {
+        Self {
+            path: Default::default(),
+            contents: Ok(Default::default()),
+            version: 0,
+            written: Default::default(),
+        }
+    }
+}
+
+impl Annotations {
+    /// Reads the annotations file at `path`. If it cannot be read, the error is logged, and returned by every later request.
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let contents = read(&path);
+        if let Err(e) = &contents {
+            error!("Cannot read annotations file {}: {e}", path.display());
+        }
+        Self {
+            path,
+            contents,
+            version: 0,
+            written: Default::default(),
+        }
+    }
+
+    /// Returns the number of changes made since the file was read, which identifies the current annotations.
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn contents(&self) -> Result<&AnnotationFile, SessionError> {
+        self.contents.as_ref().map_err(Clone::clone)
+    }
+
+    /// Returns the write which replaces the file with the current annotations.
+    fn changed(&mut self) -> Result<AnnotationsWrite, SessionError> {
+        self.version += 1;
+        let contents = serde_json::to_vec(self.contents()?)
+            .map_err(|e| SessionError::AnnotationFormat(e.to_string()))?;
+        Ok(AnnotationsWrite {
+            path: self.path.clone(),
+            version: self.version,
+            contents,
+            written: self.written.clone(),
+        })
+    }
+
+    /// Attaches a comment on `time_bin` to `channel` of the message with `metadata`,
+    /// and returns the new annotation, with the write which records it.
+    pub(crate) fn add(
+        &mut self,
+        metadata: &DigitiserMetadata,
+        channel: Channel,
+        time_bin: Time,
+        text: &str,
+        author: &str,
+        created: Timestamp,
+    ) -> Result<(Annotation, AnnotationsWrite), SessionError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(SessionError::EmptyAnnotation);
+        }
+
+        let contents = self.contents.as_mut().map_err(|e| e.clone())?;
+        let annotation = Annotation {
+            id: contents.next_id,
+            time_bin,
+            text: text.to_owned(),
+            author: author.trim().to_owned(),
+            created,
+        };
+        contents.next_id += 1;
+        contents.annotations.push(StoredAnnotation {
+            metadata: metadata.clone(),
+            channel,
+            annotation: annotation.clone(),
+        });
+        info!(
+            "Added annotation {} to channel {channel} of digitiser {} in frame {}",
+            annotation.id, metadata.id, metadata.frame_number
+        );
+        Ok((annotation, self.changed()?))
+    }
+
+    /// Returns the annotations of `channel` of the message with `metadata`, in order of time bin.
+    pub(crate) fn get(
+        &self,
+        metadata: &DigitiserMetadata,
+        channel: Channel,
+    ) -> Result<Vec<Annotation>, SessionError> {
+        let mut annotations = self
+            .contents()?
+            .annotations
+            .iter()
+            .filter(|stored| stored.channel == channel && stored.metadata == *metadata)
+            .map(|stored| stored.annotation.clone())
+            .collect::<Vec<_>>();
+        annotations.sort_by_key(|annotation| (annotation.time_bin, annotation.id));
+        Ok(annotations)
+    }
+
+    /// Removes the annotation with the given `id`, from whichever trace it is attached to,
+    /// and returns the write which records its removal.
+    pub(crate) fn delete(&mut self, id: AnnotationId) -> Result<AnnotationsWrite, SessionError> {
+        let contents = self.contents.as_mut().map_err(|e| e.clone())?;
+        let num_annotations = contents.annotations.len();
+        contents
+            .annotations
+            .retain(|stored| stored.annotation.id != id);
+        if contents.annotations.len() == num_annotations {
+            return Err(SessionError::AnnotationNotFound(id));
+        }
+        info!("Deleted annotation {id}");
+        self.changed()
+    }
+}
+
+/// A snapshot of the annotations, to be written to the annotations file once the [SessionEngine] has been unlocked.
+///
+/// [SessionEngine]: crate::sessions::SessionEngine
+#[must_use = "the annotations file is only replaced once the write is persisted"]
+pub struct AnnotationsWrite {
+    path: PathBuf,
+    version: u64,
+    contents: Vec<u8>,
+    written: Arc<Mutex<u64>>,
+}
+
+impl AnnotationsWrite {
+    /// Replaces the annotations file with the snapshot, unless a later snapshot has already been written.
+    ///
+    /// If this fails, the change is still held in memory, and is written with the next change to succeed.
+    pub async fn persist(self) -> Result<(), SessionError> {
+        let Self {
+            path,
+            version,
+            contents,
+            written,
+        } = self;
+        let mut written = written.lock().await;
+        if *written >= version {
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || write(&path, &contents))
+            .await
+            .map_err(|e| SessionError::AnnotationIo(e.to_string()))??;
+        *written = version;
+        Ok(())
+    }
+}
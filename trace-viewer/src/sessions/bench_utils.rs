@@ -0,0 +1,110 @@
+//! Builds synthetic sessions for the benchmarks, and exposes the queries they measure.
+use crate::{
+    Channel, DigitizerId, FrameNumber,
+    sessions::{
+        saved_session::SavedSession,
+        session::{Session, trace_summary},
+    },
+    structs::{
+        Cache, DigitiserMetadata, DigitiserTrace, ResultsPage, SearchTarget, SearchTargetBy,
+        SearchTargetMode, SortResultsBy, TraceSummary,
+    },
+};
+use chrono::{DateTime, TimeDelta};
+use std::{borrow::Cow, collections::HashMap};
+
+/// The channels of each digitiser message.
+const CHANNELS: [Channel; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+/// A session whose search has completed, holding messages of several digitisers in consecutive frames.
+///
+/// A copy of the session's cache is kept, so each query can be compared against a linear scan of its messages,
+/// as was done before the cache was indexed.
+pub struct SyntheticSession {
+    session: Session,
+    cache: Cache,
+}
+
+impl SyntheticSession {
+    /// Creates a session of `num_messages` messages, each of one of `num_digitisers` digitisers,
+    /// with every digitiser sending a message per frame, 20ms apart.
+    pub fn new(num_messages: usize, num_digitisers: DigitizerId) -> Self {
+        let target = SearchTarget {
+            mode: SearchTargetMode::Timestamp {
+                timestamp: DateTime::UNIX_EPOCH,
+            },
+            by: SearchTargetBy::All,
+            number: num_messages,
+        };
+        let traces = (0..num_messages)
+            .map(|message| {
+                let frame_number = (message / num_digitisers as usize) as FrameNumber;
+                let metadata = DigitiserMetadata {
+                    timestamp: DateTime::UNIX_EPOCH
+                        + TimeDelta::milliseconds(20 * frame_number as i64),
+                    id: (message % num_digitisers as usize) as DigitizerId,
+                    frame_number,
+                    period_number: 0,
+                    protons_per_pulse: 0,
+                    running: true,
+                    veto_flags: 0,
+                };
+                let trace = DigitiserTrace {
+                    traces: CHANNELS
+                        .into_iter()
+                        .map(|channel| (channel, vec![0, 100, 0]))
+                        .collect(),
+                    events: HashMap::new(),
+                };
+                (metadata, trace)
+            })
+            .collect::<Vec<_>>();
+        let cache = Cache::from_traces(traces.clone(), []);
+        let saved = SavedSession {
+            target: Cow::Owned(target),
+            eventlist_topic_indices: Vec::new(),
+            traces: traces
+                .into_iter()
+                .map(|(metadata, trace)| (Cow::Owned(metadata), Cow::Owned(trace)))
+                .collect(),
+        };
+        Self {
+            session: Session::from_saved(saved, 600),
+            cache,
+        }
+    }
+
+    /// Returns the frame number of the message at position `index` of the results list, as when it is selected.
+    pub fn select(&self, index: usize) -> Option<FrameNumber> {
+        self.session
+            .get_metadata(index, CHANNELS[0])
+            .ok()
+            .map(|metadata| metadata.frame_number)
+    }
+
+    /// As [Self::select], but walks the messages in order to find that at position `index`.
+    pub fn select_by_scan(&self, index: usize) -> Option<FrameNumber> {
+        self.cache
+            .iter_channels()
+            .nth(index)
+            .map(|(metadata, _)| metadata.frame_number)
+    }
+
+    /// Returns the page of at most `limit` messages, starting at position `offset` of the results list, in order of timestamp.
+    pub fn page(&self, offset: usize, limit: usize) -> Option<ResultsPage> {
+        self.session
+            .get_results_page(offset, limit, SortResultsBy::Timestamp, None)
+            .ok()
+    }
+
+    /// As [Self::page], but walks the messages in order to find those on the page.
+    pub fn page_by_scan(&self, offset: usize, limit: usize) -> Vec<TraceSummary> {
+        self.cache
+            .iter_channels()
+            .enumerate()
+            .skip(offset)
+            .take(limit)
+            .map(|(index, (metadata, channels))| trace_summary(index, metadata, channels.to_vec()))
+            .collect()
+    }
+}
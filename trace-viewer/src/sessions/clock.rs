@@ -0,0 +1,30 @@
+//! The clock by which sessions are aged, which tests can set, so that expiry can be tested without waiting.
+use crate::Timestamp;
+use chrono::Utc;
+
+#[cfg(test)]
+thread_local! {
+    /// If set, the time returned by [now] on this thread.
+    static MOCK_NOW: std::cell::Cell<Option<Timestamp>> = const { std::cell::Cell::new(None) };
+}
+
+/// Returns the current time.
+pub(crate) fn now() -> Timestamp {
+    #[cfg(test)]
+    let mock = MOCK_NOW.get();
+    #[cfg(not(test))]
+    let mock = None;
+    mock.unwrap_or_else(Utc::now)
+}
+
+/// Sets the time returned by [now] on this thread.
+#[cfg(test)]
+pub(crate) fn set_mock_now(now: Timestamp) {
+    MOCK_NOW.set(Some(now));
+}
+
+/// Advances the time returned by [now] on this thread, which should already be set by [set_mock_now].
+#[cfg(test)]
+pub(crate) fn advance_mock_now(delta: chrono::TimeDelta) {
+    MOCK_NOW.set(MOCK_NOW.get().map(|now| now + delta));
+}
@@ -0,0 +1,349 @@
+//! Follows the trace topic in near real time, so the most recent traces can be viewed without running a search.
+use crate::{
+    DigitizerId, Timestamp,
+    app::SessionError,
+    sessions::{clock, session::trace_summary},
+    structs::{
+        BorrowedMessageError, DigitiserMetadata, DigitiserTrace, FBMessage, FromMessage,
+        TraceMessage, TraceSummary,
+    },
+};
+use chrono::TimeDelta;
+use digital_muon_common::seek::ResolveOffsets;
+use digital_muon_streaming_types::FrameMetadata;
+use rdkafka::{
+    Offset, TopicPartitionList,
+    consumer::{Consumer, StreamConsumer},
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+/// How long the live tail waits before receiving again after the first of consecutive errors.
+const RECV_ERROR_BACKOFF_INITIAL: Duration = Duration::from_millis(100);
+/// The longest the live tail waits before receiving again, however many consecutive errors there are.
+const RECV_ERROR_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// Holds the most recent trace messages of each digitiser, up to a fixed number per digitiser.
+pub(crate) struct LatestTraces {
+    capacity: usize,
+    digitisers: BTreeMap<DigitizerId, VecDeque<(DigitiserMetadata, DigitiserTrace)>>,
+}
+
+impl LatestTraces {
+    /// Creates an empty buffer.
+    /// # Parameters
+    /// - capacity: the number of messages kept for each digitiser, this is at least one.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            digitisers: Default::default(),
+        }
+    }
+
+    /// Appends a message to those of its digitiser, evicting the oldest if the digitiser's buffer is full.
+    pub(crate) fn push(&mut self, metadata: DigitiserMetadata, trace: DigitiserTrace) {
+        let messages = self.digitisers.entry(metadata.id).or_default();
+        if messages.len() == self.capacity {
+            messages.pop_front();
+        }
+        messages.push_back((metadata, trace));
+    }
+
+    /// Returns the messages of the given digitiser, oldest first.
+    #[cfg(test)]
+    pub(crate) fn messages(
+        &self,
+        id: DigitizerId,
+    ) -> impl Iterator<Item = &(DigitiserMetadata, DigitiserTrace)> {
+        self.digitisers.get(&id).into_iter().flatten()
+    }
+
+    /// Returns the most recent message of each digitiser, ordered by digitiser id.
+    pub(crate) fn latest(&self) -> impl Iterator<Item = &(DigitiserMetadata, DigitiserTrace)> {
+        self.digitisers.values().filter_map(VecDeque::back)
+    }
+
+    /// Returns the most recent message of the given digitiser.
+    pub(crate) fn latest_of(
+        &self,
+        id: DigitizerId,
+    ) -> Option<&(DigitiserMetadata, DigitiserTrace)> {
+        self.digitisers.get(&id).and_then(VecDeque::back)
+    }
+}
+
+/// The settings needed to start a live tail, copied from the session engine,
+/// so that its consumer can be created without holding the engine's lock, see [SessionEngine::live_tail_start].
+///
+/// [SessionEngine::live_tail_start]: super::SessionEngine::live_tail_start
+pub(crate) struct LiveTailStart {
+    pub(crate) broker: String,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) timeout: Duration,
+    pub(crate) capacity: usize,
+    pub(crate) session_ttl_sec: i64,
+}
+
+impl LiveTailStart {
+    /// Creates a consumer assigned to the end of every partition of the trace topic, and starts following it.
+    ///
+    /// The live tail has its own consumer group, so does not disturb the offsets of searches.
+    #[instrument(skip_all, fields(topic = %self.topic))]
+    pub(crate) fn start(self) -> Result<LiveTail, SessionError> {
+        let consumer = digital_muon_common::create_default_consumer(
+            &self.broker,
+            &self.username,
+            &self.password,
+            &format!("{}-live-{}", self.consumer_group, Uuid::new_v4()),
+            None,
+        )?;
+
+        let mut offsets = TopicPartitionList::new();
+        for partition in consumer.fetch_partitions(&self.topic, self.timeout)? {
+            offsets.add_partition_offset(&self.topic, partition, Offset::End)?;
+        }
+        consumer.assign(&offsets)?;
+
+        Ok(LiveTail::follow(
+            consumer,
+            self.capacity,
+            self.session_ttl_sec,
+        ))
+    }
+}
+
+/// A background task which follows the trace topic, and the buffer of the messages it has received.
+///
+/// The task is aborted when this is dropped, so stopping or purging the live tail ends the task.
+/// It expires as a [Session] does, once it has not been refreshed for the session time-to-live.
+///
+/// [Session]: super::session::Session
+pub(crate) struct LiveTail {
+    latest: Arc<Mutex<LatestTraces>>,
+    handle: JoinHandle<()>,
+    last_used: Timestamp,
+    session_ttl: TimeDelta,
+}
+
+impl LiveTail {
+    /// Spawns the task which fills the buffer.
+    /// # Parameters
+    /// - capacity: the number of messages kept for each digitiser.
+    /// - session_ttl_sec: the time after the last refresh when the live tail expires.
+    /// - task: given the buffer, returns the future which fills it.
+    fn spawn<F, Fut>(capacity: usize, session_ttl_sec: i64, task: F) -> Self
+    where
+        F: FnOnce(Arc<Mutex<LatestTraces>>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let latest = Arc::new(Mutex::new(LatestTraces::new(capacity)));
+        Self {
+            handle: tokio::task::spawn(task(latest.clone())),
+            latest,
+            last_used: clock::now(),
+            session_ttl: TimeDelta::seconds(session_ttl_sec),
+        }
+    }
+
+    /// Spawns a task which pushes every trace message received by `consumer` into the buffer.
+    /// The consumer should already be assigned to the trace topic.
+    pub(crate) fn follow(consumer: StreamConsumer, capacity: usize, session_ttl_sec: i64) -> Self {
+        Self::spawn(capacity, session_ttl_sec, move |latest| {
+            follow_trace_topic(consumer, latest)
+        })
+    }
+
+    /// Returns a summary of the most recent message of each digitiser, ordered by digitiser id.
+    /// The `index` of each summary is its position in the list.
+    pub(crate) fn get_latest_traces(&self) -> Vec<TraceSummary> {
+        lock(&self.latest)
+            .latest()
+            .enumerate()
+            .map(|(index, (metadata, trace))| {
+                trace_summary(index, metadata, trace.traces.keys().copied().collect())
+            })
+            .collect()
+    }
+
+    /// Returns a copy of the most recent message of the given digitiser.
+    pub(crate) fn get_latest_trace(
+        &self,
+        id: DigitizerId,
+    ) -> Result<(DigitiserMetadata, DigitiserTrace), SessionError> {
+        lock(&self.latest)
+            .latest_of(id)
+            .cloned()
+            .ok_or(SessionError::TraceNotFound)
+    }
+
+    pub(crate) fn expired(&self, now: Timestamp) -> bool {
+        self.last_used + self.session_ttl < now
+    }
+
+    pub(crate) fn refresh(&mut self) {
+        self.last_used = clock::now()
+    }
+}
+
+#[cfg(test)]
+impl LiveTail {
+    /// Creates a live tail whose task does nothing until it is aborted,
+    /// and a receiver which completes when it is.
+    pub(crate) fn pending(session_ttl_sec: i64) -> (Self, tokio::sync::oneshot::Receiver<()>) {
+        let (aborted_send, aborted_recv) = tokio::sync::oneshot::channel::<()>();
+        let live_tail = Self::spawn(1, session_ttl_sec, move |_| async move {
+            // The sender is only dropped, completing the receiver, when the task is aborted.
+            let _aborted_send = aborted_send;
+            std::future::pending::<()>().await
+        });
+        (live_tail, aborted_recv)
+    }
+}
+
+impl Drop for LiveTail {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn lock(latest: &Mutex<LatestTraces>) -> MutexGuard<'_, LatestTraces> {
+    latest
+        .lock()
+        .expect("The buffer is never locked across a panic, this should never fail.")
+}
+
+fn unpack_trace<'a>(
+    message: &'a TraceMessage<'a>,
+) -> Result<(DigitiserMetadata, DigitiserTrace), BorrowedMessageError> {
+    let trace = message.try_unpacked_message()?;
+    let frame_metadata: FrameMetadata = trace.metadata().try_into()?;
+    Ok((
+        DigitiserMetadata::new(trace.digitizer_id(), &frame_metadata),
+        DigitiserTrace::from_message(&trace),
+    ))
+}
+
+/// Pushes every trace message received by `consumer` into `latest`.
+///
+/// After an error, the consumer is not polled again until a backoff has elapsed, which doubles with each consecutive error,
+/// from [RECV_ERROR_BACKOFF_INITIAL] up to [RECV_ERROR_BACKOFF_MAX], so an unreachable broker is not polled continually.
+#[instrument(skip_all)]
+async fn follow_trace_topic(consumer: StreamConsumer, latest: Arc<Mutex<LatestTraces>>) {
+    let mut backoff = RECV_ERROR_BACKOFF_INITIAL;
+    loop {
+        match consumer.recv().await {
+            Ok(message) => {
+                backoff = RECV_ERROR_BACKOFF_INITIAL;
+                match TraceMessage::try_from(message).and_then(|message| unpack_trace(&message)) {
+                    Ok((metadata, trace)) => {
+                        lock(&latest).push(metadata, trace);
+                    }
+                    Err(e) => warn!("Cannot unpack trace message: {e}"),
+                }
+            }
+            Err(e) => {
+                warn!("{e}, retrying in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RECV_ERROR_BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::collections::HashMap;
+
+    fn message(frame_number: u32, id: DigitizerId) -> (DigitiserMetadata, DigitiserTrace) {
+        (
+            DigitiserMetadata {
+                timestamp: DateTime::from_timestamp_millis(20 * frame_number as i64).unwrap(),
+                id,
+                frame_number,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                veto_flags: 0,
+            },
+            DigitiserTrace {
+                traces: HashMap::from([(id as u32, vec![0, 1, 0])]),
+                events: Default::default(),
+            },
+        )
+    }
+
+    fn frame_numbers<'a>(
+        messages: impl Iterator<Item = &'a (DigitiserMetadata, DigitiserTrace)>,
+    ) -> Vec<u32> {
+        messages
+            .map(|(metadata, _)| metadata.frame_number)
+            .collect()
+    }
+
+    #[test]
+    fn oldest_messages_are_evicted() {
+        let mut latest = LatestTraces::new(3);
+        for frame_number in 0..5 {
+            for id in [2, 1] {
+                let (metadata, trace) = message(frame_number, id);
+                latest.push(metadata, trace);
+            }
+        }
+        assert_eq!(frame_numbers(latest.messages(1)), [2, 3, 4]);
+        assert_eq!(frame_numbers(latest.messages(2)), [2, 3, 4]);
+        assert_eq!(latest.messages(3).count(), 0);
+
+        // The latest messages are ordered by digitiser id.
+        let ids = latest
+            .latest()
+            .map(|(metadata, _)| metadata.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(frame_numbers(latest.latest()), [4, 4]);
+    }
+
+    #[test]
+    fn each_digitiser_is_evicted_independently() {
+        let mut latest = LatestTraces::new(2);
+        for frame_number in 0..4 {
+            let (metadata, trace) = message(frame_number, 1);
+            latest.push(metadata, trace);
+        }
+        let (metadata, trace) = message(7, 2);
+        latest.push(metadata, trace);
+
+        assert_eq!(frame_numbers(latest.messages(1)), [2, 3]);
+        assert_eq!(frame_numbers(latest.messages(2)), [7]);
+        assert_eq!(latest.latest_of(1).unwrap().0.frame_number, 3);
+        assert!(latest.latest_of(3).is_none());
+    }
+
+    #[test]
+    fn capacity_is_at_least_one() {
+        let mut latest = LatestTraces::new(0);
+        for frame_number in 0..3 {
+            let (metadata, trace) = message(frame_number, 1);
+            latest.push(metadata, trace);
+        }
+        assert_eq!(frame_numbers(latest.messages(1)), [2]);
+    }
+
+    #[tokio::test]
+    async fn task_is_aborted_when_dropped() {
+        let (live_tail, dropped_recv) = LiveTail::pending(600);
+        tokio::task::yield_now().await;
+        drop(live_tail);
+        assert!(dropped_recv.await.is_err());
+    }
+}
@@ -1,7 +1,18 @@
 //! These structs implement the session engine, which processes requests
 //! from the [crate::app::server_functions] module.
+mod annotations;
+#[cfg(feature = "bench-utils")]
+pub mod bench_utils;
+mod clock;
+mod live_tail;
+mod plot_cache;
+mod raw_export;
+mod runs;
+mod saved_session;
 mod session;
 mod session_engine;
 
+pub use annotations::AnnotationsWrite;
+pub(crate) use raw_export::{RawExportError, RawExportWriter};
 pub use session::SessionSearchBody;
 pub use session_engine::{SessionEngine, SessionEngineSettings};
@@ -0,0 +1,312 @@
+//! Caches the plots of channels of trace messages, so that a channel plotted again, by any session, is not regenerated.
+use crate::{
+    Channel, DigitizerId,
+    structs::{DigitiserMetadata, EventFilter, PlotCacheStatus, TracePlotly, TraceView},
+};
+use std::{
+    collections::{HashMap, HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+};
+
+/// Returns the hash of `value`, which is stable whilst the server runs.
+fn hash_of(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies a plot by the message and channel plotted, and everything else which determines it.
+///
+/// A message is identified by its metadata rather than a session, so the same plot is shared by every session which found the message.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct PlotKey {
+    digitiser_id: DigitizerId,
+    /// The hash of the message's metadata, which identifies its frame.
+    metadata_hash: u64,
+    channel: Channel,
+    /// The names of the eventlist topics whose events are plotted.
+    eventlist_topics: Vec<String>,
+    /// The range and decimation of the trace plotted.
+    view: TraceView,
+    event_filter: EventFilter,
+    /// The version of the annotations, so a plot is not reused once any are added or deleted.
+    annotations_version: u64,
+}
+
+impl PlotKey {
+    pub(crate) fn new(
+        metadata: &DigitiserMetadata,
+        channel: Channel,
+        eventlist_topics: Vec<String>,
+        annotations_version: u64,
+        event_filter: &EventFilter,
+        view: &TraceView,
+    ) -> Self {
+        Self {
+            digitiser_id: metadata.id,
+            metadata_hash: hash_of(metadata),
+            channel,
+            eventlist_topics,
+            view: *view,
+            event_filter: event_filter.clone(),
+            annotations_version,
+        }
+    }
+}
+
+/// Returns the bytes of the json strings of `plot`, which dominate the memory it holds.
+fn plot_size_bytes(plot: &TracePlotly) -> usize {
+    plot.title.len()
+        + plot.layout.len()
+        + plot
+            .trace_data
+            .iter()
+            .chain(&plot.eventlist_data)
+            .map(String::len)
+            .sum::<usize>()
+}
+
+struct CachedPlot {
+    plot: TracePlotly,
+    size_bytes: usize,
+    /// The value of [PlotCache::ticks] when the plot was last inserted or fetched.
+    last_used: u64,
+    /// The keys of the sessions which have inserted or fetched the plot.
+    sessions: HashSet<String>,
+}
+
+/// A least recently used cache of plots, bounded by both its number of plots and their total bytes.
+/// A bound of zero disables the cache.
+#[derive(Default)]
+pub(crate) struct PlotCache {
+    max_entries: usize,
+    max_bytes: usize,
+    plots: HashMap<PlotKey, CachedPlot>,
+    /// The total bytes of the cached plots, see [plot_size_bytes].
+    size_bytes: usize,
+    /// Counts the insertions and fetches, so the least recently used plot is that with the lowest [CachedPlot::last_used].
+    ticks: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl PlotCache {
+    pub(crate) fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Returns a copy of the plot with `key`, if cached, and records its use by the session with key `session`.
+    /// Each call is counted as either a hit or a miss.
+    pub(crate) fn get(&mut self, session: &str, key: &PlotKey) -> Option<TracePlotly> {
+        self.ticks += 1;
+        match self.plots.get_mut(key) {
+            Some(cached) => {
+                self.hits += 1;
+                cached.last_used = self.ticks;
+                cached.sessions.insert(session.to_owned());
+                Some(cached.plot.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches `plot` under `key` for the session with key `session`,
+    /// and then removes the least recently used plots until the cache is within its bounds.
+    /// A plot larger than the byte bound is not cached.
+    pub(crate) fn insert(&mut self, session: &str, key: PlotKey, plot: TracePlotly) {
+        let size_bytes = plot_size_bytes(&plot);
+        if self.max_entries == 0 || size_bytes > self.max_bytes {
+            return;
+        }
+        self.ticks += 1;
+        let cached = CachedPlot {
+            plot,
+            size_bytes,
+            last_used: self.ticks,
+            sessions: HashSet::from([session.to_owned()]),
+        };
+        self.size_bytes += size_bytes;
+        if let Some(replaced) = self.plots.insert(key, cached) {
+            self.size_bytes -= replaced.size_bytes;
+        }
+        self.evict_least_recently_used();
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        while self.plots.len() > self.max_entries || self.size_bytes > self.max_bytes {
+            let Some(key) = self
+                .plots
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &PlotKey) {
+        if let Some(removed) = self.plots.remove(key) {
+            self.size_bytes -= removed.size_bytes;
+        }
+    }
+
+    /// Forgets the session with key `session`, which has been removed, and removes the plots no other session has used.
+    pub(crate) fn release_session(&mut self, session: &str) {
+        let unused = self
+            .plots
+            .iter_mut()
+            .filter_map(|(key, cached)| {
+                cached.sessions.remove(session);
+                cached.sessions.is_empty().then(|| key.clone())
+            })
+            .collect::<Vec<_>>();
+        for key in unused {
+            self.remove(&key);
+        }
+    }
+
+    pub(crate) fn status(&self) -> PlotCacheStatus {
+        PlotCacheStatus {
+            entries: self.plots.len(),
+            size_bytes: self.size_bytes,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn metadata() -> DigitiserMetadata {
+        DigitiserMetadata {
+            timestamp: DateTime::from_timestamp_millis(0).unwrap(),
+            id: 4,
+            frame_number: 3,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            veto_flags: 0,
+        }
+    }
+
+    fn key(max_points: Option<usize>) -> PlotKey {
+        PlotKey::new(
+            &metadata(),
+            1,
+            vec!["daq".to_owned()],
+            0,
+            &EventFilter::default(),
+            &TraceView::whole_trace(max_points),
+        )
+    }
+
+    /// Returns a plot whose json strings hold `size_bytes` bytes.
+    fn plot(size_bytes: usize) -> TracePlotly {
+        TracePlotly {
+            title: "x".repeat(size_bytes),
+            trace_data: Vec::new(),
+            eventlist_data: Vec::new(),
+            layout: String::new(),
+            range: None,
+            decimation_factor: 1,
+        }
+    }
+
+    #[test]
+    fn identical_request_is_a_hit() {
+        let mut cache = PlotCache::new(8, 1000);
+        assert!(cache.get("a", &key(Some(100))).is_none());
+        cache.insert("a", key(Some(100)), plot(10));
+
+        // The plot is shared by other sessions.
+        let cached = cache.get("b", &key(Some(100))).unwrap();
+        assert_eq!(cached.title, plot(10).title);
+        let status = cache.status();
+        assert_eq!((status.hits, status.misses), (1, 1));
+        assert_eq!((status.entries, status.size_bytes), (1, 10));
+    }
+
+    #[test]
+    fn differing_decimation_is_a_miss() {
+        let mut cache = PlotCache::new(8, 1000);
+        cache.insert("a", key(Some(100)), plot(10));
+        assert!(cache.get("a", &key(Some(200))).is_none());
+        assert!(cache.get("a", &key(None)).is_none());
+        assert_eq!(cache.status().misses, 2);
+
+        let annotated = PlotKey::new(
+            &metadata(),
+            1,
+            vec!["daq".to_owned()],
+            1,
+            &EventFilter::default(),
+            &TraceView::whole_trace(Some(100)),
+        );
+        assert!(cache.get("a", &annotated).is_none());
+    }
+
+    #[test]
+    fn eviction_respects_byte_bound() {
+        let mut cache = PlotCache::new(8, 100);
+        cache.insert("a", key(Some(1)), plot(40));
+        cache.insert("a", key(Some(2)), plot(40));
+        // Using the first plot makes the second the least recently used.
+        cache.get("a", &key(Some(1))).unwrap();
+        cache.insert("a", key(Some(3)), plot(40));
+
+        let status = cache.status();
+        assert_eq!((status.entries, status.size_bytes), (2, 80));
+        assert!(cache.get("a", &key(Some(1))).is_some());
+        assert!(cache.get("a", &key(Some(2))).is_none());
+        assert!(cache.get("a", &key(Some(3))).is_some());
+
+        // A plot larger than the bound is never cached.
+        cache.insert("a", key(Some(4)), plot(101));
+        assert!(cache.get("a", &key(Some(4))).is_none());
+        assert_eq!(cache.status().size_bytes, 80);
+    }
+
+    #[test]
+    fn eviction_respects_entry_bound() {
+        let mut cache = PlotCache::new(2, 1000);
+        for max_points in 1..=3 {
+            cache.insert("a", key(Some(max_points)), plot(10));
+        }
+        assert_eq!(cache.status().entries, 2);
+        assert!(cache.get("a", &key(Some(1))).is_none());
+
+        let mut disabled = PlotCache::default();
+        disabled.insert("a", key(Some(1)), plot(10));
+        assert_eq!(disabled.status().entries, 0);
+    }
+
+    #[test]
+    fn plots_are_removed_with_their_last_session() {
+        let mut cache = PlotCache::new(8, 1000);
+        cache.insert("a", key(Some(1)), plot(10));
+        cache.insert("a", key(Some(2)), plot(10));
+        cache.get("b", &key(Some(2))).unwrap();
+
+        cache.release_session("a");
+        assert_eq!(cache.status().entries, 1);
+        assert!(cache.get("b", &key(Some(2))).is_some());
+
+        cache.release_session("b");
+        let status = cache.status();
+        assert_eq!((status.entries, status.size_bytes), (0, 0));
+    }
+}
@@ -0,0 +1,267 @@
+//! Writes the trace messages of a session, as received, to a zip archive, one file per message.
+//!
+//! The archive is written to a stream, so each file can be sent as soon as it is written,
+//! and no more than one message is held by the archive at once.
+use crate::{
+    Channel, DigitizerId, FrameNumber, Timestamp, app::SessionError, structs::DigitiserMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, io::Write, mem, rc::Rc};
+use thiserror::Error;
+use zip::{
+    CompressionMethod, ZipWriter,
+    result::ZipError,
+    write::{SimpleFileOptions, StreamWriter},
+};
+
+/// The name of the file of the archive which describes the other files.
+pub(crate) const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Debug, Error)]
+pub(crate) enum RawExportError {
+    #[error("Session Error: {0}")]
+    Session(#[from] SessionError),
+    #[error("Zip Error: {0}")]
+    Zip(#[from] ZipError),
+    #[error("IO Error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Json Error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Describes a file of the archive, which holds the bytes of one trace message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    /// The name of the file in the archive.
+    pub(crate) file: String,
+    /// The position of the message in the session's results list.
+    pub(crate) index: usize,
+    pub(crate) digitiser_id: DigitizerId,
+    pub(crate) frame_number: FrameNumber,
+    pub(crate) period_number: u64,
+    pub(crate) timestamp: Timestamp,
+    /// The channels of the message, in the order they appear in it.
+    pub(crate) channels: Vec<Channel>,
+    /// The length of the file.
+    pub(crate) bytes: usize,
+}
+
+/// The contents of [MANIFEST_FILE_NAME], which is the last file of the archive.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RawExportManifest {
+    /// The session whose results were exported.
+    pub(crate) session: String,
+    /// The event list topics whose event lists were joined to the traces when searching.
+    /// Event lists are not included in the raw payloads.
+    pub(crate) eventlist_topics: Vec<String>,
+    /// Each file of the archive, other than the manifest, in order of index.
+    pub(crate) entries: Vec<ManifestEntry>,
+    /// The indices of messages without a raw payload, such as those of a saved session, which are not in the archive.
+    pub(crate) omitted: Vec<usize>,
+}
+
+/// Returns the name of the file holding the message with the given metadata.
+pub(crate) fn entry_file_name(metadata: &DigitiserMetadata) -> String {
+    format!(
+        "digitiser_{}_frame_{}_{}.bin",
+        metadata.id,
+        metadata.frame_number,
+        metadata.timestamp.format("%Y-%m-%dT%H-%M-%S%.9f")
+    )
+}
+
+/// The bytes of the archive which have been written but not yet taken by [RawExportWriter].
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a zip archive a file at a time, returning the bytes written by each.
+///
+/// Concatenating the bytes returned by each call to [Self::add_entry], then by [Self::finish], gives the whole archive.
+pub(crate) struct RawExportWriter {
+    zip: ZipWriter<StreamWriter<SharedBuffer>>,
+    buffer: SharedBuffer,
+    manifest: RawExportManifest,
+}
+
+impl RawExportWriter {
+    /// Creates an archive of the results of session `session`, whose event lists were captured from `eventlist_topics`.
+    pub(crate) fn new(session: String, eventlist_topics: Vec<String>) -> Self {
+        let buffer = SharedBuffer::default();
+        Self {
+            zip: ZipWriter::new_stream(buffer.clone()),
+            buffer,
+            manifest: RawExportManifest {
+                session,
+                eventlist_topics,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Writes the file of the message at position `index` of the results list, or records it as omitted if it has no `payload`.
+    /// # Returns
+    /// The bytes of the archive written since the previous call.
+    pub(crate) fn add_entry(
+        &mut self,
+        index: usize,
+        metadata: &DigitiserMetadata,
+        channels: &[Channel],
+        payload: Option<&[u8]>,
+    ) -> Result<Vec<u8>, RawExportError> {
+        let Some(payload) = payload else {
+            self.manifest.omitted.push(index);
+            return Ok(self.buffer.take());
+        };
+        let file = entry_file_name(metadata);
+        self.zip.start_file(file.as_str(), Self::options())?;
+        self.zip.write_all(payload)?;
+        self.manifest.entries.push(ManifestEntry {
+            file,
+            index,
+            digitiser_id: metadata.id,
+            frame_number: metadata.frame_number,
+            period_number: metadata.period_number,
+            timestamp: metadata.timestamp,
+            channels: channels.to_vec(),
+            bytes: payload.len(),
+        });
+        Ok(self.buffer.take())
+    }
+
+    /// Writes the manifest and the archive's central directory.
+    /// # Returns
+    /// The remaining bytes of the archive.
+    pub(crate) fn finish(mut self) -> Result<Vec<u8>, RawExportError> {
+        self.zip.start_file(MANIFEST_FILE_NAME, Self::options())?;
+        serde_json::to_writer_pretty(&mut self.zip, &self.manifest)?;
+        self.zip.finish()?;
+        Ok(self.buffer.take())
+    }
+
+    fn options() -> SimpleFileOptions {
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    fn metadata(id: DigitizerId, frame_number: FrameNumber) -> DigitiserMetadata {
+        DigitiserMetadata {
+            timestamp: DateTime::from_timestamp_millis(1_700_000_000_000 + frame_number as i64)
+                .unwrap(),
+            id,
+            frame_number,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            veto_flags: 0,
+        }
+    }
+
+    /// Writes an archive of `messages`, in turn, and returns it.
+    fn export(messages: &[(DigitiserMetadata, Option<Vec<u8>>)]) -> Vec<u8> {
+        let mut writer = RawExportWriter::new("session".to_owned(), vec!["events".to_owned()]);
+        let mut archive = Vec::new();
+        for (index, (metadata, payload)) in messages.iter().enumerate() {
+            archive.extend(
+                writer
+                    .add_entry(index, metadata, &[0, 1], payload.as_deref())
+                    .unwrap(),
+            );
+        }
+        archive.extend(writer.finish().unwrap());
+        archive
+    }
+
+    fn read_file(archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn archive_holds_payloads_and_manifest() {
+        let messages = [
+            (metadata(1, 7), Some(vec![1, 2, 3, 4])),
+            (metadata(2, 7), Some(vec![5; 1000])),
+            (metadata(1, 8), Some(Vec::new())),
+        ];
+        let mut archive = ZipArchive::new(Cursor::new(export(&messages))).unwrap();
+        assert_eq!(archive.len(), messages.len() + 1);
+
+        let manifest: RawExportManifest =
+            serde_json::from_slice(&read_file(&mut archive, MANIFEST_FILE_NAME)).unwrap();
+        assert_eq!(manifest.session, "session");
+        assert_eq!(manifest.eventlist_topics, ["events"]);
+        assert!(manifest.omitted.is_empty());
+        assert_eq!(manifest.entries.len(), messages.len());
+
+        for (entry, (index, (metadata, payload))) in
+            manifest.entries.iter().zip(messages.iter().enumerate())
+        {
+            let payload = payload.as_ref().unwrap();
+            assert_eq!(entry.index, index);
+            assert_eq!(entry.file, entry_file_name(metadata));
+            assert_eq!(entry.digitiser_id, metadata.id);
+            assert_eq!(entry.frame_number, metadata.frame_number);
+            assert_eq!(entry.timestamp, metadata.timestamp);
+            assert_eq!(entry.channels, [0, 1]);
+            assert_eq!(entry.bytes, payload.len());
+            assert_eq!(&read_file(&mut archive, &entry.file), payload);
+        }
+    }
+
+    #[test]
+    fn messages_without_payload_are_omitted() {
+        let messages = [
+            (metadata(1, 7), None),
+            (metadata(2, 7), Some(vec![1, 2, 3])),
+        ];
+        let mut archive = ZipArchive::new(Cursor::new(export(&messages))).unwrap();
+        assert_eq!(archive.len(), 2);
+
+        let manifest: RawExportManifest =
+            serde_json::from_slice(&read_file(&mut archive, MANIFEST_FILE_NAME)).unwrap();
+        assert_eq!(manifest.omitted, [0]);
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].index, 1);
+        assert_eq!(
+            read_file(&mut archive, &manifest.entries[0].file),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn file_names_identify_message() {
+        assert_eq!(
+            entry_file_name(&metadata(3, 12)),
+            "digitiser_3_frame_12_2023-11-14T22-13-20.012000000.bin"
+        );
+    }
+}
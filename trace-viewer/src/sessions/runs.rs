@@ -0,0 +1,286 @@
+//! Scans the run control topic for run start and run stop messages, from which the runs are derived.
+use crate::{
+    Timestamp,
+    app::SessionError,
+    structs::{BorrowedMessageError, RunInfo},
+};
+use chrono::DateTime;
+use digital_muon_common::seek::ResolveOffsets;
+use digital_muon_streaming_types::{
+    run_start_pl72::{root_as_run_start, run_start_buffer_has_identifier},
+    run_stop_6s4t::{root_as_run_stop, run_stop_buffer_has_identifier},
+};
+use rdkafka::{
+    Message, Offset, TopicPartitionList,
+    consumer::{Consumer, StreamConsumer},
+};
+use std::collections::HashMap;
+use tokio::time::Duration;
+use tracing::{instrument, warn};
+
+/// The parts of a run start or run stop message needed to derive the runs.
+#[derive(Clone, Debug)]
+pub(crate) enum ControlMessage {
+    Start { name: String, time: Timestamp },
+    Stop { name: String, time: Timestamp },
+}
+
+impl ControlMessage {
+    /// Decodes a message of the control topic.
+    ///
+    /// # Returns
+    /// The message, or [None] if it is neither a run start nor a run stop message.
+    fn decode(payload: &[u8]) -> Result<Option<Self>, BorrowedMessageError> {
+        let time = |ms: u64| {
+            i64::try_from(ms)
+                .ok()
+                .and_then(DateTime::from_timestamp_millis)
+                .ok_or(BorrowedMessageError::TimeMissing)
+        };
+        if run_start_buffer_has_identifier(payload) {
+            let run_start = root_as_run_start(payload)?;
+            Ok(Some(Self::Start {
+                name: run_start.run_name().unwrap_or_default().to_owned(),
+                time: time(run_start.start_time())?,
+            }))
+        } else if run_stop_buffer_has_identifier(payload) {
+            let run_stop = root_as_run_stop(payload)?;
+            Ok(Some(Self::Stop {
+                name: run_stop.run_name().unwrap_or_default().to_owned(),
+                time: time(run_stop.stop_time())?,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn time(&self) -> Timestamp {
+        match self {
+            Self::Start { time, .. } | Self::Stop { time, .. } => *time,
+        }
+    }
+}
+
+/// Derives the runs recorded by control messages, ordered by start time.
+///
+/// A run which is still running when the next run starts is stopped at the start of the next run,
+/// as its run stop message is missing. Run stop messages which do not name the current run are ignored.
+/// # Parameters
+/// - messages: the control messages, in any order.
+pub(crate) fn derive_runs(messages: impl IntoIterator<Item = ControlMessage>) -> Vec<RunInfo> {
+    let mut messages = messages.into_iter().collect::<Vec<_>>();
+    // The sort is stable, so a run stopping at the same time as the next starts remains in order.
+    messages.sort_by_key(ControlMessage::time);
+
+    let mut runs = Vec::<RunInfo>::new();
+    for message in messages {
+        let current = runs.last_mut().filter(|run| run.stop.is_none());
+        match message {
+            ControlMessage::Start { name, time } => {
+                if let Some(current) = current {
+                    warn!(
+                        "Run {} has no stop message before {name} starts",
+                        current.name
+                    );
+                    current.stop = Some(time);
+                }
+                runs.push(RunInfo {
+                    name,
+                    start: time,
+                    stop: None,
+                });
+            }
+            ControlMessage::Stop { name, time } => match current {
+                Some(current) if current.name == name => current.stop = Some(time),
+                _ => warn!("Run stop message of {name} does not match a running run"),
+            },
+        }
+    }
+    runs
+}
+
+/// The settings needed to scan the control topic, copied from the session engine,
+/// so that the topic can be scanned without holding the engine's lock, see [SessionEngine::run_scan].
+///
+/// [SessionEngine::run_scan]: super::SessionEngine::run_scan
+pub(crate) struct RunScan {
+    pub(crate) broker: String,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) timeout: Duration,
+}
+
+impl RunScan {
+    /// Reads the whole control topic, and returns the runs derived from it, see [scan_control_topic].
+    ///
+    /// The scan has its own consumer group, so does not disturb the offsets of searches.
+    #[instrument(skip_all, fields(topic = %self.topic))]
+    pub(crate) async fn scan(self) -> Result<Vec<RunInfo>, SessionError> {
+        let consumer = digital_muon_common::create_default_consumer(
+            &self.broker,
+            &self.username,
+            &self.password,
+            &format!("{}-runs", self.consumer_group),
+            None,
+        )?;
+        scan_control_topic(&consumer, &self.topic, self.timeout).await
+    }
+}
+
+/// Reads every message of the control topic which exists when called, and derives the runs from them.
+/// # Parameters
+/// - consumer: a consumer, this is reassigned to the control topic.
+/// - topic: the control topic.
+/// - timeout: how long to wait for the broker, and for each message.
+#[instrument(skip(consumer))]
+pub(crate) async fn scan_control_topic(
+    consumer: &StreamConsumer,
+    topic: &str,
+    timeout: Duration,
+) -> Result<Vec<RunInfo>, SessionError> {
+    // The offset after the last message of each partition which is still to be read.
+    let mut end_offsets = HashMap::<i32, i64>::new();
+    let mut offsets = TopicPartitionList::new();
+    for partition in consumer.fetch_partitions(topic, timeout)? {
+        let (low, high) = consumer.fetch_watermarks(topic, partition, timeout)?;
+        if low < high {
+            end_offsets.insert(partition, high);
+            offsets.add_partition_offset(topic, partition, Offset::Offset(low))?;
+        }
+    }
+    if end_offsets.is_empty() {
+        return Ok(Vec::new());
+    }
+    consumer.assign(&offsets)?;
+
+    let mut messages = Vec::new();
+    while !end_offsets.is_empty() {
+        let message = tokio::time::timeout(timeout, consumer.recv())
+            .await
+            .map_err(|_| SessionError::ControlTopicTimeout)??;
+        match message.payload().map(ControlMessage::decode).transpose() {
+            Ok(Some(Some(control_message))) => messages.push(control_message),
+            Ok(_) => {}
+            Err(e) => warn!("Cannot decode control message: {e}"),
+        }
+        if end_offsets
+            .get(&message.partition())
+            .is_some_and(|&end| message.offset() + 1 >= end)
+        {
+            end_offsets.remove(&message.partition());
+        }
+    }
+    Ok(derive_runs(messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digital_muon_streaming_types::{
+        flatbuffers::FlatBufferBuilder,
+        run_start_pl72::{RunStart, RunStartArgs, finish_run_start_buffer},
+        run_stop_6s4t::{RunStop, RunStopArgs, finish_run_stop_buffer},
+    };
+
+    fn time(ms: i64) -> Timestamp {
+        DateTime::from_timestamp_millis(ms).unwrap()
+    }
+
+    fn start(name: &str, ms: i64) -> ControlMessage {
+        ControlMessage::Start {
+            name: name.to_owned(),
+            time: time(ms),
+        }
+    }
+
+    fn stop(name: &str, ms: i64) -> ControlMessage {
+        ControlMessage::Stop {
+            name: name.to_owned(),
+            time: time(ms),
+        }
+    }
+
+    fn run(name: &str, start: i64, stop: Option<i64>) -> RunInfo {
+        RunInfo {
+            name: name.to_owned(),
+            start: time(start),
+            stop: stop.map(time),
+        }
+    }
+
+    #[test]
+    fn runs_are_derived_from_start_and_stop() {
+        let runs = derive_runs([
+            start("A", 10),
+            stop("A", 20),
+            start("B", 30),
+            stop("B", 40),
+            start("C", 40),
+        ]);
+        assert_eq!(
+            runs,
+            [
+                run("A", 10, Some(20)),
+                run("B", 30, Some(40)),
+                run("C", 40, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_stop_is_implied_by_next_start() {
+        let runs = derive_runs([start("A", 10), start("B", 30), stop("B", 40)]);
+        assert_eq!(runs, [run("A", 10, Some(30)), run("B", 30, Some(40))]);
+    }
+
+    #[test]
+    fn messages_are_ordered_by_time() {
+        let runs = derive_runs([stop("B", 40), start("B", 30), stop("A", 20), start("A", 10)]);
+        assert_eq!(runs, [run("A", 10, Some(20)), run("B", 30, Some(40))]);
+    }
+
+    #[test]
+    fn unmatched_stops_are_ignored() {
+        let runs = derive_runs([
+            stop("A", 5),
+            start("A", 10),
+            stop("B", 15),
+            stop("A", 20),
+            stop("A", 25),
+        ]);
+        assert_eq!(runs, [run("A", 10, Some(20))]);
+    }
+
+    #[test]
+    fn control_messages_are_decoded() {
+        let mut fbb = FlatBufferBuilder::new();
+        let args = RunStartArgs {
+            start_time: 1_000,
+            run_name: Some(fbb.create_string("A")),
+            ..Default::default()
+        };
+        let message = RunStart::create(&mut fbb, &args);
+        finish_run_start_buffer(&mut fbb, message);
+        assert!(matches!(
+            ControlMessage::decode(fbb.finished_data()).unwrap(),
+            Some(ControlMessage::Start { name, time: t }) if name == "A" && t == time(1_000)
+        ));
+
+        let mut fbb = FlatBufferBuilder::new();
+        let args = RunStopArgs {
+            stop_time: 2_000,
+            run_name: Some(fbb.create_string("A")),
+            ..Default::default()
+        };
+        let message = RunStop::create(&mut fbb, &args);
+        finish_run_stop_buffer(&mut fbb, message);
+        assert!(matches!(
+            ControlMessage::decode(fbb.finished_data()).unwrap(),
+            Some(ControlMessage::Stop { name, time: t }) if name == "A" && t == time(2_000)
+        ));
+
+        assert!(ControlMessage::decode(&[0; 16]).unwrap().is_none());
+    }
+}
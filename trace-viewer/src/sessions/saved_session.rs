@@ -0,0 +1,281 @@
+//! Stores the results of sessions on disk, so they can be reloaded after a refresh or restart.
+use crate::{
+    app::SessionError,
+    structs::{Cache, DigitiserMetadata, DigitiserTrace, SearchTarget},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+use tracing::info;
+use uuid::Uuid;
+
+/// The extension of saved session files.
+const EXTENSION: &str = "json";
+
+/// The contents of a saved session file.
+///
+/// When saving, this borrows the session's metadata and decodes its traces, and when loading, it owns both.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SavedSession<'a> {
+    pub(crate) target: Cow<'a, SearchTarget>,
+    pub(crate) eventlist_topic_indices: Vec<usize>,
+    pub(crate) traces: Vec<(Cow<'a, DigitiserMetadata>, Cow<'a, DigitiserTrace>)>,
+}
+
+impl<'a> SavedSession<'a> {
+    pub(crate) fn new(target: &'a SearchTarget, cache: &'a Cache) -> Self {
+        Self {
+            target: Cow::Borrowed(target),
+            eventlist_topic_indices: cache.get_eventlist_topic_indices().copied().collect(),
+            traces: cache
+                .iter_decoded()
+                .map(|(metadata, trace)| (Cow::Borrowed(metadata), Cow::Owned(trace)))
+                .collect(),
+        }
+    }
+
+    /// Copies the borrowed metadata, so the session can be written once the [SessionEngine] has been unlocked.
+    ///
+    /// [SessionEngine]: crate::sessions::SessionEngine
+    pub(crate) fn into_owned(self) -> SavedSession<'static> {
+        SavedSession {
+            target: Cow::Owned(self.target.into_owned()),
+            eventlist_topic_indices: self.eventlist_topic_indices,
+            traces: self
+                .traces
+                .into_iter()
+                .map(|(metadata, trace)| {
+                    (
+                        Cow::Owned(metadata.into_owned()),
+                        Cow::Owned(trace.into_owned()),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the search target and a cache containing the saved traces and event lists.
+    pub(crate) fn into_parts(self) -> (SearchTarget, Cache) {
+        let cache = Cache::from_traces(
+            self.traces
+                .into_iter()
+                .map(|(metadata, trace)| (metadata.into_owned(), trace.into_owned())),
+            self.eventlist_topic_indices,
+        );
+        (self.target.into_owned(), cache)
+    }
+}
+
+/// Returns the path of the file in `dir` storing the session `name`.
+///
+/// Names are restricted to alphanumerics, `-` and `_`, so they cannot refer to files outside of `dir`.
+fn path(dir: &Path, name: &str) -> Result<PathBuf, SessionError> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(SessionError::InvalidSavedSessionName(name.to_owned()));
+    }
+    Ok(dir.join(name).with_extension(EXTENSION))
+}
+
+fn io_error(error: std::io::Error) -> SessionError {
+    SessionError::SavedSessionIo(error.to_string())
+}
+
+/// Writes `session` to the file in `dir` named `name`.
+/// If such a file already exists, it is only replaced if `overwrite` is set.
+pub(crate) fn save(
+    dir: &Path,
+    name: &str,
+    overwrite: bool,
+    session: &SavedSession,
+) -> Result<(), SessionError> {
+    let path = path(dir, name)?;
+    write_file(dir, &path, name, overwrite, |writer| {
+        serde_json::to_writer(writer, session)
+            .map_err(|e| SessionError::SavedSessionFormat(e.to_string()))
+    })?;
+    info!("Saved session {name} to {path:?}");
+    Ok(())
+}
+
+/// Writes the file at `path`, storing the session `name`, with `write`.
+///
+/// The contents are written to a temporary file in `dir`, which then replaces the file at `path`,
+/// so a failed write leaves any previous file intact, and concurrent writes cannot interleave.
+/// If `overwrite` is not set, the file is created empty beforehand, so an existing one is never replaced.
+fn write_file(
+    dir: &Path,
+    path: &Path,
+    name: &str,
+    overwrite: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), SessionError>,
+) -> Result<(), SessionError> {
+    fs::create_dir_all(dir).map_err(io_error)?;
+
+    if !overwrite {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => SessionError::SavedSessionExists(name.to_owned()),
+                _ => io_error(e),
+            })?;
+    }
+
+    let temp_path = dir.join(format!(".{name}.{}.tmp", Uuid::new_v4()));
+    let result = File::create(&temp_path)
+        .map_err(io_error)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush().map_err(io_error)?;
+            writer.get_ref().sync_all().map_err(io_error)
+        })
+        .and_then(|()| fs::rename(&temp_path, path).map_err(io_error));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        if !overwrite {
+            let _ = fs::remove_file(path);
+        }
+    }
+    result
+}
+
+/// Reads the session named `name` from `dir`.
+pub(crate) fn load(dir: &Path, name: &str) -> Result<SavedSession<'static>, SessionError> {
+    let path = path(dir, name)?;
+    let file = File::open(&path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => SessionError::SavedSessionNotFound(name.to_owned()),
+        _ => io_error(e),
+    })?;
+
+    let session = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| SessionError::SavedSessionFormat(e.to_string()))?;
+    info!("Loaded session {name} from {path:?}");
+    Ok(session)
+}
+
+/// A snapshot of a session's results, to be written to the saved sessions directory once the [SessionEngine] has been unlocked.
+///
+/// [SessionEngine]: crate::sessions::SessionEngine
+#[must_use = "the session is only saved once the write is persisted"]
+pub(crate) struct SavedSessionWrite {
+    dir: PathBuf,
+    name: String,
+    overwrite: bool,
+    session: SavedSession<'static>,
+}
+
+impl SavedSessionWrite {
+    pub(crate) fn new(dir: PathBuf, name: &str, overwrite: bool, session: SavedSession) -> Self {
+        Self {
+            dir,
+            name: name.to_owned(),
+            overwrite,
+            session: session.into_owned(),
+        }
+    }
+
+    /// Writes the snapshot to its file, see [save], on a thread where blocking is acceptable.
+    pub(crate) async fn persist(self) -> Result<(), SessionError> {
+        tokio::task::spawn_blocking(move || {
+            save(&self.dir, &self.name, self.overwrite, &self.session)
+        })
+        .await
+        .map_err(|e| SessionError::SavedSessionIo(e.to_string()))?
+    }
+}
+
+/// The location of a saved session, to be read once the [SessionEngine] has been unlocked.
+///
+/// [SessionEngine]: crate::sessions::SessionEngine
+pub(crate) struct SavedSessionRead {
+    dir: PathBuf,
+    name: String,
+}
+
+impl SavedSessionRead {
+    pub(crate) fn new(dir: PathBuf, name: &str) -> Self {
+        Self {
+            dir,
+            name: name.to_owned(),
+        }
+    }
+
+    /// Reads the saved session from its file, see [load], on a thread where blocking is acceptable.
+    pub(crate) async fn read(self) -> Result<SavedSession<'static>, SessionError> {
+        tokio::task::spawn_blocking(move || load(&self.dir, &self.name))
+            .await
+            .map_err(|e| SessionError::SavedSessionIo(e.to_string()))?
+    }
+}
+
+/// Returns the names of the sessions saved in `dir`, in alphabetical order.
+///
+/// If `dir` does not exist yet, no sessions have been saved, so this returns an empty list.
+pub(crate) fn list(dir: &Path) -> Result<Vec<String>, SessionError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(io_error(e)),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(io_error)?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == EXTENSION)
+            && let Some(name) = path.file_stem().and_then(|name| name.to_str())
+        {
+            names.push(name.to_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{SearchTargetBy, SearchTargetMode};
+    use chrono::DateTime;
+
+    #[test]
+    fn failed_overwrite_leaves_previous_save() {
+        let dir = std::env::temp_dir().join(format!("trace-viewer-test-{}", Uuid::new_v4()));
+        let target = SearchTarget {
+            mode: SearchTargetMode::Timestamp {
+                timestamp: DateTime::from_timestamp_millis(0).unwrap(),
+            },
+            by: SearchTargetBy::All,
+            number: 2,
+        };
+        let cache = Cache::from_traces([], [0, 1]);
+        save(&dir, "session", false, &SavedSession::new(&target, &cache)).unwrap();
+
+        let path = path(&dir, "session").unwrap();
+        let result = write_file(&dir, &path, "session", true, |writer| {
+            writer.write_all(b"{\"target\":").map_err(io_error)?;
+            Err(SessionError::SavedSessionFormat("interrupted".to_owned()))
+        });
+        assert!(matches!(result, Err(SessionError::SavedSessionFormat(_))));
+
+        let loaded = load(&dir, "session").unwrap();
+        assert_eq!(loaded.target.number, 2);
+        assert_eq!(loaded.eventlist_topic_indices, [0, 1]);
+        assert_eq!(list(&dir).unwrap(), ["session"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
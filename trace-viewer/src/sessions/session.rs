@@ -1,135 +1,452 @@
 use crate::{
-    Timestamp,
+    Channel, Timestamp,
     app::SessionError,
-    finder::SearchEngine,
+    finder::{SearchEngine, SearchMonitor},
+    sessions::{clock, saved_session::SavedSession},
     structs::{
-        DigitiserMetadata, DigitiserTrace, SearchResults, SearchSummary, SearchTarget, TraceSummary,
+        Cache, DigitiserEventList, DigitiserMetadata, DigitiserTrace, ResultsPage, RunAnnotation,
+        RunInfo, SearchProgress, SearchResults, SearchSummary, SearchTarget, SortResultsBy,
+        TraceStatistics, TraceSummary,
     },
 };
-use chrono::{TimeDelta, Utc};
-use tokio::{sync::oneshot, task::JoinHandle};
+use chrono::TimeDelta;
+use std::{cmp::Reverse, sync::OnceLock};
+use tokio::task::JoinHandle;
 use tracing::instrument;
 
 pub struct SessionSearchBody {
     pub(crate) handle: JoinHandle<Result<SearchResults, SessionError>>,
-    pub(crate) cancel_recv: oneshot::Receiver<()>,
 }
 
 pub struct Session {
     target: SearchTarget,
     results: Option<SearchResults>,
+    /// The statistics of each message in the results, computed when first requested.
+    statistics: OnceLock<Vec<TraceStatistics>>,
     search_body: Option<SessionSearchBody>,
-    cancel_send: Option<oneshot::Sender<()>>,
-    expiration: Timestamp,
+    /// Records the progress of the session's search, and cancels it.
+    monitor: SearchMonitor,
+    /// The estimated bytes held by the results, see [Cache::size_bytes].
+    size_bytes: usize,
+    created: Timestamp,
+    /// When the session was created or last refreshed.
+    last_used: Timestamp,
     session_ttl: TimeDelta,
+    /// The number of messages written by the most recent raw export of the results, if one has started.
+    raw_export_progress: Option<usize>,
 }
 
 impl Session {
-    const EXPIRE_TIME_MIN: i64 = 10;
-
     pub(crate) fn new_search(
         mut searcher: SearchEngine,
         target: SearchTarget,
         session_ttl_sec: i64,
     ) -> Self {
-        let (cancel_send, cancel_recv) = oneshot::channel();
+        Self::spawn_search(target.clone(), session_ttl_sec, move |monitor| async move {
+            Ok(searcher.search(target, &monitor).await?)
+        })
+    }
+
+    /// Spawns the task which runs the session's search.
+    /// # Parameters
+    /// - target: the target of the search.
+    /// - session_ttl_sec: the time after the last refresh when the session expires.
+    /// - task: given the monitor of the search, returns the future which runs it.
+    fn spawn_search<F, Fut>(target: SearchTarget, session_ttl_sec: i64, task: F) -> Self
+    where
+        F: FnOnce(SearchMonitor) -> Fut,
+        Fut: Future<Output = Result<SearchResults, SessionError>> + Send + 'static,
+    {
+        let monitor = SearchMonitor::default();
+        let search = task(monitor.clone());
+        let finished = monitor.clone();
+        let now = clock::now();
         Session {
-            target: target.clone(),
+            target,
             results: None,
+            statistics: OnceLock::new(),
             search_body: Some(SessionSearchBody {
-                handle: tokio::task::spawn(async move { Ok(searcher.search(target).await?) }),
-                cancel_recv,
+                handle: tokio::task::spawn(async move {
+                    let results = search.await;
+                    finished.finish();
+                    results
+                }),
             }),
-            cancel_send: Some(cancel_send),
-            expiration: Utc::now() + TimeDelta::minutes(Self::EXPIRE_TIME_MIN),
+            monitor,
+            size_bytes: 0,
+            created: now,
+            last_used: now,
+            session_ttl: TimeDelta::seconds(session_ttl_sec),
+            raw_export_progress: None,
+        }
+    }
+
+    /// Creates a session whose search has already completed, from a saved session.
+    pub(crate) fn from_saved(saved: SavedSession, session_ttl_sec: i64) -> Self {
+        let (target, cache) = saved.into_parts();
+        let now = clock::now();
+        let monitor = SearchMonitor::default();
+        monitor.finish();
+        Session {
+            target,
+            size_bytes: cache.size_bytes(),
+            results: Some(SearchResults::Successful { cache }),
+            statistics: OnceLock::new(),
+            search_body: None,
+            monitor,
+            created: now,
+            last_used: now,
             session_ttl: TimeDelta::seconds(session_ttl_sec),
+            raw_export_progress: None,
         }
     }
 
+    /// Returns the session's results in the form in which they are saved.
+    pub(crate) fn to_saved(&self) -> Result<SavedSession<'_>, SessionError> {
+        Ok(SavedSession::new(&self.target, self.cache()?))
+    }
+
+    fn cache(&self) -> Result<&Cache, SessionError> {
+        self.results
+            .as_ref()
+            .ok_or(SessionError::ResultsMissing)?
+            .cache()
+    }
+
+    /// Takes the search body, so its search can be awaited.
+    ///
+    /// A session has only one search, so if it is already being awaited, this is rejected.
     #[instrument(skip_all)]
     pub fn take_search_body(&mut self) -> Result<SessionSearchBody, SessionError> {
-        self.search_body
-            .take()
-            .ok_or(SessionError::BodyAlreadyTaken)
+        self.search_body.take().ok_or_else(|| {
+            if self.monitor.progress().finished {
+                SessionError::BodyAlreadyTaken
+            } else {
+                SessionError::SearchInProgress
+            }
+        })
     }
 
+    /// Asks the session's search to stop, after which its results are those found so far.
     #[instrument(skip_all)]
     pub fn cancel(&mut self) -> Result<(), SessionError> {
-        self.cancel_send
-            .take()
-            .ok_or(SessionError::AttemptedToCancelTwice)?
-            .send(())
-            .map_err(|_| SessionError::CouldNotSendCancelSignal)
+        self.monitor
+            .cancel()
+            .then_some(())
+            .ok_or(SessionError::AttemptedToCancelTwice)
+    }
+
+    /// Returns the progress of the session's search.
+    pub fn search_progress(&self) -> SearchProgress {
+        self.monitor.progress()
     }
 
     #[instrument(skip_all)]
     pub fn register_results(&mut self, result: SearchResults) {
+        self.size_bytes = result.cache().map(Cache::size_bytes).unwrap_or_default();
         self.results = Some(result);
     }
 
     #[instrument(skip_all)]
     pub fn get_search_summaries(&self) -> Result<SearchSummary, SessionError> {
-        let cache = self
-            .results
-            .as_ref()
-            .ok_or(SessionError::ResultsMissing)?
-            .cache()?;
-        let traces = cache
-            .iter()
-            .enumerate()
-            .map(|(index, (metadata, trace))| {
-                let date = metadata
-                    .timestamp
-                    .date_naive()
-                    .format("%y-%m-%d")
-                    .to_string();
-                let time = metadata.timestamp.time().format("%H:%M:%S.%f").to_string();
-                let frame_number = metadata.frame_number;
-                let period_number = metadata.period_number;
-                let protons_per_pulse = metadata.protons_per_pulse;
-                let running = metadata.running;
-                let veto_flags = metadata.veto_flags;
-                let id = metadata.id;
-                let channels = trace.traces.keys().copied().collect::<Vec<_>>();
-                TraceSummary {
-                    date,
-                    time,
-                    frame_number,
-                    period_number,
-                    protons_per_pulse,
-                    running,
-                    veto_flags,
-                    index,
-                    id,
-                    channels,
-                }
-            })
-            .collect::<Vec<_>>();
+        let cache = self.cache()?;
         Ok(SearchSummary {
             eventlist_topic_indices: cache.get_eventlist_topic_indices().copied().collect(),
             target: self.target.clone(),
+            num_results: cache.len(),
+        })
+    }
+
+    /// Returns summaries of at most `limit` messages, starting at position `offset` of the results list ordered by `sort_by`.
+    /// Each summary is annotated with the run, of `runs`, during which its message occurred.
+    ///
+    /// No traces are decoded, unless sorting by a statistic which has not yet been computed.
+    #[instrument(skip_all)]
+    pub fn get_results_page(
+        &self,
+        offset: usize,
+        limit: usize,
+        sort_by: SortResultsBy,
+        runs: Option<&[RunInfo]>,
+    ) -> Result<ResultsPage, SessionError> {
+        let cache = self.cache()?;
+        let indices = if sort_by == SortResultsBy::Timestamp {
+            // The results list is in order of timestamp, so the page is a range of indices.
+            (offset..offset.saturating_add(limit).min(cache.len())).collect::<Vec<_>>()
+        } else {
+            let statistics = self.statistics(cache);
+            let mut indices = (0..cache.len()).collect::<Vec<_>>();
+            indices.sort_by_key(|&index| Reverse(sort_by.key(&statistics[index])));
+            indices.into_iter().skip(offset).take(limit).collect()
+        };
+        let traces = indices
+            .into_iter()
+            .map(|index| {
+                let (metadata, channels) = cache
+                    .get_channels(index)
+                    .expect("Index should be in range, this should never fail.");
+                TraceSummary {
+                    run: RunAnnotation::new(runs, &metadata.timestamp),
+                    ..trace_summary(index, metadata, channels.to_vec())
+                }
+            })
+            .collect();
+        Ok(ResultsPage {
+            offset,
+            num_results: cache.len(),
             traces,
         })
     }
 
-    pub(crate) fn get_selected_trace(
+    /// Returns the statistics of each message in the cache, in order of index.
+    #[instrument(skip_all)]
+    pub fn get_trace_statistics(&self) -> Result<Vec<TraceStatistics>, SessionError> {
+        Ok(self.statistics(self.cache()?).to_vec())
+    }
+
+    /// Returns the statistics of each message in `cache`, decoding every message the first time this is called.
+    fn statistics(&self, cache: &Cache) -> &[TraceStatistics] {
+        self.statistics.get_or_init(|| {
+            cache
+                .iter_decoded()
+                .enumerate()
+                .map(|(index, (_, trace))| TraceStatistics::new(index, &trace))
+                .collect()
+        })
+    }
+
+    /// Returns the event lists from the eventlist topic with index `topic_index` of each message in the results list.
+    /// No traces are decoded.
+    pub(crate) fn get_event_lists(
+        &self,
+        topic_index: usize,
+    ) -> Result<impl Iterator<Item = &DigitiserEventList>, SessionError> {
+        Ok(self.cache()?.iter_event_lists(topic_index))
+    }
+
+    /// Returns the indices of the eventlist topics searched, in ascending order.
+    /// No traces are decoded.
+    pub(crate) fn get_eventlist_topic_indices(&self) -> Result<Vec<usize>, SessionError> {
+        let mut indices = self
+            .cache()?
+            .get_eventlist_topic_indices()
+            .copied()
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        Ok(indices)
+    }
+
+    /// Returns the metadata of the message at position `index` of the results list, checking it has `channel`.
+    /// No traces are decoded.
+    pub(crate) fn get_metadata(
         &self,
         index: usize,
-    ) -> Result<(&DigitiserMetadata, &DigitiserTrace), SessionError> {
-        self.results
-            .as_ref()
-            .ok_or(SessionError::ResultsMissing)?
+        channel: Channel,
+    ) -> Result<&DigitiserMetadata, SessionError> {
+        let (metadata, channels) = self
             .cache()?
-            .iter()
-            .nth(index)
+            .get_channels(index)
+            .ok_or(SessionError::TraceNotFound)?;
+        if channels.contains(&channel) {
+            Ok(metadata)
+        } else {
+            Err(SessionError::ChannelNotFound)
+        }
+    }
+
+    /// Decodes the message at position `index` of the results list, in order of timestamp.
+    pub(crate) fn get_selected_trace(
+        &self,
+        index: usize,
+    ) -> Result<(&DigitiserMetadata, DigitiserTrace), SessionError> {
+        self.cache()?.get(index).ok_or(SessionError::TraceNotFound)
+    }
+
+    /// Returns the metadata, channels and raw payload of the message at position `index` of the results list, in order of timestamp.
+    /// The payload is [None] if the message has no bytes as received, such as those of a saved session.
+    pub(crate) fn get_raw_trace(
+        &self,
+        index: usize,
+    ) -> Result<(&DigitiserMetadata, &[Channel], Option<&[u8]>), SessionError> {
+        self.cache()?
+            .get_raw(index)
             .ok_or(SessionError::TraceNotFound)
     }
 
-    pub(crate) fn expired(&self) -> bool {
-        self.expiration < Utc::now()
+    /// Returns the number of messages written by the most recent raw export, if one has started.
+    pub(crate) fn raw_export_progress(&self) -> Option<usize> {
+        self.raw_export_progress
+    }
+
+    pub(crate) fn set_raw_export_progress(&mut self, written: usize) {
+        self.raw_export_progress = Some(written)
+    }
+
+    /// Returns the estimated bytes held by the results, which is zero until they are registered.
+    pub(crate) fn size_bytes(&self) -> usize {
+        self.size_bytes
+    }
+
+    pub(crate) fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    pub(crate) fn last_used(&self) -> Timestamp {
+        self.last_used
+    }
+
+    /// Returns true if the session has not been refreshed within its time-to-live of `now`.
+    pub(crate) fn expired(&self, now: Timestamp) -> bool {
+        self.last_used + self.session_ttl < now
     }
 
     pub(crate) fn refresh(&mut self) {
-        self.expiration = Utc::now() + self.session_ttl
+        self.last_used = clock::now()
+    }
+}
+
+impl Drop for Session {
+    /// Stops the session's search, if it is running, so a purged session's search does not continue unobserved.
+    fn drop(&mut self) {
+        self.monitor.cancel();
+    }
+}
+
+/// Summarises the message at position `index` of the results list, whose run is unknown.
+pub(super) fn trace_summary(
+    index: usize,
+    metadata: &DigitiserMetadata,
+    channels: Vec<Channel>,
+) -> TraceSummary {
+    let date = metadata
+        .timestamp
+        .date_naive()
+        .format("%y-%m-%d")
+        .to_string();
+    let time = metadata.timestamp.time().format("%H:%M:%S.%f").to_string();
+    let frame_number = metadata.frame_number;
+    let period_number = metadata.period_number;
+    let protons_per_pulse = metadata.protons_per_pulse;
+    let running = metadata.running;
+    let veto_flags = metadata.veto_flags;
+    let id = metadata.id;
+    TraceSummary {
+        date,
+        time,
+        frame_number,
+        period_number,
+        protons_per_pulse,
+        running,
+        veto_flags,
+        index,
+        id,
+        channels,
+        run: RunAnnotation::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{
+        SearchTargetBy, SearchTargetMode,
+        search_results_test_utils::{payloads_decoded, trace_message},
+    };
+    use chrono::DateTime;
+    use digital_muon_streaming_types::dat2_digitizer_analog_trace_v2_generated::root_as_digitizer_analog_trace_message;
+    use std::time::Duration;
+
+    fn target() -> SearchTarget {
+        SearchTarget {
+            mode: SearchTargetMode::Dragnet {
+                timestamp: DateTime::from_timestamp_millis(0).unwrap(),
+                backstep: 0,
+                forward_distance: 100,
+                until: None,
+            },
+            by: SearchTargetBy::All,
+            number: 1,
+        }
+    }
+
+    /// Returns a session whose search finds nothing, and runs until it is cancelled.
+    fn search_until_cancelled() -> Session {
+        Session::spawn_search(target(), 600, |monitor| async move {
+            while !monitor.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            Ok(SearchResults::Successful {
+                cache: Cache::new(),
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn running_search_is_awaited_once() {
+        let mut session = search_until_cancelled();
+        let body = session.take_search_body().unwrap();
+        assert!(matches!(
+            session.take_search_body(),
+            Err(SessionError::SearchInProgress)
+        ));
+        assert!(!session.search_progress().finished);
+
+        session.cancel().unwrap();
+        assert!(matches!(
+            session.cancel(),
+            Err(SessionError::AttemptedToCancelTwice)
+        ));
+        session.register_results(body.handle.await.unwrap().unwrap());
+
+        // The results found before the search was cancelled can be browsed.
+        assert_eq!(session.get_search_summaries().unwrap().num_results, 0);
+        let progress = session.search_progress();
+        assert!(progress.cancelled && progress.finished);
+        assert!(matches!(
+            session.take_search_body(),
+            Err(SessionError::BodyAlreadyTaken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropping_session_cancels_its_search() {
+        let mut session = search_until_cancelled();
+        let body = session.take_search_body().unwrap();
+        drop(session);
+        assert!(body.handle.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn search_results_are_browsed_without_decoding_traces() {
+        // The search caches the payloads of trace messages as received, as the finder does.
+        let mut session = Session::spawn_search(target(), 600, |_| async move {
+            let mut cache = Cache::new();
+            for frame_number in 0..3 {
+                let bytes = trace_message(1, frame_number);
+                cache
+                    .push_trace(
+                        &root_as_digitizer_analog_trace_message(&bytes).unwrap(),
+                        &bytes,
+                    )
+                    .unwrap();
+            }
+            Ok(SearchResults::Successful { cache })
+        });
+        let body = session.take_search_body().unwrap();
+        session.register_results(body.handle.await.unwrap().unwrap());
+
+        assert_eq!(session.get_search_summaries().unwrap().num_results, 3);
+        let page = session
+            .get_results_page(0, 10, SortResultsBy::Timestamp, None)
+            .unwrap();
+        assert_eq!(page.traces.len(), 3);
+        assert!(page.traces.iter().any(|trace| trace.channels == [0, 1]));
+        assert_eq!(session.get_metadata(1, 0).unwrap().frame_number, 1);
+        assert_eq!(payloads_decoded(), 0);
+
+        // Statistics are only computed on request, which decodes every message once.
+        session.get_trace_statistics().unwrap();
+        session.get_trace_statistics().unwrap();
+        assert_eq!(payloads_decoded(), 3);
     }
 }
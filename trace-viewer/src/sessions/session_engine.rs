@@ -1,12 +1,24 @@
 use crate::{
+    Channel, Time,
     app::{ServerError, SessionError},
     finder::SearchEngine,
-    sessions::session::Session,
-    structs::{BrokerInfo, SearchTarget, Topics},
+    sessions::{
+        annotations::{Annotations, AnnotationsWrite},
+        clock,
+        live_tail::{LiveTail, LiveTailStart},
+        plot_cache::{PlotCache, PlotKey},
+        runs::RunScan,
+        saved_session::{self, SavedSession, SavedSessionRead, SavedSessionWrite},
+        session::Session,
+    },
+    structs::{
+        Annotation, AnnotationId, BrokerInfo, DigitiserMetadata, EngineStatus, EventFilter,
+        RunInfo, SearchTarget, SelectedTraceIndex, SessionStatus, Topics, TracePlotly, TraceView,
+    },
 };
-use std::{collections::HashMap, sync::Arc};
+use std::{cmp::Reverse, collections::HashMap, path::PathBuf, sync::Arc};
 use tokio::{sync::Mutex, time::Duration};
-use tracing::{debug, instrument, trace};
+use tracing::{debug, info, instrument, trace};
 use uuid::Uuid;
 
 /// Encapsulates all run-time settings which are needed by the session engine.
@@ -18,24 +30,56 @@ pub struct SessionEngineSettings {
     pub password: Option<String>,
     pub consumer_group: String,
     pub session_ttl_sec: i64,
+    pub saved_sessions_dir: PathBuf,
+    /// The file in which annotations are stored, read when the engine starts, see [SessionEngine::add_annotation].
+    pub annotations_file: PathBuf,
+    pub live_tail_capacity: usize,
+    /// The most points of a trace included in an exported plot, longer traces are decimated.
+    pub export_max_points: usize,
+    /// If present, the most bytes the results of every session may hold, see [SessionEngine::memory_used_bytes].
+    pub memory_cap_bytes: Option<usize>,
+    /// The most plots held by the plot cache, see [SessionEngine::cached_plot].
+    pub plot_cache_max_entries: usize,
+    /// The most bytes of json held by the plot cache, see [SessionEngine::cached_plot].
+    pub plot_cache_max_bytes: usize,
 }
 
 #[derive(Default)]
 pub struct SessionEngine {
     settings: SessionEngineSettings,
     sessions: HashMap<String, Session>,
+    live_tails: HashMap<String, LiveTail>,
+    /// The runs derived from the control topic, once it has been scanned.
+    runs: Option<Vec<RunInfo>>,
+    /// The plots of channels, shared by every session.
+    plot_cache: PlotCache,
+    /// The annotations of every trace, read from [SessionEngineSettings::annotations_file].
+    annotations: Annotations,
 }
 
 impl SessionEngine {
+    /// How long to wait for the broker when looking up the partitions of the trace topic.
+    const FETCH_PARTITIONS_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The number of characters of a session's key included in its [SessionStatus].
+    const KEY_PREFIX_LEN: usize = 8;
+
     pub fn with_arc_mutex(settings: SessionEngineSettings) -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(Self {
+            plot_cache: PlotCache::new(
+                settings.plot_cache_max_entries,
+                settings.plot_cache_max_bytes,
+            ),
+            annotations: Annotations::load(settings.annotations_file.clone()),
             settings,
             sessions: Default::default(),
+            live_tails: Default::default(),
+            runs: None,
         }))
     }
     fn generate_key(&self) -> String {
         let mut key = Uuid::new_v4().to_string();
-        while self.sessions.contains_key(&key) {
+        while self.sessions.contains_key(&key) || self.live_tails.contains_key(&key) {
             key = Uuid::new_v4().to_string();
         }
         key
@@ -46,6 +90,7 @@ impl SessionEngine {
         target: SearchTarget,
         events_topic_indices: Vec<usize>,
     ) -> Result<String, SessionError> {
+        self.check_memory_cap()?;
         let consumer = digital_muon_common::create_default_consumer(
             &self.settings.broker,
             &self.settings.username,
@@ -64,6 +109,165 @@ impl SessionEngine {
         Ok(key)
     }
 
+    /// Returns the write which saves the results of the session with the given `uuid` under `name`,
+    /// which should be persisted after the engine is unlocked.
+    /// An existing saved session of the same name is only replaced if `overwrite` is set.
+    #[instrument(skip(self))]
+    pub(crate) fn save_session(
+        &self,
+        uuid: &str,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<SavedSessionWrite, SessionError> {
+        let saved = self.session(uuid)?.to_saved()?;
+        Ok(SavedSessionWrite::new(
+            self.settings.saved_sessions_dir.clone(),
+            name,
+            overwrite,
+            saved,
+        ))
+    }
+
+    /// Returns the read of the saved session `name`, which should be made after the engine is unlocked,
+    /// and its session then created by [Self::insert_saved_session].
+    /// Returns an error if the sessions already hold the memory cap.
+    #[instrument(skip(self))]
+    pub(crate) fn load_session(&self, name: &str) -> Result<SavedSessionRead, SessionError> {
+        self.check_memory_cap()?;
+        Ok(SavedSessionRead::new(
+            self.settings.saved_sessions_dir.clone(),
+            name,
+        ))
+    }
+
+    /// Creates a new session from a saved session read by [SavedSessionRead::read], and returns its key.
+    /// The memory cap is checked again, as other sessions may have been created whilst it was read.
+    #[instrument(skip_all)]
+    pub(crate) fn insert_saved_session(
+        &mut self,
+        saved: SavedSession,
+    ) -> Result<String, SessionError> {
+        self.check_memory_cap()?;
+        let key = self.generate_key();
+        self.sessions.insert(
+            key.clone(),
+            Session::from_saved(saved, self.settings.session_ttl_sec),
+        );
+        Ok(key)
+    }
+
+    /// Returns the names of all saved sessions.
+    pub fn list_saved_sessions(&self) -> Result<Vec<String>, SessionError> {
+        saved_session::list(&self.settings.saved_sessions_dir)
+    }
+
+    /// Attaches a comment on `time_bin` to the selected channel of a message of the session with the given `uuid`.
+    ///
+    /// The annotation is keyed by the message's metadata, rather than the session, so it is shown whenever the trace is viewed.
+    /// It is recorded in the annotations file once the returned write is persisted, which should be after the engine is unlocked.
+    #[instrument(skip(self, text))]
+    pub fn add_annotation(
+        &mut self,
+        uuid: &str,
+        index_and_channel: &SelectedTraceIndex,
+        time_bin: Time,
+        text: &str,
+        author: &str,
+    ) -> Result<(Annotation, AnnotationsWrite), SessionError> {
+        let metadata = self
+            .sessions
+            .get(uuid)
+            .ok_or(SessionError::DoesNotExist)?
+            .get_metadata(index_and_channel.index, index_and_channel.channel)?;
+        self.annotations.add(
+            metadata,
+            index_and_channel.channel,
+            time_bin,
+            text,
+            author,
+            clock::now(),
+        )
+    }
+
+    /// Returns the annotations of the selected channel of a message of the session with the given `uuid`, in order of time bin.
+    pub fn get_annotations(
+        &self,
+        uuid: &str,
+        index_and_channel: &SelectedTraceIndex,
+    ) -> Result<Vec<Annotation>, SessionError> {
+        let metadata = self
+            .session(uuid)?
+            .get_metadata(index_and_channel.index, index_and_channel.channel)?;
+        self.annotations_of(metadata, index_and_channel.channel)
+    }
+
+    /// Returns the annotations of `channel` of the message with `metadata`, in order of time bin.
+    pub(crate) fn annotations_of(
+        &self,
+        metadata: &DigitiserMetadata,
+        channel: Channel,
+    ) -> Result<Vec<Annotation>, SessionError> {
+        self.annotations.get(metadata, channel)
+    }
+
+    /// Returns the key under which the plot of the selected channel of a message of the session with the given `uuid` is cached,
+    /// when plotted with `event_filter` and `view`. No traces are decoded.
+    ///
+    /// The key includes the version of the annotations, and the names of every eventlist topic searched by the session.
+    pub(crate) fn plot_key(
+        &self,
+        uuid: &str,
+        index_and_channel: &SelectedTraceIndex,
+        event_filter: &EventFilter,
+        view: &TraceView,
+    ) -> Result<PlotKey, SessionError> {
+        let session = self.session(uuid)?;
+        let metadata = session.get_metadata(index_and_channel.index, index_and_channel.channel)?;
+        let eventlist_topics = session
+            .get_eventlist_topic_indices()?
+            .into_iter()
+            .map(|index| {
+                self.settings
+                    .topics
+                    .digitiser_event_topic
+                    .get(index)
+                    .cloned()
+                    .ok_or(SessionError::EventTopicNotFound(index))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PlotKey::new(
+            metadata,
+            index_and_channel.channel,
+            eventlist_topics,
+            self.annotations.version(),
+            event_filter,
+            view,
+        ))
+    }
+
+    /// Returns the plot cached under `key`, if any, for the session with the given `uuid`.
+    ///
+    /// Plots are shared by every session which found the same message, and are removed once the last session to use them is,
+    /// or when the least recently used plots exceed the bounds of the cache.
+    pub(crate) fn cached_plot(&mut self, uuid: &str, key: &PlotKey) -> Option<TracePlotly> {
+        self.plot_cache.get(uuid, key)
+    }
+
+    /// Caches `plot` under `key`, for the session with the given `uuid`, see [Self::cached_plot].
+    pub(crate) fn cache_plot(&mut self, uuid: &str, key: PlotKey, plot: TracePlotly) {
+        self.plot_cache.insert(uuid, key, plot);
+    }
+
+    /// Removes the annotation with the given `id`.
+    /// Its removal is recorded in the annotations file once the returned write is persisted, see [Self::add_annotation].
+    #[instrument(skip(self))]
+    pub fn delete_annotation(
+        &mut self,
+        id: AnnotationId,
+    ) -> Result<AnnotationsWrite, SessionError> {
+        self.annotations.delete(id)
+    }
+
     pub fn session(&self, uuid: &str) -> Result<&Session, SessionError> {
         self.sessions.get(uuid).ok_or(SessionError::DoesNotExist)
     }
@@ -78,20 +282,182 @@ impl SessionEngine {
             .ok_or(SessionError::DoesNotExist)
     }
 
+    /// Returns what is needed to start following the trace topic from its end.
+    ///
+    /// Connecting to the broker is slow, so the live tail is started after the engine's lock is released,
+    /// and is then stored by [Self::insert_live_tail].
+    pub(crate) fn live_tail_start(&self) -> LiveTailStart {
+        LiveTailStart {
+            broker: self.settings.broker.clone(),
+            username: self.settings.username.clone(),
+            password: self.settings.password.clone(),
+            consumer_group: self.settings.consumer_group.clone(),
+            topic: self.settings.topics.trace_topic.clone(),
+            timeout: Self::FETCH_PARTITIONS_TIMEOUT,
+            capacity: self.settings.live_tail_capacity,
+            session_ttl_sec: self.settings.session_ttl_sec,
+        }
+    }
+
+    /// Stores a live tail started by [LiveTailStart::start], and returns its key.
+    pub(crate) fn insert_live_tail(&mut self, live_tail: LiveTail) -> String {
+        let key = self.generate_key();
+        self.live_tails.insert(key.clone(), live_tail);
+        key
+    }
+
+    /// Stops the live tail with the given `uuid`, ending its task.
+    #[instrument(skip(self))]
+    pub fn stop_live_tail(&mut self, uuid: &str) -> Result<(), SessionError> {
+        self.live_tails
+            .remove(uuid)
+            .map(|_| ())
+            .ok_or(SessionError::LiveTailDoesNotExist)
+    }
+
+    pub(crate) fn live_tail(&self, uuid: &str) -> Result<&LiveTail, SessionError> {
+        self.live_tails
+            .get(uuid)
+            .ok_or(SessionError::LiveTailDoesNotExist)
+    }
+
+    pub(crate) fn live_tail_mut(&mut self, uuid: &str) -> Result<&mut LiveTail, SessionError> {
+        self.live_tails
+            .get_mut(uuid)
+            .ok_or(SessionError::LiveTailDoesNotExist)
+    }
+
+    /// Returns what is needed to scan the control topic for runs, or [None] if no control topic is set.
+    ///
+    /// The scan is slow, so is run after the engine's lock is released,
+    /// and its runs are then stored by [Self::set_runs].
+    pub(crate) fn run_scan(&self) -> Option<RunScan> {
+        Some(RunScan {
+            broker: self.settings.broker.clone(),
+            username: self.settings.username.clone(),
+            password: self.settings.password.clone(),
+            consumer_group: self.settings.consumer_group.clone(),
+            topic: self.settings.topics.control_topic.clone()?,
+            timeout: Self::FETCH_PARTITIONS_TIMEOUT,
+        })
+    }
+
+    /// Stores the runs found by a scan of the control topic, with which results are annotated.
+    pub(crate) fn set_runs(&mut self, runs: Vec<RunInfo>) {
+        self.runs = Some(runs);
+    }
+
+    /// Returns the runs found by the most recent scan of the control topic, if any.
+    pub fn runs(&self) -> Option<&[RunInfo]> {
+        self.runs.as_deref()
+    }
+
+    /// Returns the estimated bytes held by the results of every session, see [Session::size_bytes].
+    pub fn memory_used_bytes(&self) -> usize {
+        self.sessions.values().map(Session::size_bytes).sum()
+    }
+
+    /// Returns an error if the sessions hold at least the memory cap, so no more should be created.
+    fn check_memory_cap(&self) -> Result<(), SessionError> {
+        let used = self.memory_used_bytes();
+        match self.settings.memory_cap_bytes {
+            Some(cap) if used >= cap => Err(SessionError::MemoryCapExceeded { used, cap }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the sessions, most recently used first, and the memory they hold.
+    pub fn status(&self) -> EngineStatus {
+        let now = clock::now();
+        let mut sessions = self
+            .sessions
+            .iter()
+            .map(|(uuid, session)| (session.last_used(), uuid, session))
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|&(last_used, _, _)| Reverse(last_used));
+        EngineStatus {
+            sessions: sessions
+                .into_iter()
+                .map(|(last_used, uuid, session)| SessionStatus {
+                    key_prefix: uuid.chars().take(Self::KEY_PREFIX_LEN).collect(),
+                    size_bytes: session.size_bytes(),
+                    age_sec: (now - session.created()).num_seconds(),
+                    idle_sec: (now - last_used).num_seconds(),
+                })
+                .collect(),
+            memory_used_bytes: self.memory_used_bytes(),
+            memory_cap_bytes: self.settings.memory_cap_bytes,
+            session_ttl_sec: self.settings.session_ttl_sec,
+            plot_cache: self.plot_cache.status(),
+        }
+    }
+
+    /// Removes sessions, least recently used first, until their results hold no more than the memory cap,
+    /// and returns the keys of those removed, in order of removal.
+    /// Sessions whose results have not been registered hold nothing, so are not removed.
+    fn evict_least_recently_used(&mut self) -> Vec<String> {
+        let Some(cap) = self.settings.memory_cap_bytes else {
+            return Vec::new();
+        };
+        let mut used = self.memory_used_bytes();
+        let mut candidates = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.size_bytes() > 0)
+            .map(|(uuid, session)| (session.last_used(), uuid.clone()))
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        let mut evicted = Vec::new();
+        for (_, uuid) in candidates {
+            if used <= cap {
+                break;
+            }
+            if let Some(session) = self.sessions.remove(&uuid) {
+                used -= session.size_bytes();
+                self.plot_cache.release_session(&uuid);
+                evicted.push(uuid);
+            }
+        }
+        evicted
+    }
+
+    /// Removes every expired session and live tail, and then the least recently used sessions if the memory cap is exceeded.
+    ///
+    /// This should only be called with the engine locked, so no server function can be using a session when it is removed.
     #[instrument(skip_all)]
     pub fn purge_expired(&mut self) {
+        let now = clock::now();
         let dead_uuids: Vec<String> = self
             .sessions
-            .keys()
-            .filter(|&uuid| self.sessions.get(uuid).is_some_and(Session::expired))
-            .cloned()
+            .iter()
+            .filter(|(_, session)| session.expired(now))
+            .map(|(uuid, _)| uuid.clone())
             .collect::<Vec<_>>();
 
         debug!("Purging {} dead session(s)", dead_uuids.len());
 
         for uuid in dead_uuids {
             self.sessions.remove_entry(&uuid);
+            self.plot_cache.release_session(&uuid);
+        }
+
+        let evicted = self.evict_least_recently_used();
+        if !evicted.is_empty() {
+            info!(
+                "Evicted {} session(s) to keep within the memory cap",
+                evicted.len()
+            );
         }
+
+        // Dropping a live tail ends its task.
+        let num_live_tails = self.live_tails.len();
+        self.live_tails
+            .retain(|_, live_tail| !live_tail.expired(now));
+        debug!(
+            "Purging {} dead live tail(s)",
+            num_live_tails - self.live_tails.len()
+        );
     }
 
     pub fn spawn_purge_task(
@@ -133,3 +499,698 @@ impl SessionEngine {
             .await?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{
+        Cache, DigitiserMetadata, DigitiserTrace, Event, RunAnnotation, SearchTargetBy,
+        SearchTargetMode, SortResultsBy,
+    };
+    use chrono::{DateTime, TimeDelta};
+    use std::path::Path;
+
+    /// A directory which is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("trace-viewer-test-{}", Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn metadata(offset_ms: i64, id: u8) -> DigitiserMetadata {
+        DigitiserMetadata {
+            timestamp: DateTime::from_timestamp_millis(offset_ms).unwrap(),
+            id,
+            frame_number: 3,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            veto_flags: 0,
+        }
+    }
+
+    fn trace(channel: u32, intensity: u16) -> DigitiserTrace {
+        DigitiserTrace {
+            traces: HashMap::from([(channel, vec![0, intensity, 0])]),
+            events: HashMap::from([(
+                1,
+                HashMap::from([(channel, vec![Event { time: 1, intensity }])]),
+            )]),
+        }
+    }
+
+    /// Returns an engine saving to `dir`, with a single session containing two digitiser messages.
+    fn engine_with_session(dir: &Path) -> (SessionEngine, String) {
+        let mut engine = SessionEngine {
+            settings: SessionEngineSettings {
+                session_ttl_sec: 600,
+                saved_sessions_dir: dir.to_owned(),
+                ..Default::default()
+            },
+            sessions: Default::default(),
+            live_tails: Default::default(),
+            runs: None,
+            plot_cache: Default::default(),
+            annotations: Default::default(),
+        };
+        let target = SearchTarget {
+            mode: SearchTargetMode::Timestamp {
+                timestamp: DateTime::from_timestamp_millis(0).unwrap(),
+            },
+            by: SearchTargetBy::All,
+            number: 2,
+        };
+        let cache = Cache::from_traces(
+            [
+                (metadata(5, 2), trace(4, 20)),
+                (metadata(2, 1), trace(7, 10)),
+            ],
+            [0, 1],
+        );
+        let key = engine.generate_key();
+        engine.sessions.insert(
+            key.clone(),
+            Session::from_saved(SavedSession::new(&target, &cache), 600),
+        );
+        (engine, key)
+    }
+
+    /// Saves the session with the given `uuid` under `name`, as the server function does.
+    async fn save(
+        engine: &SessionEngine,
+        uuid: &str,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<(), SessionError> {
+        engine.save_session(uuid, name, overwrite)?.persist().await
+    }
+
+    /// Creates a new session from the saved session `name`, as the server function does, and returns its key.
+    async fn load(engine: &mut SessionEngine, name: &str) -> Result<String, SessionError> {
+        let saved = engine.load_session(name)?.read().await?;
+        engine.insert_saved_session(saved)
+    }
+
+    #[tokio::test]
+    async fn saved_session_round_trips() {
+        let dir = TempDir::new();
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+
+        save(&engine, &uuid, "two_messages", false).await.unwrap();
+        assert_eq!(engine.list_saved_sessions().unwrap(), vec!["two_messages"]);
+
+        let loaded = load(&mut engine, "two_messages").await.unwrap();
+        assert_ne!(loaded, uuid);
+        for index in 0..2 {
+            assert_eq!(
+                engine
+                    .session(&loaded)
+                    .unwrap()
+                    .get_selected_trace(index)
+                    .unwrap(),
+                engine
+                    .session(&uuid)
+                    .unwrap()
+                    .get_selected_trace(index)
+                    .unwrap()
+            );
+        }
+        assert!(matches!(
+            engine.session(&loaded).unwrap().get_selected_trace(2),
+            Err(SessionError::TraceNotFound)
+        ));
+
+        let summary = engine
+            .session(&loaded)
+            .unwrap()
+            .get_search_summaries()
+            .unwrap();
+        assert_eq!(summary.eventlist_topic_indices, vec![0, 1]);
+    }
+
+    #[test]
+    fn results_are_paged_in_order() {
+        let dir = TempDir::new();
+        let (engine, uuid) = engine_with_session(&dir.0);
+        let session = engine.session(&uuid).unwrap();
+        let ids = |offset, limit, sort_by| {
+            let page = session
+                .get_results_page(offset, limit, sort_by, engine.runs())
+                .unwrap();
+            assert_eq!(page.num_results, 2);
+            page.traces
+                .into_iter()
+                .map(|summary| (summary.index, summary.id))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(0, 1, SortResultsBy::Timestamp), [(0, 1)]);
+        assert_eq!(ids(1, 10, SortResultsBy::Timestamp), [(1, 2)]);
+        assert_eq!(ids(0, 10, SortResultsBy::MaxAmplitude), [(1, 2), (0, 1)]);
+        assert!(ids(2, 10, SortResultsBy::TotalEvents).is_empty());
+    }
+
+    #[test]
+    fn runs_are_only_scanned_from_a_control_topic() {
+        let mut engine = SessionEngine::default();
+        assert!(engine.run_scan().is_none());
+
+        engine.settings.topics.control_topic = Some("control".to_owned());
+        let run_scan = engine.run_scan().unwrap();
+        assert_eq!(run_scan.topic, "control");
+    }
+
+    #[test]
+    fn results_are_annotated_with_runs() {
+        let dir = TempDir::new();
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+        let runs = |engine: &SessionEngine| {
+            engine
+                .session(&uuid)
+                .unwrap()
+                .get_results_page(0, 10, SortResultsBy::Timestamp, engine.runs())
+                .unwrap()
+                .traces
+                .into_iter()
+                .map(|summary| summary.run)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            runs(&engine),
+            [RunAnnotation::Unknown, RunAnnotation::Unknown]
+        );
+
+        engine.set_runs(vec![RunInfo {
+            name: "A".to_owned(),
+            start: DateTime::from_timestamp_millis(0).unwrap(),
+            stop: Some(DateTime::from_timestamp_millis(4).unwrap()),
+        }]);
+        assert_eq!(
+            runs(&engine),
+            [
+                RunAnnotation::Run("A".to_owned()),
+                RunAnnotation::BetweenRuns
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn saved_sessions_are_not_overwritten_by_default() {
+        let dir = TempDir::new();
+        let (engine, uuid) = engine_with_session(&dir.0);
+
+        save(&engine, &uuid, "session", false).await.unwrap();
+        assert!(matches!(
+            save(&engine, &uuid, "session", false).await,
+            Err(SessionError::SavedSessionExists(_))
+        ));
+        save(&engine, &uuid, "session", true).await.unwrap();
+        assert_eq!(engine.list_saved_sessions().unwrap(), vec!["session"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_saved_session_names() {
+        let dir = TempDir::new();
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+
+        for name in ["", "../session", "a/b", "session.json"] {
+            assert!(matches!(
+                save(&engine, &uuid, name, false).await,
+                Err(SessionError::InvalidSavedSessionName(_))
+            ));
+        }
+        assert!(matches!(
+            load(&mut engine, "missing").await,
+            Err(SessionError::SavedSessionNotFound(_))
+        ));
+        assert!(engine.list_saved_sessions().unwrap().is_empty());
+    }
+
+    fn selected(index: usize, channel: Channel) -> SelectedTraceIndex {
+        SelectedTraceIndex { index, channel }
+    }
+
+    /// Adds an annotation to `engine`, without writing it to the annotations file.
+    fn annotate(
+        engine: &mut SessionEngine,
+        uuid: &str,
+        index_and_channel: &SelectedTraceIndex,
+        time_bin: Time,
+        text: &str,
+        author: &str,
+    ) -> Result<Annotation, SessionError> {
+        engine
+            .add_annotation(uuid, index_and_channel, time_bin, text, author)
+            .map(|(annotation, _)| annotation)
+    }
+
+    #[test]
+    fn annotations_round_trip() {
+        let dir = TempDir::new();
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+        clock::set_mock_now(DateTime::from_timestamp_millis(0).unwrap());
+
+        let later = annotate(
+            &mut engine,
+            &uuid,
+            &selected(0, 7),
+            2,
+            "end of ringing",
+            "bob",
+        )
+        .unwrap();
+        let earlier = annotate(
+            &mut engine,
+            &uuid,
+            &selected(0, 7),
+            1,
+            " ringing starts here ",
+            "alice",
+        )
+        .unwrap();
+        let other = annotate(&mut engine, &uuid, &selected(1, 4), 1, "quiet", "alice").unwrap();
+        assert_eq!(earlier.text, "ringing starts here");
+        assert_eq!(earlier.created, clock::now());
+        assert_ne!(earlier.id, later.id);
+
+        assert_eq!(
+            engine.get_annotations(&uuid, &selected(0, 7)).unwrap(),
+            [earlier.clone(), later.clone()]
+        );
+        assert_eq!(
+            engine.get_annotations(&uuid, &selected(1, 4)).unwrap(),
+            [other.clone()]
+        );
+
+        assert!(engine.delete_annotation(later.id).is_ok());
+        assert_eq!(
+            engine.get_annotations(&uuid, &selected(0, 7)).unwrap(),
+            [earlier]
+        );
+        assert!(matches!(
+            engine.delete_annotation(later.id),
+            Err(SessionError::AnnotationNotFound(id)) if id == later.id
+        ));
+
+        // Ids of deleted annotations are not reused.
+        let next = annotate(&mut engine, &uuid, &selected(1, 4), 0, "start", "").unwrap();
+        assert!(next.id > other.id);
+    }
+
+    #[test]
+    fn invalid_annotations() {
+        let dir = TempDir::new();
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+
+        assert!(matches!(
+            engine.add_annotation(&uuid, &selected(0, 7), 1, "  ", "alice"),
+            Err(SessionError::EmptyAnnotation)
+        ));
+        assert!(matches!(
+            engine.add_annotation(&uuid, &selected(0, 4), 1, "text", "alice"),
+            Err(SessionError::ChannelNotFound)
+        ));
+        assert!(matches!(
+            engine.add_annotation(&uuid, &selected(2, 7), 1, "text", "alice"),
+            Err(SessionError::TraceNotFound)
+        ));
+        assert!(
+            engine
+                .get_annotations(&uuid, &selected(0, 7))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn annotations_outlive_sessions() {
+        let dir = TempDir::new();
+        let path = dir.0.join("annotations.json");
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+        engine.annotations = Annotations::load(path.clone());
+        let (annotation, write) = engine
+            .add_annotation(&uuid, &selected(1, 4), 1, "ringing starts here", "alice")
+            .unwrap();
+        let (_, stale_write) = engine
+            .add_annotation(&uuid, &selected(1, 4), 2, "ringing ends here", "alice")
+            .unwrap();
+        let deleted = engine.delete_annotation(annotation.id + 1).unwrap();
+
+        // Writes persisted out of order never replace a later snapshot.
+        write.persist().await.unwrap();
+        deleted.persist().await.unwrap();
+        stale_write.persist().await.unwrap();
+
+        // Drop the session, then find the same message by another search, in a restarted engine.
+        engine.sessions.remove(&uuid);
+        let mut engine = SessionEngine {
+            annotations: Annotations::load(path),
+            ..Default::default()
+        };
+        let target = SearchTarget {
+            mode: SearchTargetMode::Timestamp {
+                timestamp: DateTime::from_timestamp_millis(5).unwrap(),
+            },
+            by: SearchTargetBy::All,
+            number: 1,
+        };
+        let cache = Cache::from_traces([(metadata(5, 2), trace(4, 20))], [0]);
+        let found = engine.generate_key();
+        engine.sessions.insert(
+            found.clone(),
+            Session::from_saved(SavedSession::new(&target, &cache), 600),
+        );
+
+        assert_eq!(
+            engine.get_annotations(&found, &selected(0, 4)).unwrap(),
+            [annotation]
+        );
+    }
+
+    #[test]
+    fn unreadable_annotations_are_never_replaced() {
+        let dir = TempDir::new();
+        let path = dir.0.join("annotations.json");
+        let (mut engine, uuid) = engine_with_session(&dir.0);
+        std::fs::create_dir_all(&dir.0).unwrap();
+        std::fs::write(&path, "not json").unwrap();
+        engine.annotations = Annotations::load(path.clone());
+
+        assert!(matches!(
+            engine.get_annotations(&uuid, &selected(0, 7)),
+            Err(SessionError::AnnotationFormat(_))
+        ));
+        assert!(matches!(
+            annotate(&mut engine, &uuid, &selected(0, 7), 1, "ringing", "alice"),
+            Err(SessionError::AnnotationFormat(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
+    }
+
+    /// Inserts a session with a single digitiser message into `engine`, and returns its key.
+    fn insert_session(engine: &mut SessionEngine) -> String {
+        let target = SearchTarget {
+            mode: SearchTargetMode::Timestamp {
+                timestamp: DateTime::from_timestamp_millis(0).unwrap(),
+            },
+            by: SearchTargetBy::All,
+            number: 1,
+        };
+        let cache = Cache::from_traces([(metadata(0, 1), trace(0, 10))], [0]);
+        let key = engine.generate_key();
+        engine.sessions.insert(
+            key.clone(),
+            Session::from_saved(
+                SavedSession::new(&target, &cache),
+                engine.settings.session_ttl_sec,
+            ),
+        );
+        key
+    }
+
+    /// Returns an engine whose sessions expire after 600 seconds, with the given memory cap, and sets the mock clock.
+    fn engine_with_cap(memory_cap_bytes: Option<usize>) -> SessionEngine {
+        clock::set_mock_now(DateTime::from_timestamp_millis(0).unwrap());
+        SessionEngine {
+            settings: SessionEngineSettings {
+                session_ttl_sec: 600,
+                memory_cap_bytes,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn idle_sessions_are_purged() {
+        let mut engine = engine_with_cap(None);
+        let idle = insert_session(&mut engine);
+        clock::advance_mock_now(TimeDelta::seconds(300));
+        let refreshed = insert_session(&mut engine);
+        clock::advance_mock_now(TimeDelta::seconds(300));
+        engine.session_mut(&refreshed).unwrap().refresh();
+
+        // Neither session has been idle for longer than its time-to-live.
+        engine.purge_expired();
+        assert!(engine.session(&idle).is_ok());
+
+        clock::advance_mock_now(TimeDelta::seconds(1));
+        engine.purge_expired();
+        assert!(matches!(
+            engine.session(&idle),
+            Err(SessionError::DoesNotExist)
+        ));
+        assert!(engine.session(&refreshed).is_ok());
+    }
+
+    #[test]
+    fn sessions_are_evicted_least_recently_used_first() {
+        let mut engine = engine_with_cap(None);
+        let keys = (0..4)
+            .map(|_| {
+                clock::advance_mock_now(TimeDelta::seconds(1));
+                insert_session(&mut engine)
+            })
+            .collect::<Vec<_>>();
+        let size = engine.session(&keys[0]).unwrap().size_bytes();
+        assert!(size > 0);
+        assert_eq!(engine.memory_used_bytes(), 4 * size);
+
+        clock::advance_mock_now(TimeDelta::seconds(1));
+        engine.session_mut(&keys[0]).unwrap().refresh();
+
+        // Within the cap, nothing is evicted.
+        engine.settings.memory_cap_bytes = Some(4 * size);
+        assert!(engine.evict_least_recently_used().is_empty());
+
+        engine.settings.memory_cap_bytes = Some(2 * size);
+        assert_eq!(
+            engine.evict_least_recently_used(),
+            [keys[1].clone(), keys[2].clone()]
+        );
+        assert!(engine.session(&keys[0]).is_ok());
+        assert!(engine.session(&keys[3]).is_ok());
+        assert_eq!(engine.memory_used_bytes(), 2 * size);
+    }
+
+    #[test]
+    fn remaining_sessions_are_unchanged_by_eviction_and_purging() {
+        let mut engine = engine_with_cap(None);
+        let keys = (0..3)
+            .map(|_| {
+                clock::advance_mock_now(TimeDelta::seconds(1));
+                insert_session(&mut engine)
+            })
+            .collect::<Vec<_>>();
+        let page = |engine: &SessionEngine, key: &str| {
+            let session = engine.session(key).unwrap();
+            let summary = session.get_search_summaries().unwrap();
+            let page = session
+                .get_results_page(0, 10, SortResultsBy::Timestamp, None)
+                .unwrap();
+            (
+                summary.num_results,
+                page.traces
+                    .into_iter()
+                    .map(|trace| (trace.index, trace.id, trace.channels))
+                    .collect::<Vec<_>>(),
+                session.get_selected_trace(0).unwrap().1,
+            )
+        };
+        let before = page(&engine, &keys[2]);
+        assert_eq!(before.0, 1);
+        assert_eq!(
+            before.1.iter().map(|trace| trace.1).collect::<Vec<_>>(),
+            [1]
+        );
+
+        // The least recently used session is evicted.
+        let size = engine.memory_used_bytes() / 3;
+        engine.settings.memory_cap_bytes = Some(2 * size);
+        engine.purge_expired();
+        assert!(engine.session(&keys[0]).is_err());
+        assert_eq!(page(&engine, &keys[2]), before);
+
+        // Then the next once it has expired.
+        engine.settings.memory_cap_bytes = None;
+        clock::advance_mock_now(TimeDelta::seconds(600));
+        engine.session_mut(&keys[2]).unwrap().refresh();
+        engine.purge_expired();
+        assert!(engine.session(&keys[1]).is_err());
+        assert_eq!(page(&engine, &keys[2]), before);
+        assert_eq!(engine.memory_used_bytes(), size);
+    }
+
+    #[tokio::test]
+    async fn new_sessions_are_rejected_at_the_memory_cap() {
+        let dir = TempDir::new();
+        let mut engine = engine_with_cap(None);
+        engine.settings.saved_sessions_dir = dir.0.clone();
+        let uuid = insert_session(&mut engine);
+        save(&engine, &uuid, "session", false).await.unwrap();
+        let size = engine.memory_used_bytes();
+
+        engine.settings.memory_cap_bytes = Some(size + 1);
+        clock::advance_mock_now(TimeDelta::seconds(1));
+        load(&mut engine, "session").await.unwrap();
+
+        let target = engine
+            .session(&uuid)
+            .unwrap()
+            .get_search_summaries()
+            .unwrap()
+            .target;
+        assert!(matches!(
+            engine.create_new_search(target, vec![0]),
+            Err(SessionError::MemoryCapExceeded { used, cap }) if used == 2 * size && cap == size + 1
+        ));
+        assert!(matches!(
+            load(&mut engine, "session").await,
+            Err(SessionError::MemoryCapExceeded { .. })
+        ));
+
+        // Purging evicts the least recently used session, which makes room for another.
+        engine.purge_expired();
+        assert!(matches!(
+            engine.session(&uuid),
+            Err(SessionError::DoesNotExist)
+        ));
+        load(&mut engine, "session").await.unwrap();
+    }
+
+    #[test]
+    fn status_lists_most_recently_used_sessions_first() {
+        let mut engine = engine_with_cap(Some(1000));
+        let older = insert_session(&mut engine);
+        clock::advance_mock_now(TimeDelta::seconds(10));
+        let newer = insert_session(&mut engine);
+        clock::advance_mock_now(TimeDelta::seconds(5));
+
+        let status = engine.status();
+        let size = engine.session(&older).unwrap().size_bytes();
+        assert_eq!(status.memory_used_bytes, 2 * size);
+        assert_eq!(status.memory_cap_bytes, Some(1000));
+        assert_eq!(status.session_ttl_sec, 600);
+        let sessions = status
+            .sessions
+            .iter()
+            .map(|session| {
+                (
+                    session.key_prefix.as_str(),
+                    session.size_bytes,
+                    session.age_sec,
+                    session.idle_sec,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sessions,
+            [(&newer[..8], size, 5, 5), (&older[..8], size, 15, 15)]
+        );
+    }
+
+    #[test]
+    fn plots_are_shared_by_sessions_until_the_last_is_purged() {
+        let mut engine = engine_with_cap(None);
+        engine.settings.topics.digitiser_event_topic = vec!["daq".to_owned()];
+        engine.plot_cache = PlotCache::new(8, 1024);
+        let older = insert_session(&mut engine);
+        clock::advance_mock_now(TimeDelta::seconds(300));
+        let newer = insert_session(&mut engine);
+        let key = |engine: &SessionEngine, uuid: &str| {
+            engine
+                .plot_key(
+                    uuid,
+                    &selected(0, 0),
+                    &EventFilter::default(),
+                    &TraceView::whole_trace(Some(100)),
+                )
+                .unwrap()
+        };
+        let plot = TracePlotly {
+            title: "Channel 0 from Digitiser 1".to_owned(),
+            trace_data: Vec::new(),
+            eventlist_data: Vec::new(),
+            layout: String::new(),
+            range: None,
+            decimation_factor: 1,
+        };
+
+        let older_key = key(&engine, &older);
+        assert!(engine.cached_plot(&older, &older_key).is_none());
+        engine.cache_plot(&older, older_key, plot.clone());
+
+        // Both sessions found the same message, so share its plot.
+        let newer_key = key(&engine, &newer);
+        assert_eq!(
+            engine.cached_plot(&newer, &newer_key).unwrap().title,
+            plot.title
+        );
+        let status = engine.status().plot_cache;
+        assert_eq!((status.entries, status.hits, status.misses), (1, 1, 1));
+
+        // Annotating the channel changes its plot.
+        annotate(&mut engine, &newer, &selected(0, 0), 1, "ringing", "").unwrap();
+        assert_ne!(key(&engine, &newer), newer_key);
+
+        // The plot is kept whilst a session which used it remains.
+        clock::advance_mock_now(TimeDelta::seconds(301));
+        engine.purge_expired();
+        assert!(engine.session(&older).is_err());
+        assert_eq!(engine.status().plot_cache.entries, 1);
+
+        clock::advance_mock_now(TimeDelta::seconds(300));
+        engine.purge_expired();
+        assert!(engine.session(&newer).is_err());
+        assert_eq!(engine.status().plot_cache.entries, 0);
+    }
+
+    #[tokio::test]
+    async fn stopping_a_live_tail_ends_its_task() {
+        let mut engine = SessionEngine::default();
+        let (live_tail, aborted) = LiveTail::pending(600);
+        let uuid = engine.insert_live_tail(live_tail);
+
+        engine.stop_live_tail(&uuid).unwrap();
+        assert!(aborted.await.is_err());
+        assert!(matches!(
+            engine.stop_live_tail(&uuid),
+            Err(SessionError::LiveTailDoesNotExist)
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_live_tails_are_purged() {
+        clock::set_mock_now(DateTime::UNIX_EPOCH);
+        let mut engine = SessionEngine::default();
+        let (expired, expired_aborted) = LiveTail::pending(600);
+        let (live, mut live_aborted) = LiveTail::pending(600);
+        let expired_uuid = engine.insert_live_tail(expired);
+        let live_uuid = engine.insert_live_tail(live);
+
+        // Polling a live tail refreshes it, so only that which is not polled expires.
+        clock::advance_mock_now(TimeDelta::seconds(500));
+        engine.live_tail_mut(&live_uuid).unwrap().refresh();
+        clock::advance_mock_now(TimeDelta::seconds(200));
+        engine.purge_expired();
+        assert!(expired_aborted.await.is_err());
+        assert!(matches!(
+            engine.live_tail(&expired_uuid),
+            Err(SessionError::LiveTailDoesNotExist)
+        ));
+
+        assert!(engine.live_tail(&live_uuid).is_ok());
+        assert_eq!(
+            live_aborted.try_recv(),
+            Err(tokio::sync::oneshot::error::TryRecvError::Empty)
+        );
+    }
+}
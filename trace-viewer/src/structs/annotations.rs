@@ -0,0 +1,21 @@
+//! Describes the comments users attach to a channel of a trace message.
+use crate::{Time, Timestamp};
+use serde::{Deserialize, Serialize};
+
+/// Uniquely identifies an annotation within the annotation store.
+pub type AnnotationId = u64;
+
+/// A comment on a time bin of a channel of a trace message. Should be created by [add_annotation()].
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Identifies the annotation, so it can be deleted.
+    pub id: AnnotationId,
+    /// The time bin of the trace to which the comment refers.
+    pub time_bin: Time,
+    /// The comment.
+    pub text: String,
+    /// Who made the comment.
+    pub author: String,
+    /// When the comment was made.
+    pub created: Timestamp,
+}
@@ -0,0 +1,165 @@
+//! Defines the detectors which can be run on a displayed trace.
+use crate::{Intensity, structs::SelectedTraceIndex};
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, EnumString};
+
+/// The polarity of the trace signal, i.e. whether events cause positive or negative signals.
+#[derive(
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+pub enum DetectorPolarity {
+    #[default]
+    Positive,
+    Negative,
+}
+
+/// The detectors of the trace-to-events component which can be run from the viewer, with their parameters.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum DetectorMode {
+    /// See the `fixed-threshold-discriminator` mode of trace-to-events.
+    FixedThreshold {
+        threshold: f64,
+        duration: usize,
+        cool_off: usize,
+    },
+    /// See the `adaptive-threshold-discriminator` mode of trace-to-events.
+    AdaptiveThreshold {
+        sigma_threshold: f64,
+        noise_window_size: usize,
+        duration: usize,
+        cool_off: usize,
+    },
+    /// See the `differential-threshold-discriminator` mode of trace-to-events.
+    DifferentialThreshold {
+        begin_threshold: f64,
+        begin_duration: usize,
+        end_threshold: f64,
+        end_duration: usize,
+        cool_off: usize,
+    },
+}
+
+/// Encapsulates a detector and the settings of the trace signal it is run on.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DetectorConfig {
+    /// The detector to run, and its parameters.
+    pub mode: DetectorMode,
+    /// The polarity of the trace signal.
+    pub polarity: DetectorPolarity,
+    /// The baseline of the trace signal.
+    pub baseline: Intensity,
+}
+
+#[cfg(feature = "ssr")]
+impl DetectorConfig {
+    /// Returns the legend text for the events found by this detector.
+    pub(crate) fn legend_name(&self, num_events: usize) -> String {
+        let parameters = match &self.mode {
+            DetectorMode::FixedThreshold {
+                threshold,
+                duration,
+                cool_off,
+            } => format!(
+                "Fixed Threshold: threshold {threshold}, duration {duration}, cool off {cool_off}"
+            ),
+            DetectorMode::AdaptiveThreshold {
+                sigma_threshold,
+                noise_window_size,
+                duration,
+                cool_off,
+            } => format!(
+                "Adaptive Threshold: sigma {sigma_threshold}, window {noise_window_size}, duration {duration}, cool off {cool_off}"
+            ),
+            DetectorMode::DifferentialThreshold {
+                begin_threshold,
+                begin_duration,
+                end_threshold,
+                end_duration,
+                cool_off,
+            } => format!(
+                "Differential Threshold: begin {begin_threshold} for {begin_duration}, end {end_threshold} for {end_duration}, cool off {cool_off}"
+            ),
+        };
+        format!(
+            "{parameters}, {} polarity, baseline {} ({num_events} events)",
+            self.polarity, self.baseline
+        )
+    }
+}
+
+/// The events found by running a detector on a trace, created by [run_detector_on_trace()].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DetectorEvents {
+    /// The trace message and channel the detector was run on.
+    pub index_and_channel: SelectedTraceIndex,
+    /// Json string of the event list data plotly graph.
+    pub eventlist_data: String,
+}
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use trace_to_events::{
+            AdaptiveThresholdDiscriminatorParameters, DifferentialThresholdDiscriminatorParameters,
+            FixedThresholdDiscriminatorParameters, Mode, Polarity,
+        };
+
+        impl DetectorMode {
+            /// Converts to the corresponding trace-to-events [Mode].
+            pub(crate) fn to_mode(&self) -> Mode {
+                match *self {
+                    Self::FixedThreshold { threshold, duration, cool_off } => {
+                        Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                            threshold,
+                            duration,
+                            cool_off,
+                            disarm_threshold: None,
+                            veto_threshold: None,
+                            veto_extend: 0,
+                        })
+                    }
+                    Self::AdaptiveThreshold { sigma_threshold, noise_window_size, duration, cool_off } => {
+                        Mode::AdaptiveThresholdDiscriminator(AdaptiveThresholdDiscriminatorParameters {
+                            sigma_threshold,
+                            noise_window_size,
+                            duration,
+                            cool_off,
+                        })
+                    }
+                    Self::DifferentialThreshold { begin_threshold, begin_duration, end_threshold, end_duration, cool_off } => {
+                        Mode::DifferentialThresholdDiscriminator(DifferentialThresholdDiscriminatorParameters {
+                            begin_threshold,
+                            begin_duration,
+                            end_threshold,
+                            end_duration,
+                            cool_off,
+                            peak_height_mode: Default::default(),
+                            peak_height_basis: Default::default(),
+                            ..Default::default()
+                        })
+                    }
+                }
+            }
+        }
+
+        impl From<DetectorPolarity> for Polarity {
+            fn from(value: DetectorPolarity) -> Self {
+                match value {
+                    DetectorPolarity::Positive => Polarity::Positive,
+                    DetectorPolarity::Negative => Polarity::Negative,
+                }
+            }
+        }
+    }
+}
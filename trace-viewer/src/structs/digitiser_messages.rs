@@ -32,7 +32,7 @@ pub(crate) struct DigitiserMetadata {
 
 /// Encapsulates all traces of a digitiser trace message.
 #[allow(dead_code)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub(crate) struct DigitiserTrace {
     /// Maps channels to traces.
     pub(crate) traces: HashMap<Channel, Trace>,
@@ -61,10 +61,41 @@ pub(crate) type DigitiserEventList = HashMap<Channel, EventList>;
 cfg_if! {
     if #[cfg(feature = "ssr")] {
         use digital_muon_streaming_types::{
+            FrameMetadata,
             dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage,
             dev2_digitizer_event_v2_generated::DigitizerEventListMessage,
         };
 
+        impl DigitiserMetadata {
+            /// Creates the metadata of a digitiser message.
+            /// # Parameters
+            /// - id: the digitiser which sent the message.
+            /// - metadata: the metadata of the frame the message belongs to.
+            pub(crate) fn new(id: DigitizerId, metadata: &FrameMetadata) -> Self {
+                Self {
+                    timestamp: metadata.timestamp,
+                    id,
+                    frame_number: metadata.frame_number,
+                    period_number: metadata.period_number,
+                    protons_per_pulse: metadata.protons_per_pulse,
+                    running: metadata.running,
+                    veto_flags: metadata.veto_flags,
+                }
+            }
+
+            /// Returns the metadata of the frame the message belongs to.
+            pub(crate) fn frame_metadata(&self) -> FrameMetadata {
+                FrameMetadata {
+                    timestamp: self.timestamp,
+                    period_number: self.period_number,
+                    protons_per_pulse: self.protons_per_pulse,
+                    running: self.running,
+                    frame_number: self.frame_number,
+                    veto_flags: self.veto_flags,
+                }
+            }
+        }
+
         /// Provides method for creating object from a generic message.
         ///
         /// This trait is used instead of [From<&M>] so it can be implemented for [DigitizerEventListMessage],
@@ -0,0 +1,47 @@
+//! Describes the sessions held by the server, and the memory they use.
+use serde::{Deserialize, Serialize};
+
+/// The state of the session engine. Should be created by [get_engine_status()].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EngineStatus {
+    /// The sessions, most recently used first.
+    pub sessions: Vec<SessionStatus>,
+    /// The estimated bytes held by the results of every session.
+    pub memory_used_bytes: usize,
+    /// If present, new searches are rejected whilst the sessions hold at least this many bytes.
+    pub memory_cap_bytes: Option<usize>,
+    /// The time, in seconds, after its last refresh when a session expires.
+    pub session_ttl_sec: i64,
+    /// The state of the cache of plots shared by every session.
+    pub plot_cache: PlotCacheStatus,
+}
+
+/// The state of the cache of plots, see [create_and_fetch_plotly()].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlotCacheStatus {
+    /// The number of plots cached.
+    pub entries: usize,
+    /// The bytes of the json of the plots cached.
+    pub size_bytes: usize,
+    /// The most plots cached.
+    pub max_entries: usize,
+    /// The most bytes of the json of the plots cached.
+    pub max_bytes: usize,
+    /// The number of plots fetched from the cache.
+    pub hits: u64,
+    /// The number of plots generated because they were not cached.
+    pub misses: u64,
+}
+
+/// The state of a single session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionStatus {
+    /// The start of the session's key, which identifies the session without allowing its results to be accessed.
+    pub key_prefix: String,
+    /// The estimated bytes held by the session's results.
+    pub size_bytes: usize,
+    /// The time, in seconds, since the session was created.
+    pub age_sec: i64,
+    /// The time, in seconds, since the session was last refreshed.
+    pub idle_sec: i64,
+}
@@ -0,0 +1,176 @@
+//! Matches the events of a channel captured from two eventlist topics, so that their differences can be displayed.
+use crate::{
+    Time,
+    structs::{SelectedTraceIndex, TracePlotly, digitiser_messages::Event},
+};
+use serde::{Deserialize, Serialize};
+
+/// The events of two event lists matched in pairs whose times differ by no more than a tolerance.
+/// Should be created by [EventMatching::new].
+#[derive(Default, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EventMatching {
+    /// The matched pairs, the first of each from the first list and the second from the second, in order of the first's time.
+    pub(crate) matched: Vec<(Event, Event)>,
+    /// The events of the first list which are not matched, in their original order.
+    pub(crate) only_in_a: Vec<Event>,
+    /// The events of the second list which are not matched, in their original order.
+    pub(crate) only_in_b: Vec<Event>,
+}
+
+#[cfg(feature = "ssr")]
+impl EventMatching {
+    /// Matches the events of `a` with those of `b` by greedy nearest-neighbour matching.
+    /// Of all pairs whose times differ by no more than `time_tolerance`, the closest is matched first,
+    /// then the closest of the remaining pairs whose events are both unmatched, and so on.
+    /// Ties are broken in favour of the earlier events of `a`, then of `b`, so that
+    /// events with duplicate times are matched in their original order.
+    pub(crate) fn new(a: &[Event], b: &[Event], time_tolerance: Time) -> Self {
+        // The indices of `b` in order of time, so the events near to each of `a` form a contiguous range.
+        let mut b_by_time = (0..b.len()).collect::<Vec<_>>();
+        b_by_time.sort_by_key(|&j| b[j].time);
+
+        let mut candidates = a
+            .iter()
+            .enumerate()
+            .flat_map(|(i, event)| {
+                let start = b_by_time
+                    .partition_point(|&j| b[j].time < event.time.saturating_sub(time_tolerance));
+                let end = b_by_time
+                    .partition_point(|&j| b[j].time <= event.time.saturating_add(time_tolerance));
+                b_by_time[start..end]
+                    .iter()
+                    .map(move |&j| (event.time.abs_diff(b[j].time), i, j))
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+
+        let mut a_matched = vec![false; a.len()];
+        let mut b_matched = vec![false; b.len()];
+        let mut matched = Vec::new();
+        for (_, i, j) in candidates {
+            if !a_matched[i] && !b_matched[j] {
+                a_matched[i] = true;
+                b_matched[j] = true;
+                matched.push((a[i], b[j]));
+            }
+        }
+        matched.sort_by_key(|(event, _)| event.time);
+
+        let unmatched = |events: &[Event], is_matched: &[bool]| {
+            events
+                .iter()
+                .zip(is_matched)
+                .filter_map(|(event, &is_matched)| (!is_matched).then_some(*event))
+                .collect()
+        };
+        Self {
+            only_in_a: unmatched(a, &a_matched),
+            only_in_b: unmatched(b, &b_matched),
+            matched,
+        }
+    }
+
+    /// Returns the statistics of the matching, in which the lists are named by the topics they were captured from.
+    pub(crate) fn summary(&self, topic_a: &str, topic_b: &str, time_tolerance: Time) -> String {
+        format!(
+            "{} matched within {time_tolerance} samples, {} only in {topic_a}, {} only in {topic_b}.",
+            self.matched.len(),
+            self.only_in_a.len(),
+            self.only_in_b.len()
+        )
+    }
+}
+
+/// The comparison of the events of a trace's channel captured from two eventlist topics.
+/// Should be created by [compare_eventlists()].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventListComparison {
+    /// The trace message and channel whose events are compared.
+    pub index_and_channel: SelectedTraceIndex,
+    /// The events of the first topic matched with those of the second.
+    pub matching: EventMatching,
+    /// The plot of the trace with the events of both topics, in which the unmatched events are emphasised.
+    pub trace_plotly: TracePlotly,
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn events(events: &[(Time, u16)]) -> Vec<Event> {
+        events
+            .iter()
+            .map(|&(time, intensity)| Event { time, intensity })
+            .collect()
+    }
+
+    #[test]
+    fn nearest_events_are_matched() {
+        let a = events(&[(10, 1), (20, 2), (50, 3)]);
+        let b = events(&[(22, 4), (11, 5), (80, 6)]);
+        let matching = EventMatching::new(&a, &b, 3);
+        assert_eq!(matching.matched, vec![(a[0], b[1]), (a[1], b[0])]);
+        assert_eq!(matching.only_in_a, vec![a[2]]);
+        assert_eq!(matching.only_in_b, vec![b[2]]);
+        assert_eq!(
+            matching.summary("old", "new", 3),
+            "2 matched within 3 samples, 1 only in old, 1 only in new."
+        );
+    }
+
+    #[test]
+    fn closest_pair_is_matched_first() {
+        // The event of `b` at 14 is nearer to that of `a` at 15 than to that at 10,
+        // so the latter is matched with the event at 8 instead.
+        let a = events(&[(10, 1), (15, 2)]);
+        let b = events(&[(8, 3), (14, 4)]);
+        let matching = EventMatching::new(&a, &b, 5);
+        assert_eq!(matching.matched, vec![(a[0], b[0]), (a[1], b[1])]);
+        assert!(matching.only_in_a.is_empty());
+        assert!(matching.only_in_b.is_empty());
+    }
+
+    #[test]
+    fn duplicate_times_are_matched_once_each() {
+        let a = events(&[(10, 1), (10, 2), (30, 3)]);
+        let b = events(&[(10, 4), (30, 5), (30, 6)]);
+        let matching = EventMatching::new(&a, &b, 0);
+        assert_eq!(matching.matched, vec![(a[0], b[0]), (a[2], b[1])]);
+        assert_eq!(matching.only_in_a, vec![a[1]]);
+        assert_eq!(matching.only_in_b, vec![b[2]]);
+    }
+
+    #[test]
+    fn empty_lists_are_unmatched() {
+        let a = events(&[(10, 1), (20, 2)]);
+        let matching = EventMatching::new(&a, &[], 10);
+        assert!(matching.matched.is_empty());
+        assert_eq!(matching.only_in_a, a);
+        assert!(matching.only_in_b.is_empty());
+
+        let matching = EventMatching::new(&[], &a, 10);
+        assert!(matching.matched.is_empty());
+        assert!(matching.only_in_a.is_empty());
+        assert_eq!(matching.only_in_b, a);
+
+        assert_eq!(EventMatching::new(&[], &[], 10), EventMatching::default());
+    }
+
+    #[test]
+    fn zero_tolerance_matches_equal_times_only() {
+        let a = events(&[(10, 1), (20, 2)]);
+        let b = events(&[(11, 3), (20, 4)]);
+        let matching = EventMatching::new(&a, &b, 0);
+        assert_eq!(matching.matched, vec![(a[1], b[1])]);
+        assert_eq!(matching.only_in_a, vec![a[0]]);
+        assert_eq!(matching.only_in_b, vec![b[0]]);
+    }
+
+    #[test]
+    fn tolerance_is_inclusive_without_overflow() {
+        let a = events(&[(2, 1), (Time::MAX, 2)]);
+        let b = events(&[(0, 3), (Time::MAX - 2, 4)]);
+        let matching = EventMatching::new(&a, &b, 2);
+        assert_eq!(matching.matched, vec![(a[0], b[0]), (a[1], b[1])]);
+    }
+}
@@ -0,0 +1,146 @@
+//! Defines the keyboard shortcuts which can be set on the command line.
+use leptos::ev::KeyboardEvent;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use strum::{Display, EnumString};
+use thiserror::Error;
+
+/// The modifier key which must be held for a [KeyboardShortcut] to trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Modifier {
+    Ctrl,
+    Alt,
+    Shift,
+    Meta,
+}
+
+/// The modifier keys held when a key is pressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeldModifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+}
+
+impl From<&KeyboardEvent> for HeldModifiers {
+    fn from(ev: &KeyboardEvent) -> Self {
+        Self {
+            ctrl: ev.ctrl_key(),
+            alt: ev.alt_key(),
+            shift: ev.shift_key(),
+            meta: ev.meta_key(),
+        }
+    }
+}
+
+impl HeldModifiers {
+    /// Returns true if any modifier key is held.
+    pub fn any(&self) -> bool {
+        self.ctrl || self.alt || self.shift || self.meta
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum KeyboardShortcutError {
+    #[error("Keyboard shortcut should be of the form <MODIFIER>+<KEY>, found: {0}")]
+    Format(String),
+    #[error("Unknown modifier {0}, should be one of ctrl, alt, shift or meta")]
+    Modifier(String),
+}
+
+/// A key which triggers an action when pressed whilst its modifier key is held,
+/// written as `<MODIFIER>+<KEY>`, for instance `alt+e`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyboardShortcut {
+    pub modifier: Modifier,
+    /// The key, compared case insensitively to the key of the keyboard event.
+    pub key: String,
+}
+
+impl KeyboardShortcut {
+    /// Returns true if `key` pressed whilst `held` triggers the shortcut.
+    /// The shortcut is not triggered if any modifier key other than its own is also held,
+    /// except for shift, which is allowed so that the case of `key` does not matter.
+    pub fn matches(&self, key: &str, held: HeldModifiers) -> bool {
+        let modifier_held = match self.modifier {
+            Modifier::Ctrl => held.ctrl && !held.alt && !held.meta,
+            Modifier::Alt => held.alt && !held.ctrl && !held.meta,
+            Modifier::Shift => held.shift && !held.ctrl && !held.alt && !held.meta,
+            Modifier::Meta => held.meta && !held.ctrl && !held.alt,
+        };
+        modifier_held && key.eq_ignore_ascii_case(&self.key)
+    }
+}
+
+impl FromStr for KeyboardShortcut {
+    type Err = KeyboardShortcutError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (modifier, key) = s
+            .split_once('+')
+            .filter(|(_, key)| !key.is_empty())
+            .ok_or_else(|| KeyboardShortcutError::Format(s.to_owned()))?;
+        Ok(Self {
+            modifier: modifier
+                .trim()
+                .parse()
+                .map_err(|_| KeyboardShortcutError::Modifier(modifier.to_owned()))?,
+            key: key.trim().to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for KeyboardShortcut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.modifier, self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(ctrl: bool, alt: bool, shift: bool) -> HeldModifiers {
+        HeldModifiers {
+            ctrl,
+            alt,
+            shift,
+            meta: false,
+        }
+    }
+
+    #[test]
+    fn shortcuts_are_parsed() {
+        let shortcut: KeyboardShortcut = "Alt+e".parse().unwrap();
+        assert_eq!(shortcut.modifier, Modifier::Alt);
+        assert_eq!(shortcut.key, "e");
+        assert_eq!(shortcut.to_string(), "alt+e");
+        // The plus key can itself be the key.
+        assert_eq!("ctrl++".parse::<KeyboardShortcut>().unwrap().key, "+");
+
+        assert!(matches!(
+            "e".parse::<KeyboardShortcut>(),
+            Err(KeyboardShortcutError::Format(_))
+        ));
+        assert!(matches!(
+            "alt+".parse::<KeyboardShortcut>(),
+            Err(KeyboardShortcutError::Format(_))
+        ));
+        assert!(matches!(
+            "hyper+e".parse::<KeyboardShortcut>(),
+            Err(KeyboardShortcutError::Modifier(_))
+        ));
+    }
+
+    #[test]
+    fn shortcut_requires_its_modifier_only() {
+        let shortcut: KeyboardShortcut = "alt+e".parse().unwrap();
+        assert!(shortcut.matches("e", held(false, true, false)));
+        assert!(shortcut.matches("E", held(false, true, true)));
+        assert!(!shortcut.matches("e", held(false, false, false)));
+        assert!(!shortcut.matches("e", held(true, true, false)));
+        assert!(!shortcut.matches("f", held(false, true, false)));
+    }
+}
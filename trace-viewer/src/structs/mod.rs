@@ -3,18 +3,35 @@
 //! These fall into two categories:
 //! - Server-side only: these are gated behind the "ssr" feature flag.
 //! - Client-Server transferable: these must implement [Clone], [Debug], [Serialize] and [Deserialize].
+mod annotations;
 mod broker_info;
+mod detector;
 mod digitiser_messages;
+mod engine_status;
+mod event_matching;
+mod keyboard_shortcut;
+mod runs;
 mod search;
+mod statistics;
 mod trace_messages;
 
 use crate::{Channel, DigitizerId, Timestamp};
 use cfg_if::cfg_if;
 use serde::{Deserialize, Serialize};
 
+pub use annotations::{Annotation, AnnotationId};
 pub use broker_info::{BrokerInfo, BrokerTopicInfo};
-pub use search::{SearchTarget, SearchTargetBy, SearchTargetMode};
-pub use trace_messages::{SearchSummary, SelectedTraceIndex, TracePlotly, TraceSummary};
+pub use detector::{DetectorConfig, DetectorEvents, DetectorMode, DetectorPolarity};
+pub use engine_status::{EngineStatus, PlotCacheStatus, SessionStatus};
+pub use event_matching::{EventListComparison, EventMatching};
+pub use keyboard_shortcut::{HeldModifiers, KeyboardShortcut, KeyboardShortcutError, Modifier};
+pub use runs::{RunAnnotation, RunInfo};
+pub use search::{SearchProgress, SearchTarget, SearchTargetBy, SearchTargetMode};
+pub use statistics::{ChannelStatistics, TraceStatistics};
+pub use trace_messages::{
+    EventFilter, HistogramQuantity, MultiTracePlotly, PlotlyJs, ResultsPage, SearchSummary,
+    SelectedTraceIndex, SortResultsBy, TimeRange, TracePlotly, TraceSummary, TraceView,
+};
 use url::Url;
 
 cfg_if! {
@@ -23,10 +40,12 @@ cfg_if! {
 
         use clap::Args; // This should be imported only for server-side use.
 
-        pub(crate) use digitiser_messages::{DigitiserMetadata, DigitiserTrace, EventList, Trace};
+        pub(crate) use digitiser_messages::{DigitiserEventList, DigitiserMetadata, DigitiserTrace, Event, FromMessage, Trace};
         pub(crate) use server_only::{Cache, BorrowedMessageError, SearchResults, EventListMessage, FBMessage, TraceMessage};
 
         pub use server_only::ServerSideData;
+        #[cfg(test)]
+        pub(crate) use server_only::search_results_test_utils;
     }
 }
 
@@ -41,6 +60,10 @@ pub struct Topics {
     /// Kafka digitiser event list topic.
     #[cfg_attr(feature = "ssr", clap(long))]
     pub digitiser_event_topic: Vec<String>,
+
+    /// Kafka run control topic. If set, the runs recorded on it can be selected when searching.
+    #[cfg_attr(feature = "ssr", clap(long))]
+    pub control_topic: Option<String>,
 }
 
 /// Contains the settings defined in the CLI used as default values in the UI's inputs.
@@ -78,4 +101,6 @@ pub struct ClientSideData {
     pub link_to_redpanda_console: Option<String>,
     pub refresh_session_interval_sec: u64,
     pub public_url: Url,
+    /// Exports the plot of the selected channel, see [KeyboardShortcut].
+    pub export_shortcut: KeyboardShortcut,
 }
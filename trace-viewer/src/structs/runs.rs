@@ -0,0 +1,114 @@
+//! Describes the runs recorded by the run start and run stop messages of the control topic.
+use crate::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// A run, derived from the control topic. Should be created by [get_runs()].
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RunInfo {
+    /// Name of the run.
+    pub name: String,
+    /// Time at which the run started.
+    pub start: Timestamp,
+    /// Time at which the run stopped, or absent if it is ongoing.
+    /// If the run's stop message is missing, this is the start of the next run.
+    pub stop: Option<Timestamp>,
+}
+
+impl Display for RunInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} to ",
+            self.name,
+            self.start.format("%y-%m-%d %H:%M:%S")
+        )?;
+        match self.stop {
+            Some(stop) => write!(f, "{}", stop.format("%y-%m-%d %H:%M:%S")),
+            None => write!(f, "ongoing"),
+        }
+    }
+}
+
+/// The run during which a message occurred.
+#[derive(Default, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum RunAnnotation {
+    /// The runs are not known, as the control topic is not set, or has not been scanned.
+    #[default]
+    Unknown,
+    /// The message occurred during the named run.
+    Run(String),
+    /// The message occurred outside of every run.
+    BetweenRuns,
+}
+
+#[cfg(feature = "ssr")]
+impl RunAnnotation {
+    /// Returns the annotation of a message at `timestamp`.
+    /// # Parameters
+    /// - runs: the known runs, if the control topic has been scanned.
+    /// - timestamp: the time of the message.
+    pub(crate) fn new(runs: Option<&[RunInfo]>, timestamp: &Timestamp) -> Self {
+        let Some(runs) = runs else {
+            return Self::Unknown;
+        };
+        runs.iter()
+            .find(|run| run.start <= *timestamp && run.stop.is_none_or(|stop| *timestamp < stop))
+            .map(|run| Self::Run(run.name.clone()))
+            .unwrap_or(Self::BetweenRuns)
+    }
+}
+
+impl Display for RunAnnotation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "unknown"),
+            Self::Run(name) => write!(f, "{name}"),
+            Self::BetweenRuns => write!(f, "between runs"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn time(ms: i64) -> Timestamp {
+        DateTime::from_timestamp_millis(ms).unwrap()
+    }
+
+    fn run(name: &str, start: i64, stop: Option<i64>) -> RunInfo {
+        RunInfo {
+            name: name.to_owned(),
+            start: time(start),
+            stop: stop.map(time),
+        }
+    }
+
+    #[test]
+    fn messages_are_annotated_with_their_run() {
+        let runs = [
+            run("A", 10, Some(20)),
+            run("B", 30, Some(40)),
+            run("C", 40, None),
+        ];
+        let annotate = |ms| RunAnnotation::new(Some(&runs), &time(ms));
+        assert_eq!(annotate(5), RunAnnotation::BetweenRuns);
+        assert_eq!(annotate(10), RunAnnotation::Run("A".to_owned()));
+        assert_eq!(annotate(19), RunAnnotation::Run("A".to_owned()));
+        // Runs exclude their stop time.
+        assert_eq!(annotate(20), RunAnnotation::BetweenRuns);
+        assert_eq!(annotate(40), RunAnnotation::Run("C".to_owned()));
+        assert_eq!(annotate(1000), RunAnnotation::Run("C".to_owned()));
+    }
+
+    #[test]
+    fn unscanned_runs_are_unknown() {
+        assert_eq!(RunAnnotation::new(None, &time(0)), RunAnnotation::Unknown);
+        assert_eq!(
+            RunAnnotation::new(Some(&[]), &time(0)),
+            RunAnnotation::BetweenRuns
+        );
+    }
+}
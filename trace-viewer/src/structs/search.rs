@@ -17,6 +17,9 @@ pub enum SearchTargetMode {
         timestamp: Timestamp,
         backstep: i64,
         forward_distance: usize,
+        /// If present, only traces timestamped at or before this are matched, for instance the stop of a run.
+        #[serde(default)]
+        until: Option<Timestamp>,
     },
 }
 
@@ -26,3 +29,20 @@ pub enum SearchTargetBy {
     ByChannels { channels: Vec<Channel> },
     ByDigitiserIds { digitiser_ids: Vec<DigitizerId> },
 }
+
+/// The progress of a session's search, which the client polls whilst the search runs.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchProgress {
+    /// The number of messages received from the broker, of the trace and event list topics.
+    pub messages_scanned: usize,
+    /// The number of messages received which match the search.
+    pub matches_found: usize,
+    /// The timestamp of the last message received.
+    pub position: Option<Timestamp>,
+    /// The estimated fraction of the search complete, between zero and one.
+    pub fraction_complete: f64,
+    /// True if the search has been cancelled, in which case its results are those found before it stopped.
+    pub cancelled: bool,
+    /// True once the search has stopped, whether it completed or was cancelled.
+    pub finished: bool,
+}
@@ -9,6 +9,8 @@ pub(crate) use borrowed_messages::{
     BorrowedMessageError, EventListMessage, FBMessage, TraceMessage,
 };
 pub(crate) use search_results::{Cache, SearchResults};
+#[cfg(test)]
+pub(crate) use search_results::test_utils as search_results_test_utils;
 
 /// Encapsulates all run-time settings which are only available to the server.
 #[derive(Default, Clone)]
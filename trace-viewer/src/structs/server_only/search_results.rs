@@ -1,17 +1,22 @@
 use crate::{
+    Intensity,
     app::SessionError,
     structs::digitiser_messages::{
-        DigitiserEventList, DigitiserMetadata, DigitiserTrace, FromMessage,
+        DigitiserEventList, DigitiserMetadata, DigitiserTrace, Event, FromMessage,
     },
 };
+use digital_muon_common::{Channel, DigitizerId, FrameKey};
 use digital_muon_streaming_types::{
-    dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage,
+    FrameMetadata,
+    dat2_digitizer_analog_trace_v2_generated::{
+        DigitizerAnalogTraceMessage, root_as_digitizer_analog_trace_message,
+    },
     dev2_digitizer_event_v2_generated::DigitizerEventListMessage,
     time_conversions::GpsTimeConversionError,
 };
-use std::collections::{
-    BTreeMap,
-    btree_map::{self, Entry},
+use std::{
+    collections::{BTreeMap, HashMap, btree_map::Entry, hash_map},
+    mem,
 };
 use tracing::{error, info};
 
@@ -30,54 +35,293 @@ impl SearchResults {
     }
 }
 
+/// Identifies the message of a digitiser for a given frame.
+///
+/// Frames are identified by [FrameKey], ignoring veto flags, so event lists are matched
+/// to traces in the same way the digitiser aggregator matches digitiser messages to frames.
+type MessageKey = (DigitizerId, FrameKey);
+
+fn message_key(id: DigitizerId, metadata: &FrameMetadata) -> MessageKey {
+    (id, FrameKey::from(metadata))
+}
+
+/// The traces of a cached digitiser message.
+#[derive(Debug, Clone)]
+enum TracePayload {
+    /// The bytes of the [DigitizerAnalogTraceMessage] as received, which are decoded each time the message is selected.
+    Raw(Vec<u8>),
+    /// Traces which were decoded before being cached, such as those of a saved session.
+    Decoded(DigitiserTrace),
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The number of raw payloads decoded on this thread, so tests can check that decoding is deferred.
+    static PAYLOADS_DECODED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// A digitiser message held by the [Cache].
+#[derive(Debug, Clone)]
+struct CachedTrace {
+    /// The channels of the message, in the order they appear in it.
+    channels: Vec<Channel>,
+    payload: TracePayload,
+    /// Maps eventlist topic indices to the event list attached to the message.
+    events: HashMap<usize, DigitiserEventList>,
+}
+
+impl CachedTrace {
+    /// Creates the cached message from `msg`, and `payload`, the bytes as received from which it was unpacked.
+    fn from_message(msg: &DigitizerAnalogTraceMessage<'_>, payload: &[u8]) -> Self {
+        Self {
+            channels: msg
+                .channels()
+                .into_iter()
+                .flatten()
+                .map(|channel| channel.channel())
+                .collect(),
+            payload: TracePayload::Raw(payload.to_vec()),
+            events: Default::default(),
+        }
+    }
+
+    fn from_trace(mut trace: DigitiserTrace) -> Self {
+        Self {
+            channels: trace.traces.keys().copied().collect(),
+            events: mem::take(&mut trace.events),
+            payload: TracePayload::Decoded(trace),
+        }
+    }
+
+    /// Returns an estimate of the bytes held by the message's payload and attached event lists.
+    fn size_bytes(&self) -> usize {
+        let payload = match &self.payload {
+            TracePayload::Raw(bytes) => bytes.len(),
+            TracePayload::Decoded(trace) => trace
+                .traces
+                .values()
+                .map(|trace| trace.len() * size_of::<Intensity>())
+                .sum(),
+        };
+        payload + self.events.values().map(event_list_size).sum::<usize>()
+    }
+
+    /// Returns the traces of the message, with its event lists attached.
+    fn decode(&self) -> DigitiserTrace {
+        let mut trace = match &self.payload {
+            TracePayload::Raw(bytes) => {
+                #[cfg(test)]
+                PAYLOADS_DECODED.set(PAYLOADS_DECODED.get() + 1);
+                DigitiserTrace::from_message(
+                    &root_as_digitizer_analog_trace_message(bytes)
+                        .expect("Payload was unpacked when cached, this should never fail."),
+                )
+            }
+            TracePayload::Decoded(trace) => trace.clone(),
+        };
+        trace.events.extend(
+            self.events
+                .iter()
+                .map(|(&topic, events)| (topic, events.clone())),
+        );
+        trace
+    }
+}
+
+/// Inserts `metadata` into `ordered`, which is in ascending order, keeping it so.
+fn insert_sorted(ordered: &mut Vec<DigitiserMetadata>, metadata: DigitiserMetadata) {
+    let position = ordered
+        .binary_search(&metadata)
+        .unwrap_or_else(|position| position);
+    ordered.insert(position, metadata);
+}
+
+/// Returns an estimate of the bytes held by the events of `events`.
+fn event_list_size(events: &DigitiserEventList) -> usize {
+    events
+        .values()
+        .map(|events| events.len() * size_of::<Event>())
+        .sum()
+}
+
+/// Holds the results of a search.
+///
+/// Trace messages are kept as received, and only decoded when requested, so large searches can be held and listed cheaply.
 #[derive(Debug, Clone)]
 pub struct Cache {
-    traces: BTreeMap<DigitiserMetadata, DigitiserTrace>,
-    events: BTreeMap<usize, BTreeMap<DigitiserMetadata, DigitiserEventList>>,
+    traces: BTreeMap<DigitiserMetadata, CachedTrace>,
+    /// Maps the [MessageKey] of each trace to its key in `traces`.
+    trace_keys: HashMap<MessageKey, DigitiserMetadata>,
+    /// Maps the index of each eventlist topic to its event lists, so topics are never looked up by name.
+    events: BTreeMap<usize, BTreeMap<MessageKey, DigitiserEventList>>,
+    /// The keys of `traces`, in the same order as [Self::iter_channels].
+    ///
+    /// This allows traces to be selected by index without walking the map.
+    ordered: Vec<DigitiserMetadata>,
+    /// The keys of `traces` of each digitiser, in the same order as [Self::iter_channels].
+    by_digitiser: BTreeMap<DigitizerId, Vec<DigitiserMetadata>>,
 }
 
 impl Cache {
     pub(crate) fn new() -> Self {
         Self {
             traces: Default::default(),
+            trace_keys: Default::default(),
             events: Default::default(),
+            ordered: Default::default(),
+            by_digitiser: Default::default(),
+        }
+    }
+
+    /// Creates a cache from traces whose event lists are already attached, such as those of a saved session.
+    ///
+    /// Topics in `eventlist_topic_indices` are listed by [Self::get_eventlist_topic_indices] even if none of their event lists are attached.
+    pub(crate) fn from_traces(
+        traces: impl IntoIterator<Item = (DigitiserMetadata, DigitiserTrace)>,
+        eventlist_topic_indices: impl IntoIterator<Item = usize>,
+    ) -> Self {
+        let mut cache = Self::new();
+        for topic_index in eventlist_topic_indices {
+            cache.events.entry(topic_index).or_default();
+        }
+        for (metadata, trace) in traces {
+            let key = message_key(metadata.id, &metadata.frame_metadata());
+            for (&topic_index, events) in &trace.events {
+                cache
+                    .events
+                    .entry(topic_index)
+                    .or_default()
+                    .insert(key.clone(), events.clone());
+            }
+            cache.insert_trace(metadata, CachedTrace::from_trace(trace));
         }
+        cache
     }
 
+    /// Adds the trace message `msg`, keeping `payload`, the bytes of the Kafka message from which it was unpacked,
+    /// which are only decoded when the message is selected.
     #[tracing::instrument(skip_all)]
     pub(crate) fn push_trace(
         &mut self,
         msg: &DigitizerAnalogTraceMessage<'_>,
+        payload: &[u8],
     ) -> Result<(), GpsTimeConversionError> {
-        let metadata = DigitiserMetadata {
-            id: msg.digitizer_id(),
-            timestamp: msg
-                .metadata()
-                .timestamp()
-                .copied()
-                .expect("Timestamp should exist.")
-                .try_into()?,
-            frame_number: msg.metadata().frame_number(),
-            period_number: msg.metadata().period_number(),
-            protons_per_pulse: msg.metadata().protons_per_pulse(),
-            running: msg.metadata().running(),
-            veto_flags: msg.metadata().veto_flags(),
-        };
+        let frame_metadata: FrameMetadata = msg.metadata().try_into()?;
+        let metadata = DigitiserMetadata::new(msg.digitizer_id(), &frame_metadata);
 
-        match self.traces.entry(metadata) {
-            Entry::Occupied(occupied_entry) => {
-                error!("Trace already found: {0:?}", occupied_entry.key());
+        self.insert_trace(metadata, CachedTrace::from_message(msg, payload));
+        Ok(())
+    }
+
+    fn insert_trace(&mut self, metadata: DigitiserMetadata, trace: CachedTrace) {
+        match self
+            .trace_keys
+            .entry(message_key(metadata.id, &metadata.frame_metadata()))
+        {
+            hash_map::Entry::Occupied(occupied_entry) => {
+                error!("Trace already found: {0:?}", occupied_entry.get());
             }
-            Entry::Vacant(vacant_entry) => {
-                info!("Trace Entered: {:?}", vacant_entry.key());
-                vacant_entry.insert(DigitiserTrace::from_message(msg));
+            hash_map::Entry::Vacant(vacant_entry) => {
+                info!("Trace Entered: {metadata:?}");
+                // Messages usually arrive in order, in which case these are pushes to the end.
+                insert_sorted(&mut self.ordered, metadata.clone());
+                insert_sorted(
+                    self.by_digitiser.entry(metadata.id).or_default(),
+                    metadata.clone(),
+                );
+                self.traces.insert(metadata.clone(), trace);
+                vacant_entry.insert(metadata);
             }
         }
-        Ok(())
     }
 
-    pub(crate) fn iter(&self) -> btree_map::Iter<'_, DigitiserMetadata, DigitiserTrace> {
-        self.traces.iter()
+    /// Returns the number of trace messages in the cache.
+    pub(crate) fn len(&self) -> usize {
+        self.ordered.len()
+    }
+
+    /// Returns the number of trace messages of each digitiser, in ascending order of digitiser id.
+    #[cfg(test)]
+    pub(crate) fn messages_per_digitiser(&self) -> impl Iterator<Item = (DigitizerId, usize)> {
+        self.by_digitiser
+            .iter()
+            .map(|(&id, messages)| (id, messages.len()))
+    }
+
+    /// Returns an estimate of the bytes held by the cache, the sum of the sizes of its payloads and event lists.
+    pub(crate) fn size_bytes(&self) -> usize {
+        let traces = self
+            .traces
+            .values()
+            .map(CachedTrace::size_bytes)
+            .sum::<usize>();
+        let events = self
+            .events
+            .values()
+            .flat_map(BTreeMap::values)
+            .map(event_list_size)
+            .sum::<usize>();
+        traces + events
+    }
+
+    /// Returns the metadata and channels of each message, without decoding any traces.
+    pub(crate) fn iter_channels(&self) -> impl Iterator<Item = (&DigitiserMetadata, &[Channel])> {
+        self.traces
+            .iter()
+            .map(|(metadata, trace)| (metadata, trace.channels.as_slice()))
+    }
+
+    /// Decodes each message in turn, in the same order as [Self::iter_channels].
+    pub(crate) fn iter_decoded(
+        &self,
+    ) -> impl Iterator<Item = (&DigitiserMetadata, DigitiserTrace)> {
+        self.traces
+            .iter()
+            .map(|(metadata, trace)| (metadata, trace.decode()))
+    }
+
+    /// Returns the event lists from the eventlist topic with index `topic_index` attached to each message,
+    /// in the same order as [Self::iter_channels], without decoding any traces.
+    pub(crate) fn iter_event_lists(
+        &self,
+        topic_index: usize,
+    ) -> impl Iterator<Item = &DigitiserEventList> {
+        self.traces
+            .values()
+            .filter_map(move |trace| trace.events.get(&topic_index))
+    }
+
+    /// Returns the metadata and channels of the message at position `index` of [Self::iter_channels].
+    pub(crate) fn get_channels(&self, index: usize) -> Option<(&DigitiserMetadata, &[Channel])> {
+        self.get_cached(index)
+            .map(|(metadata, trace)| (metadata, trace.channels.as_slice()))
+    }
+
+    /// Decodes the message at position `index` of [Self::iter_decoded].
+    pub(crate) fn get(&self, index: usize) -> Option<(&DigitiserMetadata, DigitiserTrace)> {
+        self.get_cached(index)
+            .map(|(metadata, trace)| (metadata, trace.decode()))
+    }
+
+    /// Returns the metadata and channels of the message at position `index` of [Self::iter_channels],
+    /// with the bytes of the message as received, or [None] if it was decoded before being cached.
+    pub(crate) fn get_raw(
+        &self,
+        index: usize,
+    ) -> Option<(&DigitiserMetadata, &[Channel], Option<&[u8]>)> {
+        self.get_cached(index).map(|(metadata, trace)| {
+            let payload = match &trace.payload {
+                TracePayload::Raw(bytes) => Some(bytes.as_slice()),
+                TracePayload::Decoded(_) => None,
+            };
+            (metadata, trace.channels.as_slice(), payload)
+        })
+    }
+
+    fn get_cached(&self, index: usize) -> Option<(&DigitiserMetadata, &CachedTrace)> {
+        self.ordered
+            .get(index)
+            .and_then(|metadata| self.traces.get_key_value(metadata))
     }
 
     #[tracing::instrument(skip_all)]
@@ -86,24 +330,12 @@ impl Cache {
         topic_index: usize,
         msg: &DigitizerEventListMessage<'_>,
     ) -> Result<(), GpsTimeConversionError> {
-        let metadata = DigitiserMetadata {
-            id: msg.digitizer_id(),
-            timestamp: msg
-                .metadata()
-                .timestamp()
-                .copied()
-                .expect("Timestamp should exist.")
-                .try_into()?,
-            frame_number: msg.metadata().frame_number(),
-            period_number: msg.metadata().period_number(),
-            protons_per_pulse: msg.metadata().protons_per_pulse(),
-            running: msg.metadata().running(),
-            veto_flags: msg.metadata().veto_flags(),
-        };
+        let frame_metadata: FrameMetadata = msg.metadata().try_into()?;
         let events = self.events.entry(topic_index).or_default();
-        match events.entry(metadata) {
+        match events.entry(message_key(msg.digitizer_id(), &frame_metadata)) {
             Entry::Occupied(occupied_entry) => {
-                error!("Event list already found: {0:?}", occupied_entry.key());
+                let (id, key) = occupied_entry.key();
+                error!("Event list already found for digitiser {id}: {key}");
             }
             Entry::Vacant(vacant_entry) => {
                 vacant_entry.insert(DigitiserEventList::from_message(msg));
@@ -114,17 +346,19 @@ impl Cache {
 
     pub(crate) fn attach_event_lists_to_trace(&mut self) {
         for (&topic, events) in &self.events {
-            for (metadata, events) in events {
-                match self.traces.entry(metadata.clone()) {
-                    Entry::Occupied(mut occupied_entry) => {
+            for (message_key, events) in events {
+                match self
+                    .trace_keys
+                    .get(message_key)
+                    .and_then(|metadata| self.traces.get_mut(metadata))
+                {
+                    Some(trace) => {
                         info!("Found Trace for Events");
-                        occupied_entry
-                            .get_mut()
-                            .events
-                            .insert(topic, events.clone());
+                        trace.events.insert(topic, events.clone());
                     }
-                    Entry::Vacant(vacant_entry) => {
-                        error!("Trace not found: {0:?}", vacant_entry.key());
+                    None => {
+                        let (id, key) = message_key;
+                        error!("Trace not found for digitiser {id}: {key}");
                     }
                 }
             }
@@ -135,3 +369,281 @@ impl Cache {
         self.events.keys()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::PAYLOADS_DECODED;
+    use digital_muon_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::{
+            ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+            DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+
+    /// Returns the number of raw payloads decoded on this thread.
+    pub(crate) fn payloads_decoded() -> usize {
+        PAYLOADS_DECODED.get()
+    }
+
+    /// Returns the bytes of a trace message of digitiser `id` in frame `frame_number`, with channels 0 and 1.
+    pub(crate) fn trace_message(id: u8, frame_number: u32) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let channels = [0, 1].map(|channel| {
+            let voltage = fbb.create_vector::<u16>(&[10, 50, 10]);
+            ChannelTrace::create(
+                &mut fbb,
+                &ChannelTraceArgs {
+                    channel,
+                    voltage: Some(voltage),
+                },
+            )
+        });
+        let channels = fbb.create_vector(&channels);
+        let timestamp = GpsTime::new(22, 205, 10, 55, 30, 0, 1, 5);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                frame_number,
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                timestamp: Some(&timestamp),
+                veto_flags: 0,
+            },
+        );
+        let message = DigitizerAnalogTraceMessage::create(
+            &mut fbb,
+            &DigitizerAnalogTraceMessageArgs {
+                digitizer_id: id,
+                metadata: Some(metadata),
+                sample_rate: 1_000_000_000,
+                channels: Some(channels),
+            },
+        );
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        test_utils::{payloads_decoded, trace_message},
+        *,
+    };
+    use chrono::DateTime;
+    use std::collections::HashMap;
+
+    fn metadata(offset_ms: i64, id: u8) -> DigitiserMetadata {
+        DigitiserMetadata {
+            timestamp: DateTime::from_timestamp_millis(offset_ms).unwrap(),
+            id,
+            frame_number: 0,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            veto_flags: 0,
+        }
+    }
+
+    fn trace() -> CachedTrace {
+        CachedTrace::from_trace(DigitiserTrace {
+            traces: HashMap::new(),
+            events: HashMap::new(),
+        })
+    }
+
+    fn assert_index_consistent(cache: &Cache) {
+        assert_eq!(cache.ordered.len(), cache.traces.len());
+        assert_eq!(cache.trace_keys.len(), cache.traces.len());
+        for (index, (metadata, _)) in cache.iter_channels().enumerate() {
+            assert_eq!(cache.get_channels(index).map(|(m, _)| m), Some(metadata));
+            let key = message_key(metadata.id, &metadata.frame_metadata());
+            assert_eq!(cache.trace_keys.get(&key), Some(metadata));
+        }
+        assert!(cache.get_channels(cache.traces.len()).is_none());
+
+        // Each digitiser's messages are those of the cache with its id, in the same order.
+        for (&id, messages) in &cache.by_digitiser {
+            let expected = cache
+                .iter_channels()
+                .map(|(metadata, _)| metadata)
+                .filter(|metadata| metadata.id == id)
+                .collect::<Vec<_>>();
+            assert_eq!(messages.iter().collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(
+            cache
+                .messages_per_digitiser()
+                .map(|(_, count)| count)
+                .sum::<usize>(),
+            cache.len()
+        );
+    }
+
+    #[test]
+    fn index_follows_in_order_ingestion() {
+        let mut cache = Cache::new();
+        for offset in 0..10 {
+            cache.insert_trace(metadata(offset, 1), trace());
+        }
+        assert_index_consistent(&cache);
+    }
+
+    #[test]
+    fn index_follows_out_of_order_ingestion() {
+        let mut cache = Cache::new();
+        for (offset, id) in [(5, 1), (1, 2), (9, 1), (1, 1), (3, 4), (0, 3)] {
+            cache.insert_trace(metadata(offset, id), trace());
+        }
+        assert_index_consistent(&cache);
+        assert_eq!(
+            cache.messages_per_digitiser().collect::<Vec<_>>(),
+            [(1, 3), (2, 1), (3, 1), (4, 1)]
+        );
+    }
+
+    #[test]
+    fn index_ignores_duplicate_ingestion() {
+        let mut cache = Cache::new();
+        let duplicate = metadata(2, 1);
+        cache.insert_trace(duplicate.clone(), trace());
+        cache.insert_trace(metadata(1, 1), trace());
+        cache.insert_trace(duplicate, trace());
+        assert_eq!(cache.ordered.len(), 2);
+        assert_eq!(cache.by_digitiser[&1].len(), 2);
+        assert_index_consistent(&cache);
+    }
+
+    fn push_events(cache: &mut Cache, metadata: &DigitiserMetadata) {
+        cache.events.entry(0).or_default().insert(
+            message_key(metadata.id, &metadata.frame_metadata()),
+            HashMap::from([(0, Vec::new())]),
+        );
+    }
+
+    #[test]
+    fn event_lists_attached_to_traces_ignoring_veto_flags() {
+        let mut cache = Cache::new();
+        cache.insert_trace(metadata(1, 1), trace());
+        push_events(
+            &mut cache,
+            &DigitiserMetadata {
+                veto_flags: 4,
+                ..metadata(1, 1)
+            },
+        );
+        cache.attach_event_lists_to_trace();
+        let (_, trace) = cache.get(0).unwrap();
+        assert!(trace.events.contains_key(&0));
+    }
+
+    #[test]
+    fn event_lists_not_attached_to_traces_of_different_periods() {
+        let mut cache = Cache::new();
+        cache.insert_trace(metadata(1, 1), trace());
+        push_events(
+            &mut cache,
+            &DigitiserMetadata {
+                period_number: 1,
+                ..metadata(1, 1)
+            },
+        );
+        cache.attach_event_lists_to_trace();
+        let (_, trace) = cache.get(0).unwrap();
+        assert!(trace.events.is_empty());
+    }
+
+    #[test]
+    fn payloads_only_decoded_when_selected() {
+        let mut cache = Cache::new();
+        for frame_number in 0..3 {
+            let bytes = trace_message(1, frame_number);
+            cache
+                .push_trace(
+                    &root_as_digitizer_analog_trace_message(&bytes).unwrap(),
+                    &bytes,
+                )
+                .unwrap();
+        }
+        let metadata = cache.get_channels(2).unwrap().0.clone();
+        push_events(&mut cache, &metadata);
+        cache.attach_event_lists_to_trace();
+
+        assert_eq!(cache.len(), 3);
+        for (_, channels) in cache.iter_channels() {
+            assert_eq!(channels, [0, 1]);
+        }
+        assert_eq!(payloads_decoded(), 0);
+
+        let (metadata, trace) = cache.get(2).unwrap();
+        assert_eq!(payloads_decoded(), 1);
+        assert_eq!(metadata.frame_number, 2);
+        assert_eq!(trace.traces.get(&1), Some(&vec![10, 50, 10]));
+        assert!(trace.events.contains_key(&0));
+    }
+
+    #[test]
+    fn raw_payloads_are_bytes_as_received() {
+        let mut cache = Cache::new();
+        let bytes = trace_message(1, 0);
+        cache
+            .push_trace(
+                &root_as_digitizer_analog_trace_message(&bytes).unwrap(),
+                &bytes,
+            )
+            .unwrap();
+        // This precedes the trace message, so is at index 0.
+        cache.insert_trace(metadata(1, 2), trace());
+
+        let (metadata, _, payload) = cache.get_raw(0).unwrap();
+        assert_eq!(metadata.id, 2);
+        assert_eq!(payload, None);
+
+        let (metadata, channels, payload) = cache.get_raw(1).unwrap();
+        assert_eq!(metadata.id, 1);
+        assert_eq!(channels, [0, 1]);
+        assert_eq!(payload, Some(bytes.as_slice()));
+
+        assert!(cache.get_raw(2).is_none());
+        assert_eq!(payloads_decoded(), 0);
+    }
+
+    #[test]
+    fn size_is_sum_of_payloads_and_event_lists() {
+        let mut cache = Cache::new();
+        assert_eq!(cache.size_bytes(), 0);
+
+        let bytes = trace_message(1, 0);
+        cache
+            .push_trace(
+                &root_as_digitizer_analog_trace_message(&bytes).unwrap(),
+                &bytes,
+            )
+            .unwrap();
+        assert_eq!(cache.size_bytes(), bytes.len());
+
+        cache.insert_trace(
+            metadata(1, 2),
+            CachedTrace::from_trace(DigitiserTrace {
+                traces: HashMap::from([(0, vec![1, 2, 3])]),
+                events: HashMap::from([(
+                    0,
+                    HashMap::from([(
+                        0,
+                        vec![Event {
+                            time: 1,
+                            intensity: 2,
+                        }],
+                    )]),
+                )]),
+            }),
+        );
+        assert_eq!(
+            cache.size_bytes(),
+            bytes.len() + 3 * size_of::<Intensity>() + size_of::<Event>()
+        );
+    }
+}
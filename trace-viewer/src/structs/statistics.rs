@@ -0,0 +1,165 @@
+//! Summary statistics of the traces and event lists of each message in the results list.
+use crate::{Channel, Intensity};
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Statistics of a single channel of a trace message.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ChannelStatistics {
+    /// The channel these statistics describe.
+    pub channel: Channel,
+    /// The smallest intensity in the trace.
+    pub min: Intensity,
+    /// The largest intensity in the trace.
+    pub max: Intensity,
+    /// The mean intensity of the trace.
+    pub mean: f64,
+    /// An estimate of the RMS noise of the trace.
+    ///
+    /// This is the RMS of the differences between consecutive samples, divided by `√2`,
+    /// so is unaffected by the baseline of the trace.
+    pub rms_noise: f64,
+    /// Maps the index of each eventlist topic found for the message to the number of events of this channel.
+    pub events: BTreeMap<usize, usize>,
+}
+
+/// Statistics of a trace message, and its event lists.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TraceStatistics {
+    /// Index of the message in the corresponding [Cache].
+    pub index: usize,
+    /// Statistics of each channel, in ascending order of channel.
+    pub channels: Vec<ChannelStatistics>,
+}
+
+impl TraceStatistics {
+    /// Returns the largest intensity over all channels.
+    pub fn max_amplitude(&self) -> Intensity {
+        self.channels
+            .iter()
+            .map(|channel| channel.max)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of events over all channels and eventlist topics.
+    pub fn total_events(&self) -> usize {
+        self.channels
+            .iter()
+            .flat_map(|channel| channel.events.values())
+            .sum()
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::DigitiserTrace;
+
+        impl ChannelStatistics {
+            fn new(channel: Channel, trace: &[Intensity], digitiser_trace: &DigitiserTrace) -> Self {
+                let min = trace.iter().copied().min().unwrap_or_default();
+                let max = trace.iter().copied().max().unwrap_or_default();
+                let mean = if trace.is_empty() {
+                    0.0
+                } else {
+                    trace.iter().map(|&intensity| intensity as f64).sum::<f64>() / trace.len() as f64
+                };
+                let rms_noise = if trace.len() < 2 {
+                    0.0
+                } else {
+                    let sum_of_squares = trace
+                        .windows(2)
+                        .map(|pair| (pair[1] as f64 - pair[0] as f64).powi(2))
+                        .sum::<f64>();
+                    (sum_of_squares / (trace.len() - 1) as f64 / 2.0).sqrt()
+                };
+                let events = digitiser_trace
+                    .events
+                    .iter()
+                    .map(|(&topic_index, events)| (topic_index, events.get(&channel).map(Vec::len).unwrap_or_default()))
+                    .collect();
+
+                Self { channel, min, max, mean, rms_noise, events }
+            }
+        }
+
+        impl TraceStatistics {
+            /// Computes the statistics of `digitiser_trace`, which is at position `index` of the [Cache].
+            pub(crate) fn new(index: usize, digitiser_trace: &DigitiserTrace) -> Self {
+                let mut channels = digitiser_trace
+                    .traces
+                    .iter()
+                    .map(|(&channel, trace)| ChannelStatistics::new(channel, trace, digitiser_trace))
+                    .collect::<Vec<_>>();
+                channels.sort_by_key(|channel| channel.channel);
+                Self { index, channels }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::structs::Event;
+    use std::collections::HashMap;
+
+    fn digitiser_trace() -> DigitiserTrace {
+        let events = |count| {
+            (0..count)
+                .map(|time| Event { time, intensity: 1 })
+                .collect::<Vec<_>>()
+        };
+        DigitiserTrace {
+            traces: HashMap::from([(5, vec![10, 12, 8, 10, 30, 10]), (2, vec![4, 4])]),
+            events: HashMap::from([
+                (0, HashMap::from([(5, events(2))])),
+                (3, HashMap::from([(5, events(1)), (2, events(4))])),
+            ]),
+        }
+    }
+
+    #[test]
+    fn channel_statistics() {
+        let statistics = TraceStatistics::new(7, &digitiser_trace());
+        assert_eq!(statistics.index, 7);
+        assert_eq!(statistics.channels.len(), 2);
+
+        let channel = &statistics.channels[0];
+        assert_eq!(channel.channel, 2);
+        assert_eq!((channel.min, channel.max), (4, 4));
+        assert_eq!(channel.mean, 4.0);
+        assert_eq!(channel.rms_noise, 0.0);
+        assert_eq!(channel.events, BTreeMap::from([(0, 0), (3, 4)]));
+
+        let channel = &statistics.channels[1];
+        assert_eq!(channel.channel, 5);
+        assert_eq!((channel.min, channel.max), (8, 30));
+        // (10 + 12 + 8 + 10 + 30 + 10) / 6
+        assert!((channel.mean - 80.0 / 6.0).abs() < 1e-9);
+        // The differences are 2, -4, 2, 20, -20, whose squares sum to 824.
+        assert!((channel.rms_noise - (824.0_f64 / 5.0 / 2.0).sqrt()).abs() < 1e-9);
+        assert_eq!(channel.events, BTreeMap::from([(0, 2), (3, 1)]));
+    }
+
+    #[test]
+    fn aggregates() {
+        let statistics = TraceStatistics::new(0, &digitiser_trace());
+        assert_eq!(statistics.max_amplitude(), 30);
+        assert_eq!(statistics.total_events(), 7);
+    }
+
+    #[test]
+    fn empty_trace() {
+        let digitiser_trace = DigitiserTrace {
+            traces: HashMap::from([(1, vec![])]),
+            events: HashMap::new(),
+        };
+        let statistics = TraceStatistics::new(0, &digitiser_trace);
+        assert_eq!(statistics.max_amplitude(), 0);
+        assert_eq!(statistics.total_events(), 0);
+        assert_eq!(statistics.channels[0].mean, 0.0);
+        assert_eq!(statistics.channels[0].rms_noise, 0.0);
+    }
+}
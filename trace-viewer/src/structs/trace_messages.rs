@@ -1,5 +1,10 @@
-use crate::structs::SearchTarget;
+use crate::{
+    Channel, Intensity, Time,
+    structs::{RunAnnotation, SearchTarget, TraceStatistics},
+};
+use cfg_if::cfg_if;
 use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, EnumString};
 
 /// Encapsulates the data needed to summarise the results of a search in the results section.
 #[derive(Clone, Serialize, Deserialize)]
@@ -7,6 +12,55 @@ pub struct SearchSummary {
     /// Topic from which the events were captured.
     pub eventlist_topic_indices: Vec<usize>,
     pub target: SearchTarget,
+    /// The number of messages found, these are fetched a page at a time by [get_results_page()].
+    pub num_results: usize,
+}
+
+/// The orders in which the results list can be displayed.
+#[derive(
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+pub enum SortResultsBy {
+    #[default]
+    #[strum(to_string = "Timestamp")]
+    Timestamp,
+    #[strum(to_string = "Max Amplitude")]
+    MaxAmplitude,
+    #[strum(to_string = "Total Events")]
+    TotalEvents,
+}
+
+impl SortResultsBy {
+    /// Returns the value by which a message with `statistics` is sorted, in descending order.
+    pub fn key(&self, statistics: &TraceStatistics) -> usize {
+        match self {
+            Self::Timestamp => 0,
+            Self::MaxAmplitude => statistics.max_amplitude() as usize,
+            Self::TotalEvents => statistics.total_events(),
+        }
+    }
+}
+
+/// A contiguous part of the results list, in the requested order.
+/// Should be created by [get_results_page()].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResultsPage {
+    /// The position in the ordered results list of the first message of the page.
+    pub offset: usize,
+    /// The number of messages in the whole results list.
+    pub num_results: usize,
+    /// Summaries of the messages of the page, in order.
     pub traces: Vec<TraceSummary>,
 }
 
@@ -33,6 +87,8 @@ pub struct TraceSummary {
     pub channels: Vec<u32>,
     /// Index of the message in the corresponding [Cache].
     pub index: usize,
+    /// The run during which the message occurred.
+    pub run: RunAnnotation,
 }
 
 /// Represents a trace message and channel stored in a [Cache].
@@ -45,15 +101,329 @@ pub struct SelectedTraceIndex {
 }
 
 /// Encapsulates data needed by the [DisplayGraph] component.
-/// Should be created by [create_plotly()]
+/// Should be created by [create_plotly()] or [create_multi_plotly()]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TracePlotly {
     /// Text to be displayed as a graph heading.
     pub title: String,
-    /// Json string of the trace data plotly graph.
-    pub trace_data: String,
-    /// If present, Json string of the event list data plotly graph.
+    /// Json strings of the trace data plotly graphs, one for each channel plotted.
+    pub trace_data: Vec<String>,
+    /// Json strings of the event list data plotly graphs, and of the markers of the trace's annotations, if any.
     pub eventlist_data: Vec<String>,
     /// Json string of the plotly layout to use.
     pub layout: String,
+    /// The time bins of the trace which are plotted, or [None] if the whole trace is.
+    pub range: Option<TimeRange>,
+    /// The number of samples of the trace each pair of plotted points represents, see [TraceView::max_points].
+    /// This is one if the samples are plotted at full resolution.
+    pub decimation_factor: usize,
+}
+
+impl TracePlotly {
+    /// Returns the view to fetch when the plot is zoomed to `zoomed`, or reset to the whole trace if it is [None],
+    /// given the most points to plot, `max_points`. This is [None] if the plotted data already suffices.
+    ///
+    /// A zoomed range is fetched if it extends beyond the plotted range,
+    /// or if the plotted data is decimated but the range can be plotted at full resolution.
+    pub fn zoomed_view(
+        &self,
+        zoomed: Option<TimeRange>,
+        max_points: Option<usize>,
+    ) -> Option<TraceView> {
+        let refetch = match zoomed {
+            Some(zoomed) => {
+                let covered = self.range.is_none_or(|range| range.contains(&zoomed));
+                let fits = max_points.is_none_or(|max_points| zoomed.len() <= max_points);
+                !covered || (self.decimation_factor > 1 && fits)
+            }
+            None => self.range.is_some(),
+        };
+        refetch.then_some(TraceView {
+            max_points,
+            range: zoomed,
+        })
+    }
+}
+
+/// A range of time bins of a trace, from `start` up to, but excluding, `end`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl TimeRange {
+    /// The number of time bins in the range.
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start) as usize
+    }
+
+    /// Returns true if the range contains no time bins.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if every time bin of `other` lies within the range.
+    pub fn contains(&self, other: &TimeRange) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+/// Determines which part of a trace is plotted, and at what resolution.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TraceView {
+    /// If present, a trace, or range of it, with more samples than this is decimated, keeping the lowest
+    /// and highest sample of each of `max_points / 2` equal intervals, so that pulses remain visible.
+    pub max_points: Option<usize>,
+    /// If present, only these time bins of the trace are plotted, otherwise the whole trace is.
+    pub range: Option<TimeRange>,
+}
+
+impl TraceView {
+    /// Plots the whole trace, decimated if it has more than `max_points` samples.
+    pub fn whole_trace(max_points: Option<usize>) -> Self {
+        Self {
+            max_points,
+            range: None,
+        }
+    }
+}
+
+/// Encapsulates a plot of several channels of one trace message.
+/// Should be created by [create_multi_plotly()]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MultiTracePlotly {
+    /// The plot of the requested channels which exist in the trace message.
+    pub trace_plotly: TracePlotly,
+    /// The requested channels which do not exist in the trace message, these are not plotted.
+    pub missing_channels: Vec<Channel>,
+}
+
+/// How an exported plot includes the plotly.js library.
+#[derive(
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PlotlyJs {
+    /// The library is loaded from its CDN when the file is opened, which requires an internet connection.
+    #[default]
+    #[strum(to_string = "CDN")]
+    Cdn,
+    /// The library is embedded in the file, which is then several megabytes larger.
+    #[strum(to_string = "Inline")]
+    Inline,
+}
+
+/// The property of events whose distribution is plotted by [create_histogram_plotly()].
+#[derive(
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+    EnumString,
+    Display,
+    EnumIter,
+)]
+pub enum HistogramQuantity {
+    /// The time of each event, relative to the start of its frame.
+    #[default]
+    #[strum(to_string = "Time")]
+    Time,
+    /// The intensity of each event.
+    #[strum(to_string = "Amplitude")]
+    Amplitude,
+}
+
+impl HistogramQuantity {
+    /// Returns the title of the axis along which the quantity is binned.
+    pub fn axis_title(&self) -> &'static str {
+        match self {
+            Self::Time => "Time (ns)",
+            Self::Amplitude => "Intensity",
+        }
+    }
+}
+
+/// Bounds on the events displayed on the plot, all of which are inclusive.
+/// Filtering is applied when the plot is created, so does not alter the stored session data.
+#[derive(Default, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct EventFilter {
+    /// If present, events with lower intensity are hidden.
+    pub min_intensity: Option<Intensity>,
+    /// If present, events with higher intensity are hidden.
+    pub max_intensity: Option<Intensity>,
+    /// If present, events occuring earlier are hidden.
+    pub min_time: Option<Time>,
+    /// If present, events occuring later are hidden.
+    pub max_time: Option<Time>,
+}
+
+cfg_if! {
+    if #[cfg(feature = "ssr")] {
+        use crate::structs::digitiser_messages::Event;
+
+        impl EventFilter {
+            /// Returns true if no bounds are set, in which case all events are shown.
+            pub(crate) fn is_empty(&self) -> bool {
+                *self == Self::default()
+            }
+
+            /// Returns true if `event` lies within all bounds.
+            pub(crate) fn accepts(&self, event: &Event) -> bool {
+                self.min_intensity.is_none_or(|min| event.intensity >= min)
+                    && self.max_intensity.is_none_or(|max| event.intensity <= max)
+                    && self.min_time.is_none_or(|min| event.time >= min)
+                    && self.max_time.is_none_or(|max| event.time <= max)
+            }
+
+            /// Returns the events which should be shown, and the number which are hidden.
+            pub(crate) fn apply<'a>(&self, events: &'a [Event]) -> (Vec<&'a Event>, usize) {
+                let shown = events
+                    .iter()
+                    .filter(|event| self.accepts(event))
+                    .collect::<Vec<_>>();
+                let hidden = events.len() - shown.len();
+                (shown, hidden)
+            }
+
+            /// Returns the legend text for the events of `topic`, reporting the number hidden when filtering.
+            pub(crate) fn legend_name(&self, topic: &str, shown: usize, hidden: usize) -> String {
+                if self.is_empty() {
+                    format!("Events: {topic}")
+                } else {
+                    format!("Events: {topic} ({shown} shown, {hidden} hidden)")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "ssr")]
+    fn events() -> Vec<Event> {
+        [(10, 5), (20, 50), (30, 100), (40, 500)]
+            .map(|(time, intensity)| Event { time, intensity })
+            .to_vec()
+    }
+
+    #[test]
+    #[cfg(feature = "ssr")]
+    fn empty_filter_shows_all() {
+        let filter = EventFilter::default();
+        let events = events();
+        let (shown, hidden) = filter.apply(&events);
+        assert_eq!(shown, events.iter().collect::<Vec<_>>());
+        assert_eq!(hidden, 0);
+        assert_eq!(
+            filter.legend_name("daq", shown.len(), hidden),
+            "Events: daq"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ssr")]
+    fn bounds_are_inclusive() {
+        let events = events();
+        let filter = EventFilter {
+            min_intensity: Some(50),
+            max_intensity: Some(500),
+            ..Default::default()
+        };
+        let (shown, hidden) = filter.apply(&events);
+        assert_eq!(shown, events[1..].iter().collect::<Vec<_>>());
+        assert_eq!(hidden, 1);
+
+        let filter = EventFilter {
+            min_time: Some(20),
+            max_time: Some(30),
+            ..Default::default()
+        };
+        let (shown, hidden) = filter.apply(&events);
+        assert_eq!(shown, events[1..3].iter().collect::<Vec<_>>());
+        assert_eq!(hidden, 2);
+
+        let filter = EventFilter {
+            min_intensity: Some(100),
+            max_time: Some(30),
+            ..Default::default()
+        };
+        let (shown, hidden) = filter.apply(&events);
+        assert_eq!(shown, vec![&events[2]]);
+        assert_eq!(hidden, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "ssr")]
+    fn legend_reports_hidden() {
+        let filter = EventFilter {
+            min_intensity: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.legend_name("daq", 42, 318),
+            "Events: daq (42 shown, 318 hidden)"
+        );
+    }
+
+    fn plotly(range: Option<TimeRange>, decimation_factor: usize) -> TracePlotly {
+        TracePlotly {
+            title: String::new(),
+            trace_data: Vec::new(),
+            eventlist_data: Vec::new(),
+            layout: String::new(),
+            range,
+            decimation_factor,
+        }
+    }
+
+    #[test]
+    fn zooming_refetches_only_when_needed() {
+        let range = |start, end| TimeRange { start, end };
+        let view = |range| TraceView {
+            max_points: Some(100),
+            range,
+        };
+
+        // A decimated plot of the whole trace is refetched at full resolution once the zoomed range fits.
+        let decimated = plotly(None, 10);
+        assert_eq!(decimated.zoomed_view(Some(range(0, 101)), Some(100)), None);
+        assert_eq!(
+            decimated.zoomed_view(Some(range(50, 150)), Some(100)),
+            Some(view(Some(range(50, 150))))
+        );
+        assert_eq!(decimated.zoomed_view(None, Some(100)), None);
+
+        // A full resolution plot of a range is only refetched if the zoomed range extends beyond it.
+        let zoomed = plotly(Some(range(50, 150)), 1);
+        assert_eq!(zoomed.zoomed_view(Some(range(60, 70)), Some(100)), None);
+        assert_eq!(
+            zoomed.zoomed_view(Some(range(0, 1000)), Some(100)),
+            Some(view(Some(range(0, 1000))))
+        );
+        assert_eq!(zoomed.zoomed_view(None, Some(100)), Some(view(None)));
+
+        // Without a maximum, the whole trace is plotted at full resolution, so is never refetched.
+        let full = plotly(None, 1);
+        assert_eq!(full.zoomed_view(Some(range(0, 1_000_000)), None), None);
+        assert_eq!(full.zoomed_view(None, None), None);
+    }
 }